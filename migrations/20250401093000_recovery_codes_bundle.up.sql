@@ -0,0 +1,7 @@
+CREATE TABLE recovery_codes_bundle (
+    id text PRIMARY KEY,
+    user_id bigint NOT NULL,
+    codes text NOT NULL,
+    expires timestamp without time zone NOT NULL,
+    FOREIGN KEY(user_id) REFERENCES "user"(id) ON DELETE CASCADE
+);
@@ -0,0 +1 @@
+ALTER TABLE webhook ADD COLUMN paused_until timestamp without time zone NULL;
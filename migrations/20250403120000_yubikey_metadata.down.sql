@@ -0,0 +1,2 @@
+ALTER TABLE yubikey DROP COLUMN firmware_version;
+ALTER TABLE yubikey DROP COLUMN model;
@@ -0,0 +1,2 @@
+ALTER TABLE yubikey ADD COLUMN model text NULL;
+ALTER TABLE yubikey ADD COLUMN firmware_version text NULL;
@@ -0,0 +1 @@
+ALTER TABLE session ADD COLUMN last_seen timestamp without time zone NULL;
@@ -0,0 +1 @@
+ALTER TABLE webhook ADD COLUMN on_enrollment_pending boolean NOT NULL DEFAULT false;
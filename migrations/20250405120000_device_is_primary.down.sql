@@ -0,0 +1,2 @@
+DROP INDEX device_user_primary;
+ALTER TABLE device DROP COLUMN is_primary;
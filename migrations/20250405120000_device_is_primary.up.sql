@@ -0,0 +1,2 @@
+ALTER TABLE device ADD COLUMN is_primary boolean NOT NULL DEFAULT false;
+CREATE UNIQUE INDEX device_user_primary ON device (user_id) WHERE is_primary;
@@ -0,0 +1 @@
+ALTER TABLE webhook DROP COLUMN custom_headers;
@@ -0,0 +1 @@
+ALTER TABLE webhook ADD COLUMN custom_headers jsonb NOT NULL DEFAULT '{}';
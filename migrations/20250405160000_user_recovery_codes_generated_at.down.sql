@@ -0,0 +1 @@
+ALTER TABLE "user" DROP COLUMN recovery_codes_generated_at;
@@ -0,0 +1 @@
+ALTER TABLE "user" ADD COLUMN recovery_codes_generated_at timestamp without time zone NULL;
@@ -0,0 +1,2 @@
+ALTER TABLE "user" DROP COLUMN totp_digits;
+ALTER TABLE "user" DROP COLUMN totp_period;
@@ -0,0 +1,2 @@
+ALTER TABLE "user" ADD COLUMN totp_digits integer NOT NULL DEFAULT 6;
+ALTER TABLE "user" ADD COLUMN totp_period integer NOT NULL DEFAULT 30;
@@ -0,0 +1,2 @@
+ALTER TABLE openidprovider DROP COLUMN rp_initiated_logout;
+ALTER TABLE session DROP COLUMN id_token;
@@ -0,0 +1,2 @@
+ALTER TABLE openidprovider ADD COLUMN rp_initiated_logout boolean NOT NULL DEFAULT false;
+ALTER TABLE session ADD COLUMN id_token text NULL;
@@ -0,0 +1 @@
+ALTER TABLE token ADD COLUMN custom_welcome_message text NULL;
@@ -0,0 +1 @@
+ALTER TABLE "user" ADD COLUMN suspended_until timestamp without time zone;
@@ -0,0 +1 @@
+ALTER TABLE "user" ADD COLUMN password_changed_at timestamp without time zone NOT NULL DEFAULT NOW();
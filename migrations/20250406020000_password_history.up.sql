@@ -0,0 +1,8 @@
+CREATE TABLE password_history (
+    id bigserial PRIMARY KEY,
+    user_id bigint NOT NULL,
+    password_hash text NOT NULL,
+    created timestamp without time zone NOT NULL DEFAULT NOW(),
+    FOREIGN KEY(user_id) REFERENCES "user"(id) ON DELETE CASCADE
+);
+CREATE INDEX password_history_user_id ON password_history(user_id);
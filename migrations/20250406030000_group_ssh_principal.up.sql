@@ -0,0 +1 @@
+ALTER TABLE "group" ADD COLUMN ssh_principal text;
@@ -0,0 +1,2 @@
+UPDATE authentication_key SET key = key || ' ' || comment WHERE comment IS NOT NULL;
+ALTER TABLE authentication_key DROP COLUMN comment;
@@ -0,0 +1,5 @@
+ALTER TABLE authentication_key ADD COLUMN comment text;
+UPDATE authentication_key SET
+    comment = NULLIF(regexp_replace(btrim(key, E' \t\r\n'), '^\S+\s+\S+\s*', ''), ''),
+    key = array_to_string((regexp_split_to_array(btrim(key, E' \t\r\n'), '\s+'))[1:2], ' ')
+WHERE key_type = 'ssh' AND btrim(key, E' \t\r\n') ~ '^\S+\s+\S+';
@@ -0,0 +1 @@
+ALTER TABLE authentication_key ADD COLUMN expires_at timestamp without time zone;
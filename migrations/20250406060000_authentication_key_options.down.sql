@@ -0,0 +1 @@
+ALTER TABLE authentication_key DROP COLUMN options;
@@ -0,0 +1 @@
+ALTER TABLE authentication_key ADD COLUMN options text;
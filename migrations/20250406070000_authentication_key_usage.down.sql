@@ -0,0 +1,2 @@
+ALTER TABLE authentication_key DROP COLUMN usage;
+DROP TYPE authentication_key_usage;
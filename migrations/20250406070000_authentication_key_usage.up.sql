@@ -0,0 +1,2 @@
+CREATE TYPE authentication_key_usage AS ENUM ('interactive', 'deploy', 'both');
+ALTER TABLE authentication_key ADD COLUMN usage authentication_key_usage NOT NULL DEFAULT 'interactive';
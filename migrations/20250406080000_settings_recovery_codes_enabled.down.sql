@@ -0,0 +1 @@
+ALTER TABLE settings DROP COLUMN recovery_codes_enabled;
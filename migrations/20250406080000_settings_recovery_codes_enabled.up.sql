@@ -0,0 +1 @@
+ALTER TABLE settings ADD COLUMN recovery_codes_enabled BOOLEAN NOT NULL DEFAULT TRUE;
@@ -0,0 +1 @@
+ALTER TABLE webhook ADD COLUMN body_template text NULL;
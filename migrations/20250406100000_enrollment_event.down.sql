@@ -0,0 +1,2 @@
+DROP TABLE enrollment_event;
+DROP TYPE enrollment_event_type;
@@ -0,0 +1,23 @@
+CREATE TYPE enrollment_event_type AS ENUM (
+    'token_created',
+    'email_sent',
+    'session_started',
+    'completed',
+    'expired',
+    'revoked'
+);
+CREATE TABLE enrollment_event (
+    id bigserial PRIMARY KEY NOT NULL,
+    -- no foreign key, history outlives revoked tokens
+    token_id text NOT NULL,
+    user_id bigint NOT NULL,
+    event_type enrollment_event_type NOT NULL,
+    admin_id bigint NULL,
+    ip_address text NULL,
+    user_agent text NULL,
+    timestamp timestamp without time zone NOT NULL,
+    FOREIGN KEY(user_id) REFERENCES "user"(id) ON DELETE CASCADE,
+    FOREIGN KEY(admin_id) REFERENCES "user"(id) ON DELETE SET NULL
+);
+CREATE INDEX enrollment_event_token_id ON enrollment_event (token_id);
+CREATE INDEX enrollment_event_user_id ON enrollment_event (user_id);
@@ -0,0 +1,6 @@
+CREATE TABLE ssh_host_group (
+    hostname text NOT NULL,
+    group_id bigint NOT NULL,
+    FOREIGN KEY(group_id) REFERENCES "group"(id) ON DELETE CASCADE,
+    PRIMARY KEY(hostname, group_id)
+);
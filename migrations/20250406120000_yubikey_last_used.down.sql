@@ -0,0 +1,2 @@
+ALTER TABLE yubikey DROP COLUMN disabled_at;
+ALTER TABLE yubikey DROP COLUMN last_used_at;
@@ -0,0 +1,4 @@
+ALTER TABLE yubikey ADD COLUMN last_used_at timestamp without time zone NULL;
+ALTER TABLE yubikey ADD COLUMN disabled_at timestamp without time zone NULL;
+-- existing keys count as used now, so they aren't disabled right after upgrade
+UPDATE yubikey SET last_used_at = now();
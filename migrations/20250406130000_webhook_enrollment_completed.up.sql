@@ -0,0 +1 @@
+ALTER TABLE webhook ADD COLUMN on_enrollment_completed boolean NOT NULL DEFAULT false;
@@ -0,0 +1,3 @@
+DROP TABLE webhook_delivery;
+ALTER TABLE webhook DROP COLUMN retry_base_delay;
+ALTER TABLE webhook DROP COLUMN max_attempts;
@@ -0,0 +1,14 @@
+ALTER TABLE webhook ADD COLUMN max_attempts integer NOT NULL DEFAULT 5;
+-- seconds, doubled after each failed attempt
+ALTER TABLE webhook ADD COLUMN retry_base_delay integer NOT NULL DEFAULT 10;
+CREATE TABLE webhook_delivery (
+    id bigserial PRIMARY KEY NOT NULL,
+    webhook_id bigint NOT NULL,
+    event text NOT NULL,
+    body text NOT NULL,
+    attempts integer NOT NULL,
+    next_attempt_at timestamp without time zone NOT NULL,
+    created_at timestamp without time zone NOT NULL,
+    FOREIGN KEY(webhook_id) REFERENCES webhook(id) ON DELETE CASCADE
+);
+CREATE INDEX webhook_delivery_next_attempt_at ON webhook_delivery (next_attempt_at);
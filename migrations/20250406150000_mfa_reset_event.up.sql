@@ -0,0 +1,9 @@
+CREATE TABLE mfa_reset_event (
+    id bigserial PRIMARY KEY NOT NULL,
+    user_id bigint NOT NULL,
+    admin_id bigint NULL,
+    timestamp timestamp without time zone NOT NULL,
+    FOREIGN KEY(user_id) REFERENCES "user"(id) ON DELETE CASCADE,
+    FOREIGN KEY(admin_id) REFERENCES "user"(id) ON DELETE SET NULL
+);
+CREATE INDEX mfa_reset_event_user_id ON mfa_reset_event (user_id);
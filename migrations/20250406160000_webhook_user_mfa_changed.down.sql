@@ -0,0 +1 @@
+ALTER TABLE webhook DROP COLUMN on_user_mfa_changed;
@@ -0,0 +1 @@
+ALTER TABLE webhook ADD COLUMN on_user_mfa_changed boolean NOT NULL DEFAULT false;
@@ -0,0 +1 @@
+ALTER TABLE webhook ADD COLUMN group_id bigint NULL REFERENCES "group"(id) ON DELETE RESTRICT;
@@ -0,0 +1 @@
+ALTER TABLE settings DROP COLUMN security_notification_email;
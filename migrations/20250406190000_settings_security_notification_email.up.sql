@@ -0,0 +1 @@
+ALTER TABLE settings ADD COLUMN security_notification_email text NULL;
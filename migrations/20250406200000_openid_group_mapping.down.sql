@@ -0,0 +1 @@
+ALTER TABLE openidprovider DROP COLUMN group_mapping;
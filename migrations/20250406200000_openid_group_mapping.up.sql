@@ -0,0 +1 @@
+ALTER TABLE openidprovider ADD COLUMN group_mapping jsonb NOT NULL DEFAULT '{}';
@@ -0,0 +1 @@
+ALTER TABLE wireguard_network DROP COLUMN dns_search_domains;
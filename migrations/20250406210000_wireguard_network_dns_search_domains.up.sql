@@ -0,0 +1 @@
+ALTER TABLE wireguard_network ADD COLUMN dns_search_domains text[] NOT NULL DEFAULT '{}';
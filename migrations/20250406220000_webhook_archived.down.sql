@@ -0,0 +1,3 @@
+DROP TABLE webhook_delivery_log;
+DROP TYPE webhook_delivery_status;
+ALTER TABLE webhook DROP COLUMN archived;
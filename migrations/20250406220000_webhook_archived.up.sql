@@ -0,0 +1,16 @@
+ALTER TABLE webhook ADD COLUMN archived boolean NOT NULL DEFAULT false;
+CREATE TYPE webhook_delivery_status AS ENUM ('delivered', 'failed', 'discarded');
+-- outcome of finished deliveries, kept for audit after the webhook is archived
+CREATE TABLE webhook_delivery_log (
+    id bigserial PRIMARY KEY NOT NULL,
+    webhook_id bigint NOT NULL,
+    event text NOT NULL,
+    body text NOT NULL,
+    status webhook_delivery_status NOT NULL,
+    attempts integer NOT NULL,
+    error text NULL,
+    created_at timestamp without time zone NOT NULL,
+    finished_at timestamp without time zone NOT NULL,
+    FOREIGN KEY(webhook_id) REFERENCES webhook(id) ON DELETE CASCADE
+);
+CREATE INDEX webhook_delivery_log_webhook_id ON webhook_delivery_log (webhook_id);
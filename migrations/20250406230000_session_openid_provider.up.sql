@@ -0,0 +1,2 @@
+-- provider which issued the ID token, used for RP-initiated logout
+ALTER TABLE session ADD COLUMN openid_provider_id bigint NULL REFERENCES openidprovider(id) ON DELETE SET NULL;
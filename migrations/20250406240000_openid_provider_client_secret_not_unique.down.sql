@@ -0,0 +1 @@
+ALTER TABLE openidprovider ADD CONSTRAINT openidprovider_client_secret_unique UNIQUE (client_secret);
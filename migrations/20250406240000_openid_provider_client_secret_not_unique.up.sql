@@ -0,0 +1,2 @@
+-- client secrets are encrypted with a random nonce, so uniqueness of stored values is meaningless
+ALTER TABLE openidprovider DROP CONSTRAINT openidprovider_client_secret_unique;
@@ -153,6 +153,9 @@ pub fn derive(input: TokenStream) -> TokenStream {
                     } else if tokens == "secret" {
                         // FIXME: hard-coded struct name
                         return Some(quote! { &self.#name as &Option<SecretString> });
+                    } else if tokens == "encrypted" {
+                        // FIXME: hard-coded struct name
+                        return Some(quote! { &self.#name as &EncryptedSecret });
                     } else {
                         return Some(quote! { &self.#name });
                     }
@@ -1,10 +1,13 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use axum::extract::FromRef;
 use axum_extra::extract::cookie::Key;
-use reqwest::Client;
+use chrono::{TimeDelta, Utc};
+use reqwest::{header::CONTENT_TYPE, Client};
 use secrecy::ExposeSecret;
-use serde_json::json;
 use sqlx::PgPool;
 use tokio::{
     sync::{
@@ -12,23 +15,103 @@ use tokio::{
         mpsc::{UnboundedReceiver, UnboundedSender},
     },
     task::spawn,
+    time::sleep,
 };
 use webauthn_rs::prelude::*;
 
 use crate::{
     auth::failed_login::FailedLoginMap,
-    db::{AppEvent, GatewayEvent, WebHook},
+    db::{
+        models::{
+            webhook::{sign_body, MfaState, UserMfaChangedData},
+            webhook_delivery::WebHookDelivery,
+        },
+        AppEvent, GatewayEvent, Id, User, WebHook,
+    },
     grpc::gateway::{send_multiple_wireguard_events, send_wireguard_event},
     mail::Mail,
     server_config,
+    sms::TwilioSmsSender,
 };
 
+/// Time reserved for a single delivery attempt, see [`WebHookDelivery::claim_due`].
+const WEBHOOK_DELIVERY_LEASE: TimeDelta = TimeDelta::minutes(1);
+const WEBHOOK_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const WEBHOOK_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+fn webhook_client() -> Client {
+    Client::builder()
+        .user_agent("reqwest")
+        .timeout(WEBHOOK_REQUEST_TIMEOUT)
+        .build()
+        .unwrap()
+}
+
+/// Attempt webhook delivery. Deliveries which fail with non-2xx response or an error
+/// (e.g. timeout) are scheduled for a retry, until the webhook runs out of attempts.
+async fn deliver_webhook(
+    client: &Client,
+    pool: &PgPool,
+    webhook: &WebHook<Id>,
+    mut delivery: WebHookDelivery<Id>,
+) {
+    let mut request = client.post(&webhook.url);
+    for (name, value) in webhook.custom_headers.iter() {
+        request = request.header(name, value);
+    }
+    // signed for each attempt, so that retries carry a current timestamp
+    let timestamp = Utc::now().timestamp();
+    let signature = sign_body(&webhook.token, timestamp, &delivery.body);
+    let result = request
+        .header(CONTENT_TYPE, "application/json")
+        .body(delivery.body.clone())
+        .bearer_auth(&webhook.token)
+        .header("x-defguard-event", &delivery.event)
+        .header("x-defguard-timestamp", timestamp)
+        .header("x-defguard-signature", signature)
+        .send()
+        .await;
+    let error = match result {
+        Ok(res) if res.status().is_success() => {
+            info!("Trigger sent to {}, status {}", webhook.url, res.status());
+            if let Err(err) = delivery.record_success(pool).await {
+                error!(
+                    "Failed to record delivered trigger to {}: {err}",
+                    webhook.url
+                );
+            }
+            return;
+        }
+        Ok(res) => format!("status {}", res.status()),
+        Err(err) => err.to_string(),
+    };
+    let base_delay = TimeDelta::seconds(webhook.retry_base_delay.into());
+    match delivery
+        .record_failure(pool, webhook.max_attempts, base_delay, &error)
+        .await
+    {
+        Ok(true) => warn!(
+            "Error sending trigger to {} (attempt {}): {error}, retrying at {}",
+            webhook.url, delivery.attempts, delivery.next_attempt_at
+        ),
+        Ok(false) => error!(
+            "Giving up sending trigger to {} after {} attempts: {error}",
+            webhook.url, delivery.attempts
+        ),
+        Err(err) => error!(
+            "Failed to record failed trigger delivery to {}: {err}",
+            webhook.url
+        ),
+    }
+}
+
 #[derive(Clone)]
 pub struct AppState {
     pub pool: PgPool,
     tx: UnboundedSender<AppEvent>,
     pub wireguard_tx: Sender<GatewayEvent>,
     pub mail_tx: UnboundedSender<Mail>,
+    pub sms_sender: Arc<TwilioSmsSender>,
     pub webauthn: Arc<Webauthn>,
     pub failed_logins: Arc<Mutex<FailedLoginMap>>,
     key: Key,
@@ -43,36 +126,47 @@ impl AppState {
         }
     }
 
+    /// Trigger [`AppEvent::UserMfaChanged`] if MFA state of the user differs from `previous`.
+    pub(crate) fn trigger_mfa_changed(&self, user: &User<Id>, previous: MfaState) {
+        if let Some(data) = UserMfaChangedData::new(user, previous) {
+            self.trigger_action(AppEvent::UserMfaChanged(data));
+        }
+    }
+
     /// Handle webhook events
     async fn handle_triggers(pool: PgPool, mut rx: UnboundedReceiver<AppEvent>) {
-        let reqwest_client = Client::builder().user_agent("reqwest").build().unwrap();
+        let reqwest_client = webhook_client();
         while let Some(msg) = rx.recv().await {
             debug!("WebHook triggered");
             debug!("Retrieving webhooks");
             if let Ok(webhooks) = WebHook::all_enabled(&pool, &msg).await {
                 info!("Found webhooks: {webhooks:?}");
-                let (payload, event) = match msg {
-                    AppEvent::UserCreated(user) => (json!(user), "user_created"),
-                    AppEvent::UserModified(user) => (json!(user), "user_modified"),
-                    AppEvent::UserDeleted(username) => {
-                        (json!({ "username": username }), "user_deleted")
-                    }
-                    AppEvent::HWKeyProvision(data) => (json!(data), "user_keys"),
-                };
+                let (payload, event) = msg.payload();
                 for webhook in webhooks {
-                    match reqwest_client
-                        .post(&webhook.url)
-                        .bearer_auth(&webhook.token)
-                        .header("x-defguard-event", event)
-                        .json(&payload)
-                        .send()
-                        .await
-                    {
-                        Ok(res) => {
-                            info!("Trigger sent to {}, status {}", webhook.url, res.status());
+                    let body = match &webhook.body_template {
+                        Some(template) => match msg.render_body(template) {
+                            Ok(body) => body,
+                            Err(err) => {
+                                error!(
+                                    "Failed to render body template of webhook {}: {err}",
+                                    webhook.url
+                                );
+                                continue;
+                            }
+                        },
+                        None => payload.to_string(),
+                    };
+                    // persist delivery before the first attempt, so it can be retried
+                    let delivery =
+                        WebHookDelivery::new(webhook.id, event, body, WEBHOOK_DELIVERY_LEASE)
+                            .save(&pool)
+                            .await;
+                    match delivery {
+                        Ok(delivery) => {
+                            deliver_webhook(&reqwest_client, &pool, &webhook, delivery).await;
                         }
                         Err(err) => {
-                            error!("Error sending trigger to {}: {err}", webhook.url);
+                            error!("Failed to store trigger delivery to {}: {err}", webhook.url);
                         }
                     }
                 }
@@ -80,6 +174,52 @@ impl AppState {
         }
     }
 
+    /// Retry failed webhook deliveries, including the ones pending before a restart.
+    async fn retry_triggers(pool: PgPool) {
+        let reqwest_client = webhook_client();
+        loop {
+            sleep(WEBHOOK_RETRY_INTERVAL).await;
+            let deliveries = match WebHookDelivery::claim_due(&pool, WEBHOOK_DELIVERY_LEASE).await {
+                Ok(deliveries) => deliveries,
+                Err(err) => {
+                    error!("Failed to fetch pending trigger deliveries: {err}");
+                    continue;
+                }
+            };
+            for delivery in deliveries {
+                match WebHook::find_by_id(&pool, delivery.webhook_id).await {
+                    // archived while claimed; the delivery has been discarded already
+                    Ok(Some(webhook)) if webhook.archived => {}
+                    Ok(Some(webhook)) if webhook.enabled => {
+                        // paused webhook keeps the delivery until the lease passes
+                        if webhook
+                            .paused_until
+                            .is_some_and(|until| until > Utc::now().naive_utc())
+                        {
+                            continue;
+                        }
+                        deliver_webhook(&reqwest_client, &pool, &webhook, delivery).await;
+                    }
+                    Ok(_) => {
+                        debug!(
+                            "Dropping trigger delivery {} of disabled webhook {}",
+                            delivery.id, delivery.webhook_id
+                        );
+                        if let Err(err) = delivery.discard(&pool).await {
+                            error!("Failed to discard trigger delivery: {err}");
+                        }
+                    }
+                    Err(err) => {
+                        error!(
+                            "Failed to fetch webhook {} for retry: {err}",
+                            delivery.webhook_id
+                        );
+                    }
+                }
+            }
+        }
+    }
+
     /// Sends given `GatewayEvent` to be handled by gateway GRPC server.
     /// Convenience wrapper around [`send_wireguard_event`]
     pub fn send_wireguard_event(&self, event: GatewayEvent) {
@@ -102,6 +242,7 @@ impl AppState {
         failed_logins: Arc<Mutex<FailedLoginMap>>,
     ) -> Self {
         spawn(Self::handle_triggers(pool.clone(), rx));
+        spawn(Self::retry_triggers(pool.clone()));
 
         let config = server_config();
         let webauthn_builder = WebauthnBuilder::new(
@@ -125,6 +266,7 @@ impl AppState {
             tx,
             wireguard_tx,
             mail_tx,
+            sms_sender: Arc::new(TwilioSmsSender::from_config()),
             webauthn,
             failed_logins,
             key,
@@ -38,6 +38,8 @@ pub static YUBIBRIDGE_SECRET_ENV: &str = "DEFGUARD_YUBIBRIDGE_SECRET";
 pub const TOTP_CODE_VALIDITY_PERIOD: u64 = 30;
 pub const EMAIL_CODE_DIGITS: u32 = 6;
 pub const TOTP_CODE_DIGITS: u32 = 6;
+// issuer shown in authenticator apps
+pub const TOTP_ISSUER: &str = "Defguard";
 
 #[derive(Clone, Copy, Default)]
 pub enum ClaimsType {
@@ -172,11 +174,14 @@ where
         if let Some(session_cookie) = cookies.get(SESSION_COOKIE_NAME) {
             return {
                 match Session::find_by_id(&appstate.pool, session_cookie.value()).await {
-                    Ok(Some(session)) => {
+                    Ok(Some(mut session)) => {
                         if session.expired() {
                             let _result = session.delete(&appstate.pool).await;
                             Err(WebError::Authorization("Session expired".into()))
                         } else {
+                            if let Err(err) = session.touch_last_seen(&appstate.pool).await {
+                                warn!("Failed to update session last seen timestamp: {err}");
+                            }
                             Ok(session)
                         }
                     }
@@ -215,16 +220,13 @@ impl SessionInfo {
             .iter()
             .any(|group| group_names.contains(&group.name.as_str()))
     }
-}
-
-impl<S> FromRequestParts<S> for SessionInfo
-where
-    S: Send + Sync,
-    AppState: FromRef<S>,
-{
-    type Rejection = WebError;
 
-    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+    /// Extract session regardless of [`User::must_change_password`] flag.
+    async fn from_parts<S>(parts: &mut Parts, state: &S) -> Result<Self, WebError>
+    where
+        S: Send + Sync,
+        AppState: FromRef<S>,
+    {
         let session = Session::from_request_parts(parts, state).await?;
         let appstate = AppState::from_ref(state);
         let user = User::find_by_id(&appstate.pool, session.user_id).await?;
@@ -260,6 +262,40 @@ where
     }
 }
 
+impl<S> FromRequestParts<S> for SessionInfo
+where
+    S: Send + Sync,
+    AppState: FromRef<S>,
+{
+    type Rejection = WebError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let session_info = Self::from_parts(parts, state).await?;
+        if session_info.user.must_change_password
+            && session_info.session.state != SessionState::ApiTokenVerified
+        {
+            return Err(WebError::PasswordChangeRequired);
+        }
+        Ok(session_info)
+    }
+}
+
+/// Like [`SessionInfo`], but also accepted while the user is required to change their
+/// password. Only meant for the password change endpoint.
+pub struct PasswordChangeSession(pub SessionInfo);
+
+impl<S> FromRequestParts<S> for PasswordChangeSession
+where
+    S: Send + Sync,
+    AppState: FromRef<S>,
+{
+    type Rejection = WebError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        SessionInfo::from_parts(parts, state).await.map(Self)
+    }
+}
+
 #[macro_export]
 macro_rules! role {
     ($name:ident, $($permission:path)*) => {
@@ -280,6 +316,9 @@ macro_rules! role {
                 if !session_info.user.is_active {
                     return Err(WebError::Forbidden("user is disabled".into()));
                 }
+                if let Some(until) = session_info.user.active_suspension() {
+                    return Err(WebError::UserSuspended(until));
+                }
                 let appstate = AppState::from_ref(state);
                 $(
                 let groups_with_permission = Group::find_by_permission(
@@ -123,14 +123,14 @@ async fn main() -> Result<(), anyhow::Error> {
 
     // run services
     tokio::select! {
-        res = run_grpc_bidi_stream(pool.clone(), wireguard_tx.clone(), mail_tx.clone()), if config.proxy_url.is_some() => error!("Proxy gRPC stream returned early: {res:?}"),
+        res = run_grpc_bidi_stream(pool.clone(), wireguard_tx.clone(), mail_tx.clone(), webhook_tx.clone()), if config.proxy_url.is_some() => error!("Proxy gRPC stream returned early: {res:?}"),
         res = run_grpc_server(Arc::clone(&worker_state), pool.clone(), Arc::clone(&gateway_state), wireguard_tx.clone(), mail_tx.clone(), grpc_cert, grpc_key, failed_logins.clone()) => error!("gRPC server returned early: {res:?}"),
-        res = run_web_server(worker_state, gateway_state, webhook_tx, webhook_rx, wireguard_tx.clone(), mail_tx, pool.clone(), failed_logins) => error!("Web server returned early: {res:?}"),
+        res = run_web_server(worker_state, gateway_state, webhook_tx.clone(), webhook_rx, wireguard_tx.clone(), mail_tx.clone(), pool.clone(), failed_logins) => error!("Web server returned early: {res:?}"),
         res = run_mail_handler(mail_rx, pool.clone()) => error!("Mail handler returned early: {res:?}"),
         res = run_periodic_peer_disconnect(pool.clone(), wireguard_tx.clone()) => error!("Periodic peer disconnect task returned early: {res:?}"),
         res = run_periodic_stats_purge(pool.clone(), config.stats_purge_frequency.into(), config.stats_purge_threshold.into()), if !config.disable_stats_purge => error!("Periodic stats purge task returned early: {res:?}"),
         res = run_periodic_license_check(&pool) => error!("Periodic license check task returned early: {res:?}"),
-        res = run_utility_thread(&pool, wireguard_tx) => error!("Utility thread returned early: {res:?}"),
+        res = run_utility_thread(&pool, wireguard_tx, webhook_tx, mail_tx) => error!("Utility thread returned early: {res:?}"),
     }
     Ok(())
 }
@@ -0,0 +1,188 @@
+use std::sync::OnceLock;
+
+use aes_gcm::{
+    aead::{Aead, KeyInit},
+    Aes256Gcm, Key, Nonce,
+};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use rand::{thread_rng, Rng};
+use thiserror::Error;
+
+use crate::server_config;
+
+/// Length of keys used by [`AesGcmCipher`] in bytes.
+pub const SECRET_ENCRYPTION_KEY_LENGTH: usize = 32;
+/// Length of AES-GCM nonce in bytes.
+const NONCE_LENGTH: usize = 12;
+/// Prefix of values produced by [`seal`], distinguishing them from legacy plaintext.
+const SEALED_PREFIX: &str = "enc:v1:";
+
+static SECRET_CIPHER: OnceLock<Box<dyn SecretCipher>> = OnceLock::new();
+
+#[derive(Debug, Error)]
+pub enum CipherError {
+    #[error("Encryption failed")]
+    Encryption,
+    #[error("Decryption failed")]
+    Decryption,
+    #[error("Malformed encrypted value")]
+    Malformed,
+}
+
+/// Key encryption key used to wrap data keys of secrets stored in the database.
+/// Implemented by [`AesGcmCipher`] using a key from configuration; other implementations
+/// may delegate to a key management service.
+pub trait SecretCipher: Send + Sync {
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, CipherError>;
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, CipherError>;
+}
+
+/// AES-256-GCM with a random nonce prepended to the ciphertext.
+pub struct AesGcmCipher {
+    cipher: Aes256Gcm,
+}
+
+impl AesGcmCipher {
+    #[must_use]
+    pub fn new(key: &[u8; SECRET_ENCRYPTION_KEY_LENGTH]) -> Self {
+        Self {
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key)),
+        }
+    }
+}
+
+impl SecretCipher for AesGcmCipher {
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, CipherError> {
+        let nonce: [u8; NONCE_LENGTH] = thread_rng().gen();
+        let ciphertext = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), plaintext)
+            .map_err(|_| CipherError::Encryption)?;
+        Ok([nonce.as_slice(), &ciphertext].concat())
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, CipherError> {
+        if ciphertext.len() < NONCE_LENGTH {
+            return Err(CipherError::Malformed);
+        }
+        let (nonce, ciphertext) = ciphertext.split_at(NONCE_LENGTH);
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| CipherError::Decryption)
+    }
+}
+
+/// Set cipher used for secrets stored in the database. Returns `false` if it has
+/// already been set, either explicitly or by the first use of [`secret_cipher`].
+pub fn set_secret_cipher(cipher: Box<dyn SecretCipher>) -> bool {
+    SECRET_CIPHER.set(cipher).is_ok()
+}
+
+/// Cipher used for secrets stored in the database, by default [`AesGcmCipher`] with
+/// the key from configuration.
+pub fn secret_cipher() -> &'static dyn SecretCipher {
+    SECRET_CIPHER
+        .get_or_init(|| Box::new(AesGcmCipher::new(&server_config().secret_encryption_key())))
+        .as_ref()
+}
+
+/// Check if the value has been produced by [`seal`].
+#[must_use]
+pub fn is_sealed(value: &str) -> bool {
+    value.starts_with(SEALED_PREFIX)
+}
+
+/// Encrypt secret with envelope encryption: the secret is encrypted with a random data key,
+/// which in turn is encrypted (wrapped) with `cipher`.
+pub fn seal(cipher: &dyn SecretCipher, secret: &str) -> Result<String, CipherError> {
+    let data_key: [u8; SECRET_ENCRYPTION_KEY_LENGTH] = thread_rng().gen();
+    let ciphertext = AesGcmCipher::new(&data_key).encrypt(secret.as_bytes())?;
+    let wrapped_key = cipher.encrypt(&data_key)?;
+    Ok(format!(
+        "{SEALED_PREFIX}{}:{}",
+        BASE64_STANDARD.encode(wrapped_key),
+        BASE64_STANDARD.encode(ciphertext)
+    ))
+}
+
+/// Decrypt secret encrypted with [`seal`].
+pub fn open(cipher: &dyn SecretCipher, sealed: &str) -> Result<String, CipherError> {
+    let (wrapped_key, ciphertext) = sealed
+        .strip_prefix(SEALED_PREFIX)
+        .and_then(|value| value.split_once(':'))
+        .ok_or(CipherError::Malformed)?;
+    let wrapped_key = BASE64_STANDARD
+        .decode(wrapped_key)
+        .map_err(|_| CipherError::Malformed)?;
+    let ciphertext = BASE64_STANDARD
+        .decode(ciphertext)
+        .map_err(|_| CipherError::Malformed)?;
+    let data_key: [u8; SECRET_ENCRYPTION_KEY_LENGTH] = cipher
+        .decrypt(&wrapped_key)?
+        .try_into()
+        .map_err(|_| CipherError::Malformed)?;
+    let secret = AesGcmCipher::new(&data_key).decrypt(&ciphertext)?;
+    String::from_utf8(secret).map_err(|_| CipherError::Malformed)
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    /// Deterministic cipher which XORs data with a fixed byte.
+    pub(crate) struct XorCipher(pub u8);
+
+    impl SecretCipher for XorCipher {
+        fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, CipherError> {
+            Ok(plaintext.iter().map(|byte| byte ^ self.0).collect())
+        }
+
+        fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, CipherError> {
+            self.encrypt(ciphertext)
+        }
+    }
+
+    #[test]
+    fn test_aes_gcm_cipher() {
+        let cipher = AesGcmCipher::new(&[7; SECRET_ENCRYPTION_KEY_LENGTH]);
+        let ciphertext = cipher.encrypt(b"secret").unwrap();
+        assert_ne!(&ciphertext[NONCE_LENGTH..], b"secret");
+        // random nonce
+        assert_ne!(ciphertext, cipher.encrypt(b"secret").unwrap());
+        assert_eq!(cipher.decrypt(&ciphertext).unwrap(), b"secret");
+
+        let other = AesGcmCipher::new(&[8; SECRET_ENCRYPTION_KEY_LENGTH]);
+        assert!(other.decrypt(&ciphertext).is_err());
+        assert!(cipher.decrypt(b"short").is_err());
+    }
+
+    #[test]
+    fn test_seal_and_open() {
+        let cipher = XorCipher(0x5a);
+        let sealed = seal(&cipher, "client_secret").unwrap();
+        assert!(is_sealed(&sealed));
+        assert!(!sealed.contains("client_secret"));
+        assert_eq!(open(&cipher, &sealed).unwrap(), "client_secret");
+
+        // data key is wrapped with the given cipher
+        let wrapped_key = sealed
+            .strip_prefix(SEALED_PREFIX)
+            .unwrap()
+            .split_once(':')
+            .unwrap()
+            .0;
+        let data_key: Vec<u8> = BASE64_STANDARD
+            .decode(wrapped_key)
+            .unwrap()
+            .iter()
+            .map(|byte| byte ^ 0x5a)
+            .collect();
+        assert_eq!(data_key.len(), SECRET_ENCRYPTION_KEY_LENGTH);
+
+        // wrong key encryption key
+        assert!(open(&XorCipher(0x33), &sealed).is_err());
+        // legacy plaintext
+        assert!(!is_sealed("client_secret"));
+        assert!(open(&cipher, "client_secret").is_err());
+    }
+}
@@ -1,4 +1,7 @@
-use clap::{Args, Parser, Subcommand};
+use argon2::{Algorithm, Argon2, Params, ParamsBuilder, Version};
+use axum::http::StatusCode;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use humantime::Duration;
 use ipnetwork::IpNetwork;
 use openidconnect::{core::CoreRsaPrivateSigningKey, JsonWebKeyId};
@@ -10,6 +13,25 @@ use rsa::{
     RsaPrivateKey,
 };
 use secrecy::{ExposeSecret, SecretString};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    auth::{TOTP_CODE_DIGITS, TOTP_CODE_VALIDITY_PERIOD},
+    cipher::SECRET_ENCRYPTION_KEY_LENGTH,
+    db::models::{
+        authentication_key::{SshKeyAlgorithm, SshKeyPolicy, DEFAULT_SSH_MIN_RSA_BITS},
+        enrollment::{
+            validate_session_timeout, validate_token_timeout, TokenAlphabet,
+            DEFAULT_TOKEN_RANDOM_LENGTH, MAX_TOKEN_PREFIX_LENGTH, MAX_TOKEN_RANDOM_LENGTH,
+            MIN_TOKEN_RANDOM_LENGTH,
+        },
+    },
+    password::{
+        BreachCheckFailure, PasswordCharClass, PasswordValidator, DEFAULT_PASSWORD_MAX_LENGTH,
+        DEFAULT_PASSWORD_MIN_LENGTH,
+    },
+    SERVER_CONFIG,
+};
 
 #[derive(Clone, Parser, Serialize, Debug)]
 #[command(version)]
@@ -29,6 +51,13 @@ pub struct DefGuardConfig {
     #[serde(skip_serializing)]
     pub secret_key: SecretString,
 
+    // base64-encoded 32-byte key encrypting secrets stored in the database;
+    // derived from `secret_key` if not set, in which case changing `secret_key` makes stored
+    // secrets unreadable; set it explicitly before rotating `secret_key`
+    #[arg(long, env = "DEFGUARD_SECRET_ENCRYPTION_KEY")]
+    #[serde(skip_serializing)]
+    pub secret_encryption_key: Option<SecretString>,
+
     #[arg(long, env = "DEFGUARD_DB_HOST", default_value = "localhost")]
     pub database_host: String,
 
@@ -92,7 +121,12 @@ pub struct DefGuardConfig {
     #[arg(long, env = "DEFGUARD_ENROLLMENT_URL", value_parser = Url::parse, default_value = "http://localhost:8080")]
     pub enrollment_url: Url,
 
-    #[arg(long, env = "DEFGUARD_ENROLLMENT_TOKEN_TIMEOUT", default_value = "24h")]
+    #[arg(
+        long,
+        env = "DEFGUARD_ENROLLMENT_TOKEN_TIMEOUT",
+        value_parser = Self::parse_token_timeout,
+        default_value = "24h"
+    )]
     #[serde(skip_serializing)]
     pub enrollment_token_timeout: Duration,
 
@@ -100,6 +134,43 @@ pub struct DefGuardConfig {
     #[serde(skip_serializing)]
     pub mfa_code_timeout: Duration,
 
+    // length of TOTP codes and their validity period (in seconds) for newly configured
+    // authenticators; existing ones keep the values they were provisioned with
+    #[arg(
+        long,
+        env = "DEFGUARD_TOTP_DIGITS",
+        default_value_t = TOTP_CODE_DIGITS,
+        value_parser = clap::value_parser!(u32).range(6..=8)
+    )]
+    pub totp_digits: u32,
+
+    #[arg(
+        long,
+        env = "DEFGUARD_TOTP_PERIOD",
+        default_value_t = TOTP_CODE_VALIDITY_PERIOD,
+        value_parser = clap::value_parser!(u64).range(15..=300)
+    )]
+    pub totp_period: u64,
+
+    // number of TOTP time steps before and after the current one accepted to tolerate clock skew
+    #[arg(long, env = "DEFGUARD_TOTP_DRIFT_STEPS", default_value_t = 1)]
+    pub totp_drift_steps: u64,
+
+    // number of TOTP time steps tolerated when confirming a new authenticator, independent of
+    // `totp_drift_steps`, so setup on a device with skewed clock doesn't fail while login stays strict
+    #[arg(long, env = "DEFGUARD_TOTP_SETUP_DRIFT_STEPS", default_value_t = 2)]
+    pub totp_setup_drift_steps: u64,
+
+    // maximum number of security keys (WebAuthn) a user can register; admins are not limited
+    #[arg(long, env = "DEFGUARD_MAX_WEBAUTHN_KEYS_PER_USER", default_value_t = 5)]
+    pub max_webauthn_keys_per_user: usize,
+
+    // maximum number of devices a user can have connected at the same time to MFA-protected
+    // locations; devices over the limit which connected last are disconnected, so they have
+    // to pass MFA again to reconnect
+    #[arg(long, env = "DEFGUARD_MAX_CONCURRENT_DEVICES_PER_USER")]
+    pub max_concurrent_devices_per_user: Option<usize>,
+
     #[arg(long, env = "DEFGUARD_SESSION_TIMEOUT", default_value = "7d")]
     #[serde(skip_serializing)]
     pub session_timeout: Duration,
@@ -107,6 +178,7 @@ pub struct DefGuardConfig {
     #[arg(
         long,
         env = "DEFGUARD_PASSWORD_RESET_TOKEN_TIMEOUT",
+        value_parser = Self::parse_token_timeout,
         default_value = "24h"
     )]
     #[serde(skip_serializing)]
@@ -115,6 +187,7 @@ pub struct DefGuardConfig {
     #[arg(
         long,
         env = "DEFGUARD_ENROLLMENT_SESSION_TIMEOUT",
+        value_parser = Self::parse_session_timeout,
         default_value = "10m"
     )]
     #[serde(skip_serializing)]
@@ -123,11 +196,212 @@ pub struct DefGuardConfig {
     #[arg(
         long,
         env = "DEFGUARD_PASSWORD_RESET_SESSION_TIMEOUT",
+        value_parser = Self::parse_session_timeout,
         default_value = "10m"
     )]
     #[serde(skip_serializing)]
     pub password_reset_session_timeout: Duration,
 
+    // prepended to enrollment and password reset tokens (e.g. "dgenr_") to make them
+    // recognizable in logs and by secret scanners
+    #[arg(
+        long,
+        env = "DEFGUARD_ENROLLMENT_TOKEN_PREFIX",
+        value_parser = Self::parse_token_prefix,
+        default_value = ""
+    )]
+    pub enrollment_token_prefix: String,
+
+    // length of the random part of enrollment and password reset tokens
+    #[arg(
+        long,
+        env = "DEFGUARD_ENROLLMENT_TOKEN_LENGTH",
+        value_parser = Self::parse_token_length,
+        default_value_t = DEFAULT_TOKEN_RANDOM_LENGTH
+    )]
+    pub enrollment_token_length: usize,
+
+    // characters used in enrollment and password reset tokens; "unambiguous" leaves out
+    // characters which are easy to confuse when tokens are typed by hand
+    #[arg(
+        long,
+        env = "DEFGUARD_ENROLLMENT_TOKEN_ALPHABET",
+        value_enum,
+        default_value = "alphanumeric"
+    )]
+    #[serde(skip_serializing)]
+    pub enrollment_token_alphabet: TokenAlphabet,
+
+    // what to do with a started enrollment when the enrollment email can't be delivered
+    #[arg(
+        long,
+        env = "DEFGUARD_ENROLLMENT_MAIL_FAILURE_POLICY",
+        value_enum,
+        default_value = "warn"
+    )]
+    #[serde(skip_serializing)]
+    pub enrollment_mail_failure_policy: EnrollmentMailFailurePolicy,
+
+    // text enrollment link also to users who have an email address; users without
+    // email address get a text message whenever they have a phone number
+    #[arg(long, env = "DEFGUARD_ENROLLMENT_SMS_WITH_EMAIL")]
+    pub enrollment_sms_with_email: bool,
+
+    // Twilio credentials and sender phone number for text messages
+    #[arg(long, env = "DEFGUARD_SMS_TWILIO_ACCOUNT_SID")]
+    pub sms_twilio_account_sid: Option<String>,
+
+    #[arg(long, env = "DEFGUARD_SMS_TWILIO_AUTH_TOKEN")]
+    #[serde(skip_serializing)]
+    pub sms_twilio_auth_token: Option<SecretString>,
+
+    #[arg(long, env = "DEFGUARD_SMS_FROM")]
+    pub sms_from: Option<String>,
+
+    // unused enrollment tokens older than this trigger the enrollment pending webhook
+    #[arg(
+        long,
+        env = "DEFGUARD_ENROLLMENT_PENDING_THRESHOLD",
+        default_value = "12h"
+    )]
+    #[serde(skip_serializing)]
+    pub enrollment_pending_threshold: Duration,
+
+    #[arg(
+        long,
+        env = "DEFGUARD_RECOVERY_CODES_BUNDLE_TIMEOUT",
+        default_value = "5m"
+    )]
+    #[serde(skip_serializing)]
+    pub recovery_codes_bundle_timeout: Duration,
+
+    // minimum time between recovery codes regenerations requested by the user
+    #[arg(
+        long,
+        env = "DEFGUARD_RECOVERY_CODES_REGENERATION_COOLDOWN",
+        default_value = "1h"
+    )]
+    #[serde(skip_serializing)]
+    pub recovery_codes_regeneration_cooldown: Duration,
+
+    // status code returned when request data fails validation
+    #[arg(
+        long,
+        env = "DEFGUARD_VALIDATION_ERROR_STATUS",
+        value_enum,
+        default_value = "400"
+    )]
+    #[serde(skip_serializing)]
+    pub validation_error_status: ValidationErrorStatus,
+
+    // cost of Argon2 password hashing; changes only apply to newly created hashes
+    #[arg(long, env = "DEFGUARD_ARGON2_MEMORY_KIB", default_value_t = Params::DEFAULT_M_COST)]
+    pub argon2_memory_kib: u32,
+
+    #[arg(long, env = "DEFGUARD_ARGON2_ITERATIONS", default_value_t = Params::DEFAULT_T_COST)]
+    pub argon2_iterations: u32,
+
+    #[arg(long, env = "DEFGUARD_ARGON2_PARALLELISM", default_value_t = Params::DEFAULT_P_COST)]
+    pub argon2_parallelism: u32,
+
+    // server-side secret mixed into passwords before hashing; hashes record the pepper
+    // version, so previous peppers must be kept until all passwords have been rehashed
+    #[arg(long, env = "DEFGUARD_PASSWORD_PEPPER")]
+    #[serde(skip_serializing)]
+    pub password_pepper: Option<SecretString>,
+
+    #[arg(long, env = "DEFGUARD_PASSWORD_PEPPER_VERSION", default_value_t = 1)]
+    pub password_pepper_version: u32,
+
+    // comma-separated `<version>:<pepper>` pairs
+    #[arg(
+        long,
+        env = "DEFGUARD_PASSWORD_PREVIOUS_PEPPERS",
+        value_delimiter = ','
+    )]
+    #[serde(skip_serializing)]
+    pub password_previous_peppers: Vec<SecretString>,
+
+    // password policy applied to new passwords; existing passwords are not affected
+    #[arg(long, env = "DEFGUARD_PASSWORD_MIN_LENGTH", default_value_t = DEFAULT_PASSWORD_MIN_LENGTH)]
+    pub password_min_length: usize,
+
+    #[arg(long, env = "DEFGUARD_PASSWORD_MAX_LENGTH", default_value_t = DEFAULT_PASSWORD_MAX_LENGTH)]
+    pub password_max_length: usize,
+
+    #[arg(
+        long,
+        env = "DEFGUARD_PASSWORD_REQUIRED_CLASSES",
+        value_enum,
+        value_delimiter = ',',
+        default_value = "lowercase,uppercase,digit,special"
+    )]
+    pub password_required_classes: Vec<PasswordCharClass>,
+
+    // reject new passwords found in known data breaches more than this many times,
+    // checked with HaveIBeenPwned; disabled if not set
+    #[arg(long, env = "DEFGUARD_PASSWORD_BREACH_THRESHOLD")]
+    pub password_breach_threshold: Option<u64>,
+
+    // whether to accept (open) or reject (closed) passwords if breach check fails
+    #[arg(
+        long,
+        env = "DEFGUARD_PASSWORD_BREACH_CHECK_FAILURE",
+        value_enum,
+        default_value = "open"
+    )]
+    pub password_breach_check_failure: BreachCheckFailure,
+
+    // passwords older than this have to be changed after logging in; disabled if not set
+    #[arg(long, env = "DEFGUARD_PASSWORD_MAX_AGE")]
+    #[serde(skip_serializing)]
+    pub password_max_age: Option<Duration>,
+
+    // number of previous passwords which can't be reused; 0 disables password history
+    #[arg(long, env = "DEFGUARD_PASSWORD_HISTORY_DEPTH", default_value_t = 0)]
+    pub password_history_depth: usize,
+
+    // SSH key algorithms accepted when adding authentication keys
+    #[arg(
+        long,
+        env = "DEFGUARD_SSH_ALLOWED_KEY_ALGORITHMS",
+        value_enum,
+        value_delimiter = ',',
+        default_value = "rsa,ecdsa,ed25519,sk-ecdsa,sk-ed25519"
+    )]
+    pub ssh_allowed_key_algorithms: Vec<SshKeyAlgorithm>,
+
+    #[arg(long, env = "DEFGUARD_SSH_MIN_RSA_BITS", default_value_t = DEFAULT_SSH_MIN_RSA_BITS)]
+    pub ssh_min_rsa_bits: usize,
+
+    // reject SSH keys already registered by another user
+    #[arg(long, env = "DEFGUARD_SSH_KEY_GLOBAL_UNIQUE", default_value_t = false)]
+    pub ssh_key_global_unique: bool,
+
+    // YubiKeys which weren't provisioned, re-enabled or reported as used for this long
+    // are disabled; disabled if not set
+    #[arg(long, env = "DEFGUARD_YUBIKEY_UNUSED_DISABLE_THRESHOLD")]
+    #[serde(skip_serializing)]
+    pub yubikey_unused_disable_threshold: Option<Duration>,
+
+    // minimum response time of the public SSH authorized keys endpoint; disabled if not set
+    #[arg(long, env = "DEFGUARD_SSH_AUTHORIZED_KEYS_MIN_RESPONSE_TIME")]
+    #[serde(skip_serializing)]
+    pub ssh_authorized_keys_min_response_time: Option<Duration>,
+
+    // how long discovery documents of external OpenID providers are cached
+    #[arg(
+        long,
+        env = "DEFGUARD_OPENID_DISCOVERY_CACHE_TTL",
+        default_value = "1h"
+    )]
+    #[serde(skip_serializing)]
+    pub openid_discovery_cache_ttl: Duration,
+
+    // keyserver used to fetch GPG keys by fingerprint; must support the VKS API
+    #[arg(long, env = "DEFGUARD_GPG_KEYSERVER_URL", value_parser = Url::parse, default_value = "https://keys.openpgp.org")]
+    pub gpg_keyserver_url: Url,
+
     #[arg(long, env = "DEFGUARD_COOKIE_DOMAIN")]
     pub cookie_domain: Option<String>,
 
@@ -159,6 +433,118 @@ pub struct DefGuardConfig {
     pub check_period_renewal_window: Duration,
 }
 
+/// HTTP status used for rejected request data.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum ValidationErrorStatus {
+    #[default]
+    #[value(name = "400")]
+    BadRequest,
+    #[value(name = "422")]
+    UnprocessableEntity,
+}
+
+impl ValidationErrorStatus {
+    #[must_use]
+    pub fn status_code(self) -> StatusCode {
+        match self {
+            Self::BadRequest => StatusCode::BAD_REQUEST,
+            Self::UnprocessableEntity => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+}
+
+/// Handling of enrollment email delivery failures.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum EnrollmentMailFailurePolicy {
+    /// Remove the new token and fail the enrollment.
+    Abort,
+    /// Keep the token and report the failure, so the email can be resent.
+    #[default]
+    Warn,
+}
+
+/// Argon2 cost parameters used when hashing passwords.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Argon2Params {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for Argon2Params {
+    fn default() -> Self {
+        Self {
+            memory_kib: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+impl Argon2Params {
+    /// Parameters from server configuration, or crate defaults if configuration is not loaded.
+    #[must_use]
+    pub fn current() -> Self {
+        SERVER_CONFIG
+            .get()
+            .map(DefGuardConfig::argon2_params)
+            .unwrap_or_default()
+    }
+
+    /// Build an Argon2 instance (Argon2id, v19) using these parameters.
+    pub fn hasher(&self) -> Result<Argon2<'static>, argon2::Error> {
+        let params = Params::new(self.memory_kib, self.iterations, self.parallelism, None)?;
+        Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+    }
+
+    /// Like [`Self::hasher`], but produced hashes carry `keyid` parameter.
+    pub fn hasher_with_keyid(&self, keyid: &[u8]) -> Result<Argon2<'static>, argon2::Error> {
+        let params = ParamsBuilder::new()
+            .m_cost(self.memory_kib)
+            .t_cost(self.iterations)
+            .p_cost(self.parallelism)
+            .keyid(keyid)
+            .build()?;
+        Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+    }
+}
+
+/// Server-side secret mixed into passwords before hashing.
+#[derive(Clone, Debug)]
+pub struct PasswordPepper {
+    /// Stored in password hashes to find the pepper on verification. Version 0 is reserved
+    /// for hashes created without a pepper.
+    pub version: u32,
+    pub secret: SecretString,
+}
+
+/// Pepper used for new password hashes and previous ones, still needed to verify
+/// hashes created before rotation.
+#[derive(Clone, Debug, Default)]
+pub struct PasswordPeppers {
+    pub active: Option<PasswordPepper>,
+    pub previous: Vec<PasswordPepper>,
+}
+
+impl PasswordPeppers {
+    /// Peppers from server configuration, or none if configuration is not loaded.
+    #[must_use]
+    pub fn current() -> Self {
+        SERVER_CONFIG
+            .get()
+            .map(DefGuardConfig::password_peppers)
+            .unwrap_or_default()
+    }
+
+    #[must_use]
+    pub fn find(&self, version: u32) -> Option<&PasswordPepper> {
+        self.active
+            .iter()
+            .chain(&self.previous)
+            .find(|pepper| pepper.version == version)
+    }
+}
+
 #[derive(Clone, Debug, Subcommand)]
 pub enum Command {
     #[command(
@@ -194,6 +580,9 @@ impl DefGuardConfig {
         config.validate_rp_id();
         config.validate_cookie_domain();
         config.validate_secret_key();
+        config.validate_secret_encryption_key();
+        config.validate_argon2_params();
+        config.validate_password_peppers();
         config
     }
 
@@ -246,6 +635,133 @@ impl DefGuardConfig {
         );
     }
 
+    fn validate_secret_encryption_key(&self) {
+        if let Some(key) = &self.secret_encryption_key {
+            let length = BASE64_STANDARD
+                .decode(key.expose_secret())
+                .map(|key| key.len());
+            assert!(
+                length == Ok(SECRET_ENCRYPTION_KEY_LENGTH),
+                "SECRET_ENCRYPTION_KEY must be {SECRET_ENCRYPTION_KEY_LENGTH} bytes encoded in base64"
+            );
+        }
+    }
+
+    fn validate_argon2_params(&self) {
+        if let Err(err) = self.argon2_params().hasher() {
+            panic!("Invalid Argon2 parameters: {err}");
+        }
+    }
+
+    fn validate_password_peppers(&self) {
+        assert!(
+            self.password_pepper_version > 0,
+            "PASSWORD_PEPPER_VERSION must be greater than 0"
+        );
+        let mut versions = vec![self.password_pepper_version];
+        for entry in &self.password_previous_peppers {
+            let version = entry
+                .expose_secret()
+                .split_once(':')
+                .filter(|(_, pepper)| !pepper.is_empty())
+                .and_then(|(version, _)| version.parse::<u32>().ok())
+                .filter(|version| *version > 0)
+                .expect("PASSWORD_PREVIOUS_PEPPERS entries must be `<version>:<pepper>` pairs");
+            assert!(
+                !versions.contains(&version),
+                "Password pepper version {version} is configured more than once"
+            );
+            versions.push(version);
+        }
+    }
+
+    #[must_use]
+    pub fn password_peppers(&self) -> PasswordPeppers {
+        let active = self.password_pepper.as_ref().map(|secret| PasswordPepper {
+            version: self.password_pepper_version,
+            secret: secret.clone(),
+        });
+        let previous = self
+            .password_previous_peppers
+            .iter()
+            .filter_map(|entry| {
+                let (version, secret) = entry.expose_secret().split_once(':')?;
+                Some(PasswordPepper {
+                    version: version.parse().ok()?,
+                    secret: secret.to_string().into(),
+                })
+            })
+            .collect();
+        PasswordPeppers { active, previous }
+    }
+
+    #[must_use]
+    pub fn argon2_params(&self) -> Argon2Params {
+        Argon2Params {
+            memory_kib: self.argon2_memory_kib,
+            iterations: self.argon2_iterations,
+            parallelism: self.argon2_parallelism,
+        }
+    }
+
+    #[must_use]
+    pub fn password_validator(&self) -> PasswordValidator {
+        PasswordValidator {
+            min_length: self.password_min_length,
+            max_length: self.password_max_length,
+            required_classes: self.password_required_classes.clone(),
+        }
+    }
+
+    #[must_use]
+    pub fn ssh_key_policy(&self) -> SshKeyPolicy {
+        SshKeyPolicy {
+            allowed_algorithms: self.ssh_allowed_key_algorithms.clone(),
+            min_rsa_bits: self.ssh_min_rsa_bits,
+        }
+    }
+
+    /// Token prefix may contain only ASCII letters, digits, `_` and `-`.
+    fn parse_token_prefix(prefix: &str) -> Result<String, String> {
+        if prefix.len() > MAX_TOKEN_PREFIX_LENGTH {
+            return Err(format!(
+                "token prefix can't be longer than {MAX_TOKEN_PREFIX_LENGTH} characters"
+            ));
+        }
+        if !prefix
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err("token prefix may contain only letters, digits, '_' and '-'".into());
+        }
+        Ok(prefix.to_string())
+    }
+
+    /// Token length has to stay within [`MIN_TOKEN_RANDOM_LENGTH`] and [`MAX_TOKEN_RANDOM_LENGTH`].
+    fn parse_token_length(length: &str) -> Result<usize, String> {
+        let length: usize = length.parse().map_err(|err| format!("{err}"))?;
+        if !(MIN_TOKEN_RANDOM_LENGTH..=MAX_TOKEN_RANDOM_LENGTH).contains(&length) {
+            return Err(format!(
+                "token length has to be between {MIN_TOKEN_RANDOM_LENGTH} and {MAX_TOKEN_RANDOM_LENGTH}"
+            ));
+        }
+        Ok(length)
+    }
+
+    /// Token timeouts are checked on startup, so that tokens can always be issued.
+    fn parse_token_timeout(timeout: &str) -> Result<Duration, String> {
+        let timeout: Duration = timeout.parse().map_err(|err| format!("{err}"))?;
+        validate_token_timeout(timeout.as_secs()).map_err(|err| err.to_string())?;
+        Ok(timeout)
+    }
+
+    /// Session timeouts are checked on startup, so that sessions can always be started.
+    fn parse_session_timeout(timeout: &str) -> Result<Duration, String> {
+        let timeout: Duration = timeout.parse().map_err(|err| format!("{err}"))?;
+        validate_session_timeout(timeout.as_secs()).map_err(|err| err.to_string())?;
+        Ok(timeout)
+    }
+
     /// Try PKCS#1 and PKCS#8 PEM formats.
     fn parse_openid_key(path: &str) -> Result<RsaPrivateKey, rsa::pkcs8::Error> {
         if let Ok(key) = RsaPrivateKey::read_pkcs1_pem_file(path) {
@@ -266,6 +782,26 @@ impl DefGuardConfig {
         }
     }
 
+    /// Key encrypting secrets stored in the database. Unless configured explicitly,
+    /// it's derived from the secret key, so rotating the secret key without setting this one
+    /// to the previously derived value breaks decryption of already stored secrets.
+    #[must_use]
+    pub fn secret_encryption_key(&self) -> [u8; SECRET_ENCRYPTION_KEY_LENGTH] {
+        if let Some(key) = self
+            .secret_encryption_key
+            .as_ref()
+            .and_then(|key| BASE64_STANDARD.decode(key.expose_secret()).ok())
+            .and_then(|key| key.try_into().ok())
+        {
+            return key;
+        }
+        Sha256::new()
+            .chain_update(b"defguard secret encryption key")
+            .chain_update(self.secret_key.expose_secret())
+            .finalize()
+            .into()
+    }
+
     /// Returns configured URL with "auth/callback" appended to the path.
     #[must_use]
     pub(crate) fn callback_url(&self) -> Url {
@@ -334,6 +870,24 @@ mod tests {
         assert_eq!(config.cookie_domain, Some("example.com".to_string()));
     }
 
+    #[test]
+    fn test_validation_error_status() {
+        env::remove_var("DEFGUARD_VALIDATION_ERROR_STATUS");
+        let config = DefGuardConfig::new();
+        assert_eq!(
+            config.validation_error_status,
+            ValidationErrorStatus::BadRequest
+        );
+
+        env::set_var("DEFGUARD_VALIDATION_ERROR_STATUS", "422");
+        let config = DefGuardConfig::new();
+        assert_eq!(
+            config.validation_error_status.status_code(),
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+        env::remove_var("DEFGUARD_VALIDATION_ERROR_STATUS");
+    }
+
     #[test]
     fn test_callback_url() {
         env::set_var("DEFGUARD_URL", "https://defguard.example.com");
@@ -350,4 +904,59 @@ mod tests {
             "https://defguard.example.com:8443/path/auth/callback"
         );
     }
+
+    #[test]
+    fn test_enrollment_token_format() {
+        let config = DefGuardConfig::try_parse_from(["defguard"]).unwrap();
+        assert_eq!(config.enrollment_token_length, DEFAULT_TOKEN_RANDOM_LENGTH);
+        assert_eq!(
+            config.enrollment_token_alphabet,
+            TokenAlphabet::Alphanumeric
+        );
+
+        let config = DefGuardConfig::try_parse_from([
+            "defguard",
+            "--enrollment-token-length",
+            "64",
+            "--enrollment-token-alphabet",
+            "unambiguous",
+        ])
+        .unwrap();
+        assert_eq!(config.enrollment_token_length, 64);
+        assert_eq!(config.enrollment_token_alphabet, TokenAlphabet::Unambiguous);
+
+        for length in ["16", "129", "long"] {
+            assert!(DefGuardConfig::try_parse_from([
+                "defguard",
+                "--enrollment-token-length",
+                length
+            ])
+            .is_err());
+        }
+    }
+
+    #[test]
+    fn test_enrollment_timeouts() {
+        let config = DefGuardConfig::try_parse_from([
+            "defguard",
+            "--enrollment-token-timeout",
+            "7d",
+            "--enrollment-session-timeout",
+            "1h",
+        ])
+        .unwrap();
+        assert_eq!(config.enrollment_token_timeout.as_secs(), 7 * 24 * 3600);
+        assert_eq!(config.enrollment_session_timeout.as_secs(), 3600);
+
+        for (arg, timeout) in [
+            ("--enrollment-token-timeout", "0s"),
+            ("--enrollment-token-timeout", "1year"),
+            ("--password-reset-token-timeout", "500ms"),
+            ("--enrollment-session-timeout", "0s"),
+            ("--password-reset-session-timeout", "7d"),
+            ("--password-reset-session-timeout", "soon"),
+        ] {
+            assert!(DefGuardConfig::try_parse_from(["defguard", arg, timeout]).is_err());
+        }
+    }
 }
@@ -1,7 +1,17 @@
+use chrono::{NaiveDateTime, Utc};
+use clap::ValueEnum;
 use model_derive::Model;
-use sqlx::{query_as, Error as SqlxError, PgExecutor, Type};
+use sqlx::{query, query_as, query_scalar, Error as SqlxError, PgExecutor, Type};
+use ssh_key::{public::KeyData, Algorithm, PublicKey};
+use thiserror::Error;
 
-use crate::db::{Id, NoId};
+use crate::{
+    db::{Id, NoId},
+    SERVER_CONFIG,
+};
+
+/// Minimum RSA modulus size used when configuration is not loaded.
+pub const DEFAULT_SSH_MIN_RSA_BITS: usize = 2048;
 
 #[derive(Clone, Debug, Deserialize, Serialize, Type)]
 #[sqlx(type_name = "authentication_key_type", rename_all = "lowercase")]
@@ -11,6 +21,243 @@ pub(crate) enum AuthenticationKeyType {
     Gpg,
 }
 
+/// Context in which a key may be used: interactive logins, deployments (CI, bots) or both.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize, Type)]
+#[sqlx(type_name = "authentication_key_usage", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum AuthenticationKeyUsage {
+    #[default]
+    Interactive,
+    Deploy,
+    Both,
+}
+
+/// SSH public key split into parts of the OpenSSH format:
+/// `<algorithm> <base64 body> [comment]`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub(crate) struct SshKeyParts {
+    pub algorithm: String,
+    pub body: String,
+    pub comment: Option<String>,
+}
+
+impl SshKeyParts {
+    /// Split stored key into its parts. Comment may contain whitespace.
+    /// Returns `None` if algorithm or body is missing.
+    #[must_use]
+    pub fn parse(key: &str) -> Option<Self> {
+        let (algorithm, rest) = key.trim().split_once(char::is_whitespace)?;
+        let rest = rest.trim_start();
+        let (body, comment) = match rest.split_once(char::is_whitespace) {
+            Some((body, comment)) => (body, Some(comment.trim())),
+            None => (rest, None),
+        };
+        if body.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            algorithm: algorithm.to_string(),
+            body: body.to_string(),
+            comment: comment
+                .filter(|comment| !comment.is_empty())
+                .map(ToString::to_string),
+        })
+    }
+
+    /// Key without comment and with single space separator, used to compare keys.
+    #[must_use]
+    pub fn normalize(key: &str) -> Option<String> {
+        Self::parse(key).map(|parts| format!("{} {}", parts.algorithm, parts.body))
+    }
+}
+
+/// `authorized_keys` options without a value which can be set on SSH keys.
+const SSH_KEY_FLAG_OPTIONS: [&str; 11] = [
+    "agent-forwarding",
+    "no-agent-forwarding",
+    "no-port-forwarding",
+    "no-pty",
+    "no-user-rc",
+    "no-X11-forwarding",
+    "port-forwarding",
+    "pty",
+    "restrict",
+    "user-rc",
+    "X11-forwarding",
+];
+/// `authorized_keys` options with a quoted value which can be set on SSH keys.
+const SSH_KEY_VALUE_OPTIONS: [&str; 6] = [
+    "command",
+    "expiry-time",
+    "from",
+    "permitlisten",
+    "permitopen",
+    "principals",
+];
+
+#[derive(Debug, Error, PartialEq)]
+pub enum SshKeyOptionsError {
+    #[error("SSH key options are empty")]
+    Empty,
+    #[error("SSH key option {0} is not allowed")]
+    NotAllowed(String),
+    #[error("SSH key option {0} is malformed")]
+    Malformed(String),
+}
+
+/// Validate comma-separated `authorized_keys` options against the allowed options.
+/// Values must be double-quoted and can't contain quotes or control characters.
+/// Returns options with surrounding whitespace removed.
+pub fn validate_ssh_key_options(options: &str) -> Result<String, SshKeyOptionsError> {
+    let options = options.trim();
+    if options.is_empty() {
+        return Err(SshKeyOptionsError::Empty);
+    }
+    // option values are quoted and can't contain quotes, so commas inside quotes
+    // are the only ones not separating options
+    let mut in_quotes = false;
+    let mut start = 0;
+    let mut items = Vec::new();
+    for (index, c) in options.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => {
+                items.push(&options[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    if in_quotes {
+        return Err(SshKeyOptionsError::Malformed(options.to_string()));
+    }
+    items.push(&options[start..]);
+
+    for item in items {
+        match item.split_once('=') {
+            None => {
+                if !SSH_KEY_FLAG_OPTIONS
+                    .iter()
+                    .any(|flag| flag.eq_ignore_ascii_case(item))
+                {
+                    return Err(SshKeyOptionsError::NotAllowed(item.to_string()));
+                }
+            }
+            Some((name, value)) => {
+                if !SSH_KEY_VALUE_OPTIONS
+                    .iter()
+                    .any(|option| option.eq_ignore_ascii_case(name))
+                {
+                    return Err(SshKeyOptionsError::NotAllowed(name.to_string()));
+                }
+                let valid_value = value
+                    .strip_prefix('"')
+                    .and_then(|value| value.strip_suffix('"'))
+                    .is_some_and(|value| {
+                        !value.is_empty() && !value.contains(|c: char| c == '"' || c.is_control())
+                    });
+                if !valid_value {
+                    return Err(SshKeyOptionsError::Malformed(item.to_string()));
+                }
+            }
+        }
+    }
+
+    Ok(options.to_string())
+}
+
+/// SSH key algorithm families which can be allowed by [`SshKeyPolicy`].
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum SshKeyAlgorithm {
+    Rsa,
+    Dsa,
+    Ecdsa,
+    Ed25519,
+    SkEcdsa,
+    SkEd25519,
+}
+
+impl SshKeyAlgorithm {
+    /// Algorithm family of a parsed key, `None` for algorithms unknown to us.
+    #[must_use]
+    pub fn of(key: &PublicKey) -> Option<Self> {
+        match key.algorithm() {
+            Algorithm::Rsa { .. } => Some(Self::Rsa),
+            Algorithm::Dsa => Some(Self::Dsa),
+            Algorithm::Ecdsa { .. } => Some(Self::Ecdsa),
+            Algorithm::Ed25519 => Some(Self::Ed25519),
+            Algorithm::SkEcdsaSha2NistP256 => Some(Self::SkEcdsa),
+            Algorithm::SkEd25519 => Some(Self::SkEd25519),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum SshKeyPolicyError {
+    #[error("SSH key algorithm {0} is not allowed")]
+    AlgorithmNotAllowed(String),
+    #[error("RSA key is {0} bits long, at least {1} bits are required")]
+    RsaKeyTooShort(usize, usize),
+}
+
+/// Accepted SSH key algorithms and minimum key strength.
+#[derive(Clone, Debug)]
+pub struct SshKeyPolicy {
+    pub allowed_algorithms: Vec<SshKeyAlgorithm>,
+    pub min_rsa_bits: usize,
+}
+
+impl Default for SshKeyPolicy {
+    fn default() -> Self {
+        Self {
+            allowed_algorithms: vec![
+                SshKeyAlgorithm::Rsa,
+                SshKeyAlgorithm::Ecdsa,
+                SshKeyAlgorithm::Ed25519,
+                SshKeyAlgorithm::SkEcdsa,
+                SshKeyAlgorithm::SkEd25519,
+            ],
+            min_rsa_bits: DEFAULT_SSH_MIN_RSA_BITS,
+        }
+    }
+}
+
+impl SshKeyPolicy {
+    /// Policy from server configuration, or defaults if configuration is not loaded.
+    #[must_use]
+    pub fn current() -> Self {
+        SERVER_CONFIG
+            .get()
+            .map(|config| config.ssh_key_policy())
+            .unwrap_or_default()
+    }
+
+    /// Check parsed SSH key against the policy.
+    pub fn check(&self, key: &PublicKey) -> Result<(), SshKeyPolicyError> {
+        let algorithm = key.algorithm();
+        if !SshKeyAlgorithm::of(key).is_some_and(|alg| self.allowed_algorithms.contains(&alg)) {
+            return Err(SshKeyPolicyError::AlgorithmNotAllowed(
+                algorithm.as_str().to_string(),
+            ));
+        }
+        if let KeyData::Rsa(rsa) = key.key_data() {
+            let bits = rsa.n.as_positive_bytes().map_or(0, |modulus| {
+                modulus.len() * 8
+                    - modulus
+                        .first()
+                        .map_or(0, |byte| byte.leading_zeros() as usize)
+            });
+            if bits < self.min_rsa_bits {
+                return Err(SshKeyPolicyError::RsaKeyTooShort(bits, self.min_rsa_bits));
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Deserialize, Model, Serialize)]
 #[table(authentication_key)]
 pub(crate) struct AuthenticationKey<I = NoId> {
@@ -21,9 +268,18 @@ pub(crate) struct AuthenticationKey<I = NoId> {
     pub key: String,
     #[model(enum)]
     key_type: AuthenticationKeyType,
+    // trailing comment of SSH keys, stored apart from the canonical `key`
+    pub comment: Option<String>,
+    // expired keys are left out of `authorized_keys`
+    pub expires_at: Option<NaiveDateTime>,
+    // `authorized_keys` options prepended to SSH keys, see [`validate_ssh_key_options`]
+    pub options: Option<String>,
+    #[model(enum)]
+    pub usage: AuthenticationKeyUsage,
 }
 
 impl AuthenticationKey {
+    /// SSH keys are split into canonical `<algorithm> <body>` form and the comment.
     #[must_use]
     pub fn new(
         user_id: Id,
@@ -32,6 +288,19 @@ impl AuthenticationKey {
         key_type: AuthenticationKeyType,
         yubikey_id: Option<i64>,
     ) -> Self {
+        let (key, comment) = match key_type {
+            AuthenticationKeyType::Ssh => match SshKeyParts::parse(&key) {
+                Some(parts) => (
+                    format!("{} {}", parts.algorithm, parts.body),
+                    // keep comment on a single line
+                    parts
+                        .comment
+                        .map(|comment| comment.split_whitespace().collect::<Vec<_>>().join(" ")),
+                ),
+                None => (key, None),
+            },
+            AuthenticationKeyType::Gpg => (key, None),
+        };
         Self {
             id: NoId,
             yubikey_id,
@@ -39,11 +308,101 @@ impl AuthenticationKey {
             key,
             name,
             key_type,
+            comment,
+            expires_at: None,
+            options: None,
+            usage: AuthenticationKeyUsage::default(),
         }
     }
 }
 
+impl<I> AuthenticationKey<I> {
+    #[must_use]
+    pub fn key_type(&self) -> &AuthenticationKeyType {
+        &self.key_type
+    }
+
+    #[must_use]
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= Utc::now().naive_utc())
+    }
+
+    /// Line for `authorized_keys` file: `[options] <algorithm> <body> [comment]`.
+    #[must_use]
+    pub fn authorized_keys_line(&self) -> String {
+        let mut line = match &self.options {
+            Some(options) => format!("{options} {}", self.key),
+            None => self.key.clone(),
+        };
+        if let Some(comment) = &self.comment {
+            line.push(' ');
+            line.push_str(comment);
+        }
+        line
+    }
+}
+
 impl AuthenticationKey<Id> {
+    /// SSH keys of a user which haven't expired, for `authorized_keys` output.
+    /// With `usage` given, only keys meant for that usage or [`AuthenticationKeyUsage::Both`]
+    /// are returned.
+    pub async fn find_valid_ssh_keys<'e, E>(
+        executor: E,
+        user_id: Id,
+        usage: Option<AuthenticationKeyUsage>,
+    ) -> Result<Vec<Self>, SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
+        query_as!(
+            Self,
+            "SELECT id, user_id, yubikey_id \"yubikey_id?\", key, \
+            name, key_type \"key_type: AuthenticationKeyType\", comment, expires_at, options, \
+            usage \"usage: AuthenticationKeyUsage\" \
+            FROM authentication_key WHERE user_id = $1 AND key_type = 'ssh' \
+            AND (expires_at IS NULL OR expires_at > NOW()) \
+            AND ($2::authentication_key_usage IS NULL OR usage = $2 OR usage = 'both') \
+            AND (yubikey_id IS NULL \
+            OR yubikey_id NOT IN (SELECT id FROM yubikey WHERE disabled_at IS NOT NULL))",
+            user_id,
+            usage as Option<AuthenticationKeyUsage>
+        )
+        .fetch_all(executor)
+        .await
+    }
+
+    /// Delete expired keys of all users. Returns number of deleted keys.
+    pub async fn delete_expired<'e, E>(executor: E) -> Result<u64, SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
+        let result = query!(
+            "DELETE FROM authentication_key WHERE expires_at IS NOT NULL AND expires_at <= NOW()"
+        )
+        .execute(executor)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Ids of users who registered given SSH key. SSH keys are stored without comments,
+    /// so `normalized_key` should come from [`SshKeyParts::normalize`].
+    pub async fn find_ssh_key_owners<'e, E>(
+        executor: E,
+        normalized_key: &str,
+    ) -> Result<Vec<Id>, SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
+        query_scalar!(
+            "SELECT DISTINCT user_id FROM authentication_key \
+            WHERE key_type = 'ssh' AND key = $1",
+            normalized_key
+        )
+        .fetch_all(executor)
+        .await
+    }
+
     pub async fn find_by_user_id<'e, E>(
         executor: E,
         user_id: Id,
@@ -57,7 +416,8 @@ impl AuthenticationKey<Id> {
                 query_as!(
                     Self,
                     "SELECT id, user_id, yubikey_id \"yubikey_id?\", key, \
-                    name, key_type \"key_type: AuthenticationKeyType\" \
+                    name, key_type \"key_type: AuthenticationKeyType\", comment, expires_at, options, \
+            usage \"usage: AuthenticationKeyUsage\" \
                     FROM authentication_key WHERE user_id = $1 AND key_type = $2",
                     user_id,
                     &key_type as &AuthenticationKeyType
@@ -69,7 +429,8 @@ impl AuthenticationKey<Id> {
                 query_as!(
                     Self,
                     "SELECT id, user_id, yubikey_id \"yubikey_id?\", key, \
-                    name, key_type \"key_type: AuthenticationKeyType\" \
+                    name, key_type \"key_type: AuthenticationKeyType\", comment, expires_at, options, \
+            usage \"usage: AuthenticationKeyUsage\" \
                     FROM authentication_key WHERE user_id = $1",
                     user_id
                 )
@@ -79,3 +440,363 @@ impl AuthenticationKey<Id> {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeDelta;
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::db::User;
+
+    #[test]
+    fn test_parse_ssh_key_with_comment() {
+        let parts = SshKeyParts::parse(
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIHrFKj9GdPIfzXbZMkmOSF0T1IM9SbFe1o5U3FvYpN9A hpotter@hogwart",
+        )
+        .unwrap();
+        assert_eq!(parts.algorithm, "ssh-ed25519");
+        assert_eq!(
+            parts.body,
+            "AAAAC3NzaC1lZDI1NTE5AAAAIHrFKj9GdPIfzXbZMkmOSF0T1IM9SbFe1o5U3FvYpN9A"
+        );
+        assert_eq!(parts.comment.as_deref(), Some("hpotter@hogwart"));
+
+        // comment containing whitespace is kept intact
+        let parts = SshKeyParts::parse("ssh-rsa AAAAB3NzaC1yc2E  Harry Potter laptop \n").unwrap();
+        assert_eq!(parts.algorithm, "ssh-rsa");
+        assert_eq!(parts.body, "AAAAB3NzaC1yc2E");
+        assert_eq!(parts.comment.as_deref(), Some("Harry Potter laptop"));
+    }
+
+    #[test]
+    fn test_parse_ssh_key_without_comment() {
+        let parts = SshKeyParts::parse("ssh-ed25519 AAAAC3NzaC1lZDI1NTE5\n").unwrap();
+        assert_eq!(parts.algorithm, "ssh-ed25519");
+        assert_eq!(parts.body, "AAAAC3NzaC1lZDI1NTE5");
+        assert_eq!(parts.comment, None);
+
+        assert_eq!(SshKeyParts::parse("ssh-ed25519"), None);
+        assert_eq!(SshKeyParts::parse(""), None);
+    }
+
+    #[test]
+    fn test_normalize_ssh_key() {
+        let normalized = Some("ssh-ed25519 AAAAC3NzaC1lZDI1NTE5".to_string());
+        assert_eq!(
+            SshKeyParts::normalize("ssh-ed25519 AAAAC3NzaC1lZDI1NTE5 hpotter@hogwart"),
+            normalized
+        );
+        assert_eq!(
+            SshKeyParts::normalize("  ssh-ed25519\tAAAAC3NzaC1lZDI1NTE5   laptop key\r\n"),
+            normalized
+        );
+        assert_eq!(
+            SshKeyParts::normalize("ssh-ed25519 AAAAC3NzaC1lZDI1NTE5"),
+            normalized
+        );
+        assert_eq!(SshKeyParts::normalize("ssh-ed25519"), None);
+    }
+
+    #[test]
+    fn test_ssh_key_comment() {
+        let key =
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIMgaU2fumArNGBEEmHvIx20M3CDd106QELh/smzgv+uL";
+
+        let with_comment = AuthenticationKey::new(
+            1,
+            format!("{key}   Harry's\tlaptop\n"),
+            None,
+            AuthenticationKeyType::Ssh,
+            None,
+        );
+        assert_eq!(with_comment.key, key);
+        assert_eq!(with_comment.comment.as_deref(), Some("Harry's laptop"));
+        let line = with_comment.authorized_keys_line();
+        assert_eq!(line, format!("{key} Harry's laptop"));
+        assert!(line.parse::<PublicKey>().is_ok());
+
+        let without_comment =
+            AuthenticationKey::new(1, key.to_string(), None, AuthenticationKeyType::Ssh, None);
+        assert_eq!(without_comment.comment, None);
+        assert_eq!(without_comment.authorized_keys_line(), key);
+
+        // GPG keys are stored verbatim
+        let gpg = AuthenticationKey::new(
+            1,
+            "-----BEGIN PGP PUBLIC KEY BLOCK-----\n\nabc".into(),
+            None,
+            AuthenticationKeyType::Gpg,
+            None,
+        );
+        assert_eq!(gpg.key, "-----BEGIN PGP PUBLIC KEY BLOCK-----\n\nabc");
+        assert_eq!(gpg.comment, None);
+    }
+
+    #[sqlx::test]
+    async fn test_find_ssh_key_owners(pool: PgPool) {
+        let mut users = Vec::new();
+        for (username, email) in [
+            ("hpotter", "h.potter@hogwart.edu.uk"),
+            ("rweasley", "r.weasley@hogwart.edu.uk"),
+        ] {
+            let user = User::new(username, None, "Last", "First", email, None)
+                .unwrap()
+                .save(&pool)
+                .await
+                .unwrap();
+            users.push(user);
+        }
+        let key =
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIMgaU2fumArNGBEEmHvIx20M3CDd106QELh/smzgv+uL";
+        for (user, stored) in [
+            (&users[0], format!("{key} hpotter@hogwart")),
+            (&users[1], key.replace(' ', "  ") + "\tron's laptop"),
+        ] {
+            AuthenticationKey::new(user.id, stored, None, AuthenticationKeyType::Ssh, None)
+                .save(&pool)
+                .await
+                .unwrap();
+        }
+
+        let normalized = SshKeyParts::normalize(&format!("{key} other comment")).unwrap();
+        let mut owners = AuthenticationKey::find_ssh_key_owners(&pool, &normalized)
+            .await
+            .unwrap();
+        owners.sort_unstable();
+        assert_eq!(owners, [users[0].id, users[1].id]);
+
+        let other =
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIK1ej+W6YY6PDUK2HAiJJ0Ia6WuGfxQf3IojblaIRc+A";
+        assert!(AuthenticationKey::find_ssh_key_owners(&pool, other)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[sqlx::test]
+    async fn test_expired_keys(pool: PgPool) {
+        let user = User::new(
+            "hpotter",
+            None,
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+        let now = Utc::now().naive_utc();
+        let mut ids = Vec::new();
+        for (key, expires_at) in [
+            (
+                "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIMgaU2fumArNGBEEmHvIx20M3CDd106QELh/smzgv+uL",
+                None,
+            ),
+            (
+                "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIK1ej+W6YY6PDUK2HAiJJ0Ia6WuGfxQf3IojblaIRc+A",
+                Some(now + TimeDelta::days(1)),
+            ),
+            (
+                "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIHrFKj9GdPIfzXbZMkmOSF0T1IM9SbFe1o5U3FvYpN9A",
+                Some(now - TimeDelta::minutes(1)),
+            ),
+        ] {
+            let mut key =
+                AuthenticationKey::new(user.id, key.into(), None, AuthenticationKeyType::Ssh, None);
+            key.expires_at = expires_at;
+            assert_eq!(key.is_expired(), expires_at.is_some_and(|at| at < now));
+            ids.push(key.save(&pool).await.unwrap().id);
+        }
+
+        let mut valid: Vec<Id> = AuthenticationKey::find_valid_ssh_keys(&pool, user.id, None)
+            .await
+            .unwrap()
+            .iter()
+            .map(|key| key.id)
+            .collect();
+        valid.sort_unstable();
+        assert_eq!(valid, ids[..2]);
+
+        assert_eq!(AuthenticationKey::delete_expired(&pool).await.unwrap(), 1);
+        assert!(AuthenticationKey::find_by_id(&pool, ids[2])
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            AuthenticationKey::find_by_user_id(&pool, user.id, None)
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+        assert_eq!(AuthenticationKey::delete_expired(&pool).await.unwrap(), 0);
+    }
+
+    #[test]
+    fn test_validate_ssh_key_options() {
+        for options in [
+            "no-port-forwarding",
+            "no-port-forwarding,no-X11-forwarding,no-agent-forwarding",
+            "restrict,pty",
+            "from=\"10.0.0.0/8,192.168.1.*\",no-pty",
+            "command=\"/usr/bin/backup --daily\",restrict",
+            "NO-PTY",
+        ] {
+            assert_eq!(validate_ssh_key_options(options).as_deref(), Ok(options));
+        }
+        assert_eq!(
+            validate_ssh_key_options("  no-pty \n").as_deref(),
+            Ok("no-pty")
+        );
+
+        assert_eq!(
+            validate_ssh_key_options(" "),
+            Err(SshKeyOptionsError::Empty)
+        );
+        for (options, option) in [
+            ("tunnel=\"0\"", "tunnel"),
+            ("environment=\"PATH=/tmp\"", "environment"),
+            ("no-pty,unknown", "unknown"),
+            ("no-pty, restrict", " restrict"),
+            ("no-pty,", ""),
+        ] {
+            assert_eq!(
+                validate_ssh_key_options(options),
+                Err(SshKeyOptionsError::NotAllowed(option.into()))
+            );
+        }
+        for options in [
+            "from=10.0.0.1",
+            "from=\"10.0.0.1",
+            "from=\"\"",
+            "command=\"echo \"hi\"\"",
+            "command=\"ls\nssh-ed25519 AAAA\"",
+        ] {
+            assert!(matches!(
+                validate_ssh_key_options(options),
+                Err(SshKeyOptionsError::Malformed(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_authorized_keys_line_with_options() {
+        let key =
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIMgaU2fumArNGBEEmHvIx20M3CDd106QELh/smzgv+uL";
+        let mut auth_key = AuthenticationKey::new(
+            1,
+            format!("{key} hpotter@hogwart"),
+            None,
+            AuthenticationKeyType::Ssh,
+            None,
+        );
+        auth_key.options = Some("from=\"10.0.0.0/8\",no-pty".into());
+        assert_eq!(
+            auth_key.authorized_keys_line(),
+            format!("from=\"10.0.0.0/8\",no-pty {key} hpotter@hogwart")
+        );
+
+        auth_key.comment = None;
+        assert_eq!(
+            auth_key.authorized_keys_line(),
+            format!("from=\"10.0.0.0/8\",no-pty {key}")
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_ssh_key_usage(pool: PgPool) {
+        let user = User::new(
+            "hpotter",
+            None,
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+        let mut ids = Vec::new();
+        for (key, usage) in [
+            (
+                "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIMgaU2fumArNGBEEmHvIx20M3CDd106QELh/smzgv+uL",
+                AuthenticationKeyUsage::Interactive,
+            ),
+            (
+                "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIK1ej+W6YY6PDUK2HAiJJ0Ia6WuGfxQf3IojblaIRc+A",
+                AuthenticationKeyUsage::Deploy,
+            ),
+            (
+                "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIHrFKj9GdPIfzXbZMkmOSF0T1IM9SbFe1o5U3FvYpN9A",
+                AuthenticationKeyUsage::Both,
+            ),
+        ] {
+            let mut key =
+                AuthenticationKey::new(user.id, key.into(), None, AuthenticationKeyType::Ssh, None);
+            key.usage = usage;
+            ids.push(key.save(&pool).await.unwrap().id);
+        }
+
+        for (usage, expected) in [
+            (
+                Some(AuthenticationKeyUsage::Interactive),
+                vec![ids[0], ids[2]],
+            ),
+            (Some(AuthenticationKeyUsage::Deploy), vec![ids[1], ids[2]]),
+            (None, ids.clone()),
+        ] {
+            let mut found: Vec<Id> = AuthenticationKey::find_valid_ssh_keys(&pool, user.id, usage)
+                .await
+                .unwrap()
+                .iter()
+                .map(|key| key.id)
+                .collect();
+            found.sort_unstable();
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn test_ssh_key_policy() {
+        let policy = SshKeyPolicy::default();
+        let parse = |key: &str| key.parse::<PublicKey>().unwrap();
+
+        let ed25519 = parse(
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIMgaU2fumArNGBEEmHvIx20M3CDd106QELh/smzgv+uL hpotter@hogwart",
+        );
+        assert_eq!(policy.check(&ed25519), Ok(()));
+
+        let dsa = parse(
+            "ssh-dss AAAAB3NzaC1kc3MAAACBAPAQtXQkIFpKzNdLyxFeojUI6hD74zO1TXj38cZJsJDaD8cSzsUFAy1S3Wmvg1zMBPGiMktD/OTnf05odb+W6Ayce80Cb+hY8R+FMA33DYa/LjRSKV53JmpKg39+4nxM2shWm+Z49gsKds3iuECqiJ30NpksqxIi92If54R/GpH5AAAAFQC7Rc6wgqDkyoQ7SnaF0qJbRzNjdwAAAIEAlAxqMhnB+8gB8lIY+UG+zlq+wWOryotKYlmadoX8iPU+xkQeWGm9N4gPWgZOkRfq8k+V2wjO56zcUEXlWh8Zdv2BZJfeAbVm1H1j8P3q57tRrSMhpVWDw8DosFQtoj8YiFd+BaGGxSlX4lnDV3nhirjsOsh2Qq4AUver4nVfH7sAAACBAJ/hl9vNVhdTXdbP5w5cMtyiVP1w5xFbVNAnhzdFeYKD6LZYC893qh9vi5mNvF9wR2FYqaDFvvhc+EThxx2zGh9cs6wS2oFVRfbLlyxme7URhqMoyvxmtuMdb2d/ZT3u90gSg6RwVHROCE5wOH1sAnEDzV9eaQHrwYIJskBbgfAV hpotter@hogwart",
+        );
+        assert_eq!(
+            policy.check(&dsa),
+            Err(SshKeyPolicyError::AlgorithmNotAllowed("ssh-dss".into()))
+        );
+
+        let rsa_1024 = parse(
+            "ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAAAgQCg8laYdn8CRBC+crGbP/mHifrA3G3xwFMLd8+5AGma7qxVrSfKDaJ0op2Z66QiXglUIhrys4yJenCPxYGIGBpdBupZJ3Hbt7UKsmLufOlIAAzsuZoX8gLpdjCS7bRenvUmZLWiA+70jYl8vVYDlwNwC4Cbjs2SlCX0iLt/rCyq2Q== hpotter@hogwart",
+        );
+        assert_eq!(
+            policy.check(&rsa_1024),
+            Err(SshKeyPolicyError::RsaKeyTooShort(1024, 2048))
+        );
+
+        let rsa_2048 = parse(
+            "ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAABAQCrU4U+VtOgmKKMmAjlcxnxyh07Q/pJvZTrQcY0YBt28wkPcvg55b9lO8DacGTuxXPoEgMOfe2p2xqXi+j59JGppfslagZ+S+O6jE6mYY3N8z0Ul1GAq0xPq+5QqWpyoUfWilUwGPTZFZCUnBDs0aF4pJELCX2uyVmHYZ2QKi22zfvqQSGlJdZvHVtQDR38qVB3FnHdvuCh51VHmaN4LbrKtrkLc2Ec0x85D05rrSkwHAYgKc/izKfOhiGABEPYsyAyjhBfkcxX1R0/vsGFkpXpXM1c7B6TM8iiQozscqRIr0qVw7TWAQXP6DtA2CRiBTZMFdklkos5+XUz/l1pxRsJ hpotter@hogwart",
+        );
+        assert_eq!(policy.check(&rsa_2048), Ok(()));
+
+        // DSA may be explicitly allowed
+        let policy = SshKeyPolicy {
+            allowed_algorithms: vec![SshKeyAlgorithm::Dsa],
+            min_rsa_bits: DEFAULT_SSH_MIN_RSA_BITS,
+        };
+        assert_eq!(policy.check(&dsa), Ok(()));
+        assert!(policy.check(&ed25519).is_err());
+    }
+}
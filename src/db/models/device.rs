@@ -77,6 +77,8 @@ pub struct Device<I = NoId> {
     /// added to all networks it should be in, but it's not ready to be used yet due to
     /// e.g. public key not properly set up yet.
     pub configured: bool,
+    /// User's preferred device. At most one device per user can be primary.
+    pub is_primary: bool,
 }
 
 impl fmt::Display for Device<NoId> {
@@ -213,6 +215,18 @@ impl UserDevice {
     }
 }
 
+/// Device without a recent handshake, together with its owner. Used for finding abandoned devices.
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct StaleDeviceInfo {
+    pub id: Id,
+    pub name: String,
+    pub user_id: Id,
+    pub username: String,
+    pub created: NaiveDateTime,
+    /// Most recent handshake in any network, `None` if the device has never connected.
+    pub latest_handshake: Option<NaiveDateTime>,
+}
+
 #[derive(Clone, Debug, Deserialize, FromRow, Serialize)]
 pub struct WireguardNetworkDevice {
     pub wireguard_network_id: Id,
@@ -437,8 +451,9 @@ impl WireguardNetworkDevice {
     {
         query_as!(
             WireguardNetwork,
-            "SELECT id, name, address, port, pubkey, prvkey, endpoint, dns, allowed_ips, \
-            connected_at, mfa_enabled, keepalive_interval, peer_disconnect_threshold \
+            "SELECT id, name, address, port, pubkey, prvkey, endpoint, dns, \
+            dns_search_domains, allowed_ips, connected_at, mfa_enabled, keepalive_interval, \
+            peer_disconnect_threshold \
             FROM wireguard_network WHERE id = $1",
             self.wireguard_network_id
         )
@@ -478,11 +493,58 @@ impl Device {
             device_type,
             description,
             configured,
+            is_primary: false,
         }
     }
 }
 
 impl Device<Id> {
+    /// Find devices whose latest handshake in all networks is older than `cutoff`,
+    /// including devices which have never connected. Never connected devices come first.
+    pub async fn find_stale<'e, E>(
+        executor: E,
+        cutoff: NaiveDateTime,
+    ) -> Result<Vec<StaleDeviceInfo>, SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
+        query_as!(
+            StaleDeviceInfo,
+            "SELECT d.id, d.name, d.user_id, u.username, d.created, \
+            s.latest_handshake \"latest_handshake?\" \
+            FROM device d \
+            JOIN \"user\" u ON u.id = d.user_id \
+            LEFT JOIN ( \
+                SELECT device_id, MAX(latest_handshake) latest_handshake \
+                FROM wireguard_peer_stats \
+                GROUP BY device_id \
+            ) s ON s.device_id = d.id \
+            WHERE s.latest_handshake IS NULL OR s.latest_handshake < $1 \
+            ORDER BY s.latest_handshake NULLS FIRST, d.id",
+            cutoff
+        )
+        .fetch_all(executor)
+        .await
+    }
+
+    /// Make this device user's primary device; the previous primary device is unset.
+    pub async fn set_primary(&mut self, transaction: &mut PgConnection) -> Result<(), SqlxError> {
+        query!(
+            "UPDATE device SET is_primary = FALSE \
+            WHERE user_id = $1 AND id <> $2 AND is_primary",
+            self.user_id,
+            self.id
+        )
+        .execute(&mut *transaction)
+        .await?;
+        query!("UPDATE device SET is_primary = TRUE WHERE id = $1", self.id)
+            .execute(&mut *transaction)
+            .await?;
+        self.is_primary = true;
+
+        Ok(())
+    }
+
     pub(crate) fn update_from(&mut self, other: ModifyDevice) {
         self.name = other.name;
         self.wireguard_pubkey = other.wireguard_pubkey;
@@ -495,16 +557,10 @@ impl Device<Id> {
         network: &WireguardNetwork<Id>,
         wireguard_network_device: &WireguardNetworkDevice,
     ) -> String {
-        let dns = match &network.dns {
-            Some(dns) => {
-                if dns.is_empty() {
-                    String::new()
-                } else {
-                    format!("DNS = {dns}")
-                }
-            }
-            None => String::new(),
-        };
+        // wg-quick accepts both DNS servers and search domains on a single `DNS` line
+        let dns = network
+            .dns_entries()
+            .map_or(String::new(), |dns| format!("DNS = {dns}"));
 
         let allowed_ips = if network.allowed_ips.is_empty() {
             String::new()
@@ -546,7 +602,7 @@ impl Device<Id> {
         query_as!(
             Self,
             "SELECT d.id, d.name, d.wireguard_pubkey, d.user_id, d.created, d.description, \
-            d.device_type  \"device_type: DeviceType\", configured \
+            d.device_type  \"device_type: DeviceType\", configured, is_primary \
             FROM device d \
             JOIN wireguard_network_device wnd ON d.id = wnd.device_id \
             WHERE wnd.wireguard_ip = $1 AND wnd.wireguard_network_id = $2",
@@ -567,7 +623,7 @@ impl Device<Id> {
         query_as!(
             Self,
             "SELECT id, name, wireguard_pubkey, user_id, created, description, \
-            device_type \"device_type: DeviceType\", configured \
+            device_type \"device_type: DeviceType\", configured, is_primary \
             FROM device WHERE wireguard_pubkey = $1",
             pubkey
         )
@@ -583,7 +639,7 @@ impl Device<Id> {
         query_as!(
             Self,
             "SELECT device.id, name, wireguard_pubkey, user_id, created, description, \
-            device_type \"device_type: DeviceType\", configured \
+            device_type \"device_type: DeviceType\", configured, is_primary \
             FROM device JOIN \"user\" ON device.user_id = \"user\".id \
             WHERE device.id = $1 AND \"user\".username = $2",
             id,
@@ -600,7 +656,7 @@ impl Device<Id> {
         query_as!(
             Self,
             "SELECT device.id, name, wireguard_pubkey, user_id, created, description, \
-            device_type \"device_type: DeviceType\", configured \
+            device_type \"device_type: DeviceType\", configured, is_primary \
             FROM device JOIN \"user\" ON device.user_id = \"user\".id \
             WHERE \"user\".username = $1",
             username
@@ -634,7 +690,7 @@ impl Device<Id> {
             address: wireguard_network_device.wireguard_ip,
             allowed_ips: network.allowed_ips.clone(),
             pubkey: network.pubkey.clone(),
-            dns: network.dns.clone(),
+            dns: network.dns_entries(),
             mfa_enabled: network.mfa_enabled,
             keepalive_interval: network.keepalive_interval,
         };
@@ -667,7 +723,7 @@ impl Device<Id> {
             address: wireguard_network_device.wireguard_ip,
             allowed_ips: network.allowed_ips.clone(),
             pubkey: network.pubkey.clone(),
-            dns: network.dns.clone(),
+            dns: network.dns_entries(),
             mfa_enabled: network.mfa_enabled,
             keepalive_interval: network.keepalive_interval,
         };
@@ -719,6 +775,7 @@ impl Device<Id> {
                 network_info.push(device_network_info);
 
                 let config = Self::create_config(&network, &wireguard_network_device);
+                let dns = network.dns_entries();
                 configs.push(DeviceConfig {
                     network_id: network.id,
                     network_name: network.name,
@@ -727,7 +784,7 @@ impl Device<Id> {
                     address: wireguard_network_device.wireguard_ip,
                     allowed_ips: network.allowed_ips,
                     pubkey: network.pubkey,
-                    dns: network.dns,
+                    dns,
                     mfa_enabled: network.mfa_enabled,
                     keepalive_interval: network.keepalive_interval,
                 });
@@ -811,8 +868,9 @@ impl Device<Id> {
     {
         query_as!(
             WireguardNetwork,
-            "SELECT id, name, address, port, pubkey, prvkey, endpoint, dns, allowed_ips, \
-            connected_at, mfa_enabled, keepalive_interval, peer_disconnect_threshold \
+            "SELECT id, name, address, port, pubkey, prvkey, endpoint, dns, \
+            dns_search_domains, allowed_ips, connected_at, mfa_enabled, keepalive_interval, \
+            peer_disconnect_threshold \
             FROM wireguard_network WHERE id IN \
             (SELECT wireguard_network_id FROM wireguard_network_device WHERE device_id = $1 ORDER BY id LIMIT 1)",
             self.id
@@ -840,7 +898,7 @@ impl Device<Id> {
     {
         query_as!(Self,
             "SELECT id, name, wireguard_pubkey, user_id, created, description, device_type \"device_type: DeviceType\", \
-            configured \
+            configured, is_primary \
             FROM device WHERE device_type = $1 ORDER BY name",
             device_type as DeviceType
         ).fetch_all(executor).await
@@ -856,7 +914,7 @@ impl Device<Id> {
     {
         query_as!(Self,
             "SELECT id, name, wireguard_pubkey, user_id, created, description, device_type \"device_type: DeviceType\", \
-            configured \
+            configured, is_primary \
             FROM device WHERE device_type = $1 \
             AND id IN (SELECT device_id FROM wireguard_network_device WHERE wireguard_network_id = $2) \
             ORDER BY name",
@@ -873,7 +931,8 @@ impl Device<Id> {
             User,
             "SELECT id, username, password_hash, last_name, first_name, email, \
             phone, mfa_enabled, totp_enabled, email_mfa_enabled, \
-            totp_secret, email_mfa_secret, mfa_method \"mfa_method: _\", recovery_codes, is_active, openid_sub \
+            totp_secret, totp_digits, totp_period, email_mfa_secret, mfa_method \"mfa_method: _\", recovery_codes, is_active, openid_sub, \
+            suspended_until, password_changed_at, must_change_password \
             FROM \"user\" WHERE id = $1",
             self.user_id
         ).fetch_one(executor).await
@@ -884,10 +943,11 @@ impl Device<Id> {
 mod test {
     use std::str::FromStr;
 
+    use chrono::TimeDelta;
     use claims::{assert_err, assert_ok};
 
     use super::*;
-    use crate::db::User;
+    use crate::db::{models::wireguard_peer_stats::WireguardPeerStats, User};
 
     impl Device<Id> {
         /// Create new device and assign IP in a given network
@@ -944,12 +1004,13 @@ mod test {
 
         let user = User::new(
             "testuser",
-            Some("hunter2"),
+            Some("Hunter2!x"),
             "Tester",
             "Test",
             "test@test.com",
             None,
         )
+        .unwrap()
         .save(&pool)
         .await
         .unwrap();
@@ -966,6 +1027,146 @@ mod test {
         assert!(device.is_err());
     }
 
+    #[sqlx::test]
+    async fn test_find_stale(pool: PgPool) {
+        let mut network = WireguardNetwork::default();
+        network.try_set_address("10.1.1.1/29").unwrap();
+        let network = network.save(&pool).await.unwrap();
+        let user = User::new(
+            "hpotter",
+            Some("Pass123!"),
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+
+        let now = Utc::now().naive_utc();
+        let mut devices = Vec::new();
+        for (name, handshake_days) in [("recent", Some(1)), ("stale", Some(60)), ("never", None)] {
+            let device = Device::new(
+                name.into(),
+                format!("key-{name}"),
+                user.id,
+                DeviceType::User,
+                None,
+                true,
+            )
+            .save(&pool)
+            .await
+            .unwrap();
+            if let Some(days) = handshake_days {
+                // older stats don't matter, only the latest handshake counts
+                for handshake in [now - TimeDelta::days(90), now - TimeDelta::days(days)] {
+                    WireguardPeerStats {
+                        id: NoId,
+                        device_id: device.id,
+                        collected_at: handshake,
+                        network: network.id,
+                        endpoint: None,
+                        upload: 0,
+                        download: 0,
+                        latest_handshake: handshake,
+                        allowed_ips: None,
+                    }
+                    .save(&pool)
+                    .await
+                    .unwrap();
+                }
+            }
+            devices.push(device);
+        }
+
+        let stale = Device::find_stale(&pool, now - TimeDelta::days(30))
+            .await
+            .unwrap();
+        let names: Vec<&str> = stale.iter().map(|device| device.name.as_str()).collect();
+        assert_eq!(names, ["never", "stale"]);
+        assert!(stale.iter().all(|device| device.username == "hpotter"));
+        assert_eq!(stale[0].latest_handshake, None);
+        assert_eq!(stale[1].id, devices[1].id);
+        assert_eq!(
+            stale[1].latest_handshake.unwrap().and_utc().timestamp(),
+            (now - TimeDelta::days(60)).and_utc().timestamp()
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_set_primary(pool: PgPool) {
+        let user = User::new(
+            "hpotter",
+            Some("Pass123!"),
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+        let other_user = User::new(
+            "hgranger",
+            Some("Pass123!"),
+            "Granger",
+            "Hermione",
+            "h.granger@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+        let mut devices = Vec::new();
+        for (name, user_id) in [
+            ("laptop", user.id),
+            ("phone", user.id),
+            ("tablet", other_user.id),
+        ] {
+            let device = Device::new(
+                name.into(),
+                format!("{name}-key"),
+                user_id,
+                DeviceType::User,
+                None,
+                true,
+            )
+            .save(&pool)
+            .await
+            .unwrap();
+            assert!(!device.is_primary);
+            devices.push(device);
+        }
+
+        let mut transaction = pool.begin().await.unwrap();
+        devices[0].set_primary(&mut transaction).await.unwrap();
+        devices[2].set_primary(&mut transaction).await.unwrap();
+        devices[1].set_primary(&mut transaction).await.unwrap();
+        transaction.commit().await.unwrap();
+
+        let laptop = Device::find_by_id(&pool, devices[0].id)
+            .await
+            .unwrap()
+            .unwrap();
+        let phone = Device::find_by_id(&pool, devices[1].id)
+            .await
+            .unwrap()
+            .unwrap();
+        let tablet = Device::find_by_id(&pool, devices[2].id)
+            .await
+            .unwrap()
+            .unwrap();
+        // previous primary device has been cleared
+        assert!(!laptop.is_primary);
+        assert!(phone.is_primary);
+        // other users' devices are not affected
+        assert!(tablet.is_primary);
+    }
+
     #[test]
     fn test_pubkey_validation() {
         let invalid_test_key = "invalid_key";
@@ -975,28 +1176,59 @@ mod test {
         assert_ok!(Device::validate_pubkey(valid_test_key));
     }
 
+    #[sqlx::test]
+    async fn test_create_config_dns(pool: PgPool) {
+        let mut network = WireguardNetwork::default();
+        network.try_set_address("10.1.1.1/24").unwrap();
+        network.dns = Some("10.1.1.2".into());
+        let mut network = network.save(&pool).await.unwrap();
+        let network_device =
+            WireguardNetworkDevice::new(network.id, 1, IpAddr::from_str("10.1.1.10").unwrap());
+
+        // DNS servers only
+        let config = Device::create_config(&network, &network_device);
+        assert!(config.contains("\nDNS = 10.1.1.2\n"));
+
+        // DNS servers and search domains
+        network.dns_search_domains = vec!["example.com".into(), "corp.example.com".into()];
+        let config = Device::create_config(&network, &network_device);
+        assert!(config.contains("\nDNS = 10.1.1.2, example.com, corp.example.com\n"));
+
+        // search domains only
+        network.dns = None;
+        let config = Device::create_config(&network, &network_device);
+        assert!(config.contains("\nDNS = example.com, corp.example.com\n"));
+
+        // neither
+        network.dns_search_domains.clear();
+        let config = Device::create_config(&network, &network_device);
+        assert!(!config.contains("DNS"));
+    }
+
     #[sqlx::test]
     fn test_all_for_network_and_user(pool: PgPool) {
         let user = User::new(
             "testuser",
-            Some("hunter2"),
+            Some("Hunter2!x"),
             "Tester",
             "Test",
             "email@email.com",
             None,
         )
+        .unwrap()
         .save(&pool)
         .await
         .unwrap();
 
         let user2 = User::new(
             "testuser2",
-            Some("hunter2"),
+            Some("Hunter2!x"),
             "Tester",
             "Test",
             "email2@email.com",
             None,
         )
+        .unwrap()
         .save(&pool)
         .await
         .unwrap();
@@ -1,24 +1,51 @@
+use std::collections::HashMap;
+
 use chrono::{NaiveDateTime, TimeDelta, Utc};
+use clap::ValueEnum;
 use reqwest::Url;
-use sqlx::{query, query_as, Error as SqlxError, PgConnection, PgExecutor, PgPool};
+use serde::Serialize;
+use sqlx::{query, query_as, query_scalar, Error as SqlxError, PgConnection, PgExecutor, PgPool};
 use tera::{Context, Tera};
 use thiserror::Error;
-use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
 use tonic::{Code, Status};
+use utoipa::ToSchema;
 
-use super::{settings::Settings, User};
+use super::{
+    enrollment_event::{EnrollmentEvent, EnrollmentEventType},
+    settings::Settings,
+    webhook::{EnrollmentCompletedData, EnrollmentPendingData},
+    User,
+};
 use crate::{
-    db::Id,
+    config::EnrollmentMailFailurePolicy,
+    db::{AppEvent, Id},
     mail::Mail,
-    random::gen_alphanumeric,
+    random::{gen_alphanumeric, gen_unambiguous},
     server_config,
+    sms::{Sms, SmsSender},
     templates::{self, TemplateError},
-    VERSION,
+    SERVER_CONFIG, VERSION,
 };
 
 pub static ENROLLMENT_TOKEN_TYPE: &str = "ENROLLMENT";
 pub static PASSWORD_RESET_TOKEN_TYPE: &str = "PASSWORD_RESET";
 
+/// Upper bound for enrollment and desktop configuration token validity (30 days).
+pub const MAX_TOKEN_TIMEOUT_SECONDS: u64 = 30 * 24 * 3600;
+/// Upper bound for enrollment session duration (1 day).
+pub const MAX_SESSION_TIMEOUT_SECONDS: u64 = 24 * 3600;
+/// Default length of the random part of token ids. Configured prefix is prepended to it,
+/// so the prefix never reduces token entropy.
+pub const DEFAULT_TOKEN_RANDOM_LENGTH: usize = 32;
+/// Lower bound for configurable token length. Even with the smaller unambiguous alphabet
+/// this gives over 180 bits of entropy, so guessing and collisions remain infeasible.
+pub const MIN_TOKEN_RANDOM_LENGTH: usize = 32;
+/// Upper bound for configurable token length; token ids are stored in a `text` column.
+pub const MAX_TOKEN_RANDOM_LENGTH: usize = 128;
+/// Upper bound for configurable token prefix length.
+pub const MAX_TOKEN_PREFIX_LENGTH: usize = 16;
+
 static ENROLLMENT_START_MAIL_SUBJECT: &str = "Defguard user enrollment";
 static DESKTOP_START_MAIL_SUBJECT: &str = "Defguard desktop client configuration";
 
@@ -42,8 +69,14 @@ pub enum TokenError {
     AdminNotFound,
     #[error("User account is already activated")]
     AlreadyActive,
+    #[error("Enrollment was started without an email address")]
+    NoEmail,
+    #[error("Invalid {0} timeout: {1}s, must be between 1s and {2}s")]
+    InvalidTimeout(&'static str, u64, u64),
     #[error("Failed to send enrollment notification: {0}")]
     NotificationError(String),
+    #[error("Failed to send enrollment text message: {0}")]
+    SmsError(String),
     #[error("Enrollment welcome message not configured")]
     WelcomeMsgNotConfigured,
     #[error("Enrollment welcome email not configured")]
@@ -63,20 +96,119 @@ impl From<TokenError> for Status {
             | TokenError::UserNotFound
             | TokenError::UserDisabled
             | TokenError::NotificationError(_)
+            | TokenError::SmsError(_)
             | TokenError::WelcomeMsgNotConfigured
             | TokenError::WelcomeEmailNotConfigured
             | TokenError::TemplateError(_)
-            | TokenError::TemplateErrorInternal(_) => (Code::Internal, "unexpected error"),
+            | TokenError::TemplateErrorInternal(_)
+            | TokenError::InvalidTimeout(..) => (Code::Internal, "unexpected error"),
             TokenError::NotFound | TokenError::SessionExpired | TokenError::TokenUsed => {
                 (Code::Unauthenticated, "invalid token")
             }
             TokenError::AlreadyActive => (Code::InvalidArgument, "already active"),
+            TokenError::NoEmail => (Code::InvalidArgument, "no email address"),
             TokenError::TokenExpired => (Code::Unauthenticated, "token expired"),
         };
         Status::new(code, msg)
     }
 }
 
+/// Make sure a timeout is neither zero (token or session would expire immediately)
+/// nor larger than `max_seconds`.
+fn validate_timeout(name: &'static str, seconds: u64, max_seconds: u64) -> Result<(), TokenError> {
+    if seconds == 0 || seconds > max_seconds {
+        error!("Invalid {name} timeout: {seconds}s");
+        return Err(TokenError::InvalidTimeout(name, seconds, max_seconds));
+    }
+    Ok(())
+}
+
+/// Validate token timeout against [`MAX_TOKEN_TIMEOUT_SECONDS`].
+pub(crate) fn validate_token_timeout(seconds: u64) -> Result<(), TokenError> {
+    validate_timeout("token", seconds, MAX_TOKEN_TIMEOUT_SECONDS)
+}
+
+/// Validate session timeout against [`MAX_SESSION_TIMEOUT_SECONDS`].
+pub(crate) fn validate_session_timeout(seconds: u64) -> Result<(), TokenError> {
+    validate_timeout("session", seconds, MAX_SESSION_TIMEOUT_SECONDS)
+}
+
+/// Token prefix from server configuration, or no prefix if configuration is not loaded.
+fn token_prefix() -> &'static str {
+    SERVER_CONFIG
+        .get()
+        .map_or("", |config| config.enrollment_token_prefix.as_str())
+}
+
+/// Characters used in the random part of token ids.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum TokenAlphabet {
+    /// ASCII letters and digits.
+    #[default]
+    Alphanumeric,
+    /// ASCII letters and digits without easily confused ones (`0`/`O`, `1`/`l`/`I`).
+    Unambiguous,
+}
+
+/// Token length and alphabet from server configuration, or defaults if configuration is not loaded.
+fn token_format() -> (usize, TokenAlphabet) {
+    SERVER_CONFIG.get().map_or(
+        (DEFAULT_TOKEN_RANDOM_LENGTH, TokenAlphabet::default()),
+        |config| {
+            (
+                config.enrollment_token_length,
+                config.enrollment_token_alphabet,
+            )
+        },
+    )
+}
+
+fn generate_token_id(prefix: &str, length: usize, alphabet: TokenAlphabet) -> String {
+    let random = match alphabet {
+        TokenAlphabet::Alphanumeric => gen_alphanumeric(length),
+        TokenAlphabet::Unambiguous => gen_unambiguous(length),
+    };
+    format!("{prefix}{random}")
+}
+
+/// Ids under which token `id` may be stored. Tokens created before the prefix was configured
+/// don't have it, and users may paste a token without the prefix.
+fn token_id_candidates(id: &str, prefix: &str) -> Vec<String> {
+    let mut candidates = vec![id.to_string()];
+    if !prefix.is_empty() {
+        match id.strip_prefix(prefix) {
+            Some(stripped) => candidates.push(stripped.to_string()),
+            None => candidates.push(format!("{prefix}{id}")),
+        }
+    }
+    candidates
+}
+
+/// Token summary used in the admin listing. The token id is a secret, so it's not included.
+#[derive(Debug, Serialize)]
+pub struct TokenInfo {
+    pub user_id: Id,
+    pub username: String,
+    pub admin_id: Option<Id>,
+    pub email: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub used_at: Option<NaiveDateTime>,
+    pub token_type: Option<String>,
+}
+
+/// Enrollment progress of tokens issued in a time range, see [`Token::completion_stats`].
+#[derive(Debug, Default, PartialEq, Serialize, ToSchema)]
+pub struct EnrollmentStats {
+    pub issued: i64,
+    /// Enrollment session has been started with the token.
+    pub started: i64,
+    /// Enrollment has been completed with the token.
+    pub completed: i64,
+    /// Token has expired without being used.
+    pub expired_unused: i64,
+}
+
 // Representation of a user enrollment session
 #[derive(Clone, Debug)]
 pub struct Token {
@@ -89,6 +221,8 @@ pub struct Token {
     pub used_at: Option<NaiveDateTime>,
     pub token_type: Option<String>,
     pub device_id: Option<Id>,
+    /// Overrides the welcome message from [`Settings`] for this enrollment only.
+    pub custom_welcome_message: Option<String>,
 }
 
 impl Token {
@@ -101,8 +235,9 @@ impl Token {
         token_type: Option<String>,
     ) -> Self {
         let now = Utc::now();
+        let (length, alphabet) = token_format();
         Self {
-            id: gen_alphanumeric(32),
+            id: generate_token_id(token_prefix(), length, alphabet),
             user_id,
             admin_id,
             email,
@@ -111,16 +246,25 @@ impl Token {
             used_at: None,
             token_type,
             device_id: None,
+            custom_welcome_message: None,
         }
     }
 
+    /// Store new token; creation of enrollment tokens is recorded in enrollment audit log along
+    /// with acting admin.
     pub async fn save<'e, E>(&self, executor: E) -> Result<(), TokenError>
     where
         E: PgExecutor<'e>,
     {
+        // single statement, so that audit log entry is stored with any executor
         query!(
-            "INSERT INTO token (id, user_id, admin_id, email, created_at, expires_at, used_at, token_type, device_id) \
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+            "WITH event AS (\
+                INSERT INTO enrollment_event (token_id, user_id, event_type, admin_id, timestamp) \
+                SELECT $1, $2, 'token_created', $3, $5 WHERE $8 = $11\
+            ) \
+            INSERT INTO token (id, user_id, admin_id, email, created_at, expires_at, used_at, token_type, \
+            device_id, custom_welcome_message) \
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
             self.id,
             self.user_id,
             self.admin_id,
@@ -129,13 +273,20 @@ impl Token {
             self.expires_at,
             self.used_at,
             self.token_type,
-            self.device_id
+            self.device_id,
+            self.custom_welcome_message,
+            ENROLLMENT_TOKEN_TYPE
         )
         .execute(executor)
         .await?;
         Ok(())
     }
 
+    /// Only enrollment tokens are recorded in enrollment audit log.
+    fn is_enrollment(&self) -> bool {
+        self.token_type.as_deref() == Some(ENROLLMENT_TOKEN_TYPE)
+    }
+
     // check if token has already expired
     #[must_use]
     pub fn is_expired(&self) -> bool {
@@ -149,27 +300,34 @@ impl Token {
     }
 
     // check if enrollment session is still valid
-    // after using the token user has 10 minutes to complete enrollment
+    // after using the token user has `session_timeout_seconds` to complete enrollment
     #[must_use]
     pub fn is_session_valid(&self, session_timeout_seconds: u64) -> bool {
-        if let Some(used_at) = self.used_at {
-            let now = Utc::now();
-            return now.naive_utc()
-                < (used_at + TimeDelta::seconds(session_timeout_seconds as i64));
-        }
-        false
+        self.session_deadline(session_timeout_seconds)
+            .is_some_and(|deadline| Utc::now().naive_utc() < deadline)
+    }
+
+    /// Deadline of the session started with this token, `None` if no session was started.
+    #[must_use]
+    pub fn session_deadline(&self, session_timeout_seconds: u64) -> Option<NaiveDateTime> {
+        self.used_at
+            .map(|used_at| used_at + TimeDelta::seconds(session_timeout_seconds as i64))
     }
 
     // check if token can be used to start an enrollment session
     // and set timestamp if token is valid
     // returns session deadline
+    // client IP address and user agent are recorded in enrollment audit log
     pub async fn start_session(
         &mut self,
         transaction: &mut PgConnection,
         session_timeout_seconds: u64,
+        ip_address: Option<&str>,
+        user_agent: Option<&str>,
     ) -> Result<NaiveDateTime, TokenError> {
         // check if token can be used
         debug!("Creating a new session.");
+        validate_session_timeout(session_timeout_seconds)?;
         if self.is_expired() {
             debug!("Token is already expired. Cannot establish a new session.");
             return Err(TokenError::TokenExpired);
@@ -189,9 +347,19 @@ impl Token {
             None => {
                 let now = Utc::now().naive_utc();
                 query!("UPDATE token SET used_at = $1 WHERE id = $2", now, self.id)
-                    .execute(transaction)
+                    .execute(&mut *transaction)
                     .await?;
                 self.used_at = Some(now);
+                if self.is_enrollment() {
+                    EnrollmentEvent::new(
+                        &self.id,
+                        self.user_id,
+                        EnrollmentEventType::SessionStarted,
+                    )
+                    .with_client(ip_address, user_agent)
+                    .save(transaction)
+                    .await?;
+                }
 
                 debug!("Generate a new session successfully.");
                 Ok(now + TimeDelta::seconds(session_timeout_seconds as i64))
@@ -199,12 +367,21 @@ impl Token {
         }
     }
 
+    /// Find token by id, with or without the configured prefix.
     pub async fn find_by_id(pool: &PgPool, id: &str) -> Result<Self, TokenError> {
+        Self::find_by_id_with_prefix(pool, id, token_prefix()).await
+    }
+
+    async fn find_by_id_with_prefix(
+        pool: &PgPool,
+        id: &str,
+        prefix: &str,
+    ) -> Result<Self, TokenError> {
         if let Some(enrollment) = query_as!(
             Self,
-            "SELECT id, user_id, admin_id, email, created_at, expires_at, used_at, token_type, device_id \
-            FROM token WHERE id = $1",
-            id
+            "SELECT id, user_id, admin_id, email, created_at, expires_at, used_at, token_type, device_id, \
+            custom_welcome_message FROM token WHERE id = ANY($1) LIMIT 1",
+            &token_id_candidates(id, prefix)
         )
         .fetch_optional(pool)
         .await?
@@ -217,17 +394,227 @@ impl Token {
         }
     }
 
-    pub async fn fetch_all(pool: &PgPool) -> Result<Vec<Self>, TokenError> {
+    /// Revoke all tokens of given type issued for the user. Tokens are deleted, so an enrollment
+    /// or password reset session in progress can't be continued either.
+    /// Returns the revoked tokens.
+    pub async fn revoke(
+        pool: &PgPool,
+        user_id: Id,
+        token_type: &str,
+        admin_id: Id,
+    ) -> Result<Vec<Self>, TokenError> {
+        debug!("Revoking {token_type} tokens of user {user_id}");
+        let mut transaction = pool.begin().await?;
+        // keep expiry of removed tokens in audit log
+        Token::record_expired(&mut *transaction).await?;
+        let tokens = query_as!(
+            Self,
+            "DELETE FROM token WHERE user_id = $1 AND token_type = $2 \
+            RETURNING id, user_id, admin_id, email, created_at, expires_at, used_at, token_type, \
+            device_id, custom_welcome_message",
+            user_id,
+            token_type
+        )
+        .fetch_all(&mut *transaction)
+        .await?;
+        if tokens.is_empty() {
+            debug!("User {user_id} has no {token_type} tokens.");
+            return Err(TokenError::NotFound);
+        }
+        for token in tokens.iter().filter(|token| token.is_enrollment()) {
+            let mut event =
+                EnrollmentEvent::new(&token.id, token.user_id, EnrollmentEventType::Revoked);
+            event.admin_id = Some(admin_id);
+            event.save(&mut *transaction).await?;
+        }
+        transaction.commit().await?;
+        info!(
+            "Revoked {} {token_type} tokens of user {user_id}",
+            tokens.len()
+        );
+
+        Ok(tokens)
+    }
+
+    /// Enrollment audit log of all enrollment tokens of a user, oldest event first.
+    /// Available also for revoked tokens.
+    pub async fn history(
+        pool: &PgPool,
+        user_id: Id,
+    ) -> Result<Vec<EnrollmentEvent<Id>>, TokenError> {
+        Ok(EnrollmentEvent::find_by_user_id(pool, user_id).await?)
+    }
+
+    /// Record expiry of unused tokens in enrollment audit log. Each token is recorded only once.
+    /// Returns number of newly recorded tokens.
+    pub async fn record_expired<'e, E>(executor: E) -> Result<u64, TokenError>
+    where
+        E: PgExecutor<'e>,
+    {
+        let result = query!(
+            "INSERT INTO enrollment_event (token_id, user_id, event_type, timestamp) \
+            SELECT id, user_id, 'expired', expires_at FROM token \
+            WHERE token_type = $1 AND used_at IS NULL AND expires_at <= NOW() AND NOT EXISTS (\
+                SELECT 1 FROM enrollment_event \
+                WHERE token_id = token.id AND event_type = 'expired'\
+            )",
+            ENROLLMENT_TOKEN_TYPE
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Find the most recent unused enrollment token of a user which hasn't expired yet.
+    pub async fn find_pending_enrollment(pool: &PgPool, user_id: Id) -> Result<Self, TokenError> {
+        query_as!(
+            Self,
+            "SELECT id, user_id, admin_id, email, created_at, expires_at, used_at, token_type, device_id, \
+            custom_welcome_message FROM token \
+            WHERE user_id = $1 AND token_type = $2 AND used_at IS NULL AND expires_at > NOW() \
+            ORDER BY created_at DESC LIMIT 1",
+            user_id,
+            ENROLLMENT_TOKEN_TYPE
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or(TokenError::NotFound)
+    }
+
+    /// Send enrollment start notifications for this token again: the email to the address given
+    /// when enrollment was started and the text message to user's phone number, the same way as
+    /// with [`User::start_enrollment`]. The token stays the same, so links which were already
+    /// sent keep working.
+    pub async fn resend_notification<S: SmsSender>(
+        &self,
+        pool: &PgPool,
+        mail_tx: &UnboundedSender<Mail>,
+        sms_sender: &S,
+        enrollment_service_url: Url,
+    ) -> Result<(), TokenError> {
+        if self.token_type.as_deref() != Some(ENROLLMENT_TOKEN_TYPE) {
+            debug!("Token {} is not an enrollment token", self.id);
+            return Err(TokenError::NotFound);
+        }
+        if self.is_expired() {
+            return Err(TokenError::TokenExpired);
+        }
+        if self.is_used() {
+            return Err(TokenError::TokenUsed);
+        }
+        let user = self.fetch_user(pool).await?;
+        let has_phone = user.phone.as_ref().is_some_and(|phone| !phone.is_empty());
+        if self.email.is_none() && !has_phone {
+            return Err(TokenError::NoEmail);
+        }
+        user.send_enrollment_start_notifications(
+            pool,
+            self,
+            self.email.clone(),
+            enrollment_service_url,
+            mail_tx,
+            sms_sender,
+            EnrollmentMailFailurePolicy::Abort,
+        )
+        .await?;
+        info!("Resent enrollment notification for user {}", user.username);
+
+        Ok(())
+    }
+
+    /// Remove the token. Its history is kept.
+    async fn delete<'e, E>(&self, executor: E) -> Result<(), TokenError>
+    where
+        E: PgExecutor<'e>,
+    {
+        query!("DELETE FROM token WHERE id = $1", self.id)
+            .execute(executor)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Fetch tokens, newest first. `None` means no limit.
+    pub async fn fetch_all(pool: &PgPool, limit: Option<i64>) -> Result<Vec<Self>, TokenError> {
         let tokens = query_as!(
             Self,
-            "SELECT id, user_id, admin_id, email, created_at, expires_at, used_at, token_type, device_id \
-            FROM token",
+            "SELECT id, user_id, admin_id, email, created_at, expires_at, used_at, token_type, device_id, \
+            custom_welcome_message FROM token ORDER BY created_at DESC LIMIT $1",
+            limit
         )
         .fetch_all(pool)
         .await?;
         Ok(tokens)
     }
 
+    /// Fetch a page of token summaries with owners' usernames, newest first.
+    pub async fn fetch_page_info<'e, E>(
+        executor: E,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<TokenInfo>, TokenError>
+    where
+        E: PgExecutor<'e>,
+    {
+        let tokens = query_as!(
+            TokenInfo,
+            "SELECT t.user_id, u.username, t.admin_id, t.email, t.created_at, t.expires_at, \
+            t.used_at, t.token_type FROM token t JOIN \"user\" u ON u.id = t.user_id \
+            ORDER BY t.created_at DESC, t.id LIMIT $1 OFFSET $2",
+            limit,
+            offset
+        )
+        .fetch_all(executor)
+        .await?;
+        Ok(tokens)
+    }
+
+    /// Count enrollment tokens created in `[from, to)` time range by their progress.
+    /// Computed from enrollment audit log, so revoked and removed tokens are counted as well.
+    pub async fn completion_stats<'e, E>(
+        executor: E,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> Result<EnrollmentStats, TokenError>
+    where
+        E: PgExecutor<'e>,
+    {
+        let stats = query_as!(
+            EnrollmentStats,
+            "SELECT COUNT(*) \"issued!\", \
+            COUNT(*) FILTER (WHERE e.started) \"started!\", \
+            COUNT(*) FILTER (WHERE e.completed) \"completed!\", \
+            COUNT(*) FILTER (WHERE NOT e.started AND (e.expired OR t.expires_at <= $3)) \
+            \"expired_unused!\" \
+            FROM enrollment_event c LEFT JOIN token t ON t.id = c.token_id, \
+            LATERAL (\
+                SELECT bool_or(event_type = 'session_started') started, \
+                bool_or(event_type = 'completed') completed, \
+                bool_or(event_type = 'expired') expired \
+                FROM enrollment_event WHERE token_id = c.token_id\
+            ) e \
+            WHERE c.event_type = 'token_created' AND c.timestamp >= $1 AND c.timestamp < $2",
+            from,
+            to,
+            Utc::now().naive_utc()
+        )
+        .fetch_one(executor)
+        .await?;
+        Ok(stats)
+    }
+
+    /// Number of all stored tokens.
+    pub async fn count<'e, E>(executor: E) -> Result<i64, TokenError>
+    where
+        E: PgExecutor<'e>,
+    {
+        let count = query_scalar!("SELECT COUNT(*) \"count!\" FROM token")
+            .fetch_one(executor)
+            .await?;
+        Ok(count)
+    }
+
     pub async fn fetch_user<'e, E>(&self, executor: E) -> Result<User<Id>, TokenError>
     where
         E: PgExecutor<'e>,
@@ -260,6 +647,73 @@ impl Token {
         Ok(user)
     }
 
+    /// Find users who haven't finished enrollment although their enrollment token
+    /// was created before `created_before` and is still valid.
+    pub async fn find_pending_enrollments(
+        pool: &PgPool,
+        created_before: NaiveDateTime,
+    ) -> Result<Vec<EnrollmentPendingData>, SqlxError> {
+        let now = Utc::now().naive_utc();
+        let rows = query!(
+            "SELECT u.username, u.email, t.expires_at FROM token t \
+            JOIN \"user\" u ON u.id = t.user_id \
+            WHERE t.token_type = $1 AND t.used_at IS NULL \
+            AND t.created_at < $2 AND t.expires_at > $3 \
+            AND u.password_hash IS NULL AND u.openid_sub IS NULL \
+            ORDER BY t.expires_at",
+            ENROLLMENT_TOKEN_TYPE,
+            created_before,
+            now,
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| EnrollmentPendingData {
+                username: row.username,
+                email: row.email,
+                expires_at: row.expires_at,
+                expires_in: (row.expires_at - now).num_seconds(),
+            })
+            .collect())
+    }
+
+    /// Trigger enrollment pending webhook event for each user whose enrollment
+    /// has been waiting for longer than `threshold`.
+    pub async fn notify_pending_enrollments(
+        pool: &PgPool,
+        webhook_tx: &UnboundedSender<AppEvent>,
+        threshold: std::time::Duration,
+    ) -> Result<(), SqlxError> {
+        debug!("Looking for pending enrollments older than {threshold:?}");
+        let created_before = Utc::now().naive_utc()
+            - TimeDelta::from_std(threshold).expect("Failed to parse duration");
+        let pending = Token::find_pending_enrollments(pool, created_before).await?;
+        let count = pending.len();
+        for data in pending {
+            debug!("Enrollment of user {} is pending", data.username);
+            if let Err(err) = webhook_tx.send(AppEvent::EnrollmentPending(data)) {
+                error!("Error sending enrollment pending event: {err}");
+            }
+        }
+        info!("Triggered enrollment pending event for {count} users");
+
+        Ok(())
+    }
+
+    /// Trigger enrollment completed webhook event for user who has just finished enrollment.
+    pub fn notify_enrollment_completed(webhook_tx: &UnboundedSender<AppEvent>, user: &User<Id>) {
+        let data = EnrollmentCompletedData {
+            user_id: user.id,
+            username: user.username.clone(),
+            completed_at: Utc::now().naive_utc(),
+        };
+        if let Err(err) = webhook_tx.send(AppEvent::EnrollmentCompleted(data)) {
+            error!("Error sending enrollment completed event: {err}");
+        }
+    }
+
     pub async fn delete_unused_user_tokens<'e, E>(
         executor: E,
         user_id: Id,
@@ -268,20 +722,54 @@ impl Token {
         E: PgExecutor<'e>,
     {
         debug!("Deleting unused tokens for the user.");
+        // expiry of removed enrollment tokens is recorded in audit log, see `record_expired`
+        let deleted = query_scalar!(
+            "WITH deleted AS (\
+                DELETE FROM token WHERE user_id = $1 AND used_at IS NULL \
+                RETURNING id, user_id, token_type, expires_at\
+            ), expired AS (\
+                INSERT INTO enrollment_event (token_id, user_id, event_type, timestamp) \
+                SELECT id, user_id, 'expired', expires_at FROM deleted \
+                WHERE token_type = $2 AND expires_at <= NOW() AND NOT EXISTS (\
+                    SELECT 1 FROM enrollment_event \
+                    WHERE token_id = deleted.id AND event_type = 'expired'\
+                )\
+            ) \
+            SELECT COUNT(*) \"count!\" FROM deleted",
+            user_id,
+            ENROLLMENT_TOKEN_TYPE
+        )
+        .fetch_one(executor)
+        .await?;
+        info!("Deleted {deleted} unused enrollment tokens for the user.");
+
+        Ok(())
+    }
+
+    /// Reassign unused tokens issued by `from_admin_id` to `to_admin_id`, so that pending
+    /// enrollments remain attributable after the issuing admin leaves.
+    /// Returns the number of reassigned tokens.
+    pub async fn reassign_admin(
+        transaction: &mut PgConnection,
+        from_admin_id: Id,
+        to_admin_id: Id,
+    ) -> Result<u64, TokenError> {
+        debug!("Reassigning unused tokens issued by admin {from_admin_id} to admin {to_admin_id}");
         let result = query!(
-            "DELETE FROM token \
-            WHERE user_id = $1 \
+            "UPDATE token SET admin_id = $2 \
+            WHERE admin_id = $1 \
             AND used_at IS NULL",
-            user_id
+            from_admin_id,
+            to_admin_id
         )
-        .execute(executor)
+        .execute(transaction)
         .await?;
         info!(
-            "Deleted {} unused enrollment tokens for the user.",
+            "Reassigned {} unused tokens issued by admin {from_admin_id} to admin {to_admin_id}",
             result.rows_affected()
         );
 
-        Ok(())
+        Ok(result.rows_affected())
     }
 
     pub async fn delete_unused_user_password_reset_tokens(
@@ -346,6 +834,14 @@ impl Token {
         Ok(context)
     }
 
+    // Welcome message template: the one set for this enrollment, or the global one
+    fn welcome_message(&self, settings: &Settings) -> Result<String, TokenError> {
+        match &self.custom_welcome_message {
+            Some(message) => Ok(message.clone()),
+            None => settings.enrollment_welcome_message(),
+        }
+    }
+
     // Replace template tags and return markdown content
     // to be displayed on final enrollment page
     pub async fn get_welcome_page_content(
@@ -356,7 +852,7 @@ impl Token {
 
         // load configured content as template
         let mut tera = Tera::default();
-        tera.add_raw_template("welcome_page", &settings.enrollment_welcome_message()?)?;
+        tera.add_raw_template("welcome_page", &self.welcome_message(&settings)?)?;
 
         let context = self.get_welcome_message_context(&mut *transaction).await?;
 
@@ -373,8 +869,13 @@ impl Token {
         let settings = Settings::get_current_settings();
 
         // load configured content as template
+        let welcome_email = if settings.enrollment_use_welcome_message_as_email {
+            self.welcome_message(&settings)?
+        } else {
+            settings.enrollment_welcome_email()?
+        };
         let mut tera = Tera::default();
-        tera.add_raw_template("welcome_email", &settings.enrollment_welcome_email()?)?;
+        tera.add_raw_template("welcome_email", &welcome_email)?;
 
         let context = self.get_welcome_message_context(&mut *transaction).await?;
         let content = tera.render("welcome_email", &context)?;
@@ -387,20 +888,40 @@ impl Token {
     }
 }
 
+/// Enrollment started with [`User::start_enrollment`].
+#[derive(Debug)]
+pub struct StartedEnrollment {
+    pub token: String,
+    /// Delivery error of the enrollment email, kept only with [`EnrollmentMailFailurePolicy::Warn`].
+    pub mail_error: Option<String>,
+    /// Delivery error of the enrollment text message, kept only with
+    /// [`EnrollmentMailFailurePolicy::Warn`].
+    pub sms_error: Option<String>,
+}
+
 impl User<Id> {
     /// Start user enrollment process
     /// This creates a new enrollment token valid for 24h
-    /// and optionally sends enrollment email notification to user
-    pub async fn start_enrollment(
+    /// and optionally sends enrollment email notification to user.
+    /// Users with a phone number get the enrollment link in a text message if no email
+    /// is provided, or in addition to the email with `enrollment_sms_with_email` set.
+    /// `custom_welcome_message` replaces the global welcome message for this enrollment.
+    /// Notifications are sent only after the token is committed. If the email or text message
+    /// can't be delivered, `mail_failure_policy` decides whether the new token is removed and
+    /// an error is returned, or the failure is reported in the result.
+    pub async fn start_enrollment<S: SmsSender>(
         &self,
-        transaction: &mut PgConnection,
+        pool: &PgPool,
         admin: &User<Id>,
         email: Option<String>,
         token_timeout_seconds: u64,
         enrollment_service_url: Url,
         send_user_notification: bool,
         mail_tx: UnboundedSender<Mail>,
-    ) -> Result<String, TokenError> {
+        sms_sender: &S,
+        custom_welcome_message: Option<String>,
+        mail_failure_policy: EnrollmentMailFailurePolicy,
+    ) -> Result<StartedEnrollment, TokenError> {
         info!(
             "User {} started a new enrollment process for user {}.",
             admin.username, self.username
@@ -409,6 +930,188 @@ impl User<Id> {
             "Notify user by mail about the enrollment process: {}",
             send_user_notification
         );
+        let mut transaction = pool.begin().await?;
+        let enrollment = self
+            .create_enrollment_token(
+                &mut transaction,
+                admin,
+                email.clone(),
+                token_timeout_seconds,
+                custom_welcome_message,
+            )
+            .await?;
+        transaction.commit().await?;
+        info!(
+            "New enrollment token has been generated for {}.",
+            self.username
+        );
+
+        let (mail_error, sms_error) = if send_user_notification {
+            match self
+                .send_enrollment_start_notifications(
+                    pool,
+                    &enrollment,
+                    email,
+                    enrollment_service_url,
+                    &mail_tx,
+                    sms_sender,
+                    mail_failure_policy,
+                )
+                .await
+            {
+                Ok(errors) => errors,
+                Err(err) => {
+                    debug!(
+                        "Removing enrollment token of user {}, the notification failed.",
+                        self.username
+                    );
+                    enrollment.delete(pool).await?;
+                    return Err(err);
+                }
+            }
+        } else {
+            (None, None)
+        };
+
+        Ok(StartedEnrollment {
+            token: enrollment.id,
+            mail_error,
+            sms_error,
+        })
+    }
+
+    /// Send enrollment link by email to `email`, and by text message to user's phone number
+    /// if there's no email or `enrollment_sms_with_email` is set.
+    /// Returns delivery errors of the email and the text message which are tolerated by
+    /// `mail_failure_policy`.
+    async fn send_enrollment_start_notifications<S: SmsSender>(
+        &self,
+        pool: &PgPool,
+        enrollment: &Token,
+        email: Option<String>,
+        enrollment_service_url: Url,
+        mail_tx: &UnboundedSender<Mail>,
+        sms_sender: &S,
+        mail_failure_policy: EnrollmentMailFailurePolicy,
+    ) -> Result<(Option<String>, Option<String>), TokenError> {
+        let send_sms = email.is_none() || server_config().enrollment_sms_with_email;
+        let mut mail_error = None;
+        if let Some(email) = email {
+            let mut conn = pool.acquire().await?;
+            mail_error = self
+                .send_enrollment_start_mail(
+                    &mut conn,
+                    enrollment,
+                    email,
+                    enrollment_service_url.clone(),
+                    mail_tx,
+                    mail_failure_policy,
+                )
+                .await?;
+        }
+        let mut sms_error = None;
+        if let Some(phone) = self
+            .phone
+            .clone()
+            .filter(|phone| send_sms && !phone.is_empty())
+        {
+            sms_error = self
+                .send_enrollment_start_sms(
+                    enrollment,
+                    phone,
+                    enrollment_service_url,
+                    sms_sender,
+                    mail_failure_policy,
+                )
+                .await?;
+        }
+
+        Ok((mail_error, sms_error))
+    }
+
+    /// Start enrollment process for multiple users at once, with a shared token timeout.
+    /// All tokens are created in a single transaction, so that either all users get a token
+    /// or none of them does. Notifications are sent only after the transaction is committed,
+    /// the same way as with [`User::start_enrollment`]; delivery failures don't affect the tokens
+    /// and are reported per user in [`StartedEnrollment`].
+    pub async fn start_bulk_enrollment<S: SmsSender>(
+        pool: &PgPool,
+        admin: &User<Id>,
+        user_ids: &[Id],
+        token_timeout_seconds: u64,
+        enrollment_service_url: Url,
+        send_user_notification: bool,
+        mail_tx: UnboundedSender<Mail>,
+        sms_sender: &S,
+    ) -> Result<HashMap<Id, StartedEnrollment>, TokenError> {
+        info!(
+            "User {} started enrollment process for {} users.",
+            admin.username,
+            user_ids.len()
+        );
+        let mut transaction = pool.begin().await?;
+        let mut enrollments = Vec::with_capacity(user_ids.len());
+        for user_id in user_ids {
+            let Some(user) = User::find_by_id(&mut *transaction, *user_id).await? else {
+                error!("Can't start enrollment for non-existing user {user_id}");
+                return Err(TokenError::UserNotFound);
+            };
+            let email = (!user.email.is_empty()).then(|| user.email.clone());
+            let enrollment = user
+                .create_enrollment_token(
+                    &mut transaction,
+                    admin,
+                    email,
+                    token_timeout_seconds,
+                    None,
+                )
+                .await?;
+            enrollments.push((user, enrollment));
+        }
+        transaction.commit().await?;
+        info!(
+            "New enrollment tokens have been generated for {} users.",
+            enrollments.len()
+        );
+
+        let mut started = HashMap::with_capacity(enrollments.len());
+        for (user, enrollment) in enrollments {
+            let (mail_error, sms_error) = if send_user_notification {
+                user.send_enrollment_start_notifications(
+                    pool,
+                    &enrollment,
+                    enrollment.email.clone(),
+                    enrollment_service_url.clone(),
+                    &mail_tx,
+                    sms_sender,
+                    EnrollmentMailFailurePolicy::Warn,
+                )
+                .await?
+            } else {
+                (None, None)
+            };
+            started.insert(
+                user.id,
+                StartedEnrollment {
+                    token: enrollment.id,
+                    mail_error,
+                    sms_error,
+                },
+            );
+        }
+
+        Ok(started)
+    }
+
+    /// Replace unused tokens of an active user without password with a new enrollment token.
+    async fn create_enrollment_token(
+        &self,
+        transaction: &mut PgConnection,
+        admin: &User<Id>,
+        email: Option<String>,
+        token_timeout_seconds: u64,
+        custom_welcome_message: Option<String>,
+    ) -> Result<Token, TokenError> {
         debug!("Check if {} has a password.", self.username);
         if self.has_password() {
             debug!(
@@ -427,17 +1130,19 @@ impl User<Id> {
             return Err(TokenError::UserDisabled);
         }
 
+        validate_token_timeout(token_timeout_seconds)?;
         self.clear_unused_enrollment_tokens(&mut *transaction)
             .await?;
 
         debug!("Create a new enrollment token for user {}.", self.username);
-        let enrollment = Token::new(
+        let mut enrollment = Token::new(
             self.id,
             Some(admin.id),
-            email.clone(),
+            email,
             token_timeout_seconds,
             Some(ENROLLMENT_TOKEN_TYPE.to_string()),
         );
+        enrollment.custom_welcome_message = custom_welcome_message;
         debug!("Saving a new enrollment token...");
         enrollment.save(&mut *transaction).await?;
         debug!(
@@ -445,54 +1150,125 @@ impl User<Id> {
             enrollment.id, self.username
         );
 
-        if send_user_notification {
-            if let Some(email) = email {
+        Ok(enrollment)
+    }
+
+    /// Send enrollment start mail and wait for the delivery result.
+    /// Returns delivery error if it's tolerated by `mail_failure_policy`.
+    async fn send_enrollment_start_mail(
+        &self,
+        transaction: &mut PgConnection,
+        enrollment: &Token,
+        email: String,
+        enrollment_service_url: Url,
+        mail_tx: &UnboundedSender<Mail>,
+        mail_failure_policy: EnrollmentMailFailurePolicy,
+    ) -> Result<Option<String>, TokenError> {
+        debug!(
+            "Sending an enrollment mail for user {} to {email}.",
+            self.username
+        );
+        let (result_tx, mut result_rx) = unbounded_channel();
+        let base_message_context = enrollment
+            .get_welcome_message_context(&mut *transaction)
+            .await?;
+        let mail = Mail {
+            to: email.clone(),
+            subject: ENROLLMENT_START_MAIL_SUBJECT.to_string(),
+            content: templates::enrollment_start_mail(
+                base_message_context,
+                enrollment_service_url,
+                &enrollment.id,
+            )
+            .map_err(|err| {
                 debug!(
-                    "Sending an enrollment mail for user {} to {email}.",
+                    "Cannot send an email to the user {} due to the error {}.",
+                    self.username,
+                    err.to_string()
+                );
+                TokenError::NotificationError(err.to_string())
+            })?,
+            attachments: Vec::new(),
+            result_tx: Some(result_tx),
+        };
+        let delivery = match mail_tx.send(mail) {
+            Ok(()) => match result_rx.recv().await {
+                Some(Ok(_)) => Ok(()),
+                Some(Err(err)) => Err(err.to_string()),
+                None => Err("mail was not sent".to_string()),
+            },
+            Err(err) => Err(err.to_string()),
+        };
+        match delivery {
+            Ok(()) => {
+                EnrollmentEvent::new(
+                    &enrollment.id,
+                    enrollment.user_id,
+                    EnrollmentEventType::EmailSent,
+                )
+                .save(&mut *transaction)
+                .await?;
+                info!(
+                    "Sent enrollment start mail for user {} to {email}",
                     self.username
                 );
-                let base_message_context = enrollment
-                    .get_welcome_message_context(&mut *transaction)
-                    .await?;
-                let mail = Mail {
-                    to: email.clone(),
-                    subject: ENROLLMENT_START_MAIL_SUBJECT.to_string(),
-                    content: templates::enrollment_start_mail(
-                        base_message_context,
-                        enrollment_service_url,
-                        &enrollment.id,
-                    )
-                    .map_err(|err| {
-                        debug!(
-                            "Cannot send an email to the user {} due to the error {}.",
-                            self.username,
-                            err.to_string()
-                        );
-                        TokenError::NotificationError(err.to_string())
-                    })?,
-                    attachments: Vec::new(),
-                    result_tx: None,
-                };
-                match mail_tx.send(mail) {
-                    Ok(()) => {
-                        info!(
-                            "Sent enrollment start mail for user {} to {email}",
-                            self.username
-                        );
-                    }
-                    Err(err) => {
-                        error!("Error sending mail: {err}");
-                        return Err(TokenError::NotificationError(err.to_string()));
-                    }
-                }
+                Ok(None)
             }
+            Err(err) => match mail_failure_policy {
+                EnrollmentMailFailurePolicy::Abort => {
+                    error!("Error sending mail: {err}");
+                    Err(TokenError::NotificationError(err))
+                }
+                EnrollmentMailFailurePolicy::Warn => {
+                    warn!(
+                        "Error sending enrollment start mail for user {} to {email}, \
+                        keeping the enrollment token: {err}",
+                        self.username
+                    );
+                    Ok(Some(err))
+                }
+            },
         }
-        info!(
-            "New enrollment token has been generated for {}.",
+    }
+
+    /// Text enrollment link to the user.
+    /// Returns delivery error if it's tolerated by `failure_policy`.
+    async fn send_enrollment_start_sms<S: SmsSender>(
+        &self,
+        enrollment: &Token,
+        phone: String,
+        enrollment_service_url: Url,
+        sms_sender: &S,
+        failure_policy: EnrollmentMailFailurePolicy,
+    ) -> Result<Option<String>, TokenError> {
+        debug!(
+            "Sending an enrollment text message for user {}.",
             self.username
         );
-
-        Ok(enrollment.id)
+        let sms = Sms {
+            to: phone,
+            content: templates::enrollment_start_sms(enrollment_service_url, &enrollment.id),
+        };
+        match sms_sender.send(sms).await {
+            Ok(()) => {
+                debug!("Sent enrollment text message for user {}", self.username);
+                Ok(None)
+            }
+            Err(err) => match failure_policy {
+                EnrollmentMailFailurePolicy::Abort => {
+                    error!("Error sending text message: {err}");
+                    Err(TokenError::SmsError(err.to_string()))
+                }
+                EnrollmentMailFailurePolicy::Warn => {
+                    warn!(
+                        "Error sending enrollment text message for user {}, \
+                        keeping the enrollment token: {err}",
+                        self.username
+                    );
+                    Ok(Some(err.to_string()))
+                }
+            },
+        }
     }
 
     /// Start user remote desktop configuration process
@@ -529,6 +1305,7 @@ impl User<Id> {
             return Err(TokenError::UserDisabled);
         }
 
+        validate_token_timeout(token_timeout_seconds)?;
         self.clear_unused_enrollment_tokens(&mut *transaction)
             .await?;
         debug!("Cleared unused tokens for {}.", self.username);
@@ -634,3 +1411,1161 @@ impl Settings {
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use std::sync::Mutex;
+
+    use lettre::transport::smtp::response::{Category, Code, Detail, Response, Severity};
+    use tokio::sync::mpsc::unbounded_channel;
+
+    use super::*;
+    use crate::{
+        config::DefGuardConfig, db::models::settings::initialize_current_settings, mail::MailError,
+        sms::SmsError, SERVER_CONFIG,
+    };
+
+    /// Collects text messages instead of sending them.
+    #[derive(Default)]
+    struct MockSmsSender {
+        sent: Mutex<Vec<Sms>>,
+        unavailable: bool,
+    }
+
+    impl SmsSender for MockSmsSender {
+        async fn send(&self, sms: Sms) -> Result<(), SmsError> {
+            if self.unavailable {
+                return Err(SmsError::Request("unavailable".into()));
+            }
+            self.sent.lock().unwrap().push(sms);
+            Ok(())
+        }
+    }
+
+    async fn make_users(pool: &PgPool) -> (User<Id>, User<Id>) {
+        let admin = User::new(
+            "admin",
+            Some("Pass123!"),
+            "Dumbledore",
+            "Albus",
+            "a.dumbledore@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(pool)
+        .await
+        .unwrap();
+        let user = User::new(
+            "hpotter",
+            None,
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(pool)
+        .await
+        .unwrap();
+        (admin, user)
+    }
+
+    #[test]
+    fn test_token_format() {
+        let id = generate_token_id("", DEFAULT_TOKEN_RANDOM_LENGTH, TokenAlphabet::Alphanumeric);
+        assert_eq!(id.len(), 32);
+        assert!(id.chars().all(|c| c.is_ascii_alphanumeric()));
+
+        let id = generate_token_id("dgenr_", 64, TokenAlphabet::Unambiguous);
+        let random = id.strip_prefix("dgenr_").unwrap();
+        assert_eq!(random.len(), 64);
+        assert!(random.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert!(!random.contains(['0', 'O', '1', 'l', 'I']));
+
+        // tokens don't repeat
+        let ids: HashSet<String> = (0..1000)
+            .map(|_| generate_token_id("", MIN_TOKEN_RANDOM_LENGTH, TokenAlphabet::Unambiguous))
+            .collect();
+        assert_eq!(ids.len(), 1000);
+
+        // defaults are used without configuration
+        assert_eq!(
+            token_format(),
+            (DEFAULT_TOKEN_RANDOM_LENGTH, TokenAlphabet::Alphanumeric)
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_token_prefix(pool: PgPool) {
+        let (admin, user) = make_users(&pool).await;
+
+        let mut token = Token::new(
+            user.id,
+            Some(admin.id),
+            None,
+            3600,
+            Some(ENROLLMENT_TOKEN_TYPE.into()),
+        );
+        token.id = generate_token_id(
+            "dgenr_",
+            DEFAULT_TOKEN_RANDOM_LENGTH,
+            TokenAlphabet::Alphanumeric,
+        );
+        token.save(&pool).await.unwrap();
+        let random = token.id.strip_prefix("dgenr_").unwrap();
+        assert_eq!(random.len(), DEFAULT_TOKEN_RANDOM_LENGTH);
+        assert!(random.chars().all(|c| c.is_ascii_alphanumeric()));
+
+        let found = Token::find_by_id_with_prefix(&pool, &token.id, "dgenr_")
+            .await
+            .unwrap();
+        assert_eq!(found.id, token.id);
+        // prefix omitted
+        let found = Token::find_by_id_with_prefix(&pool, random, "dgenr_")
+            .await
+            .unwrap();
+        assert_eq!(found.id, token.id);
+
+        // token created before the prefix was configured
+        let legacy = Token::new(
+            user.id,
+            Some(admin.id),
+            None,
+            3600,
+            Some(ENROLLMENT_TOKEN_TYPE.into()),
+        );
+        legacy.save(&pool).await.unwrap();
+        let found = Token::find_by_id_with_prefix(&pool, &format!("dgenr_{}", legacy.id), "dgenr_")
+            .await
+            .unwrap();
+        assert_eq!(found.id, legacy.id);
+
+        assert!(matches!(
+            Token::find_by_id_with_prefix(&pool, "dgenr_unknown", "dgenr_").await,
+            Err(TokenError::NotFound)
+        ));
+    }
+
+    #[sqlx::test]
+    async fn test_enrollment_token_timeout(pool: PgPool) {
+        let (admin, user) = make_users(&pool).await;
+        let (mail_tx, _mail_rx) = unbounded_channel();
+        let url = Url::parse("http://localhost:8080").unwrap();
+
+        for timeout in [0, MAX_TOKEN_TIMEOUT_SECONDS + 1] {
+            let result = user
+                .start_enrollment(
+                    &pool,
+                    &admin,
+                    None,
+                    timeout,
+                    url.clone(),
+                    false,
+                    mail_tx.clone(),
+                    &MockSmsSender::default(),
+                    None,
+                    EnrollmentMailFailurePolicy::Abort,
+                )
+                .await;
+            assert!(matches!(result, Err(TokenError::InvalidTimeout(..))));
+        }
+
+        let token_id = user
+            .start_enrollment(
+                &pool,
+                &admin,
+                None,
+                MAX_TOKEN_TIMEOUT_SECONDS,
+                url,
+                false,
+                mail_tx,
+                &MockSmsSender::default(),
+                None,
+                EnrollmentMailFailurePolicy::Abort,
+            )
+            .await
+            .unwrap()
+            .token;
+        assert!(!Token::find_by_id(&pool, &token_id)
+            .await
+            .unwrap()
+            .is_expired());
+    }
+
+    #[test]
+    fn test_welcome_message_override() {
+        let settings = Settings {
+            enrollment_welcome_message: Some("Global welcome".into()),
+            ..Default::default()
+        };
+        let mut token = Token::new(1, None, None, 3600, Some(ENROLLMENT_TOKEN_TYPE.into()));
+        assert_eq!(token.welcome_message(&settings).unwrap(), "Global welcome");
+
+        token.custom_welcome_message = Some("Welcome to the cohort".into());
+        assert_eq!(
+            token.welcome_message(&settings).unwrap(),
+            "Welcome to the cohort"
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_custom_welcome_message(pool: PgPool) {
+        let _ = SERVER_CONFIG.set(DefGuardConfig::new_test_config());
+        initialize_current_settings(&pool).await.unwrap();
+        let (admin, user) = make_users(&pool).await;
+        let (mail_tx, _mail_rx) = unbounded_channel();
+        let url = Url::parse("http://localhost:8080").unwrap();
+
+        let custom_token_id = user
+            .start_enrollment(
+                &pool,
+                &admin,
+                None,
+                3600,
+                url.clone(),
+                false,
+                mail_tx.clone(),
+                &MockSmsSender::default(),
+                Some("Hello {{ first_name }}, welcome to the Quidditch team!".into()),
+                EnrollmentMailFailurePolicy::Abort,
+            )
+            .await
+            .unwrap()
+            .token;
+
+        // placeholders are replaced in the custom message
+        let token = Token::find_by_id(&pool, &custom_token_id).await.unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        let content = token.get_welcome_page_content(&mut conn).await.unwrap();
+        assert_eq!(content, "Hello Harry, welcome to the Quidditch team!");
+
+        // without override the global message is used
+        let token_id = user
+            .start_enrollment(
+                &pool,
+                &admin,
+                None,
+                3600,
+                url,
+                false,
+                mail_tx,
+                &MockSmsSender::default(),
+                None,
+                EnrollmentMailFailurePolicy::Abort,
+            )
+            .await
+            .unwrap()
+            .token;
+        let token = Token::find_by_id(&pool, &token_id).await.unwrap();
+        assert!(token.custom_welcome_message.is_none());
+        let mut conn = pool.acquire().await.unwrap();
+        let content = token.get_welcome_page_content(&mut conn).await.unwrap();
+        let global = Settings::get_current_settings()
+            .enrollment_welcome_message()
+            .unwrap();
+        let expected = Tera::one_off(
+            &global,
+            &token.get_welcome_message_context(&mut conn).await.unwrap(),
+            false,
+        )
+        .unwrap();
+        assert_eq!(content, expected);
+        assert!(!content.contains("Quidditch"));
+    }
+
+    #[sqlx::test]
+    async fn test_enrollment_session_timeout(pool: PgPool) {
+        let (admin, user) = make_users(&pool).await;
+        let mut token = Token::new(
+            user.id,
+            Some(admin.id),
+            None,
+            3600,
+            Some(ENROLLMENT_TOKEN_TYPE.to_string()),
+        );
+        token.save(&pool).await.unwrap();
+
+        let mut conn = pool.acquire().await.unwrap();
+        for timeout in [0, MAX_SESSION_TIMEOUT_SECONDS + 1] {
+            let result = token.start_session(&mut conn, timeout, None, None).await;
+            assert!(matches!(result, Err(TokenError::InvalidTimeout(..))));
+            assert!(!token.is_used());
+        }
+
+        let deadline = token
+            .start_session(&mut conn, 600, None, None)
+            .await
+            .unwrap();
+        assert!(deadline > Utc::now().naive_utc());
+        assert!(token.is_used());
+    }
+
+    #[sqlx::test]
+    async fn test_session_deadline(pool: PgPool) {
+        let (admin, user) = make_users(&pool).await;
+        let mut token = Token::new(
+            user.id,
+            Some(admin.id),
+            None,
+            3600,
+            Some(ENROLLMENT_TOKEN_TYPE.to_string()),
+        );
+        token.save(&pool).await.unwrap();
+        assert_eq!(token.session_deadline(600), None);
+
+        let mut conn = pool.acquire().await.unwrap();
+        let deadline = token
+            .start_session(&mut conn, 600, None, None)
+            .await
+            .unwrap();
+        let used_at = token.used_at.unwrap();
+        assert_eq!(deadline, used_at + TimeDelta::seconds(600));
+        assert_eq!(token.session_deadline(600), Some(deadline));
+
+        // session in progress keeps its deadline
+        let again = token
+            .start_session(&mut conn, 600, None, None)
+            .await
+            .unwrap();
+        assert_eq!(again, deadline);
+    }
+
+    #[sqlx::test]
+    async fn test_notify_pending_enrollments(pool: PgPool) {
+        let (admin, user) = make_users(&pool).await;
+        let fresh_user = User::new(
+            "hgranger",
+            None,
+            "Granger",
+            "Hermione",
+            "h.granger@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+
+        // stale enrollment
+        let mut token = Token::new(
+            user.id,
+            Some(admin.id),
+            None,
+            24 * 3600,
+            Some(ENROLLMENT_TOKEN_TYPE.to_string()),
+        );
+        token.created_at -= TimeDelta::hours(13);
+        token.save(&pool).await.unwrap();
+
+        // fresh enrollment
+        Token::new(
+            fresh_user.id,
+            Some(admin.id),
+            None,
+            24 * 3600,
+            Some(ENROLLMENT_TOKEN_TYPE.to_string()),
+        )
+        .save(&pool)
+        .await
+        .unwrap();
+
+        let (webhook_tx, mut webhook_rx) = unbounded_channel();
+        Token::notify_pending_enrollments(
+            &pool,
+            &webhook_tx,
+            std::time::Duration::from_secs(12 * 3600),
+        )
+        .await
+        .unwrap();
+
+        let Ok(AppEvent::EnrollmentPending(data)) = webhook_rx.try_recv() else {
+            panic!("Expected enrollment pending event");
+        };
+        assert_eq!(data.username, "hpotter");
+        assert_eq!(data.email, "h.potter@hogwart.edu.uk");
+        assert_eq!(data.expires_at, token.expires_at);
+        assert!(data.expires_in > 0);
+        assert!(webhook_rx.try_recv().is_err());
+    }
+
+    #[sqlx::test]
+    async fn test_fetch_all_order(pool: PgPool) {
+        let (admin, user) = make_users(&pool).await;
+        let mut ids = Vec::new();
+        for hours in [3, 1, 2] {
+            let mut token = Token::new(
+                user.id,
+                Some(admin.id),
+                None,
+                3600,
+                Some(ENROLLMENT_TOKEN_TYPE.to_string()),
+            );
+            token.created_at -= TimeDelta::hours(hours);
+            token.save(&pool).await.unwrap();
+            ids.push(token.id);
+        }
+
+        let tokens = Token::fetch_all(&pool, None).await.unwrap();
+        let fetched: Vec<_> = tokens.iter().map(|token| token.id.as_str()).collect();
+        assert_eq!(fetched, [ids[1].as_str(), ids[2].as_str(), ids[0].as_str()]);
+
+        let tokens = Token::fetch_all(&pool, Some(2)).await.unwrap();
+        let fetched: Vec<_> = tokens.iter().map(|token| token.id.as_str()).collect();
+        assert_eq!(fetched, [ids[1].as_str(), ids[2].as_str()]);
+    }
+
+    /// Mail handler stand-in which fails to deliver every mail.
+    fn failing_mailer() -> UnboundedSender<Mail> {
+        let (mail_tx, mut mail_rx) = unbounded_channel::<Mail>();
+        tokio::spawn(async move {
+            while let Some(mail) = mail_rx.recv().await {
+                if let Some(result_tx) = mail.result_tx {
+                    let _ = result_tx.send(Err(MailError::SmtpNotConfigured));
+                }
+            }
+        });
+        mail_tx
+    }
+
+    #[sqlx::test]
+    async fn test_enrollment_mail_failure_policy(pool: PgPool) {
+        let _ = SERVER_CONFIG.set(DefGuardConfig::new_test_config());
+        initialize_current_settings(&pool).await.unwrap();
+        let (admin, user) = make_users(&pool).await;
+        let url = Url::parse("http://localhost:8080").unwrap();
+
+        // abort: enrollment fails and the token is removed
+        let result = user
+            .start_enrollment(
+                &pool,
+                &admin,
+                Some(user.email.clone()),
+                3600,
+                url.clone(),
+                true,
+                failing_mailer(),
+                &MockSmsSender::default(),
+                None,
+                EnrollmentMailFailurePolicy::Abort,
+            )
+            .await;
+        assert!(matches!(result, Err(TokenError::NotificationError(_))));
+        assert!(Token::fetch_all(&pool, None).await.unwrap().is_empty());
+
+        // warn: token is kept and the failure is reported
+        let enrollment = user
+            .start_enrollment(
+                &pool,
+                &admin,
+                Some(user.email.clone()),
+                3600,
+                url,
+                true,
+                failing_mailer(),
+                &MockSmsSender::default(),
+                None,
+                EnrollmentMailFailurePolicy::Warn,
+            )
+            .await
+            .unwrap();
+        assert!(enrollment.mail_error.is_some());
+        let tokens = Token::fetch_all(&pool, None).await.unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].id, enrollment.token);
+    }
+
+    #[sqlx::test]
+    async fn test_reassign_admin(pool: PgPool) {
+        let (admin, user) = make_users(&pool).await;
+        let new_admin = User::new(
+            "mmcgonagall",
+            Some("Pass123!"),
+            "McGonagall",
+            "Minerva",
+            "m.mcgonagall@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+
+        let pending = Token::new(
+            user.id,
+            Some(admin.id),
+            None,
+            3600,
+            Some(ENROLLMENT_TOKEN_TYPE.into()),
+        );
+        pending.save(&pool).await.unwrap();
+        let mut used = Token::new(
+            user.id,
+            Some(admin.id),
+            None,
+            3600,
+            Some(ENROLLMENT_TOKEN_TYPE.into()),
+        );
+        used.used_at = Some(Utc::now().naive_utc());
+        used.save(&pool).await.unwrap();
+
+        let mut transaction = pool.begin().await.unwrap();
+        let reassigned = Token::reassign_admin(&mut transaction, admin.id, new_admin.id)
+            .await
+            .unwrap();
+        transaction.commit().await.unwrap();
+        assert_eq!(reassigned, 1);
+
+        // only the pending token is reassigned, used one keeps its history
+        let pending = Token::find_by_id(&pool, &pending.id).await.unwrap();
+        assert_eq!(pending.admin_id, Some(new_admin.id));
+        let used = Token::find_by_id(&pool, &used.id).await.unwrap();
+        assert_eq!(used.admin_id, Some(admin.id));
+    }
+
+    #[sqlx::test]
+    async fn test_completion_stats(pool: PgPool) {
+        let (admin, harry) = make_users(&pool).await;
+        let ron = User::new(
+            "rweasley",
+            Some("Pass123!"),
+            "Weasley",
+            "Ron",
+            "r.weasley@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+        let now = Utc::now().naive_utc();
+        let enrollment = |user_id| {
+            Token::new(
+                user_id,
+                Some(admin.id),
+                None,
+                3600,
+                Some(ENROLLMENT_TOKEN_TYPE.into()),
+            )
+        };
+
+        let record = |token: &Token, event_type, timestamp| {
+            let mut event = EnrollmentEvent::new(&token.id, token.user_id, event_type);
+            event.timestamp = timestamp;
+            event
+        };
+
+        // started, but not completed
+        let mut started = enrollment(harry.id);
+        started.used_at = Some(now);
+        started.save(&pool).await.unwrap();
+        record(&started, EnrollmentEventType::SessionStarted, now)
+            .save(&pool)
+            .await
+            .unwrap();
+        // completed
+        let mut completed = enrollment(ron.id);
+        completed.used_at = Some(now);
+        completed.save(&pool).await.unwrap();
+        for event_type in [
+            EnrollmentEventType::SessionStarted,
+            EnrollmentEventType::Completed,
+        ] {
+            record(&completed, event_type, now)
+                .save(&pool)
+                .await
+                .unwrap();
+        }
+        // expired without being used
+        let mut expired = enrollment(harry.id);
+        expired.expires_at = now - TimeDelta::hours(1);
+        expired.save(&pool).await.unwrap();
+        // pending
+        enrollment(harry.id).save(&pool).await.unwrap();
+        // other token types are not counted
+        let mut password_reset = Token::new(
+            ron.id,
+            Some(admin.id),
+            None,
+            3600,
+            Some(PASSWORD_RESET_TOKEN_TYPE.into()),
+        );
+        password_reset.used_at = Some(now);
+        password_reset.save(&pool).await.unwrap();
+        // outside of the time range
+        let mut old = enrollment(ron.id);
+        old.created_at = now - TimeDelta::days(60);
+        old.used_at = Some(old.created_at);
+        old.save(&pool).await.unwrap();
+        for event_type in [
+            EnrollmentEventType::SessionStarted,
+            EnrollmentEventType::Completed,
+        ] {
+            record(&old, event_type, old.created_at)
+                .save(&pool)
+                .await
+                .unwrap();
+        }
+
+        let recent_stats = EnrollmentStats {
+            issued: 4,
+            started: 2,
+            completed: 1,
+            expired_unused: 1,
+        };
+        let stats =
+            Token::completion_stats(&pool, now - TimeDelta::days(7), now + TimeDelta::minutes(1))
+                .await
+                .unwrap();
+        assert_eq!(stats, recent_stats);
+
+        let stats =
+            Token::completion_stats(&pool, now - TimeDelta::days(90), now - TimeDelta::days(7))
+                .await
+                .unwrap();
+        assert_eq!(
+            stats,
+            EnrollmentStats {
+                issued: 1,
+                started: 1,
+                completed: 1,
+                expired_unused: 0,
+            }
+        );
+
+        // removed and revoked tokens are still counted
+        Token::delete_unused_user_tokens(&pool, harry.id)
+            .await
+            .unwrap();
+        Token::revoke(&pool, ron.id, ENROLLMENT_TOKEN_TYPE, admin.id)
+            .await
+            .unwrap();
+        assert_eq!(Token::count(&pool).await.unwrap(), 2);
+        let stats =
+            Token::completion_stats(&pool, now - TimeDelta::days(7), now + TimeDelta::minutes(1))
+                .await
+                .unwrap();
+        assert_eq!(stats, recent_stats);
+    }
+
+    #[sqlx::test]
+    async fn test_bulk_enrollment(pool: PgPool) {
+        let _ = SERVER_CONFIG.set(DefGuardConfig::new_test_config());
+        initialize_current_settings(&pool).await.unwrap();
+        let (admin, harry) = make_users(&pool).await;
+        let ron = User::new(
+            "rweasley",
+            None,
+            "Weasley",
+            "Ron",
+            "r.weasley@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+        // user without an email address
+        let neville = User::new("nlongbottom", None, "Longbottom", "Neville", "", None)
+            .unwrap()
+            .save(&pool)
+            .await
+            .unwrap();
+        let user_ids = [harry.id, ron.id, neville.id];
+
+        // unused tokens from previous attempts
+        for user_id in user_ids {
+            Token::new(
+                user_id,
+                Some(admin.id),
+                None,
+                3600,
+                Some(ENROLLMENT_TOKEN_TYPE.into()),
+            )
+            .save(&pool)
+            .await
+            .unwrap();
+        }
+        let old_tokens = Token::fetch_all(&pool, None).await.unwrap();
+
+        // mail handler which delivers every mail and records the recipient
+        let (mail_tx, mut mail_rx) = unbounded_channel::<Mail>();
+        let (recipient_tx, mut recipient_rx) = unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(mail) = mail_rx.recv().await {
+                recipient_tx.send(mail.to).unwrap();
+                if let Some(result_tx) = mail.result_tx {
+                    let code = Code::new(
+                        Severity::PositiveCompletion,
+                        Category::MailSystem,
+                        Detail::Zero,
+                    );
+                    let _ = result_tx.send(Ok(Response::new(code, Vec::new())));
+                }
+            }
+        });
+
+        let started = User::start_bulk_enrollment(
+            &pool,
+            &admin,
+            &user_ids,
+            3600,
+            Url::parse("http://localhost:8080").unwrap(),
+            true,
+            mail_tx.clone(),
+            &MockSmsSender::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(started.len(), 3);
+        let tokens = Token::fetch_all(&pool, None).await.unwrap();
+        assert_eq!(tokens.len(), 3);
+        for user_id in user_ids {
+            let token = tokens
+                .iter()
+                .find(|token| token.user_id == user_id)
+                .unwrap();
+            assert_eq!(started[&user_id].token, token.id);
+            assert_eq!(token.admin_id, Some(admin.id));
+            assert!(token.used_at.is_none());
+        }
+        // unused tokens were cleared
+        for old_token in old_tokens {
+            assert!(!tokens.iter().any(|token| token.id == old_token.id));
+        }
+        // mail is sent only to users with an address
+        let mut recipients = vec![
+            recipient_rx.recv().await.unwrap(),
+            recipient_rx.recv().await.unwrap(),
+        ];
+        recipients.sort();
+        assert_eq!(recipients, vec![harry.email.clone(), ron.email.clone()]);
+        assert!(recipient_rx.try_recv().is_err());
+
+        assert!(started
+            .values()
+            .all(|enrollment| enrollment.mail_error.is_none()));
+
+        // one failure rolls back the whole batch
+        let result = User::start_bulk_enrollment(
+            &pool,
+            &admin,
+            &[harry.id, admin.id],
+            3600,
+            Url::parse("http://localhost:8080").unwrap(),
+            false,
+            mail_tx,
+            &MockSmsSender::default(),
+        )
+        .await;
+        assert!(matches!(result, Err(TokenError::AlreadyActive)));
+        let after_rollback = Token::fetch_all(&pool, None).await.unwrap();
+        assert_eq!(after_rollback.len(), 3);
+        assert!(after_rollback
+            .iter()
+            .any(|token| token.id == started[&harry.id].token));
+
+        // delivery failures are reported per user and keep the tokens
+        let started = User::start_bulk_enrollment(
+            &pool,
+            &admin,
+            &[harry.id, neville.id],
+            3600,
+            Url::parse("http://localhost:8080").unwrap(),
+            true,
+            failing_mailer(),
+            &MockSmsSender::default(),
+        )
+        .await
+        .unwrap();
+        assert!(started[&harry.id].mail_error.is_some());
+        assert!(started[&neville.id].mail_error.is_none());
+        let tokens = Token::fetch_all(&pool, None).await.unwrap();
+        assert!(tokens
+            .iter()
+            .any(|token| token.id == started[&harry.id].token));
+    }
+
+    #[sqlx::test]
+    async fn test_revoke_token(pool: PgPool) {
+        let (admin, user) = make_users(&pool).await;
+
+        // unused token
+        let token = Token::new(
+            user.id,
+            Some(admin.id),
+            None,
+            3600,
+            Some(ENROLLMENT_TOKEN_TYPE.into()),
+        );
+        token.save(&pool).await.unwrap();
+        let password_reset = Token::new(
+            user.id,
+            Some(admin.id),
+            None,
+            3600,
+            Some(PASSWORD_RESET_TOKEN_TYPE.into()),
+        );
+        password_reset.save(&pool).await.unwrap();
+        let revoked = Token::revoke(&pool, user.id, ENROLLMENT_TOKEN_TYPE, admin.id)
+            .await
+            .unwrap();
+        assert_eq!(revoked.len(), 1);
+        assert_eq!(revoked[0].id, token.id);
+        assert!(matches!(
+            Token::find_by_id(&pool, &token.id).await,
+            Err(TokenError::NotFound)
+        ));
+        // tokens of other types are kept
+        Token::find_by_id(&pool, &password_reset.id).await.unwrap();
+        // revoking again fails
+        assert!(matches!(
+            Token::revoke(&pool, user.id, ENROLLMENT_TOKEN_TYPE, admin.id).await,
+            Err(TokenError::NotFound)
+        ));
+
+        // token with a session in progress
+        let mut token = Token::new(
+            user.id,
+            Some(admin.id),
+            None,
+            3600,
+            Some(ENROLLMENT_TOKEN_TYPE.into()),
+        );
+        token.save(&pool).await.unwrap();
+        let mut transaction = pool.begin().await.unwrap();
+        token
+            .start_session(&mut transaction, 600, None, None)
+            .await
+            .unwrap();
+        transaction.commit().await.unwrap();
+        Token::revoke(&pool, user.id, ENROLLMENT_TOKEN_TYPE, admin.id)
+            .await
+            .unwrap();
+        // session can't be resumed, since the token is gone
+        assert!(matches!(
+            Token::find_by_id(&pool, &token.id).await,
+            Err(TokenError::NotFound)
+        ));
+    }
+
+    #[sqlx::test]
+    async fn test_resend_notification(pool: PgPool) {
+        let _ = SERVER_CONFIG.set(DefGuardConfig::new_test_config());
+        initialize_current_settings(&pool).await.unwrap();
+        let (admin, user) = make_users(&pool).await;
+        let url = Url::parse("http://localhost:8080").unwrap();
+
+        // mail handler which delivers every mail and passes it on for inspection
+        let (mail_tx, mut mail_rx) = unbounded_channel::<Mail>();
+        let (sent_tx, mut sent_rx) = unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(mut mail) = mail_rx.recv().await {
+                if let Some(result_tx) = mail.result_tx.take() {
+                    let code = Code::new(
+                        Severity::PositiveCompletion,
+                        Category::MailSystem,
+                        Detail::Zero,
+                    );
+                    let _ = result_tx.send(Ok(Response::new(code, Vec::new())));
+                }
+                sent_tx.send(mail).unwrap();
+            }
+        });
+
+        let enrollment = user
+            .start_enrollment(
+                &pool,
+                &admin,
+                Some(user.email.clone()),
+                3600,
+                url.clone(),
+                false,
+                mail_tx.clone(),
+                &MockSmsSender::default(),
+                None,
+                EnrollmentMailFailurePolicy::Abort,
+            )
+            .await
+            .unwrap();
+        assert!(sent_rx.try_recv().is_err());
+
+        let token = Token::find_pending_enrollment(&pool, user.id)
+            .await
+            .unwrap();
+        assert_eq!(token.id, enrollment.token);
+        token
+            .resend_notification(&pool, &mail_tx, &MockSmsSender::default(), url.clone())
+            .await
+            .unwrap();
+        let mail = sent_rx.recv().await.unwrap();
+        assert_eq!(mail.to, user.email);
+        assert_eq!(mail.subject, ENROLLMENT_START_MAIL_SUBJECT);
+        assert!(mail.content.contains(&enrollment.token));
+        // token is unchanged
+        let tokens = Token::fetch_all(&pool, None).await.unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].id, enrollment.token);
+
+        // used token can't be resent
+        let mut token = Token::find_by_id(&pool, &enrollment.token).await.unwrap();
+        let mut transaction = pool.begin().await.unwrap();
+        token
+            .start_session(&mut transaction, 600, None, None)
+            .await
+            .unwrap();
+        transaction.commit().await.unwrap();
+        assert!(matches!(
+            token
+                .resend_notification(&pool, &mail_tx, &MockSmsSender::default(), url.clone())
+                .await,
+            Err(TokenError::TokenUsed)
+        ));
+        assert!(matches!(
+            Token::find_pending_enrollment(&pool, user.id).await,
+            Err(TokenError::NotFound)
+        ));
+
+        // neither can an expired one
+        let mut expired = Token::new(
+            user.id,
+            Some(admin.id),
+            Some(user.email.clone()),
+            3600,
+            Some(ENROLLMENT_TOKEN_TYPE.into()),
+        );
+        expired.expires_at = Utc::now().naive_utc() - TimeDelta::minutes(1);
+        assert!(matches!(
+            expired
+                .resend_notification(&pool, &mail_tx, &MockSmsSender::default(), url)
+                .await,
+            Err(TokenError::TokenExpired)
+        ));
+        assert!(sent_rx.try_recv().is_err());
+    }
+
+    #[sqlx::test]
+    async fn test_enrollment_history(pool: PgPool) {
+        let (admin, user) = make_users(&pool).await;
+        let token = Token::new(
+            user.id,
+            Some(admin.id),
+            Some(user.email.clone()),
+            3600,
+            Some(ENROLLMENT_TOKEN_TYPE.into()),
+        );
+        token.save(&pool).await.unwrap();
+
+        let mut token = Token::find_by_id(&pool, &token.id).await.unwrap();
+        let mut transaction = pool.begin().await.unwrap();
+        token
+            .start_session(&mut transaction, 600, Some("10.0.0.1"), Some("curl/8.0"))
+            .await
+            .unwrap();
+        transaction.commit().await.unwrap();
+        // resuming the session isn't a new event
+        let mut transaction = pool.begin().await.unwrap();
+        token
+            .start_session(&mut transaction, 600, None, None)
+            .await
+            .unwrap();
+        transaction.commit().await.unwrap();
+
+        let history = Token::history(&pool, user.id).await.unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].event_type, EnrollmentEventType::TokenCreated);
+        assert_eq!(history[0].admin_id, Some(admin.id));
+        assert_eq!(history[0].timestamp, token.created_at);
+        assert_eq!(history[1].event_type, EnrollmentEventType::SessionStarted);
+        assert_eq!(history[1].ip_address.as_deref(), Some("10.0.0.1"));
+        assert_eq!(history[1].user_agent.as_deref(), Some("curl/8.0"));
+        assert!(history
+            .iter()
+            .all(|event| event.token_id == token.id && event.user_id == user.id));
+
+        // other token types aren't recorded
+        let mut reset = Token::new(
+            user.id,
+            Some(admin.id),
+            None,
+            3600,
+            Some(PASSWORD_RESET_TOKEN_TYPE.into()),
+        );
+        reset.created_at -= TimeDelta::hours(1);
+        reset.expires_at = Utc::now().naive_utc() - TimeDelta::minutes(1);
+        reset.save(&pool).await.unwrap();
+        assert_eq!(Token::record_expired(&pool).await.unwrap(), 0);
+        assert_eq!(Token::history(&pool, user.id).await.unwrap().len(), 2);
+
+        // expiry of unused tokens is recorded once
+        let mut expired = Token::new(
+            user.id,
+            Some(admin.id),
+            None,
+            3600,
+            Some(ENROLLMENT_TOKEN_TYPE.into()),
+        );
+        expired.created_at -= TimeDelta::hours(1);
+        expired.expires_at = Utc::now().naive_utc() - TimeDelta::minutes(1);
+        expired.save(&pool).await.unwrap();
+        assert_eq!(Token::record_expired(&pool).await.unwrap(), 1);
+        assert_eq!(Token::record_expired(&pool).await.unwrap(), 0);
+        let history = Token::history(&pool, user.id).await.unwrap();
+        assert_eq!(history.len(), 4);
+        let expired_events: Vec<_> = history
+            .iter()
+            .filter(|event| event.token_id == expired.id)
+            .map(|event| event.event_type)
+            .collect();
+        assert_eq!(
+            expired_events,
+            [
+                EnrollmentEventType::TokenCreated,
+                EnrollmentEventType::Expired
+            ]
+        );
+
+        // history outlives revoked tokens
+        Token::revoke(&pool, user.id, ENROLLMENT_TOKEN_TYPE, admin.id)
+            .await
+            .unwrap();
+        Token::revoke(&pool, user.id, PASSWORD_RESET_TOKEN_TYPE, admin.id)
+            .await
+            .unwrap();
+        let history = Token::history(&pool, user.id).await.unwrap();
+        assert_eq!(history.len(), 6);
+        assert!(history[4..].iter().all(|event| {
+            event.event_type == EnrollmentEventType::Revoked && event.admin_id == Some(admin.id)
+        }));
+
+        assert!(Token::history(&pool, admin.id).await.unwrap().is_empty());
+    }
+
+    #[sqlx::test]
+    async fn test_enrollment_sms(pool: PgPool) {
+        let _ = SERVER_CONFIG.set(DefGuardConfig::new_test_config());
+        initialize_current_settings(&pool).await.unwrap();
+        let (admin, mut user) = make_users(&pool).await;
+        let url = Url::parse("http://localhost:8080").unwrap();
+        // no mail is expected
+        let (mail_tx, mut mail_rx) = unbounded_channel::<Mail>();
+
+        // user without phone number gets nothing
+        let sms_sender = MockSmsSender::default();
+        user.start_enrollment(
+            &pool,
+            &admin,
+            None,
+            3600,
+            url.clone(),
+            true,
+            mail_tx.clone(),
+            &sms_sender,
+            None,
+            EnrollmentMailFailurePolicy::Abort,
+        )
+        .await
+        .unwrap();
+        assert!(sms_sender.sent.lock().unwrap().is_empty());
+
+        // phone-only user gets a text message with enrollment link
+        user.phone = Some("+48123456789".into());
+        user.save(&pool).await.unwrap();
+        let enrollment = user
+            .start_enrollment(
+                &pool,
+                &admin,
+                None,
+                3600,
+                url.clone(),
+                true,
+                mail_tx.clone(),
+                &sms_sender,
+                None,
+                EnrollmentMailFailurePolicy::Abort,
+            )
+            .await
+            .unwrap();
+        assert!(enrollment.sms_error.is_none());
+        let sent = sms_sender.sent.lock().unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to, "+48123456789");
+        assert!(sent[0]
+            .content
+            .contains(&format!("token={}", enrollment.token)));
+        drop(sent);
+
+        // no text message without notification
+        user.start_enrollment(
+            &pool,
+            &admin,
+            None,
+            3600,
+            url.clone(),
+            false,
+            mail_tx.clone(),
+            &sms_sender,
+            None,
+            EnrollmentMailFailurePolicy::Abort,
+        )
+        .await
+        .unwrap();
+        assert_eq!(sms_sender.sent.lock().unwrap().len(), 1);
+
+        // delivery failure follows mail failure policy
+        let unavailable = MockSmsSender {
+            unavailable: true,
+            ..Default::default()
+        };
+        let result = user
+            .start_enrollment(
+                &pool,
+                &admin,
+                None,
+                3600,
+                url.clone(),
+                true,
+                mail_tx.clone(),
+                &unavailable,
+                None,
+                EnrollmentMailFailurePolicy::Abort,
+            )
+            .await;
+        assert!(matches!(result, Err(TokenError::SmsError(_))));
+        let enrollment = user
+            .start_enrollment(
+                &pool,
+                &admin,
+                None,
+                3600,
+                url.clone(),
+                true,
+                mail_tx.clone(),
+                &unavailable,
+                None,
+                EnrollmentMailFailurePolicy::Warn,
+            )
+            .await
+            .unwrap();
+        assert!(enrollment.sms_error.is_some());
+
+        // resending texts the same link again
+        let token = Token::find_pending_enrollment(&pool, user.id)
+            .await
+            .unwrap();
+        assert_eq!(token.id, enrollment.token);
+        token
+            .resend_notification(&pool, &mail_tx, &sms_sender, url)
+            .await
+            .unwrap();
+        let sent = sms_sender.sent.lock().unwrap();
+        assert_eq!(sent.len(), 2);
+        assert!(sent[1]
+            .content
+            .contains(&format!("token={}", enrollment.token)));
+        drop(sent);
+        assert!(mail_rx.try_recv().is_err());
+    }
+}
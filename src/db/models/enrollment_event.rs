@@ -0,0 +1,78 @@
+use chrono::{NaiveDateTime, Utc};
+use model_derive::Model;
+use sqlx::{query_as, Error as SqlxError, PgExecutor, Type};
+use utoipa::ToSchema;
+
+use crate::db::{Id, NoId};
+
+/// Enrollment token lifecycle transition.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize, ToSchema, Type)]
+#[sqlx(type_name = "enrollment_event_type", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum EnrollmentEventType {
+    TokenCreated,
+    EmailSent,
+    SessionStarted,
+    Completed,
+    Expired,
+    Revoked,
+}
+
+/// Entry of enrollment audit log. Acting admin is set for token creation and revocation,
+/// client IP address and user agent for session start and completion.
+/// Only enrollment tokens are recorded; the token id is a secret, so it's not serialized.
+#[derive(Debug, Model, Serialize, ToSchema)]
+#[table(enrollment_event)]
+pub struct EnrollmentEvent<I = NoId> {
+    pub id: I,
+    #[serde(skip)]
+    pub token_id: String,
+    pub user_id: Id,
+    #[model(enum)]
+    pub event_type: EnrollmentEventType,
+    pub admin_id: Option<Id>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub timestamp: NaiveDateTime,
+}
+
+impl EnrollmentEvent {
+    #[must_use]
+    pub fn new<S: Into<String>>(token_id: S, user_id: Id, event_type: EnrollmentEventType) -> Self {
+        Self {
+            id: NoId,
+            token_id: token_id.into(),
+            user_id,
+            event_type,
+            admin_id: None,
+            ip_address: None,
+            user_agent: None,
+            timestamp: Utc::now().naive_utc(),
+        }
+    }
+
+    /// Set client details, empty values are skipped.
+    #[must_use]
+    pub fn with_client(mut self, ip_address: Option<&str>, user_agent: Option<&str>) -> Self {
+        self.ip_address = ip_address.filter(|ip| !ip.is_empty()).map(Into::into);
+        self.user_agent = user_agent.filter(|agent| !agent.is_empty()).map(Into::into);
+        self
+    }
+}
+
+impl EnrollmentEvent<Id> {
+    /// Fetch events of all enrollment tokens of given user, oldest first.
+    pub async fn find_by_user_id<'e, E>(executor: E, user_id: Id) -> Result<Vec<Self>, SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
+        query_as!(
+            Self,
+            "SELECT id, token_id, user_id, event_type \"event_type: _\", admin_id, ip_address, \
+            user_agent, timestamp FROM enrollment_event WHERE user_id = $1 ORDER BY timestamp, id",
+            user_id
+        )
+        .fetch_all(executor)
+        .await
+    }
+}
@@ -19,11 +19,21 @@ impl fmt::Display for Permission {
     }
 }
 
+/// Group granted SSH access to a host, with the number of its active members.
+#[derive(Debug, Deserialize, PartialEq, Serialize, ToSchema)]
+pub struct SshHostGroup {
+    pub id: Id,
+    pub name: String,
+    pub user_count: i64,
+}
+
 #[derive(Debug, Model, ToSchema, FromRow)]
 pub struct Group<I = NoId> {
     pub(crate) id: I,
     pub name: String,
     pub is_admin: bool,
+    /// Principal put in SSH certificates of group members. Group name is used if not set.
+    pub ssh_principal: Option<String>,
 }
 
 impl Group {
@@ -33,10 +43,36 @@ impl Group {
             id: NoId,
             name: name.into(),
             is_admin: false,
+            ssh_principal: None,
         }
     }
 }
 
+/// Maximum length of SSH principal.
+const MAX_SSH_PRINCIPAL_LENGTH: usize = 255;
+
+/// Check SSH principal syntax: printable ASCII without whitespace, and without commas,
+/// which separate principals in `authorized_keys` options and `AuthorizedPrincipalsFile`.
+pub(crate) fn validate_ssh_principal(principal: &str) -> Result<(), String> {
+    if principal.is_empty() || principal.len() > MAX_SSH_PRINCIPAL_LENGTH {
+        return Err(format!(
+            "SSH principal must be 1 to {MAX_SSH_PRINCIPAL_LENGTH} characters long"
+        ));
+    }
+    if !principal.chars().all(|c| c.is_ascii_graphic() && c != ',') {
+        return Err(format!("Invalid SSH principal: {principal}"));
+    }
+    Ok(())
+}
+
+impl<I> Group<I> {
+    /// Principal used for this group in SSH certificates.
+    #[must_use]
+    pub fn ssh_principal_name(&self) -> &str {
+        self.ssh_principal.as_deref().unwrap_or(&self.name)
+    }
+}
+
 impl Group<Id> {
     pub async fn find_by_name<'e, E>(executor: E, name: &str) -> Result<Option<Self>, SqlxError>
     where
@@ -44,13 +80,27 @@ impl Group<Id> {
     {
         query_as!(
             Self,
-            "SELECT id, name, is_admin FROM \"group\" WHERE name = $1",
+            "SELECT id, name, is_admin, ssh_principal FROM \"group\" WHERE name = $1",
             name
         )
         .fetch_optional(executor)
         .await
     }
 
+    /// Number of members of every group, including groups without members.
+    pub async fn member_counts<'e, E>(executor: E) -> Result<Vec<(Id, i64)>, SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
+        query_as(
+            "SELECT g.id, COUNT(gu.user_id) FROM \"group\" g \
+            LEFT JOIN group_user gu ON gu.group_id = g.id \
+            GROUP BY g.id ORDER BY g.id",
+        )
+        .fetch_all(executor)
+        .await
+    }
+
     pub async fn member_usernames<'e, E>(&self, executor: E) -> Result<Vec<String>, SqlxError>
     where
         E: PgExecutor<'e>,
@@ -71,8 +121,9 @@ impl Group<Id> {
         query_as!(
             User,
             "SELECT \"user\".id, username, password_hash, last_name, first_name, email, \
-            phone, mfa_enabled, totp_enabled, totp_secret, email_mfa_enabled, email_mfa_secret, \
-            mfa_method \"mfa_method: _\", recovery_codes, is_active, openid_sub \
+            phone, mfa_enabled, totp_enabled, totp_secret, totp_digits, totp_period, email_mfa_enabled, email_mfa_secret, \
+            mfa_method \"mfa_method: _\", recovery_codes, is_active, openid_sub, \
+            suspended_until, password_changed_at, must_change_password \
             FROM \"user\" \
             JOIN group_user ON \"user\".id = group_user.user_id \
             WHERE group_user.group_id = $1",
@@ -82,6 +133,28 @@ impl Group<Id> {
         .await
     }
 
+    /// Copy all members of the source group into this group. Users who already belong to this
+    /// group are left untouched. Returns the number of newly added members.
+    pub async fn clone_members_from<'e, E>(
+        &self,
+        executor: E,
+        source_group_id: Id,
+    ) -> Result<u64, SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
+        let result = query!(
+            "INSERT INTO group_user (group_id, user_id) \
+            SELECT $1, user_id FROM group_user WHERE group_id = $2 \
+            ON CONFLICT DO NOTHING",
+            self.id,
+            source_group_id
+        )
+        .execute(executor)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
     /// Fetches a list of VPN locations where a given group is explicitly allowed.
     /// This does not include VPN locations where all groups are implicitly allowed (admin group),
     /// because no access control in configured.
@@ -106,7 +179,7 @@ impl Group<Id> {
         E: PgExecutor<'e>,
     {
         let query = format!(
-            "SELECT id, name, is_admin FROM \"group\" WHERE {permission} = TRUE ORDER BY id"
+            "SELECT id, name, is_admin, ssh_principal FROM \"group\" WHERE {permission} = TRUE ORDER BY id"
         );
         query_as(&query).fetch_all(executor).await
     }
@@ -144,6 +217,68 @@ impl Group<Id> {
             .await?;
         Ok(())
     }
+
+    /// Groups granted SSH access to `hostname`, sorted by name.
+    pub async fn find_by_ssh_host<'e, E>(
+        executor: E,
+        hostname: &str,
+    ) -> Result<Vec<SshHostGroup>, SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
+        query_as!(
+            SshHostGroup,
+            "SELECT g.id, g.name, COUNT(u.id) \"user_count!\" FROM ssh_host_group h \
+            JOIN \"group\" g ON g.id = h.group_id \
+            LEFT JOIN group_user gu ON gu.group_id = g.id \
+            LEFT JOIN \"user\" u ON u.id = gu.user_id AND u.is_active \
+            WHERE h.hostname = $1 GROUP BY g.id, g.name ORDER BY g.name",
+            hostname
+        )
+        .fetch_all(executor)
+        .await
+    }
+
+    /// Ids of users belonging to any group granted SSH access to `hostname`.
+    pub async fn find_ssh_host_member_ids<'e, E>(
+        executor: E,
+        hostname: &str,
+    ) -> Result<Vec<Id>, SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
+        query_scalar!(
+            "SELECT DISTINCT gu.user_id FROM ssh_host_group h \
+            JOIN group_user gu ON gu.group_id = h.group_id WHERE h.hostname = $1",
+            hostname
+        )
+        .fetch_all(executor)
+        .await
+    }
+
+    /// Replace groups granted SSH access to `hostname`.
+    pub async fn set_ssh_host_groups(
+        transaction: &mut PgConnection,
+        hostname: &str,
+        group_ids: &[Id],
+    ) -> Result<(), SqlxError> {
+        query!("DELETE FROM ssh_host_group WHERE hostname = $1", hostname)
+            .execute(&mut *transaction)
+            .await?;
+        query!(
+            "INSERT INTO ssh_host_group (hostname, group_id) \
+            SELECT $1, group_id FROM UNNEST($2::bigint[]) group_id ON CONFLICT DO NOTHING",
+            hostname,
+            group_ids
+        )
+        .execute(&mut *transaction)
+        .await?;
+        info!(
+            "Granted SSH access to host {hostname} to {} groups",
+            group_ids.len()
+        );
+        Ok(())
+    }
 }
 
 impl WireguardNetwork<Id> {
@@ -310,12 +445,13 @@ mod test {
         let group = Group::new("worker").save(&pool).await.unwrap();
         let user = User::new(
             "hpotter",
-            Some("pass123"),
+            Some("Pass123!"),
             "Potter",
             "Harry",
             "h.potter@hogwart.edu.uk",
             None,
         )
+        .unwrap()
         .save(&pool)
         .await
         .unwrap();
@@ -331,17 +467,190 @@ mod test {
         assert!(members.is_empty());
     }
 
+    #[sqlx::test]
+    async fn test_member_counts(pool: PgPool) {
+        let gryffindor = Group::new("gryffindor").save(&pool).await.unwrap();
+        let slytherin = Group::new("slytherin").save(&pool).await.unwrap();
+        let hufflepuff = Group::new("hufflepuff").save(&pool).await.unwrap();
+        let students = [
+            ("hpotter", "h.potter@hogwart.edu.uk", &gryffindor),
+            ("hgranger", "h.granger@hogwart.edu.uk", &gryffindor),
+            ("rweasley", "r.weasley@hogwart.edu.uk", &gryffindor),
+            ("dmalfoy", "d.malfoy@hogwart.edu.uk", &slytherin),
+        ];
+        for (username, email, group) in students {
+            let user = User::new(username, None, "Student", "Hogwart", email, None)
+                .unwrap()
+                .save(&pool)
+                .await
+                .unwrap();
+            user.add_to_group(&pool, group).await.unwrap();
+        }
+
+        let counts = Group::member_counts(&pool).await.unwrap();
+        assert!(counts.contains(&(gryffindor.id, 3)));
+        assert!(counts.contains(&(slytherin.id, 1)));
+        // empty groups are included
+        assert!(counts.contains(&(hufflepuff.id, 0)));
+        let groups = Group::all(&pool).await.unwrap();
+        assert_eq!(counts.len(), groups.len());
+    }
+
+    #[sqlx::test]
+    async fn test_ssh_host_groups(pool: PgPool) {
+        let gryffindor = Group::new("gryffindor").save(&pool).await.unwrap();
+        let slytherin = Group::new("slytherin").save(&pool).await.unwrap();
+        let hufflepuff = Group::new("hufflepuff").save(&pool).await.unwrap();
+        let students = [
+            ("hpotter", "h.potter@hogwart.edu.uk", &gryffindor, true),
+            ("hgranger", "h.granger@hogwart.edu.uk", &gryffindor, true),
+            (
+                "nlongbottom",
+                "n.longbottom@hogwart.edu.uk",
+                &gryffindor,
+                false,
+            ),
+            ("dmalfoy", "d.malfoy@hogwart.edu.uk", &slytherin, true),
+        ];
+        for (username, email, group, is_active) in students {
+            let mut user = User::new(username, None, "Student", "Hogwart", email, None).unwrap();
+            user.is_active = is_active;
+            let user = user.save(&pool).await.unwrap();
+            user.add_to_group(&pool, group).await.unwrap();
+        }
+
+        let mut transaction = pool.begin().await.unwrap();
+        Group::set_ssh_host_groups(
+            &mut transaction,
+            "common-room.hogwart.edu.uk",
+            &[slytherin.id, gryffindor.id],
+        )
+        .await
+        .unwrap();
+        Group::set_ssh_host_groups(
+            &mut transaction,
+            "greenhouse.hogwart.edu.uk",
+            &[hufflepuff.id],
+        )
+        .await
+        .unwrap();
+        transaction.commit().await.unwrap();
+
+        let groups = Group::find_by_ssh_host(&pool, "common-room.hogwart.edu.uk")
+            .await
+            .unwrap();
+        assert_eq!(
+            groups,
+            [
+                // inactive members are not counted
+                SshHostGroup {
+                    id: gryffindor.id,
+                    name: "gryffindor".into(),
+                    user_count: 2,
+                },
+                SshHostGroup {
+                    id: slytherin.id,
+                    name: "slytherin".into(),
+                    user_count: 1,
+                },
+            ]
+        );
+        assert!(Group::find_by_ssh_host(&pool, "unknown.hogwart.edu.uk")
+            .await
+            .unwrap()
+            .is_empty());
+
+        // groups are replaced
+        let mut transaction = pool.begin().await.unwrap();
+        Group::set_ssh_host_groups(
+            &mut transaction,
+            "common-room.hogwart.edu.uk",
+            &[gryffindor.id],
+        )
+        .await
+        .unwrap();
+        transaction.commit().await.unwrap();
+        let groups = Group::find_by_ssh_host(&pool, "common-room.hogwart.edu.uk")
+            .await
+            .unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].id, gryffindor.id);
+        // other hosts are not affected
+        let groups = Group::find_by_ssh_host(&pool, "greenhouse.hogwart.edu.uk")
+            .await
+            .unwrap();
+        assert_eq!(groups.len(), 1);
+    }
+
+    #[sqlx::test]
+    async fn test_clone_members_from(pool: PgPool) {
+        let gryffindor = Group::new("gryffindor").save(&pool).await.unwrap();
+        let quidditch = Group::new("quidditch").save(&pool).await.unwrap();
+        let mut gryffindors = Vec::new();
+        for (username, email) in [
+            ("hpotter", "h.potter@hogwart.edu.uk"),
+            ("hgranger", "h.granger@hogwart.edu.uk"),
+            ("rweasley", "r.weasley@hogwart.edu.uk"),
+        ] {
+            let user = User::new(username, None, "Student", "Hogwart", email, None)
+                .unwrap()
+                .save(&pool)
+                .await
+                .unwrap();
+            user.add_to_group(&pool, &gryffindor).await.unwrap();
+            gryffindors.push(user);
+        }
+        // already a member of the target group
+        gryffindors[0]
+            .add_to_group(&pool, &quidditch)
+            .await
+            .unwrap();
+        // not a member of the source group
+        User::new(
+            "dmalfoy",
+            None,
+            "Malfoy",
+            "Draco",
+            "d.malfoy@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+
+        let added = quidditch
+            .clone_members_from(&pool, gryffindor.id)
+            .await
+            .unwrap();
+        assert_eq!(added, 2);
+
+        let mut members = quidditch.member_usernames(&pool).await.unwrap();
+        members.sort();
+        assert_eq!(members, ["hgranger", "hpotter", "rweasley"]);
+        // source group is unchanged
+        assert_eq!(gryffindor.member_usernames(&pool).await.unwrap().len(), 3);
+
+        // cloning again adds nothing
+        let added = quidditch
+            .clone_members_from(&pool, gryffindor.id)
+            .await
+            .unwrap();
+        assert_eq!(added, 0);
+    }
+
     #[sqlx::test]
     async fn test_group_permissions(pool: PgPool) {
         let group = Group::new("admin2").save(&pool).await.unwrap();
         let user = User::new(
             "hpotter",
-            Some("pass123"),
+            Some("Pass123!"),
             "Potter",
             "Harry",
             "h.potter@hogwart.edu.uk",
             None,
         )
+        .unwrap()
         .save(&pool)
         .await
         .unwrap();
@@ -0,0 +1,46 @@
+use chrono::{NaiveDateTime, Utc};
+use model_derive::Model;
+use sqlx::{query_as, Error as SqlxError, PgExecutor};
+
+use crate::db::{Id, NoId};
+
+/// Audit entry of MFA reset performed by an admin, see [`User::bulk_disable_mfa`].
+///
+/// [`User::bulk_disable_mfa`]: crate::db::User::bulk_disable_mfa
+#[derive(Debug, Model, Serialize)]
+#[table(mfa_reset_event)]
+pub struct MfaResetEvent<I = NoId> {
+    pub id: I,
+    pub user_id: Id,
+    pub admin_id: Option<Id>,
+    pub timestamp: NaiveDateTime,
+}
+
+impl MfaResetEvent {
+    #[must_use]
+    pub fn new(user_id: Id, admin_id: Option<Id>) -> Self {
+        Self {
+            id: NoId,
+            user_id,
+            admin_id,
+            timestamp: Utc::now().naive_utc(),
+        }
+    }
+}
+
+impl MfaResetEvent<Id> {
+    /// Fetch MFA resets of given user, oldest first.
+    pub async fn find_by_user_id<'e, E>(executor: E, user_id: Id) -> Result<Vec<Self>, SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
+        query_as!(
+            Self,
+            "SELECT id, user_id, admin_id, timestamp FROM mfa_reset_event \
+            WHERE user_id = $1 ORDER BY timestamp, id",
+            user_id
+        )
+        .fetch_all(executor)
+        .await
+    }
+}
@@ -4,8 +4,10 @@ pub mod authentication_key;
 pub mod device;
 pub mod device_login;
 pub mod enrollment;
+pub mod enrollment_event;
 pub mod error;
 pub mod group;
+pub mod mfa_reset_event;
 #[cfg(feature = "openid")]
 pub mod oauth2authorizedapp;
 #[cfg(feature = "openid")]
@@ -13,23 +15,27 @@ pub mod oauth2client;
 #[cfg(feature = "openid")]
 pub mod oauth2token;
 pub mod polling_token;
+pub mod recovery_codes;
 pub mod session;
 pub mod settings;
 pub mod user;
 pub mod webauthn;
 pub mod webhook;
+pub mod webhook_delivery;
 pub mod wireguard;
 pub mod wireguard_peer_stats;
 pub mod yubikey;
 
+use chrono::NaiveDateTime;
 use sqlx::{query_as, Error as SqlxError, PgConnection, PgPool};
 use utoipa::ToSchema;
 
 use self::{
     device::UserDevice,
-    user::{MFAMethod, User},
+    user::{MFAMethod, RecoveryCodesSummary, User},
 };
 use super::{Group, Id};
+use crate::SERVER_CONFIG;
 
 #[cfg(feature = "openid")]
 #[derive(Deserialize, Serialize)]
@@ -54,6 +60,16 @@ pub struct SecurityKey {
     pub name: String,
 }
 
+/// Security key together with its owner, used in the admin listing.
+/// Credential data is deliberately left out.
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct SecurityKeyOwnerInfo {
+    pub id: Id,
+    pub name: String,
+    pub user_id: Id,
+    pub username: String,
+}
+
 // Basic user info used in user list, etc.
 #[derive(Clone, Debug, Deserialize, Serialize, ToSchema)]
 pub struct UserInfo {
@@ -70,6 +86,18 @@ pub struct UserInfo {
     pub mfa_method: MFAMethod,
     pub authorized_apps: Vec<OAuth2AuthorizedAppInfo>,
     pub is_active: bool,
+    /// Read-only, use the dedicated endpoints to suspend a user.
+    #[serde(default)]
+    pub suspended_until: Option<NaiveDateTime>,
+    /// Password has to be changed before using the account; read-only.
+    #[serde(default)]
+    pub password_expired: bool,
+    /// Set only if maximum password age is configured and the user has a password; read-only.
+    #[serde(default)]
+    pub days_until_password_expiry: Option<i64>,
+    /// Password has been reset by an admin and has to be changed by the user; read-only.
+    #[serde(default)]
+    pub must_change_password: bool,
     pub enrolled: bool,
     pub is_admin: bool,
 }
@@ -78,6 +106,11 @@ impl UserInfo {
     pub async fn from_user(pool: &PgPool, user: &User<Id>) -> Result<Self, SqlxError> {
         let groups = user.member_of_names(pool).await?;
         let authorized_apps = user.oauth2authorizedapps(pool).await?;
+        let password_max_age = SERVER_CONFIG
+            .get()
+            .and_then(|config| config.password_max_age)
+            .filter(|_| user.has_password())
+            .map(Into::into);
 
         Ok(Self {
             id: user.id,
@@ -93,6 +126,12 @@ impl UserInfo {
             mfa_method: user.mfa_method.clone(),
             authorized_apps,
             is_active: user.is_active,
+            suspended_until: user.active_suspension(),
+            password_expired: password_max_age
+                .is_some_and(|max_age| user.password_expired(max_age)),
+            days_until_password_expiry: password_max_age
+                .map(|max_age| user.days_until_password_expiry(max_age)),
+            must_change_password: user.must_change_password,
             enrolled: user.is_enrolled(),
             is_admin: user.is_admin(pool).await?,
         })
@@ -188,6 +227,9 @@ pub struct UserDetails {
     pub devices: Vec<UserDevice>,
     #[serde(default)]
     pub security_keys: Vec<SecurityKey>,
+    /// Only filled in for administrators and the user themselves.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recovery_codes: Option<RecoveryCodesSummary>,
 }
 
 impl UserDetails {
@@ -199,11 +241,12 @@ impl UserDetails {
             user: UserInfo::from_user(pool, user).await?,
             devices,
             security_keys,
+            recovery_codes: None,
         })
     }
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
 pub struct MFAInfo {
     mfa_method: MFAMethod,
     totp_available: bool,
@@ -260,12 +303,13 @@ mod test {
     async fn test_user_info(pool: PgPool) {
         let user = User::new(
             "hpotter",
-            Some("pass123"),
+            Some("Pass123!"),
             "Potter",
             "Harry",
             "h.potter@hogwart.edu.uk",
             None,
         )
+        .unwrap()
         .save(&pool)
         .await
         .unwrap();
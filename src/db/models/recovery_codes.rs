@@ -0,0 +1,95 @@
+use chrono::{NaiveDateTime, TimeDelta, Utc};
+use sqlx::{query, query_scalar, Error as SqlxError, PgExecutor};
+
+use crate::{db::Id, random::gen_alphanumeric, secret::EncryptedSecret, server_config};
+
+/// Separator of codes in the encrypted `codes` column.
+const CODES_SEPARATOR: &str = "\n";
+
+/// Freshly generated recovery codes parked on the server until the user
+/// exchanges the bundle id for them. The id is an opaque random value which is
+/// only meaningful to this table; each bundle can be exchanged only once and is
+/// useless after `expires`. Codes are stored encrypted and expired bundles are
+/// purged periodically by the utility thread.
+pub struct RecoveryCodesBundle {
+    pub id: String,
+    pub user_id: Id,
+    pub codes: Vec<String>,
+    pub expires: NaiveDateTime,
+}
+
+impl RecoveryCodesBundle {
+    #[must_use]
+    pub fn new(user_id: Id, codes: Vec<String>) -> Self {
+        let timeout = server_config().recovery_codes_bundle_timeout;
+        Self {
+            id: gen_alphanumeric(48),
+            user_id,
+            codes,
+            expires: (Utc::now() + TimeDelta::seconds(timeout.as_secs() as i64)).naive_utc(),
+        }
+    }
+
+    pub async fn save<'e, E>(&self, executor: E) -> Result<(), SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
+        query!(
+            "INSERT INTO recovery_codes_bundle (id, user_id, codes, expires) \
+            VALUES ($1, $2, $3, $4)",
+            self.id,
+            self.user_id,
+            &EncryptedSecret::from(self.codes.join(CODES_SEPARATOR)) as &EncryptedSecret,
+            self.expires,
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    /// One-time token which can be exchanged for the codes.
+    #[must_use]
+    pub fn token(&self) -> &str {
+        &self.id
+    }
+
+    /// Consume the bundle identified by `token`.
+    /// Returns `None` if the token is unknown, belongs to another user,
+    /// has expired or has already been used.
+    pub async fn exchange<'e, E>(
+        executor: E,
+        user_id: Id,
+        token: &str,
+    ) -> Result<Option<Vec<String>>, SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
+        let codes = query_scalar!(
+            "DELETE FROM recovery_codes_bundle \
+            WHERE id = $1 AND user_id = $2 AND expires > now() \
+            RETURNING codes \"codes: EncryptedSecret\"",
+            token,
+            user_id,
+        )
+        .fetch_optional(executor)
+        .await?;
+        Ok(codes.map(|codes| {
+            codes
+                .expose_secret()
+                .split(CODES_SEPARATOR)
+                .map(ToString::to_string)
+                .collect()
+        }))
+    }
+
+    /// Remove expired bundles which were never exchanged. Returns the number of removed bundles.
+    pub async fn delete_expired<'e, E>(executor: E) -> Result<u64, SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
+        let result = query!("DELETE FROM recovery_codes_bundle WHERE expires <= now()")
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
@@ -4,6 +4,9 @@ use webauthn_rs::prelude::{PasskeyAuthentication, PasskeyRegistration};
 
 use crate::{db::Id, random::gen_alphanumeric, server_config};
 
+/// Minimal interval between consecutive `last_seen` updates.
+const LAST_SEEN_UPDATE_INTERVAL_SECONDS: i64 = 60;
+
 #[derive(Clone, PartialEq, Type)]
 #[repr(i16)]
 pub enum SessionState {
@@ -25,6 +28,10 @@ pub struct Session {
     pub webauthn_challenge: Option<Vec<u8>>,
     pub ip_address: String,
     pub device_info: Option<String>,
+    pub last_seen: Option<NaiveDateTime>,
+    // ID token issued by OpenID provider, kept for RP-initiated logout
+    pub id_token: Option<String>,
+    pub openid_provider_id: Option<Id>,
 }
 
 impl Session {
@@ -46,6 +53,9 @@ impl Session {
             webauthn_challenge: None,
             ip_address,
             device_info,
+            last_seen: None,
+            id_token: None,
+            openid_provider_id: None,
         }
     }
 
@@ -58,7 +68,7 @@ impl Session {
         query_as!(
             Self,
             "SELECT id, user_id, state \"state: SessionState\", created, expires, webauthn_challenge, \
-            ip_address, device_info FROM session WHERE id = $1",
+            ip_address, device_info, last_seen, id_token, openid_provider_id FROM session WHERE id = $1",
             id
         )
         .fetch_optional(pool)
@@ -67,8 +77,8 @@ impl Session {
 
     pub async fn save(&self, pool: &PgPool) -> Result<(), SqlxError> {
         query!(
-            "INSERT INTO session (id, user_id, state, created, expires, webauthn_challenge, ip_address, device_info) \
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+            "INSERT INTO session (id, user_id, state, created, expires, webauthn_challenge, ip_address, device_info, last_seen, id_token, \
+            openid_provider_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
             self.id,
             self.user_id,
             self.state.clone() as i16,
@@ -77,6 +87,9 @@ impl Session {
             self.webauthn_challenge,
             self.ip_address,
             self.device_info,
+            self.last_seen,
+            self.id_token,
+            self.openid_provider_id,
         )
         .execute(pool)
         .await?;
@@ -97,6 +110,31 @@ impl Session {
         Ok(())
     }
 
+    /// Record session activity. To avoid a database write on every request,
+    /// `last_seen` is only updated if the previous update is older than a minute.
+    /// Returns `true` if the timestamp was written.
+    pub async fn touch_last_seen<'e, E>(&mut self, executor: E) -> Result<bool, SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
+        let now = Utc::now().naive_utc();
+        if let Some(last_seen) = self.last_seen {
+            if now - last_seen < TimeDelta::seconds(LAST_SEEN_UPDATE_INTERVAL_SECONDS) {
+                return Ok(false);
+            }
+        }
+        query!(
+            "UPDATE session SET last_seen = $1 WHERE id = $2",
+            now,
+            self.id
+        )
+        .execute(executor)
+        .await?;
+        self.last_seen = Some(now);
+
+        Ok(true)
+    }
+
     #[must_use]
     pub fn get_passkey_registration(&self) -> Option<PasskeyRegistration> {
         self.webauthn_challenge
@@ -155,6 +193,30 @@ impl Session {
         Ok(())
     }
 
+    /// Store ID token along with the provider which issued it.
+    pub async fn set_id_token<'e, E>(
+        &mut self,
+        executor: E,
+        provider_id: Id,
+        id_token: String,
+    ) -> Result<(), SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
+        query!(
+            "UPDATE session SET id_token = $1, openid_provider_id = $2 WHERE id = $3",
+            id_token,
+            provider_id,
+            self.id
+        )
+        .execute(executor)
+        .await?;
+        self.id_token = Some(id_token);
+        self.openid_provider_id = Some(provider_id);
+
+        Ok(())
+    }
+
     pub async fn delete<'e, E>(self, executor: E) -> Result<(), SqlxError>
     where
         E: PgExecutor<'e>,
@@ -188,3 +250,64 @@ impl Session {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{config::DefGuardConfig, db::User, SERVER_CONFIG};
+
+    #[sqlx::test]
+    async fn test_touch_last_seen(pool: PgPool) {
+        let config = DefGuardConfig::new_test_config();
+        let _ = SERVER_CONFIG.set(config);
+
+        let user = User::new(
+            "hpotter",
+            Some("Pass123!"),
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+        let mut session = Session::new(
+            user.id,
+            SessionState::PasswordVerified,
+            "127.0.0.1".into(),
+            None,
+        );
+        session.save(&pool).await.unwrap();
+
+        // first request writes; compare stored value, as database keeps only microseconds
+        assert!(session.touch_last_seen(&pool).await.unwrap());
+        let last_seen = Session::find_by_id(&pool, &session.id)
+            .await
+            .unwrap()
+            .unwrap()
+            .last_seen
+            .unwrap();
+        assert!(session.last_seen.unwrap() - last_seen < TimeDelta::microseconds(1));
+
+        // repeated requests within the window don't write
+        for _ in 0..3 {
+            let mut session = Session::find_by_id(&pool, &session.id)
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(!session.touch_last_seen(&pool).await.unwrap());
+        }
+        let fetched = Session::find_by_id(&pool, &session.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(fetched.last_seen, Some(last_seen));
+
+        // once the window passes, timestamp is updated again
+        session.last_seen = Some(last_seen - TimeDelta::seconds(61));
+        assert!(session.touch_last_seen(&pool).await.unwrap());
+        assert!(session.last_seen.unwrap() > last_seen - TimeDelta::seconds(61));
+    }
+}
@@ -1,5 +1,6 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, str::FromStr};
 
+use lettre::Address;
 use sqlx::{query, query_as, PgExecutor, PgPool, Type};
 use struct_patch::Patch;
 use thiserror::Error;
@@ -33,10 +34,27 @@ pub async fn update_current_settings(
     Ok(())
 }
 
+/// Whether recovery codes can be used. Enabled if settings have not been initialized.
+pub fn recovery_codes_enabled() -> bool {
+    get_settings()
+        .as_ref()
+        .map_or(true, |settings| settings.recovery_codes_enabled)
+}
+
+/// Address which receives notifications about security events, if configured.
+pub fn security_notification_email() -> Option<String> {
+    get_settings()
+        .as_ref()
+        .and_then(|settings| settings.security_notification_email.clone())
+        .filter(|email| !email.is_empty())
+}
+
 #[derive(Error, Debug)]
 pub enum SettingsValidationError {
     #[error("Cannot enable gateway disconnect notifications. SMTP is not configured")]
     CannotEnableGatewayNotifications,
+    #[error("Security notification address is not a valid email address")]
+    InvalidSecurityNotificationEmail,
 }
 
 #[derive(Clone, Deserialize, Serialize, PartialEq, Eq, Type, Debug, Default)]
@@ -99,6 +117,12 @@ pub struct Settings {
     pub gateway_disconnect_notifications_enabled: bool,
     pub gateway_disconnect_notifications_inactivity_threshold: i32,
     pub gateway_disconnect_notifications_reconnect_notification_enabled: bool,
+    // Recovery codes. When disabled, no codes are generated and existing ones are rejected,
+    // so users who lose their MFA methods can only regain access with help of an admin.
+    pub recovery_codes_enabled: bool,
+    // Security distribution list notified about break-glass logins, recovery code usage
+    // and low admin count, in addition to the acting admin.
+    pub security_notification_email: Option<String>,
 }
 
 impl Settings {
@@ -121,7 +145,8 @@ impl Settings {
             ldap_group_member_attr, ldap_member_attr, ldap_use_starttls, ldap_tls_verify_cert, \
             openid_create_account, license, gateway_disconnect_notifications_enabled, \
             gateway_disconnect_notifications_inactivity_threshold, \
-            gateway_disconnect_notifications_reconnect_notification_enabled \
+            gateway_disconnect_notifications_reconnect_notification_enabled, \
+            recovery_codes_enabled, security_notification_email \
             FROM \"settings\" WHERE id = 1",
         )
         .fetch_optional(executor)
@@ -136,6 +161,12 @@ impl Settings {
             warn!("Cannot enable gateway disconnect notifications. SMTP is not configured.");
             return Err(SettingsValidationError::CannotEnableGatewayNotifications);
         };
+        if let Some(email) = &self.security_notification_email {
+            if Address::from_str(email).is_err() {
+                warn!("Invalid security notification address: {email}");
+                return Err(SettingsValidationError::InvalidSecurityNotificationEmail);
+            }
+        }
 
         Ok(())
     }
@@ -183,7 +214,9 @@ impl Settings {
             license = $35, \
             gateway_disconnect_notifications_enabled = $36, \
             gateway_disconnect_notifications_inactivity_threshold = $37, \
-            gateway_disconnect_notifications_reconnect_notification_enabled = $38 \
+            gateway_disconnect_notifications_reconnect_notification_enabled = $38, \
+            recovery_codes_enabled = $39, \
+            security_notification_email = $40 \
             WHERE id = 1",
             self.openid_enabled,
             self.wireguard_enabled,
@@ -222,7 +255,9 @@ impl Settings {
             self.license,
             self.gateway_disconnect_notifications_enabled,
             self.gateway_disconnect_notifications_inactivity_threshold,
-            self.gateway_disconnect_notifications_reconnect_notification_enabled
+            self.gateway_disconnect_notifications_reconnect_notification_enabled,
+            self.recovery_codes_enabled,
+            self.security_notification_email
         )
         .execute(executor)
         .await?;
@@ -385,4 +420,27 @@ mod test {
         settings.smtp_password = Some(SecretStringWrapper::from_str("hunter2").unwrap());
         assert!(settings.smtp_configured());
     }
+
+    #[test]
+    fn test_security_notification_email_validation() {
+        let mut settings = Settings::default();
+        assert!(settings.validate().is_ok());
+
+        settings.security_notification_email = Some("security@hogwart.edu.uk".into());
+        assert!(settings.validate().is_ok());
+
+        for invalid in [
+            "",
+            "security",
+            "security@",
+            "@hogwart.edu.uk",
+            "a b@hogwart.edu.uk",
+        ] {
+            settings.security_notification_email = Some(invalid.into());
+            assert!(matches!(
+                settings.validate(),
+                Err(SettingsValidationError::InvalidSecurityNotificationEmail)
+            ));
+        }
+    }
 }
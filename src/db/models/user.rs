@@ -1,17 +1,22 @@
-use std::{fmt, time::SystemTime};
-
-use argon2::{
-    password_hash::{
-        errors::Error as HashError, rand_core::OsRng, PasswordHash, PasswordHasher,
-        PasswordVerifier, SaltString,
-    },
-    Argon2,
+use std::{
+    fmt,
+    time::{Duration, SystemTime},
+};
+
+use argon2::password_hash::{
+    errors::Error as HashError, rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier,
+    SaltString,
 };
 use axum::http::StatusCode;
+use chrono::{NaiveDateTime, TimeDelta, Utc};
+use hmac::{Hmac, Mac};
 use model_derive::Model;
+use reqwest::Url;
+use secrecy::ExposeSecret;
+use sha2::Sha256;
 use sqlx::{
-    query, query_as, query_scalar, Error as SqlxError, FromRow, PgConnection, PgExecutor, PgPool,
-    Type,
+    postgres::types::PgInterval, query, query_as, query_scalar, Error as SqlxError, FromRow,
+    PgConnection, PgExecutor, PgPool, Type,
 };
 use tokio::sync::broadcast::Sender;
 use totp_lite::{totp_custom, Sha1};
@@ -20,20 +25,28 @@ use utoipa::ToSchema;
 use super::{
     device::{Device, DeviceInfo, DeviceType, UserDevice},
     group::Group,
+    mfa_reset_event::MfaResetEvent,
+    settings::recovery_codes_enabled,
     webauthn::WebAuthn,
+    wireguard::WIREGUARD_MAX_HANDSHAKE,
     MFAInfo, OAuth2AuthorizedAppInfo, SecurityKey,
 };
 use crate::{
     auth::{EMAIL_CODE_DIGITS, TOTP_CODE_DIGITS, TOTP_CODE_VALIDITY_PERIOD},
+    config::{Argon2Params, PasswordPepper, PasswordPeppers},
     db::{models::group::Permission, GatewayEvent, Id, NoId, Session, WireguardNetwork},
     error::WebError,
     grpc::gateway::send_multiple_wireguard_events,
     ldap::utils::ldap_delete_user,
+    password::{password_history_depth, validate_password, PasswordError},
     random::{gen_alphanumeric, gen_totp_secret},
     server_config,
 };
 
 const RECOVERY_CODES_COUNT: usize = 8;
+/// Below this number of unused recovery codes users are advised to regenerate them.
+const RECOVERY_CODES_LOW_THRESHOLD: usize = 3;
+const MAX_USERNAME_LENGTH: usize = 63;
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, ToSchema, Type)]
 #[sqlx(type_name = "mfa_method", rename_all = "snake_case")]
@@ -71,11 +84,51 @@ pub struct UserDiagnostic {
     pub enrolled: bool,
 }
 
+/// Number of users with each MFA method configured. Users with several methods are counted
+/// once for every method they have.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, ToSchema)]
+pub struct MfaDistribution {
+    pub total: i64,
+    pub totp: i64,
+    pub email: i64,
+    pub webauthn: i64,
+    /// Users without any MFA method configured.
+    pub without_mfa: i64,
+}
+
+/// Recovery codes state which is safe to show to administrators.
+/// Never contains the codes themselves.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, ToSchema)]
+pub struct RecoveryCodesSummary {
+    /// Number of unused recovery codes.
+    pub count: usize,
+    /// Recovery codes were issued for the current MFA setup.
+    pub generated: bool,
+    /// Few unused recovery codes are left and a new set should be generated.
+    pub regenerate_recommended: bool,
+}
+
+/// User with related data needed by the profile page, fetched by
+/// [`User::find_by_username_with_relations`].
+#[derive(Debug)]
+pub struct UserWithRelations {
+    pub user: User<Id>,
+    /// Names of groups the user is a member of, sorted.
+    pub groups: Vec<String>,
+    pub device_count: i64,
+    pub mfa_info: MFAInfo,
+}
+
 #[derive(Clone, Debug, Model, PartialEq, Serialize, FromRow)]
 pub struct User<I = NoId> {
     pub id: I,
     pub username: String,
     pub(crate) password_hash: Option<String>,
+    /// When the password was last set, used to enforce the maximum password age.
+    pub password_changed_at: NaiveDateTime,
+    /// Set by admin password resets; only password change is allowed until the user
+    /// sets a new password.
+    pub must_change_password: bool,
     pub last_name: String,
     pub first_name: String,
     pub email: String,
@@ -86,10 +139,16 @@ pub struct User<I = NoId> {
     /// used OpenID to log in.
     // FIXME: must be unique
     pub openid_sub: Option<String>,
+    /// Logins and VPN access are blocked until this time. Unlike `is_active`, the suspension
+    /// lifts by itself once the time has passed.
+    pub suspended_until: Option<NaiveDateTime>,
     // secret has been verified and TOTP can be used
     pub(crate) totp_enabled: bool,
     pub(crate) email_mfa_enabled: bool,
     pub(crate) totp_secret: Option<Vec<u8>>,
+    // code length and time step (in seconds) the TOTP secret was provisioned with
+    pub(crate) totp_digits: i32,
+    pub(crate) totp_period: i32,
     pub(crate) email_mfa_secret: Option<Vec<u8>>,
     #[model(enum)]
     pub(crate) mfa_method: MFAMethod,
@@ -97,15 +156,159 @@ pub struct User<I = NoId> {
     pub(crate) recovery_codes: Vec<String>,
 }
 
-fn hash_password(password: &str) -> Result<String, HashError> {
+/// Turn e-mail local-part into a string which satisfies the username policy:
+/// plus-addressing tag is dropped, invalid characters are removed,
+/// and the result starts with a letter or digit.
+fn sanitize_username(email: &str) -> String {
+    let local_part = email.split('@').next().unwrap_or_default();
+    let local_part = local_part.split('+').next().unwrap_or_default();
+    let username: String = local_part
+        .to_ascii_lowercase()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+        .skip_while(|c| !c.is_ascii_alphanumeric())
+        .take(MAX_USERNAME_LENGTH)
+        .collect();
+    if username.is_empty() {
+        "user".into()
+    } else {
+        username
+    }
+}
+
+fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODES_COUNT)
+        .map(|_| gen_alphanumeric(16))
+        .collect()
+}
+
+const RECOVERY_CODE_HASH_PREFIX: &str = "sha256$";
+
+// Recovery codes are long random strings, so a salted SHA-256 is sufficient here.
+// Stored format: `sha256$<salt>$<hex digest>`.
+fn hash_recovery_code(code: &str) -> String {
+    let salt = gen_alphanumeric(16);
+    let digest = sha256::digest(format!("{salt}{code}"));
+    format!("{RECOVERY_CODE_HASH_PREFIX}{salt}${digest}")
+}
+
+// Comparison time depends only on input lengths, not on where the inputs differ.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// Entries without the hash prefix are legacy plaintext codes.
+fn recovery_code_matches(stored: &str, code: &str) -> bool {
+    match stored
+        .strip_prefix(RECOVERY_CODE_HASH_PREFIX)
+        .and_then(|hash| hash.split_once('$'))
+    {
+        Some((salt, digest)) => constant_time_eq(
+            sha256::digest(format!("{salt}{code}")).as_bytes(),
+            digest.as_bytes(),
+        ),
+        None => constant_time_eq(stored.as_bytes(), code.as_bytes()),
+    }
+}
+
+// Password is replaced with its HMAC keyed with the pepper, so hashes leaked without
+// the pepper can't be brute-forced.
+fn pepper_password(password: &str, pepper: &PasswordPepper) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(pepper.secret.expose_secret().as_bytes())
+        .expect("HMAC accepts keys of any size");
+    mac.update(password.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+// Pepper version is stored as Argon2 `keyid`; hashes without it are unpeppered.
+fn pepper_version(params: &argon2::Params) -> u32 {
+    params
+        .keyid()
+        .try_into()
+        .map(u32::from_be_bytes)
+        .unwrap_or_default()
+}
+
+fn hash_password(password: &str, params: &Argon2Params) -> Result<String, HashError> {
+    hash_password_with(password, params, PasswordPeppers::current().active.as_ref())
+}
+
+fn hash_password_with(
+    password: &str,
+    params: &Argon2Params,
+    pepper: Option<&PasswordPepper>,
+) -> Result<String, HashError> {
     let salt = SaltString::generate(&mut OsRng);
-    Ok(Argon2::default()
-        .hash_password(password.as_bytes(), &salt)?
-        .to_string())
+    let hash = match pepper {
+        Some(pepper) => params
+            .hasher_with_keyid(&pepper.version.to_be_bytes())?
+            .hash_password(&pepper_password(password, pepper), &salt)?,
+        None => params.hasher()?.hash_password(password.as_bytes(), &salt)?,
+    };
+    Ok(hash.to_string())
+}
+
+fn verify_password(password: &str, hash: &str, params: &Argon2Params) -> Result<(), HashError> {
+    verify_password_with(password, hash, params, &PasswordPeppers::current())
+}
+
+// Cost parameters and pepper version are read from the PHC string, so hashes created with
+// different parameters or before pepper rotation still verify.
+fn verify_password_with(
+    password: &str,
+    hash: &str,
+    params: &Argon2Params,
+    peppers: &PasswordPeppers,
+) -> Result<(), HashError> {
+    let parsed_hash = PasswordHash::new(hash)?;
+    let hasher = params.hasher()?;
+    match pepper_version(&argon2::Params::try_from(&parsed_hash)?) {
+        0 => hasher.verify_password(password.as_bytes(), &parsed_hash),
+        version => {
+            let Some(pepper) = peppers.find(version) else {
+                error!("Password hash uses unknown pepper version {version}");
+                return Err(HashError::Password);
+            };
+            hasher.verify_password(&pepper_password(password, pepper), &parsed_hash)
+        }
+    }
+}
+
+// Hash should be replaced if any of its cost parameters is below the target,
+// or if it wasn't created with the active pepper.
+fn needs_rehash(hash: &str, params: &Argon2Params, peppers: &PasswordPeppers) -> bool {
+    let Ok(parsed_hash) = PasswordHash::new(hash) else {
+        return false;
+    };
+    let Ok(hash_params) = argon2::Params::try_from(&parsed_hash) else {
+        return false;
+    };
+    let active_version = peppers
+        .active
+        .as_ref()
+        .map(|pepper| pepper.version)
+        .unwrap_or_default();
+    pepper_version(&hash_params) != active_version
+        || hash_params.m_cost() < params.memory_kib
+        || hash_params.t_cost() < params.iterations
+        || hash_params.p_cost() < params.parallelism
+}
+
+/// Validate password against the configured policy and hash it.
+fn hash_new_password(password: &str) -> Result<String, PasswordError> {
+    validate_password(password)?;
+    hash_unchecked_password(password)
+}
+
+fn hash_unchecked_password(password: &str) -> Result<String, PasswordError> {
+    hash_password(password, &Argon2Params::current())
+        .map_err(|err| PasswordError::Hash(err.to_string()))
 }
 
 impl User {
-    #[must_use]
+    /// Create a new user. Password, if given, has to satisfy the configured password policy.
+    /// It isn't checked against known data breaches, callers must run
+    /// `handlers::user::check_password_strength` first.
     pub fn new<S: Into<String>>(
         username: S,
         password: Option<&str>,
@@ -113,12 +316,14 @@ impl User {
         first_name: S,
         email: S,
         phone: Option<String>,
-    ) -> Self {
-        let password_hash = password.and_then(|password_hash| hash_password(password_hash).ok());
-        Self {
+    ) -> Result<Self, PasswordError> {
+        let password_hash = password.map(hash_new_password).transpose()?;
+        Ok(Self {
             id: NoId,
             username: username.into(),
             password_hash,
+            password_changed_at: Utc::now().naive_utc(),
+            must_change_password: false,
             last_name: last_name.into(),
             first_name: first_name.into(),
             email: email.into(),
@@ -127,30 +332,74 @@ impl User {
             totp_enabled: false,
             email_mfa_enabled: false,
             totp_secret: None,
+            totp_digits: TOTP_CODE_DIGITS as i32,
+            totp_period: TOTP_CODE_VALIDITY_PERIOD as i32,
             email_mfa_secret: None,
             mfa_method: MFAMethod::None,
             recovery_codes: Vec::new(),
             is_active: true,
             openid_sub: None,
+            suspended_until: None,
+        })
+    }
+
+    /// Derive a username from the local part of `email`.
+    /// If such username is already taken, a numeric suffix is appended.
+    pub async fn derive_username(pool: &PgPool, email: &str) -> Result<String, SqlxError> {
+        let base = sanitize_username(email);
+        let mut username = base.clone();
+        let mut suffix = 1;
+        while User::find_by_username(pool, &username).await?.is_some() {
+            suffix += 1;
+            let suffix = suffix.to_string();
+            let length = base.len().min(MAX_USERNAME_LENGTH - suffix.len());
+            username = format!("{}{suffix}", &base[..length]);
         }
+        Ok(username)
     }
 }
 
 impl<I> User<I> {
-    pub fn set_password(&mut self, password: &str) {
-        self.password_hash = hash_password(password).ok();
+    /// Set a password without checking the password policy. Only for passwords managed
+    /// by an external directory, which already verified them.
+    pub(crate) fn set_unchecked_password(&mut self, password: &str) -> Result<(), PasswordError> {
+        self.password_hash = Some(hash_unchecked_password(password)?);
+        self.password_changed_at = Utc::now().naive_utc();
+        Ok(())
     }
 
     pub(crate) fn verify_password(&self, password: &str) -> Result<(), HashError> {
         if let Some(hash) = &self.password_hash {
-            let parsed_hash = PasswordHash::new(hash)?;
-            Argon2::default().verify_password(password.as_bytes(), &parsed_hash)
+            verify_password(password, hash, &Argon2Params::current())
         } else {
             error!("Password not set for user {}", self.username);
             Err(HashError::Password)
         }
     }
 
+    /// Summarize recovery codes without revealing them. Codes are generated when MFA is
+    /// turned on, so they count as generated even if all of them have been used.
+    #[must_use]
+    pub fn recovery_codes_summary(&self) -> RecoveryCodesSummary {
+        RecoveryCodesSummary {
+            count: self.recovery_codes_count(),
+            generated: self.mfa_enabled || !self.recovery_codes.is_empty(),
+            regenerate_recommended: self.recovery_codes_low(),
+        }
+    }
+
+    /// Number of unused recovery codes.
+    #[must_use]
+    pub fn recovery_codes_count(&self) -> usize {
+        self.recovery_codes.len()
+    }
+
+    /// MFA is enabled, but fewer than [`RECOVERY_CODES_LOW_THRESHOLD`] recovery codes are left.
+    #[must_use]
+    pub fn recovery_codes_low(&self) -> bool {
+        self.mfa_enabled && self.recovery_codes_count() < RECOVERY_CODES_LOW_THRESHOLD
+    }
+
     #[must_use]
     pub(crate) fn has_password(&self) -> bool {
         self.password_hash.is_some()
@@ -168,18 +417,190 @@ impl<I> User<I> {
     pub(crate) fn is_enrolled(&self) -> bool {
         self.password_hash.is_some() || self.openid_sub.is_some()
     }
+
+    /// Return the end of user's suspension if it is still in effect.
+    #[must_use]
+    pub fn active_suspension(&self) -> Option<NaiveDateTime> {
+        self.suspended_until
+            .filter(|until| *until > Utc::now().naive_utc())
+    }
+
+    #[must_use]
+    pub fn is_suspended(&self) -> bool {
+        self.active_suspension().is_some()
+    }
+
+    fn password_expires_at(&self, max_age: Duration) -> NaiveDateTime {
+        TimeDelta::from_std(max_age)
+            .ok()
+            .and_then(|max_age| self.password_changed_at.checked_add_signed(max_age))
+            .unwrap_or(NaiveDateTime::MAX)
+    }
+
+    /// Check if the password is older than `max_age`. Users without a password never expire.
+    #[must_use]
+    pub fn password_expired(&self, max_age: Duration) -> bool {
+        self.has_password() && self.password_expires_at(max_age) <= Utc::now().naive_utc()
+    }
+
+    /// Number of whole days left until the password expires, negative after it has expired.
+    #[must_use]
+    pub fn days_until_password_expiry(&self, max_age: Duration) -> i64 {
+        (self.password_expires_at(max_age) - Utc::now().naive_utc()).num_days()
+    }
 }
 
 impl User<Id> {
+    /// Set a new password. It has to satisfy the configured password policy and must not be
+    /// one of the recently used passwords. Replaced password is pushed to password history.
+    /// Clears [`User::must_change_password`] flag, callers resetting the password on behalf
+    /// of the user should set it again.
+    ///
+    /// Password isn't checked against known data breaches, callers must run
+    /// `handlers::user::check_password_strength` first.
+    pub async fn set_password(
+        &mut self,
+        transaction: &mut PgConnection,
+        password: &str,
+    ) -> Result<(), PasswordError> {
+        self.set_password_with_history(transaction, password, password_history_depth())
+            .await
+    }
+
+    async fn set_password_with_history(
+        &mut self,
+        transaction: &mut PgConnection,
+        password: &str,
+        depth: usize,
+    ) -> Result<(), PasswordError> {
+        let password_hash = hash_new_password(password)?;
+        if depth > 0 {
+            if self
+                .password_recently_used(&mut *transaction, password, depth)
+                .await?
+            {
+                return Err(PasswordError::Reused(depth));
+            }
+            if let Some(old_hash) = &self.password_hash {
+                self.push_password_history(&mut *transaction, old_hash, depth)
+                    .await
+                    .map_err(|err| PasswordError::History(err.to_string()))?;
+            }
+        }
+        self.password_hash = Some(password_hash);
+        self.password_changed_at = Utc::now().naive_utc();
+        self.must_change_password = false;
+        Ok(())
+    }
+
+    /// Check password against the current one and the last `depth` replaced passwords.
+    async fn password_recently_used(
+        &self,
+        transaction: &mut PgConnection,
+        password: &str,
+        depth: usize,
+    ) -> Result<bool, PasswordError> {
+        if self.password_hash.is_some() && self.verify_password(password).is_ok() {
+            return Ok(true);
+        }
+        let hashes = query_scalar!(
+            "SELECT password_hash FROM password_history WHERE user_id = $1 \
+            ORDER BY created DESC, id DESC LIMIT $2",
+            self.id,
+            depth as i64
+        )
+        .fetch_all(&mut *transaction)
+        .await
+        .map_err(|err| PasswordError::History(err.to_string()))?;
+        let params = Argon2Params::current();
+        Ok(hashes
+            .iter()
+            .any(|hash| verify_password(password, hash, &params).is_ok()))
+    }
+
+    /// Store replaced password hash, keeping only the last `depth` entries.
+    async fn push_password_history(
+        &self,
+        transaction: &mut PgConnection,
+        password_hash: &str,
+        depth: usize,
+    ) -> Result<(), SqlxError> {
+        query!(
+            "INSERT INTO password_history (user_id, password_hash) VALUES ($1, $2)",
+            self.id,
+            password_hash
+        )
+        .execute(&mut *transaction)
+        .await?;
+        query!(
+            "DELETE FROM password_history WHERE user_id = $1 AND id NOT IN \
+            (SELECT id FROM password_history WHERE user_id = $1 \
+            ORDER BY created DESC, id DESC LIMIT $2)",
+            self.id,
+            depth as i64
+        )
+        .execute(&mut *transaction)
+        .await?;
+        Ok(())
+    }
+
+    /// Verify password and, if stored hash was created with weaker parameters than
+    /// currently configured, replace it with a new hash. Failure to store the new hash
+    /// is logged but doesn't affect the verification result.
+    pub async fn verify_and_upgrade_password(
+        &mut self,
+        pool: &PgPool,
+        password: &str,
+    ) -> Result<(), HashError> {
+        self.verify_password(password)?;
+
+        let params = Argon2Params::current();
+        let Some(hash) = &self.password_hash else {
+            return Ok(());
+        };
+        if !needs_rehash(hash, &params, &PasswordPeppers::current()) {
+            return Ok(());
+        }
+        debug!("Upgrading password hash of user {}", self.username);
+        let new_hash = hash_password(password, &params)?;
+        match query!(
+            "UPDATE \"user\" SET password_hash = $2 WHERE id = $1",
+            self.id,
+            new_hash
+        )
+        .execute(pool)
+        .await
+        {
+            Ok(_) => {
+                self.password_hash = Some(new_hash);
+                info!("Upgraded password hash of user {}", self.username);
+            }
+            Err(err) => error!(
+                "Failed to upgrade password hash of user {}: {err}",
+                self.username
+            ),
+        }
+
+        Ok(())
+    }
+
     /// Generate new TOTP secret, save it, then return it as RFC 4648 base32-encoded string.
+    /// Code length and period are taken from server configuration and stored along with
+    /// the secret, so changing the configuration doesn't affect already provisioned secrets.
     pub async fn new_totp_secret<'e, E>(&mut self, executor: E) -> Result<String, SqlxError>
     where
         E: PgExecutor<'e>,
     {
         let secret = gen_totp_secret();
+        let config = server_config();
+        let digits = config.totp_digits as i32;
+        let period = config.totp_period as i32;
         query!(
-            "UPDATE \"user\" SET totp_secret = $1 WHERE id = $2",
+            "UPDATE \"user\" SET totp_secret = $1, totp_digits = $2, totp_period = $3 \
+            WHERE id = $4",
             secret,
+            digits,
+            period,
             self.id
         )
         .execute(executor)
@@ -187,6 +608,8 @@ impl User<Id> {
 
         let secret_base32 = base32::encode(base32::Alphabet::Rfc4648 { padding: false }, &secret);
         self.totp_secret = Some(secret);
+        self.totp_digits = digits;
+        self.totp_period = period;
         Ok(secret_base32)
     }
 
@@ -209,14 +632,34 @@ impl User<Id> {
         Ok(())
     }
 
-    pub async fn set_mfa_method<'e, E>(
+    /// Check that the user has configured the factor backing given MFA method.
+    pub async fn validate_mfa_method(
+        &self,
+        pool: &PgPool,
+        mfa_method: &MFAMethod,
+    ) -> Result<(), WebError> {
+        if *mfa_method == MFAMethod::None
+            || self.available_mfa_methods(pool).await?.contains(mfa_method)
+        {
+            Ok(())
+        } else {
+            warn!(
+                "User {} has no {mfa_method} factor configured",
+                self.username
+            );
+            Err(WebError::BadRequest(format!(
+                "MFA method {mfa_method} is not configured"
+            )))
+        }
+    }
+
+    /// Set default MFA method. Fails if the user hasn't configured the corresponding factor.
+    pub async fn set_mfa_method(
         &mut self,
-        executor: E,
+        pool: &PgPool,
         mfa_method: MFAMethod,
-    ) -> Result<(), SqlxError>
-    where
-        E: PgExecutor<'e>,
-    {
+    ) -> Result<(), WebError> {
+        self.validate_mfa_method(pool, &mfa_method).await?;
         info!(
             "Setting MFA method for user {} to {mfa_method:?}",
             self.username
@@ -226,7 +669,7 @@ impl User<Id> {
             self.id,
             &mfa_method as &MFAMethod
         )
-        .execute(executor)
+        .execute(pool)
         .await?;
         self.mfa_method = mfa_method;
 
@@ -256,6 +699,15 @@ impl User<Id> {
         .await
     }
 
+    /// Every MFA method the user has configured, so the login flow can let them choose one.
+    pub async fn available_mfa_methods(&self, pool: &PgPool) -> Result<Vec<MFAMethod>, SqlxError> {
+        let methods = MFAInfo::for_user(pool, self)
+            .await?
+            .and_then(|info| info.list_available_methods())
+            .unwrap_or_default();
+        Ok(methods)
+    }
+
     /// Verify the state of mfa flags are correct.
     /// Recovers from invalid mfa_method
     /// Use this function after removing any of the authentication factors.
@@ -327,6 +779,56 @@ impl User<Id> {
         Ok(())
     }
 
+    /// Suspend user until given time, log out all his sessions and update gateways state.
+    pub async fn suspend(
+        &mut self,
+        transaction: &mut PgConnection,
+        until: NaiveDateTime,
+        wg_tx: &Sender<GatewayEvent>,
+    ) -> Result<(), WebError> {
+        self.suspended_until = Some(until);
+        self.save(&mut *transaction).await?;
+        self.logout_all_sessions(&mut *transaction).await?;
+        self.sync_allowed_devices(transaction, wg_tx).await?;
+        Ok(())
+    }
+
+    /// Lift user suspension and restore his VPN access.
+    pub async fn lift_suspension(
+        &mut self,
+        transaction: &mut PgConnection,
+        wg_tx: &Sender<GatewayEvent>,
+    ) -> Result<(), WebError> {
+        self.suspended_until = None;
+        self.save(&mut *transaction).await?;
+        self.sync_allowed_devices(transaction, wg_tx).await?;
+        Ok(())
+    }
+
+    /// Lift suspensions which have already expired, so that gateways get the devices back.
+    pub async fn lift_expired_suspensions(
+        pool: &PgPool,
+        wg_tx: &Sender<GatewayEvent>,
+    ) -> Result<(), WebError> {
+        let mut transaction = pool.begin().await?;
+        let user_ids =
+            query_scalar!("SELECT id FROM \"user\" WHERE suspended_until <= NOW() FOR UPDATE")
+                .fetch_all(&mut *transaction)
+                .await?;
+        for id in user_ids {
+            if let Some(mut user) = Self::find_by_id(&mut *transaction, id).await? {
+                debug!(
+                    "Suspension of user {} has expired, lifting it",
+                    user.username
+                );
+                user.lift_suspension(&mut transaction, wg_tx).await?;
+                info!("Suspension of user {} lifted", user.username);
+            }
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+
     /// Update gateway state based on this user device access rights
     pub async fn sync_allowed_devices(
         &self,
@@ -383,43 +885,124 @@ impl User<Id> {
 
     /// Get recovery codes. If recovery codes exist, this function returns `None`.
     /// That way recovery codes are returned only once - when MFA is turned on.
+    /// Only hashes of the codes are stored.
+    /// No codes are generated if recovery codes are disabled in settings.
     pub async fn get_recovery_codes<'e, E>(
         &mut self,
         executor: E,
     ) -> Result<Option<Vec<String>>, SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
+        self.get_recovery_codes_with(executor, recovery_codes_enabled())
+            .await
+    }
+
+    async fn get_recovery_codes_with<'e, E>(
+        &mut self,
+        executor: E,
+        codes_enabled: bool,
+    ) -> Result<Option<Vec<String>>, SqlxError>
     where
         E: PgExecutor<'e>,
     {
         if !self.recovery_codes.is_empty() {
             return Ok(None);
         }
-
-        for _ in 0..RECOVERY_CODES_COUNT {
-            let code = gen_alphanumeric(16);
-            self.recovery_codes.push(code);
+        if !codes_enabled {
+            debug!(
+                "Recovery codes are disabled, not generating them for user {}",
+                self.username
+            );
+            return Ok(None);
         }
+
+        let codes = generate_recovery_codes();
+        self.recovery_codes = codes.iter().map(|code| hash_recovery_code(code)).collect();
         query!(
-            "UPDATE \"user\" SET recovery_codes = $2 WHERE id = $1",
+            "UPDATE \"user\" SET recovery_codes = $2, recovery_codes_generated_at = now() \
+            WHERE id = $1",
             self.id,
             &self.recovery_codes
         )
         .execute(executor)
         .await?;
 
-        Ok(Some(self.recovery_codes.clone()))
+        Ok(Some(codes))
     }
 
-    /// Disable MFA; discard recovery codes, TOTP secret, and security keys.
-    pub async fn disable_mfa(&mut self, pool: &PgPool) -> Result<(), SqlxError> {
+    /// Replace recovery codes with a new set. Unless `bypass_cooldown` is set, this fails
+    /// if codes were generated less than `recovery_codes_regeneration_cooldown` ago.
+    pub async fn regenerate_recovery_codes(
+        &mut self,
+        pool: &PgPool,
+        bypass_cooldown: bool,
+    ) -> Result<Vec<String>, WebError> {
+        self.regenerate_recovery_codes_with(pool, bypass_cooldown, recovery_codes_enabled())
+            .await
+    }
+
+    async fn regenerate_recovery_codes_with(
+        &mut self,
+        pool: &PgPool,
+        bypass_cooldown: bool,
+        codes_enabled: bool,
+    ) -> Result<Vec<String>, WebError> {
+        if !codes_enabled {
+            return Err(WebError::BadRequest("Recovery codes are disabled".into()));
+        }
+        if !bypass_cooldown {
+            let generated_at = query_scalar!(
+                "SELECT recovery_codes_generated_at FROM \"user\" WHERE id = $1",
+                self.id
+            )
+            .fetch_one(pool)
+            .await?;
+            if let Some(generated_at) = generated_at {
+                let cooldown = server_config().recovery_codes_regeneration_cooldown;
+                let remaining = generated_at + TimeDelta::seconds(cooldown.as_secs() as i64)
+                    - Utc::now().naive_utc();
+                if remaining > TimeDelta::zero() {
+                    return Err(WebError::RecoveryCodesCooldown(
+                        remaining.num_seconds().max(1),
+                    ));
+                }
+            }
+        }
+
+        let codes = generate_recovery_codes();
+        self.recovery_codes = codes.iter().map(|code| hash_recovery_code(code)).collect();
+        query!(
+            "UPDATE \"user\" SET recovery_codes = $2, recovery_codes_generated_at = now() \
+            WHERE id = $1",
+            self.id,
+            &self.recovery_codes
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(codes)
+    }
+
+    /// Disable MFA of given user in the database, see [`User::disable_mfa`].
+    async fn clear_mfa(conn: &mut PgConnection, user_id: Id) -> Result<(), SqlxError> {
         query!(
             "UPDATE \"user\" SET mfa_enabled = FALSE, mfa_method = 'none', totp_enabled = FALSE, email_mfa_enabled = FALSE, \
             totp_secret = NULL, email_mfa_secret = NULL, recovery_codes = '{}' WHERE id = $1",
-            self.id
+            user_id
         )
-        .execute(pool)
+        .execute(&mut *conn)
         .await?;
-        WebAuthn::delete_all_for_user(pool, self.id).await?;
+        WebAuthn::delete_all_for_user(&mut *conn, user_id).await
+    }
 
+    /// Disable MFA; discard recovery codes, TOTP secret, and security keys.
+    pub async fn disable_mfa(&mut self, pool: &PgPool) -> Result<(), SqlxError> {
+        let mut transaction = pool.begin().await?;
+        Self::clear_mfa(&mut transaction, self.id).await?;
+        transaction.commit().await?;
+
+        self.mfa_enabled = false;
         self.totp_secret = None;
         self.email_mfa_secret = None;
         self.totp_enabled = false;
@@ -430,11 +1013,47 @@ impl User<Id> {
         Ok(())
     }
 
-    /// Enable TOTP
-    pub async fn enable_totp<'e, E>(&mut self, executor: E) -> Result<(), SqlxError>
+    /// Disable MFA of many users at once, e.g. when migrating to another MFA provider.
+    /// All users are reset in a single transaction and each reset is recorded
+    /// in [`MfaResetEvent`] audit log. Ids of non-existent users are skipped.
+    /// Returns number of users whose MFA was reset.
+    pub async fn bulk_disable_mfa(
+        pool: &PgPool,
+        user_ids: &[Id],
+        admin_id: Id,
+    ) -> Result<u64, SqlxError> {
+        let mut transaction = pool.begin().await?;
+        let user_ids = query_scalar!(
+            "SELECT id FROM \"user\" WHERE id = ANY($1) ORDER BY id",
+            user_ids
+        )
+        .fetch_all(&mut *transaction)
+        .await?;
+        for user_id in &user_ids {
+            debug!("Disabling MFA of user {user_id} by admin {admin_id}");
+            Self::clear_mfa(&mut transaction, *user_id).await?;
+            MfaResetEvent::new(*user_id, Some(admin_id))
+                .save(&mut *transaction)
+                .await?;
+        }
+        transaction.commit().await?;
+        info!("Admin {admin_id} disabled MFA of {} users", user_ids.len());
+
+        Ok(user_ids.len() as u64)
+    }
+
+    // Mark TOTP as enabled. The secret has to be generated first with [`User::new_totp_secret`].
+    async fn set_totp_enabled<'e, E>(&mut self, executor: E) -> Result<(), WebError>
     where
         E: PgExecutor<'e>,
     {
+        if self.totp_secret.is_none() {
+            warn!(
+                "Refusing to enable TOTP for user {} without a secret",
+                self.username
+            );
+            return Err(WebError::BadRequest("TOTP secret not generated".into()));
+        }
         if !self.totp_enabled {
             query!(
                 "UPDATE \"user\" SET totp_enabled = TRUE WHERE id = $1",
@@ -448,36 +1067,100 @@ impl User<Id> {
         Ok(())
     }
 
-    /// Disable TOTP; discard the secret.
-    pub async fn disable_totp(&mut self, pool: &PgPool) -> Result<(), SqlxError> {
-        if self.totp_enabled {
-            // FIXME: check if this flag is set correctly when TOTP is the only method
-            self.mfa_enabled = self.check_mfa_enabled(pool).await?;
-            self.totp_enabled = false;
-            self.totp_secret = None;
-
-            query!(
-                "UPDATE \"user\" SET mfa_enabled = $2, totp_enabled = $3 AND totp_secret = $4 \
-                WHERE id = $1",
-                self.id,
-                self.mfa_enabled,
-                self.totp_enabled,
-                self.totp_secret,
-            )
-            .execute(pool)
-            .await?;
-        }
-
-        Ok(())
-    }
-
-    /// Enable email MFA
-    pub async fn enable_email_mfa<'e, E>(&mut self, executor: E) -> Result<(), SqlxError>
+    /// Enable TOTP without checking that the user can generate valid codes.
+    #[deprecated(note = "use `User::confirm_totp_enroll` to verify a code before enabling TOTP")]
+    pub async fn enable_totp<'e, E>(&mut self, executor: E) -> Result<(), WebError>
     where
         E: PgExecutor<'e>,
     {
-        if !self.email_mfa_enabled {
-            query!(
+        self.set_totp_enabled(executor).await
+    }
+
+    /// Enable TOTP only if `code` is valid for the pending secret, which confirms that
+    /// the user has set up their authenticator app. Returns `false` for an invalid code.
+    /// Codes are checked within `totp_setup_drift_steps`, which may be wider than the login window.
+    pub async fn confirm_totp_enroll<'e, E>(
+        &mut self,
+        executor: E,
+        code: &str,
+    ) -> Result<bool, WebError>
+    where
+        E: PgExecutor<'e>,
+    {
+        if self.totp_secret.is_none() {
+            return Err(WebError::BadRequest("TOTP secret not generated".into()));
+        }
+        if !self.verify_totp_code_in_window(code, server_config().totp_setup_drift_steps) {
+            debug!(
+                "Invalid TOTP code during enrollment of user {}",
+                self.username
+            );
+            return Ok(false);
+        }
+        self.set_totp_enabled(executor).await?;
+
+        Ok(true)
+    }
+
+    /// Finish TOTP setup in a single transaction: verify `code`, enable TOTP and MFA, and
+    /// generate recovery codes (see [`User::get_recovery_codes`] for when `None` is returned).
+    /// TOTP becomes the default MFA method if none was set. On invalid code nothing changes.
+    pub async fn complete_totp_setup(
+        &mut self,
+        pool: &PgPool,
+        code: &str,
+    ) -> Result<Option<Vec<String>>, WebError> {
+        debug!("Completing TOTP setup for user {}", self.username);
+        // work on a copy, so that a failed transaction doesn't leave this user half-configured
+        let mut user = self.clone();
+        let mut transaction = pool.begin().await?;
+        if !user.confirm_totp_enroll(&mut *transaction, code).await? {
+            transaction.rollback().await?;
+            return Err(WebError::ObjectNotFound("Invalid TOTP code".into()));
+        }
+        if user.mfa_method == MFAMethod::None {
+            user.mfa_method = MFAMethod::OneTimePassword;
+        }
+        query!(
+            "UPDATE \"user\" SET mfa_enabled = TRUE, mfa_method = $2 WHERE id = $1",
+            user.id,
+            &user.mfa_method as &MFAMethod
+        )
+        .execute(&mut *transaction)
+        .await?;
+        user.mfa_enabled = true;
+        let codes = user.get_recovery_codes(&mut *transaction).await?;
+        transaction.commit().await?;
+        *self = user;
+        info!("Completed TOTP setup for user {}", self.username);
+
+        Ok(codes)
+    }
+
+    /// Disable TOTP; discard the secret. Other factors are left untouched, call
+    /// [`User::verify_mfa_state`] afterwards to update MFA flags.
+    pub async fn disable_totp(&mut self, pool: &PgPool) -> Result<(), SqlxError> {
+        if self.totp_enabled {
+            query!(
+                "UPDATE \"user\" SET totp_enabled = FALSE, totp_secret = NULL WHERE id = $1",
+                self.id,
+            )
+            .execute(pool)
+            .await?;
+            self.totp_enabled = false;
+            self.totp_secret = None;
+        }
+
+        Ok(())
+    }
+
+    /// Enable email MFA
+    pub async fn enable_email_mfa<'e, E>(&mut self, executor: E) -> Result<(), SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
+        if !self.email_mfa_enabled {
+            query!(
                 "UPDATE \"user\" SET email_mfa_enabled = TRUE WHERE id = $1",
                 self.id
             )
@@ -540,6 +1223,27 @@ impl User<Id> {
         Ok(res)
     }
 
+    /// Count users having each MFA method configured.
+    pub async fn mfa_distribution<'e, E>(executor: E) -> Result<MfaDistribution, SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
+        query_as!(
+            MfaDistribution,
+            "SELECT COUNT(*) \"total!\", \
+            COUNT(*) FILTER (WHERE totp_enabled) \"totp!\", \
+            COUNT(*) FILTER (WHERE email_mfa_enabled) \"email!\", \
+            COUNT(*) FILTER (WHERE has_webauthn) \"webauthn!\", \
+            COUNT(*) FILTER (WHERE NOT (totp_enabled OR email_mfa_enabled OR has_webauthn)) \
+            \"without_mfa!\" \
+            FROM (SELECT totp_enabled, email_mfa_enabled, \
+            EXISTS (SELECT 1 FROM webauthn WHERE user_id = \"user\".id) has_webauthn \
+            FROM \"user\") u"
+        )
+        .fetch_one(executor)
+        .await
+    }
+
     /// Return all members of group
     pub async fn find_by_group_name(
         pool: &PgPool,
@@ -548,9 +1252,10 @@ impl User<Id> {
         let users = query_as!(
             Self,
             "SELECT \"user\".id, username, password_hash, last_name, first_name, email, \
-            phone, mfa_enabled, totp_enabled, totp_secret, \
+            phone, mfa_enabled, totp_enabled, totp_secret, totp_digits, totp_period, \
             email_mfa_enabled, email_mfa_secret, \
-            mfa_method \"mfa_method: _\", recovery_codes, is_active, openid_sub \
+            mfa_method \"mfa_method: _\", recovery_codes, is_active, openid_sub, \
+            suspended_until, password_changed_at, must_change_password \
             FROM \"user\" \
             INNER JOIN \"group_user\" ON \"user\".id = \"group_user\".user_id \
             INNER JOIN \"group\" ON \"group_user\".group_id = \"group\".id \
@@ -563,22 +1268,93 @@ impl User<Id> {
         Ok(users)
     }
 
-    /// Check if TOTP `code` is valid.
+    /// Return users which are members of every one of the given groups.
+    /// Empty `group_names` yields no users.
+    pub async fn find_in_all_groups<'e, E>(
+        executor: E,
+        group_names: &[&str],
+    ) -> Result<Vec<User<Id>>, SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
+        let mut group_names = group_names.to_vec();
+        group_names.sort_unstable();
+        group_names.dedup();
+        if group_names.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        query_as(
+            "SELECT u.id, u.username, u.password_hash, u.last_name, u.first_name, u.email, \
+            u.phone, u.mfa_enabled, u.totp_enabled, u.email_mfa_enabled, u.totp_secret, u.totp_digits, u.totp_period, \
+            u.email_mfa_secret, u.mfa_method, u.recovery_codes, u.is_active, u.openid_sub, \
+            u.suspended_until, u.password_changed_at, u.must_change_password \
+            FROM \"user\" u \
+            JOIN group_user gu ON u.id = gu.user_id \
+            JOIN \"group\" g ON gu.group_id = g.id \
+            WHERE g.name = ANY($1) \
+            GROUP BY u.id \
+            HAVING COUNT(DISTINCT g.id) = $2 \
+            ORDER BY u.id",
+        )
+        .bind(&group_names)
+        .bind(group_names.len() as i64)
+        .fetch_all(executor)
+        .await
+    }
+
+    /// Key URI (`otpauth://totp/...`) for provisioning authenticator apps with current TOTP
+    /// secret, e.g. by encoding it in a QR code. Returns `None` if TOTP secret is not set.
+    #[must_use]
+    pub fn totp_provisioning_uri(&self, issuer: &str) -> Option<String> {
+        let secret = self.totp_secret.as_ref()?;
+        let secret = base32::encode(base32::Alphabet::Rfc4648 { padding: false }, secret);
+        let account = if self.email.is_empty() {
+            &self.username
+        } else {
+            &self.email
+        };
+
+        let mut uri = Url::parse("otpauth://totp").ok()?;
+        uri.path_segments_mut()
+            .ok()?
+            .push(&format!("{issuer}:{account}"));
+        uri.query_pairs_mut()
+            .append_pair("secret", &secret)
+            .append_pair("issuer", issuer)
+            .append_pair("digits", &self.totp_digits.to_string())
+            .append_pair("period", &self.totp_period.to_string());
+        // Some authenticators don't decode `+` as space in the issuer.
+        let query = uri.query().map(|query| query.replace('+', "%20"));
+        uri.set_query(query.as_deref());
+
+        Some(uri.to_string())
+    }
+
+    /// Check if TOTP `code` is valid. To tolerate clock skew, codes from
+    /// `totp_drift_steps` time steps before and after the current one are accepted too.
     #[must_use]
     pub fn verify_totp_code(&self, code: &str) -> bool {
-        if let Some(totp_secret) = &self.totp_secret {
-            if let Ok(timestamp) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
-                let expected_code = totp_custom::<Sha1>(
-                    TOTP_CODE_VALIDITY_PERIOD,
-                    TOTP_CODE_DIGITS,
-                    totp_secret,
-                    timestamp.as_secs(),
-                );
-                return code == expected_code;
-            }
-        }
+        self.verify_totp_code_in_window(code, server_config().totp_drift_steps)
+    }
 
-        false
+    fn verify_totp_code_in_window(&self, code: &str, drift_steps: u64) -> bool {
+        let Some(totp_secret) = &self.totp_secret else {
+            return false;
+        };
+        let Ok(timestamp) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) else {
+            return false;
+        };
+        let period = self.totp_period as u64;
+        let drift_steps = drift_steps as i64;
+        (-drift_steps..=drift_steps).any(|step| {
+            timestamp
+                .as_secs()
+                .checked_add_signed(step * period as i64)
+                .is_some_and(|time| {
+                    code == totp_custom::<Sha1>(period, self.totp_digits as u32, totp_secret, time)
+                })
+        })
     }
 
     /// Generate MFA code for email verification.
@@ -665,16 +1441,63 @@ impl User<Id> {
         false
     }
 
+    /// Check if recovery code is valid without consuming it. All stored codes are compared,
+    /// so timing doesn't reveal which one matched. Meant for admin diagnostics only;
+    /// use [`User::verify_recovery_code`] when authenticating.
+    #[must_use]
+    pub(crate) fn check_recovery_code(&self, code: &str) -> bool {
+        self.check_recovery_code_with(code, recovery_codes_enabled())
+    }
+
+    fn check_recovery_code_with(&self, code: &str, codes_enabled: bool) -> bool {
+        codes_enabled
+            && self.recovery_codes.iter().fold(false, |found, stored| {
+                recovery_code_matches(stored, code) | found
+            })
+    }
+
     /// Verify recovery code. If it is valid, consume it, so it can't be used again.
+    /// Remaining legacy plaintext codes are replaced with their hashes.
+    /// All codes are rejected if recovery codes are disabled in settings.
     pub(crate) async fn verify_recovery_code(
         &mut self,
         pool: &PgPool,
         code: &str,
     ) -> Result<bool, SqlxError> {
-        if let Some(index) = self.recovery_codes.iter().position(|c| c == code) {
+        self.verify_recovery_code_with(pool, code, recovery_codes_enabled())
+            .await
+    }
+
+    async fn verify_recovery_code_with(
+        &mut self,
+        pool: &PgPool,
+        code: &str,
+        codes_enabled: bool,
+    ) -> Result<bool, SqlxError> {
+        if !codes_enabled {
+            debug!(
+                "Recovery codes are disabled, rejecting code of user {}",
+                self.username
+            );
+            return Ok(false);
+        }
+        let index = self
+            .recovery_codes
+            .iter()
+            .position(|stored| recovery_code_matches(stored, code));
+        if let Some(index) = index {
             // Note: swap_remove() should be faster than remove().
             self.recovery_codes.swap_remove(index);
+        }
+        let mut migrated = false;
+        for stored in &mut self.recovery_codes {
+            if !stored.starts_with(RECOVERY_CODE_HASH_PREFIX) {
+                *stored = hash_recovery_code(stored);
+                migrated = true;
+            }
+        }
 
+        if index.is_some() || migrated {
             query!(
                 "UPDATE \"user\" SET recovery_codes = $2 WHERE id = $1",
                 self.id,
@@ -682,11 +1505,9 @@ impl User<Id> {
             )
             .execute(pool)
             .await?;
-
-            Ok(true)
-        } else {
-            Ok(false)
         }
+
+        Ok(index.is_some())
     }
 
     pub async fn find_by_username<'e, E>(
@@ -700,7 +1521,8 @@ impl User<Id> {
             Self,
             "SELECT id, username, password_hash, last_name, first_name, email, \
             phone, mfa_enabled, totp_enabled, email_mfa_enabled, \
-            totp_secret, email_mfa_secret, mfa_method \"mfa_method: _\", recovery_codes, is_active, openid_sub \
+            totp_secret, totp_digits, totp_period, email_mfa_secret, mfa_method \"mfa_method: _\", recovery_codes, is_active, openid_sub, \
+            suspended_until, password_changed_at, must_change_password \
             FROM \"user\" WHERE username = $1",
             username
         )
@@ -708,6 +1530,39 @@ impl User<Id> {
         .await
     }
 
+    /// Fetch user along with group names, number of devices and MFA summary.
+    /// Relations are fetched with a single query, instead of one query for each of them.
+    pub async fn find_by_username_with_relations(
+        pool: &PgPool,
+        username: &str,
+    ) -> Result<Option<UserWithRelations>, SqlxError> {
+        let Some(user) = Self::find_by_username(pool, username).await? else {
+            return Ok(None);
+        };
+        let relations = query!(
+            "SELECT \
+            COALESCE((SELECT ARRAY_AGG(g.name ORDER BY g.name) FROM \"group\" g \
+                JOIN group_user gu ON gu.group_id = g.id WHERE gu.user_id = $1), '{}') \"groups!\", \
+            (SELECT count(*) FROM device WHERE user_id = $1) \"device_count!\", \
+            (SELECT count(*) > 0 FROM webauthn WHERE user_id = $1) \"webauthn_available!\"",
+            user.id
+        )
+        .fetch_one(pool)
+        .await?;
+        let mfa_info = MFAInfo {
+            mfa_method: user.mfa_method.clone(),
+            totp_available: user.totp_enabled,
+            webauthn_available: relations.webauthn_available,
+            email_available: user.email_mfa_enabled,
+        };
+        Ok(Some(UserWithRelations {
+            user,
+            groups: relations.groups,
+            device_count: relations.device_count,
+            mfa_info,
+        }))
+    }
+
     pub(crate) async fn find_by_email<'e, E>(
         executor: E,
         email: &str,
@@ -718,8 +1573,9 @@ impl User<Id> {
         query_as!(
             Self,
             "SELECT id, username, password_hash, last_name, first_name, email, phone, \
-            mfa_enabled, totp_enabled, email_mfa_enabled, totp_secret, email_mfa_secret, \
-            mfa_method \"mfa_method: _\", recovery_codes, is_active, openid_sub \
+            mfa_enabled, totp_enabled, email_mfa_enabled, totp_secret, totp_digits, totp_period, email_mfa_secret, \
+            mfa_method \"mfa_method: _\", recovery_codes, is_active, openid_sub, \
+            suspended_until, password_changed_at, must_change_password \
             FROM \"user\" WHERE email ILIKE $1",
             email
         )
@@ -736,8 +1592,9 @@ impl User<Id> {
     {
         query_as(
             "SELECT id, username, password_hash, last_name, first_name, email, phone, \
-            mfa_enabled, totp_enabled, email_mfa_enabled, totp_secret, email_mfa_secret, \
-            mfa_method, recovery_codes, is_active, openid_sub \
+            mfa_enabled, totp_enabled, email_mfa_enabled, totp_secret, totp_digits, totp_period, email_mfa_secret, \
+            mfa_method, recovery_codes, is_active, openid_sub, \
+            suspended_until, password_changed_at, must_change_password \
             FROM \"user\" WHERE email = ANY($1)",
         )
         .bind(emails)
@@ -756,8 +1613,9 @@ impl User<Id> {
         query_as!(
             Self,
             "SELECT id, username, password_hash, last_name, first_name, email, phone, \
-            mfa_enabled, totp_enabled, email_mfa_enabled, totp_secret, email_mfa_secret, \
-            mfa_method \"mfa_method: _\", recovery_codes, is_active, openid_sub \
+            mfa_enabled, totp_enabled, email_mfa_enabled, totp_secret, totp_digits, totp_period, email_mfa_secret, \
+            mfa_method \"mfa_method: _\", recovery_codes, is_active, openid_sub, \
+            suspended_until, password_changed_at, must_change_password \
             FROM \"user\" WHERE openid_sub = $1 LIMIT 1",
             sub
         )
@@ -765,6 +1623,39 @@ impl User<Id> {
         .await
     }
 
+    /// Fetch a page of users ordered by id.
+    pub async fn fetch_page<'e, E>(
+        executor: E,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Self>, SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
+        query_as!(
+            Self,
+            "SELECT id, username, password_hash, last_name, first_name, email, phone, \
+            mfa_enabled, totp_enabled, email_mfa_enabled, totp_secret, totp_digits, totp_period, email_mfa_secret, \
+            mfa_method \"mfa_method: _\", recovery_codes, is_active, openid_sub, \
+            suspended_until, password_changed_at, must_change_password \
+            FROM \"user\" ORDER BY id LIMIT $1 OFFSET $2",
+            limit,
+            offset
+        )
+        .fetch_all(executor)
+        .await
+    }
+
+    /// Number of all users.
+    pub async fn count<'e, E>(executor: E) -> Result<i64, SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
+        query_scalar!("SELECT COUNT(*) \"count!\" FROM \"user\"")
+            .fetch_one(executor)
+            .await
+    }
+
     pub(crate) async fn member_of_names<'e, E>(&self, executor: E) -> Result<Vec<String>, SqlxError>
     where
         E: PgExecutor<'e>,
@@ -778,13 +1669,28 @@ impl User<Id> {
         .await
     }
 
+    /// Principals to put in this user's SSH certificate, one for each group membership.
+    pub async fn ssh_principals<'e, E>(&self, executor: E) -> Result<Vec<String>, SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
+        query_scalar!(
+            "SELECT COALESCE(\"group\".ssh_principal, \"group\".name) \"principal!\" \
+            FROM \"group\" JOIN group_user ON \"group\".id = group_user.group_id \
+            WHERE group_user.user_id = $1 ORDER BY \"group\".name",
+            self.id
+        )
+        .fetch_all(executor)
+        .await
+    }
+
     pub(crate) async fn member_of<'e, E>(&self, executor: E) -> Result<Vec<Group<Id>>, SqlxError>
     where
         E: PgExecutor<'e>,
     {
         query_as!(
             Group,
-            "SELECT id, name, is_admin FROM \"group\" JOIN group_user ON \"group\".id = group_user.group_id \
+            "SELECT id, name, is_admin, ssh_principal FROM \"group\" JOIN group_user ON \"group\".id = group_user.group_id \
             WHERE group_user.user_id = $1",
             self.id
         )
@@ -816,7 +1722,7 @@ impl User<Id> {
         query_as!(
             Device,
             "SELECT device.id, name, wireguard_pubkey, user_id, created, description, \
-            device_type \"device_type: DeviceType\", configured \
+            device_type \"device_type: DeviceType\", configured, is_primary \
             FROM device WHERE user_id = $1 and device_type = 'user'::device_type \
             ORDER BY id",
             self.id
@@ -928,7 +1834,7 @@ impl User<Id> {
             }
 
             // create admin user
-            let password_hash = hash_password(default_admin_pass)?;
+            let password_hash = hash_password(default_admin_pass, &Argon2Params::current())?;
             let result = query_scalar!(
                 "INSERT INTO \"user\" (username, password_hash, last_name, first_name, email) \
                 VALUES ('admin', $1, 'Administrator', 'DefGuard', 'admin@defguard') \
@@ -988,7 +1894,8 @@ impl User<Id> {
             Self,
             "SELECT u.id, u.username, u.password_hash, u.last_name, u.first_name, u.email, \
             u.phone, u.mfa_enabled, u.totp_enabled, u.email_mfa_enabled, \
-            u.totp_secret, u.email_mfa_secret, u.mfa_method \"mfa_method: _\", u.recovery_codes, u.is_active, u.openid_sub \
+            u.totp_secret, u.totp_digits, u.totp_period, u.email_mfa_secret, u.mfa_method \"mfa_method: _\", u.recovery_codes, u.is_active, u.openid_sub, \
+            u.suspended_until, u.password_changed_at, u.must_change_password \
             FROM \"user\" u \
             JOIN \"device\" d ON u.id = d.user_id \
             WHERE d.id = $1",
@@ -998,6 +1905,29 @@ impl User<Id> {
         .await
     }
 
+    /// Count user devices which are currently connected to any location,
+    /// i.e. made a handshake within `WIREGUARD_MAX_HANDSHAKE`.
+    pub async fn connected_devices_count<'e, E>(&self, executor: E) -> Result<i64, SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
+        query_scalar!(
+            "WITH stats AS ( \
+                SELECT DISTINCT ON (device_id, network) device_id, latest_handshake \
+                FROM wireguard_peer_stats \
+                ORDER BY device_id, network, collected_at DESC \
+            ) \
+            SELECT COUNT(DISTINCT d.id) \"count!\" \
+            FROM device d JOIN stats ON d.id = stats.device_id \
+            WHERE d.user_id = $1 AND d.device_type = 'user'::device_type \
+            AND (NOW() - stats.latest_handshake) < $2",
+            self.id,
+            PgInterval::try_from(WIREGUARD_MAX_HANDSHAKE).unwrap(),
+        )
+        .fetch_one(executor)
+        .await
+    }
+
     /// Find users which emails are NOT in `user_emails`.
     pub(crate) async fn exclude<'e, E>(
         executor: E,
@@ -1009,8 +1939,9 @@ impl User<Id> {
         // This can't be a macro since sqlx can't handle an array of slices in a macro.
         query_as(
             "SELECT id, username, password_hash, last_name, first_name, email, phone, \
-            mfa_enabled, totp_enabled, email_mfa_enabled, totp_secret, email_mfa_secret, \
-            mfa_method, recovery_codes, is_active, openid_sub \
+            mfa_enabled, totp_enabled, email_mfa_enabled, totp_secret, totp_digits, totp_period, email_mfa_secret, \
+            mfa_method, recovery_codes, is_active, openid_sub, \
+            suspended_until, password_changed_at, must_change_password \
             FROM \"user\" WHERE email NOT IN (SELECT * FROM UNNEST($1::TEXT[]))",
         )
         .bind(user_emails)
@@ -1038,7 +1969,8 @@ impl User<Id> {
             "
             SELECT u.id, u.username, u.password_hash, u.last_name, u.first_name, u.email, \
             u.phone, u.mfa_enabled, u.totp_enabled, u.email_mfa_enabled, \
-            u.totp_secret, u.email_mfa_secret, u.mfa_method \"mfa_method: _\", u.recovery_codes, u.is_active, u.openid_sub \
+            u.totp_secret, u.totp_digits, u.totp_period, u.email_mfa_secret, u.mfa_method \"mfa_method: _\", u.recovery_codes, u.is_active, u.openid_sub, \
+            u.suspended_until, u.password_changed_at, u.must_change_password \
             FROM \"user\" u \
             WHERE EXISTS (SELECT 1 FROM group_user gu LEFT JOIN \"group\" g ON gu.group_id = g.id \
             WHERE is_admin = true AND user_id = u.id) AND u.is_active = true"
@@ -1050,11 +1982,26 @@ impl User<Id> {
 
 #[cfg(test)]
 mod test {
+    use std::collections::HashMap;
+
     use super::*;
     use crate::{
-        config::DefGuardConfig, db::models::settings::initialize_current_settings, SERVER_CONFIG,
+        config::DefGuardConfig, db::models::wireguard_peer_stats::WireguardPeerStats, SERVER_CONFIG,
     };
 
+    fn current_totp_code(user: &User<Id>) -> String {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        totp_custom::<Sha1>(
+            user.totp_period as u64,
+            user.totp_digits as u32,
+            user.totp_secret.as_ref().unwrap(),
+            timestamp,
+        )
+    }
+
     #[sqlx::test]
     async fn test_mfa_code(pool: PgPool) {
         let config = DefGuardConfig::new_test_config();
@@ -1063,12 +2010,13 @@ mod test {
 
         let mut user = User::new(
             "hpotter",
-            Some("pass123"),
+            Some("Pass123!"),
             "Potter",
             "Harry",
             "h.potter@hogwart.edu.uk",
             None,
         )
+        .unwrap()
         .save(&pool)
         .await
         .unwrap();
@@ -1086,12 +2034,13 @@ mod test {
     async fn test_user(pool: PgPool) {
         let mut user = User::new(
             "hpotter",
-            Some("pass123"),
+            Some("Pass123!"),
             "Potter",
             "Harry",
             "h.potter@hogwart.edu.uk",
             None,
         )
+        .unwrap()
         .save(&pool)
         .await
         .unwrap();
@@ -1107,7 +2056,7 @@ mod test {
         assert!(fetched_user.is_some());
         assert_eq!(fetched_user.unwrap().email, "harry.potter@hogwart.edu.uk");
 
-        assert!(user.verify_password("pass123").is_ok());
+        assert!(user.verify_password("Pass123!").is_ok());
 
         let fetched_user = User::find_by_username(&pool, "rweasley").await.unwrap();
         assert!(fetched_user.is_none());
@@ -1117,24 +2066,26 @@ mod test {
     async fn test_all_users(pool: PgPool) {
         User::new(
             "hpotter",
-            Some("pass123"),
+            Some("Pass123!"),
             "Potter",
             "Harry",
             "h.potter@hogwart.edu.uk",
             None,
         )
+        .unwrap()
         .save(&pool)
         .await
         .unwrap();
 
         let albus = User::new(
             "adumbledore",
-            Some("magic!"),
+            Some("Magic123!"),
             "Dumbledore",
             "Albus",
             "a.dumbledore@hogwart.edu.uk",
             None,
         )
+        .unwrap()
         .save(&pool)
         .await
         .unwrap();
@@ -1149,131 +2100,1130 @@ mod test {
     }
 
     #[sqlx::test]
-    async fn test_recovery_codes(pool: PgPool) {
+    async fn test_mfa_distribution(pool: PgPool) {
+        let config = DefGuardConfig::new_test_config();
+        let _ = SERVER_CONFIG.set(config.clone());
+
+        let distribution = User::mfa_distribution(&pool).await.unwrap();
+        assert_eq!(distribution, MfaDistribution::default());
+
+        let mut users = Vec::new();
+        for (username, email) in [
+            ("hpotter", "h.potter@hogwart.edu.uk"),
+            ("hgranger", "h.granger@hogwart.edu.uk"),
+            ("rweasley", "r.weasley@hogwart.edu.uk"),
+            ("nlongbottom", "n.longbottom@hogwart.edu.uk"),
+        ] {
+            let user = User::new(username, None, "Student", "Hogwart", email, None)
+                .unwrap()
+                .save(&pool)
+                .await
+                .unwrap();
+            users.push(user);
+        }
+        // Harry: TOTP and a security key
+        users[0].new_totp_secret(&pool).await.unwrap();
+        let code = current_totp_code(&users[0]);
+        assert!(users[0].confirm_totp_enroll(&pool, &code).await.unwrap());
+        query!(
+            "INSERT INTO webauthn (user_id, name, passkey) VALUES ($1, 'key', '')",
+            users[0].id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        // Hermione: e-mail only
+        users[1].new_email_secret(&pool).await.unwrap();
+        users[1].enable_email_mfa(&pool).await.unwrap();
+        // Ron: TOTP only, Neville: nothing
+        users[2].new_totp_secret(&pool).await.unwrap();
+        let code = current_totp_code(&users[2]);
+        assert!(users[2].confirm_totp_enroll(&pool, &code).await.unwrap());
+
+        let distribution = User::mfa_distribution(&pool).await.unwrap();
+        assert_eq!(
+            distribution,
+            MfaDistribution {
+                total: 4,
+                totp: 2,
+                email: 1,
+                webauthn: 1,
+                without_mfa: 1,
+            }
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_available_mfa_methods(pool: PgPool) {
+        let config = DefGuardConfig::new_test_config();
+        let _ = SERVER_CONFIG.set(config.clone());
+
         let mut harry = User::new(
             "hpotter",
-            Some("pass123"),
+            Some("Pass123!"),
             "Potter",
             "Harry",
             "h.potter@hogwart.edu.uk",
             None,
         )
+        .unwrap()
         .save(&pool)
         .await
         .unwrap();
-        harry.get_recovery_codes(&pool).await.unwrap();
-        assert_eq!(harry.recovery_codes.len(), RECOVERY_CODES_COUNT);
+        assert!(harry.available_mfa_methods(&pool).await.unwrap().is_empty());
 
-        let fetched_user = User::find_by_username(&pool, "hpotter").await.unwrap();
-        assert!(fetched_user.is_some());
+        harry.new_totp_secret(&pool).await.unwrap();
+        let code = current_totp_code(&harry);
+        assert!(harry.confirm_totp_enroll(&pool, &code).await.unwrap());
+        query!(
+            "INSERT INTO webauthn (user_id, name, passkey) VALUES ($1, 'key', '')",
+            harry.id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
 
-        let mut user = fetched_user.unwrap();
-        assert_eq!(user.recovery_codes.len(), RECOVERY_CODES_COUNT);
-        assert!(!user
-            .verify_recovery_code(&pool, "invalid code")
-            .await
-            .unwrap());
-        let codes = user.recovery_codes.clone();
-        for code in &codes {
-            assert!(user.verify_recovery_code(&pool, code).await.unwrap());
-        }
-        assert_eq!(user.recovery_codes.len(), 0);
+        let methods = harry.available_mfa_methods(&pool).await.unwrap();
+        assert_eq!(methods.len(), 2);
+        assert!(methods.contains(&MFAMethod::OneTimePassword));
+        assert!(methods.contains(&MFAMethod::Webauthn));
     }
 
     #[sqlx::test]
-    async fn test_email_case_insensitivity(pool: PgPool) {
-        let harry = User::new(
+    async fn test_disable_totp_keeps_security_keys(pool: PgPool) {
+        let config = DefGuardConfig::new_test_config();
+        let _ = SERVER_CONFIG.set(config.clone());
+
+        let mut harry = User::new(
             "hpotter",
-            Some("pass123"),
+            Some("Pass123!"),
             "Potter",
             "Harry",
             "h.potter@hogwart.edu.uk",
             None,
-        );
-        assert!(harry.save(&pool).await.is_ok());
-
-        let henry = User::new(
-            "h.potter",
-            Some("pass123"),
-            "Potter",
-            "Henry",
-            "h.potter@hogwart.edu.uk",
-            None,
-        );
-        assert!(henry.save(&pool).await.is_err());
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+        harry.new_totp_secret(&pool).await.unwrap();
+        let code = current_totp_code(&harry);
+        assert!(harry.confirm_totp_enroll(&pool, &code).await.unwrap());
+        query!(
+            "INSERT INTO webauthn (user_id, name, passkey) VALUES ($1, 'key', '')",
+            harry.id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        harry.verify_mfa_state(&pool).await.unwrap();
+        assert!(harry.mfa_enabled);
+
+        harry.disable_totp(&pool).await.unwrap();
+        harry.verify_mfa_state(&pool).await.unwrap();
+
+        let user = User::find_by_id(&pool, harry.id).await.unwrap().unwrap();
+        assert!(!user.totp_enabled);
+        assert!(user.totp_secret.is_none());
+        // security key is still there, so MFA stays on
+        assert_eq!(WebAuthn::count_for_user(&pool, harry.id).await.unwrap(), 1);
+        assert!(user.mfa_enabled);
+        assert_eq!(user.mfa_method, MFAMethod::Webauthn);
     }
 
     #[sqlx::test]
-    async fn test_is_admin(pool: PgPool) {
+    async fn test_set_mfa_method(pool: PgPool) {
         let config = DefGuardConfig::new_test_config();
         let _ = SERVER_CONFIG.set(config.clone());
 
-        let user = User::new(
+        let mut harry = User::new(
             "hpotter",
-            Some("pass123"),
+            Some("Pass123!"),
             "Potter",
             "Harry",
             "h.potter@hogwart.edu.uk",
             None,
         )
+        .unwrap()
         .save(&pool)
         .await
         .unwrap();
 
-        let is_admin = user.is_admin(&pool).await.unwrap();
+        // no factors configured
+        for method in [
+            MFAMethod::OneTimePassword,
+            MFAMethod::Webauthn,
+            MFAMethod::Email,
+        ] {
+            let result = harry.set_mfa_method(&pool, method).await;
+            assert!(matches!(result, Err(WebError::BadRequest(_))));
+            assert_eq!(harry.mfa_method, MFAMethod::None);
+        }
+        harry.set_mfa_method(&pool, MFAMethod::None).await.unwrap();
 
-        assert!(!is_admin);
+        harry.new_totp_secret(&pool).await.unwrap();
+        let code = current_totp_code(&harry);
+        assert!(harry.confirm_totp_enroll(&pool, &code).await.unwrap());
+        harry
+            .set_mfa_method(&pool, MFAMethod::OneTimePassword)
+            .await
+            .unwrap();
+        assert_eq!(harry.mfa_method, MFAMethod::OneTimePassword);
 
+        harry.new_email_secret(&pool).await.unwrap();
+        harry.enable_email_mfa(&pool).await.unwrap();
+        harry.set_mfa_method(&pool, MFAMethod::Email).await.unwrap();
+        assert_eq!(harry.mfa_method, MFAMethod::Email);
+
+        // still no security key
+        let result = harry.set_mfa_method(&pool, MFAMethod::Webauthn).await;
+        assert!(matches!(result, Err(WebError::BadRequest(_))));
         query!(
-            "INSERT INTO group_user (group_id, user_id) VALUES (1, $1)",
-            user.id
+            "INSERT INTO webauthn (user_id, name, passkey) VALUES ($1, 'key', '')",
+            harry.id
         )
         .execute(&pool)
         .await
         .unwrap();
+        harry
+            .set_mfa_method(&pool, MFAMethod::Webauthn)
+            .await
+            .unwrap();
 
-        let is_admin = user.is_admin(&pool).await.unwrap();
-
-        assert!(is_admin);
+        let user = User::find_by_id(&pool, harry.id).await.unwrap().unwrap();
+        assert_eq!(user.mfa_method, MFAMethod::Webauthn);
     }
 
     #[sqlx::test]
-    async fn test_find_admins(pool: PgPool) {
+    #[allow(deprecated)]
+    async fn test_enable_totp(pool: PgPool) {
         let config = DefGuardConfig::new_test_config();
         let _ = SERVER_CONFIG.set(config.clone());
 
-        let user = User::new(
+        let mut harry = User::new(
             "hpotter",
-            Some("pass123"),
+            Some("Pass123!"),
             "Potter",
             "Harry",
             "h.potter@hogwart.edu.uk",
             None,
         )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+
+        // secret is required
+        let result = harry.enable_totp(&pool).await;
+        assert!(matches!(result, Err(WebError::BadRequest(_))));
+        assert!(!harry.totp_enabled);
+        let user = User::find_by_id(&pool, harry.id).await.unwrap().unwrap();
+        assert!(!user.totp_enabled);
+
+        harry.new_totp_secret(&pool).await.unwrap();
+        harry.enable_totp(&pool).await.unwrap();
+        assert!(harry.totp_enabled);
+        let user = User::find_by_id(&pool, harry.id).await.unwrap().unwrap();
+        assert!(user.totp_enabled);
+        assert!(user.totp_secret.is_some());
+    }
+
+    #[sqlx::test]
+    async fn test_confirm_totp_enroll(pool: PgPool) {
+        let config = DefGuardConfig::new_test_config();
+        let _ = SERVER_CONFIG.set(config.clone());
+
+        let mut harry = User::new(
+            "hpotter",
+            Some("Pass123!"),
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+        harry.new_totp_secret(&pool).await.unwrap();
+
+        // wrong code leaves TOTP disabled
+        assert!(!harry.confirm_totp_enroll(&pool, "invalid").await.unwrap());
+        assert!(!harry.totp_enabled);
+        let user = User::find_by_id(&pool, harry.id).await.unwrap().unwrap();
+        assert!(!user.totp_enabled);
+
+        // correct code enables TOTP
+        let code = current_totp_code(&harry);
+        assert!(harry.confirm_totp_enroll(&pool, &code).await.unwrap());
+        assert!(harry.totp_enabled);
+        let user = User::find_by_id(&pool, harry.id).await.unwrap().unwrap();
+        assert!(user.totp_enabled);
+    }
+
+    #[sqlx::test]
+    async fn test_confirm_totp_enroll_grace(pool: PgPool) {
+        let config = DefGuardConfig::new_test_config();
+        let _ = SERVER_CONFIG.set(config.clone());
+        let config = server_config();
+        assert!(config.totp_setup_drift_steps > config.totp_drift_steps);
+
+        let mut harry = User::new(
+            "hpotter",
+            Some("Pass123!"),
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+        harry.new_totp_secret(&pool).await.unwrap();
+
+        // code from a clock one step behind the login window
+        let period = harry.totp_period as u64;
+        let steps = config.totp_drift_steps + 1;
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let code = totp_custom::<Sha1>(
+            period,
+            harry.totp_digits as u32,
+            harry.totp_secret.as_ref().unwrap(),
+            timestamp - steps * period,
+        );
+
+        // rejected at login, accepted during setup
+        assert!(!harry.verify_totp_code(&code));
+        assert!(harry.confirm_totp_enroll(&pool, &code).await.unwrap());
+        assert!(harry.totp_enabled);
+        assert!(!harry.verify_totp_code(&code));
+    }
+
+    #[sqlx::test]
+    async fn test_complete_totp_setup(pool: PgPool) {
+        let _ = SERVER_CONFIG.set(DefGuardConfig::new_test_config());
+        let mut harry = User::new(
+            "hpotter",
+            Some("Pass123!"),
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+        harry.new_totp_secret(&pool).await.unwrap();
+
+        // invalid code changes nothing
+        let before = harry.clone();
+        assert!(harry.complete_totp_setup(&pool, "000000").await.is_err());
+        assert_eq!(harry, before);
+        let stored = User::find_by_id(&pool, harry.id).await.unwrap().unwrap();
+        assert_eq!(stored, before);
+        assert!(!stored.totp_enabled);
+        assert!(!stored.mfa_enabled);
+        assert!(stored.recovery_codes.is_empty());
+
+        let code = current_totp_code(&harry);
+        let codes = harry
+            .complete_totp_setup(&pool, &code)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!codes.is_empty());
+        let stored = User::find_by_id(&pool, harry.id).await.unwrap().unwrap();
+        assert_eq!(stored, harry);
+        assert!(stored.totp_enabled);
+        assert!(stored.mfa_enabled);
+        assert_eq!(stored.mfa_method, MFAMethod::OneTimePassword);
+        assert_eq!(stored.recovery_codes.len(), codes.len());
+    }
+
+    #[sqlx::test]
+    async fn test_bulk_disable_mfa(pool: PgPool) {
+        let _ = SERVER_CONFIG.set(DefGuardConfig::new_test_config());
+        let admin = User::new(
+            "admin",
+            Some("Pass123!"),
+            "Dumbledore",
+            "Albus",
+            "a.dumbledore@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+        let mut users = Vec::new();
+        for (username, email) in [
+            ("hpotter", "h.potter@hogwart.edu.uk"),
+            ("hgranger", "h.granger@hogwart.edu.uk"),
+            ("rweasley", "r.weasley@hogwart.edu.uk"),
+        ] {
+            let mut user = User::new(username, Some("Pass123!"), "Last", "First", email, None)
+                .unwrap()
+                .save(&pool)
+                .await
+                .unwrap();
+            user.new_totp_secret(&pool).await.unwrap();
+            let code = current_totp_code(&user);
+            user.complete_totp_setup(&pool, &code)
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(user.mfa_enabled);
+            users.push(user);
+        }
+
+        // non-existent user is skipped
+        let count = User::bulk_disable_mfa(&pool, &[users[0].id, users[1].id, 9999], admin.id)
+            .await
+            .unwrap();
+        assert_eq!(count, 2);
+
+        for user in &users[..2] {
+            let stored = User::find_by_id(&pool, user.id).await.unwrap().unwrap();
+            assert!(!stored.mfa_enabled);
+            assert!(!stored.totp_enabled);
+            assert!(stored.totp_secret.is_none());
+            assert_eq!(stored.mfa_method, MFAMethod::None);
+            assert!(stored.recovery_codes.is_empty());
+
+            let events = MfaResetEvent::find_by_user_id(&pool, user.id)
+                .await
+                .unwrap();
+            assert_eq!(events.len(), 1);
+            assert_eq!(events[0].admin_id, Some(admin.id));
+        }
+
+        // other users are left alone
+        let stored = User::find_by_id(&pool, users[2].id).await.unwrap().unwrap();
+        assert!(stored.mfa_enabled);
+        assert!(stored.totp_enabled);
+        assert!(MfaResetEvent::find_by_user_id(&pool, users[2].id)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[sqlx::test]
+    async fn test_recovery_codes(pool: PgPool) {
+        let mut harry = User::new(
+            "hpotter",
+            Some("Pass123!"),
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+        let codes = harry.get_recovery_codes(&pool).await.unwrap().unwrap();
+        assert_eq!(codes.len(), RECOVERY_CODES_COUNT);
+        assert_eq!(harry.recovery_codes.len(), RECOVERY_CODES_COUNT);
+
+        let fetched_user = User::find_by_username(&pool, "hpotter").await.unwrap();
+        assert!(fetched_user.is_some());
+
+        let mut user = fetched_user.unwrap();
+        assert_eq!(user.recovery_codes.len(), RECOVERY_CODES_COUNT);
+        // only hashes are stored
+        for code in &codes {
+            assert!(!user.recovery_codes.contains(code));
+        }
+        assert!(user
+            .recovery_codes
+            .iter()
+            .all(|stored| stored.starts_with(RECOVERY_CODE_HASH_PREFIX)));
+        assert!(!user
+            .verify_recovery_code(&pool, "invalid code")
+            .await
+            .unwrap());
+        for code in &codes {
+            assert!(user.verify_recovery_code(&pool, code).await.unwrap());
+            // codes can be used only once
+            assert!(!user.verify_recovery_code(&pool, code).await.unwrap());
+        }
+        assert_eq!(user.recovery_codes.len(), 0);
+    }
+
+    #[sqlx::test]
+    async fn test_recovery_codes_disabled(pool: PgPool) {
+        let mut harry = User::new(
+            "hpotter",
+            Some("Pass123!"),
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+        let mut ron = User::new(
+            "rweasley",
+            Some("Pass123!"),
+            "Weasley",
+            "Ron",
+            "r.weasley@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+        let codes = harry
+            .get_recovery_codes_with(&pool, true)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // no codes are generated while recovery codes are disabled
+        assert!(ron
+            .get_recovery_codes_with(&pool, false)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(ron.recovery_codes.is_empty());
+        assert!(matches!(
+            ron.regenerate_recovery_codes_with(&pool, true, false).await,
+            Err(WebError::BadRequest(_))
+        ));
+
+        // existing codes are rejected and left untouched
+        assert!(!harry.check_recovery_code_with(&codes[0], false));
+        assert!(!harry
+            .verify_recovery_code_with(&pool, &codes[0], false)
+            .await
+            .unwrap());
+        assert_eq!(harry.recovery_codes.len(), RECOVERY_CODES_COUNT);
+
+        // and work again once recovery codes are re-enabled
+        assert!(harry.check_recovery_code_with(&codes[0], true));
+        assert!(harry
+            .verify_recovery_code_with(&pool, &codes[0], true)
+            .await
+            .unwrap());
+    }
+
+    #[sqlx::test]
+    async fn test_check_recovery_code(pool: PgPool) {
+        let mut harry = User::new(
+            "hpotter",
+            Some("Pass123!"),
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+        assert!(!harry.check_recovery_code("invalid code"));
+
+        let codes = harry.get_recovery_codes(&pool).await.unwrap().unwrap();
+        let stored = harry.recovery_codes.clone();
+        for code in &codes {
+            // checking doesn't consume the code
+            assert!(harry.check_recovery_code(code));
+            assert!(harry.check_recovery_code(code));
+        }
+        assert!(!harry.check_recovery_code("invalid code"));
+        assert_eq!(harry.recovery_codes, stored);
+        let fetched = User::find_by_id(&pool, harry.id).await.unwrap().unwrap();
+        assert_eq!(fetched.recovery_codes, stored);
+
+        // consumed code is no longer valid
+        let mut harry = fetched;
+        assert!(harry.verify_recovery_code(&pool, &codes[0]).await.unwrap());
+        assert!(!harry.check_recovery_code(&codes[0]));
+        assert!(harry.check_recovery_code(&codes[1]));
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"abcd"));
+        assert!(constant_time_eq(b"", b""));
+    }
+
+    #[sqlx::test]
+    async fn test_legacy_plaintext_recovery_codes(pool: PgPool) {
+        let harry = User::new(
+            "hpotter",
+            Some("Pass123!"),
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+        // codes stored before hashing was introduced
+        let legacy_codes = vec![
+            "firstlegacycode0".to_string(),
+            "secondlegacycode".to_string(),
+        ];
+        query!(
+            "UPDATE \"user\" SET recovery_codes = $2 WHERE id = $1",
+            harry.id,
+            &legacy_codes
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let mut user = User::find_by_id(&pool, harry.id).await.unwrap().unwrap();
+        assert!(user
+            .verify_recovery_code(&pool, &legacy_codes[0])
+            .await
+            .unwrap());
+
+        // the remaining plaintext code has been replaced with its hash
+        let mut user = User::find_by_id(&pool, harry.id).await.unwrap().unwrap();
+        assert_eq!(user.recovery_codes.len(), 1);
+        assert_ne!(user.recovery_codes[0], legacy_codes[1]);
+        assert!(user.recovery_codes[0].starts_with(RECOVERY_CODE_HASH_PREFIX));
+        assert!(!user
+            .verify_recovery_code(&pool, &legacy_codes[0])
+            .await
+            .unwrap());
+        assert!(user
+            .verify_recovery_code(&pool, &legacy_codes[1])
+            .await
+            .unwrap());
+        assert!(user.recovery_codes.is_empty());
+    }
+
+    #[sqlx::test]
+    async fn test_regenerate_recovery_codes(pool: PgPool) {
+        let config = DefGuardConfig::new_test_config();
+        let _ = SERVER_CONFIG.set(config.clone());
+
+        let mut harry = User::new(
+            "hpotter",
+            Some("Pass123!"),
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+        let initial_codes = harry.get_recovery_codes(&pool).await.unwrap().unwrap();
+
+        // within cooldown
+        let result = harry.regenerate_recovery_codes(&pool, false).await;
+        assert!(matches!(result, Err(WebError::RecoveryCodesCooldown(_))));
+        assert!(harry
+            .verify_recovery_code(&pool, &initial_codes[0])
+            .await
+            .unwrap());
+
+        // admins are not limited
+        let admin_codes = harry.regenerate_recovery_codes(&pool, true).await.unwrap();
+        assert_eq!(admin_codes.len(), RECOVERY_CODES_COUNT);
+        assert_ne!(admin_codes, initial_codes);
+
+        // after cooldown
+        query!(
+            "UPDATE \"user\" SET recovery_codes_generated_at = now() - interval '2 hours' \
+            WHERE id = $1",
+            harry.id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let codes = harry.regenerate_recovery_codes(&pool, false).await.unwrap();
+        assert_ne!(codes, admin_codes);
+        let mut user = User::find_by_id(&pool, harry.id).await.unwrap().unwrap();
+        assert_eq!(user.recovery_codes.len(), codes.len());
+        assert!(!user
+            .verify_recovery_code(&pool, &initial_codes[1])
+            .await
+            .unwrap());
+        assert!(user.verify_recovery_code(&pool, &codes[0]).await.unwrap());
+
+        // regeneration starts a new cooldown
+        let result = harry.regenerate_recovery_codes(&pool, false).await;
+        assert!(matches!(result, Err(WebError::RecoveryCodesCooldown(_))));
+    }
+
+    #[sqlx::test]
+    async fn test_regeneration_invalidates_recovery_codes(pool: PgPool) {
+        let config = DefGuardConfig::new_test_config();
+        let _ = SERVER_CONFIG.set(config.clone());
+
+        let mut harry = User::new(
+            "hpotter",
+            Some("Pass123!"),
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+        harry.mfa_enabled = true;
+        let old_codes = harry.get_recovery_codes(&pool).await.unwrap().unwrap();
+        assert_eq!(harry.recovery_codes_count(), RECOVERY_CODES_COUNT);
+        assert!(!harry.recovery_codes_low());
+
+        // use up codes until regeneration is recommended
+        let threshold = RECOVERY_CODES_COUNT - RECOVERY_CODES_LOW_THRESHOLD;
+        for code in &old_codes[..threshold] {
+            assert!(harry.verify_recovery_code(&pool, code).await.unwrap());
+        }
+        assert!(!harry.recovery_codes_low());
+        assert!(harry
+            .verify_recovery_code(&pool, &old_codes[threshold])
+            .await
+            .unwrap());
+        assert_eq!(
+            harry.recovery_codes_count(),
+            RECOVERY_CODES_LOW_THRESHOLD - 1
+        );
+        assert!(harry.recovery_codes_low());
+
+        let new_codes = harry.regenerate_recovery_codes(&pool, true).await.unwrap();
+        assert_eq!(new_codes.len(), RECOVERY_CODES_COUNT);
+        assert_eq!(harry.recovery_codes_count(), RECOVERY_CODES_COUNT);
+        assert!(!harry.recovery_codes_low());
+
+        // none of the previously issued codes work anymore
+        let mut user = User::find_by_id(&pool, harry.id).await.unwrap().unwrap();
+        for code in &old_codes {
+            assert!(!user.verify_recovery_code(&pool, code).await.unwrap());
+        }
+        assert!(user
+            .verify_recovery_code(&pool, &new_codes[0])
+            .await
+            .unwrap());
+    }
+
+    #[test]
+    fn test_recovery_codes_summary() {
+        let mut harry = User::new(
+            "hpotter",
+            Some("Pass123!"),
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            harry.recovery_codes_summary(),
+            RecoveryCodesSummary {
+                count: 0,
+                generated: false,
+                regenerate_recommended: false,
+            }
+        );
+
+        harry.mfa_enabled = true;
+        harry.recovery_codes = vec!["first-code".into(), "second-code".into()];
+        let summary = harry.recovery_codes_summary();
+        assert_eq!(summary.count, 2);
+        assert!(summary.generated);
+        assert!(summary.regenerate_recommended);
+        let serialized = serde_json::to_string(&summary).unwrap();
+        assert!(!serialized.contains("first-code"));
+        assert!(!serialized.contains("second-code"));
+
+        // all codes used up
+        harry.recovery_codes.clear();
+        assert_eq!(
+            harry.recovery_codes_summary(),
+            RecoveryCodesSummary {
+                count: 0,
+                generated: true,
+                regenerate_recommended: true,
+            }
+        );
+    }
+
+    #[test]
+    fn test_totp_code_parameters() {
+        let _ = SERVER_CONFIG.set(DefGuardConfig::new_test_config());
+
+        let mut harry = User::new(
+            "hpotter",
+            Some("Pass123!"),
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap();
+        let secret = gen_totp_secret();
+        harry.totp_secret = Some(secret.clone());
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        // defaults
+        let code = totp_custom::<Sha1>(TOTP_CODE_VALIDITY_PERIOD, 6, &secret, timestamp);
+        assert!(harry.verify_totp_code(&code));
+
+        // 8-digit codes with 60 second step
+        harry.totp_digits = 8;
+        harry.totp_period = 60;
+        let code = totp_custom::<Sha1>(60, 8, &secret, timestamp);
+        assert_eq!(code.len(), 8);
+        assert!(harry.verify_totp_code(&code));
+        let short_code = totp_custom::<Sha1>(60, 6, &secret, timestamp);
+        assert!(!harry.verify_totp_code(&short_code));
+    }
+
+    #[test]
+    fn test_totp_provisioning_uri() {
+        let mut harry = User::new(
+            "hpotter",
+            Some("Pass123!"),
+            "Potter",
+            "Harry",
+            "h.potter+test@hogwart.edu.uk",
+            None,
+        )
+        .unwrap();
+        assert_eq!(harry.totp_provisioning_uri("Defguard"), None);
+
+        let secret = gen_totp_secret();
+        harry.totp_secret = Some(secret.clone());
+        let uri = harry.totp_provisioning_uri("Hogwarts & Co: VPN").unwrap();
+        assert!(!uri.contains(' '));
+
+        let parsed = Url::parse(&uri).unwrap();
+        assert_eq!(parsed.scheme(), "otpauth");
+        assert_eq!(parsed.host_str(), Some("totp"));
+        let segments: Vec<&str> = parsed.path_segments().unwrap().collect();
+        assert_eq!(segments.len(), 1);
+        assert_eq!(
+            segments[0],
+            "Hogwarts%20&%20Co:%20VPN:h.potter+test@hogwart.edu.uk"
+        );
+        let params: HashMap<String, String> = parsed.query_pairs().into_owned().collect();
+        assert_eq!(
+            params["secret"],
+            base32::encode(base32::Alphabet::Rfc4648 { padding: false }, &secret)
+        );
+        assert_eq!(params["issuer"], "Hogwarts & Co: VPN");
+        assert_eq!(params["digits"], "6");
+        assert_eq!(params["period"], "30");
+
+        // username is used if there is no email
+        harry.email = String::new();
+        let uri = harry.totp_provisioning_uri("Defguard").unwrap();
+        assert!(uri.starts_with("otpauth://totp/Defguard:hpotter?"));
+    }
+
+    #[test]
+    fn test_totp_drift_window() {
+        let mut harry = User::new(
+            "hpotter",
+            Some("Pass123!"),
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap();
+        let secret = gen_totp_secret();
+        harry.totp_secret = Some(secret.clone());
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let code_at = |steps: i64| {
+            let time = now.checked_add_signed(steps * TOTP_CODE_VALIDITY_PERIOD as i64);
+            totp_custom::<Sha1>(TOTP_CODE_VALIDITY_PERIOD, 6, &secret, time.unwrap())
+        };
+
+        // within window
+        assert!(harry.verify_totp_code_in_window(&code_at(0), 1));
+        assert!(harry.verify_totp_code_in_window(&code_at(-1), 1));
+        assert!(harry.verify_totp_code_in_window(&code_at(1), 1));
+
+        // outside window
+        assert!(!harry.verify_totp_code_in_window(&code_at(-2), 1));
+        assert!(!harry.verify_totp_code_in_window(&code_at(2), 1));
+        assert!(!harry.verify_totp_code_in_window(&code_at(-1), 0));
+        assert!(!harry.verify_totp_code_in_window(&code_at(1), 0));
+
+        // wider window
+        assert!(harry.verify_totp_code_in_window(&code_at(-2), 2));
+        assert!(harry.verify_totp_code_in_window(&code_at(2), 2));
+    }
+
+    #[test]
+    fn test_argon2_params() {
+        let weak = Argon2Params {
+            memory_kib: 8 * 1024,
+            iterations: 1,
+            parallelism: 1,
+        };
+        let strong = Argon2Params {
+            memory_kib: 32 * 1024,
+            iterations: 3,
+            parallelism: 2,
+        };
+
+        let weak_hash = hash_password("pass123", &weak).unwrap();
+        assert!(weak_hash.contains("m=8192,t=1,p=1"));
+        let strong_hash = hash_password("pass123", &strong).unwrap();
+        assert!(strong_hash.contains("m=32768,t=3,p=2"));
+
+        // parameters are taken from the hash itself
+        assert!(verify_password("pass123", &weak_hash, &strong).is_ok());
+        assert!(verify_password("pass123", &strong_hash, &weak).is_ok());
+        assert!(verify_password("pass123", &weak_hash, &Argon2Params::default()).is_ok());
+        assert!(verify_password("wrong", &weak_hash, &strong).is_err());
+
+        let invalid = Argon2Params {
+            memory_kib: 1,
+            ..weak
+        };
+        assert!(hash_password("pass123", &invalid).is_err());
+    }
+
+    #[test]
+    fn test_password_pepper() {
+        let params = Argon2Params {
+            memory_kib: 8 * 1024,
+            iterations: 1,
+            parallelism: 1,
+        };
+        let pepper_v1 = PasswordPepper {
+            version: 1,
+            secret: "first pepper".to_string().into(),
+        };
+        let pepper_v2 = PasswordPepper {
+            version: 2,
+            secret: "second pepper".to_string().into(),
+        };
+        let no_pepper = PasswordPeppers::default();
+        let peppers_v1 = PasswordPeppers {
+            active: Some(pepper_v1.clone()),
+            previous: Vec::new(),
+        };
+        let peppers_v2 = PasswordPeppers {
+            active: Some(pepper_v2.clone()),
+            previous: vec![pepper_v1.clone()],
+        };
+
+        // unpeppered hashes verify regardless of configured peppers
+        let plain_hash = hash_password_with("pass123", &params, None).unwrap();
+        assert!(!plain_hash.contains("keyid"));
+        for peppers in [&no_pepper, &peppers_v1, &peppers_v2] {
+            assert!(verify_password_with("pass123", &plain_hash, &params, peppers).is_ok());
+            assert!(verify_password_with("wrong", &plain_hash, &params, peppers).is_err());
+        }
+        assert!(!needs_rehash(&plain_hash, &params, &no_pepper));
+        assert!(needs_rehash(&plain_hash, &params, &peppers_v1));
+
+        // peppered hash can't be verified without the pepper
+        let v1_hash = hash_password_with("pass123", &params, Some(&pepper_v1)).unwrap();
+        assert!(v1_hash.contains("keyid="));
+        assert!(verify_password_with("pass123", &v1_hash, &params, &peppers_v1).is_ok());
+        assert!(verify_password_with("wrong", &v1_hash, &params, &peppers_v1).is_err());
+        assert!(verify_password_with("pass123", &v1_hash, &params, &no_pepper).is_err());
+        assert!(!needs_rehash(&v1_hash, &params, &peppers_v1));
+
+        // after rotation old hashes verify with previous pepper and get rehashed
+        assert!(verify_password_with("pass123", &v1_hash, &params, &peppers_v2).is_ok());
+        assert!(needs_rehash(&v1_hash, &params, &peppers_v2));
+        let v2_hash = hash_password_with("pass123", &params, Some(&pepper_v2)).unwrap();
+        assert!(verify_password_with("pass123", &v2_hash, &params, &peppers_v2).is_ok());
+        assert!(verify_password_with("pass123", &v2_hash, &params, &peppers_v1).is_err());
+        assert!(!needs_rehash(&v2_hash, &params, &peppers_v2));
+
+        // same version with a different secret
+        let wrong_pepper = PasswordPeppers {
+            active: Some(PasswordPepper {
+                version: 2,
+                secret: "other pepper".to_string().into(),
+            }),
+            previous: Vec::new(),
+        };
+        assert!(verify_password_with("pass123", &v2_hash, &params, &wrong_pepper).is_err());
+    }
+
+    #[sqlx::test]
+    async fn test_password_hash_upgrade(pool: PgPool) {
+        let weak = Argon2Params {
+            memory_kib: 8 * 1024,
+            iterations: 1,
+            parallelism: 1,
+        };
+        let mut harry = User::new(
+            "hpotter",
+            None,
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap();
+        let weak_hash = hash_password("pass123", &weak).unwrap();
+        harry.password_hash = Some(weak_hash.clone());
+        let mut harry = harry.save(&pool).await.unwrap();
+        assert!(needs_rehash(
+            &weak_hash,
+            &Argon2Params::current(),
+            &PasswordPeppers::default()
+        ));
+
+        // failed verification doesn't touch stored hash
+        assert!(harry
+            .verify_and_upgrade_password(&pool, "wrong")
+            .await
+            .is_err());
+        let user = User::find_by_id(&pool, harry.id).await.unwrap().unwrap();
+        assert_eq!(user.password_hash, Some(weak_hash.clone()));
+
+        // successful verification upgrades the hash
+        harry
+            .verify_and_upgrade_password(&pool, "pass123")
+            .await
+            .unwrap();
+        let mut user = User::find_by_id(&pool, harry.id).await.unwrap().unwrap();
+        let upgraded_hash = user.password_hash.clone().unwrap();
+        assert_ne!(upgraded_hash, weak_hash);
+        assert_eq!(harry.password_hash, Some(upgraded_hash.clone()));
+        assert!(!needs_rehash(
+            &upgraded_hash,
+            &Argon2Params::current(),
+            &PasswordPeppers::default()
+        ));
+        assert!(user.verify_password("pass123").is_ok());
+
+        // up-to-date hash is left alone
+        user.verify_and_upgrade_password(&pool, "pass123")
+            .await
+            .unwrap();
+        let user = User::find_by_id(&pool, harry.id).await.unwrap().unwrap();
+        assert_eq!(user.password_hash, Some(upgraded_hash));
+    }
+
+    #[sqlx::test]
+    async fn test_email_case_insensitivity(pool: PgPool) {
+        let harry = User::new(
+            "hpotter",
+            Some("Pass123!"),
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap();
+        assert!(harry.save(&pool).await.is_ok());
+
+        let henry = User::new(
+            "h.potter",
+            Some("Pass123!"),
+            "Potter",
+            "Henry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap();
+        assert!(henry.save(&pool).await.is_err());
+    }
+
+    #[sqlx::test]
+    async fn test_is_admin(pool: PgPool) {
+        let config = DefGuardConfig::new_test_config();
+        let _ = SERVER_CONFIG.set(config.clone());
+
+        let user = User::new(
+            "hpotter",
+            Some("Pass123!"),
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+
+        let is_admin = user.is_admin(&pool).await.unwrap();
+
+        assert!(!is_admin);
+
+        query!(
+            "INSERT INTO group_user (group_id, user_id) VALUES (1, $1)",
+            user.id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let is_admin = user.is_admin(&pool).await.unwrap();
+
+        assert!(is_admin);
+    }
+
+    #[sqlx::test]
+    async fn test_find_admins(pool: PgPool) {
+        let config = DefGuardConfig::new_test_config();
+        let _ = SERVER_CONFIG.set(config.clone());
+
+        let user = User::new(
+            "hpotter",
+            Some("Pass123!"),
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
         .save(&pool)
         .await
         .unwrap();
 
         let user2 = User::new(
             "hpotter2",
-            Some("pass123"),
+            Some("Pass123!"),
             "Potter",
             "Harry",
             "h.potter2@hogwart.edu.uk",
             None,
         )
+        .unwrap()
         .save(&pool)
         .await
         .unwrap();
 
         User::new(
             "hpotter3",
-            Some("pass123"),
+            Some("Pass123!"),
             "Potter",
             "Harry",
             "h.potter3@hogwart.edu.uk",
             None,
         )
+        .unwrap()
         .save(&pool)
         .await
         .unwrap();
@@ -1297,34 +3247,37 @@ mod test {
     async fn test_get_missing(pool: PgPool) {
         let user1 = User::new(
             "hpotter",
-            Some("pass123"),
+            Some("Pass123!"),
             "Potter",
             "Harry",
             "h.potter@hogwart.edu.uk",
             None,
         )
+        .unwrap()
         .save(&pool)
         .await
         .unwrap();
         let user2 = User::new(
             "hpotter2",
-            Some("pass1234"),
+            Some("Pass1234!"),
             "Potter2",
             "Harry2",
             "h.potter2@hogwart.edu.uk",
             None,
         )
+        .unwrap()
         .save(&pool)
         .await
         .unwrap();
         let albus = User::new(
             "adumbledore",
-            Some("magic!"),
+            Some("Magic123!"),
             "Dumbledore",
             "Albus",
             "a.dumbledore@hogwart.edu.uk",
             None,
         )
+        .unwrap()
         .save(&pool)
         .await
         .unwrap();
@@ -1339,34 +3292,37 @@ mod test {
     async fn test_find_many_by_emails(pool: PgPool) {
         let user1 = User::new(
             "hpotter",
-            Some("pass123"),
+            Some("Pass123!"),
             "Potter",
             "Harry",
             "h.potter@hogwart.edu.uk",
             None,
         )
+        .unwrap()
         .save(&pool)
         .await
         .unwrap();
         User::new(
             "hpotter2",
-            Some("pass1234"),
+            Some("Pass1234!"),
             "Potter2",
             "Harry2",
             "h.potter2@hogwart.edu.uk",
             None,
         )
+        .unwrap()
         .save(&pool)
         .await
         .unwrap();
         let albus = User::new(
             "adumbledore",
-            Some("magic!"),
+            Some("Magic123!"),
             "Dumbledore",
             "Albus",
             "a.dumbledore@hogwart.edu.uk",
             None,
         )
+        .unwrap()
         .save(&pool)
         .await
         .unwrap();
@@ -1379,4 +3335,487 @@ mod test {
         assert_eq!(users[0].id, user1.id);
         assert_eq!(users[1].id, albus.id);
     }
+
+    #[sqlx::test]
+    async fn test_find_in_all_groups(pool: PgPool) {
+        let employees = Group::new("employees").save(&pool).await.unwrap();
+        let vpn = Group::new("vpn-allowed").save(&pool).await.unwrap();
+        let harry = User::new(
+            "hpotter",
+            Some("Pass123!"),
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+        let ron = User::new(
+            "rweasley",
+            Some("Pass123!"),
+            "Weasley",
+            "Ron",
+            "r.weasley@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+        let hermione = User::new(
+            "hgranger",
+            Some("Pass123!"),
+            "Granger",
+            "Hermione",
+            "h.granger@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+        User::new(
+            "nlongbottom",
+            Some("Pass123!"),
+            "Longbottom",
+            "Neville",
+            "n.longbottom@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+
+        harry.add_to_group(&pool, &employees).await.unwrap();
+        harry.add_to_group(&pool, &vpn).await.unwrap();
+        ron.add_to_group(&pool, &employees).await.unwrap();
+        hermione.add_to_group(&pool, &vpn).await.unwrap();
+
+        let users = User::find_in_all_groups(&pool, &["employees", "vpn-allowed"])
+            .await
+            .unwrap();
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].id, harry.id);
+
+        // duplicated names don't change the result
+        let users = User::find_in_all_groups(&pool, &["vpn-allowed", "employees", "employees"])
+            .await
+            .unwrap();
+        assert_eq!(users.len(), 1);
+
+        let users = User::find_in_all_groups(&pool, &["employees"])
+            .await
+            .unwrap();
+        assert_eq!(users.len(), 2);
+        assert_eq!(users[0].id, harry.id);
+        assert_eq!(users[1].id, ron.id);
+
+        // non-existent group
+        let users = User::find_in_all_groups(&pool, &["employees", "slytherin"])
+            .await
+            .unwrap();
+        assert!(users.is_empty());
+
+        let users = User::find_in_all_groups(&pool, &[]).await.unwrap();
+        assert!(users.is_empty());
+    }
+
+    #[sqlx::test]
+    async fn test_connected_devices_count(pool: PgPool) {
+        let mut network = WireguardNetwork::default();
+        network.try_set_address("10.1.1.1/29").unwrap();
+        let network = network.save(&pool).await.unwrap();
+        let user = User::new(
+            "hpotter",
+            Some("Pass123!"),
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+        assert_eq!(user.connected_devices_count(&pool).await.unwrap(), 0);
+
+        let now = Utc::now().naive_utc();
+        // handshakes made 1, 2 and 30 minutes ago
+        for handshake_minutes in [1, 2, 30] {
+            let device = Device::new(
+                format!("device-{handshake_minutes}"),
+                format!("key-{handshake_minutes}"),
+                user.id,
+                DeviceType::User,
+                None,
+                true,
+            )
+            .save(&pool)
+            .await
+            .unwrap();
+            WireguardPeerStats {
+                id: NoId,
+                device_id: device.id,
+                collected_at: now,
+                network: network.id,
+                endpoint: Some("11.22.33.44".into()),
+                upload: 10,
+                download: 20,
+                latest_handshake: now - TimeDelta::minutes(handshake_minutes),
+                allowed_ips: Some("10.1.1.0/24".into()),
+            }
+            .save(&pool)
+            .await
+            .unwrap();
+        }
+        assert_eq!(user.connected_devices_count(&pool).await.unwrap(), 2);
+    }
+
+    #[sqlx::test]
+    async fn test_password_policy(pool: PgPool) {
+        let user = User::new(
+            "hpotter",
+            Some("pass123"),
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        );
+        assert_eq!(user.err(), Some(PasswordError::TooShort(8)));
+
+        let mut user = User::new(
+            "hpotter",
+            Some("Pass123!"),
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        assert_eq!(
+            user.set_password(&mut conn, "password123!").await,
+            Err(PasswordError::MissingUppercase)
+        );
+        assert!(user.verify_password("Pass123!").is_ok());
+        user.set_password(&mut conn, "NewPass123!").await.unwrap();
+        assert!(user.verify_password("NewPass123!").is_ok());
+    }
+
+    #[sqlx::test]
+    async fn test_password_history(pool: PgPool) {
+        let mut user = User::new(
+            "hpotter",
+            Some("First123!"),
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        for password in ["Second123!", "Third123!"] {
+            user.set_password_with_history(&mut conn, password, 3)
+                .await
+                .unwrap();
+        }
+
+        // neither previous nor current password can be reused
+        assert_eq!(
+            user.set_password_with_history(&mut conn, "First123!", 3)
+                .await,
+            Err(PasswordError::Reused(3))
+        );
+        assert_eq!(
+            user.set_password_with_history(&mut conn, "Third123!", 3)
+                .await,
+            Err(PasswordError::Reused(3))
+        );
+        assert!(user.verify_password("Third123!").is_ok());
+
+        user.set_password_with_history(&mut conn, "Unrelated123!", 3)
+            .await
+            .unwrap();
+        assert!(user.verify_password("Unrelated123!").is_ok());
+
+        // history is trimmed to the configured depth
+        let count = query_scalar!(
+            "SELECT count(*) \"count!\" FROM password_history WHERE user_id = $1",
+            user.id
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(count, 3);
+        user.set_password_with_history(&mut conn, "Fifth123!", 3)
+            .await
+            .unwrap();
+        user.set_password_with_history(&mut conn, "First123!", 3)
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn test_sanitize_username() {
+        assert_eq!(sanitize_username("h.potter@hogwart.edu.uk"), "h.potter");
+        assert_eq!(
+            sanitize_username("Harry.Potter+spam@hogwart.edu.uk"),
+            "harry.potter"
+        );
+        assert_eq!(sanitize_username("__h!p#otter@hogwart.edu.uk"), "hpotter");
+        assert_eq!(sanitize_username("hpötter@hogwart.edu.uk"), "hptter");
+        assert_eq!(sanitize_username("!!!@hogwart.edu.uk"), "user");
+        assert_eq!(
+            sanitize_username(&"h".repeat(100)).len(),
+            MAX_USERNAME_LENGTH
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_derive_username(pool: PgPool) {
+        let email = "H.Potter@hogwart.edu.uk";
+        let username = User::derive_username(&pool, email).await.unwrap();
+        assert_eq!(username, "h.potter");
+
+        User::new(username.as_str(), None, "Potter", "Harry", email, None)
+            .unwrap()
+            .save(&pool)
+            .await
+            .unwrap();
+        let username = User::derive_username(&pool, "h.potter@gmail.com")
+            .await
+            .unwrap();
+        assert_eq!(username, "h.potter2");
+
+        User::new(
+            username.as_str(),
+            None,
+            "Potter",
+            "Henry",
+            "h.potter@gmail.com",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+        let username = User::derive_username(&pool, "h.potter+other@gmail.com")
+            .await
+            .unwrap();
+        assert_eq!(username, "h.potter3");
+
+        // suffix doesn't exceed the length limit
+        let long_email = format!("{}@hogwart.edu.uk", "h".repeat(70));
+        let username = User::derive_username(&pool, &long_email).await.unwrap();
+        User::new(
+            username.as_str(),
+            None,
+            "Potter",
+            "Harry",
+            long_email.as_str(),
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+        let username = User::derive_username(&pool, &long_email).await.unwrap();
+        assert_eq!(username.len(), MAX_USERNAME_LENGTH);
+        assert!(username.ends_with('2'));
+    }
+
+    #[sqlx::test]
+    async fn test_user_suspension(pool: PgPool) {
+        let (wg_tx, _wg_rx) = tokio::sync::broadcast::channel(16);
+        let mut harry = User::new(
+            "hpotter",
+            Some("Pass123!"),
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+        let mut ron = User::new(
+            "rweasley",
+            Some("Pass123!"),
+            "Weasley",
+            "Ronald",
+            "r.weasley@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+        assert!(!harry.is_suspended());
+
+        let now = Utc::now().naive_utc();
+        let mut transaction = pool.begin().await.unwrap();
+        harry
+            .suspend(&mut transaction, now + TimeDelta::hours(1), &wg_tx)
+            .await
+            .unwrap();
+        ron.suspend(&mut transaction, now + TimeDelta::hours(1), &wg_tx)
+            .await
+            .unwrap();
+        transaction.commit().await.unwrap();
+        assert!(harry.is_suspended());
+        assert!(harry.is_active);
+
+        // expired suspension is no longer in effect, even before it's lifted
+        harry.suspended_until = Some(now - TimeDelta::minutes(1));
+        harry.save(&pool).await.unwrap();
+        assert!(!harry.is_suspended());
+        assert_eq!(harry.active_suspension(), None);
+
+        User::lift_expired_suspensions(&pool, &wg_tx).await.unwrap();
+        let harry = User::find_by_id(&pool, harry.id).await.unwrap().unwrap();
+        assert_eq!(harry.suspended_until, None);
+        let ron = User::find_by_id(&pool, ron.id).await.unwrap().unwrap();
+        assert!(ron.is_suspended());
+    }
+
+    #[sqlx::test]
+    async fn test_password_expiry(pool: PgPool) {
+        let max_age = Duration::from_secs(90 * 24 * 60 * 60);
+        let mut user = User::new(
+            "hpotter",
+            Some("Pass123!"),
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+        assert!(!user.password_expired(max_age));
+        assert_eq!(user.days_until_password_expiry(max_age), 89);
+
+        user.password_changed_at = Utc::now().naive_utc() - TimeDelta::days(100);
+        assert!(user.password_expired(max_age));
+        assert_eq!(user.days_until_password_expiry(max_age), -10);
+
+        // setting a new password resets the expiry
+        let mut conn = pool.acquire().await.unwrap();
+        user.set_password(&mut conn, "NewPass123!").await.unwrap();
+        assert!(!user.password_expired(max_age));
+
+        // users without a password never expire
+        let mut user = User::new(
+            "hpotter",
+            None,
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap();
+        user.password_changed_at = Utc::now().naive_utc() - TimeDelta::days(100);
+        assert!(!user.password_expired(max_age));
+    }
+
+    #[sqlx::test]
+    async fn test_ssh_principals(pool: PgPool) {
+        let user = User::new(
+            "hpotter",
+            Some("Pass123!"),
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+        let mut gryffindor = Group::new("gryffindor");
+        gryffindor.ssh_principal = Some("lions".into());
+        let gryffindor = gryffindor.save(&pool).await.unwrap();
+        let quidditch = Group::new("quidditch").save(&pool).await.unwrap();
+        Group::new("slytherin").save(&pool).await.unwrap();
+        user.add_to_group(&pool, &gryffindor).await.unwrap();
+        user.add_to_group(&pool, &quidditch).await.unwrap();
+
+        let principals = user.ssh_principals(&pool).await.unwrap();
+        assert_eq!(principals, ["lions", "quidditch"]);
+        assert_eq!(gryffindor.ssh_principal_name(), "lions");
+        assert_eq!(quidditch.ssh_principal_name(), "quidditch");
+    }
+
+    #[sqlx::test]
+    async fn test_find_by_username_with_relations(pool: PgPool) {
+        assert!(User::find_by_username_with_relations(&pool, "hpotter")
+            .await
+            .unwrap()
+            .is_none());
+
+        let mut user = User::new(
+            "hpotter",
+            Some("Pass123!"),
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+        user.new_totp_secret(&pool).await.unwrap();
+        let code = current_totp_code(&user);
+        assert!(user.confirm_totp_enroll(&pool, &code).await.unwrap());
+        for name in ["quidditch", "gryffindor"] {
+            let group = Group::new(name).save(&pool).await.unwrap();
+            user.add_to_group(&pool, &group).await.unwrap();
+        }
+        for i in 0..2 {
+            Device::new(
+                format!("device-{i}"),
+                format!("key-{i}"),
+                user.id,
+                DeviceType::User,
+                None,
+                true,
+            )
+            .save(&pool)
+            .await
+            .unwrap();
+        }
+
+        let result = User::find_by_username_with_relations(&pool, "hpotter")
+            .await
+            .unwrap()
+            .unwrap();
+        let user = User::find_by_username(&pool, "hpotter")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(result.user, user);
+        let mut groups = user.member_of_names(&pool).await.unwrap();
+        groups.sort();
+        assert_eq!(result.groups, groups);
+        assert_eq!(
+            result.device_count,
+            user.devices(&pool).await.unwrap().len() as i64
+        );
+        assert_eq!(
+            Some(result.mfa_info),
+            MFAInfo::for_user(&pool, &user).await.unwrap()
+        );
+    }
 }
@@ -2,7 +2,7 @@ use model_derive::Model;
 use sqlx::{query, query_as, query_scalar, Error as SqlxError, PgExecutor, PgPool};
 use webauthn_rs::prelude::Passkey;
 
-use super::error::ModelError;
+use super::{error::ModelError, SecurityKeyOwnerInfo};
 use crate::db::{Id, NoId};
 
 #[derive(Model)]
@@ -61,6 +61,50 @@ impl WebAuthn<Id> {
         .await
     }
 
+    /// Number of security keys registered by a given user.
+    pub async fn count_for_user<'e, E>(executor: E, user_id: Id) -> Result<i64, SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
+        query_scalar!(
+            "SELECT COUNT(*) \"count!\" FROM webauthn WHERE user_id = $1",
+            user_id
+        )
+        .fetch_one(executor)
+        .await
+    }
+
+    /// Number of security keys registered by all users.
+    pub async fn count<'e, E>(executor: E) -> Result<i64, SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
+        query_scalar!("SELECT COUNT(*) \"count!\" FROM webauthn")
+            .fetch_one(executor)
+            .await
+    }
+
+    /// Fetch security keys of all users along with owners' usernames, ordered by owner and key name.
+    pub async fn all_with_owners<'e, E>(
+        executor: E,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<SecurityKeyOwnerInfo>, SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
+        query_as!(
+            SecurityKeyOwnerInfo,
+            "SELECT w.id, w.name, w.user_id, u.username FROM webauthn w \
+            JOIN \"user\" u ON u.id = w.user_id \
+            ORDER BY u.username, w.name, w.id LIMIT $1 OFFSET $2",
+            limit,
+            offset
+        )
+        .fetch_all(executor)
+        .await
+    }
+
     /// Delete all for a given user.
     pub async fn delete_all_for_user<'e, E>(executor: E, user_id: Id) -> Result<(), SqlxError>
     where
@@ -72,3 +116,59 @@ impl WebAuthn<Id> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::User;
+
+    #[sqlx::test]
+    async fn test_all_with_owners(pool: PgPool) {
+        let mut owners = Vec::new();
+        for (username, email) in [
+            ("hpotter", "h.potter@hogwart.edu.uk"),
+            ("rweasley", "r.weasley@hogwart.edu.uk"),
+        ] {
+            let user = User::new(username, None, "Last", "First", email, None)
+                .unwrap()
+                .save(&pool)
+                .await
+                .unwrap();
+            owners.push(user);
+        }
+        for (user, name) in [
+            (&owners[0], "yubikey"),
+            (&owners[1], "solokey"),
+            (&owners[1], "titan"),
+        ] {
+            WebAuthn {
+                id: NoId,
+                user_id: user.id,
+                name: name.into(),
+                passkey: vec![1, 2, 3],
+            }
+            .save(&pool)
+            .await
+            .unwrap();
+        }
+
+        let keys = WebAuthn::all_with_owners(&pool, 10, 0).await.unwrap();
+        let keys: Vec<_> = keys
+            .iter()
+            .map(|key| (key.username.as_str(), key.name.as_str(), key.user_id))
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                ("hpotter", "yubikey", owners[0].id),
+                ("rweasley", "solokey", owners[1].id),
+                ("rweasley", "titan", owners[1].id),
+            ]
+        );
+
+        let page = WebAuthn::all_with_owners(&pool, 2, 1).await.unwrap();
+        assert_eq!(page.len(), 2);
+        assert_eq!(page[0].name, "solokey");
+        assert_eq!(page[1].name, "titan");
+    }
+}
@@ -1,17 +1,42 @@
+use std::collections::HashMap;
+
+use chrono::NaiveDateTime;
+use hmac::{Hmac, Mac};
 use model_derive::Model;
-use sqlx::{query_as, Error as SqlxError, FromRow, PgPool};
+use reqwest::header::{HeaderName, HeaderValue};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use sqlx::{
+    query_as, query_scalar, types::Json, Error as SqlxError, FromRow, PgConnection, PgExecutor,
+    PgPool,
+};
+use tera::{Context, Tera};
 
-use super::UserInfo;
-use crate::db::{Id, NoId};
+use super::{webhook_delivery::WebHookDelivery, UserInfo};
+use crate::{
+    db::{Id, MFAMethod, NoId, User},
+    hex::to_lower_hex,
+};
 
 /// App events which triggers webhook action
 #[derive(Debug)]
 pub enum AppEvent {
     UserCreated(UserInfo),
-    UserModified(UserInfo),
-    UserDeleted(String),
+    UserModified(UserModifiedData),
+    UserDeleted(UserDeletedData),
     HWKeyProvision(HWKeyUserData),
+    EnrollmentPending(EnrollmentPendingData),
+    EnrollmentCompleted(EnrollmentCompletedData),
+    UserMfaChanged(UserMfaChangedData),
 }
+/// User data of UserDeleted AppEvent. Group membership is gone by the time the event is handled,
+/// so it's captured beforehand.
+#[derive(Debug)]
+pub struct UserDeletedData {
+    pub username: String,
+    pub group_ids: Vec<Id>,
+}
+
 /// User data send on HWKeyProvision AppEvent
 #[derive(Debug, Serialize)]
 pub struct HWKeyUserData {
@@ -22,6 +47,131 @@ pub struct HWKeyUserData {
     pub serial: String,
 }
 
+/// Fields which never leave the server in plain form.
+const REDACTED_FIELDS: [&str; 4] = [
+    "password_hash",
+    "totp_secret",
+    "email_mfa_secret",
+    "recovery_codes",
+];
+const REDACTED_VALUE: &str = "***";
+
+/// Single field changed by user modification.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub old: Value,
+    pub new: Value,
+}
+
+/// User data send on UserModified AppEvent: current user state along with changed fields.
+#[derive(Debug, Serialize)]
+pub struct UserModifiedData {
+    #[serde(flatten)]
+    pub user: UserInfo,
+    pub changes: Vec<FieldChange>,
+}
+
+impl UserModifiedData {
+    #[must_use]
+    pub fn new(old: &UserInfo, new: UserInfo) -> Self {
+        let changes = diff_fields(&json!(old), &json!(new));
+        Self { user: new, changes }
+    }
+}
+
+/// Compare top-level fields of two JSON objects.
+fn diff_fields(old: &Value, new: &Value) -> Vec<FieldChange> {
+    let (Some(old), Some(new)) = (old.as_object(), new.as_object()) else {
+        return Vec::new();
+    };
+    new.iter()
+        .filter_map(|(field, new_value)| {
+            let old_value = old.get(field).unwrap_or(&Value::Null);
+            if old_value == new_value {
+                return None;
+            }
+            let change = if REDACTED_FIELDS.contains(&field.as_str()) {
+                FieldChange {
+                    field: field.clone(),
+                    old: REDACTED_VALUE.into(),
+                    new: REDACTED_VALUE.into(),
+                }
+            } else {
+                FieldChange {
+                    field: field.clone(),
+                    old: old_value.clone(),
+                    new: new_value.clone(),
+                }
+            };
+            Some(change)
+        })
+        .collect()
+}
+
+/// User data send on EnrollmentPending AppEvent
+#[derive(Debug, Serialize)]
+pub struct EnrollmentPendingData {
+    pub username: String,
+    pub email: String,
+    pub expires_at: NaiveDateTime,
+    // seconds until enrollment token expires
+    pub expires_in: i64,
+}
+
+/// User data send on EnrollmentCompleted AppEvent
+#[derive(Debug, Serialize)]
+pub struct EnrollmentCompletedData {
+    pub user_id: Id,
+    pub username: String,
+    pub completed_at: NaiveDateTime,
+}
+
+/// MFA configuration of a user.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MfaState {
+    pub mfa_enabled: bool,
+    pub mfa_method: MFAMethod,
+    pub totp_enabled: bool,
+    pub email_mfa_enabled: bool,
+}
+
+impl<I> From<&User<I>> for MfaState {
+    fn from(user: &User<I>) -> Self {
+        Self {
+            mfa_enabled: user.mfa_enabled,
+            mfa_method: user.mfa_method.clone(),
+            totp_enabled: user.totp_enabled,
+            email_mfa_enabled: user.email_mfa_enabled,
+        }
+    }
+}
+
+/// User data send on UserMfaChanged AppEvent: current MFA state along with the previous one,
+/// so that receivers can tell e.g. MFA downgrades apart.
+#[derive(Debug, Serialize)]
+pub struct UserMfaChangedData {
+    pub user_id: Id,
+    pub username: String,
+    #[serde(flatten)]
+    pub state: MfaState,
+    pub previous: MfaState,
+}
+
+impl UserMfaChangedData {
+    /// Returns `None` if MFA state of the user hasn't changed.
+    #[must_use]
+    pub fn new(user: &User<Id>, previous: MfaState) -> Option<Self> {
+        let state = MfaState::from(user);
+        (state != previous).then(|| Self {
+            user_id: user.id,
+            username: user.username.clone(),
+            state,
+            previous,
+        })
+    }
+}
+
 impl AppEvent {
     // Debug name
     #[must_use]
@@ -31,9 +181,43 @@ impl AppEvent {
             Self::UserModified(_) => "user modified",
             Self::UserDeleted(_) => "user deleted",
             Self::HWKeyProvision(_) => "hwkey provisioned",
+            Self::EnrollmentPending(_) => "enrollment pending",
+            Self::EnrollmentCompleted(_) => "enrollment completed",
+            Self::UserMfaChanged(_) => "user MFA changed",
         }
     }
 
+    /// Name of the user the event concerns.
+    #[must_use]
+    pub fn username(&self) -> &str {
+        match self {
+            Self::UserCreated(user) => &user.username,
+            Self::UserModified(data) => &data.user.username,
+            Self::UserDeleted(data) => &data.username,
+            Self::HWKeyProvision(data) => &data.username,
+            Self::EnrollmentPending(data) => &data.username,
+            Self::EnrollmentCompleted(data) => &data.username,
+            Self::UserMfaChanged(data) => &data.username,
+        }
+    }
+
+    /// Ids of groups the user is a member of, used to select webhooks scoped to a group.
+    pub async fn group_ids<'e, E>(&self, executor: E) -> Result<Vec<Id>, SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
+        if let Self::UserDeleted(data) = self {
+            return Ok(data.group_ids.clone());
+        }
+        query_scalar!(
+            "SELECT group_id \"group_id!\" FROM group_user \
+            JOIN \"user\" ON \"user\".id = group_user.user_id WHERE \"user\".username = $1",
+            self.username()
+        )
+        .fetch_all(executor)
+        .await
+    }
+
     /// Database column name.
     #[must_use]
     pub fn column_name(&self) -> &str {
@@ -42,8 +226,149 @@ impl AppEvent {
             Self::UserModified(_) => "on_user_modified",
             Self::UserDeleted(_) => "on_user_deleted",
             Self::HWKeyProvision(_) => "on_hwkey_provision",
+            Self::EnrollmentPending(_) => "on_enrollment_pending",
+            Self::EnrollmentCompleted(_) => "on_enrollment_completed",
+            Self::UserMfaChanged(_) => "on_user_mfa_changed",
+        }
+    }
+
+    /// Webhook payload and event name sent in `x-defguard-event` header.
+    #[must_use]
+    pub fn payload(&self) -> (Value, &'static str) {
+        match self {
+            Self::UserCreated(user) => (json!(user), "user_created"),
+            Self::UserModified(data) => (json!(data), "user_modified"),
+            Self::UserDeleted(data) => (json!({ "username": data.username }), "user_deleted"),
+            Self::HWKeyProvision(data) => (json!(data), "user_keys"),
+            Self::EnrollmentPending(data) => (json!(data), "enrollment_pending"),
+            Self::EnrollmentCompleted(data) => (json!(data), "enrollment_completed"),
+            Self::UserMfaChanged(data) => (json!(data), "user_mfa_changed"),
+        }
+    }
+
+    /// Render webhook body from user-defined template, see [`render_body_template`].
+    pub fn render_body(&self, template: &str) -> Result<String, tera::Error> {
+        let (payload, event) = self.payload();
+        render_body_template(template, event, &payload)
+    }
+}
+
+/// User fields available as body template placeholders. Fields missing from the event payload
+/// (e.g. `email` for deleted user) are rendered as empty strings.
+const TEMPLATE_USER_FIELDS: [&str; 4] = ["username", "email", "first_name", "last_name"];
+
+/// Escape value so it can be safely placed inside a JSON string.
+fn escape_json(input: &str) -> String {
+    let quoted = Value::from(input).to_string();
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+/// Render custom webhook body.
+///
+/// Available placeholders: `{{event}}`, `{{username}}`, `{{email}}`, `{{first_name}}`,
+/// `{{last_name}}` and `{{payload}}` (default JSON payload). Values are JSON-escaped and meant
+/// to be used inside JSON strings, e.g. `{"text": "User {{username}} has been created"}`.
+fn render_body_template(
+    template: &str,
+    event: &str,
+    payload: &Value,
+) -> Result<String, tera::Error> {
+    let mut context = Context::new();
+    context.insert("event", event);
+    for field in TEMPLATE_USER_FIELDS {
+        let value = payload
+            .get(field)
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        context.insert(field, value);
+    }
+    context.insert("payload", &payload.to_string());
+
+    let mut tera = Tera::default();
+    tera.autoescape_on(vec![".json"]);
+    tera.set_escape_fn(escape_json);
+    tera.add_raw_template("body.json", template)?;
+    tera.render("body.json", &context)
+}
+
+/// Check that body template uses known placeholders only and renders to valid JSON.
+pub fn validate_body_template(template: &str) -> Result<(), String> {
+    let payload = json!({
+        "username": "hpotter",
+        "email": "h.potter@hogwart.edu.uk",
+        "first_name": "Harry \"The Boy Who Lived\"",
+        "last_name": "Potter",
+    });
+    let body = render_body_template(template, "user_created", &payload)
+        .map_err(|err| format!("Invalid body template: {err}"))?;
+    serde_json::from_str::<Value>(&body)
+        .map_err(|err| format!("Body template doesn't render valid JSON: {err}"))?;
+    Ok(())
+}
+
+/// Signature of webhook request sent in `x-defguard-signature` header.
+///
+/// Receivers can verify a request by computing HMAC-SHA256 keyed with the webhook token
+/// over the value of `x-defguard-timestamp` header (Unix time in seconds), a dot, and the raw
+/// request body, e.g. `1700000000.{"event":"user_deleted"}`. The header contains the result
+/// as lowercase hex prefixed with `sha256=`. To prevent replay attacks, receivers should also
+/// reject requests with timestamps too far from their current time.
+#[must_use]
+pub fn sign_body(token: &str, timestamp: i64, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(token.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    format!("sha256={}", to_lower_hex(&mac.finalize().into_bytes()))
+}
+
+/// Headers set by Defguard itself which can't be overridden by custom headers.
+const RESERVED_HEADERS: [&str; 8] = [
+    "authorization",
+    "content-length",
+    "content-type",
+    "host",
+    "transfer-encoding",
+    "x-defguard-event",
+    "x-defguard-signature",
+    "x-defguard-timestamp",
+];
+
+pub const DEFAULT_MAX_ATTEMPTS: i32 = 5;
+pub const DEFAULT_RETRY_BASE_DELAY: i32 = 10;
+const MAX_ATTEMPTS_LIMIT: i32 = 20;
+const RETRY_BASE_DELAY_LIMIT: i32 = 3600;
+
+/// Check that retry policy is within sane limits.
+pub fn validate_retry_policy(max_attempts: i32, retry_base_delay: i32) -> Result<(), String> {
+    if !(1..=MAX_ATTEMPTS_LIMIT).contains(&max_attempts) {
+        return Err(format!(
+            "Maximum attempts must be between 1 and {MAX_ATTEMPTS_LIMIT}"
+        ));
+    }
+    if !(1..=RETRY_BASE_DELAY_LIMIT).contains(&retry_base_delay) {
+        return Err(format!(
+            "Retry base delay must be between 1 and {RETRY_BASE_DELAY_LIMIT} seconds"
+        ));
+    }
+    Ok(())
+}
+
+/// Check that custom headers are valid and don't override reserved headers.
+pub fn validate_custom_headers(headers: &HashMap<String, String>) -> Result<(), String> {
+    for (name, value) in headers {
+        let Ok(header_name) = HeaderName::from_bytes(name.as_bytes()) else {
+            return Err(format!("Invalid header name {name}"));
+        };
+        if RESERVED_HEADERS.contains(&header_name.as_str()) {
+            return Err(format!("Header {name} is reserved"));
+        }
+        if HeaderValue::from_str(value).is_err() {
+            return Err(format!("Invalid value of header {name}"));
         }
     }
+    Ok(())
 }
 
 #[derive(Debug, Deserialize, FromRow, Model, Serialize)]
@@ -57,29 +382,520 @@ pub struct WebHook<I = NoId> {
     pub on_user_deleted: bool,
     pub on_user_modified: bool,
     pub on_hwkey_provision: bool,
+    pub on_enrollment_pending: bool,
+    pub on_enrollment_completed: bool,
+    pub on_user_mfa_changed: bool,
+    // events are sent only for members of this group
+    pub group_id: Option<Id>,
+    // events are not sent until this time
+    pub paused_until: Option<NaiveDateTime>,
+    // additional headers sent with each request
+    #[model(ref)]
+    pub custom_headers: Json<HashMap<String, String>>,
+    // replaces default JSON payload if set
+    pub body_template: Option<String>,
+    // failed deliveries are retried until this many attempts are made
+    pub max_attempts: i32,
+    // seconds before the first retry, doubled after each failed attempt
+    pub retry_base_delay: i32,
+    // archived webhooks are hidden and never triggered, but kept along with their deliveries
+    pub archived: bool,
 }
 
 impl WebHook<Id> {
-    /// Fetch all enabled webhooks.
+    /// Fetch all webhooks which haven't been archived.
+    pub async fn all_active<'e, E>(executor: E) -> Result<Vec<Self>, SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
+        query_as!(
+            Self,
+            "SELECT id, url, description, token, enabled, on_user_created, \
+            on_user_deleted, on_user_modified, on_hwkey_provision, on_enrollment_pending, \
+            on_enrollment_completed, on_user_mfa_changed, group_id, paused_until, \
+            custom_headers \"custom_headers: _\", body_template, max_attempts, retry_base_delay, \
+            archived FROM webhook WHERE NOT archived ORDER BY id"
+        )
+        .fetch_all(executor)
+        .await
+    }
+
+    /// Fetch all enabled webhooks, skipping the ones which are currently paused or archived,
+    /// and the ones scoped to a group the user isn't a member of.
     pub async fn all_enabled(pool: &PgPool, trigger: &AppEvent) -> Result<Vec<Self>, SqlxError> {
         let column_name = trigger.column_name();
+        let group_ids = trigger.group_ids(pool).await?;
         let query = format!(
             "SELECT id, url, description, token, enabled, on_user_created, \
-            on_user_deleted, on_user_modified, on_hwkey_provision FROM webhook \
-            WHERE enabled AND {column_name}"
+            on_user_deleted, on_user_modified, on_hwkey_provision, on_enrollment_pending, \
+            on_enrollment_completed, on_user_mfa_changed, group_id, paused_until, custom_headers, \
+            body_template, max_attempts, retry_base_delay, archived FROM webhook \
+            WHERE enabled AND NOT archived AND {column_name} AND (paused_until IS NULL OR paused_until <= now()) \
+            AND (group_id IS NULL OR group_id = ANY($1))"
         );
-        query_as(&query).fetch_all(pool).await
+        query_as(&query).bind(group_ids).fetch_all(pool).await
+    }
+
+    /// Archive webhook, so it's hidden and no longer triggered. Its pending deliveries are
+    /// discarded, while the delivery log is kept for audit.
+    pub async fn archive(&mut self, pool: &PgPool) -> Result<(), SqlxError> {
+        let mut transaction = pool.begin().await?;
+        self.archived = true;
+        self.save(&mut *transaction).await?;
+        WebHookDelivery::discard_for_webhook(&mut *transaction, self.id).await?;
+        transaction.commit().await
+    }
+
+    /// Disable and archive webhooks scoped to given group, so that the group can be removed
+    /// without the webhooks being triggered for all users. Group scope is cleared, as archived
+    /// webhooks are never triggered anyway. Returns ids of the archived webhooks.
+    pub async fn archive_for_group(
+        transaction: &mut PgConnection,
+        group_id: Id,
+    ) -> Result<Vec<Id>, SqlxError> {
+        let ids = query_scalar!(
+            "UPDATE webhook SET enabled = false, archived = true, group_id = NULL \
+            WHERE group_id = $1 RETURNING id",
+            group_id
+        )
+        .fetch_all(&mut *transaction)
+        .await?;
+        for id in &ids {
+            WebHookDelivery::discard_for_webhook(&mut *transaction, *id).await?;
+        }
+
+        Ok(ids)
+    }
+
+    /// Find [`WebHook`] by id, skipping archived ones.
+    pub async fn find_active_by_id<'e, E>(executor: E, id: Id) -> Result<Option<Self>, SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
+        query_as!(
+            Self,
+            "SELECT id, url, description, token, enabled, on_user_created, \
+            on_user_deleted, on_user_modified, on_hwkey_provision, on_enrollment_pending, \
+            on_enrollment_completed, on_user_mfa_changed, group_id, paused_until, \
+            custom_headers \"custom_headers: _\", body_template, max_attempts, retry_base_delay, \
+            archived FROM webhook WHERE id = $1 AND NOT archived",
+            id
+        )
+        .fetch_optional(executor)
+        .await
     }
 
-    /// Find [`WebHook`] by URL.
-    pub async fn find_by_url(pool: &PgPool, url: &str) -> Result<Option<Self>, SqlxError> {
+    /// Find [`WebHook`] by URL, skipping archived ones.
+    pub async fn find_by_url<'e, E>(executor: E, url: &str) -> Result<Option<Self>, SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
         query_as!(
             Self,
             "SELECT id, url, description, token, enabled, on_user_created, \
-            on_user_deleted, on_user_modified, on_hwkey_provision FROM webhook WHERE url = $1",
+            on_user_deleted, on_user_modified, on_hwkey_provision, on_enrollment_pending, \
+            on_enrollment_completed, on_user_mfa_changed, group_id, paused_until, \
+            custom_headers \"custom_headers: _\", body_template, max_attempts, retry_base_delay, \
+            archived FROM webhook WHERE url = $1 AND NOT archived",
             url
         )
-        .fetch_optional(pool)
+        .fetch_optional(executor)
+        .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{TimeDelta, Utc};
+
+    use super::*;
+    use crate::db::Group;
+
+    fn user_info() -> UserInfo {
+        UserInfo {
+            id: 1,
+            username: "hpotter".into(),
+            last_name: "Potter".into(),
+            first_name: "Harry".into(),
+            email: "h.potter@hogwart.edu.uk".into(),
+            phone: None,
+            mfa_enabled: false,
+            totp_enabled: false,
+            email_mfa_enabled: false,
+            groups: vec!["gryffindor".into()],
+            mfa_method: MFAMethod::None,
+            authorized_apps: Vec::new(),
+            is_active: true,
+            suspended_until: None,
+            password_expired: false,
+            days_until_password_expiry: None,
+            must_change_password: false,
+            enrolled: true,
+            is_admin: false,
+        }
+    }
+
+    #[test]
+    fn test_user_modified_payload() {
+        let old = user_info();
+        let mut new = user_info();
+        new.email = "harry@hogwart.edu.uk".into();
+        new.phone = Some("123456789".into());
+
+        let (payload, event) = AppEvent::UserModified(UserModifiedData::new(&old, new)).payload();
+        assert_eq!(event, "user_modified");
+        // user fields are still at the top level
+        assert_eq!(payload["username"], "hpotter");
+        assert_eq!(payload["email"], "harry@hogwart.edu.uk");
+
+        let mut changes = payload["changes"].as_array().unwrap().clone();
+        changes.sort_by_key(|change| change["field"].as_str().unwrap().to_string());
+        assert_eq!(
+            changes,
+            [
+                json!({"field": "email", "old": "h.potter@hogwart.edu.uk", "new": "harry@hogwart.edu.uk"}),
+                json!({"field": "phone", "old": null, "new": "123456789"}),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_custom_headers() {
+        let mut headers = HashMap::from([("X-Api-Key".to_string(), "secret".to_string())]);
+        assert!(validate_custom_headers(&headers).is_ok());
+
+        for reserved in [
+            "Host",
+            "content-length",
+            "Authorization",
+            "X-Defguard-Signature",
+        ] {
+            headers.insert(reserved.into(), "value".into());
+            assert!(validate_custom_headers(&headers).is_err());
+            headers.remove(reserved);
+        }
+
+        headers.insert("X-Invalid Name".into(), "value".into());
+        assert!(validate_custom_headers(&headers).is_err());
+        headers.remove("X-Invalid Name");
+
+        headers.insert("X-Multiline".into(), "line\nbreak".into());
+        assert!(validate_custom_headers(&headers).is_err());
+    }
+
+    #[test]
+    fn test_sign_body() {
+        let body = r#"{"event":"user_deleted"}"#;
+        assert_eq!(
+            sign_body("1234567890", 1_700_000_000, body),
+            "sha256=f02b55134525a1e52f26c1438f4a890db8734c96841977d827268662b5f04f95"
+        );
+        assert_ne!(
+            sign_body("1234567890", 1_700_000_001, body),
+            sign_body("1234567890", 1_700_000_000, body)
+        );
+    }
+
+    #[test]
+    fn test_diff_redacts_secrets() {
+        let old = json!({"username": "hpotter", "totp_secret": "old"});
+        let new = json!({"username": "hpotter", "totp_secret": "new"});
+        assert_eq!(
+            diff_fields(&old, &new),
+            [FieldChange {
+                field: "totp_secret".into(),
+                old: REDACTED_VALUE.into(),
+                new: REDACTED_VALUE.into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_render_body_template() {
+        let template = r#"{"text": "{{event}}: {{first_name}} {{last_name}} <{{email}}>"}"#;
+        assert!(validate_body_template(template).is_ok());
+
+        let mut user = user_info();
+        user.first_name = "Harry \"The Boy Who Lived\"".into();
+        let body = AppEvent::UserCreated(user).render_body(template).unwrap();
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body,
+            json!({"text": "user_created: Harry \"The Boy Who Lived\" Potter <h.potter@hogwart.edu.uk>"})
+        );
+
+        // missing fields are rendered empty
+        let body = AppEvent::UserDeleted(UserDeletedData {
+            username: "hpotter".into(),
+            group_ids: Vec::new(),
+        })
+        .render_body(r#"{"user": "{{username}}", "email": "{{email}}"}"#)
+        .unwrap();
+        assert_eq!(body, r#"{"user": "hpotter", "email": ""}"#);
+
+        // default payload can be embedded as a string
+        let body = AppEvent::UserDeleted(UserDeletedData {
+            username: "hpotter".into(),
+            group_ids: Vec::new(),
+        })
+        .render_body(r#"{"data": "{{payload}}"}"#)
+        .unwrap();
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["data"], r#"{"username":"hpotter"}"#);
+
+        // unknown placeholder
+        assert!(validate_body_template(r#"{"text": "{{password}}"}"#).is_err());
+        // not a JSON
+        assert!(validate_body_template("User {{username}} created").is_err());
+        assert!(validate_body_template(r#"{"text": {{username}}}"#).is_err());
+    }
+
+    #[sqlx::test]
+    async fn test_user_mfa_changed_data(pool: PgPool) {
+        let mut user = User::new(
+            "hpotter",
+            Some("pass123"),
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+        assert!(UserMfaChangedData::new(&user, MfaState::from(&user)).is_none());
+
+        let previous = MfaState::from(&user);
+        user.mfa_enabled = true;
+        user.totp_enabled = true;
+        user.mfa_method = MFAMethod::OneTimePassword;
+        let data = UserMfaChangedData::new(&user, previous).unwrap();
+        let (payload, event) = AppEvent::UserMfaChanged(data).payload();
+        assert_eq!(event, "user_mfa_changed");
+        assert_eq!(
+            payload,
+            json!({
+                "user_id": user.id,
+                "username": "hpotter",
+                "mfa_enabled": true,
+                "mfa_method": "OneTimePassword",
+                "totp_enabled": true,
+                "email_mfa_enabled": false,
+                "previous": {
+                    "mfa_enabled": false,
+                    "mfa_method": "None",
+                    "totp_enabled": false,
+                    "email_mfa_enabled": false,
+                },
+            })
+        );
+
+        // downgrade
+        let previous = MfaState::from(&user);
+        user.disable_mfa(&pool).await.unwrap();
+        let data = UserMfaChangedData::new(&user, previous).unwrap();
+        assert!(!data.state.mfa_enabled);
+        assert_eq!(data.state.mfa_method, MFAMethod::None);
+        assert!(data.previous.mfa_enabled);
+    }
+
+    #[sqlx::test]
+    async fn test_paused_webhook(pool: PgPool) {
+        let mut webhook = WebHook {
+            id: NoId,
+            url: "http://localhost:3000/trigger-happy".into(),
+            description: "Test".into(),
+            token: "1234567890".into(),
+            enabled: true,
+            on_user_created: false,
+            on_user_deleted: true,
+            on_user_modified: false,
+            on_hwkey_provision: false,
+            on_enrollment_pending: false,
+            on_enrollment_completed: false,
+            on_user_mfa_changed: false,
+            group_id: None,
+            paused_until: Some((Utc::now() + TimeDelta::hours(1)).naive_utc()),
+            custom_headers: Json(HashMap::new()),
+            body_template: None,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            retry_base_delay: DEFAULT_RETRY_BASE_DELAY,
+            archived: false,
+        }
+        .save(&pool)
+        .await
+        .unwrap();
+        let event = AppEvent::UserDeleted(UserDeletedData {
+            username: "hpotter".into(),
+            group_ids: Vec::new(),
+        });
+
+        // paused webhook is skipped
+        let webhooks = WebHook::all_enabled(&pool, &event).await.unwrap();
+        assert!(webhooks.is_empty());
+
+        // webhook resumes once the pause is over
+        webhook.paused_until = Some((Utc::now() - TimeDelta::minutes(1)).naive_utc());
+        webhook.save(&pool).await.unwrap();
+        let webhooks = WebHook::all_enabled(&pool, &event).await.unwrap();
+        assert_eq!(webhooks.len(), 1);
+        assert_eq!(webhooks[0].id, webhook.id);
+
+        webhook.paused_until = None;
+        webhook.save(&pool).await.unwrap();
+        let webhooks = WebHook::all_enabled(&pool, &event).await.unwrap();
+        assert_eq!(webhooks.len(), 1);
+    }
+
+    #[sqlx::test]
+    async fn test_archived_webhook(pool: PgPool) {
+        let mut webhook = WebHook {
+            id: NoId,
+            url: "http://localhost:3000/archived".into(),
+            description: "Archived".into(),
+            token: "1234567890".into(),
+            enabled: true,
+            on_user_created: false,
+            on_user_deleted: true,
+            on_user_modified: false,
+            on_hwkey_provision: false,
+            on_enrollment_pending: false,
+            on_enrollment_completed: false,
+            on_user_mfa_changed: false,
+            group_id: None,
+            paused_until: None,
+            custom_headers: Json(HashMap::new()),
+            body_template: None,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            retry_base_delay: DEFAULT_RETRY_BASE_DELAY,
+            archived: false,
+        }
+        .save(&pool)
+        .await
+        .unwrap();
+        let event = AppEvent::UserDeleted(UserDeletedData {
+            username: "hpotter".into(),
+            group_ids: Vec::new(),
+        });
+        assert_eq!(WebHook::all_active(&pool).await.unwrap().len(), 1);
+        assert_eq!(WebHook::all_enabled(&pool, &event).await.unwrap().len(), 1);
+
+        // archived webhook is hidden and skipped, but still stored
+        webhook.archived = true;
+        webhook.save(&pool).await.unwrap();
+        assert!(WebHook::all_active(&pool).await.unwrap().is_empty());
+        assert!(WebHook::all_enabled(&pool, &event)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(WebHook::find_by_id(&pool, webhook.id)
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[sqlx::test]
+    async fn test_group_scoped_webhook(pool: PgPool) {
+        let contractors = Group::new("contractors").save(&pool).await.unwrap();
+        let harry = User::new("hpotter", None, "Potter", "Harry", "", None)
+            .unwrap()
+            .save(&pool)
+            .await
+            .unwrap();
+        harry.add_to_group(&pool, &contractors).await.unwrap();
+        let ron = User::new("rweasley", None, "Weasley", "Ron", "", None)
+            .unwrap()
+            .save(&pool)
+            .await
+            .unwrap();
+
+        let webhook = WebHook {
+            id: NoId,
+            url: "http://localhost:3000/contractors".into(),
+            description: "Contractors".into(),
+            token: "1234567890".into(),
+            enabled: true,
+            on_user_created: false,
+            on_user_deleted: true,
+            on_user_modified: false,
+            on_hwkey_provision: false,
+            on_enrollment_pending: false,
+            on_enrollment_completed: true,
+            on_user_mfa_changed: false,
+            group_id: Some(contractors.id),
+            paused_until: None,
+            custom_headers: Json(HashMap::new()),
+            body_template: None,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            retry_base_delay: DEFAULT_RETRY_BASE_DELAY,
+            archived: false,
+        }
+        .save(&pool)
         .await
+        .unwrap();
+        let completed = |user: &User<Id>| {
+            AppEvent::EnrollmentCompleted(EnrollmentCompletedData {
+                user_id: user.id,
+                username: user.username.clone(),
+                completed_at: Utc::now().naive_utc(),
+            })
+        };
+
+        // only members of the group trigger the webhook
+        let webhooks = WebHook::all_enabled(&pool, &completed(&harry))
+            .await
+            .unwrap();
+        assert_eq!(webhooks.len(), 1);
+        assert_eq!(webhooks[0].id, webhook.id);
+        assert!(WebHook::all_enabled(&pool, &completed(&ron))
+            .await
+            .unwrap()
+            .is_empty());
+
+        // membership of deleted user is captured with the event
+        let group_ids = vec![contractors.id];
+        harry.delete(&pool).await.unwrap();
+        let deleted = AppEvent::UserDeleted(UserDeletedData {
+            username: "hpotter".into(),
+            group_ids,
+        });
+        assert_eq!(
+            WebHook::all_enabled(&pool, &deleted).await.unwrap().len(),
+            1
+        );
+        let deleted = AppEvent::UserDeleted(UserDeletedData {
+            username: "rweasley".into(),
+            group_ids: Vec::new(),
+        });
+        assert!(WebHook::all_enabled(&pool, &deleted)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // group can't be removed while webhooks are scoped to it
+        let mut transaction = pool.begin().await.unwrap();
+        let group = Group::find_by_id(&pool, contractors.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(group.delete(&mut *transaction).await.is_err());
+        transaction.rollback().await.unwrap();
+
+        // webhooks are archived along with the group instead
+        let mut transaction = pool.begin().await.unwrap();
+        let archived = WebHook::archive_for_group(&mut transaction, contractors.id)
+            .await
+            .unwrap();
+        assert_eq!(archived, [webhook.id]);
+        contractors.delete(&mut *transaction).await.unwrap();
+        transaction.commit().await.unwrap();
+        let webhook = WebHook::find_by_id(&pool, webhook.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(webhook.archived);
+        assert!(!webhook.enabled);
+        assert!(webhook.group_id.is_none());
     }
 }
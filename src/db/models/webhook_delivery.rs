@@ -0,0 +1,414 @@
+use chrono::{NaiveDateTime, TimeDelta, Utc};
+use model_derive::Model;
+use rand::{thread_rng, Rng};
+use sqlx::{query, query_as, Error as SqlxError, PgExecutor, Type};
+
+use crate::db::{Id, NoId};
+
+/// Upper limit of delay between delivery attempts.
+const MAX_RETRY_DELAY: TimeDelta = TimeDelta::hours(1);
+/// Maximum number of deliveries claimed at once.
+const CLAIM_LIMIT: i64 = 100;
+
+/// Delay before the next attempt after `attempts` failed ones: `base_delay` doubled after each
+/// failure, capped at [`MAX_RETRY_DELAY`]. Random jitter reduces it by up to a half,
+/// so that retries of many deliveries don't hit the receiver at once.
+#[must_use]
+pub fn retry_delay(base_delay: TimeDelta, attempts: i32) -> TimeDelta {
+    let exponent = attempts.clamp(1, 31) - 1;
+    let delay = base_delay
+        .checked_mul(1 << exponent)
+        .map_or(MAX_RETRY_DELAY, |delay| delay.min(MAX_RETRY_DELAY));
+    let millis = delay.num_milliseconds();
+    TimeDelta::milliseconds(thread_rng().gen_range(millis / 2..=millis))
+}
+
+/// Final state of a delivery, see [`WebHookDeliveryLog`].
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize, Type)]
+#[sqlx(type_name = "webhook_delivery_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum WebHookDeliveryStatus {
+    Delivered,
+    // no attempts left
+    Failed,
+    // webhook archived or disabled before the delivery succeeded
+    Discarded,
+}
+
+/// Webhook request which hasn't been delivered yet. Deliveries are persisted, so that retries
+/// survive a restart, and moved to [`WebHookDeliveryLog`] once delivered or given up on.
+#[derive(Debug, Model, Serialize)]
+#[table(webhook_delivery)]
+pub struct WebHookDelivery<I = NoId> {
+    pub id: I,
+    pub webhook_id: Id,
+    // sent in `x-defguard-event` header
+    pub event: String,
+    pub body: String,
+    // number of failed attempts
+    pub attempts: i32,
+    pub next_attempt_at: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+}
+
+impl WebHookDelivery {
+    /// New delivery, claimed for `lease` by the caller which is about to attempt it.
+    #[must_use]
+    pub fn new<S: Into<String>>(webhook_id: Id, event: S, body: String, lease: TimeDelta) -> Self {
+        let now = Utc::now().naive_utc();
+        Self {
+            id: NoId,
+            webhook_id,
+            event: event.into(),
+            body,
+            attempts: 0,
+            next_attempt_at: now + lease,
+            created_at: now,
+        }
+    }
+}
+
+/// Finished delivery. The log is kept after the webhook is archived, and removed only when
+/// the webhook is deleted for good.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct WebHookDeliveryLog {
+    pub id: Id,
+    pub webhook_id: Id,
+    pub event: String,
+    pub body: String,
+    pub status: WebHookDeliveryStatus,
+    // number of attempts made
+    pub attempts: i32,
+    // error of the last attempt of a failed delivery
+    pub error: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub finished_at: NaiveDateTime,
+}
+
+impl WebHookDeliveryLog {
+    /// Fetch finished deliveries of a given webhook, oldest first.
+    pub async fn all_for_webhook<'e, E>(executor: E, webhook_id: Id) -> Result<Vec<Self>, SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
+        query_as!(
+            Self,
+            "SELECT id, webhook_id, event, body, status \"status: _\", attempts, error, created_at, \
+            finished_at FROM webhook_delivery_log WHERE webhook_id = $1 ORDER BY created_at, id",
+            webhook_id
+        )
+        .fetch_all(executor)
+        .await
+    }
+}
+
+impl WebHookDelivery<Id> {
+    /// Fetch all deliveries of a given webhook, oldest first.
+    pub async fn all_for_webhook<'e, E>(executor: E, webhook_id: Id) -> Result<Vec<Self>, SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
+        query_as!(
+            Self,
+            "SELECT id, webhook_id, event, body, attempts, next_attempt_at, created_at \
+            FROM webhook_delivery WHERE webhook_id = $1 ORDER BY created_at, id",
+            webhook_id
+        )
+        .fetch_all(executor)
+        .await
+    }
+
+    /// Fetch deliveries due for an attempt, postponing them by `lease`, so that a delivery
+    /// isn't attempted again while in progress. If the attempt is interrupted, e.g. by
+    /// a restart, the delivery is retried after the lease passes. Deliveries of archived
+    /// webhooks are never claimed.
+    pub async fn claim_due<'e, E>(executor: E, lease: TimeDelta) -> Result<Vec<Self>, SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
+        let now = Utc::now().naive_utc();
+        query_as!(
+            Self,
+            "UPDATE webhook_delivery SET next_attempt_at = $1 \
+            WHERE id IN (SELECT id FROM webhook_delivery WHERE next_attempt_at <= $2 \
+            AND webhook_id NOT IN (SELECT id FROM webhook WHERE archived) \
+            ORDER BY next_attempt_at LIMIT $3 FOR UPDATE SKIP LOCKED) \
+            RETURNING id, webhook_id, event, body, attempts, next_attempt_at, created_at",
+            now + lease,
+            now,
+            CLAIM_LIMIT
+        )
+        .fetch_all(executor)
+        .await
+    }
+
+    /// Move pending deliveries of a webhook to the log as discarded, e.g. when it's archived.
+    pub async fn discard_for_webhook<'e, E>(executor: E, webhook_id: Id) -> Result<u64, SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
+        let result = query!(
+            "WITH discarded AS (DELETE FROM webhook_delivery WHERE webhook_id = $1 \
+            RETURNING webhook_id, event, body, attempts, created_at) \
+            INSERT INTO webhook_delivery_log \
+            (webhook_id, event, body, status, attempts, error, created_at, finished_at) \
+            SELECT webhook_id, event, body, 'discarded', attempts, NULL, created_at, $2 \
+            FROM discarded",
+            webhook_id,
+            Utc::now().naive_utc()
+        )
+        .execute(executor)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Remove the delivery and record its outcome in [`WebHookDeliveryLog`].
+    async fn finish<'e, E>(
+        &self,
+        executor: E,
+        status: WebHookDeliveryStatus,
+        error: Option<&str>,
+    ) -> Result<(), SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
+        query!(
+            "WITH finished AS (DELETE FROM webhook_delivery WHERE id = $1 \
+            RETURNING webhook_id, event, body, created_at) \
+            INSERT INTO webhook_delivery_log \
+            (webhook_id, event, body, status, attempts, error, created_at, finished_at) \
+            SELECT webhook_id, event, body, $2::webhook_delivery_status, $3::integer, $4::text, \
+            created_at, $5 FROM finished",
+            self.id,
+            status as WebHookDeliveryStatus,
+            self.attempts,
+            error,
+            Utc::now().naive_utc()
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    /// Record successful attempt, moving the delivery to the log.
+    pub async fn record_success<'e, E>(&mut self, executor: E) -> Result<(), SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
+        self.attempts += 1;
+        self.finish(executor, WebHookDeliveryStatus::Delivered, None)
+            .await
+    }
+
+    /// Give up the delivery, e.g. because its webhook has been disabled.
+    pub async fn discard<'e, E>(&self, executor: E) -> Result<(), SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
+        self.finish(executor, WebHookDeliveryStatus::Discarded, None)
+            .await
+    }
+
+    /// Record failed attempt and schedule the next one with [`retry_delay`].
+    /// Returns `false` and moves the delivery to the log if there are no attempts left.
+    pub async fn record_failure<'e, E>(
+        &mut self,
+        executor: E,
+        max_attempts: i32,
+        base_delay: TimeDelta,
+        error: &str,
+    ) -> Result<bool, SqlxError>
+    where
+        E: PgExecutor<'e>,
+    {
+        self.attempts += 1;
+        if self.attempts >= max_attempts {
+            self.finish(executor, WebHookDeliveryStatus::Failed, Some(error))
+                .await?;
+            return Ok(false);
+        }
+        self.next_attempt_at = Utc::now().naive_utc() + retry_delay(base_delay, self.attempts);
+        query!(
+            "UPDATE webhook_delivery SET attempts = $2, next_attempt_at = $3 WHERE id = $1",
+            self.id,
+            self.attempts,
+            self.next_attempt_at
+        )
+        .execute(executor)
+        .await?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use sqlx::{types::Json, PgPool};
+
+    use super::*;
+    use crate::db::WebHook;
+
+    #[test]
+    fn test_retry_delay() {
+        let base = TimeDelta::seconds(10);
+        for (attempts, max) in [(1, 10), (2, 20), (3, 40), (4, 80)] {
+            let delay = retry_delay(base, attempts);
+            assert!(delay >= TimeDelta::seconds(max) / 2);
+            assert!(delay <= TimeDelta::seconds(max));
+        }
+        assert!(retry_delay(base, 30) <= MAX_RETRY_DELAY);
+        assert!(retry_delay(base, i32::MAX) <= MAX_RETRY_DELAY);
+    }
+
+    #[sqlx::test]
+    async fn test_delivery_retries(pool: PgPool) {
+        let webhook = WebHook {
+            id: NoId,
+            url: "http://localhost:3000/retry".into(),
+            description: "Retry".into(),
+            token: "1234567890".into(),
+            enabled: true,
+            on_user_created: true,
+            on_user_deleted: false,
+            on_user_modified: false,
+            on_hwkey_provision: false,
+            on_enrollment_pending: false,
+            on_enrollment_completed: false,
+            on_user_mfa_changed: false,
+            group_id: None,
+            paused_until: None,
+            custom_headers: Json(HashMap::new()),
+            body_template: None,
+            max_attempts: 3,
+            retry_base_delay: 10,
+            archived: false,
+        }
+        .save(&pool)
+        .await
+        .unwrap();
+
+        let lease = TimeDelta::minutes(1);
+        let mut delivery = WebHookDelivery::new(webhook.id, "user_created", "{}".into(), lease)
+            .save(&pool)
+            .await
+            .unwrap();
+        // claimed by its creator
+        assert!(WebHookDelivery::claim_due(&pool, lease)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // overdue delivery, e.g. left over after restart
+        delivery.next_attempt_at = Utc::now().naive_utc() - TimeDelta::seconds(1);
+        delivery.save(&pool).await.unwrap();
+        let mut claimed = WebHookDelivery::claim_due(&pool, lease).await.unwrap();
+        assert_eq!(claimed.len(), 1);
+        assert!(claimed[0].next_attempt_at > Utc::now().naive_utc());
+        assert!(WebHookDelivery::claim_due(&pool, lease)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let mut delivery = claimed.remove(0);
+        let base_delay = TimeDelta::seconds(10);
+        assert!(delivery
+            .record_failure(&pool, webhook.max_attempts, base_delay, "status 503")
+            .await
+            .unwrap());
+        assert!(delivery
+            .record_failure(&pool, webhook.max_attempts, base_delay, "status 503")
+            .await
+            .unwrap());
+        let stored = WebHookDelivery::find_by_id(&pool, delivery.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.attempts, 2);
+        assert!(stored.next_attempt_at > Utc::now().naive_utc());
+
+        // no attempts left
+        assert!(!delivery
+            .record_failure(&pool, webhook.max_attempts, base_delay, "status 503")
+            .await
+            .unwrap());
+        assert!(WebHookDelivery::find_by_id(&pool, delivery.id)
+            .await
+            .unwrap()
+            .is_none());
+        let log = WebHookDeliveryLog::all_for_webhook(&pool, webhook.id)
+            .await
+            .unwrap();
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].status, WebHookDeliveryStatus::Failed);
+        assert_eq!(log[0].attempts, 3);
+        assert_eq!(log[0].error.as_deref(), Some("status 503"));
+
+        // successful delivery is logged as well
+        let mut delivery = WebHookDelivery::new(webhook.id, "user_created", "{}".into(), lease)
+            .save(&pool)
+            .await
+            .unwrap();
+        delivery.record_success(&pool).await.unwrap();
+        assert!(WebHookDelivery::find_by_id(&pool, delivery.id)
+            .await
+            .unwrap()
+            .is_none());
+        let log = WebHookDeliveryLog::all_for_webhook(&pool, webhook.id)
+            .await
+            .unwrap();
+        assert_eq!(log.len(), 2);
+        assert_eq!(log[1].status, WebHookDeliveryStatus::Delivered);
+        assert_eq!(log[1].attempts, 1);
+    }
+
+    #[sqlx::test]
+    async fn test_archived_webhook_deliveries(pool: PgPool) {
+        let mut webhook = WebHook {
+            id: NoId,
+            url: "http://localhost:3000/archived".into(),
+            description: "Archived".into(),
+            token: "1234567890".into(),
+            enabled: true,
+            on_user_created: true,
+            on_user_deleted: false,
+            on_user_modified: false,
+            on_hwkey_provision: false,
+            on_enrollment_pending: false,
+            on_enrollment_completed: false,
+            on_user_mfa_changed: false,
+            group_id: None,
+            paused_until: None,
+            custom_headers: Json(HashMap::new()),
+            body_template: None,
+            max_attempts: 3,
+            retry_base_delay: 10,
+            archived: false,
+        }
+        .save(&pool)
+        .await
+        .unwrap();
+        let mut delivery =
+            WebHookDelivery::new(webhook.id, "user_created", "{}".into(), TimeDelta::zero());
+        delivery.next_attempt_at = Utc::now().naive_utc() - TimeDelta::seconds(1);
+        delivery.save(&pool).await.unwrap();
+
+        // pending delivery of archived webhook is discarded, but stays in the log
+        webhook.archive(&pool).await.unwrap();
+        let lease = TimeDelta::minutes(1);
+        assert!(WebHookDelivery::claim_due(&pool, lease)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(WebHookDelivery::all_for_webhook(&pool, webhook.id)
+            .await
+            .unwrap()
+            .is_empty());
+        let log = WebHookDeliveryLog::all_for_webhook(&pool, webhook.id)
+            .await
+            .unwrap();
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].event, "user_created");
+        assert_eq!(log[0].status, WebHookDeliveryStatus::Discarded);
+    }
+}
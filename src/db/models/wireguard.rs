@@ -92,6 +92,10 @@ pub struct WireguardNetwork<I = NoId> {
     pub prvkey: String,
     pub endpoint: String,
     pub dns: Option<String>,
+    /// Search domains appended to the `DNS` line of device configs.
+    #[model(ref)]
+    #[serde(default)]
+    pub dns_search_domains: Vec<String>,
     #[model(ref)]
     #[schema(value_type = String)]
     pub allowed_ips: Vec<IpNetwork>,
@@ -138,6 +142,30 @@ pub enum WireguardNetworkError {
     DeviceError(#[from] DeviceError),
 }
 
+/// Maximum length of a domain name, excluding the trailing dot.
+const MAX_DOMAIN_LENGTH: usize = 253;
+/// Maximum length of a single domain name label.
+const MAX_LABEL_LENGTH: usize = 63;
+
+/// Check that DNS search domains are syntactically valid domain names.
+pub(crate) fn validate_dns_search_domains(domains: &[String]) -> Result<(), String> {
+    for domain in domains {
+        let valid = !domain.is_empty()
+            && domain.len() <= MAX_DOMAIN_LENGTH
+            && domain.split('.').all(|label| {
+                !label.is_empty()
+                    && label.len() <= MAX_LABEL_LENGTH
+                    && !label.starts_with('-')
+                    && !label.ends_with('-')
+                    && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            });
+        if !valid {
+            return Err(format!("Invalid DNS search domain: {domain}"));
+        }
+    }
+    Ok(())
+}
+
 impl WireguardNetwork {
     pub fn new(
         name: String,
@@ -161,6 +189,7 @@ impl WireguardNetwork {
             prvkey: BASE64_STANDARD.encode(prvkey.to_bytes()),
             endpoint,
             dns,
+            dns_search_domains: Vec::new(),
             allowed_ips,
             connected_at: None,
             mfa_enabled,
@@ -185,6 +214,24 @@ impl WireguardNetwork {
 }
 
 impl WireguardNetwork<Id> {
+    /// DNS servers followed by search domains, comma-separated as on the `DNS` line of wg-quick
+    /// configs. Desktop clients receive the same list.
+    #[must_use]
+    pub fn dns_entries(&self) -> Option<String> {
+        let entries: Vec<&str> = self
+            .dns
+            .iter()
+            .filter(|dns| !dns.is_empty())
+            .chain(&self.dns_search_domains)
+            .map(String::as_str)
+            .collect();
+        if entries.is_empty() {
+            None
+        } else {
+            Some(entries.join(", "))
+        }
+    }
+
     pub(crate) async fn find_by_name<'e, E>(
         executor: E,
         name: &str,
@@ -194,8 +241,9 @@ impl WireguardNetwork<Id> {
     {
         let networks = query_as!(
             WireguardNetwork,
-            "SELECT id, name, address, port, pubkey, prvkey, endpoint, dns, allowed_ips, \
-            connected_at, mfa_enabled, keepalive_interval, peer_disconnect_threshold \
+            "SELECT id, name, address, port, pubkey, prvkey, endpoint, dns, \
+            dns_search_domains, allowed_ips, connected_at, mfa_enabled, keepalive_interval, \
+            peer_disconnect_threshold \
             FROM wireguard_network WHERE name = $1",
             name
         )
@@ -270,13 +318,14 @@ impl WireguardNetwork<Id> {
                 query_as!(
                 Device,
                 "SELECT DISTINCT ON (d.id) d.id, d.name, d.wireguard_pubkey, d.user_id, d.created, d.description, d.device_type \"device_type: DeviceType\", \
-                configured
+                configured, is_primary
                 FROM device d \
                 JOIN \"user\" u ON d.user_id = u.id \
                 JOIN group_user gu ON u.id = gu.user_id \
                 JOIN \"group\" g ON gu.group_id = g.id \
                 WHERE g.\"name\" IN (SELECT * FROM UNNEST($1::text[])) \
                 AND u.is_active = true \
+                AND (u.suspended_until IS NULL OR u.suspended_until <= NOW()) \
                 AND d.device_type = 'user'::device_type \
                 ORDER BY d.id ASC",
                 &allowed_groups
@@ -289,10 +338,11 @@ impl WireguardNetwork<Id> {
                 query_as!(
                     Device,
                     "SELECT d.id, d.name, d.wireguard_pubkey, d.user_id, d.created, d.description, d.device_type \"device_type: DeviceType\", \
-                    configured \
+                    configured, is_primary \
                     FROM device d \
                     JOIN \"user\" u ON d.user_id = u.id \
                     WHERE u.is_active = true \
+                    AND (u.suspended_until IS NULL OR u.suspended_until <= NOW()) \
                     AND d.device_type = 'user'::device_type \
                     ORDER BY d.id ASC"
                 )
@@ -319,13 +369,14 @@ impl WireguardNetwork<Id> {
                 query_as!(
                 Device,
                 "SELECT DISTINCT ON (d.id) d.id, d.name, d.wireguard_pubkey, d.user_id, d.created, d.description, d.device_type \"device_type: DeviceType\", \
-                configured
+                configured, is_primary
                 FROM device d \
                 JOIN \"user\" u ON d.user_id = u.id \
                 JOIN group_user gu ON u.id = gu.user_id \
                 JOIN \"group\" g ON gu.group_id = g.id \
                 WHERE g.\"name\" IN (SELECT * FROM UNNEST($1::text[])) \
                 AND u.is_active = true \
+                AND (u.suspended_until IS NULL OR u.suspended_until <= NOW()) \
                 AND d.device_type = 'user'::device_type \
                 AND d.user_id = $2 \
                 ORDER BY d.id ASC",
@@ -339,10 +390,11 @@ impl WireguardNetwork<Id> {
                 query_as!(
                     Device,
                     "SELECT d.id, d.name, d.wireguard_pubkey, d.user_id, d.created, d.description, d.device_type \"device_type: DeviceType\", \
-                    configured \
+                    configured, is_primary \
                     FROM device d \
                     JOIN \"user\" u ON d.user_id = u.id \
                     WHERE u.is_active = true \
+                    AND (u.suspended_until IS NULL OR u.suspended_until <= NOW()) \
                     AND d.device_type = 'user'::device_type \
                     AND d.user_id = $1 \
                     ORDER BY d.id ASC", user_id
@@ -739,7 +791,7 @@ impl WireguardNetwork<Id> {
     }
 
     /// Finds when the device connected based on handshake timestamps.
-    async fn connected_at(
+    pub(crate) async fn connected_at(
         &self,
         conn: &PgPool,
         device_id: Id,
@@ -907,7 +959,7 @@ impl WireguardNetwork<Id> {
         let devices = query_as!(
             Device,
             "SELECT DISTINCT ON (d.id) d.id, d.name, d.wireguard_pubkey, d.user_id, d.created, \
-            d.description, d.device_type \"device_type: DeviceType\", d.configured \
+            d.description, d.device_type \"device_type: DeviceType\", d.configured, d.is_primary \
             FROM device d JOIN wireguard_peer_stats s ON d.id = s.device_id \
             WHERE s.latest_handshake >= $1 AND s.network = $2 \
             AND d.device_type = $3",
@@ -1061,7 +1113,7 @@ impl WireguardNetwork<Id> {
             Device,
             "SELECT \
                 id, name, wireguard_pubkey, user_id, created, description, device_type \"device_type: DeviceType\", \
-                configured \
+                configured, is_primary \
             FROM device WHERE id in (SELECT device_id FROM wireguard_network_device WHERE wireguard_network_id = $1) \
             AND device_type = $2",
             self.id,
@@ -1084,6 +1136,7 @@ impl Default for WireguardNetwork {
             prvkey: String::default(),
             endpoint: String::default(),
             dns: Option::default(),
+            dns_search_domains: Vec::default(),
             allowed_ips: Vec::default(),
             connected_at: Option::default(),
             mfa_enabled: false,
@@ -1162,6 +1215,39 @@ mod test {
     use super::*;
     use crate::db::Group;
 
+    #[test]
+    fn test_validate_dns_search_domains() {
+        let valid = [
+            "example.com",
+            "corp.example.com",
+            "local",
+            "my-site.example.org",
+        ];
+        assert!(validate_dns_search_domains(&valid.map(String::from)).is_ok());
+        assert!(validate_dns_search_domains(&[]).is_ok());
+
+        for invalid in [
+            "",
+            "example..com",
+            ".example.com",
+            "example.com.",
+            "-example.com",
+            "example-.com",
+            "exa mple.com",
+            "example_com",
+            "10.0.0.1/24",
+        ] {
+            assert!(
+                validate_dns_search_domains(&[invalid.to_string()]).is_err(),
+                "{invalid} should be rejected"
+            );
+        }
+        let long_label = format!("{}.com", "a".repeat(64));
+        assert!(validate_dns_search_domains(&[long_label]).is_err());
+        let long_domain = vec!["a".repeat(60); 5].join(".");
+        assert!(validate_dns_search_domains(&[long_domain]).is_err());
+    }
+
     #[sqlx::test]
     async fn test_connected_at_reconnection(pool: PgPool) {
         let mut network = WireguardNetwork::default();
@@ -1170,12 +1256,13 @@ mod test {
 
         let user = User::new(
             "testuser",
-            Some("hunter2"),
+            Some("Hunter2!x"),
             "Tester",
             "Test",
             "test@test.com",
             None,
         )
+        .unwrap()
         .save(&pool)
         .await
         .unwrap();
@@ -1233,12 +1320,13 @@ mod test {
 
         let user = User::new(
             "testuser",
-            Some("hunter2"),
+            Some("Hunter2!x"),
             "Tester",
             "Test",
             "test@test.com",
             None,
         )
+        .unwrap()
         .save(&pool)
         .await
         .unwrap();
@@ -1294,24 +1382,26 @@ mod test {
 
         let user1 = User::new(
             "user1",
-            Some("pass1"),
+            Some("Password1!"),
             "Test",
             "User1",
             "user1@test.com",
             None,
         )
+        .unwrap()
         .save(&pool)
         .await
         .unwrap();
 
         let user2 = User::new(
             "user2",
-            Some("pass2"),
+            Some("Password2!"),
             "Test",
             "User2",
             "user2@test.com",
             None,
         )
+        .unwrap()
         .save(&pool)
         .await
         .unwrap();
@@ -1382,24 +1472,26 @@ mod test {
 
         let user1 = User::new(
             "user1",
-            Some("pass1"),
+            Some("Password1!"),
             "Test",
             "User1",
             "user1@test.com",
             None,
         )
+        .unwrap()
         .save(&pool)
         .await
         .unwrap();
 
         let user2 = User::new(
             "user2",
-            Some("pass2"),
+            Some("Password2!"),
             "Test",
             "User2",
             "user2@test.com",
             None,
         )
+        .unwrap()
         .save(&pool)
         .await
         .unwrap();
@@ -1463,24 +1555,26 @@ mod test {
 
         let user1 = User::new(
             "testuser1",
-            Some("pass1"),
+            Some("Password1!"),
             "Tester1",
             "Test1",
             "test1@test.com",
             None,
         )
+        .unwrap()
         .save(&pool)
         .await
         .unwrap();
 
         let user2 = User::new(
             "testuser2",
-            Some("pass2"),
+            Some("Password2!"),
             "Tester2",
             "Test2",
             "test2@test.com",
             None,
         )
+        .unwrap()
         .save(&pool)
         .await
         .unwrap();
@@ -1571,36 +1665,39 @@ mod test {
 
         let user1 = User::new(
             "testuser1",
-            Some("pass1"),
+            Some("Password1!"),
             "Tester1",
             "Test1",
             "test1@test.com",
             None,
         )
+        .unwrap()
         .save(&pool)
         .await
         .unwrap();
 
         let user2 = User::new(
             "testuser2",
-            Some("pass2"),
+            Some("Password2!"),
             "Tester2",
             "Test2",
             "test2@test.com",
             None,
         )
+        .unwrap()
         .save(&pool)
         .await
         .unwrap();
 
         let user3 = User::new(
             "testuser3",
-            Some("pass3"),
+            Some("Password3!"),
             "Tester3",
             "Test3",
             "test3@test.com",
             None,
         )
+        .unwrap()
         .save(&pool)
         .await
         .unwrap();
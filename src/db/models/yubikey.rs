@@ -1,14 +1,41 @@
+use chrono::{NaiveDateTime, TimeDelta, Utc};
 use model_derive::Model;
 use sqlx::{query, query_as, PgExecutor};
 
 use crate::db::{Id, NoId};
 
+const MAX_MODEL_LENGTH: usize = 64;
+
 #[derive(Deserialize, Model, Serialize)]
 pub struct YubiKey<I = NoId> {
     pub id: I,
     pub name: String,
     pub serial: String,
     pub user_id: Id,
+    // e.g. "YubiKey 5 NFC", "YubiKey 5C FIPS"
+    pub model: Option<String>,
+    // e.g. "5.4.3"
+    pub firmware_version: Option<String>,
+    // last time the YubiKey was provisioned, re-enabled or reported as used by an SSH server
+    pub last_used_at: Option<NaiveDateTime>,
+    // keys of disabled YubiKeys are left out of SSH authorized keys
+    pub disabled_at: Option<NaiveDateTime>,
+}
+
+/// Loosely validate reported model name: printable ASCII of reasonable length.
+fn is_valid_model(model: &str) -> bool {
+    !model.is_empty()
+        && model.len() <= MAX_MODEL_LENGTH
+        && model.chars().all(|c| c.is_ascii_graphic() || c == ' ')
+}
+
+/// Loosely validate reported firmware version: dot-separated numbers, e.g. "5.4.3".
+fn is_valid_firmware_version(version: &str) -> bool {
+    let parts: Vec<&str> = version.split('.').collect();
+    (2..=4).contains(&parts.len())
+        && parts
+            .iter()
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
 }
 
 impl YubiKey {
@@ -19,8 +46,39 @@ impl YubiKey {
             name,
             serial,
             user_id,
+            model: None,
+            firmware_version: None,
+            last_used_at: Some(Utc::now().naive_utc()),
+            disabled_at: None,
         }
     }
+
+    /// Attach model and firmware version reported during provisioning.
+    /// Values which don't look valid are discarded.
+    #[must_use]
+    pub fn with_metadata(
+        mut self,
+        model: Option<String>,
+        firmware_version: Option<String>,
+    ) -> Self {
+        self.model = model.map(|model| model.trim().to_string()).filter(|model| {
+            let valid = is_valid_model(model);
+            if !valid {
+                warn!("Ignoring invalid YubiKey model: {model}");
+            }
+            valid
+        });
+        self.firmware_version = firmware_version
+            .map(|version| version.trim().to_string())
+            .filter(|version| {
+                let valid = is_valid_firmware_version(version);
+                if !valid {
+                    warn!("Ignoring invalid YubiKey firmware version: {version}");
+                }
+                valid
+            });
+        self
+    }
 }
 
 impl YubiKey<Id> {
@@ -30,13 +88,76 @@ impl YubiKey<Id> {
     {
         query_as!(
             Self,
-            "SELECT id, name, serial, user_id FROM \"yubikey\" WHERE user_id = $1",
+            "SELECT id, name, serial, user_id, model, firmware_version, last_used_at, disabled_at \
+            FROM \"yubikey\" WHERE user_id = $1",
             user_id
         )
         .fetch_all(executor)
         .await
     }
 
+    /// Mark enabled YubiKeys holding given SSH key as used now. SSH keys are stored without
+    /// comments, so `normalized_key` should come from [`SshKeyParts::normalize`].
+    /// Returns number of updated YubiKeys.
+    ///
+    /// [`SshKeyParts::normalize`]: crate::db::models::authentication_key::SshKeyParts::normalize
+    pub async fn mark_used_by_ssh_key<'e, E>(
+        executor: E,
+        normalized_key: &str,
+    ) -> Result<u64, sqlx::Error>
+    where
+        E: PgExecutor<'e>,
+    {
+        let result = query!(
+            "UPDATE \"yubikey\" SET last_used_at = now() \
+            WHERE disabled_at IS NULL AND id IN (SELECT yubikey_id FROM authentication_key \
+            WHERE key_type = 'ssh' AND key = $1)",
+            normalized_key
+        )
+        .execute(executor)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Disable YubiKeys which haven't been used for longer than `threshold`.
+    /// Returns YubiKeys disabled by this call.
+    pub async fn disable_unused<'e, E>(
+        executor: E,
+        threshold: TimeDelta,
+    ) -> Result<Vec<Self>, sqlx::Error>
+    where
+        E: PgExecutor<'e>,
+    {
+        let used_before = Utc::now().naive_utc() - threshold;
+        query_as!(
+            Self,
+            "UPDATE \"yubikey\" SET disabled_at = now() \
+            WHERE disabled_at IS NULL AND (last_used_at IS NULL OR last_used_at < $1) \
+            RETURNING id, name, serial, user_id, model, firmware_version, last_used_at, disabled_at",
+            used_before
+        )
+        .fetch_all(executor)
+        .await
+    }
+
+    /// Re-enable disabled YubiKey. Counts as use, so it isn't disabled again right away.
+    pub async fn enable<'e, E>(&mut self, executor: E) -> Result<(), sqlx::Error>
+    where
+        E: PgExecutor<'e>,
+    {
+        let now = Utc::now().naive_utc();
+        query!(
+            "UPDATE \"yubikey\" SET disabled_at = NULL, last_used_at = $2 WHERE id = $1",
+            self.id,
+            now
+        )
+        .execute(executor)
+        .await?;
+        self.disabled_at = None;
+        self.last_used_at = Some(now);
+        Ok(())
+    }
+
     pub async fn delete_by_id<'e, E>(executor: E, id: Id) -> Result<(), sqlx::Error>
     where
         E: PgExecutor<'e>,
@@ -47,3 +168,169 @@ impl YubiKey<Id> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::db::{
+        models::authentication_key::{AuthenticationKey, AuthenticationKeyType},
+        User,
+    };
+
+    #[test]
+    fn test_yubikey_metadata_validation() {
+        assert!(is_valid_model("YubiKey 5 NFC"));
+        assert!(is_valid_model("YubiKey 5C FIPS"));
+        assert!(!is_valid_model(""));
+        assert!(!is_valid_model("YubiKey\n5"));
+        assert!(!is_valid_model(&"Y".repeat(MAX_MODEL_LENGTH + 1)));
+
+        assert!(is_valid_firmware_version("5.4.3"));
+        assert!(is_valid_firmware_version("4.3"));
+        assert!(!is_valid_firmware_version("5"));
+        assert!(!is_valid_firmware_version("5..3"));
+        assert!(!is_valid_firmware_version("v5.4.3"));
+    }
+
+    #[sqlx::test]
+    async fn test_yubikey_metadata(pool: PgPool) {
+        let user = User::new(
+            "hpotter",
+            Some("Pass123!"),
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+
+        let yubikey = YubiKey::new("YubiKey 1".into(), "12345678".into(), user.id)
+            .with_metadata(Some("YubiKey 5 NFC".into()), Some("5.4.3".into()))
+            .save(&pool)
+            .await
+            .unwrap();
+        let fetched = YubiKey::find_by_id(&pool, yubikey.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(fetched.model.as_deref(), Some("YubiKey 5 NFC"));
+        assert_eq!(fetched.firmware_version.as_deref(), Some("5.4.3"));
+
+        // invalid metadata is not stored
+        YubiKey::new("YubiKey 2".into(), "87654321".into(), user.id)
+            .with_metadata(Some(String::new()), Some("latest".into()))
+            .save(&pool)
+            .await
+            .unwrap();
+        let yubikeys = YubiKey::find_by_user_id(&pool, user.id).await.unwrap();
+        assert_eq!(yubikeys.len(), 2);
+        let second = yubikeys
+            .iter()
+            .find(|key| key.serial == "87654321")
+            .unwrap();
+        assert!(second.model.is_none());
+        assert!(second.firmware_version.is_none());
+    }
+
+    #[sqlx::test]
+    async fn test_disable_unused_yubikeys(pool: PgPool) {
+        let user = User::new(
+            "hpotter",
+            Some("Pass123!"),
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+
+        let mut stale = YubiKey::new("Stale".into(), "12345678".into(), user.id);
+        stale.last_used_at = Some(Utc::now().naive_utc() - TimeDelta::days(100));
+        let stale = stale.save(&pool).await.unwrap();
+        let recent = YubiKey::new("Recent".into(), "87654321".into(), user.id)
+            .save(&pool)
+            .await
+            .unwrap();
+        AuthenticationKey::new(
+            user.id,
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIMgaU2fumArNGBEEmHvIx20M3CDd106QELh/smzgv+uL"
+                .into(),
+            None,
+            AuthenticationKeyType::Ssh,
+            Some(stale.id),
+        )
+        .save(&pool)
+        .await
+        .unwrap();
+        AuthenticationKey::new(
+            user.id,
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIK1ej+W6YY6PDUK2HAiJJ0Ia6WuGfxQf3IojblaIRc+A"
+                .into(),
+            None,
+            AuthenticationKeyType::Ssh,
+            Some(recent.id),
+        )
+        .save(&pool)
+        .await
+        .unwrap();
+
+        let disabled = YubiKey::disable_unused(&pool, TimeDelta::days(90))
+            .await
+            .unwrap();
+        assert_eq!(disabled.len(), 1);
+        assert_eq!(disabled[0].id, stale.id);
+        assert!(disabled[0].disabled_at.is_some());
+
+        let recent = YubiKey::find_by_id(&pool, recent.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(recent.disabled_at.is_none());
+
+        // keys of disabled YubiKey are left out of SSH keys, but not deleted
+        let keys = AuthenticationKey::find_valid_ssh_keys(&pool, user.id, None)
+            .await
+            .unwrap();
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].yubikey_id, Some(recent.id));
+        assert_eq!(
+            AuthenticationKey::find_by_user_id(&pool, user.id, None)
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+
+        // already disabled keys are not reported again
+        assert!(YubiKey::disable_unused(&pool, TimeDelta::days(90))
+            .await
+            .unwrap()
+            .is_empty());
+
+        // record is kept and can be re-enabled
+        let mut stale = YubiKey::find_by_id(&pool, stale.id).await.unwrap().unwrap();
+        assert!(stale.disabled_at.is_some());
+        stale.enable(&pool).await.unwrap();
+        let stale = YubiKey::find_by_id(&pool, stale.id).await.unwrap().unwrap();
+        assert!(stale.disabled_at.is_none());
+        assert_eq!(
+            AuthenticationKey::find_valid_ssh_keys(&pool, user.id, None)
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+        assert!(YubiKey::disable_unused(&pool, TimeDelta::days(90))
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
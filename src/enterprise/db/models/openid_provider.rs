@@ -1,9 +1,28 @@
-use std::fmt;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    net::IpAddr,
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
 
 use model_derive::Model;
-use sqlx::{query, query_as, Error as SqlxError, PgPool, Type};
+use reqwest::Url;
+use sqlx::{query, query_as, types::Json, Error as SqlxError, PgPool, Type};
+use thiserror::Error;
 
-use crate::db::{Id, NoId};
+use crate::{
+    db::{Id, NoId},
+    secret::EncryptedSecret,
+    server_config,
+};
+
+/// Discovery documents by provider's base URL, with the time they were fetched at.
+static DISCOVERY_CACHE: LazyLock<Mutex<HashMap<String, (Instant, ProviderDiscovery)>>> =
+    LazyLock::new(Mutex::default);
+
+/// Upper bound for fetching provider's discovery document and signing keys.
+pub(crate) const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(10);
 
 // The behavior when a user is deleted from the directory
 // Keep: Keep the user, despite being deleted from the external provider's directory
@@ -85,13 +104,199 @@ impl From<String> for DirectorySyncTarget {
     }
 }
 
+// How group claims are applied to membership in mapped groups
+// Additive: users are only added to groups granted by their claims
+// Authoritative: users are also removed from mapped groups not granted by their claims
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupMappingMode {
+    #[default]
+    Additive,
+    Authoritative,
+}
+
+/// Assignment of defguard groups based on groups listed in an ID token claim.
+/// Groups which don't appear in the mapping are never modified.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct GroupMapping {
+    /// Name of the claim listing user's groups at the provider.
+    pub claim: String,
+    /// Claim values mapped to names of defguard groups.
+    pub groups: HashMap<String, String>,
+    pub mode: GroupMappingMode,
+}
+
+impl Default for GroupMapping {
+    fn default() -> Self {
+        Self {
+            claim: "groups".into(),
+            groups: HashMap::new(),
+            mode: GroupMappingMode::default(),
+        }
+    }
+}
+
+impl GroupMapping {
+    /// Names of defguard groups granted by given claim values.
+    #[must_use]
+    pub fn granted_groups(&self, claim_values: &[String]) -> HashSet<&str> {
+        claim_values
+            .iter()
+            .filter_map(|value| self.groups.get(value))
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Names of all defguard groups managed by this mapping.
+    #[must_use]
+    pub fn managed_groups(&self) -> HashSet<&str> {
+        self.groups.values().map(String::as_str).collect()
+    }
+}
+
+/// Endpoints and capabilities of an OpenID provider, as advertised in its discovery document.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct ProviderDiscovery {
+    /// May differ from provider's `base_url`, e.g. for multi-tenant providers.
+    pub issuer: String,
+    pub authorization_endpoint: Url,
+    pub token_endpoint: Url,
+    pub jwks_uri: Url,
+    pub userinfo_endpoint: Option<Url>,
+    pub end_session_endpoint: Option<Url>,
+    #[serde(default)]
+    pub id_token_signing_alg_values_supported: Vec<String>,
+    /// PKCE challenge methods, empty if the provider doesn't advertise PKCE support.
+    #[serde(default)]
+    pub code_challenge_methods_supported: Vec<String>,
+}
+
+impl ProviderDiscovery {
+    /// Discovery document of the provider at `base_url`, cached for `openid_discovery_cache_ttl`.
+    pub async fn fetch(base_url: &str) -> Result<Self, DiscoveryError> {
+        fetch_discovery(base_url, *server_config().openid_discovery_cache_ttl).await
+    }
+
+    /// Check if the provider advertises PKCE with S256 challenge method.
+    #[must_use]
+    pub fn supports_pkce(&self) -> bool {
+        self.code_challenge_methods_supported
+            .iter()
+            .any(|method| method == "S256")
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("Failed to fetch provider metadata from {url}: {source}")]
+pub struct DiscoveryError {
+    url: String,
+    source: reqwest::Error,
+}
+
+/// Fetch discovery document from `base_url`, or return a cached one if it's younger than `ttl`.
+async fn fetch_discovery(
+    base_url: &str,
+    ttl: Duration,
+) -> Result<ProviderDiscovery, DiscoveryError> {
+    let cached = DISCOVERY_CACHE
+        .lock()
+        .expect("Failed to lock discovery cache")
+        .get(base_url)
+        .filter(|(fetched_at, _)| fetched_at.elapsed() < ttl)
+        .map(|(_, discovery)| discovery.clone());
+    if let Some(discovery) = cached {
+        return Ok(discovery);
+    }
+
+    let url = format!(
+        "{}/.well-known/openid-configuration",
+        base_url.trim_end_matches('/')
+    );
+    debug!("Fetching OpenID provider discovery document from {url}");
+    let discovery = async {
+        reqwest::Client::builder()
+            .timeout(DISCOVERY_TIMEOUT)
+            .build()?
+            .get(&url)
+            .send()
+            .await?
+            .error_for_status()
+    }
+    .await
+    .map_err(|source| DiscoveryError {
+        url: url.clone(),
+        source,
+    })?
+    .json::<ProviderDiscovery>()
+    .await
+    .map_err(|source| DiscoveryError { url, source })?;
+    if discovery.issuer.trim_end_matches('/') != base_url.trim_end_matches('/') {
+        debug!(
+            "OpenID provider at {base_url} advertises a different issuer: {}",
+            discovery.issuer
+        );
+    }
+
+    DISCOVERY_CACHE
+        .lock()
+        .expect("Failed to lock discovery cache")
+        .insert(base_url.to_string(), (Instant::now(), discovery.clone()));
+
+    Ok(discovery)
+}
+
+/// Problem found in OpenID provider configuration.
+#[derive(Debug, Error, PartialEq)]
+pub enum ProviderConfigProblem {
+    #[error("base URL is not a valid absolute URL")]
+    InvalidBaseUrl,
+    #[error("base URL must use HTTPS")]
+    InsecureBaseUrl,
+    #[error("client ID is empty")]
+    EmptyClientId,
+    #[error("client secret is empty")]
+    EmptyClientSecret,
+    #[error("discovery document is unreachable: {0}")]
+    DiscoveryUnreachable(String),
+}
+
+#[derive(Debug, Error)]
+pub enum OpenIdProviderError {
+    #[error("Invalid OpenID provider configuration: {}", format_problems(.0))]
+    InvalidConfig(Vec<ProviderConfigProblem>),
+    #[error(transparent)]
+    Db(#[from] SqlxError),
+}
+
+fn format_problems(problems: &[ProviderConfigProblem]) -> String {
+    problems
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// Plain HTTP is only accepted for providers running on the same host, e.g. in development.
+fn is_loopback(url: &Url) -> bool {
+    url.host_str().is_some_and(|host| {
+        host == "localhost"
+            || host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse::<IpAddr>()
+                .is_ok_and(|ip| ip.is_loopback())
+    })
+}
+
 #[derive(Deserialize, Model, Serialize)]
 pub struct OpenIdProvider<I = NoId> {
     pub id: I,
     pub name: String,
     pub base_url: String,
     pub client_id: String,
-    pub client_secret: String,
+    #[model(encrypted)]
+    pub client_secret: EncryptedSecret,
     pub display_name: Option<String>,
     // Specific stuff for Google
     pub google_service_account_key: Option<String>,
@@ -113,6 +318,10 @@ pub struct OpenIdProvider<I = NoId> {
     #[model(ref)]
     // The groups to sync from the directory, exact match
     pub directory_sync_group_match: Vec<String>,
+    // Redirect users to provider's end session endpoint when they log out
+    pub rp_initiated_logout: bool,
+    #[model(ref)]
+    pub group_mapping: Json<GroupMapping>,
 }
 
 impl OpenIdProvider {
@@ -134,13 +343,15 @@ impl OpenIdProvider {
         okta_private_jwk: Option<String>,
         okta_dirsync_client_id: Option<String>,
         directory_sync_group_match: Vec<String>,
+        rp_initiated_logout: bool,
+        group_mapping: GroupMapping,
     ) -> Self {
         Self {
             id: NoId,
             name: name.into(),
             base_url: base_url.into(),
             client_id: client_id.into(),
-            client_secret: client_secret.into(),
+            client_secret: EncryptedSecret::from(client_secret.into()),
             display_name,
             google_service_account_key,
             google_service_account_email,
@@ -153,23 +364,29 @@ impl OpenIdProvider {
             okta_private_jwk,
             okta_dirsync_client_id,
             directory_sync_group_match,
+            rp_initiated_logout,
+            group_mapping: Json(group_mapping),
         }
     }
 
-    pub async fn upsert(self, pool: &PgPool) -> Result<OpenIdProvider<Id>, SqlxError> {
-        if let Some(provider) = OpenIdProvider::<Id>::get_current(pool).await? {
+    /// Update provider with the same name or add a new one.
+    /// Fails without touching the database if the configuration is invalid.
+    pub async fn upsert(self, pool: &PgPool) -> Result<OpenIdProvider<Id>, OpenIdProviderError> {
+        self.validate(false).await?;
+        if let Some(provider) = OpenIdProvider::<Id>::find_by_name(pool, &self.name).await? {
             query!(
                 "UPDATE openidprovider SET name = $1, \
                 base_url = $2, client_id = $3, client_secret = $4, \
                 display_name = $5, google_service_account_key = $6, google_service_account_email = $7, admin_email = $8, \
                 directory_sync_enabled = $9, directory_sync_interval = $10, directory_sync_user_behavior = $11, \
                 directory_sync_admin_behavior = $12, directory_sync_target = $13, \
-                okta_private_jwk = $14, okta_dirsync_client_id = $15, directory_sync_group_match = $16 \
-                WHERE id = $17",
+                okta_private_jwk = $14, okta_dirsync_client_id = $15, directory_sync_group_match = $16, \
+                rp_initiated_logout = $17, group_mapping = $18 \
+                WHERE id = $19",
                 self.name,
                 self.base_url,
                 self.client_id,
-                self.client_secret,
+                &self.client_secret as &EncryptedSecret,
                 self.display_name,
                 self.google_service_account_key,
                 self.google_service_account_email,
@@ -182,47 +399,443 @@ impl OpenIdProvider {
                 self.okta_private_jwk,
                 self.okta_dirsync_client_id,
                 &self.directory_sync_group_match,
+                self.rp_initiated_logout,
+                &self.group_mapping as &Json<GroupMapping>,
                 provider.id,
             )
             .execute(pool)
             .await?;
 
-            Ok(provider)
+            Ok(OpenIdProvider::find_by_id(pool, provider.id)
+                .await?
+                .ok_or(SqlxError::RowNotFound)?)
+        } else {
+            Ok(self.save(pool).await?)
+        }
+    }
+}
+
+impl<I> OpenIdProvider<I> {
+    /// Check that base URL is an absolute HTTPS URL and client credentials are set.
+    /// With `check_discovery`, provider's discovery document has to be reachable as well.
+    /// All problems found are returned together.
+    pub async fn validate(&self, check_discovery: bool) -> Result<(), OpenIdProviderError> {
+        let mut problems = Vec::new();
+        match Url::parse(&self.base_url) {
+            Ok(url) if url.host_str().is_some() => {
+                if url.scheme() != "https" && !(url.scheme() == "http" && is_loopback(&url)) {
+                    problems.push(ProviderConfigProblem::InsecureBaseUrl);
+                } else if check_discovery {
+                    if let Err(err) =
+                        fetch_discovery(&self.base_url, *server_config().openid_discovery_cache_ttl)
+                            .await
+                    {
+                        problems.push(ProviderConfigProblem::DiscoveryUnreachable(err.to_string()));
+                    }
+                }
+            }
+            _ => problems.push(ProviderConfigProblem::InvalidBaseUrl),
+        }
+        if self.client_id.trim().is_empty() {
+            problems.push(ProviderConfigProblem::EmptyClientId);
+        }
+        if self.client_secret.expose_secret().trim().is_empty() {
+            problems.push(ProviderConfigProblem::EmptyClientSecret);
+        }
+
+        if problems.is_empty() {
+            Ok(())
         } else {
-            self.save(pool).await
+            Err(OpenIdProviderError::InvalidConfig(problems))
         }
     }
 }
 
 impl OpenIdProvider<Id> {
+    /// Provider's discovery document, cached for `openid_discovery_cache_ttl`.
+    pub async fn discovery(&self) -> Result<ProviderDiscovery, DiscoveryError> {
+        ProviderDiscovery::fetch(&self.base_url).await
+    }
+
     pub async fn find_by_name(pool: &PgPool, name: &str) -> Result<Option<Self>, SqlxError> {
-        query_as!(
+        let provider = query_as!(
             OpenIdProvider,
-            "SELECT id, name, base_url, client_id, client_secret, display_name, \
+            "SELECT id, name, base_url, client_id, client_secret \"client_secret: _\", display_name, \
             google_service_account_key, google_service_account_email, admin_email, directory_sync_enabled, 
             directory_sync_interval, directory_sync_user_behavior  \"directory_sync_user_behavior: DirectorySyncUserBehavior\", \
             directory_sync_admin_behavior  \"directory_sync_admin_behavior: DirectorySyncUserBehavior\", \
             directory_sync_target  \"directory_sync_target: DirectorySyncTarget\", \
-            okta_private_jwk, okta_dirsync_client_id, directory_sync_group_match \
+            okta_private_jwk, okta_dirsync_client_id, directory_sync_group_match, rp_initiated_logout, \
+            group_mapping \"group_mapping: _\" \
             FROM openidprovider WHERE name = $1",
             name
         )
         .fetch_optional(pool)
-        .await
+        .await?;
+        match provider {
+            Some(provider) => provider.encrypt_plaintext_secret(pool).await.map(Some),
+            None => Ok(None),
+        }
     }
 
+    /// The first configured provider. Used where a single provider is assumed, like directory
+    /// sync or logins which don't specify a provider.
     pub async fn get_current(pool: &PgPool) -> Result<Option<Self>, SqlxError> {
-        query_as!(
+        let provider = query_as!(
             OpenIdProvider,
-            "SELECT id, name, base_url, client_id, client_secret, display_name, \
+            "SELECT id, name, base_url, client_id, client_secret \"client_secret: _\", display_name, \
             google_service_account_key, google_service_account_email, admin_email, directory_sync_enabled, \
             directory_sync_interval, directory_sync_user_behavior \"directory_sync_user_behavior: DirectorySyncUserBehavior\", \
             directory_sync_admin_behavior  \"directory_sync_admin_behavior: DirectorySyncUserBehavior\", \
             directory_sync_target  \"directory_sync_target: DirectorySyncTarget\", \
-            okta_private_jwk, okta_dirsync_client_id, directory_sync_group_match \
-            FROM openidprovider LIMIT 1"
+            okta_private_jwk, okta_dirsync_client_id, directory_sync_group_match, rp_initiated_logout, \
+            group_mapping \"group_mapping: _\" \
+            FROM openidprovider ORDER BY id LIMIT 1"
         )
         .fetch_optional(pool)
+        .await?;
+        match provider {
+            Some(provider) => provider.encrypt_plaintext_secret(pool).await.map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Encrypt client secret stored as plaintext before encryption was introduced.
+    async fn encrypt_plaintext_secret(mut self, pool: &PgPool) -> Result<Self, SqlxError> {
+        if self.client_secret.stored_as_plaintext() {
+            info!("Encrypting client secret of OpenID provider {}", self.name);
+            self.client_secret = EncryptedSecret::from(self.client_secret.expose_secret());
+            query!(
+                "UPDATE openidprovider SET client_secret = $1 WHERE id = $2",
+                &self.client_secret as &EncryptedSecret,
+                self.id
+            )
+            .execute(pool)
+            .await?;
+        }
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use axum::{routing::get, serve, Json, Router};
+    use serde_json::json;
+    use sqlx::query_scalar;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::cipher::{is_sealed, open, secret_cipher, set_secret_cipher, test::XorCipher};
+
+    /// Serve a discovery document and count how many times it has been requested.
+    async fn start_discovery_server(issuer: &'static str) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://127.0.0.1:{}", listener.local_addr().unwrap().port());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&requests);
+        let app = Router::new().route(
+            "/.well-known/openid-configuration",
+            get(move || async move {
+                counter.fetch_add(1, Ordering::Relaxed);
+                Json(json!({
+                    "issuer": issuer,
+                    "authorization_endpoint": format!("{issuer}/oauth2/authorize"),
+                    "token_endpoint": format!("{issuer}/oauth2/token"),
+                    "jwks_uri": format!("{issuer}/discovery/keys"),
+                    "userinfo_endpoint": "https://graph.example.com/oidc/userinfo",
+                    "response_types_supported": ["code"],
+                    "subject_types_supported": ["pairwise"],
+                    "id_token_signing_alg_values_supported": ["RS256", "ES256"],
+                    "code_challenge_methods_supported": ["plain", "S256"],
+                }))
+            }),
+        );
+        tokio::spawn(async move {
+            serve(listener, app).await.unwrap();
+        });
+
+        (base_url, requests)
+    }
+
+    #[tokio::test]
+    async fn test_fetch_discovery() {
+        // issuer differs from the URL the document is served from
+        let issuer = "https://login.example.com/tenant/v2.0";
+        let (base_url, requests) = start_discovery_server(issuer).await;
+        let ttl = Duration::from_secs(60);
+
+        let discovery = fetch_discovery(&base_url, ttl).await.unwrap();
+        assert_eq!(discovery.issuer, issuer);
+        assert_eq!(
+            discovery.authorization_endpoint.as_str(),
+            "https://login.example.com/tenant/v2.0/oauth2/authorize"
+        );
+        assert_eq!(
+            discovery.token_endpoint.as_str(),
+            "https://login.example.com/tenant/v2.0/oauth2/token"
+        );
+        assert_eq!(
+            discovery.jwks_uri.as_str(),
+            "https://login.example.com/tenant/v2.0/discovery/keys"
+        );
+        assert_eq!(
+            discovery.userinfo_endpoint.as_ref().map(Url::as_str),
+            Some("https://graph.example.com/oidc/userinfo")
+        );
+        assert_eq!(discovery.end_session_endpoint, None);
+        assert_eq!(
+            discovery.id_token_signing_alg_values_supported,
+            vec!["RS256", "ES256"]
+        );
+        assert!(discovery.supports_pkce());
+        assert_eq!(requests.load(Ordering::Relaxed), 1);
+
+        // served from cache within TTL
+        assert_eq!(fetch_discovery(&base_url, ttl).await.unwrap(), discovery);
+        assert_eq!(requests.load(Ordering::Relaxed), 1);
+
+        // fetched again once expired
+        assert_eq!(
+            fetch_discovery(&base_url, Duration::ZERO).await.unwrap(),
+            discovery
+        );
+        assert_eq!(requests.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_discovery_error() {
+        assert!(
+            fetch_discovery("http://127.0.0.1:1", Duration::from_secs(60))
+                .await
+                .is_err()
+        );
+    }
+
+    fn make_provider(name: &str, base_url: &str) -> OpenIdProvider {
+        OpenIdProvider::new(
+            name.to_string(),
+            base_url.to_string(),
+            format!("{name}_client_id"),
+            format!("{name}_client_secret"),
+            Some(format!("Sign in with {name}")),
+            None,
+            None,
+            None,
+            false,
+            100,
+            DirectorySyncUserBehavior::Keep,
+            DirectorySyncUserBehavior::Keep,
+            DirectorySyncTarget::All,
+            None,
+            None,
+            Vec::new(),
+            false,
+            GroupMapping::default(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_validate() {
+        // valid config
+        let provider = make_provider("azure", "https://login.microsoftonline.com/tenant/v2.0");
+        assert!(provider.validate(false).await.is_ok());
+        let provider = make_provider("keycloak", "http://localhost:8080/realms/defguard");
+        assert!(provider.validate(false).await.is_ok());
+
+        // malformed URL
+        for base_url in [
+            "login.microsoftonline.com",
+            "https//example.com",
+            "/realms/defguard",
+        ] {
+            let provider = make_provider("azure", base_url);
+            let Err(OpenIdProviderError::InvalidConfig(problems)) = provider.validate(false).await
+            else {
+                panic!("{base_url} should be rejected");
+            };
+            assert_eq!(problems, [ProviderConfigProblem::InvalidBaseUrl]);
+        }
+        let provider = make_provider("azure", "http://login.microsoftonline.com/tenant/v2.0");
+        let Err(OpenIdProviderError::InvalidConfig(problems)) = provider.validate(false).await
+        else {
+            panic!("plain HTTP should be rejected");
+        };
+        assert_eq!(problems, [ProviderConfigProblem::InsecureBaseUrl]);
+
+        // empty client id, all problems are reported
+        let mut provider = make_provider("okta", "okta");
+        provider.client_id = " ".into();
+        provider.client_secret = "".into();
+        let err = provider.validate(false).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid OpenID provider configuration: base URL is not a valid absolute URL, \
+            client ID is empty, client secret is empty"
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_upsert_invalid_config(pool: PgPool) {
+        let mut provider = make_provider("okta", "https://partner.okta.com");
+        provider.client_id = String::new();
+        assert!(matches!(
+            provider.upsert(&pool).await,
+            Err(OpenIdProviderError::InvalidConfig(_))
+        ));
+        assert!(OpenIdProvider::all(&pool).await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_group_mapping() {
+        let mapping = GroupMapping {
+            groups: HashMap::from([
+                ("vpn-admins".into(), "admin".into()),
+                ("vpn-users".into(), "users".into()),
+                ("contractors".into(), "users".into()),
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(mapping.claim, "groups");
+        assert_eq!(mapping.mode, GroupMappingMode::Additive);
+        assert_eq!(
+            mapping.granted_groups(&["contractors".into(), "sales".into()]),
+            HashSet::from(["users"])
+        );
+        assert_eq!(mapping.managed_groups(), HashSet::from(["admin", "users"]));
+
+        // missing fields use defaults
+        let mapping: GroupMapping =
+            serde_json::from_str(r#"{"groups": {"vpn-admins": "admin"}, "mode": "authoritative"}"#)
+                .unwrap();
+        assert_eq!(mapping.claim, "groups");
+        assert_eq!(mapping.mode, GroupMappingMode::Authoritative);
+    }
+
+    #[sqlx::test]
+    async fn test_client_secret_encryption(pool: PgPool) {
+        // unless another test has already initialized the cipher
+        set_secret_cipher(Box::new(XorCipher(0x5a)));
+
+        let provider = make_provider("azure", "https://login.microsoftonline.com/tenant/v2.0")
+            .upsert(&pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            provider.client_secret.expose_secret(),
+            "azure_client_secret"
+        );
+
+        // stored secret is encrypted
+        let stored = query_scalar!(
+            "SELECT client_secret FROM openidprovider WHERE id = $1",
+            provider.id
+        )
+        .fetch_one(&pool)
         .await
+        .unwrap();
+        assert!(is_sealed(&stored));
+        assert!(!stored.contains("azure_client_secret"));
+        assert_eq!(
+            open(secret_cipher(), &stored).unwrap(),
+            "azure_client_secret"
+        );
+
+        // and decrypted on read
+        let found = OpenIdProvider::find_by_id(&pool, provider.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.client_secret.expose_secret(), "azure_client_secret");
+        assert!(!found.client_secret.stored_as_plaintext());
+        let found = OpenIdProvider::find_by_name(&pool, "azure")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.client_secret.expose_secret(), "azure_client_secret");
+
+        // plaintext secret stored before encryption is encrypted on first load
+        query!(
+            "UPDATE openidprovider SET client_secret = 'legacy_secret' WHERE id = $1",
+            provider.id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let found = OpenIdProvider::find_by_id(&pool, provider.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.client_secret.expose_secret(), "legacy_secret");
+        assert!(found.client_secret.stored_as_plaintext());
+        let current = OpenIdProvider::get_current(&pool).await.unwrap().unwrap();
+        assert_eq!(current.client_secret.expose_secret(), "legacy_secret");
+        assert!(!current.client_secret.stored_as_plaintext());
+        let stored = query_scalar!(
+            "SELECT client_secret FROM openidprovider WHERE id = $1",
+            provider.id
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert!(is_sealed(&stored));
+        assert_eq!(open(secret_cipher(), &stored).unwrap(), "legacy_secret");
+    }
+
+    #[sqlx::test]
+    async fn test_multiple_providers(pool: PgPool) {
+        set_secret_cipher(Box::new(XorCipher(0x5a)));
+        let azure = make_provider("azure", "https://login.microsoftonline.com/tenant/v2.0")
+            .upsert(&pool)
+            .await
+            .unwrap();
+        let okta = make_provider("okta", "https://partner.okta.com")
+            .upsert(&pool)
+            .await
+            .unwrap();
+        assert_ne!(azure.id, okta.id);
+        assert_eq!(OpenIdProvider::all(&pool).await.unwrap().len(), 2);
+
+        let provider = OpenIdProvider::find_by_id(&pool, azure.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(provider.name, "azure");
+        assert_eq!(provider.client_id, "azure_client_id");
+        let provider = OpenIdProvider::find_by_id(&pool, okta.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(provider.name, "okta");
+        assert_eq!(provider.base_url, "https://partner.okta.com");
+        let provider = OpenIdProvider::find_by_name(&pool, "okta")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(provider.id, okta.id);
+
+        // the first provider is still the current one
+        let current = OpenIdProvider::get_current(&pool).await.unwrap().unwrap();
+        assert_eq!(current.id, azure.id);
+
+        // upsert only modifies the provider with the same name
+        let mut updated = make_provider("okta", "https://partner.okta.com/oauth2/default");
+        updated.display_name = Some("Partners".into());
+        let updated = updated.upsert(&pool).await.unwrap();
+        assert_eq!(updated.id, okta.id);
+        assert_eq!(updated.display_name.as_deref(), Some("Partners"));
+        let provider = OpenIdProvider::find_by_id(&pool, azure.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            provider.base_url,
+            "https://login.microsoftonline.com/tenant/v2.0"
+        );
+        assert_eq!(OpenIdProvider::all(&pool).await.unwrap().len(), 2);
     }
 }
@@ -222,7 +222,7 @@ impl DirectorySyncClient {
                 debug!("Microsoft directory sync provider selected");
                 let client = microsoft::MicrosoftDirectorySync::new(
                     provider_settings.client_id,
-                    provider_settings.client_secret,
+                    provider_settings.client_secret.expose_secret().to_string(),
                     provider_settings.base_url,
                     provider_settings.directory_sync_group_match,
                 );
@@ -844,7 +844,7 @@ mod test {
             models::{device::DeviceType, settings::initialize_current_settings},
             Device, Session, SessionState, Settings, WireguardNetwork,
         },
-        enterprise::db::models::openid_provider::DirectorySyncTarget,
+        enterprise::db::models::openid_provider::{DirectorySyncTarget, GroupMapping},
         SERVER_CONFIG,
     };
 
@@ -905,6 +905,8 @@ mod test {
             None,
             None,
             vec![],
+            false,
+            GroupMapping::default(),
         )
         .save(pool)
         .await
@@ -920,6 +922,7 @@ mod test {
             format!("{name}@email.com").as_str(),
             None,
         )
+        .unwrap()
         .save(pool)
         .await
         .unwrap();
@@ -1,4 +1,10 @@
-use axum::{extract::State, http::StatusCode, Json};
+use std::collections::HashSet;
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
 use axum_client_ip::InsecureClientIp;
 use axum_extra::{
     extract::{
@@ -8,28 +14,40 @@ use axum_extra::{
     headers::UserAgent,
     TypedHeader,
 };
+use base64::prelude::{Engine, BASE64_URL_SAFE_NO_PAD};
 use openidconnect::{
-    core::{CoreAuthenticationFlow, CoreClient, CoreProviderMetadata, CoreUserInfoClaims},
+    core::{
+        CoreAuthenticationFlow, CoreClient, CoreErrorResponseType, CoreJsonWebKeySet,
+        CoreTokenResponse, CoreUserInfoClaims,
+    },
     reqwest::async_http_client,
-    AuthorizationCode, ClientId, ClientSecret, CsrfToken, IssuerUrl, Nonce, OAuth2TokenResponse,
-    RedirectUrl, Scope,
+    AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, IssuerUrl, JsonWebKeySetUrl,
+    Nonce, OAuth2TokenResponse, PkceCodeChallenge, PkceCodeVerifier, RedirectUrl,
+    RequestTokenError, Scope, TokenUrl, UserInfoUrl,
 };
 use reqwest::Url;
-use serde_json::json;
+use serde_json::{json, Value};
 use sqlx::PgPool;
+use thiserror::Error;
 use time::Duration;
+use tokio::{sync::broadcast::Sender, time::timeout};
 
 const COOKIE_MAX_AGE: Duration = Duration::days(1);
 static CSRF_COOKIE_NAME: &str = "csrf";
 static NONCE_COOKIE_NAME: &str = "nonce";
+static PROVIDER_COOKIE_NAME: &str = "openid_provider";
+static PKCE_VERIFIER_COOKIE_NAME: &str = "pkce_verifier";
 
 use super::LicenseInfo;
 use crate::{
     appstate::AppState,
-    db::{Id, Settings, User},
+    db::{GatewayEvent, Group, Id, Settings, User},
     enterprise::{
-        db::models::openid_provider::OpenIdProvider,
-        directory_sync::sync_user_groups_if_configured, limits::update_counts,
+        db::models::openid_provider::{
+            GroupMapping, GroupMappingMode, OpenIdProvider, ProviderDiscovery, DISCOVERY_TIMEOUT,
+        },
+        directory_sync::sync_user_groups_if_configured,
+        limits::update_counts,
     },
     error::WebError,
     handlers::{
@@ -40,70 +58,381 @@ use crate::{
     server_config,
 };
 
-async fn get_provider_metadata(url: &str) -> Result<CoreProviderMetadata, WebError> {
-    let issuer_url = IssuerUrl::new(url.to_string()).map_err(|err| {
-        WebError::BadRequest(format!(
-            "Failed to create issuer URL from the provided URL: {url}. Error details: {err:?}",
-        ))
+/// Build OpenID Connect client from provider's discovery document. Signing keys are fetched
+/// from the advertised JWKS URI. The advertised issuer is used, as it may differ from the
+/// provider's base URL.
+async fn core_client(
+    discovery: ProviderDiscovery,
+    client_id: ClientId,
+    client_secret: ClientSecret,
+) -> Result<CoreClient, String> {
+    let issuer_url = IssuerUrl::new(discovery.issuer.clone()).map_err(|err| {
+        format!(
+            "Invalid issuer URL advertised by the provider: {}. Error details: {err:?}",
+            discovery.issuer
+        )
     })?;
-    // Discover the provider metadata based on a known base issuer URL
-    // The url should be in the form of e.g. https://accounts.google.com
-    // The url shouldn't contain a .well-known part, it will be added automatically
-    match CoreProviderMetadata::discover_async(issuer_url, async_http_client).await {
-        Ok(provider_metadata) => Ok(provider_metadata),
+    let jwks_url = JsonWebKeySetUrl::from_url(discovery.jwks_uri);
+    let jwks = timeout(
+        DISCOVERY_TIMEOUT,
+        CoreJsonWebKeySet::fetch_async(&jwks_url, async_http_client),
+    )
+    .await
+    .map_err(|_| {
+        format!(
+            "Timed out fetching provider signing keys from {}",
+            jwks_url.url()
+        )
+    })?
+    .map_err(|err| {
+        format!(
+            "Failed to fetch provider signing keys from {}. Error details: {err:?}",
+            jwks_url.url()
+        )
+    })?;
+
+    Ok(CoreClient::new(
+        client_id,
+        Some(client_secret),
+        issuer_url,
+        AuthUrl::from_url(discovery.authorization_endpoint),
+        Some(TokenUrl::from_url(discovery.token_endpoint)),
+        discovery.userinfo_endpoint.map(UserInfoUrl::from_url),
+        jwks,
+    ))
+}
+
+/// Build RP-initiated logout URL as described in OpenID Connect RP-Initiated Logout 1.0.
+fn rp_logout_url(
+    mut end_session_endpoint: Url,
+    id_token: &str,
+    client_id: &str,
+    post_logout_redirect_uri: &Url,
+) -> Url {
+    end_session_endpoint
+        .query_pairs_mut()
+        .append_pair("id_token_hint", id_token)
+        .append_pair("client_id", client_id)
+        .append_pair(
+            "post_logout_redirect_uri",
+            post_logout_redirect_uri.as_str(),
+        );
+    end_session_endpoint
+}
+
+/// URL which logs the user out of the OpenID provider, if the provider has RP-initiated logout
+/// enabled and advertises an end session endpoint. Failures are only logged, so that they
+/// don't prevent logging out of defguard.
+/// Sessions created before the issuing provider was recorded fall back to the first provider.
+pub(crate) async fn provider_logout_url(
+    pool: &PgPool,
+    provider_id: Option<Id>,
+    id_token: &str,
+) -> Option<Url> {
+    let provider = match provider_id {
+        Some(id) => OpenIdProvider::find_by_id(pool, id).await,
+        None => OpenIdProvider::get_current(pool).await,
+    };
+    let provider = match provider {
+        Ok(Some(provider)) if provider.rp_initiated_logout => provider,
+        Ok(_) => return None,
+        Err(err) => {
+            error!("Failed to fetch OpenID provider: {err}");
+            return None;
+        }
+    };
+    match provider.discovery().await {
+        Ok(ProviderDiscovery {
+            end_session_endpoint: Some(endpoint),
+            ..
+        }) => Some(rp_logout_url(
+            endpoint,
+            id_token,
+            &provider.client_id,
+            &server_config().url,
+        )),
+        Ok(_) => {
+            warn!(
+                "OpenID provider {} doesn't support RP-initiated logout",
+                provider.name
+            );
+            None
+        }
         Err(err) => {
-            Err(WebError::Authorization(format!(
-                "Failed to discover provider metadata, make sure the provider's URL is correct: {url}. Error details: {err:?}",
-            )))
+            error!("{err}");
+            None
         }
     }
 }
 
+#[derive(Debug, Error)]
+pub enum ProviderConnectionError {
+    #[error("Failed to discover provider metadata: {0}")]
+    Discovery(String),
+    #[error("Provider rejected client credentials: {0}")]
+    InvalidClient(String),
+    #[error("Provider token endpoint is unreachable: {0}")]
+    TokenEndpoint(String),
+}
+
+impl ProviderConnectionError {
+    /// Short, stable identifier of the failure for the frontend.
+    #[must_use]
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Discovery(_) => "discovery",
+            Self::InvalidClient(_) => "invalid_client",
+            Self::TokenEndpoint(_) => "token_endpoint",
+        }
+    }
+}
+
+/// Check that provider discovery works and that its token endpoint accepts given client
+/// credentials. Client credentials grant is used to authenticate the client; providers which
+/// authenticate the client, but don't allow this grant type, are treated as reachable.
+pub(crate) async fn test_provider_connection(
+    base_url: &str,
+    client_id: &str,
+    client_secret: &str,
+) -> Result<(), ProviderConnectionError> {
+    let discovery = ProviderDiscovery::fetch(base_url)
+        .await
+        .map_err(|err| ProviderConnectionError::Discovery(err.to_string()))?;
+    let core_client = core_client(
+        discovery,
+        ClientId::new(client_id.to_string()),
+        ClientSecret::new(client_secret.to_string()),
+    )
+    .await
+    .map_err(ProviderConnectionError::Discovery)?;
+    match core_client
+        .exchange_client_credentials()
+        .request_async(async_http_client)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(RequestTokenError::ServerResponse(response)) => match response.error() {
+            CoreErrorResponseType::InvalidClient => {
+                Err(ProviderConnectionError::InvalidClient(response.to_string()))
+            }
+            _ => Ok(()),
+        },
+        Err(err) => Err(ProviderConnectionError::TokenEndpoint(err.to_string())),
+    }
+}
+
+/// Separates provider id from the random part of nonces issued for logins through the proxy,
+/// whose callback requests don't name the provider.
+const NONCE_PROVIDER_SEPARATOR: char = '.';
+
+/// Random nonce bound to given provider, see [`nonce_provider_id`].
+pub(crate) fn provider_nonce(provider_id: Id) -> Nonce {
+    Nonce::new(format!(
+        "{provider_id}{NONCE_PROVIDER_SEPARATOR}{}",
+        Nonce::new_random().secret()
+    ))
+}
+
+/// Id of the provider the nonce has been issued for, `None` if it isn't bound to any.
+pub(crate) fn nonce_provider_id(nonce: &str) -> Option<Id> {
+    nonce
+        .split_once(NONCE_PROVIDER_SEPARATOR)
+        .and_then(|(provider_id, _)| provider_id.parse().ok())
+}
+
 /// Build OpenID Connect client.
 /// `url`: redirect/callback URL
 pub(crate) async fn make_oidc_client(
     url: Url,
     provider: &OpenIdProvider<Id>,
 ) -> Result<(ClientId, CoreClient), WebError> {
-    let provider_metadata = get_provider_metadata(&provider.base_url).await?;
+    let discovery = provider.discovery().await.map_err(|err| {
+        WebError::Authorization(format!(
+            "{err}, make sure the provider's URL is correct: {}",
+            provider.base_url
+        ))
+    })?;
     let client_id = ClientId::new(provider.client_id.to_string());
-    let client_secret = ClientSecret::new(provider.client_secret.to_string());
-    let core_client = CoreClient::from_provider_metadata(
-        provider_metadata,
-        client_id.clone(),
-        Some(client_secret),
-    )
-    .set_redirect_uri(RedirectUrl::from_url(url));
+    let client_secret = ClientSecret::new(provider.client_secret.expose_secret().to_string());
+    let core_client = core_client(discovery, client_id.clone(), client_secret)
+        .await
+        .map_err(WebError::Authorization)?
+        .set_redirect_uri(RedirectUrl::from_url(url));
 
     Ok((client_id, core_client))
 }
 
-/// Get or create `User` from OpenID claims.
+/// Check if the provider advertises PKCE support. Providers whose discovery document
+/// can't be fetched are treated as not supporting it.
+async fn supports_pkce(provider: &OpenIdProvider<Id>) -> bool {
+    match provider.discovery().await {
+        Ok(discovery) => discovery.supports_pkce(),
+        Err(err) => {
+            warn!("{err}; proceeding without PKCE");
+            false
+        }
+    }
+}
+
+/// Build authorization request URL. With `use_pkce`, S256 code challenge is included and
+/// the verifier, which has to be sent on code exchange, is returned.
+fn authorization_url(
+    client: &CoreClient,
+    use_pkce: bool,
+) -> (Url, CsrfToken, Nonce, Option<PkceCodeVerifier>) {
+    let mut request = client
+        .authorize_url(
+            CoreAuthenticationFlow::AuthorizationCode,
+            CsrfToken::new_random,
+            Nonce::new_random,
+        )
+        .add_scope(Scope::new("email".into()))
+        .add_scope(Scope::new("profile".into()));
+    let pkce_verifier = if use_pkce {
+        let (challenge, verifier) = PkceCodeChallenge::new_random_sha256();
+        request = request.set_pkce_challenge(challenge);
+        Some(verifier)
+    } else {
+        None
+    };
+    let (url, csrf_state, nonce) = request.url();
+
+    (url, csrf_state, nonce, pkce_verifier)
+}
+
+/// Exchange authorization code for tokens, sending PKCE verifier if the login used one.
+async fn exchange_code(
+    client: &CoreClient,
+    code: AuthorizationCode,
+    pkce_verifier: Option<PkceCodeVerifier>,
+) -> Result<CoreTokenResponse, WebError> {
+    let mut request = client.exchange_code(code);
+    if let Some(verifier) = pkce_verifier {
+        request = request.set_pkce_verifier(verifier);
+    }
+    request
+        .request_async(async_http_client)
+        .await
+        .map_err(|err| {
+            WebError::Authorization(format!(
+                "Failed to exchange code for ID token; OpenID provider error: {err:?}"
+            ))
+        })
+}
+
+/// Values of `claim` in the payload of an already verified ID token. The claim may hold
+/// a list of strings or a single string.
+fn group_claim_values(id_token: &str, claim: &str) -> Vec<String> {
+    let claims = id_token
+        .split('.')
+        .nth(1)
+        .and_then(|payload| BASE64_URL_SAFE_NO_PAD.decode(payload).ok())
+        .and_then(|payload| serde_json::from_slice::<Value>(&payload).ok());
+    match claims.as_ref().and_then(|claims| claims.get(claim)) {
+        Some(Value::Array(values)) => values
+            .iter()
+            .filter_map(Value::as_str)
+            .map(ToString::to_string)
+            .collect(),
+        Some(Value::String(value)) => vec![value.clone()],
+        _ => {
+            debug!("Claim {claim} not found in the ID token");
+            Vec::new()
+        }
+    }
+}
+
+/// Reconcile user's membership in groups managed by provider's group mapping with group claim
+/// values from the ID token. Mapped groups which don't exist in defguard are skipped.
+pub(crate) async fn apply_group_mapping(
+    pool: &PgPool,
+    user: &User<Id>,
+    mapping: &GroupMapping,
+    claim_values: &[String],
+    wg_tx: &Sender<GatewayEvent>,
+) -> Result<(), WebError> {
+    let managed = mapping.managed_groups();
+    if managed.is_empty() {
+        return Ok(());
+    }
+    let granted = mapping.granted_groups(claim_values);
+    debug!(
+        "OpenID group claims of user {} grant groups {granted:?}",
+        user.username
+    );
+
+    let mut transaction = pool.begin().await?;
+    let current: HashSet<String> = user
+        .member_of(&mut *transaction)
+        .await?
+        .into_iter()
+        .map(|group| group.name)
+        .collect();
+    let mut changed = false;
+    for name in managed {
+        let should_be_member = granted.contains(name);
+        if current.contains(name) == should_be_member
+            || (!should_be_member && mapping.mode == GroupMappingMode::Additive)
+        {
+            continue;
+        }
+        let Some(group) = Group::find_by_name(&mut *transaction, name).await? else {
+            warn!("Group {name} from OpenID group mapping doesn't exist, skipping");
+            continue;
+        };
+        if should_be_member {
+            info!(
+                "Adding user {} to group {name} based on OpenID group claims",
+                user.username
+            );
+            user.add_to_group(&mut *transaction, &group).await?;
+        } else {
+            info!(
+                "Removing user {} from group {name} based on OpenID group claims",
+                user.username
+            );
+            user.remove_from_group(&mut *transaction, &group).await?;
+        }
+        changed = true;
+    }
+    if changed {
+        user.sync_allowed_devices(&mut transaction, wg_tx).await?;
+    }
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+/// Find provider chosen by the user, or the first configured one if none was chosen.
+async fn find_provider(pool: &PgPool, name: Option<&str>) -> Result<OpenIdProvider<Id>, WebError> {
+    if let Some(name) = name {
+        OpenIdProvider::find_by_name(pool, name)
+            .await?
+            .ok_or_else(|| WebError::ObjectNotFound(format!("OpenID provider {name} not found")))
+    } else {
+        OpenIdProvider::get_current(pool)
+            .await?
+            .ok_or_else(|| WebError::ObjectNotFound("OpenID provider not set".to_string()))
+    }
+}
+
+/// Get or create `User` from OpenID claims of the provider with given name, or the first
+/// configured provider if no name is given.
+/// If the provider has RP-initiated logout enabled, raw ID token is returned as well,
+/// along with id of the provider which issued it.
 pub(crate) async fn user_from_claims(
     pool: &PgPool,
+    wg_tx: &Sender<GatewayEvent>,
+    provider_name: Option<&str>,
     nonce: Nonce,
     code: AuthorizationCode,
+    pkce_verifier: Option<PkceCodeVerifier>,
     callback_url: Url,
-) -> Result<User<Id>, WebError> {
-    let Some(provider) = OpenIdProvider::get_current(pool).await? else {
-        return Err(WebError::ObjectNotFound(
-            "OpenID provider not set".to_string(),
-        ));
-    };
+) -> Result<(User<Id>, Option<(Id, String)>), WebError> {
+    let provider = find_provider(pool, provider_name).await?;
     let (client_id, core_client) = make_oidc_client(callback_url, &provider).await?;
     // Exchange code for ID token.
-    let token_response = match core_client
-        .exchange_code(code)
-        .request_async(async_http_client)
-        .await
-    {
-        Ok(token) => token,
-        Err(err) => {
-            return Err(WebError::Authorization(format!(
-                "Failed to exchange code for ID token; OpenID provider error: {err:?}"
-            )));
-        }
-    };
+    let token_response = exchange_code(&core_client, code, pkce_verifier).await?;
     let Some(id_token) = token_response.extra_fields().id_token() else {
         return Err(WebError::Authorization(
             "Server did not return an ID token".to_string(),
@@ -200,6 +529,10 @@ pub(crate) async fn user_from_claims(
                 debug!("User {} tried to log in, but is disabled", user.username);
                 return Err(WebError::Authorization("User is disabled".into()));
             }
+            if let Some(until) = user.active_suspension() {
+                debug!("User {} tried to log in, but is suspended", user.username);
+                return Err(WebError::UserSuspended(until));
+            }
             user
         }
         None => {
@@ -208,6 +541,10 @@ pub(crate) async fn user_from_claims(
                     debug!("User {} tried to log in, but is disabled", user.username);
                     return Err(WebError::Authorization("User is disabled".into()));
                 }
+                if let Some(until) = user.active_suspension() {
+                    debug!("User {} tried to log in, but is suspended", user.username);
+                    return Err(WebError::UserSuspended(until));
+                }
                 // User with the same email already exists, merge the accounts
                 info!(
                     "User with email address {} is logging in through OpenID Connect for the \
@@ -331,42 +668,44 @@ pub(crate) async fn user_from_claims(
                     given_name.to_string(),
                     email.to_string(),
                     phone.map(|v| v.to_string()),
-                );
+                )?;
                 user.openid_sub = Some(sub);
                 user.save(pool).await?
             }
         }
     };
 
+    let claim_values = group_claim_values(&id_token.to_string(), &provider.group_mapping.claim);
+    apply_group_mapping(pool, &user, &provider.group_mapping, &claim_values, wg_tx).await?;
+
     update_counts(pool).await?;
-    Ok(user)
+    let id_token = provider
+        .rp_initiated_logout
+        .then(|| (provider.id, id_token.to_string()));
+    Ok((user, id_token))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct AuthInfoParams {
+    /// Name of the provider chosen by the user.
+    provider: Option<String>,
 }
 
 pub(crate) async fn get_auth_info(
     _license: LicenseInfo,
     private_cookies: PrivateCookieJar,
     State(appstate): State<AppState>,
+    Query(params): Query<AuthInfoParams>,
 ) -> Result<(PrivateCookieJar, ApiResponse), WebError> {
-    let provider = OpenIdProvider::get_current(&appstate.pool).await?;
-    let Some(provider) = provider else {
-        return Err(WebError::ObjectNotFound(
-            "OpenID provider not set".to_string(),
-        ));
-    };
+    let provider = find_provider(&appstate.pool, params.provider.as_deref()).await?;
+    debug!("Starting OpenID login with provider {}", provider.name);
 
     let config = server_config();
     let (_client_id, client) = make_oidc_client(config.callback_url(), &provider).await?;
 
     // Generate the redirect URL and the values needed later for callback authenticity verification
-    let (authorize_url, csrf_state, nonce) = client
-        .authorize_url(
-            CoreAuthenticationFlow::AuthorizationCode,
-            CsrfToken::new_random,
-            Nonce::new_random,
-        )
-        .add_scope(Scope::new("email".into()))
-        .add_scope(Scope::new("profile".into()))
-        .url();
+    let use_pkce = supports_pkce(&provider).await;
+    let (authorize_url, csrf_state, nonce, pkce_verifier) = authorization_url(&client, use_pkce);
 
     let cookie_domain = config
         .cookie_domain
@@ -388,7 +727,37 @@ pub(crate) async fn get_auth_info(
         .secure(!config.cookie_insecure)
         .max_age(COOKIE_MAX_AGE)
         .build();
-    let private_cookies = private_cookies.add(nonce_cookie).add(csrf_cookie);
+    let provider_cookie = Cookie::build((PROVIDER_COOKIE_NAME, provider.name.clone()))
+        .domain(cookie_domain)
+        .path("/api/v1/openid/callback")
+        .http_only(true)
+        .same_site(SameSite::Strict)
+        .secure(!config.cookie_insecure)
+        .max_age(COOKIE_MAX_AGE)
+        .build();
+    let mut private_cookies = private_cookies
+        .add(nonce_cookie)
+        .add(csrf_cookie)
+        .add(provider_cookie);
+    if let Some(pkce_verifier) = pkce_verifier {
+        let pkce_cookie =
+            Cookie::build((PKCE_VERIFIER_COOKIE_NAME, pkce_verifier.secret().clone()))
+                .domain(cookie_domain)
+                .path("/api/v1/openid/callback")
+                .http_only(true)
+                .same_site(SameSite::Strict)
+                .secure(!config.cookie_insecure)
+                .max_age(COOKIE_MAX_AGE)
+                .build();
+        private_cookies = private_cookies.add(pkce_cookie);
+    } else {
+        // drop verifier left over by an unfinished login with another provider
+        private_cookies = private_cookies.remove(
+            Cookie::build(PKCE_VERIFIER_COOKIE_NAME)
+                .domain(cookie_domain.clone())
+                .path("/api/v1/openid/callback"),
+        );
+    }
 
     Ok((
         private_cookies,
@@ -396,7 +765,8 @@ pub(crate) async fn get_auth_info(
             json: json!(
                 {
                     "url": authorize_url,
-                    "button_display_name": provider.display_name
+                    "button_display_name": provider.display_name,
+                    "provider": provider.name,
                 }
             ),
             status: StatusCode::OK,
@@ -432,6 +802,14 @@ pub(crate) async fn auth_callback(
         .ok_or(WebError::BadRequest("CSRF cookie not found".into()))?
         .value_trimmed()
         .to_string();
+    // Callbacks of logins started before multiple providers were supported don't have it.
+    let cookie_provider = private_cookies
+        .get(PROVIDER_COOKIE_NAME)
+        .map(|cookie| cookie.value_trimmed().to_string());
+    // Only present if the provider supports PKCE.
+    let cookie_pkce_verifier = private_cookies
+        .get(PKCE_VERIFIER_COOKIE_NAME)
+        .map(|cookie| PkceCodeVerifier::new(cookie.value_trimmed().to_string()));
 
     // Verify the CSRF token
     if payload.state.secret() != &cookie_csrf {
@@ -440,18 +818,23 @@ pub(crate) async fn auth_callback(
 
     private_cookies = private_cookies
         .remove(Cookie::from(NONCE_COOKIE_NAME))
-        .remove(Cookie::from(CSRF_COOKIE_NAME));
+        .remove(Cookie::from(CSRF_COOKIE_NAME))
+        .remove(Cookie::from(PROVIDER_COOKIE_NAME))
+        .remove(Cookie::from(PKCE_VERIFIER_COOKIE_NAME));
 
     let config = server_config();
-    let mut user = user_from_claims(
+    let (mut user, id_token) = user_from_claims(
         &appstate.pool,
+        &appstate.wireguard_tx,
+        cookie_provider.as_deref(),
         Nonce::new(cookie_nonce),
         payload.code,
+        cookie_pkce_verifier,
         config.callback_url(),
     )
     .await?;
 
-    let (session, user_info, mfa_info) = create_session(
+    let (mut session, user_info, mfa_info) = create_session(
         &appstate.pool,
         &appstate.mail_tx,
         insecure_ip,
@@ -460,6 +843,12 @@ pub(crate) async fn auth_callback(
     )
     .await?;
 
+    if let Some((provider_id, id_token)) = id_token {
+        session
+            .set_id_token(&appstate.pool, provider_id, id_token)
+            .await?;
+    }
+
     let max_age = Duration::seconds(config.auth_cookie_timeout.as_secs() as i64);
     let cookie_domain = config
         .cookie_domain
@@ -523,3 +912,258 @@ pub(crate) async fn auth_callback(
         unimplemented!("Impossible to get here");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{routing::post, serve, Router};
+    use openidconnect::{core::CoreJsonWebKeySet, AuthUrl, TokenUrl};
+    use sha2::{Digest, Sha256};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    fn make_client(token_url: &str) -> CoreClient {
+        CoreClient::new(
+            ClientId::new("defguard".into()),
+            Some(ClientSecret::new("secret".into())),
+            IssuerUrl::new("https://idp.example.com".into()).unwrap(),
+            AuthUrl::new("https://idp.example.com/authorize".into()).unwrap(),
+            Some(TokenUrl::new(token_url.into()).unwrap()),
+            None,
+            CoreJsonWebKeySet::default(),
+        )
+        .set_redirect_uri(
+            RedirectUrl::new("https://defguard.example.com/auth/callback".into()).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_authorization_url_pkce() {
+        let client = make_client("https://idp.example.com/token");
+
+        let (url, csrf_state, _nonce, verifier) = authorization_url(&client, true);
+        let verifier = verifier.unwrap();
+        let params: HashMap<String, String> = url.query_pairs().into_owned().collect();
+        assert_eq!(params["state"], *csrf_state.secret());
+        assert_eq!(params["code_challenge_method"], "S256");
+        assert_eq!(
+            params["code_challenge"],
+            BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.secret()))
+        );
+
+        let (url, _csrf_state, _nonce, verifier) = authorization_url(&client, false);
+        assert!(verifier.is_none());
+        let params: HashMap<String, String> = url.query_pairs().into_owned().collect();
+        assert!(!params.contains_key("code_challenge"));
+        assert!(!params.contains_key("code_challenge_method"));
+    }
+
+    fn form_params(body: &str) -> HashMap<String, String> {
+        Url::parse(&format!("http://localhost/?{body}"))
+            .unwrap()
+            .query_pairs()
+            .into_owned()
+            .collect()
+    }
+
+    /// Serve a token endpoint which records request bodies.
+    async fn start_token_server() -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!(
+            "http://127.0.0.1:{}/token",
+            listener.local_addr().unwrap().port()
+        );
+        let bodies = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&bodies);
+        let app = Router::new().route(
+            "/token",
+            post(move |body: String| async move {
+                recorded.lock().unwrap().push(body);
+                Json(json!({ "access_token": "at", "token_type": "bearer" }))
+            }),
+        );
+        tokio::spawn(async move {
+            serve(listener, app).await.unwrap();
+        });
+
+        (url, bodies)
+    }
+
+    #[tokio::test]
+    async fn test_exchange_code_pkce() {
+        let (token_url, bodies) = start_token_server().await;
+        let client = make_client(&token_url);
+
+        let verifier = PkceCodeVerifier::new("verifier-secret-0123456789-0123456789-0123".into());
+        let token = exchange_code(
+            &client,
+            AuthorizationCode::new("code".into()),
+            Some(verifier),
+        )
+        .await
+        .unwrap();
+        assert_eq!(token.access_token().secret(), "at");
+        exchange_code(&client, AuthorizationCode::new("code".into()), None)
+            .await
+            .unwrap();
+
+        let bodies = bodies.lock().unwrap();
+        assert_eq!(bodies.len(), 2);
+        let params = form_params(&bodies[0]);
+        assert_eq!(params["code"], "code");
+        assert_eq!(
+            params["code_verifier"],
+            "verifier-secret-0123456789-0123456789-0123"
+        );
+        assert!(!form_params(&bodies[1]).contains_key("code_verifier"));
+    }
+
+    #[test]
+    fn test_rp_logout_url() {
+        let endpoint = Url::parse("https://idp.example.com/oauth2/logout?tenant=main").unwrap();
+        let redirect = Url::parse("https://defguard.example.com/").unwrap();
+        let url = rp_logout_url(endpoint, "header.payload.signature", "defguard", &redirect);
+
+        assert_eq!(url.host_str(), Some("idp.example.com"));
+        assert_eq!(url.path(), "/oauth2/logout");
+        let params: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+        assert_eq!(
+            params,
+            vec![
+                ("tenant".into(), "main".into()),
+                ("id_token_hint".into(), "header.payload.signature".into()),
+                ("client_id".into(), "defguard".into()),
+                (
+                    "post_logout_redirect_uri".into(),
+                    "https://defguard.example.com/".into()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_provider_nonce() {
+        let nonce = provider_nonce(42);
+        assert_eq!(nonce_provider_id(nonce.secret()), Some(42));
+        assert_ne!(nonce.secret(), provider_nonce(42).secret());
+
+        // plain random nonces aren't bound to a provider
+        assert_eq!(nonce_provider_id(Nonce::new_random().secret()), None);
+        assert_eq!(nonce_provider_id("abc.def"), None);
+    }
+
+    fn make_id_token(claims: &Value) -> String {
+        format!(
+            "{}.{}.signature",
+            BASE64_URL_SAFE_NO_PAD.encode(r#"{"alg":"RS256"}"#),
+            BASE64_URL_SAFE_NO_PAD.encode(claims.to_string())
+        )
+    }
+
+    #[test]
+    fn test_group_claim_values() {
+        let token = make_id_token(&json!({
+            "sub": "user",
+            "groups": ["vpn-admins", "vpn-users", 42],
+            "role": "operators",
+        }));
+        assert_eq!(
+            group_claim_values(&token, "groups"),
+            vec!["vpn-admins", "vpn-users"]
+        );
+        assert_eq!(group_claim_values(&token, "role"), vec!["operators"]);
+        assert!(group_claim_values(&token, "missing").is_empty());
+        assert!(group_claim_values("not a token", "groups").is_empty());
+    }
+
+    async fn group_names(pool: &PgPool, user: &User<Id>) -> HashSet<String> {
+        user.member_of(pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|group| group.name)
+            .collect()
+    }
+
+    #[sqlx::test]
+    async fn test_apply_group_mapping(pool: PgPool) {
+        let (wg_tx, _wg_rx) = tokio::sync::broadcast::channel(16);
+        let user = User::new(
+            "hpotter",
+            Some("pass123"),
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+        for name in ["admins", "vpn", "ops", "staff"] {
+            Group::new(name).save(&pool).await.unwrap();
+        }
+        for name in ["ops", "staff"] {
+            let group = Group::find_by_name(&pool, name).await.unwrap().unwrap();
+            user.add_to_group(&pool, &group).await.unwrap();
+        }
+        let mut mapping = GroupMapping {
+            groups: [
+                ("vpn-admins", "admins"),
+                ("vpn-users", "vpn"),
+                ("operators", "ops"),
+                ("auditors", "missing"),
+            ]
+            .into_iter()
+            .map(|(claim, group)| (claim.to_string(), group.to_string()))
+            .collect(),
+            ..Default::default()
+        };
+
+        // additive mode only adds users to granted groups
+        let token = make_id_token(&json!({ "groups": ["vpn-admins", "vpn-users", "auditors"] }));
+        let claim_values = group_claim_values(&token, &mapping.claim);
+        apply_group_mapping(&pool, &user, &mapping, &claim_values, &wg_tx)
+            .await
+            .unwrap();
+        assert_eq!(
+            group_names(&pool, &user).await,
+            HashSet::from(["admins", "vpn", "ops", "staff"].map(String::from))
+        );
+
+        // authoritative mode removes users from mapped groups which aren't granted,
+        // groups outside of the mapping are left untouched
+        mapping.mode = GroupMappingMode::Authoritative;
+        let token = make_id_token(&json!({ "groups": ["vpn-users"] }));
+        let claim_values = group_claim_values(&token, &mapping.claim);
+        apply_group_mapping(&pool, &user, &mapping, &claim_values, &wg_tx)
+            .await
+            .unwrap();
+        assert_eq!(
+            group_names(&pool, &user).await,
+            HashSet::from(["vpn", "staff"].map(String::from))
+        );
+
+        // empty mapping doesn't change anything
+        let token = make_id_token(&json!({}));
+        let claim_values = group_claim_values(&token, "groups");
+        apply_group_mapping(
+            &pool,
+            &user,
+            &GroupMapping::default(),
+            &claim_values,
+            &wg_tx,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            group_names(&pool, &user).await,
+            HashSet::from(["vpn", "staff"].map(String::from))
+        );
+    }
+}
@@ -6,13 +6,14 @@ use axum::{
 use rsa::{pkcs8::DecodePrivateKey, RsaPrivateKey};
 use serde_json::json;
 
-use super::LicenseInfo;
+use super::{openid_login::test_provider_connection, LicenseInfo};
 use crate::{
     appstate::AppState,
     auth::{AdminRole, SessionInfo},
     db::{models::settings::update_current_settings, Settings},
     enterprise::{
-        db::models::openid_provider::OpenIdProvider, directory_sync::test_directory_sync_connection,
+        db::models::openid_provider::{GroupMapping, OpenIdProvider},
+        directory_sync::test_directory_sync_connection,
     },
     handlers::{ApiResponse, ApiResult},
 };
@@ -36,6 +37,17 @@ pub struct AddProviderData {
     pub okta_private_jwk: Option<String>,
     pub okta_dirsync_client_id: Option<String>,
     pub directory_sync_group_match: Option<String>,
+    #[serde(default)]
+    pub rp_initiated_logout: bool,
+    #[serde(default)]
+    pub group_mapping: GroupMapping,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TestProviderData {
+    pub base_url: String,
+    pub client_id: String,
+    pub client_secret: String,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -50,7 +62,8 @@ pub async fn add_openid_provider(
     State(appstate): State<AppState>,
     Json(provider_data): Json<AddProviderData>,
 ) -> ApiResult {
-    let current_provider = OpenIdProvider::get_current(&appstate.pool).await?;
+    let current_provider =
+        OpenIdProvider::find_by_name(&appstate.pool, &provider_data.name).await?;
 
     // The key is sent from the frontend only when user explicitly changes it, as we never send it back.
     // Check if the thing received from the frontend is a valid RSA private key (signaling user intent to change key)
@@ -122,7 +135,7 @@ pub async fn add_openid_provider(
         vec![]
     };
 
-    // Currently, we only support one OpenID provider at a time
+    // Providers are identified by name, an existing one with the same name is updated
     let new_provider = OpenIdProvider::new(
         provider_data.name,
         provider_data.base_url,
@@ -140,6 +153,8 @@ pub async fn add_openid_provider(
         okta_private_jwk,
         provider_data.okta_dirsync_client_id,
         group_match,
+        provider_data.rp_initiated_logout,
+        provider_data.group_mapping,
     )
     .upsert(&appstate.pool)
     .await?;
@@ -237,7 +252,8 @@ pub async fn modify_openid_provider(
     if let Some(mut provider) = provider {
         provider.base_url = provider_data.base_url;
         provider.client_id = provider_data.client_id;
-        provider.client_secret = provider_data.client_secret;
+        provider.client_secret = provider_data.client_secret.into();
+        provider.validate(false).await?;
         provider.save(&appstate.pool).await?;
         info!(
             "User {} modified OpenID client {}",
@@ -301,3 +317,41 @@ pub async fn test_dirsync_connection(
         status: StatusCode::OK,
     })
 }
+
+/// Check provider discovery and client credentials without saving the provider.
+pub async fn test_openid_provider(
+    _license: LicenseInfo,
+    _admin: AdminRole,
+    session: SessionInfo,
+    Json(provider_data): Json<TestProviderData>,
+) -> ApiResult {
+    debug!(
+        "User {} testing OpenID provider connection to {}",
+        session.user.username, provider_data.base_url
+    );
+
+    if let Err(err) = test_provider_connection(
+        &provider_data.base_url,
+        &provider_data.client_id,
+        &provider_data.client_secret,
+    )
+    .await
+    {
+        warn!(
+            "User {} tested OpenID provider connection to {}, the connection failed: {err}",
+            session.user.username, provider_data.base_url
+        );
+        return Ok(ApiResponse {
+            json: json!({ "message": err.to_string(), "error": err.kind(), "success": false }),
+            status: StatusCode::OK,
+        });
+    }
+    info!(
+        "User {} tested OpenID provider connection to {}, the connection was successful",
+        session.user.username, provider_data.base_url
+    );
+    Ok(ApiResponse {
+        json: json!({ "message": "Connection successful", "success": true }),
+        status: StatusCode::OK,
+    })
+}
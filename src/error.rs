@@ -1,4 +1,6 @@
 use axum::http::StatusCode;
+use chrono::NaiveDateTime;
+use serde::Serialize;
 use sqlx::error::Error as SqlxError;
 use thiserror::Error;
 
@@ -8,9 +10,10 @@ use crate::{
         device::DeviceError, enrollment::TokenError, error::ModelError,
         settings::SettingsValidationError, wireguard::WireguardNetworkError,
     },
-    enterprise::license::LicenseError,
+    enterprise::{db::models::openid_provider::OpenIdProviderError, license::LicenseError},
     grpc::GatewayMapError,
     ldap::error::LdapError,
+    password::PasswordError,
     templates::TemplateError,
 };
 
@@ -57,6 +60,43 @@ pub enum WebError {
     LicenseError(#[from] LicenseError),
     #[error("Failed to get client IP address")]
     ClientIpError,
+    #[error("Recovery codes can be regenerated in {0} seconds")]
+    RecoveryCodesCooldown(i64),
+    #[error("Maximum number of security keys ({0}) reached")]
+    WebauthnKeyLimit(usize),
+    #[error("Key is already registered by another user")]
+    AuthenticationKeyConflict,
+    #[error("User is suspended until {0}")]
+    UserSuspended(NaiveDateTime),
+    #[error("Password change required")]
+    PasswordChangeRequired,
+    #[error("Validation failed: {}", format_field_errors(.0))]
+    Validation(Vec<FieldError>),
+}
+
+/// Single failed check of a request field, reported as part of [`WebError::Validation`].
+#[derive(Debug, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    #[must_use]
+    pub fn new<F: Into<String>, M: Into<String>>(field: F, message: M) -> Self {
+        Self {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+fn format_field_errors(errors: &[FieldError]) -> String {
+    errors
+        .iter()
+        .map(|error| format!("{}: {}", error.field, error.message))
+        .collect::<Vec<_>>()
+        .join(", ")
 }
 
 impl From<tonic::Status> for WebError {
@@ -148,14 +188,37 @@ impl From<TokenError> for WebError {
             | TokenError::SessionExpired
             | TokenError::TokenUsed
             | TokenError::UserDisabled => WebError::Authorization(err.to_string()),
-            TokenError::AlreadyActive => WebError::BadRequest(err.to_string()),
+            TokenError::AlreadyActive | TokenError::NoEmail => {
+                WebError::BadRequest(err.to_string())
+            }
             TokenError::NotificationError(_)
+            | TokenError::SmsError(_)
             | TokenError::WelcomeMsgNotConfigured
             | TokenError::WelcomeEmailNotConfigured
             | TokenError::TemplateError(_)
-            | TokenError::TemplateErrorInternal(_) => {
-                WebError::Http(StatusCode::INTERNAL_SERVER_ERROR)
+            | TokenError::TemplateErrorInternal(_)
+            // timeouts come from server configuration, which is validated on startup
+            | TokenError::InvalidTimeout(..) => WebError::Http(StatusCode::INTERNAL_SERVER_ERROR),
+        }
+    }
+}
+
+impl From<PasswordError> for WebError {
+    fn from(err: PasswordError) -> Self {
+        match err {
+            PasswordError::Hash(_) | PasswordError::History(_) => {
+                Self::Http(StatusCode::INTERNAL_SERVER_ERROR)
             }
+            _ => Self::BadRequest(err.to_string()),
+        }
+    }
+}
+
+impl From<OpenIdProviderError> for WebError {
+    fn from(err: OpenIdProviderError) -> Self {
+        match err {
+            OpenIdProviderError::InvalidConfig(_) => Self::BadRequest(err.to_string()),
+            OpenIdProviderError::Db(err) => err.into(),
         }
     }
 }
@@ -163,7 +226,8 @@ impl From<TokenError> for WebError {
 impl From<SettingsValidationError> for WebError {
     fn from(err: SettingsValidationError) -> Self {
         match err {
-            SettingsValidationError::CannotEnableGatewayNotifications => {
+            SettingsValidationError::CannotEnableGatewayNotifications
+            | SettingsValidationError::InvalidSecurityNotificationEmail => {
                 Self::BadRequest(err.to_string())
             }
         }
@@ -56,8 +56,12 @@ impl auth_service_server::AuthService for AuthServer {
         check_username(&self.failed_logins, &request.username)
             .map_err(|_| Status::resource_exhausted("too many login requests"))?;
 
-        if let Ok(Some(user)) = User::find_by_username(&self.pool, &request.username).await {
-            if user.verify_password(&request.password).is_ok() {
+        if let Ok(Some(mut user)) = User::find_by_username(&self.pool, &request.username).await {
+            if user
+                .verify_and_upgrade_password(&self.pool, &request.password)
+                .await
+                .is_ok()
+            {
                 info!("Authentication successful for user {}", request.username);
                 Ok(Response::new(AuthenticateResponse {
                     token: Self::create_jwt(&request.username).map_err(|_| {
@@ -64,6 +64,19 @@ impl ClientMfaServer {
         })
     }
 
+    /// Make sure a disabled or suspended user can't regain VPN access through MFA.
+    fn ensure_user_allowed(user: &User<Id>) -> Result<(), Status> {
+        if !user.is_active {
+            error!("User {} is disabled", user.username);
+            return Err(Status::unauthenticated("unauthorized"));
+        }
+        if user.is_suspended() {
+            error!("User {} is suspended", user.username);
+            return Err(Status::unauthenticated("unauthorized"));
+        }
+        Ok(())
+    }
+
     /// Validate JWT and extract client pubkey
     fn parse_token(token: &str) -> Result<String, Status> {
         let claims = Claims::from_jwt(ClaimsType::DesktopClient, token).map_err(|err| {
@@ -97,6 +110,7 @@ impl ClientMfaServer {
             error!("Failed to find user with ID {}", device.user_id);
             return Err(Status::invalid_argument("user not found"));
         };
+        Self::ensure_user_allowed(&user)?;
         let user_info = UserInfo::from_user(&self.pool, &user).await.map_err(|_| {
             error!("Failed to fetch user info for {}", user.username);
             Status::internal("unexpected error")
@@ -211,6 +225,13 @@ impl ClientMfaServer {
             user,
         } = session;
 
+        // user state might have changed since the login was started
+        let Ok(Some(current_user)) = User::find_by_id(&self.pool, user.id).await else {
+            error!("Failed to find user with ID {}", user.id);
+            return Err(Status::invalid_argument("user not found"));
+        };
+        Self::ensure_user_allowed(&current_user)?;
+
         // validate code
         match method {
             MfaMethod::Totp => {
@@ -294,3 +315,99 @@ impl ClientMfaServer {
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeDelta;
+    use tokio::sync::{broadcast, mpsc::unbounded_channel};
+    use tonic::Code;
+
+    use super::*;
+    use crate::{config::DefGuardConfig, SERVER_CONFIG};
+
+    async fn setup(pool: &PgPool) -> (ClientMfaServer, WireguardNetwork<Id>, User<Id>) {
+        let _ = SERVER_CONFIG.set(DefGuardConfig::new_test_config());
+        let mut network = WireguardNetwork::default();
+        network.try_set_address("10.1.1.1/29").unwrap();
+        let network = network.save(pool).await.unwrap();
+        let user = User::new(
+            "hpotter",
+            Some("Pass123!"),
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(pool)
+        .await
+        .unwrap();
+        Device::new_with_ip(pool, user.id, "dev1".into(), "key1".into(), &network)
+            .await
+            .unwrap();
+        let (mail_tx, _mail_rx) = unbounded_channel();
+        let (wg_tx, _wg_rx) = broadcast::channel(16);
+        let server = ClientMfaServer::new(pool.clone(), mail_tx, wg_tx);
+
+        (server, network, user)
+    }
+
+    #[sqlx::test]
+    async fn test_start_rejects_suspended_and_disabled_users(pool: PgPool) {
+        let (mut server, network, mut user) = setup(&pool).await;
+        let request = ClientMfaStartRequest {
+            location_id: network.id,
+            pubkey: "key1".into(),
+            method: MfaMethod::Totp.into(),
+        };
+
+        // active user only fails because TOTP isn't enabled
+        let status = server
+            .start_client_mfa_login(request.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+
+        user.suspended_until = Some(Utc::now().naive_utc() + TimeDelta::hours(1));
+        user.save(&pool).await.unwrap();
+        let status = server
+            .start_client_mfa_login(request.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+
+        user.suspended_until = None;
+        user.is_active = false;
+        user.save(&pool).await.unwrap();
+        let status = server.start_client_mfa_login(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+    }
+
+    #[sqlx::test]
+    async fn test_finish_rejects_user_suspended_after_start(pool: PgPool) {
+        let (mut server, network, mut user) = setup(&pool).await;
+        let device = Device::find_by_pubkey(&pool, "key1")
+            .await
+            .unwrap()
+            .unwrap();
+        server.sessions.insert(
+            "key1".into(),
+            ClientLoginSession {
+                method: MfaMethod::Totp,
+                location: network,
+                device,
+                user: user.clone(),
+            },
+        );
+
+        user.suspended_until = Some(Utc::now().naive_utc() + TimeDelta::hours(1));
+        user.save(&pool).await.unwrap();
+
+        let request = ClientMfaFinishRequest {
+            token: ClientMfaServer::generate_token("key1").unwrap(),
+            code: 0,
+        };
+        let status = server.finish_client_mfa_login(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+    }
+}
@@ -16,9 +16,10 @@ use crate::{
         models::{
             device::{DeviceConfig, DeviceInfo, DeviceType},
             enrollment::{Token, TokenError, ENROLLMENT_TOKEN_TYPE},
+            enrollment_event::{EnrollmentEvent, EnrollmentEventType},
             polling_token::PollingToken,
         },
-        Device, GatewayEvent, Id, Settings, User,
+        AppEvent, Device, GatewayEvent, Id, Settings, User,
     },
     enterprise::{db::models::enterprise_settings::EnterpriseSettings, limits::update_counts},
     grpc::utils::{build_device_config_response, new_polling_token},
@@ -26,6 +27,7 @@ use crate::{
     headers::get_device_info,
     ldap::utils::ldap_add_user,
     mail::Mail,
+    password::PasswordError,
     server_config,
     templates::{self, TemplateLocation},
 };
@@ -34,6 +36,7 @@ pub(super) struct EnrollmentServer {
     pool: PgPool,
     wireguard_tx: Sender<GatewayEvent>,
     mail_tx: UnboundedSender<Mail>,
+    webhook_tx: UnboundedSender<AppEvent>,
     ldap_feature_active: bool,
 }
 
@@ -43,6 +46,7 @@ impl EnrollmentServer {
         pool: PgPool,
         wireguard_tx: Sender<GatewayEvent>,
         mail_tx: UnboundedSender<Mail>,
+        webhook_tx: UnboundedSender<AppEvent>,
     ) -> Self {
         // FIXME: check if LDAP feature is enabled
         let ldap_feature_active = true;
@@ -50,6 +54,7 @@ impl EnrollmentServer {
             pool,
             wireguard_tx,
             mail_tx,
+            webhook_tx,
             ldap_feature_active,
         }
     }
@@ -93,6 +98,7 @@ impl EnrollmentServer {
     pub async fn start_enrollment(
         &self,
         request: EnrollmentStartRequest,
+        req_device_info: Option<super::proto::DeviceInfo>,
     ) -> Result<EnrollmentStartResponse, Status> {
         debug!("Starting enrollment session, request: {request:?}");
         // fetch enrollment token
@@ -139,6 +145,12 @@ impl EnrollmentServer {
                 .start_session(
                     &mut transaction,
                     server_config().enrollment_session_timeout.as_secs(),
+                    req_device_info
+                        .as_ref()
+                        .and_then(|info| info.ip_address.as_deref()),
+                    req_device_info
+                        .as_ref()
+                        .and_then(|info| info.user_agent.as_deref()),
                 )
                 .await?;
             info!(
@@ -242,20 +254,22 @@ impl EnrollmentServer {
         let enrollment = self.validate_session(request.token.as_ref()).await?;
 
         let ip_address;
+        let user_agent;
         let device_info;
         if let Some(info) = req_device_info {
             ip_address = info.ip_address.unwrap_or_default();
-            let user_agent = info.user_agent.unwrap_or_default();
+            user_agent = info.user_agent.unwrap_or_default();
             device_info = Some(get_device_info(&user_agent));
         } else {
             ip_address = String::new();
+            user_agent = String::new();
             device_info = None;
         }
         debug!("IP address {}, device info {device_info:?}", ip_address);
 
         // check if password is strong enough
         debug!("Verifying password strength for user activation process.");
-        if let Err(err) = check_password_strength(&request.password) {
+        if let Err(err) = check_password_strength(&request.password).await {
             error!("Password not strong enough: {err}");
             return Err(Status::invalid_argument("password not strong enough"));
         }
@@ -291,7 +305,15 @@ impl EnrollmentServer {
         // update user
         info!("Update user details and set a new password.");
         user.phone = request.phone_number;
-        user.set_password(&request.password);
+        user.set_password(&mut transaction, &request.password)
+            .await
+            .map_err(|err| {
+                error!("Failed to set password for user {}: {err}", user.username);
+                match err {
+                    PasswordError::Reused(_) => Status::invalid_argument(err.to_string()),
+                    _ => Status::internal("unexpected error"),
+                }
+            })?;
         user.save(&mut *transaction).await.map_err(|err| {
             error!("Failed to update user {}: {err}", user.username);
             Status::internal("unexpected error")
@@ -340,12 +362,29 @@ impl EnrollmentServer {
             )?;
         }
 
+        EnrollmentEvent::new(
+            &enrollment.id,
+            enrollment.user_id,
+            EnrollmentEventType::Completed,
+        )
+        .with_client(Some(&ip_address), Some(&user_agent))
+        .save(&mut *transaction)
+        .await
+        .map_err(|err| {
+            error!(
+                "Failed to record enrollment completion of user {}: {err}",
+                user.username
+            );
+            Status::internal("unexpected error")
+        })?;
+
         transaction.commit().await.map_err(|err| {
             error!("Failed to commit transaction: {err}");
             Status::internal("unexpected error")
         })?;
 
         info!("User {} activated", user.username);
+        Token::notify_enrollment_completed(&self.webhook_tx, &user);
         Ok(())
     }
 
@@ -830,3 +869,120 @@ impl Token {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use chrono::Utc;
+    use sqlx::types::Json;
+    use tokio::sync::{broadcast, mpsc::unbounded_channel};
+
+    use super::*;
+    use crate::{
+        config::DefGuardConfig,
+        db::{models::settings::initialize_current_settings, NoId, WebHook},
+        SERVER_CONFIG,
+    };
+
+    #[sqlx::test]
+    async fn test_activate_user_notifies_enrollment_completed(pool: PgPool) {
+        let _ = SERVER_CONFIG.set(DefGuardConfig::new_test_config());
+        initialize_current_settings(&pool).await.unwrap();
+        let user = User::new(
+            "hpotter",
+            None,
+            "Potter",
+            "Harry",
+            "h.potter@hogwart.edu.uk",
+            None,
+        )
+        .unwrap()
+        .save(&pool)
+        .await
+        .unwrap();
+        let mut webhook = WebHook {
+            id: NoId,
+            url: "http://localhost:3000/enrollment".into(),
+            description: "Enrollment".into(),
+            token: "1234567890".into(),
+            enabled: true,
+            on_user_created: false,
+            on_user_deleted: false,
+            on_user_modified: false,
+            on_hwkey_provision: false,
+            on_enrollment_pending: false,
+            on_enrollment_completed: true,
+            on_user_mfa_changed: false,
+            group_id: None,
+            paused_until: None,
+            custom_headers: Json(HashMap::new()),
+            body_template: None,
+            max_attempts: 5,
+            retry_base_delay: 10,
+            archived: false,
+        }
+        .save(&pool)
+        .await
+        .unwrap();
+
+        let mut enrollment = Token::new(
+            user.id,
+            None,
+            Some(user.email.clone()),
+            3600,
+            Some(ENROLLMENT_TOKEN_TYPE.into()),
+        );
+        enrollment.save(&pool).await.unwrap();
+        let mut transaction = pool.begin().await.unwrap();
+        enrollment
+            .start_session(&mut transaction, 600, None, None)
+            .await
+            .unwrap();
+        transaction.commit().await.unwrap();
+
+        let (wireguard_tx, _wireguard_rx) = broadcast::channel(16);
+        let (mail_tx, _mail_rx) = unbounded_channel();
+        let (webhook_tx, mut webhook_rx) = unbounded_channel();
+        let server = EnrollmentServer::new(pool.clone(), wireguard_tx, mail_tx, webhook_tx);
+
+        let started = Utc::now().naive_utc();
+        let request = ActivateUserRequest {
+            password: "Alohomora123!".into(),
+            token: Some(enrollment.id.clone()),
+            ..Default::default()
+        };
+        server.activate_user(request.clone(), None).await.unwrap();
+
+        let Ok(event) = webhook_rx.try_recv() else {
+            panic!("Expected enrollment completed event");
+        };
+        let AppEvent::EnrollmentCompleted(data) = &event else {
+            panic!("Expected enrollment completed event, got {}", event.name());
+        };
+        assert_eq!(data.user_id, user.id);
+        assert_eq!(data.username, "hpotter");
+        assert!(data.completed_at >= started);
+        assert!(webhook_rx.try_recv().is_err());
+
+        let (payload, name) = event.payload();
+        assert_eq!(name, "enrollment_completed");
+        assert_eq!(payload["user_id"], user.id);
+
+        let webhooks = WebHook::all_enabled(&pool, &event).await.unwrap();
+        assert_eq!(webhooks.len(), 1);
+        assert_eq!(webhooks[0].id, webhook.id);
+
+        // webhooks without the flag are not triggered
+        webhook.on_enrollment_completed = false;
+        webhook.save(&pool).await.unwrap();
+        assert!(WebHook::all_enabled(&pool, &event)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // failed activation doesn't notify
+        assert!(server.activate_user(request, None).await.is_err());
+        assert!(webhook_rx.try_recv().is_err());
+    }
+}
@@ -73,6 +73,7 @@ impl WireguardNetwork<Id> {
             WHERE wireguard_network_id = $1 AND (is_authorized = true OR NOT $2) \
             AND d.configured = true \
             AND u.is_active = true \
+            AND (u.suspended_until IS NULL OR u.suspended_until <= NOW()) \
             ORDER BY d.id ASC",
             self.id,
             self.mfa_enabled
@@ -56,7 +56,9 @@ use crate::{
         db::models::{enterprise_settings::EnterpriseSettings, openid_provider::OpenIdProvider},
         directory_sync::sync_user_groups_if_configured,
         grpc::polling::PollingServer,
-        handlers::openid_login::{make_oidc_client, user_from_claims},
+        handlers::openid_login::{
+            make_oidc_client, nonce_provider_id, provider_nonce, user_from_claims,
+        },
         is_enterprise_enabled,
     },
     handlers::mail::{send_gateway_disconnected_email, send_gateway_reconnected_email},
@@ -436,12 +438,17 @@ pub async fn run_grpc_bidi_stream(
     pool: PgPool,
     wireguard_tx: Sender<GatewayEvent>,
     mail_tx: UnboundedSender<Mail>,
+    webhook_tx: UnboundedSender<AppEvent>,
 ) -> Result<(), anyhow::Error> {
     let config = server_config();
 
     // TODO: merge the two
-    let enrollment_server =
-        EnrollmentServer::new(pool.clone(), wireguard_tx.clone(), mail_tx.clone());
+    let enrollment_server = EnrollmentServer::new(
+        pool.clone(),
+        wireguard_tx.clone(),
+        mail_tx.clone(),
+        webhook_tx,
+    );
     let password_reset_server = PasswordResetServer::new(pool.clone(), mail_tx.clone());
     let mut client_mfa_server = ClientMfaServer::new(pool.clone(), mail_tx, wireguard_tx.clone());
     let polling_server = PollingServer::new(pool.clone());
@@ -485,7 +492,10 @@ pub async fn run_grpc_bidi_stream(
                     let payload = match received.payload {
                         // rpc StartEnrollment (EnrollmentStartRequest) returns (EnrollmentStartResponse)
                         Some(core_request::Payload::EnrollmentStart(request)) => {
-                            match enrollment_server.start_enrollment(request).await {
+                            match enrollment_server
+                                .start_enrollment(request, received.device_info)
+                                .await
+                            {
                                 Ok(response_payload) => {
                                     Some(core_response::Payload::EnrollmentStart(response_payload))
                                 }
@@ -550,7 +560,10 @@ pub async fn run_grpc_bidi_stream(
                         }
                         // rpc StartPasswordReset (PasswordResetStartRequest) returns (PasswordResetStartResponse)
                         Some(core_request::Payload::PasswordResetStart(request)) => {
-                            match password_reset_server.start_password_reset(request).await {
+                            match password_reset_server
+                                .start_password_reset(request, received.device_info)
+                                .await
+                            {
                                 Ok(response_payload) => Some(
                                     core_response::Payload::PasswordResetStart(response_payload),
                                 ),
@@ -625,6 +638,8 @@ pub async fn run_grpc_bidi_stream(
                                     message: "no valid license".into(),
                                 }))
                             } else if let Ok(redirect_url) = Url::parse(&request.redirect_url) {
+                                // proxy requests don't name the provider, so the first one is used;
+                                // nonce records it for the callback
                                 if let Some(provider) = OpenIdProvider::get_current(&pool).await? {
                                     if let Ok((_client_id, client)) =
                                         make_oidc_client(redirect_url, &provider).await
@@ -633,7 +648,7 @@ pub async fn run_grpc_bidi_stream(
                                             .authorize_url(
                                                 CoreAuthenticationFlow::AuthorizationCode,
                                                 CsrfToken::new_random,
-                                                Nonce::new_random,
+                                                || provider_nonce(provider.id),
                                             )
                                             .add_scope(Scope::new("email".to_string()))
                                             .add_scope(Scope::new("profile".to_string()))
@@ -668,15 +683,26 @@ pub async fn run_grpc_bidi_stream(
                             match Url::parse(&request.callback_url) {
                                 Ok(callback_url) => {
                                     let code = AuthorizationCode::new(request.code);
+                                    // provider which issued the authorization URL
+                                    let provider_name = match nonce_provider_id(&request.nonce) {
+                                        Some(id) => OpenIdProvider::find_by_id(&pool, id)
+                                            .await?
+                                            .map(|provider| provider.name),
+                                        None => None,
+                                    };
                                     match user_from_claims(
                                         &pool,
+                                        &wireguard_tx,
+                                        provider_name.as_deref(),
                                         Nonce::new(request.nonce),
                                         code,
+                                        // proxy doesn't pass PKCE verifier
+                                        None,
                                         callback_url,
                                     )
                                     .await
                                     {
-                                        Ok(user) => {
+                                        Ok((user, _)) => {
                                             user.clear_unused_enrollment_tokens(&pool).await?;
                                             if let Err(err) = sync_user_groups_if_configured(
                                                 &user,
@@ -813,6 +839,9 @@ pub struct Job {
     last_name: String,
     email: String,
     username: String,
+    // YubiKey model and firmware version, as declared when scheduling the job
+    yubikey_model: Option<String>,
+    yubikey_firmware_version: Option<String>,
 }
 
 #[cfg(feature = "worker")]
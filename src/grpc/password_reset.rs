@@ -17,6 +17,7 @@ use crate::{
     },
     ldap::utils::ldap_change_password,
     mail::Mail,
+    password::PasswordError,
     server_config,
 };
 
@@ -151,6 +152,7 @@ impl PasswordResetServer {
     pub async fn start_password_reset(
         &self,
         request: PasswordResetStartRequest,
+        req_device_info: Option<DeviceInfo>,
     ) -> Result<PasswordResetStartResponse, Status> {
         debug!("Starting password reset session: {request:?}");
 
@@ -185,6 +187,12 @@ impl PasswordResetServer {
             .start_session(
                 &mut transaction,
                 server_config().password_reset_session_timeout.as_secs(),
+                req_device_info
+                    .as_ref()
+                    .and_then(|info| info.ip_address.as_deref()),
+                req_device_info
+                    .as_ref()
+                    .and_then(|info| info.user_agent.as_deref()),
             )
             .await?;
 
@@ -223,7 +231,7 @@ impl PasswordResetServer {
             user_agent = String::new();
         }
 
-        if let Err(err) = check_password_strength(&request.password) {
+        if let Err(err) = check_password_strength(&request.password).await {
             error!("Password not strong enough: {err}");
             return Err(Status::invalid_argument("password not strong enough"));
         }
@@ -244,7 +252,15 @@ impl PasswordResetServer {
         })?;
 
         // update user
-        user.set_password(&request.password);
+        user.set_password(&mut transaction, &request.password)
+            .await
+            .map_err(|err| {
+                error!("Failed to set password for user {}: {err}", user.username);
+                match err {
+                    PasswordError::Reused(_) => Status::invalid_argument(err.to_string()),
+                    _ => Status::internal("unexpected error"),
+                }
+            })?;
         user.save(&mut *transaction).await.map_err(|err| {
             error!("Failed to update user {}: {err}", user.username);
             Status::internal("unexpected error")
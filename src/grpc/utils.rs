@@ -115,6 +115,7 @@ pub(crate) async fn build_device_config_response(
                 .map(IpNetwork::to_string)
                 .collect::<Vec<String>>()
                 .join(",");
+            let dns = network.dns_entries();
             let config = ProtoDeviceConfig {
                 config: Device::create_config(&network, &wireguard_network_device),
                 network_id: network.id,
@@ -123,7 +124,7 @@ pub(crate) async fn build_device_config_response(
                 endpoint: format!("{}:{}", network.endpoint, network.port),
                 pubkey: network.pubkey,
                 allowed_ips,
-                dns: network.dns,
+                dns,
                 mfa_enabled: network.mfa_enabled,
                 keepalive_interval: network.keepalive_interval,
             };
@@ -148,6 +149,7 @@ pub(crate) async fn build_device_config_response(
                     .map(IpNetwork::to_string)
                     .collect::<Vec<String>>()
                     .join(",");
+                let dns = network.dns_entries();
                 let config = ProtoDeviceConfig {
                     config: Device::create_config(&network, &wireguard_network_device),
                     network_id: network.id,
@@ -156,7 +158,7 @@ pub(crate) async fn build_device_config_response(
                     endpoint: format!("{}:{}", network.endpoint, network.port),
                     pubkey: network.pubkey,
                     allowed_ips,
-                    dns: network.dns,
+                    dns,
                     mfa_enabled: network.mfa_enabled,
                     keepalive_interval: network.keepalive_interval,
                 };
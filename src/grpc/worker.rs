@@ -103,6 +103,8 @@ impl WorkerState {
         last_name: String,
         email: String,
         username: String,
+        yubikey_model: Option<String>,
+        yubikey_firmware_version: Option<String>,
     ) -> u32 {
         if let Some(worker) = self.workers.get_mut(worker_id) {
             let id = self.current_job_id;
@@ -113,6 +115,8 @@ impl WorkerState {
                 last_name,
                 email,
                 username,
+                yubikey_model,
+                yubikey_firmware_version,
             });
             id
         } else {
@@ -229,7 +233,7 @@ impl worker_service_server::WorkerService for WorkerServer {
         );
         // Mutex manipulation is done explicitly in a separate block to avoid compiler errors
         // https://github.com/rust-lang/rust/issues/57478
-        let username: Option<String> = {
+        let job: Option<Job> = {
             let mut state = self.state.lock().unwrap();
             // Remove job from worker
             let job = state.remove_job(&message.id, message.job_id);
@@ -247,15 +251,15 @@ impl worker_service_server::WorkerService for WorkerServer {
                         }))
                         .expect("Failed to send event.");
                 }
-                Some(job_done.username)
+                Some(job_done)
             } else {
                 None
             }
         };
 
-        if let Some(username) = username {
+        if let Some(job) = job {
             if message.success {
-                match User::find_by_username(&self.pool, &username).await {
+                match User::find_by_username(&self.pool, &job.username).await {
                     // TODO: Create respectable Authentication KEYS and Add yubikey entry to DB table "yubikey"
                     Ok(Some(user)) => {
                         // create yubikey
@@ -276,6 +280,7 @@ impl worker_service_server::WorkerService for WorkerServer {
                             None => "YubiKey".to_string(),
                         };
                         let new_yubi = YubiKey::new(name, message.yubikey_serial, user.id)
+                            .with_metadata(job.yubikey_model, job.yubikey_firmware_version)
                             .save(&self.pool)
                             .await
                             .map_err(|_| Status::internal("Failed to save YubiKey"))?;
@@ -301,7 +306,7 @@ impl worker_service_server::WorkerService for WorkerServer {
                             .await
                             .map_err(|_| Status::internal("Failed to save auth key"))?;
                     }
-                    Ok(None) => info!("User {username} not found"),
+                    Ok(None) => info!("User {} not found", job.username),
                     Err(err) => error!("Error {err}"),
                 }
             }
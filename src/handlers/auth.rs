@@ -1,7 +1,7 @@
 use std::net::IpAddr;
 
 use axum::{
-    extract::{Json, State},
+    extract::{Json, Path, State},
     http::StatusCode,
 };
 use axum_client_ip::InsecureClientIp;
@@ -22,20 +22,26 @@ use webauthn_rs::prelude::PublicKeyCredential;
 use webauthn_rs_proto::options::CollectedClientData;
 
 use super::{
-    ApiResponse, ApiResult, Auth, AuthCode, AuthResponse, AuthTotp, RecoveryCode, RecoveryCodes,
-    WebAuthnRegistration, SESSION_COOKIE_NAME,
+    ApiResponse, ApiResult, Auth, AuthCode, AuthResponse, AuthTotp, AuthenticateWithMfaRequest,
+    RecoveryCode, RecoveryCodes, RecoveryCodesToken, VerifyTotpRequest, WebAuthnRegistration,
+    SESSION_COOKIE_NAME,
 };
 use crate::{
     appstate::AppState,
     auth::{
         failed_login::{check_username, log_failed_login_attempt},
-        SessionInfo,
+        SessionInfo, TOTP_ISSUER,
     },
-    db::{Id, MFAInfo, MFAMethod, Session, SessionState, Settings, User, UserInfo, WebAuthn},
+    db::{
+        models::{recovery_codes::RecoveryCodesBundle, webhook::MfaState},
+        Id, MFAInfo, MFAMethod, Session, SessionState, Settings, User, UserInfo, WebAuthn,
+    },
+    enterprise::handlers::openid_login::provider_logout_url,
     error::WebError,
     handlers::{
         mail::{
             send_email_mfa_activation_email, send_email_mfa_code_email, send_mfa_configured_email,
+            send_security_notification_email, SecurityEvent,
         },
         SIGN_IN_COOKIE_NAME,
     },
@@ -45,6 +51,31 @@ use crate::{
     server_config,
 };
 
+/// Generate recovery codes (if user has none) and park them in a short-lived bundle.
+/// Returns a one-time token which can be exchanged for the codes.
+async fn recovery_codes_token(
+    pool: &PgPool,
+    user: &mut User<Id>,
+) -> Result<Option<String>, WebError> {
+    let Some(codes) = user.get_recovery_codes(pool).await? else {
+        return Ok(None);
+    };
+    bundle_recovery_codes(pool, user, codes).await.map(Some)
+}
+
+/// Park recovery codes in a short-lived bundle and return a token for exchanging them.
+async fn bundle_recovery_codes(
+    pool: &PgPool,
+    user: &User<Id>,
+    codes: Vec<String>,
+) -> Result<String, WebError> {
+    let bundle = RecoveryCodesBundle::new(user.id, codes);
+    bundle.save(pool).await?;
+    debug!("Stored recovery codes bundle for user {}", user.username);
+
+    Ok(bundle.token().to_string())
+}
+
 /// Common functionality for `authenticate()` and `auth_callback()`.
 /// Returns either `AuthResponse` or `MFAInfo`.
 pub(crate) async fn create_session(
@@ -139,14 +170,20 @@ pub(crate) async fn authenticate(
     check_username(&appstate.failed_logins, &username)?;
 
     let mut user = match User::find_by_username(&appstate.pool, &username).await {
-        Ok(Some(user)) => match user.verify_password(&data.password) {
+        Ok(Some(mut user)) => match user
+            .verify_and_upgrade_password(&appstate.pool, &data.password)
+            .await
+        {
             Ok(()) => {
-                if user.is_active {
-                    user
-                } else {
+                if !user.is_active {
                     info!("Failed to authenticate user {username}: user is disabled");
                     return Err(WebError::Authorization("user not found".into()));
                 }
+                if let Some(until) = user.active_suspension() {
+                    info!("Failed to authenticate user {username}: user is suspended");
+                    return Err(WebError::UserSuspended(until));
+                }
+                user
             }
             Err(err) => {
                 info!("Failed to authenticate user {username}: {err}");
@@ -156,14 +193,20 @@ pub(crate) async fn authenticate(
         },
         Ok(None) => {
             match User::find_by_email(&appstate.pool, &username).await {
-                Ok(Some(user)) => match user.verify_password(&data.password) {
+                Ok(Some(mut user)) => match user
+                    .verify_and_upgrade_password(&appstate.pool, &data.password)
+                    .await
+                {
                     Ok(()) => {
-                        if user.is_active {
-                            user
-                        } else {
+                        if !user.is_active {
                             info!("Failed to authenticate user {username}: user is disabled");
                             return Err(WebError::Authorization("user not found".into()));
                         }
+                        if let Some(until) = user.active_suspension() {
+                            info!("Failed to authenticate user {username}: user is suspended");
+                            return Err(WebError::UserSuspended(until));
+                        }
+                        user
                     }
                     Err(err) => {
                         info!("Failed to authenticate user {username}: {err}");
@@ -204,6 +247,9 @@ pub(crate) async fn authenticate(
         &mut user,
     )
     .await?;
+    if user.must_change_password {
+        info!("User {username} has to change password before proceeding");
+    }
 
     let max_age = Duration::seconds(server_config().auth_cookie_timeout.as_secs() as i64);
     let config = server_config();
@@ -266,9 +312,24 @@ pub async fn logout(
 ) -> Result<(CookieJar, ApiResponse), WebError> {
     // remove auth cookie
     let cookies = cookies.remove(Cookie::from(SESSION_COOKIE_NAME));
+    // users logged in through OpenID provider may need to be logged out there as well
+    let provider_logout_url = match &session.id_token {
+        Some(id_token) => {
+            provider_logout_url(&appstate.pool, session.openid_provider_id, id_token).await
+        }
+        None => None,
+    };
     // remove stored session
     session.delete(&appstate.pool).await?;
-    Ok((cookies, ApiResponse::default()))
+
+    let response = match provider_logout_url {
+        Some(url) => ApiResponse {
+            json: json!({ "url": url }),
+            status: StatusCode::OK,
+        },
+        None => ApiResponse::default(),
+    };
+    Ok((cookies, response))
 }
 
 /// Enable MFA
@@ -280,9 +341,11 @@ pub async fn mfa_enable(
 ) -> Result<(CookieJar, ApiResponse), WebError> {
     let mut user = session_info.user;
     debug!("Enabling MFA for user {}", user.username);
+    let previous = MfaState::from(&user);
     user.enable_mfa(&appstate.pool).await?;
     if user.mfa_enabled {
         info!("Enabled MFA for user {}", user.username);
+        appstate.trigger_mfa_changed(&user, previous);
         let cookies = cookies.remove(Cookie::from("defguard_sesssion"));
         user.logout_all_sessions(&appstate.pool).await?;
         debug!(
@@ -300,8 +363,10 @@ pub async fn mfa_enable(
 pub async fn mfa_disable(session_info: SessionInfo, State(appstate): State<AppState>) -> ApiResult {
     let mut user = session_info.user;
     debug!("Disabling MFA for user {}", user.username);
+    let previous = MfaState::from(&user);
     user.disable_mfa(&appstate.pool).await?;
     info!("Disabled MFA for user {}", user.username);
+    appstate.trigger_mfa_changed(&user, previous);
     Ok(ApiResponse::default())
 }
 
@@ -310,6 +375,7 @@ pub async fn webauthn_init(
     mut session_info: SessionInfo,
     State(appstate): State<AppState>,
 ) -> ApiResult {
+    check_webauthn_key_limit(&appstate.pool, &session_info, session_info.user.id).await?;
     let user = session_info.user;
     info!(
         "Initializing WebAuthn registration for user {}",
@@ -341,6 +407,27 @@ pub async fn webauthn_init(
     }
 }
 
+/// Ensure a user can register another security key. Administrators are not limited.
+async fn check_webauthn_key_limit(
+    pool: &PgPool,
+    session: &SessionInfo,
+    user_id: Id,
+) -> Result<(), WebError> {
+    if session.is_admin {
+        return Ok(());
+    }
+    let limit = server_config().max_webauthn_keys_per_user;
+    let count = WebAuthn::count_for_user(pool, user_id).await?;
+    if count >= limit as i64 {
+        warn!(
+            "User {} reached the limit of {limit} security keys",
+            session.user.username
+        );
+        return Err(WebError::WebauthnKeyLimit(limit));
+    }
+    Ok(())
+}
+
 /// Finish WebAuthn registration
 pub async fn webauthn_finish(
     session: SessionInfo,
@@ -351,6 +438,7 @@ pub async fn webauthn_finish(
         "Finishing WebAuthn registration for user {}",
         session.user.username
     );
+    check_webauthn_key_limit(&appstate.pool, &session, session.session.user_id).await?;
     let passkey_reg =
         session
             .session
@@ -386,7 +474,8 @@ pub async fn webauthn_finish(
     let mut user = User::find_by_id(&appstate.pool, session.session.user_id)
         .await?
         .ok_or(WebError::WebauthnRegistration("User not found".into()))?;
-    let recovery_codes = RecoveryCodes::new(user.get_recovery_codes(&appstate.pool).await?);
+    let recovery_codes =
+        RecoveryCodesToken::new(recovery_codes_token(&appstate.pool, &mut user).await?);
     let webauthn = WebAuthn::new(session.session.user_id, webauth_reg.name, &passkey)?;
     webauthn.save(&appstate.pool).await?;
     if user.mfa_method == MFAMethod::None {
@@ -396,8 +485,10 @@ pub async fn webauthn_finish(
             &MFAMethod::Webauthn,
             &appstate.mail_tx,
         )?;
+        let previous = MfaState::from(&user);
         user.set_mfa_method(&appstate.pool, MFAMethod::Webauthn)
             .await?;
+        appstate.trigger_mfa_changed(&user, previous);
     }
 
     info!("Finished Webauthn registration for user {}", user.username);
@@ -493,7 +584,12 @@ pub async fn totp_secret(session: SessionInfo, State(appstate): State<AppState>)
     let secret = user.new_totp_secret(&appstate.pool).await?;
     info!("Generated new TOTP secret for user {}", user.username);
     Ok(ApiResponse {
-        json: json!(AuthTotp::new(secret)),
+        json: json!(AuthTotp::new(
+            secret,
+            user.totp_digits as u32,
+            user.totp_period as u64,
+            user.totp_provisioning_uri(TOTP_ISSUER),
+        )),
         status: StatusCode::OK,
     })
 }
@@ -506,40 +602,166 @@ pub async fn totp_enable(
 ) -> ApiResult {
     let mut user = session.user;
     debug!("Enabling TOTP for user {}", user.username);
-    if user.verify_totp_code(&data.code) {
-        let recovery_codes = RecoveryCodes::new(user.get_recovery_codes(&appstate.pool).await?);
-        user.enable_totp(&appstate.pool).await?;
-        if user.mfa_method == MFAMethod::None {
-            send_mfa_configured_email(
-                Some(&session.session),
-                &user,
-                &MFAMethod::OneTimePassword,
-                &appstate.mail_tx,
-            )?;
-            user.set_mfa_method(&appstate.pool, MFAMethod::OneTimePassword)
-                .await?;
-        }
-
-        info!("Enabled TOTP for user {}", user.username);
-        Ok(ApiResponse {
-            json: json!(recovery_codes),
-            status: StatusCode::OK,
-        })
-    } else {
-        Err(WebError::ObjectNotFound("Invalid TOTP code".into()))
+    let first_factor = user.mfa_method == MFAMethod::None;
+    let previous = MfaState::from(&user);
+    let token = match user.complete_totp_setup(&appstate.pool, &data.code).await? {
+        Some(codes) => Some(bundle_recovery_codes(&appstate.pool, &user, codes).await?),
+        None => None,
+    };
+    if first_factor {
+        send_mfa_configured_email(
+            Some(&session.session),
+            &user,
+            &MFAMethod::OneTimePassword,
+            &appstate.mail_tx,
+        )?;
     }
+
+    info!("Enabled TOTP for user {}", user.username);
+    appstate.trigger_mfa_changed(&user, previous);
+    Ok(ApiResponse {
+        json: json!(RecoveryCodesToken::new(token)),
+        status: StatusCode::OK,
+    })
 }
 
 /// Disable TOTP
 pub async fn totp_disable(session: SessionInfo, State(appstate): State<AppState>) -> ApiResult {
     let mut user = session.user;
     debug!("Disabling TOTP for user {}", user.username);
+    let previous = MfaState::from(&user);
     user.disable_totp(&appstate.pool).await?;
     user.verify_mfa_state(&appstate.pool).await?;
     info!("Disabled TOTP for user {}", user.username);
+    appstate.trigger_mfa_changed(&user, previous);
     Ok(ApiResponse::default())
 }
 
+/// Verify user's TOTP code on behalf of an internal service (e.g. a proxy doing step-up
+/// authentication). Only available with an admin API token; failed attempts are rate-limited
+/// the same way as interactive logins.
+pub async fn verify_totp_internal(
+    session: SessionInfo,
+    State(appstate): State<AppState>,
+    Json(data): Json<VerifyTotpRequest>,
+) -> ApiResult {
+    if session.session.state != SessionState::ApiTokenVerified {
+        return Err(WebError::Forbidden("API token is required".into()));
+    }
+    let username = data.username;
+    debug!(
+        "User {} verifying TOTP code of user {username}",
+        session.user.username
+    );
+    check_username(&appstate.failed_logins, &username)?;
+
+    let verified = match User::find_by_username(&appstate.pool, &username).await? {
+        Some(user) => {
+            user.is_active
+                && !user.is_suspended()
+                && user.totp_enabled
+                && user.verify_totp_code(&data.code)
+        }
+        None => false,
+    };
+    if verified {
+        info!(
+            "User {} verified TOTP code of user {username}",
+            session.user.username
+        );
+    } else {
+        info!("Internal TOTP verification failed for user {username}");
+        log_failed_login_attempt(&appstate.failed_logins, &username);
+    }
+
+    Ok(ApiResponse {
+        json: json!({ "verified": verified }),
+        status: StatusCode::OK,
+    })
+}
+
+/// Outcome of verifying user's password and second factor in one step.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MfaAuthResult {
+    Success,
+    /// Wrong password, or the user doesn't exist, is disabled or suspended.
+    InvalidPassword,
+    /// Password was correct, but the user has no MFA to verify.
+    MfaNotEnabled,
+    /// Password was correct, but the code is neither a valid TOTP nor a recovery code.
+    InvalidMfaCode,
+}
+
+/// Verify user's password and then their TOTP or recovery code, without creating a session.
+/// A matching recovery code is consumed. Lockout rules are up to the caller.
+pub(crate) async fn authenticate_with_mfa(
+    pool: &PgPool,
+    username: &str,
+    password: &str,
+    mfa_code: &str,
+) -> Result<MfaAuthResult, WebError> {
+    let Some(mut user) = User::find_by_username(pool, username).await? else {
+        return Ok(MfaAuthResult::InvalidPassword);
+    };
+    if let Err(err) = user.verify_and_upgrade_password(pool, password).await {
+        debug!("Password verification of user {username} failed: {err}");
+        return Ok(MfaAuthResult::InvalidPassword);
+    }
+    if !user.is_active || user.is_suspended() {
+        debug!("User {username} is disabled or suspended");
+        return Ok(MfaAuthResult::InvalidPassword);
+    }
+    if !user.mfa_enabled {
+        return Ok(MfaAuthResult::MfaNotEnabled);
+    }
+    if user.totp_enabled && user.verify_totp_code(mfa_code) {
+        return Ok(MfaAuthResult::Success);
+    }
+    if user.verify_recovery_code(pool, mfa_code).await? {
+        info!("User {username} used a recovery code in step-up authentication");
+        return Ok(MfaAuthResult::Success);
+    }
+
+    Ok(MfaAuthResult::InvalidMfaCode)
+}
+
+/// Verify user's password and second factor on behalf of an internal service (e.g. a proxy
+/// doing step-up authentication). Only available with an admin API token; failed attempts
+/// are rate-limited the same way as interactive logins.
+pub async fn authenticate_with_mfa_internal(
+    session: SessionInfo,
+    State(appstate): State<AppState>,
+    Json(data): Json<AuthenticateWithMfaRequest>,
+) -> ApiResult {
+    if session.session.state != SessionState::ApiTokenVerified {
+        return Err(WebError::Forbidden("API token is required".into()));
+    }
+    let username = data.username;
+    debug!(
+        "User {} verifying password and MFA of user {username}",
+        session.user.username
+    );
+    check_username(&appstate.failed_logins, &username)?;
+
+    let result =
+        authenticate_with_mfa(&appstate.pool, &username, &data.password, &data.code).await?;
+    if result == MfaAuthResult::Success {
+        info!(
+            "User {} verified password and MFA of user {username}",
+            session.user.username
+        );
+    } else {
+        info!("Internal password and MFA verification failed for user {username}: {result:?}");
+        log_failed_login_attempt(&appstate.failed_logins, &username);
+    }
+
+    Ok(ApiResponse {
+        json: json!({ "result": result }),
+        status: StatusCode::OK,
+    })
+}
+
 /// Validate one-time passcode
 pub async fn totp_code(
     private_cookies: PrivateCookieJar,
@@ -620,7 +842,9 @@ pub async fn email_mfa_enable(
     let mut user = session.user;
     debug!("Enabling email MFA for user {}", user.username);
     if user.verify_email_mfa_code(&data.code) {
-        let recovery_codes = RecoveryCodes::new(user.get_recovery_codes(&appstate.pool).await?);
+        let recovery_codes =
+            RecoveryCodesToken::new(recovery_codes_token(&appstate.pool, &mut user).await?);
+        let previous = MfaState::from(&user);
         user.enable_email_mfa(&appstate.pool).await?;
         if user.mfa_method == MFAMethod::None {
             send_mfa_configured_email(
@@ -634,6 +858,7 @@ pub async fn email_mfa_enable(
         }
 
         info!("Enabled email MFA for user {}", user.username);
+        appstate.trigger_mfa_changed(&user, previous);
         Ok(ApiResponse {
             json: json!(recovery_codes),
             status: StatusCode::OK,
@@ -650,9 +875,11 @@ pub async fn email_mfa_disable(
 ) -> ApiResult {
     let mut user = session.user;
     debug!("Disabling email MFA for user {}", user.username);
+    let previous = MfaState::from(&user);
     user.disable_email_mfa(&appstate.pool).await?;
     user.verify_mfa_state(&appstate.pool).await?;
     info!("Disabled email MFA for user {}", user.username);
+    appstate.trigger_mfa_changed(&user, previous);
     Ok(ApiResponse::default())
 }
 
@@ -744,6 +971,16 @@ pub async fn recovery_code(
                 .await?;
             let user_info = UserInfo::from_user(&appstate.pool, &user).await?;
             info!("Authenticated user {username} with recovery code");
+            let event = if user.is_admin(&appstate.pool).await? {
+                SecurityEvent::BreakGlass {
+                    username: &username,
+                }
+            } else {
+                SecurityEvent::RecoveryCodeUsed {
+                    username: &username,
+                }
+            };
+            send_security_notification_email(&event, &appstate.mail_tx);
             if let Some(openid_cookie) = private_cookies.get(SIGN_IN_COOKIE_NAME) {
                 debug!("Found OpenID session cookie.");
                 let redirect_url = openid_cookie.value().to_string();
@@ -774,3 +1011,53 @@ pub async fn recovery_code(
     }
     Err(WebError::Http(StatusCode::UNAUTHORIZED))
 }
+
+/// Replace current user's recovery codes with a new set. Previous codes stop working.
+/// Users can do this once per configured cooldown period; admins are not limited.
+pub async fn regenerate_recovery_codes(
+    session: SessionInfo,
+    State(appstate): State<AppState>,
+) -> ApiResult {
+    let mut user = session.user;
+    debug!("Regenerating recovery codes for user {}", user.username);
+    if !user.mfa_enabled {
+        return Err(WebError::BadRequest("MFA is not enabled".into()));
+    }
+    let codes = user
+        .regenerate_recovery_codes(&appstate.pool, session.is_admin)
+        .await?;
+    let recovery_codes = RecoveryCodesToken::new(Some(
+        bundle_recovery_codes(&appstate.pool, &user, codes).await?,
+    ));
+    info!("Regenerated recovery codes for user {}", user.username);
+
+    Ok(ApiResponse {
+        json: json!(recovery_codes),
+        status: StatusCode::OK,
+    })
+}
+
+/// Exchange recovery codes token for the codes. Each token can be used only once.
+pub async fn recovery_codes_exchange(
+    session: SessionInfo,
+    State(appstate): State<AppState>,
+    Path(token): Path<String>,
+) -> ApiResult {
+    let user = session.user;
+    debug!("Exchanging recovery codes token for user {}", user.username);
+    let Some(codes) = RecoveryCodesBundle::exchange(&appstate.pool, user.id, &token).await? else {
+        warn!(
+            "Invalid, expired or already used recovery codes token for user {}",
+            user.username
+        );
+        return Err(WebError::ObjectNotFound(
+            "Recovery codes not found or already retrieved".into(),
+        ));
+    };
+    info!("Exchanged recovery codes token for user {}", user.username);
+
+    Ok(ApiResponse {
+        json: json!(RecoveryCodes::new(Some(codes))),
+        status: StatusCode::OK,
+    })
+}
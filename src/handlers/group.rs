@@ -6,11 +6,14 @@ use serde_json::json;
 use sqlx::query_as;
 use utoipa::ToSchema;
 
-use super::{ApiResponse, EditGroupInfo, GroupInfo, Username};
+use super::{mail::notify_low_admin_count, ApiResponse, EditGroupInfo, GroupInfo, Username};
 use crate::{
     appstate::AppState,
     auth::{AdminRole, SessionInfo},
-    db::{models::group::Permission, Group, User, WireguardNetwork},
+    db::{
+        models::group::{validate_ssh_principal, Permission},
+        Group, User, WebHook, WireguardNetwork,
+    },
     error::WebError,
 };
 
@@ -34,6 +37,12 @@ pub(crate) struct BulkAssignToGroupsRequest {
     users: Vec<i64>,
 }
 
+#[derive(Deserialize, Debug, ToSchema)]
+pub(crate) struct CloneGroupMembers {
+    /// Name of the group to copy members from
+    source: String,
+}
+
 /// Bulk assign users to groups
 ///
 /// Assign many users to many groups at once.
@@ -65,7 +74,8 @@ pub(crate) async fn bulk_assign_to_groups(
         User,
         "SELECT id, username, password_hash, last_name, first_name, email, \
             phone, mfa_enabled, totp_enabled, email_mfa_enabled, \
-            totp_secret, email_mfa_secret, mfa_method \"mfa_method: _\", recovery_codes, is_active, openid_sub \
+            totp_secret, totp_digits, totp_period, email_mfa_secret, mfa_method \"mfa_method: _\", recovery_codes, is_active, openid_sub, \
+            suspended_until, password_changed_at, must_change_password \
             FROM \"user\" WHERE id = ANY($1)",
         &data.users
     )
@@ -147,7 +157,7 @@ pub(crate) async fn list_groups_info(
         "SELECT g.name, \
         COALESCE(ARRAY_AGG(DISTINCT u.username) FILTER (WHERE u.username IS NOT NULL), '{}') \"members!\", \
         COALESCE(ARRAY_AGG(DISTINCT wn.name) FILTER (WHERE wn.name IS NOT NULL), '{}') \"vpn_locations!\", \
-        is_admin \
+        is_admin, ssh_principal \
         FROM \"group\" g \
         LEFT JOIN \"group_user\" gu ON gu.group_id = g.id \
         LEFT JOIN \"user\" u ON u.id = gu.user_id \
@@ -163,6 +173,23 @@ pub(crate) async fn list_groups_info(
     })
 }
 
+/// Number of members of each group, including empty groups.
+pub(crate) async fn list_group_member_counts(
+    _role: AdminRole,
+    State(appstate): State<AppState>,
+) -> Result<ApiResponse, WebError> {
+    debug!("Counting group members");
+    let counts: Vec<_> = Group::member_counts(&appstate.pool)
+        .await?
+        .into_iter()
+        .map(|(id, members)| json!({ "id": id, "members": members }))
+        .collect();
+    Ok(ApiResponse {
+        json: json!(counts),
+        status: StatusCode::OK,
+    })
+}
+
 /// Retrieve all groups.
 ///
 /// # Returns
@@ -237,9 +264,11 @@ pub(crate) async fn get_group(
         let is_admin = group
             .has_permission(&appstate.pool, Permission::IsAdmin)
             .await?;
-        info!("Retrieved group {name}");
+        let mut group_info = GroupInfo::new(name, members, vpn_locations, is_admin);
+        group_info.ssh_principal = group.ssh_principal;
+        info!("Retrieved group {}", group_info.name);
         Ok(ApiResponse {
-            json: json!(GroupInfo::new(name, members, vpn_locations, is_admin)),
+            json: json!(group_info),
             status: StatusCode::OK,
         })
     } else {
@@ -287,7 +316,12 @@ pub(crate) async fn create_group(
     let mut transaction = appstate.pool.begin().await?;
 
     // FIXME: conflicts must not return internal server error (500).
-    let group = Group::new(&group_info.name).save(&appstate.pool).await?;
+    let mut group = Group::new(&group_info.name);
+    group.ssh_principal = group_info.ssh_principal.clone().flatten();
+    if let Some(principal) = &group.ssh_principal {
+        validate_ssh_principal(principal).map_err(WebError::BadRequest)?;
+    }
+    let group = group.save(&appstate.pool).await?;
     // TODO: create group in LDAP
     group
         .set_permission(&mut *transaction, Permission::IsAdmin, group_info.is_admin)
@@ -352,9 +386,19 @@ pub(crate) async fn modify_group(
     // FIXME: LDAP operations are not reverted.
     let mut transaction = appstate.pool.begin().await?;
 
-    // Rename only when needed.
-    if group.name != group_info.name {
+    // SSH principal is left unchanged if not given.
+    let ssh_principal = match group_info.ssh_principal {
+        Some(principal) => principal,
+        None => group.ssh_principal.clone(),
+    };
+    if let Some(principal) = &ssh_principal {
+        validate_ssh_principal(principal).map_err(WebError::BadRequest)?;
+    }
+
+    // Rename or change SSH principal only when needed.
+    if group.name != group_info.name || group.ssh_principal != ssh_principal {
         group.name = group_info.name;
+        group.ssh_principal = ssh_principal;
         group.save(&mut *transaction).await?;
         // TODO: update LDAP
     }
@@ -416,6 +460,7 @@ pub(crate) async fn modify_group(
 /// Remove group with `name`.
 ///
 /// Delete group and group members.
+/// Webhooks scoped to the group are disabled and archived.
 ///
 /// # Returns
 /// If error occurs it returns `WebError` object.
@@ -457,7 +502,16 @@ pub(crate) async fn delete_group(
                 });
             }
         }
-        group.delete(&appstate.pool).await?;
+        let mut transaction = appstate.pool.begin().await?;
+        let archived = WebHook::archive_for_group(&mut transaction, group.id).await?;
+        if !archived.is_empty() {
+            info!(
+                "Archived {} webhooks scoped to group {name}",
+                archived.len()
+            );
+        }
+        group.delete(&mut *transaction).await?;
+        transaction.commit().await?;
         // TODO: delete group from LDAP
 
         // sync allowed devices for all locations
@@ -561,7 +615,9 @@ pub(crate) async fn remove_group_member(
                 "Removing user: {} from group: {}",
                 user.username, group.name
             );
+            let admin_count = User::find_admins(&appstate.pool).await?.len();
             user.remove_from_group(&appstate.pool, &group).await?;
+            notify_low_admin_count(&appstate.pool, &appstate.mail_tx, admin_count).await;
             // TODO: update LDAP
 
             WireguardNetwork::sync_all_networks(&appstate).await?;
@@ -580,3 +636,63 @@ pub(crate) async fn remove_group_member(
         Err(WebError::ObjectNotFound(format!("Group {name} not found",)))
     }
 }
+
+/// Copy members of another group into group with `name`.
+///
+/// Users who are already members of the target group are skipped.
+///
+/// # Returns
+/// Returns number of added members or `WebError` if error occurs.
+#[utoipa::path(
+    post,
+    path = "/api/v1/group/{name}/clone-members",
+    params(
+        ("name" = String, description = "Name of the group that receives the members.")
+    ),
+    request_body = CloneGroupMembers,
+    responses(
+        (status = 200, description = "Successfully copied group members.", body = ApiResponse, example = json!({"added": 3})),
+        (status = 401, description = "Unauthorized to copy group members.", body = ApiResponse, example = json!({"msg": "Session is required"})),
+        (status = 403, description = "You don't have permission to copy group members.", body = ApiResponse, example = json!({"msg": "requires privileged access"})),
+        (status = 404, description = "Source or target group doesn't exist.", body = ApiResponse, example = json!({"msg": "Group <group_name> not found"})),
+        (status = 500, description = "Cannot copy group members.", body = ApiResponse, example = json!({"msg": "Internal server error"}))
+    ),
+    security(
+        ("cookie" = []),
+        ("api_token" = [])
+    )
+)]
+pub(crate) async fn clone_group_members(
+    _role: AdminRole,
+    session: SessionInfo,
+    State(appstate): State<AppState>,
+    Path(name): Path<String>,
+    Json(data): Json<CloneGroupMembers>,
+) -> Result<ApiResponse, WebError> {
+    let Some(group) = Group::find_by_name(&appstate.pool, &name).await? else {
+        let msg = format!("Group {name} not found");
+        error!(msg);
+        return Err(WebError::ObjectNotFound(msg));
+    };
+    let Some(source) = Group::find_by_name(&appstate.pool, &data.source).await? else {
+        let msg = format!("Group {} not found", data.source);
+        error!(msg);
+        return Err(WebError::ObjectNotFound(msg));
+    };
+    debug!(
+        "User {} copying members of group {} to group {}",
+        session.user.username, source.name, group.name
+    );
+    let added = group.clone_members_from(&appstate.pool, source.id).await?;
+    if added > 0 {
+        WireguardNetwork::sync_all_networks(&appstate).await?;
+    }
+    info!(
+        "User {} copied {added} members of group {} to group {}",
+        session.user.username, source.name, group.name
+    );
+    Ok(ApiResponse {
+        json: json!({ "added": added }),
+        status: StatusCode::OK,
+    })
+}
@@ -17,7 +17,10 @@ use super::{ApiResponse, ApiResult};
 use crate::{
     appstate::AppState,
     auth::{AdminRole, SessionInfo},
-    db::{models::enrollment::TokenError, Id, MFAMethod, Session, User},
+    db::{
+        models::{enrollment::TokenError, settings::security_notification_email},
+        Id, MFAMethod, Session, User, YubiKey,
+    },
     error::WebError,
     mail::{Attachment, Mail},
     server_config,
@@ -32,16 +35,59 @@ static SUPPORT_EMAIL_SUBJECT: &str = "Defguard support data";
 
 static NEW_DEVICE_ADDED_EMAIL_SUBJECT: &str = "Defguard: new device added to your account";
 static NEW_DEVICE_LOGIN_EMAIL_SUBJECT: &str = "Defguard: new device logged in to your account";
+static DEVICE_CONFIG_EMAIL_SUBJECT: &str = "Defguard: WireGuard configuration of your device";
 
 static EMAIL_MFA_ACTIVATION_EMAIL_SUBJECT: &str = "Your Multi-Factor Authentication Activation";
 static EMAIL_MFA_CODE_EMAIL_SUBJECT: &str = "Your Multi-Factor Authentication Code for Login";
 
 static GATEWAY_DISCONNECTED: &str = "Defguard: Gateway disconnected";
 static GATEWAY_RECONNECTED: &str = "Defguard: Gateway reconnected";
+static YUBIKEY_DISABLED_EMAIL_SUBJECT: &str = "Defguard: unused YubiKey disabled";
+static SECURITY_NOTIFICATION_EMAIL_SUBJECT: &str = "Defguard: security notification";
+
+/// Number of active admins at or below which the security notification address is alerted.
+const LOW_ADMIN_COUNT: usize = 1;
 
 pub static EMAIL_PASSOWRD_RESET_START_SUBJECT: &str = "Defguard: Password reset";
 pub static EMAIL_PASSOWRD_RESET_SUCCESS_SUBJECT: &str = "Defguard: Password reset success";
 
+/// Security-relevant events reported to the security notification address.
+pub enum SecurityEvent<'a> {
+    /// An admin signed in with a recovery code, bypassing their MFA methods.
+    BreakGlass { username: &'a str },
+    /// A user signed in with one of their recovery codes.
+    RecoveryCodeUsed { username: &'a str },
+    /// The number of active admins dropped to `remaining`.
+    LowAdminCount { remaining: usize },
+}
+
+impl SecurityEvent<'_> {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::BreakGlass { .. } => "break-glass login",
+            Self::RecoveryCodeUsed { .. } => "recovery code used",
+            Self::LowAdminCount { .. } => "low admin count",
+        }
+    }
+}
+
+impl Display for SecurityEvent<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BreakGlass { username } => write!(
+                f,
+                "Admin {username} signed in with a recovery code, bypassing their MFA methods."
+            ),
+            Self::RecoveryCodeUsed { username } => {
+                write!(f, "User {username} signed in with a recovery code.")
+            }
+            Self::LowAdminCount { remaining } => {
+                write!(f, "Only {remaining} active admin(s) left.")
+            }
+        }
+    }
+}
+
 #[derive(Clone, Deserialize)]
 pub struct TestMail {
     pub to: String,
@@ -209,6 +255,45 @@ pub fn send_new_device_added_email(
     }
 }
 
+/// Send WireGuard configuration of a device as an attachment.
+/// The configuration must not contain the private key of the device.
+pub fn send_device_config_email(
+    device_name: &str,
+    network_name: &str,
+    config: String,
+    user_email: &str,
+    mail_tx: &UnboundedSender<Mail>,
+) -> Result<(), TemplateError> {
+    debug!(
+        "Sending configuration of device {device_name} for location {network_name} to {user_email}"
+    );
+    let attachment = Attachment {
+        filename: format!("{network_name}.conf"),
+        content: config.into(),
+        content_type: ContentType::TEXT_PLAIN,
+    };
+    let mail = Mail {
+        to: user_email.to_string(),
+        subject: DEVICE_CONFIG_EMAIL_SUBJECT.to_string(),
+        content: templates::device_config_mail(device_name, network_name)?,
+        attachments: vec![attachment],
+        result_tx: None,
+    };
+
+    match mail_tx.send(mail) {
+        Ok(()) => {
+            info!("Sent configuration of device {device_name} to {user_email}");
+            Ok(())
+        }
+        Err(err) => {
+            error!(
+                "Sending configuration of device {device_name} to {user_email} failed with error:\n{err}"
+            );
+            Ok(())
+        }
+    }
+}
+
 pub async fn send_gateway_disconnected_email(
     gateway_name: Option<String>,
     network_name: String,
@@ -379,6 +464,35 @@ pub fn send_mfa_configured_email(
     }
 }
 
+pub fn send_yubikey_disabled_email(
+    user: &User<Id>,
+    yubikey: &YubiKey<Id>,
+    mail_tx: &UnboundedSender<Mail>,
+) -> Result<(), TemplateError> {
+    debug!("Sending YubiKey disabled mail to {}", user.email);
+
+    let mail = Mail {
+        to: user.email.clone(),
+        subject: YUBIKEY_DISABLED_EMAIL_SUBJECT.to_string(),
+        content: templates::yubikey_disabled_mail(&yubikey.name, &yubikey.serial)?,
+        attachments: Vec::new(),
+        result_tx: None,
+    };
+
+    let to = mail.to.clone();
+
+    match mail_tx.send(mail) {
+        Ok(()) => {
+            info!("YubiKey disabled mail sent to {to}");
+            Ok(())
+        }
+        Err(err) => {
+            error!("Failed to send YubiKey disabled mail to {to} with error:\n{err}");
+            Ok(())
+        }
+    }
+}
+
 pub fn send_email_mfa_activation_email(
     user: &User<Id>,
     mail_tx: &UnboundedSender<Mail>,
@@ -509,3 +623,109 @@ pub fn send_password_reset_success_email(
     }
     Ok(())
 }
+
+/// Notify the security notification address, if one is configured, about a security event.
+/// Best-effort: failures are logged and never interrupt the calling operation.
+pub fn send_security_notification_email(event: &SecurityEvent, mail_tx: &UnboundedSender<Mail>) {
+    send_security_notification_email_with(event, mail_tx, security_notification_email());
+}
+
+fn send_security_notification_email_with(
+    event: &SecurityEvent,
+    mail_tx: &UnboundedSender<Mail>,
+    security_notification_email: Option<String>,
+) {
+    let Some(to) = security_notification_email else {
+        debug!(
+            "Security notification address not configured, skipping {}",
+            event.name()
+        );
+        return;
+    };
+    debug!("Sending {} security notification to {to}", event.name());
+
+    let content = match templates::security_notification_mail(event.name(), &event.to_string()) {
+        Ok(content) => content,
+        Err(err) => {
+            error!(
+                "Failed to render {} security notification: {err}",
+                event.name()
+            );
+            return;
+        }
+    };
+    let mail = Mail {
+        to,
+        subject: format!("{SECURITY_NOTIFICATION_EMAIL_SUBJECT}: {}", event.name()),
+        content,
+        attachments: Vec::new(),
+        result_tx: None,
+    };
+
+    let to = mail.to.clone();
+
+    match mail_tx.send(mail) {
+        Ok(()) => {
+            info!("Sent {} security notification to {to}", event.name());
+        }
+        Err(err) => {
+            error!(
+                "Sending {} security notification to {to} failed with error:\n{err}",
+                event.name()
+            );
+        }
+    }
+}
+
+/// Send low admin count security notification if the number of active admins has dropped
+/// below `previous` and reached `LOW_ADMIN_COUNT`.
+pub async fn notify_low_admin_count(
+    pool: &PgPool,
+    mail_tx: &UnboundedSender<Mail>,
+    previous: usize,
+) {
+    match User::find_admins(pool).await {
+        Ok(admins) => {
+            let remaining = admins.len();
+            if remaining < previous && remaining <= LOW_ADMIN_COUNT {
+                warn!("Number of active admins dropped to {remaining}");
+                send_security_notification_email(
+                    &SecurityEvent::LowAdminCount { remaining },
+                    mail_tx,
+                );
+            }
+        }
+        Err(err) => error!("Failed to count active admins: {err}"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_security_notification_email() {
+        let (mail_tx, mut mail_rx) = unbounded_channel();
+        let event = SecurityEvent::BreakGlass { username: "admin" };
+
+        // nothing is sent without the security notification address
+        send_security_notification_email_with(&event, &mail_tx, None);
+        assert!(mail_rx.try_recv().is_err());
+
+        send_security_notification_email_with(
+            &event,
+            &mail_tx,
+            Some("security@hogwart.edu.uk".into()),
+        );
+        let mail = mail_rx.try_recv().unwrap();
+        assert_eq!(mail.to, "security@hogwart.edu.uk");
+        assert_eq!(
+            mail.subject,
+            "Defguard: security notification: break-glass login"
+        );
+        assert!(mail
+            .content
+            .contains("Admin admin signed in with a recovery code"));
+        assert!(mail_rx.try_recv().is_err());
+    }
+}
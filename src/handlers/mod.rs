@@ -1,10 +1,14 @@
+use std::collections::HashMap;
+
 use axum::{
     http::{HeaderName, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Deserializer};
 use serde_json::{json, Value};
-use sqlx::PgPool;
+use sqlx::{types::Json as DbJson, PgPool};
 use utoipa::ToSchema;
 use webauthn_rs::prelude::RegisterPublicKeyCredential;
 
@@ -12,10 +16,14 @@ use webauthn_rs::prelude::RegisterPublicKeyCredential;
 use crate::db::Device;
 use crate::{
     auth::SessionInfo,
-    db::{Id, NoId, User, UserInfo, WebHook},
+    config::ValidationErrorStatus,
+    db::{
+        models::webhook::{DEFAULT_MAX_ATTEMPTS, DEFAULT_RETRY_BASE_DELAY},
+        Id, NoId, User, UserInfo, WebHook,
+    },
     enterprise::license::LicenseError,
     error::WebError,
-    VERSION,
+    SERVER_CONFIG, VERSION,
 };
 
 pub(crate) mod app_info;
@@ -59,6 +67,18 @@ impl ApiResponse {
 
 impl From<WebError> for ApiResponse {
     fn from(web_error: WebError) -> ApiResponse {
+        let validation_status = SERVER_CONFIG
+            .get()
+            .map(|config| config.validation_error_status)
+            .unwrap_or_default();
+        ApiResponse::from_web_error(web_error, validation_status)
+    }
+}
+
+impl ApiResponse {
+    /// Map [`WebError`] to response. Status code of validation errors is configurable,
+    /// all other codes are fixed.
+    fn from_web_error(web_error: WebError, validation_status: ValidationErrorStatus) -> Self {
         match web_error {
             WebError::ObjectNotFound(msg) => {
                 ApiResponse::new(json!({ "msg": msg }), StatusCode::NOT_FOUND)
@@ -71,6 +91,26 @@ impl From<WebError> for ApiResponse {
                 error!(msg);
                 ApiResponse::new(json!({ "msg": msg }), StatusCode::FORBIDDEN)
             }
+            WebError::UserSuspended(until) => {
+                warn!("{web_error}");
+                ApiResponse::new(
+                    json!({
+                        "msg": "User is suspended",
+                        "suspended_until": until,
+                    }),
+                    StatusCode::FORBIDDEN,
+                )
+            }
+            WebError::PasswordChangeRequired => {
+                debug!("{web_error}");
+                ApiResponse::new(
+                    json!({
+                        "msg": "Password change required",
+                        "must_change_password": true,
+                    }),
+                    StatusCode::FORBIDDEN,
+                )
+            }
             WebError::DbError(_)
             | WebError::Grpc(_)
             | WebError::Ldap(_)
@@ -97,12 +137,46 @@ impl From<WebError> for ApiResponse {
                 json!({ "msg": "Too many login attempts" }),
                 StatusCode::TOO_MANY_REQUESTS,
             ),
+            WebError::RecoveryCodesCooldown(retry_after) => {
+                warn!("{web_error}");
+                ApiResponse::new(
+                    json!({
+                        "msg": "Recovery codes were regenerated recently",
+                        "retry_after": retry_after,
+                    }),
+                    StatusCode::TOO_MANY_REQUESTS,
+                )
+            }
             WebError::IncorrectUsername(msg)
             | WebError::PubkeyValidation(msg)
             | WebError::PubkeyExists(msg)
             | WebError::BadRequest(msg) => {
                 error!(msg);
-                ApiResponse::new(json!({ "msg": msg }), StatusCode::BAD_REQUEST)
+                ApiResponse::new(json!({ "msg": msg }), validation_status.status_code())
+            }
+            WebError::WebauthnKeyLimit(limit) => {
+                warn!("{web_error}");
+                ApiResponse::new(
+                    json!({
+                        "msg": "Maximum number of security keys reached",
+                        "limit": limit,
+                    }),
+                    validation_status.status_code(),
+                )
+            }
+            WebError::AuthenticationKeyConflict => {
+                warn!("{web_error}");
+                ApiResponse::new(
+                    json!({ "msg": "Key is already registered by another user" }),
+                    StatusCode::CONFLICT,
+                )
+            }
+            WebError::Validation(errors) => {
+                warn!("Validation failed: {errors:?}");
+                ApiResponse::new(
+                    json!({ "msg": "Validation failed", "errors": errors }),
+                    validation_status.status_code(),
+                )
             }
             WebError::TemplateError(err) => {
                 error!("Template error: {err}");
@@ -183,13 +257,20 @@ impl Auth {
 #[derive(Deserialize, Serialize)]
 pub struct AuthTotp {
     pub secret: String,
+    pub digits: u32,
+    pub period: u64,
+    // otpauth URI for authenticator apps
+    pub uri: Option<String>,
 }
 
 impl AuthTotp {
     #[must_use]
-    pub fn new<S: Into<String>>(secret: S) -> Self {
+    pub fn new<S: Into<String>>(secret: S, digits: u32, period: u64, uri: Option<String>) -> Self {
         Self {
             secret: secret.into(),
+            digits,
+            period,
+            uri,
         }
     }
 }
@@ -206,12 +287,28 @@ impl AuthCode {
     }
 }
 
+#[derive(Deserialize, Serialize)]
+pub struct VerifyTotpRequest {
+    pub username: String,
+    pub code: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct AuthenticateWithMfaRequest {
+    pub username: String,
+    pub password: String,
+    /// TOTP or recovery code.
+    pub code: String,
+}
+
 #[derive(Deserialize, Serialize, ToSchema)]
 pub struct GroupInfo {
     pub name: String,
     pub members: Vec<String>,
     pub vpn_locations: Vec<String>,
     pub is_admin: bool,
+    #[serde(default)]
+    pub ssh_principal: Option<String>,
 }
 
 impl GroupInfo {
@@ -227,16 +324,36 @@ impl GroupInfo {
             members,
             vpn_locations,
             is_admin,
+            ssh_principal: None,
         }
     }
 }
 
+/// Deserialize a present field as `Some`, including `null` as `Some(None)`. Together with
+/// `#[serde(default)]` it tells a missing field apart from one explicitly cleared.
+fn deserialize_some<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
 /// Dedicated `GroupInfo` variant for group modification operations.
 #[derive(Deserialize, Serialize, ToSchema)]
 pub struct EditGroupInfo {
     pub name: String,
     pub members: Vec<String>,
     pub is_admin: bool,
+    /// Principal put in SSH certificates of group members instead of the group name.
+    /// Left unchanged when missing, cleared with `null`.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_some",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[schema(value_type = Option<String>)]
+    pub ssh_principal: Option<Option<String>>,
 }
 
 #[derive(Deserialize, Serialize, ToSchema)]
@@ -246,6 +363,8 @@ pub struct Username {
 
 #[derive(Deserialize, Serialize, ToSchema)]
 pub struct AddUserData {
+    /// Derived from e-mail if left empty.
+    #[serde(default)]
     pub username: String,
     pub last_name: String,
     pub first_name: String,
@@ -259,6 +378,9 @@ pub struct StartEnrollmentRequest {
     #[serde(default)]
     pub send_enrollment_notification: bool,
     pub email: Option<String>,
+    /// Replaces the global enrollment welcome message for this enrollment.
+    #[serde(default)]
+    pub custom_welcome_message: Option<String>,
 }
 
 #[derive(Deserialize, Serialize, ToSchema)]
@@ -272,6 +394,70 @@ pub struct PasswordChange {
     pub new_password: String,
 }
 
+#[derive(Deserialize, Serialize, ToSchema)]
+pub struct SuspendUser {
+    pub until: NaiveDateTime,
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+pub struct BulkDisableMfa {
+    /// Ids of users whose MFA should be disabled
+    pub users: Vec<Id>,
+}
+
+/// Default and maximum number of items returned by paginated listings.
+pub(crate) const DEFAULT_PAGE_LIMIT: i64 = 50;
+pub(crate) const MAX_PAGE_LIMIT: i64 = 500;
+
+/// `limit` and `offset` query parameters of paginated listings.
+#[derive(Debug, Default, Deserialize)]
+pub struct PaginationParams {
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+impl PaginationParams {
+    /// Whether any pagination parameter was given. Used by listings which return
+    /// a plain list unless pagination is requested.
+    #[must_use]
+    pub fn is_requested(&self) -> bool {
+        self.limit.is_some() || self.offset.is_some()
+    }
+
+    #[must_use]
+    pub fn limit(&self) -> i64 {
+        self.limit
+            .unwrap_or(DEFAULT_PAGE_LIMIT)
+            .clamp(1, MAX_PAGE_LIMIT)
+    }
+
+    #[must_use]
+    pub fn offset(&self) -> i64 {
+        self.offset.unwrap_or_default().max(0)
+    }
+}
+
+/// Single page of a listing along with the total number of items.
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: i64,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+impl<T> Page<T> {
+    #[must_use]
+    pub fn new(items: Vec<T>, total: i64, pagination: &PaginationParams) -> Self {
+        Self {
+            items,
+            total,
+            limit: pagination.limit(),
+            offset: pagination.offset(),
+        }
+    }
+}
+
 #[derive(Deserialize)]
 pub struct WebAuthnRegistration {
     pub name: String,
@@ -295,6 +481,19 @@ impl RecoveryCodes {
     }
 }
 
+/// Token which can be exchanged once for newly generated recovery codes.
+#[derive(Serialize)]
+pub struct RecoveryCodesToken {
+    token: Option<String>,
+}
+
+impl RecoveryCodesToken {
+    #[must_use]
+    pub fn new(token: Option<String>) -> Self {
+        Self { token }
+    }
+}
+
 #[derive(Deserialize)]
 pub struct WebHookData {
     pub url: String,
@@ -305,6 +504,31 @@ pub struct WebHookData {
     pub on_user_deleted: bool,
     pub on_user_modified: bool,
     pub on_hwkey_provision: bool,
+    #[serde(default)]
+    pub on_enrollment_pending: bool,
+    #[serde(default)]
+    pub on_enrollment_completed: bool,
+    #[serde(default)]
+    pub on_user_mfa_changed: bool,
+    #[serde(default)]
+    pub group_id: Option<Id>,
+    pub paused_until: Option<NaiveDateTime>,
+    #[serde(default)]
+    pub custom_headers: HashMap<String, String>,
+    #[serde(default)]
+    pub body_template: Option<String>,
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: i32,
+    #[serde(default = "default_retry_base_delay")]
+    pub retry_base_delay: i32,
+}
+
+fn default_max_attempts() -> i32 {
+    DEFAULT_MAX_ATTEMPTS
+}
+
+fn default_retry_base_delay() -> i32 {
+    DEFAULT_RETRY_BASE_DELAY
 }
 
 impl From<WebHookData> for WebHook {
@@ -319,6 +543,16 @@ impl From<WebHookData> for WebHook {
             on_user_deleted: data.on_user_deleted,
             on_user_modified: data.on_user_modified,
             on_hwkey_provision: data.on_hwkey_provision,
+            on_enrollment_pending: data.on_enrollment_pending,
+            on_enrollment_completed: data.on_enrollment_completed,
+            on_user_mfa_changed: data.on_user_mfa_changed,
+            group_id: data.group_id,
+            paused_until: data.paused_until,
+            custom_headers: DbJson(data.custom_headers),
+            body_template: data.body_template,
+            max_attempts: data.max_attempts,
+            retry_base_delay: data.retry_base_delay,
+            archived: false,
         }
     }
 }
@@ -382,3 +616,106 @@ pub async fn device_for_admin_or_self(
         ))),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::error::FieldError;
+
+    #[test]
+    fn test_validation_error_status() {
+        let response = ApiResponse::from_web_error(
+            WebError::BadRequest("invalid".into()),
+            ValidationErrorStatus::BadRequest,
+        );
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+
+        let response = ApiResponse::from_web_error(
+            WebError::BadRequest("invalid".into()),
+            ValidationErrorStatus::UnprocessableEntity,
+        );
+        assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(response.json, json!({ "msg": "invalid" }));
+
+        // authorization errors are not affected
+        let response = ApiResponse::from_web_error(
+            WebError::Authorization("denied".into()),
+            ValidationErrorStatus::UnprocessableEntity,
+        );
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+        let response = ApiResponse::from_web_error(
+            WebError::Forbidden("denied".into()),
+            ValidationErrorStatus::UnprocessableEntity,
+        );
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn test_field_validation_errors() {
+        let response = ApiResponse::from_web_error(
+            WebError::Validation(vec![
+                FieldError::new("email", "invalid e-mail address"),
+                FieldError::new("phone", "invalid phone number"),
+            ]),
+            ValidationErrorStatus::UnprocessableEntity,
+        );
+        assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            response.json,
+            json!({
+                "msg": "Validation failed",
+                "errors": [
+                    { "field": "email", "message": "invalid e-mail address" },
+                    { "field": "phone", "message": "invalid phone number" },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn test_page_envelope() {
+        #[derive(Serialize)]
+        struct Item {
+            username: String,
+        }
+
+        let items: Vec<Item> = ["hpotter", "rweasley"]
+            .into_iter()
+            .map(|username| Item {
+                username: username.into(),
+            })
+            .collect();
+        let pagination = PaginationParams {
+            limit: Some(2),
+            offset: Some(1),
+        };
+        let page = Page::new(items, 3, &pagination);
+        assert_eq!(
+            json!(page),
+            json!({
+                "items": [{ "username": "hpotter" }, { "username": "rweasley" }],
+                "total": 3,
+                "limit": 2,
+                "offset": 1,
+            })
+        );
+
+        let page = Page::new(Vec::<Item>::new(), 0, &PaginationParams::default());
+        assert_eq!(
+            json!(page),
+            json!({ "items": [], "total": 0, "limit": DEFAULT_PAGE_LIMIT, "offset": 0 })
+        );
+    }
+
+    #[test]
+    fn test_pagination_params_bounds() {
+        let pagination = PaginationParams {
+            limit: Some(MAX_PAGE_LIMIT + 1),
+            offset: Some(-5),
+        };
+        assert_eq!(pagination.limit(), MAX_PAGE_LIMIT);
+        assert_eq!(pagination.offset(), 0);
+        assert!(pagination.is_requested());
+        assert!(!PaginationParams::default().is_requested());
+    }
+}
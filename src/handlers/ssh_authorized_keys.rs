@@ -1,23 +1,40 @@
+use std::time::Duration;
+
 use axum::{
     extract::{Path, Query, State},
-    http::StatusCode,
+    http::{header, StatusCode},
+    response::IntoResponse,
     Json,
 };
+use chrono::{NaiveDateTime, Utc};
+use pgp::{types::PublicKeyTrait, Deserializable, SignedPublicKey};
+use reqwest::Url;
 use serde_json::json;
 use sqlx::{query, Error as SqlxError, PgExecutor, PgPool};
 use ssh_key::PublicKey;
+use tokio::time::{sleep_until, Instant};
 
 use super::{user_for_admin_or_self, ApiResponse, ApiResult};
 use crate::{
     appstate::AppState,
-    auth::SessionInfo,
+    auth::{AdminRole, SessionInfo},
     db::{
-        models::authentication_key::{AuthenticationKey, AuthenticationKeyType},
-        Group, Id, User,
+        models::{
+            authentication_key::{
+                validate_ssh_key_options, AuthenticationKey, AuthenticationKeyType,
+                AuthenticationKeyUsage, SshKeyParts, SshKeyPolicy,
+            },
+            group::SshHostGroup,
+        },
+        Group, Id, User, YubiKey,
     },
     error::WebError,
+    hex::to_lower_hex,
+    server_config,
 };
 
+const KEYSERVER_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
 #[derive(Deserialize, Serialize)]
 pub(crate) struct AuthenticationKeyInfo {
     id: Id,
@@ -28,6 +45,17 @@ pub(crate) struct AuthenticationKeyInfo {
     yubikey_serial: Option<String>,
     yubikey_id: Option<i64>,
     yubikey_name: Option<String>,
+    yubikey_model: Option<String>,
+    yubikey_firmware_version: Option<String>,
+    // set when the YubiKey was disabled for being unused
+    yubikey_disabled_at: Option<NaiveDateTime>,
+    // algorithm, body and comment of SSH keys for display purposes
+    ssh_key: Option<SshKeyParts>,
+    // primary key fingerprint of GPG keys for display purposes
+    gpg_fingerprint: Option<String>,
+    expires_at: Option<NaiveDateTime>,
+    options: Option<String>,
+    usage: AuthenticationKeyUsage,
 }
 
 impl AuthenticationKeyInfo {
@@ -37,8 +65,12 @@ impl AuthenticationKeyInfo {
     {
         let q_res = query!(
             "SELECT k.id key_id, k.name, k.key_type \"key_type: AuthenticationKeyType\", \
-            k.key, k.user_id, k.yubikey_id, \
-            y.name \"yubikey_name: Option<String>\", y.serial \"serial: Option<String>\" \
+            k.key, k.comment, k.expires_at, k.options, \
+            k.usage \"usage: AuthenticationKeyUsage\", k.user_id, k.yubikey_id, \
+            y.name \"yubikey_name: Option<String>\", y.serial \"serial: Option<String>\", \
+            y.model \"yubikey_model: Option<String>\", \
+            y.firmware_version \"yubikey_firmware_version: Option<String>\", \
+            y.disabled_at \"yubikey_disabled_at: Option<NaiveDateTime>\" \
             FROM \"authentication_key\" k \
             LEFT JOIN \"yubikey\" y ON k.yubikey_id = y.id \
             WHERE k.user_id = $1",
@@ -57,6 +89,25 @@ impl AuthenticationKeyInfo {
                 yubikey_id: q.yubikey_id,
                 yubikey_name: q.yubikey_name.clone(),
                 yubikey_serial: q.serial.clone(),
+                yubikey_model: q.yubikey_model.clone(),
+                yubikey_firmware_version: q.yubikey_firmware_version.clone(),
+                yubikey_disabled_at: q.yubikey_disabled_at,
+                ssh_key: match q.key_type {
+                    AuthenticationKeyType::Ssh => {
+                        SshKeyParts::parse(&q.key).map(|parts| SshKeyParts {
+                            comment: q.comment.clone(),
+                            ..parts
+                        })
+                    }
+                    AuthenticationKeyType::Gpg => None,
+                },
+                gpg_fingerprint: match q.key_type {
+                    AuthenticationKeyType::Ssh => None,
+                    AuthenticationKeyType::Gpg => gpg_fingerprint(&q.key),
+                },
+                expires_at: q.expires_at,
+                options: q.options.clone(),
+                usage: q.usage,
             })
             .collect();
 
@@ -64,23 +115,187 @@ impl AuthenticationKeyInfo {
     }
 }
 
-async fn add_user_ssh_keys_to_list(pool: &PgPool, user: &User<Id>, ssh_keys: &mut Vec<String>) {
-    let keys_result =
-        AuthenticationKey::find_by_user_id(pool, user.id, Some(AuthenticationKeyType::Ssh)).await;
+async fn add_user_ssh_keys_to_list(
+    pool: &PgPool,
+    user_id: Id,
+    usage: AuthenticationKeyUsage,
+    ssh_keys: &mut Vec<AuthenticationKey<Id>>,
+) {
+    let keys_result = AuthenticationKey::find_valid_ssh_keys(pool, user_id, Some(usage)).await;
 
-    if let Ok(authentication_keys) = keys_result {
-        let mut keys: Vec<String> = authentication_keys
-            .into_iter()
-            .map(|item| item.key)
-            .collect();
-        ssh_keys.append(&mut keys);
+    if let Ok(mut authentication_keys) = keys_result {
+        ssh_keys.append(&mut authentication_keys);
     }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct SshKeysRequestParams {
     username: Option<String>,
+    // comma-separated list of groups, users belonging to any of them are matched
     group: Option<String>,
+    // host being logged in to, only users in groups granted SSH access to it are matched
+    host: Option<String>,
+    // keys meant for this usage or both usages are returned, interactive by default
+    usage: Option<AuthenticationKeyUsage>,
+}
+
+impl SshKeysRequestParams {
+    /// Group names from the `group` param, without blanks and duplicates.
+    fn group_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = Vec::new();
+        for name in self.group.iter().flat_map(|group| group.split(',')) {
+            let name = name.trim();
+            if !name.is_empty() && !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        names
+    }
+}
+
+/// Reason why no SSH keys were returned. Only exposed to admins, see [`diagnose_authorized_keys`].
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SshKeysDiagnostic {
+    NoFilter,
+    UserNotFound,
+    GroupNotFound,
+    UserNotInGroup,
+    EmptyGroup,
+    NoHostAccess,
+    NoKeys,
+}
+
+/// Find public SSH keys matching request params.
+/// Along with the keys, returns the reason why the key list is empty, if it is.
+///
+/// All lookups are performed even if an earlier one already determined the result, so that
+/// response time depends as little as possible on whether given users and groups exist.
+/// This costs a few extra queries for requests which would otherwise be answered early.
+async fn find_authorized_keys(
+    pool: &PgPool,
+    params: &SshKeysRequestParams,
+) -> Result<(Vec<String>, Option<SshKeysDiagnostic>), WebError> {
+    let mut ssh_keys: Vec<AuthenticationKey<Id>> = Vec::new();
+    let usage = params.usage.unwrap_or_default();
+    let group_names = params.group_names();
+
+    if group_names.is_empty() && params.username.is_none() && params.host.is_none() {
+        return Ok((Vec::new(), Some(SshKeysDiagnostic::NoFilter)));
+    }
+    debug!(
+        "Fetching SSH keys for user {:?} in groups {group_names:?} on host {:?}",
+        params.username, params.host
+    );
+
+    // fetch groups, skipping the ones which don't exist
+    let mut groups = Vec::new();
+    for group_name in &group_names {
+        match Group::find_by_name(pool, group_name).await? {
+            Some(group) => groups.push(group),
+            None => debug!("Specified group {group_name} does not exist"),
+        }
+    }
+    // fetch all users in groups, users belonging to multiple groups are included once
+    let mut members: Vec<User<Id>> = Vec::new();
+    for group in &groups {
+        for member in group.members(pool).await? {
+            if !members.iter().any(|user| user.id == member.id) {
+                members.push(member);
+            }
+        }
+    }
+    // fetch users granted access to the host
+    let host_user_ids = match &params.host {
+        Some(hostname) => Some(Group::find_ssh_host_member_ids(pool, hostname).await?),
+        None => None,
+    };
+    let has_host_access = |user_id: Id| {
+        host_user_ids
+            .as_ref()
+            .is_none_or(|ids| ids.contains(&user_id))
+    };
+    // fetch user
+    let user = match &params.username {
+        Some(username) => User::find_by_username(pool, username).await?,
+        None => None,
+    };
+
+    let diagnostic = if !group_names.is_empty() && groups.is_empty() {
+        Some(SshKeysDiagnostic::GroupNotFound)
+    } else if params.username.is_some() && user.is_none() {
+        Some(SshKeysDiagnostic::UserNotFound)
+    } else {
+        None
+    };
+
+    if params.username.is_some() {
+        // Query keys even if the user doesn't exist; ids start at 1, so nothing matches 0.
+        let user_id = user.as_ref().map_or(0, |user| user.id);
+        let keys = AuthenticationKey::find_valid_ssh_keys(pool, user_id, Some(usage)).await?;
+        // check if user belongs to any of specified groups
+        let is_member = group_names.is_empty() || members.iter().any(|member| member.id == user_id);
+        if diagnostic.is_some() {
+            return Ok((Vec::new(), diagnostic));
+        }
+        if !is_member {
+            debug!(
+                "User {:?} is not a member of any of groups {group_names:?}",
+                params.username
+            );
+            return Ok((Vec::new(), Some(SshKeysDiagnostic::UserNotInGroup)));
+        }
+        if !has_host_access(user_id) {
+            debug!(
+                "User {:?} is not granted SSH access to host {:?}",
+                params.username, params.host
+            );
+            return Ok((Vec::new(), Some(SshKeysDiagnostic::NoHostAccess)));
+        }
+        ssh_keys.extend(keys);
+    } else {
+        if diagnostic.is_some() {
+            return Ok((Vec::new(), diagnostic));
+        }
+        let user_ids: Vec<Id> = if group_names.is_empty() {
+            // only host was specified, all users granted access to it are matched
+            host_user_ids.clone().unwrap_or_default()
+        } else {
+            if members.is_empty() {
+                debug!("Groups {group_names:?} have no members");
+                return Ok((Vec::new(), Some(SshKeysDiagnostic::EmptyGroup)));
+            }
+            members
+                .iter()
+                .map(|member| member.id)
+                .filter(|id| has_host_access(*id))
+                .collect()
+        };
+        if user_ids.is_empty() {
+            debug!(
+                "No matched users are granted SSH access to host {:?}",
+                params.host
+            );
+            return Ok((Vec::new(), Some(SshKeysDiagnostic::NoHostAccess)));
+        }
+        for user_id in user_ids {
+            add_user_ssh_keys_to_list(pool, user_id, usage, &mut ssh_keys).await;
+        }
+    }
+
+    let diagnostic = ssh_keys.is_empty().then_some(SshKeysDiagnostic::NoKeys);
+    let ssh_keys = ssh_keys
+        .into_iter()
+        .map(|key| key.authorized_keys_line())
+        .collect();
+    Ok((ssh_keys, diagnostic))
+}
+
+/// Wait until at least `min_duration` has passed since `started`.
+async fn pad_response_time(started: Instant, min_duration: Option<Duration>) {
+    if let Some(min_duration) = min_duration {
+        sleep_until(started + min_duration).await;
+    }
 }
 
 /// Fetch public SSH keys for user
@@ -88,69 +303,313 @@ pub struct SshKeysRequestParams {
 /// Meant to be used with `AuthorizedKeysCommand` config option in `sshd`.
 /// Should always return a response to partially mitigate user enumeration.
 /// Optional query params `username` and `group` are used for filtering users.
+/// `group` may be a comma-separated list, in which case members of any of the groups are matched.
+/// With `host` given, only users in groups granted SSH access to that host are matched; hosts
+/// without any groups get no keys. `host` alone matches all users granted access to the host.
+/// Optional `usage` param (`interactive` by default, or `deploy`) selects keys by their usage;
+/// keys marked for both usages are always included.
 /// If no params are specified an empty response is returned.
+///
+/// With `ssh_authorized_keys_min_response_time` configured, responses are delayed to take at least
+/// that long, hiding the remaining timing differences at the cost of slower `sshd` logins.
 pub async fn get_authorized_keys(
     params: Query<SshKeysRequestParams>,
     State(appstate): State<AppState>,
 ) -> Result<String, WebError> {
+    let started = Instant::now();
     info!("Fetching public SSH keys for {:?}", params);
-    let mut ssh_keys: Vec<String> = Vec::new();
-
-    // check if group filter was specified
-    match &params.group {
-        Some(group_name) => {
-            // fetch group
-            if let Some(group) = Group::find_by_name(&appstate.pool, group_name).await? {
-                // check if user filter was specified
-                if let Some(username) = &params.username {
-                    debug!("Fetching SSH keys for user {username} in group {group_name}");
-                    // fetch user
-                    if let Some(user) = User::find_by_username(&appstate.pool, username).await? {
-                        // check if user belongs to specified group
-                        let members = group.member_usernames(&appstate.pool).await?;
-                        if members.contains(&user.username) {
-                            add_user_ssh_keys_to_list(&appstate.pool, &user, &mut ssh_keys).await;
-                        } else {
-                            debug!("User {username} is not a member of group {group_name}",);
-                        }
-                    } else {
-                        debug!("Specified user does not exist");
-                    }
-                } else {
-                    debug!("Fetching SSH keys for all users in group {group_name}");
-                    // fetch all users in group
-                    let users = group.members(&appstate.pool).await?;
-                    for user in users {
-                        add_user_ssh_keys_to_list(&appstate.pool, &user, &mut ssh_keys).await;
-                    }
-                }
-            } else {
-                debug!("Specified group does not exist");
-            }
-        }
-        None => {
-            // check if user filter was specified
-            if let Some(username) = &params.username {
-                debug!("Fetching SSH keys for user {username}");
-                // fetch user
-                if let Some(user) = User::find_by_username(&appstate.pool, username).await? {
-                    add_user_ssh_keys_to_list(&appstate.pool, &user, &mut ssh_keys).await;
-                } else {
-                    debug!("Specified user does not exist");
-                }
-            }
-        }
-    }
+    let result = find_authorized_keys(&appstate.pool, &params).await;
+    pad_response_time(
+        started,
+        server_config()
+            .ssh_authorized_keys_min_response_time
+            .map(Into::into),
+    )
+    .await;
+    let (ssh_keys, _) = result?;
 
     // concatenate all keys into a response
     Ok(ssh_keys.join("\n"))
 }
 
+/// Same lookup as [`get_authorized_keys`], but also explains why no keys were found.
+/// Admin-only, so it doesn't weaken user enumeration mitigation of the public endpoint.
+pub async fn diagnose_authorized_keys(
+    _admin: AdminRole,
+    params: Query<SshKeysRequestParams>,
+    State(appstate): State<AppState>,
+) -> ApiResult {
+    debug!("Diagnosing public SSH keys lookup for {:?}", params);
+    let (ssh_keys, diagnostic) = find_authorized_keys(&appstate.pool, &params).await?;
+
+    Ok(ApiResponse {
+        json: json!({ "keys": ssh_keys, "reason": diagnostic }),
+        status: StatusCode::OK,
+    })
+}
+
+#[derive(Deserialize)]
+pub struct SshKeyUse {
+    key: String,
+}
+
+/// Report that an SSH key was used to log in, e.g. from `sshd` logs.
+/// Keeps YubiKeys holding the key from being disabled as unused. Unlike the public key listing,
+/// this requires admin credentials, typically an API token used by the SSH server.
+pub async fn report_ssh_key_use(
+    _admin: AdminRole,
+    session: SessionInfo,
+    State(appstate): State<AppState>,
+    Json(data): Json<SshKeyUse>,
+) -> ApiResult {
+    let Some(key) = SshKeyParts::normalize(&data.key) else {
+        return Err(WebError::BadRequest("Invalid SSH key".into()));
+    };
+    let updated = YubiKey::mark_used_by_ssh_key(&appstate.pool, &key).await?;
+    debug!(
+        "User {} reported SSH key use, {updated} YubiKeys marked as used",
+        session.user.username
+    );
+
+    Ok(ApiResponse {
+        json: json!({}),
+        status: StatusCode::OK,
+    })
+}
+
+/// Retrieve groups granted SSH access to a host.
+///
+/// Groups are returned with the number of their active members and are meant for access reviews.
+/// Only members of these groups get keys from `/api/v1/ssh_authorized_keys` for this `host`.
+///
+/// # Returns
+/// Returns a list of `SshHostGroup` objects or `WebError` if error occurs.
+#[utoipa::path(
+    get,
+    path = "/api/v1/ssh_host/{hostname}/groups",
+    params(
+        ("hostname" = String, description = "Name of the SSH host")
+    ),
+    responses(
+        (status = 200, description = "Groups granted SSH access to the host.", body = [SshHostGroup], example = json!(
+            [
+                {
+                    "id": 1,
+                    "name": "devops",
+                    "user_count": 3
+                }
+            ]
+        )),
+        (status = 401, description = "Unauthorized to retrieve host groups.", body = ApiResponse, example = json!({"msg": "Session is required"})),
+        (status = 403, description = "You don't have permission to retrieve host groups.", body = ApiResponse, example = json!({"msg": "requires privileged access"})),
+        (status = 500, description = "Cannot retrieve host groups.", body = ApiResponse, example = json!({"msg": "Internal server error"}))
+    ),
+    security(
+        ("cookie" = []),
+        ("api_token" = [])
+    )
+)]
+pub async fn get_ssh_host_groups(
+    _admin: AdminRole,
+    State(appstate): State<AppState>,
+    Path(hostname): Path<String>,
+) -> ApiResult {
+    let groups = Group::find_by_ssh_host(&appstate.pool, &hostname).await?;
+    Ok(ApiResponse {
+        json: json!(groups),
+        status: StatusCode::OK,
+    })
+}
+
+/// Replace groups granted SSH access to a host.
+///
+/// Takes a list of group names. An empty list revokes SSH access to the host from all users.
+///
+/// # Returns
+/// Returns an empty response or `WebError` if error occurs.
+#[utoipa::path(
+    put,
+    path = "/api/v1/ssh_host/{hostname}/groups",
+    params(
+        ("hostname" = String, description = "Name of the SSH host")
+    ),
+    request_body = Vec<String>,
+    responses(
+        (status = 200, description = "Successfully replaced host groups."),
+        (status = 401, description = "Unauthorized to modify host groups.", body = ApiResponse, example = json!({"msg": "Session is required"})),
+        (status = 403, description = "You don't have permission to modify host groups.", body = ApiResponse, example = json!({"msg": "requires privileged access"})),
+        (status = 404, description = "Group doesn't exist.", body = ApiResponse, example = json!({"msg": "Group <name> not found"})),
+        (status = 500, description = "Cannot modify host groups.", body = ApiResponse, example = json!({"msg": "Internal server error"}))
+    ),
+    security(
+        ("cookie" = []),
+        ("api_token" = [])
+    )
+)]
+pub async fn set_ssh_host_groups(
+    _admin: AdminRole,
+    session: SessionInfo,
+    State(appstate): State<AppState>,
+    Path(hostname): Path<String>,
+    Json(group_names): Json<Vec<String>>,
+) -> ApiResult {
+    debug!(
+        "User {} setting groups with SSH access to host {hostname}",
+        session.user.username
+    );
+    let mut transaction = appstate.pool.begin().await?;
+    let mut group_ids = Vec::with_capacity(group_names.len());
+    for name in &group_names {
+        let Some(group) = Group::find_by_name(&mut *transaction, name).await? else {
+            return Err(WebError::ObjectNotFound(format!("Group {name} not found")));
+        };
+        group_ids.push(group.id);
+    }
+    Group::set_ssh_host_groups(&mut transaction, &hostname, &group_ids).await?;
+    transaction.commit().await?;
+    info!(
+        "User {} granted SSH access to host {hostname} to groups {group_names:?}",
+        session.user.username
+    );
+    Ok(ApiResponse::default())
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct AddAuthenticationKeyData {
+    #[serde(default)]
     key: String,
     name: String,
     key_type: AuthenticationKeyType,
+    // fetch GPG key with this fingerprint from the keyserver instead of providing `key`
+    #[serde(default)]
+    fingerprint: Option<String>,
+    #[serde(default)]
+    expires_at: Option<NaiveDateTime>,
+    // `authorized_keys` options, SSH keys only
+    #[serde(default)]
+    options: Option<String>,
+    #[serde(default)]
+    usage: AuthenticationKeyUsage,
+}
+
+/// Validate `authorized_keys` options of user's key, only SSH keys can have them.
+fn ssh_key_options(
+    username: &str,
+    options: Option<&str>,
+    key_type: &AuthenticationKeyType,
+) -> Result<Option<String>, WebError> {
+    match (options, key_type) {
+        (None, _) => Ok(None),
+        (Some(options), AuthenticationKeyType::Ssh) => {
+            Ok(Some(validate_ssh_key_options(options).map_err(|err| {
+                error!("Invalid options for SSH key of user {username}: {err}");
+                WebError::BadRequest(err.to_string())
+            })?))
+        }
+        (Some(_), AuthenticationKeyType::Gpg) => Err(WebError::BadRequest(
+            "Options can be set only for SSH keys.".into(),
+        )),
+    }
+}
+
+/// Lowercase hex fingerprint without whitespace and `0x` prefix.
+fn normalize_fingerprint(fingerprint: &str) -> String {
+    let fingerprint: String = fingerprint
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_lowercase();
+    match fingerprint.strip_prefix("0x") {
+        Some(stripped) => stripped.to_string(),
+        None => fingerprint,
+    }
+}
+
+/// Parse ASCII-armored GPG public key, verify its self-signatures and return its fingerprint.
+/// Revoked and expired keys are rejected.
+fn verify_gpg_key(armored: &str) -> Result<String, WebError> {
+    let (key, _headers) = SignedPublicKey::from_string(armored).map_err(|err| {
+        debug!("Failed to parse GPG key: {err}");
+        WebError::BadRequest("GPG key failed verification.".into())
+    })?;
+    key.verify().map_err(|err| {
+        debug!("Invalid GPG key signatures: {err}");
+        WebError::BadRequest("GPG key failed verification.".into())
+    })?;
+    let fingerprint = to_lower_hex(key.fingerprint().as_bytes());
+    if !key.details.revocation_signatures.is_empty() {
+        debug!("GPG key {fingerprint} has been revoked");
+        return Err(WebError::BadRequest("GPG key has been revoked.".into()));
+    }
+    if key
+        .expires_at()
+        .is_some_and(|expires_at| expires_at <= Utc::now())
+    {
+        debug!("GPG key {fingerprint} has expired");
+        return Err(WebError::BadRequest("GPG key has expired.".into()));
+    }
+    Ok(fingerprint)
+}
+
+/// Fingerprint of a stored GPG key, without verifying the key.
+fn gpg_fingerprint(armored: &str) -> Option<String> {
+    SignedPublicKey::from_string(armored)
+        .ok()
+        .map(|(key, _headers)| to_lower_hex(key.fingerprint().as_bytes()))
+}
+
+/// Fetch GPG public key with given fingerprint from a keyserver supporting the VKS API
+/// and make sure the returned key matches the fingerprint. API path is appended to keyserver URL,
+/// so keyservers may be served under a base path.
+pub(crate) async fn fetch_gpg_key(keyserver: &Url, fingerprint: &str) -> Result<String, WebError> {
+    let fingerprint = normalize_fingerprint(fingerprint);
+    if fingerprint.len() != 40 || !fingerprint.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(WebError::BadRequest("Invalid GPG key fingerprint.".into()));
+    }
+    let mut url = keyserver.clone();
+    url.path_segments_mut()
+        .map_err(|()| {
+            error!("Invalid keyserver URL {keyserver}");
+            WebError::Http(StatusCode::INTERNAL_SERVER_ERROR)
+        })?
+        .pop_if_empty()
+        .extend(["vks", "v1", "by-fingerprint", &fingerprint.to_uppercase()]);
+    debug!("Fetching GPG key {fingerprint} from {url}");
+    let client = reqwest::Client::builder()
+        .timeout(KEYSERVER_REQUEST_TIMEOUT)
+        .build()
+        .unwrap();
+    let response = client.get(url).send().await.map_err(|err| {
+        error!("Failed to connect to keyserver {keyserver}: {err}");
+        WebError::Http(StatusCode::BAD_GATEWAY)
+    })?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Err(WebError::ObjectNotFound(format!(
+            "GPG key {fingerprint} not found on keyserver"
+        )));
+    }
+    let armored = response
+        .error_for_status()
+        .map_err(|err| {
+            error!("Keyserver {keyserver} returned an error: {err}");
+            WebError::Http(StatusCode::BAD_GATEWAY)
+        })?
+        .text()
+        .await
+        .map_err(|err| {
+            error!("Failed to read response from keyserver {keyserver}: {err}");
+            WebError::Http(StatusCode::BAD_GATEWAY)
+        })?;
+
+    if verify_gpg_key(&armored)? != fingerprint {
+        warn!("Keyserver {keyserver} returned a key not matching fingerprint {fingerprint}");
+        return Err(WebError::BadRequest(
+            "GPG key doesn't match the fingerprint.".into(),
+        ));
+    }
+    info!("Fetched GPG key {fingerprint} from {keyserver}");
+
+    Ok(armored.trim_end_matches(['\n', '\r']).to_string())
 }
 
 pub async fn add_authentication_key(
@@ -167,43 +626,103 @@ pub async fn add_authentication_key(
     // authorize request
     let user = user_for_admin_or_self(&appstate.pool, &session, &username).await?;
 
-    let trimmed_key = data.key.trim_end_matches(['\n', '\r']);
+    if data
+        .expires_at
+        .is_some_and(|expires_at| expires_at <= Utc::now().naive_utc())
+    {
+        return Err(WebError::BadRequest(
+            "Key expiration date is in the past.".into(),
+        ));
+    }
+
+    let fetched_key;
+    let trimmed_key = if let Some(fingerprint) = &data.fingerprint {
+        if !matches!(data.key_type, AuthenticationKeyType::Gpg) {
+            return Err(WebError::BadRequest(
+                "Only GPG keys can be fetched by fingerprint.".into(),
+            ));
+        }
+        fetched_key = fetch_gpg_key(&server_config().gpg_keyserver_url, fingerprint).await?;
+        fetched_key.as_str()
+    } else {
+        data.key.trim_end_matches(['\n', '\r'])
+    };
+
+    // options lock down how keys may be used, so users can't set them on their own keys
+    if data.options.is_some() && !session.is_admin {
+        warn!(
+            "User {} tried to set authentication key options without being an admin",
+            session.user.username
+        );
+        return Err(WebError::Forbidden(
+            "Only admins can set key options.".into(),
+        ));
+    }
+    let options = ssh_key_options(&username, data.options.as_deref(), &data.key_type)?;
 
     // verify key
     match data.key_type {
         AuthenticationKeyType::Ssh => {
-            let parsed = trimmed_key.parse::<PublicKey>();
-            if parsed.is_err() {
+            let Ok(parsed) = trimmed_key.parse::<PublicKey>() else {
                 error!("User {username} tried to insert invalid SSH key: {data:?}");
                 return Err(WebError::BadRequest("SSH key failed verification.".into()));
+            };
+            if let Err(err) = SshKeyPolicy::current().check(&parsed) {
+                error!("User {username} tried to insert SSH key not meeting the policy: {err}");
+                return Err(WebError::BadRequest(err.to_string()));
+            }
+        }
+        AuthenticationKeyType::Gpg => {
+            if let Err(err) = verify_gpg_key(trimmed_key) {
+                error!("User {username} tried to insert invalid GPG key: {err}");
+                return Err(err);
             }
         }
-        // FIXME: verify GPG key
-        AuthenticationKeyType::Gpg => {}
     }
 
     // check if exists
-    let exists_res = query!(
-        "SELECT COUNT(1) FROM \"authentication_key\" WHERE user_id = $1 AND key = $2",
-        user.id,
-        trimmed_key,
-    )
-    .fetch_one(&appstate.pool)
-    .await?;
-    if exists_res.count == Some(1) {
+    let exists = match data.key_type {
+        AuthenticationKeyType::Ssh => {
+            // compare SSH keys regardless of comment and whitespace
+            let normalized_key = SshKeyParts::normalize(trimmed_key).unwrap_or_default();
+            let owners =
+                AuthenticationKey::find_ssh_key_owners(&appstate.pool, &normalized_key).await?;
+            if !owners.contains(&user.id)
+                && !owners.is_empty()
+                && server_config().ssh_key_global_unique
+            {
+                error!("User {username} tried to insert SSH key registered by another user");
+                return Err(WebError::AuthenticationKeyConflict);
+            }
+            owners.contains(&user.id)
+        }
+        AuthenticationKeyType::Gpg => {
+            let exists_res = query!(
+                "SELECT COUNT(1) FROM \"authentication_key\" WHERE user_id = $1 AND key = $2",
+                user.id,
+                trimmed_key,
+            )
+            .fetch_one(&appstate.pool)
+            .await?;
+            exists_res.count == Some(1)
+        }
+    };
+    if exists {
         error!("User {username} tried to insert existing key: {data:?}");
         return Err(WebError::BadRequest("Key already exists.".into()));
     }
 
-    AuthenticationKey::new(
+    let mut key = AuthenticationKey::new(
         user.id,
         trimmed_key.to_string(),
         Some(data.name.clone()),
         data.key_type.clone(),
         None,
-    )
-    .save(&appstate.pool)
-    .await?;
+    );
+    key.expires_at = data.expires_at;
+    key.options = options;
+    key.usage = data.usage;
+    key.save(&appstate.pool).await?;
 
     info!(
         "Added new key \"{}\" of type {:?} for user {username}",
@@ -231,6 +750,34 @@ pub async fn fetch_authentication_keys(
     })
 }
 
+/// Download user's SSH public keys as an `authorized_keys` file. GPG keys are skipped.
+pub async fn export_ssh_keys(
+    State(appstate): State<AppState>,
+    Path(username): Path<String>,
+    session: SessionInfo,
+) -> Result<impl IntoResponse, WebError> {
+    debug!("Exporting SSH keys of user {username}");
+    let user = user_for_admin_or_self(&appstate.pool, &session, &username).await?;
+    let keys: Vec<String> = AuthenticationKey::find_valid_ssh_keys(&appstate.pool, user.id, None)
+        .await?
+        .into_iter()
+        .map(|key| key.authorized_keys_line())
+        .collect();
+    info!("Exported {} SSH keys of user {username}", keys.len());
+
+    let disposition = format!("attachment; filename=\"{}_authorized_keys\"", user.username);
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                "text/plain; charset=utf-8".to_string(),
+            ),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        keys.join("\n"),
+    ))
+}
+
 pub async fn delete_authentication_key(
     State(appstate): State<AppState>,
     session: SessionInfo,
@@ -295,3 +842,258 @@ pub async fn rename_authentication_key(
         status: StatusCode::OK,
     })
 }
+
+#[derive(Debug, Deserialize)]
+pub struct KeyExpiryRequest {
+    expires_at: Option<NaiveDateTime>,
+}
+
+/// Set or clear (with `null`) expiration date of user's authentication key. Admin-only.
+pub async fn set_authentication_key_expiry(
+    _admin: AdminRole,
+    State(appstate): State<AppState>,
+    session: SessionInfo,
+    Path((username, key_id)): Path<(String, i64)>,
+    Json(data): Json<KeyExpiryRequest>,
+) -> ApiResult {
+    debug!(
+        "User {} setting expiration of key {key_id} of user {username} to {:?}",
+        session.user.username, data.expires_at
+    );
+    let Some(user) = User::find_by_username(&appstate.pool, &username).await? else {
+        return Err(WebError::ObjectNotFound(format!(
+            "User {username} not found"
+        )));
+    };
+    let Some(mut key) = AuthenticationKey::find_by_id(&appstate.pool, key_id)
+        .await?
+        .filter(|key| key.user_id == user.id)
+    else {
+        error!("Key with id {key_id} of user {username} not found");
+        return Err(WebError::ObjectNotFound("Key not found".into()));
+    };
+    key.expires_at = data.expires_at;
+    key.save(&appstate.pool).await?;
+    info!(
+        "User {} set expiration of key {key_id} of user {username} to {:?}",
+        session.user.username, data.expires_at
+    );
+
+    Ok(ApiResponse {
+        json: json!({}),
+        status: StatusCode::OK,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct KeyOptionsRequest {
+    options: Option<String>,
+}
+
+/// Set or clear (with `null`) `authorized_keys` options of user's SSH key. Admin-only, so that
+/// users can't strip restrictions from their keys.
+pub async fn set_authentication_key_options(
+    _admin: AdminRole,
+    State(appstate): State<AppState>,
+    session: SessionInfo,
+    Path((username, key_id)): Path<(String, i64)>,
+    Json(data): Json<KeyOptionsRequest>,
+) -> ApiResult {
+    debug!(
+        "User {} setting options of key {key_id} of user {username} to {:?}",
+        session.user.username, data.options
+    );
+    let Some(user) = User::find_by_username(&appstate.pool, &username).await? else {
+        return Err(WebError::ObjectNotFound(format!(
+            "User {username} not found"
+        )));
+    };
+    let Some(mut key) = AuthenticationKey::find_by_id(&appstate.pool, key_id)
+        .await?
+        .filter(|key| key.user_id == user.id)
+    else {
+        error!("Key with id {key_id} of user {username} not found");
+        return Err(WebError::ObjectNotFound("Key not found".into()));
+    };
+    key.options = ssh_key_options(&username, data.options.as_deref(), key.key_type())?;
+    key.save(&appstate.pool).await?;
+    info!(
+        "User {} set options of key {key_id} of user {username} to {:?}",
+        session.user.username, key.options
+    );
+
+    Ok(ApiResponse {
+        json: json!({}),
+        status: StatusCode::OK,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{extract::Path, routing::get, Router};
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::enterprise::license::PUBLIC_KEY;
+
+    static FINGERPRINT: &str = "9A2E 3C17 E26E A308 EDCD  CA99 A884 6C01 029A 8484";
+    static VALID_GPG_KEY: &str = "-----BEGIN PGP PUBLIC KEY BLOCK-----
+
+mDMEatF1ERYJKwYBBAHaRw8BAQdAVHoF2waoSjyADEpLtZkUv1fv6ksvU7hxCdux
+7Noyrz+0JkhhcnJ5IFBvdHRlciA8aC5wb3R0ZXJAaG9nd2FydC5lZHUudWs+iJAE
+ExYIADgWIQTwu39SmTegStLUoyckYj+bOamFFAUCatF1EQIbAwULCQgHAgYVCgkI
+CwIEFgIDAQIeAQIXgAAKCRAkYj+bOamFFClcAP9SgT5wZKKkws6lFvk5QtYC+Zmd
+WleHoRTsbtWzyMbZpAD9GQgaxOF67uGATkwxGvcUqveSpZhZtas+DlityVxrZgA=
+=zKpK
+-----END PGP PUBLIC KEY BLOCK-----";
+    static EXPIRED_GPG_KEY: &str = "-----BEGIN PGP PUBLIC KEY BLOCK-----
+
+mDMEXgvhABYJKwYBBAHaRw8BAQdAKZuhJVySPyKSWyXZ3QWpHD3JFIpZjuSeH6dB
+LWGwi7S0JEV4cGlyZWQgS2V5IDxleHBpcmVkQGhvZ3dhcnQuZWR1LnVrPoiWBBMW
+CAA+FiEE0ZOCScZ9LXkJtNkHq0YxIuwSesMFAl4L4QACGwMFCQABUYAFCwkIBwIG
+FQoJCAsCBBYCAwECHgECF4AACgkQq0YxIuwSesOJLgD/XafFnfKAA418aaEygePh
+CpHA/VCeNtYY2LJfMqnlt3EA/iPPyDguSYIAA3aZXK+1+ruF8k/JlZ6t+TWzpZyV
+/d0O
+=iobB
+-----END PGP PUBLIC KEY BLOCK-----";
+    static REVOKED_GPG_KEY: &str = "-----BEGIN PGP PUBLIC KEY BLOCK-----
+
+mDMEatF1ERYJKwYBBAHaRw8BAQdAndELn8iKFHv1xdQfcmE6KHPixJNSH9ZaJ3zI
+mJfzh0CIeAQgFggAIBYhBFqpXz0aQB84HFLm+Wto6fbAFE9CBQJq0XURAh0AAAoJ
+EGto6fbAFE9CgG4BAP/nO9XXmpjM5SokSe1M7ngKxUXwUWN+Y8KBDOVsUd7jAP9x
+VtfvtQA1bznMzR4GyLRMuERwTAji8zm3Q9VsBmcPAbQkUmV2b2tlZCBLZXkgPHJl
+dm9rZWRAaG9nd2FydC5lZHUudWs+iJAEExYIADgWIQRaqV89GkAfOBxS5vlraOn2
+wBRPQgUCatF1EQIbAwULCQgHAgYVCgkICwIEFgIDAQIeAQIXgAAKCRBraOn2wBRP
+QjJ6AP9EfjHaHLjCTnpS0cuWnNxP/QqOzkkq8cvRFzIY/tbjKgD/ZNRMpf+hIu7B
+/V15htsjez/zUGTbCAVIYKy9D3+r+gs=
+=tX1B
+-----END PGP PUBLIC KEY BLOCK-----";
+
+    /// Keyserver which returns the same key for every fingerprint, except for one it doesn't know.
+    /// It's served under `/keyserver` base path as well.
+    async fn mock_keyserver() -> Url {
+        async fn by_fingerprint(Path(fingerprint): Path<String>) -> (StatusCode, &'static str) {
+            if fingerprint == "0000000000000000000000000000000000000000" {
+                (StatusCode::NOT_FOUND, "")
+            } else {
+                (StatusCode::OK, PUBLIC_KEY)
+            }
+        }
+
+        let vks = Router::new().route("/vks/v1/by-fingerprint/{fingerprint}", get(by_fingerprint));
+        let app = Router::new().nest("/keyserver", vks.clone()).merge(vks);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        Url::parse(&format!("http://{address}")).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_pad_response_time() {
+        let started = Instant::now();
+        pad_response_time(started, None).await;
+        assert!(started.elapsed() < Duration::from_millis(100));
+
+        pad_response_time(started, Some(Duration::from_millis(200))).await;
+        assert!(started.elapsed() >= Duration::from_millis(200));
+
+        // already took longer than required
+        let elapsed = started.elapsed();
+        pad_response_time(started, Some(Duration::from_millis(100))).await;
+        assert!(started.elapsed() - elapsed < Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_group_names() {
+        let params = |group: Option<&str>| SshKeysRequestParams {
+            username: None,
+            group: group.map(Into::into),
+            usage: None,
+        };
+        assert!(params(None).group_names().is_empty());
+        assert!(params(Some(" , ,")).group_names().is_empty());
+        assert_eq!(params(Some("admin")).group_names(), vec!["admin"]);
+        assert_eq!(
+            params(Some("admin, devops,,admin")).group_names(),
+            vec!["admin", "devops"]
+        );
+    }
+
+    #[test]
+    fn test_normalize_fingerprint() {
+        assert_eq!(
+            normalize_fingerprint(FINGERPRINT),
+            "9a2e3c17e26ea308edcdca99a8846c01029a8484"
+        );
+        assert_eq!(normalize_fingerprint("0xABCD"), "abcd");
+    }
+
+    #[test]
+    fn test_verify_gpg_key() {
+        assert_eq!(
+            verify_gpg_key(VALID_GPG_KEY).unwrap(),
+            "f0bb7f529937a04ad2d4a32724623f9b39a98514"
+        );
+        assert_eq!(
+            gpg_fingerprint(VALID_GPG_KEY).as_deref(),
+            Some("f0bb7f529937a04ad2d4a32724623f9b39a98514")
+        );
+
+        // truncated key
+        let truncated = &VALID_GPG_KEY[..VALID_GPG_KEY.len() / 2];
+        assert!(matches!(
+            verify_gpg_key(truncated),
+            Err(WebError::BadRequest(_))
+        ));
+        // garbage
+        assert!(matches!(
+            verify_gpg_key("-----BEGIN PGP PUBLIC KEY BLOCK-----"),
+            Err(WebError::BadRequest(_))
+        ));
+        assert!(gpg_fingerprint("garbage").is_none());
+
+        assert!(matches!(
+            verify_gpg_key(EXPIRED_GPG_KEY),
+            Err(WebError::BadRequest(msg)) if msg == "GPG key has expired."
+        ));
+        assert!(matches!(
+            verify_gpg_key(REVOKED_GPG_KEY),
+            Err(WebError::BadRequest(msg)) if msg == "GPG key has been revoked."
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_gpg_key() {
+        let keyserver = mock_keyserver().await;
+
+        // matching key
+        let key = fetch_gpg_key(&keyserver, FINGERPRINT).await.unwrap();
+        assert!(key.starts_with("-----BEGIN PGP PUBLIC KEY BLOCK-----"));
+        assert!(key.ends_with("-----END PGP PUBLIC KEY BLOCK-----"));
+
+        // keyserver returned a different key
+        let result = fetch_gpg_key(&keyserver, "1111111111111111111111111111111111111111").await;
+        assert!(matches!(result, Err(WebError::BadRequest(_))));
+
+        // key not found
+        let result = fetch_gpg_key(&keyserver, "0000000000000000000000000000000000000000").await;
+        assert!(matches!(result, Err(WebError::ObjectNotFound(_))));
+
+        // keyserver under a base path, with or without trailing slash
+        for path in ["keyserver", "keyserver/"] {
+            let keyserver = keyserver.join(path).unwrap();
+            assert!(fetch_gpg_key(&keyserver, FINGERPRINT).await.is_ok());
+        }
+
+        // invalid fingerprint
+        let result = fetch_gpg_key(&keyserver, "not a fingerprint").await;
+        assert!(matches!(result, Err(WebError::BadRequest(_))));
+
+        // keyserver unreachable
+        let result = fetch_gpg_key(&Url::parse("http://127.0.0.1:1").unwrap(), FINGERPRINT).await;
+        assert!(matches!(
+            result,
+            Err(WebError::Http(StatusCode::BAD_GATEWAY))
+        ));
+    }
+}
@@ -1,24 +1,33 @@
 use axum::{
-    extract::{Json, Path, State},
+    extract::{Json, Path, Query, State},
     http::StatusCode,
 };
+use chrono::{DateTime, TimeDelta, Utc};
 use serde_json::json;
 
 use super::{
-    mail::EMAIL_PASSOWRD_RESET_START_SUBJECT, user_for_admin_or_self, AddUserData, ApiResponse,
-    ApiResult, PasswordChange, PasswordChangeSelf, StartEnrollmentRequest, Username,
+    mail::{notify_low_admin_count, EMAIL_PASSOWRD_RESET_START_SUBJECT},
+    user_for_admin_or_self, AddUserData, ApiResponse, ApiResult, BulkDisableMfa, Page,
+    PaginationParams, PasswordChange, PasswordChangeSelf, RecoveryCode, StartEnrollmentRequest,
+    SuspendUser, Username,
 };
 use crate::{
     appstate::AppState,
-    auth::{AdminRole, SessionInfo},
+    auth::{AdminRole, PasswordChangeSession, SessionInfo},
     db::{
-        models::enrollment::{Token, PASSWORD_RESET_TOKEN_TYPE},
+        models::{
+            enrollment::{EnrollmentStats, Token, TokenInfo, PASSWORD_RESET_TOKEN_TYPE},
+            enrollment_event::EnrollmentEvent,
+            webhook::{MfaState, UserDeletedData, UserModifiedData},
+            SecurityKeyOwnerInfo,
+        },
         AppEvent, OAuth2AuthorizedApp, User, UserDetails, UserInfo, WebAuthn,
     },
     enterprise::{db::models::enterprise_settings::EnterpriseSettings, limits::update_counts},
-    error::WebError,
+    error::{FieldError, WebError},
     ldap::utils::{ldap_add_user, ldap_change_password, ldap_modify_user},
     mail::Mail,
+    password::{check_breached_password, validate_password},
     server_config, templates,
 };
 
@@ -89,31 +98,65 @@ pub fn prune_username(username: &str) -> String {
     result
 }
 
-pub(crate) fn check_password_strength(password: &str) -> Result<(), WebError> {
-    if !(8..=128).contains(&password.len()) {
-        return Err(WebError::Serialization("Incorrect password length".into()));
-    }
-    if !password.chars().any(|c| c.is_ascii_punctuation()) {
-        return Err(WebError::Serialization(
-            "No special characters in password".into(),
-        ));
+/// Check password against the password policy and, if configured, known data breaches.
+/// Has to be called before [`User::new`] or [`User::set_password`] with a user-chosen password,
+/// as the models check the policy only.
+pub(crate) async fn check_password_strength(password: &str) -> Result<(), WebError> {
+    validate_password(password).map_err(|err| WebError::Serialization(err.to_string()))?;
+    check_breached_password(password)
+        .await
+        .map_err(|err| WebError::BadRequest(err.to_string()))
+}
+
+/// Basic sanity check of an e-mail address. Deliverability is verified by the mail server.
+fn check_email(email: &str) -> Result<(), WebError> {
+    let valid = match email.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !domain.is_empty()
+                && !domain.contains('@')
+                && !email.chars().any(char::is_whitespace)
+        }
+        None => false,
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(WebError::BadRequest("Invalid e-mail address".into()))
     }
-    if !password.chars().any(|c| c.is_ascii_digit()) {
-        return Err(WebError::Serialization("No numbers in password".into()));
+}
+
+/// Phone numbers may contain digits and common separators: space + - ( ) .
+fn check_phone(phone: &str) -> Result<(), WebError> {
+    if phone.is_empty() {
+        return Ok(());
     }
-    if !password.chars().any(|c| c.is_ascii_lowercase()) {
-        return Err(WebError::Serialization(
-            "No lowercase characters in password".into(),
+    if !phone.chars().any(|c| c.is_ascii_digit()) {
+        return Err(WebError::BadRequest(
+            "Phone number must contain digits".into(),
         ));
     }
-    if !password.chars().any(|c| c.is_ascii_uppercase()) {
-        return Err(WebError::Serialization(
-            "No uppercase characters in password".into(),
+    if !phone
+        .chars()
+        .all(|c| c.is_ascii_digit() || matches!(c, ' ' | '+' | '-' | '(' | ')' | '.'))
+    {
+        return Err(WebError::BadRequest(
+            "Phone number contains invalid characters".into(),
         ));
     }
     Ok(())
 }
 
+/// Turn a failed field check into a [`FieldError`], keeping only the human-readable message.
+fn field_error(field: &str, err: WebError) -> FieldError {
+    match err {
+        WebError::Serialization(message) | WebError::BadRequest(message) => {
+            FieldError::new(field, message)
+        }
+        other => FieldError::new(field, other.to_string()),
+    }
+}
+
 /// List of all users
 ///
 /// Retrives list of users.
@@ -154,14 +197,43 @@ pub(crate) fn check_password_strength(password: &str) -> Result<(), WebError> {
         ("api_token" = []) 
     )
 )]
-pub async fn list_users(_role: AdminRole, State(appstate): State<AppState>) -> ApiResult {
-    let all_users = User::all(&appstate.pool).await?;
-    let mut users: Vec<UserInfo> = Vec::with_capacity(all_users.len());
-    for user in all_users {
+pub async fn list_users(
+    _role: AdminRole,
+    State(appstate): State<AppState>,
+    Query(pagination): Query<PaginationParams>,
+) -> ApiResult {
+    // plain list is kept for clients which don't paginate
+    if !pagination.is_requested() {
+        let all_users = User::all(&appstate.pool).await?;
+        let mut users: Vec<UserInfo> = Vec::with_capacity(all_users.len());
+        for user in all_users {
+            users.push(UserInfo::from_user(&appstate.pool, &user).await?);
+        }
+        return Ok(ApiResponse {
+            json: json!(users),
+            status: StatusCode::OK,
+        });
+    }
+
+    let page_users =
+        User::fetch_page(&appstate.pool, pagination.limit(), pagination.offset()).await?;
+    let total = User::count(&appstate.pool).await?;
+    let mut users: Vec<UserInfo> = Vec::with_capacity(page_users.len());
+    for user in page_users {
         users.push(UserInfo::from_user(&appstate.pool, &user).await?);
     }
     Ok(ApiResponse {
-        json: json!(users),
+        json: json!(Page::new(users, total, &pagination)),
+        status: StatusCode::OK,
+    })
+}
+
+/// Number of users using each MFA method, for organisation-wide security overview.
+pub async fn mfa_distribution(_role: AdminRole, State(appstate): State<AppState>) -> ApiResult {
+    debug!("Counting users by MFA method");
+    let distribution = User::mfa_distribution(&appstate.pool).await?;
+    Ok(ApiResponse {
+        json: json!(distribution),
         status: StatusCode::OK,
     })
 }
@@ -169,6 +241,7 @@ pub async fn list_users(_role: AdminRole, State(appstate): State<AppState>) -> A
 /// Get user
 ///
 /// Return a user based on provided username parameter.
+/// Administrators and the user themselves additionally receive a summary of user's recovery codes.
 ///
 /// # Returns
 /// Returns `UserDetails` object or `WebError` if error occurs.
@@ -239,16 +312,37 @@ pub async fn get_user(
     Path(username): Path<String>,
 ) -> ApiResult {
     let user = user_for_admin_or_self(&appstate.pool, &session, &username).await?;
-    let user_details = UserDetails::from_user(&appstate.pool, &user).await?;
+    let mut user_details = UserDetails::from_user(&appstate.pool, &user).await?;
+    if session.is_admin || session.user.id == user.id {
+        user_details.recovery_codes = Some(user.recovery_codes_summary());
+    }
     Ok(ApiResponse {
         json: json!(user_details),
         status: StatusCode::OK,
     })
 }
 
+/// Number of user devices currently connected to any location, along with the configured limit.
+pub async fn connected_devices(
+    session: SessionInfo,
+    State(appstate): State<AppState>,
+    Path(username): Path<String>,
+) -> ApiResult {
+    let user = user_for_admin_or_self(&appstate.pool, &session, &username).await?;
+    let connected = user.connected_devices_count(&appstate.pool).await?;
+    Ok(ApiResponse {
+        json: json!({
+            "connected": connected,
+            "limit": server_config().max_concurrent_devices_per_user,
+        }),
+        status: StatusCode::OK,
+    })
+}
+
 /// Add user
 ///
 /// Add a new user based on `AddUserData` object.
+/// If `username` is omitted, it is derived from the e-mail address.
 ///
 /// # Returns
 /// Returns `UserInfo` object or `WebError` if error occurs.
@@ -277,7 +371,7 @@ pub async fn get_user(
                 "username": "username"
             }
         )),
-        (status = 400, description = "Bad request, invalid user data.", body = ApiResponse, example = json!({})),
+        (status = 400, description = "Bad request, invalid user data.", body = ApiResponse, example = json!({"msg": "Validation failed", "errors": [{"field": "email", "message": "Invalid e-mail address"}]})),
         (status = 401, description = "Unauthorized to create a user.", body = ApiResponse, example = json!({"msg": "Session is required"})),
         (status = 403, description = "You don't have permission to create a user.", body = ApiResponse, example = json!({"msg": "access denied"})),
         (status = 500, description = "Unable to create a user.", body = ApiResponse, example = json!({"msg": "Internal server error"}))
@@ -293,52 +387,55 @@ pub async fn add_user(
     State(appstate): State<AppState>,
     Json(user_data): Json<AddUserData>,
 ) -> ApiResult {
-    let username = user_data.username.clone();
+    let username = if user_data.username.is_empty() {
+        User::derive_username(&appstate.pool, &user_data.email).await?
+    } else {
+        user_data.username.clone()
+    };
     debug!("User {} adding user {username}", session.user.username);
 
-    // check username
+    // collect all invalid fields so they can be reported at once
+    let mut errors = Vec::new();
     if let Err(err) = check_username(&username) {
         debug!("Username {username} rejected: {err}");
-        return Ok(ApiResponse {
-            json: json!({}),
-            status: StatusCode::BAD_REQUEST,
-        });
+        errors.push(field_error("username", err));
     }
-    // check if email doesn't already exist
-    if User::find_by_email(&appstate.pool, &user_data.email)
+    if let Err(err) = check_email(&user_data.email) {
+        debug!("Email {} rejected: {err}", user_data.email);
+        errors.push(field_error("email", err));
+    } else if User::find_by_email(&appstate.pool, &user_data.email)
         .await?
         .is_some()
     {
         debug!("User with email {} already exists", user_data.email);
-        return Ok(ApiResponse {
-            json: json!({}),
-            status: StatusCode::BAD_REQUEST,
-        });
+        errors.push(FieldError::new("email", "E-mail address is already in use"));
     }
-    let password = match &user_data.password {
-        Some(password) => {
-            // check password strength
-            if let Err(err) = check_password_strength(password) {
-                debug!("Password not strong enough: {err}");
-                return Ok(ApiResponse {
-                    json: json!({}),
-                    status: StatusCode::BAD_REQUEST,
-                });
-            }
-            Some(password.as_str())
+    if let Some(password) = &user_data.password {
+        if let Err(err) = check_password_strength(password).await {
+            debug!("Password not strong enough: {err}");
+            errors.push(field_error("password", err));
         }
-        None => None,
-    };
+    }
+    if let Some(phone) = &user_data.phone {
+        if let Err(err) = check_phone(phone) {
+            debug!("Phone number {phone} rejected: {err}");
+            errors.push(field_error("phone", err));
+        }
+    }
+    if !errors.is_empty() {
+        return Err(WebError::Validation(errors));
+    }
+    let password = user_data.password.as_deref();
 
     // create new user
     let user = User::new(
-        user_data.username,
+        username.clone(),
         password,
         user_data.last_name,
         user_data.first_name,
         user_data.email,
         user_data.phone,
-    )
+    )?
     .save(&appstate.pool)
     .await?;
     update_counts(&appstate.pool).await?;
@@ -368,6 +465,10 @@ pub async fn add_user(
 /// `Enrollment token` allows to start the process of gaining access to the company infrastructure `(The enrollment token is valid for 24 hours)`. On the other hand, enrollment url allows the user to access the enrollment form via the web browser or perform the enrollment through the desktop client.
 ///
 /// Optionally this endpoint can send an email notification to the user about the enrollment.
+/// Users with a phone number are notified by a text message if email is not provided.
+/// Notifications are sent after the token is saved. If they can't be delivered, the token is kept
+/// and the response contains `mail_error` or `sms_error`, unless the mail failure policy is set to
+/// `abort`; then the token is removed and an error is returned.
 /// # Returns
 /// Returns json with `enrollment token` and `enrollment url` or `WebError` if error occurs.
 #[utoipa::path(
@@ -399,17 +500,6 @@ pub async fn start_enrollment(
         session.user.username
     );
 
-    // validate request
-    if data.send_enrollment_notification && data.email.is_none() {
-        error!(
-            "Email notification is enabled for user {}, but email was not provided",
-            session.user.username
-        );
-        return Err(WebError::BadRequest(
-            "Email notification is enabled, but email was not provided".into(),
-        ));
-    }
-
     debug!(
         "Search for the user {} in database to get started with enrollment process.",
         username
@@ -421,42 +511,115 @@ pub async fn start_enrollment(
         )));
     };
 
-    debug!("Create a new database transaction to save a new enrollment token into the database.");
-    let mut transaction = appstate.pool.begin().await?;
+    // validate request; users with a phone number can be notified by a text message instead
+    let has_phone = user.phone.as_ref().is_some_and(|phone| !phone.is_empty());
+    if data.send_enrollment_notification && data.email.is_none() && !has_phone {
+        error!(
+            "Email notification is enabled for user {}, but email was not provided",
+            session.user.username
+        );
+        return Err(WebError::BadRequest(
+            "Email notification is enabled, but email was not provided".into(),
+        ));
+    }
 
     let config = server_config();
-    let enrollment_token = user
+    let enrollment = user
         .start_enrollment(
-            &mut transaction,
+            &appstate.pool,
             &session.user,
             data.email,
             config.enrollment_token_timeout.as_secs(),
             config.enrollment_url.clone(),
             data.send_enrollment_notification,
             appstate.mail_tx.clone(),
+            appstate.sms_sender.as_ref(),
+            data.custom_welcome_message,
+            config.enrollment_mail_failure_policy,
         )
         .await?;
 
-    debug!("Try to commit transaction to save the enrollment token into the databse.");
-    transaction.commit().await?;
-    debug!("Transaction committed.");
-
     info!(
         "The enrollment process for {} has ended with success.",
         session.user.username
     );
     debug!(
         "Enrollment token {}, enrollment url {}",
-        enrollment_token,
+        enrollment.token,
         config.enrollment_url.to_string()
     );
 
+    let mut json = json!({"enrollment_token": enrollment.token, "enrollment_url": config.enrollment_url.to_string()});
+    if let Some(mail_error) = enrollment.mail_error {
+        json["mail_error"] = json!(mail_error);
+    }
+    if let Some(sms_error) = enrollment.sms_error {
+        json["sms_error"] = json!(sms_error);
+    }
     Ok(ApiResponse {
-        json: json!({"enrollment_token": enrollment_token, "enrollment_url": config.enrollment_url.to_string()}),
+        json,
         status: StatusCode::CREATED,
     })
 }
 
+/// Resend enrollment notification
+///
+/// Sends the enrollment email again for the pending enrollment of the user provided as a parameter in endpoint.
+/// Users with a phone number get the text message again, on the same terms as when the enrollment was started.
+/// The enrollment token stays the same, so it's still valid until it expires.
+///
+/// # Returns
+/// Returns empty object or `WebError` if error occurs.
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/{username}/enrollment/resend",
+    params(
+        ("username" = String, description = "Name of a user.")
+    ),
+    responses(
+        (status = 200, description = "Enrollment email has been sent again.", body = ApiResponse, example = json!({})),
+        (status = 400, description = "Enrollment was started without an email address and user has no phone number.", body = ApiResponse, example = json!({"msg": "Enrollment was started without an email address"})),
+        (status = 401, description = "Unauthorized to resend enrollment email.", body = ApiResponse, example = json!({"msg": "Session is required"})),
+        (status = 403, description = "You don't have permission to resend enrollment email.", body = ApiResponse, example = json!({"msg": "requires privileged access"})),
+        (status = 404, description = "User or pending enrollment not found.", body = ApiResponse, example = json!({"msg": "Enrollment token not found"})),
+        (status = 500, description = "Unable to send enrollment email.", body = ApiResponse, example = json!({"msg": "Internal server error"}))
+    ),
+    security(
+        ("cookie" = []),
+        ("api_token" = [])
+    )
+)]
+pub async fn resend_enrollment_notification(
+    _role: AdminRole,
+    session: SessionInfo,
+    State(appstate): State<AppState>,
+    Path(username): Path<String>,
+) -> ApiResult {
+    debug!(
+        "User {} resending enrollment email for user {username}",
+        session.user.username
+    );
+    let Some(user) = User::find_by_username(&appstate.pool, &username).await? else {
+        return Err(WebError::ObjectNotFound(format!(
+            "user {username} not found"
+        )));
+    };
+    let token = Token::find_pending_enrollment(&appstate.pool, user.id).await?;
+    token
+        .resend_notification(
+            &appstate.pool,
+            &appstate.mail_tx,
+            appstate.sms_sender.as_ref(),
+            server_config().enrollment_url.clone(),
+        )
+        .await?;
+    info!(
+        "User {} resent enrollment email for user {username}",
+        session.user.username
+    );
+    Ok(ApiResponse::default())
+}
+
 /// Start remote desktop configuration
 ///
 /// Allows admin to start new remote desktop configuration for user that is provided as a parameter in endpoint.
@@ -636,6 +799,7 @@ pub async fn modify_user(
 ) -> ApiResult {
     debug!("User {} updating user {username}", session.user.username);
     let mut user = user_for_admin_or_self(&appstate.pool, &session, &username).await?;
+    let old_user_info = UserInfo::from_user(&appstate.pool, &user).await?;
     if let Err(err) = check_username(&user_info.username) {
         debug!("Username {} rejected: {err}", user_info.username);
         return Ok(ApiResponse {
@@ -643,6 +807,12 @@ pub async fn modify_user(
             status: StatusCode::BAD_REQUEST,
         });
     }
+    // only admins can change groups and status, which may leave too few admins
+    let admin_count = if session.is_admin {
+        Some(User::find_admins(&appstate.pool).await?.len())
+    } else {
+        None
+    };
 
     let mut transaction = appstate.pool.begin().await?;
 
@@ -672,6 +842,11 @@ pub async fn modify_user(
             });
         }
 
+        // keep pending enrollments of a deactivated admin attributable
+        if user.is_active && !user_info.is_active && user.is_admin(&mut *transaction).await? {
+            Token::reassign_admin(&mut transaction, user.id, session.user.id).await?;
+        }
+
         // update VPN gateway config if user status or groups have changed
         if user_info
             .handle_user_groups(&mut transaction, &mut user)
@@ -689,6 +864,10 @@ pub async fn modify_user(
         };
         user_info.into_user_all_fields(&mut user)?;
     } else {
+        if user_info.mfa_method != user.mfa_method {
+            user.validate_mfa_method(&appstate.pool, &user_info.mfa_method)
+                .await?;
+        }
         user_info.into_user_safe_fields(&mut user)?;
     }
     user.save(&mut *transaction).await?;
@@ -696,9 +875,15 @@ pub async fn modify_user(
     // TODO: Reflect user status (active/disabled) modification in ldap
     let _result = ldap_modify_user(&username, &user).await;
     let user_info = UserInfo::from_user(&appstate.pool, &user).await?;
-    appstate.trigger_action(AppEvent::UserModified(user_info));
+    appstate.trigger_action(AppEvent::UserModified(UserModifiedData::new(
+        &old_user_info,
+        user_info,
+    )));
 
     transaction.commit().await?;
+    if let Some(admin_count) = admin_count {
+        notify_low_admin_count(&appstate.pool, &appstate.mail_tx, admin_count).await;
+    }
 
     info!("User {} updated user {username}", session.user.username);
     Ok(ApiResponse::default())
@@ -744,6 +929,14 @@ pub async fn delete_user(
         });
     }
     if let Some(user) = User::find_by_username(&appstate.pool, &username).await? {
+        // membership is needed to select group-scoped webhooks
+        let group_ids = user
+            .member_of(&appstate.pool)
+            .await?
+            .into_iter()
+            .map(|group| group.id)
+            .collect();
+        let admin_count = User::find_admins(&appstate.pool).await?.len();
         // Get rid of all devices of the deleted user from networks first
         debug!(
             "User {} deleted user {username}, purging their network devices across all networks.",
@@ -753,9 +946,13 @@ pub async fn delete_user(
         user.delete_and_cleanup(&mut transaction, &appstate.wireguard_tx)
             .await?;
 
-        appstate.trigger_action(AppEvent::UserDeleted(username.clone()));
+        appstate.trigger_action(AppEvent::UserDeleted(UserDeletedData {
+            username: username.clone(),
+            group_ids,
+        }));
         transaction.commit().await?;
         update_counts(&appstate.pool).await?;
+        notify_low_admin_count(&appstate.pool, &appstate.mail_tx, admin_count).await;
 
         info!("User {} deleted user {}", session.user.username, &username);
         Ok(ApiResponse::default())
@@ -767,9 +964,178 @@ pub async fn delete_user(
     }
 }
 
+/// Suspend user
+///
+/// Block all logins and VPN access of a user until the given time.
+/// The suspension is lifted automatically afterwards.
+#[utoipa::path(
+    put,
+    path = "/api/v1/user/{username}/suspend",
+    params(
+        ("username" = String, description = "name of a user"),
+    ),
+    request_body = SuspendUser,
+    responses(
+        (status = 200, description = "User has been suspended."),
+        (status = 400, description = "Bad request, suspension end is in the past.", body = ApiResponse, example = json!({"msg": "Suspension end must be in the future"})),
+        (status = 401, description = "Unauthorized to suspend user.", body = ApiResponse, example = json!({"msg": "Session is required"})),
+        (status = 403, description = "You don't have permission to suspend user.", body = ApiResponse, example = json!({"msg": "access denied"})),
+        (status = 404, description = "User does not exist with username: <username>", body = ApiResponse, example = json!({"msg": "User <username> not found"})),
+        (status = 500, description = "Unable to suspend user.", body = ApiResponse, example = json!({"msg": "Internal server error"}))
+    ),
+    security(
+        ("cookie" = []),
+        ("api_token" = [])
+    )
+)]
+pub async fn suspend_user(
+    _role: AdminRole,
+    State(appstate): State<AppState>,
+    Path(username): Path<String>,
+    session: SessionInfo,
+    Json(data): Json<SuspendUser>,
+) -> ApiResult {
+    debug!("User {} suspending user {username}", session.user.username);
+    if session.user.username == username {
+        debug!("User {username} attempted to suspend himself");
+        return Err(WebError::BadRequest("Cannot suspend yourself".into()));
+    }
+    if data.until <= Utc::now().naive_utc() {
+        return Err(WebError::BadRequest(
+            "Suspension end must be in the future".into(),
+        ));
+    }
+    let Some(mut user) = User::find_by_username(&appstate.pool, &username).await? else {
+        error!("User {username} not found");
+        return Err(WebError::ObjectNotFound(format!(
+            "User {username} not found"
+        )));
+    };
+    let mut transaction = appstate.pool.begin().await?;
+    user.suspend(&mut transaction, data.until, &appstate.wireguard_tx)
+        .await?;
+    transaction.commit().await?;
+
+    info!(
+        "User {} suspended user {username} until {}",
+        session.user.username, data.until
+    );
+    Ok(ApiResponse::default())
+}
+
+/// Lift user suspension
+///
+/// Restore logins and VPN access of a suspended user before the suspension expires.
+#[utoipa::path(
+    delete,
+    path = "/api/v1/user/{username}/suspend",
+    params(
+        ("username" = String, description = "name of a user"),
+    ),
+    responses(
+        (status = 200, description = "User suspension has been lifted."),
+        (status = 401, description = "Unauthorized to lift user suspension.", body = ApiResponse, example = json!({"msg": "Session is required"})),
+        (status = 403, description = "You don't have permission to lift user suspension.", body = ApiResponse, example = json!({"msg": "access denied"})),
+        (status = 404, description = "User does not exist with username: <username>", body = ApiResponse, example = json!({"msg": "User <username> not found"})),
+        (status = 500, description = "Unable to lift user suspension.", body = ApiResponse, example = json!({"msg": "Internal server error"}))
+    ),
+    security(
+        ("cookie" = []),
+        ("api_token" = [])
+    )
+)]
+pub async fn lift_user_suspension(
+    _role: AdminRole,
+    State(appstate): State<AppState>,
+    Path(username): Path<String>,
+    session: SessionInfo,
+) -> ApiResult {
+    debug!(
+        "User {} lifting suspension of user {username}",
+        session.user.username
+    );
+    let Some(mut user) = User::find_by_username(&appstate.pool, &username).await? else {
+        error!("User {username} not found");
+        return Err(WebError::ObjectNotFound(format!(
+            "User {username} not found"
+        )));
+    };
+    let mut transaction = appstate.pool.begin().await?;
+    user.lift_suspension(&mut transaction, &appstate.wireguard_tx)
+        .await?;
+    transaction.commit().await?;
+
+    info!(
+        "User {} lifted suspension of user {username}",
+        session.user.username
+    );
+    Ok(ApiResponse::default())
+}
+
+/// Check recovery code
+///
+/// Check whether a recovery code claimed by a user is valid, without consuming it.
+/// Meant for support diagnostics, so admins can't check their own codes.
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/{username}/recovery_code/check",
+    params(
+        ("username" = String, description = "name of a user"),
+    ),
+    responses(
+        (status = 200, description = "Validity of the recovery code.", body = ApiResponse, example = json!({"valid": true})),
+        (status = 401, description = "Unauthorized to check recovery code.", body = ApiResponse, example = json!({"msg": "Session is required"})),
+        (status = 403, description = "You don't have permission to check recovery code.", body = ApiResponse, example = json!({"msg": "access denied"})),
+        (status = 404, description = "User does not exist with username: <username>", body = ApiResponse, example = json!({"msg": "User <username> not found"})),
+        (status = 500, description = "Unable to check recovery code.", body = ApiResponse, example = json!({"msg": "Internal server error"}))
+    ),
+    security(
+        ("cookie" = []),
+        ("api_token" = [])
+    )
+)]
+pub async fn check_recovery_code(
+    _role: AdminRole,
+    State(appstate): State<AppState>,
+    Path(username): Path<String>,
+    session: SessionInfo,
+    Json(data): Json<RecoveryCode>,
+) -> ApiResult {
+    debug!(
+        "User {} checking recovery code of user {username}",
+        session.user.username
+    );
+    let Some(user) = User::find_by_username(&appstate.pool, &username).await? else {
+        error!("User {username} not found");
+        return Err(WebError::ObjectNotFound(format!(
+            "User {username} not found"
+        )));
+    };
+    if user.id == session.user.id {
+        warn!(
+            "User {} tried to check their own recovery code",
+            session.user.username
+        );
+        return Err(WebError::Forbidden(
+            "Cannot check your own recovery code".into(),
+        ));
+    }
+    let valid = user.check_recovery_code(&data.code);
+
+    info!(
+        "User {} checked recovery code of user {username}, valid: {valid}",
+        session.user.username
+    );
+    Ok(ApiResponse {
+        json: json!({ "valid": valid }),
+        status: StatusCode::OK,
+    })
+}
+
 /// Change your own password
 ///
 /// Change your own password, it could return error if password is not strong enough.
+/// This is the only endpoint available while the password has to be changed after an admin reset.
 ///
 /// # Returns
 /// If erorr occurs, endpoint will return `WebError` object.
@@ -789,7 +1155,7 @@ pub async fn delete_user(
     )
 )]
 pub async fn change_self_password(
-    session: SessionInfo,
+    PasswordChangeSession(session): PasswordChangeSession,
     State(appstate): State<AppState>,
     Json(data): Json<PasswordChangeSelf>,
 ) -> ApiResult {
@@ -802,7 +1168,7 @@ pub async fn change_self_password(
         });
     }
 
-    if let Err(err) = check_password_strength(&data.new_password) {
+    if let Err(err) = check_password_strength(&data.new_password).await {
         debug!("User {} password change failed: {err}", user.username);
         return Ok(ApiResponse {
             json: json!({}),
@@ -810,8 +1176,11 @@ pub async fn change_self_password(
         });
     }
 
-    user.set_password(&data.new_password);
-    user.save(&appstate.pool).await?;
+    let mut transaction = appstate.pool.begin().await?;
+    user.set_password(&mut transaction, &data.new_password)
+        .await?;
+    user.save(&mut *transaction).await?;
+    transaction.commit().await?;
 
     let _ = ldap_change_password(&user.username, &data.new_password).await;
 
@@ -826,6 +1195,7 @@ pub async fn change_self_password(
 /// Change user password
 ///
 /// Change user password, it could return error if password is not strong enough.
+/// The user will have to change the password after logging in.
 ///
 /// `This endpoint doesn't allow you to change your own password. Go to: /api/v1/user/change_password.`
 ///
@@ -871,7 +1241,7 @@ pub async fn change_password(
         });
     }
 
-    if let Err(err) = check_password_strength(&data.new_password) {
+    if let Err(err) = check_password_strength(&data.new_password).await {
         debug!("Password for user {username} not strong enough: {err}");
         return Ok(ApiResponse {
             json: json!({}),
@@ -889,8 +1259,13 @@ pub async fn change_password(
     let user = User::find_by_username(&appstate.pool, &username).await?;
 
     if let Some(mut user) = user {
-        user.set_password(&data.new_password);
-        user.save(&appstate.pool).await?;
+        let mut transaction = appstate.pool.begin().await?;
+        user.set_password(&mut transaction, &data.new_password)
+            .await?;
+        // password known to the admin has to be replaced by the user
+        user.must_change_password = true;
+        user.save(&mut *transaction).await?;
+        transaction.commit().await?;
         let _ = ldap_change_password(&username, &data.new_password).await;
         info!(
             "Admin {} changed password for user {username}",
@@ -1052,8 +1427,10 @@ pub async fn delete_security_key(
     let mut user = user_for_admin_or_self(&appstate.pool, &session, &username).await?;
     if let Some(webauthn) = WebAuthn::find_by_id(&appstate.pool, id).await? {
         if webauthn.user_id == user.id {
+            let previous = MfaState::from(&user);
             webauthn.delete(&appstate.pool).await?;
             user.verify_mfa_state(&appstate.pool).await?;
+            appstate.trigger_mfa_changed(&user, previous);
             info!(
                 "User {} deleted security key {id} for user {username}",
                 session.user.username,
@@ -1075,6 +1452,351 @@ pub async fn delete_security_key(
     }
 }
 
+/// Disable MFA of many users
+///
+/// Resets MFA of given users at once, e.g. when migrating to another MFA provider:
+/// removes TOTP and email MFA secrets, security keys and recovery codes.
+/// Each reset is recorded in an audit log. Ids of non-existent users are skipped.
+///
+/// # Returns
+/// Returns number of users whose MFA was disabled or `WebError` if error occurs.
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/mfa/disable",
+    request_body = BulkDisableMfa,
+    responses(
+        (status = 200, description = "MFA disabled.", body = ApiResponse, example = json!({"count": 2})),
+        (status = 401, description = "Unauthorized to disable MFA.", body = ApiResponse, example = json!({"msg": "Session is required"})),
+        (status = 403, description = "You don't have permission to disable MFA of other users.", body = ApiResponse, example = json!({"msg": "requires privileged access"})),
+        (status = 500, description = "Cannot disable MFA.", body = ApiResponse, example = json!({"msg": "Internal server error"}))
+    ),
+    security(
+        ("cookie" = []),
+        ("api_token" = [])
+    )
+)]
+pub async fn bulk_disable_mfa(
+    _role: AdminRole,
+    session: SessionInfo,
+    State(appstate): State<AppState>,
+    Json(data): Json<BulkDisableMfa>,
+) -> ApiResult {
+    debug!(
+        "User {} disabling MFA of {} users",
+        session.user.username,
+        data.users.len()
+    );
+    let mut previous = Vec::with_capacity(data.users.len());
+    for id in &data.users {
+        if let Some(user) = User::find_by_id(&appstate.pool, *id).await? {
+            previous.push((user.id, MfaState::from(&user)));
+        }
+    }
+    let count = User::bulk_disable_mfa(&appstate.pool, &data.users, session.user.id).await?;
+    for (id, state) in previous {
+        if let Some(user) = User::find_by_id(&appstate.pool, id).await? {
+            appstate.trigger_mfa_changed(&user, state);
+        }
+    }
+    info!(
+        "User {} disabled MFA of {count} users",
+        session.user.username
+    );
+    Ok(ApiResponse {
+        json: json!({ "count": count }),
+        status: StatusCode::OK,
+    })
+}
+
+/// List security keys of all users
+///
+/// Returns registered WebAuthn security keys of all users with their owners, for security audits.
+/// Credential data is not exposed.
+///
+/// # Returns
+/// Returns a page of `SecurityKeyOwnerInfo` objects or `WebError` if error occurs.
+#[utoipa::path(
+    get,
+    path = "/api/v1/security_key",
+    params(
+        ("limit" = Option<i64>, Query, description = "maximum number of returned keys"),
+        ("offset" = Option<i64>, Query, description = "number of keys to skip"),
+    ),
+    responses(
+        (status = 200, description = "Page of security keys.", body = ApiResponse, example = json!({
+            "items": [
+                {
+                    "id": 1,
+                    "name": "yubikey",
+                    "user_id": 2,
+                    "username": "hpotter"
+                }
+            ],
+            "total": 1,
+            "limit": 50,
+            "offset": 0
+        })),
+        (status = 401, description = "Unauthorized to list security keys.", body = ApiResponse, example = json!({"msg": "Session is required"})),
+        (status = 403, description = "You don't have permission to list security keys.", body = ApiResponse, example = json!({"msg": "requires privileged access"})),
+        (status = 500, description = "Cannot list security keys.", body = ApiResponse, example = json!({"msg": "Internal server error"}))
+    ),
+    security(
+        ("cookie" = []),
+        ("api_token" = [])
+    )
+)]
+pub async fn list_security_keys(
+    _role: AdminRole,
+    State(appstate): State<AppState>,
+    Query(pagination): Query<PaginationParams>,
+) -> ApiResult {
+    debug!("Listing security keys of all users");
+    let keys =
+        WebAuthn::all_with_owners(&appstate.pool, pagination.limit(), pagination.offset()).await?;
+    let total = WebAuthn::count(&appstate.pool).await?;
+    Ok(ApiResponse {
+        json: json!(Page::new(keys, total, &pagination)),
+        status: StatusCode::OK,
+    })
+}
+
+/// List enrollment tokens
+///
+/// Returns enrollment, desktop configuration and password reset tokens of all users, newest first.
+/// Token secrets are not exposed.
+///
+/// # Returns
+/// Returns a page of `TokenInfo` objects or `WebError` if error occurs.
+#[utoipa::path(
+    get,
+    path = "/api/v1/enrollment",
+    params(
+        ("limit" = Option<i64>, Query, description = "maximum number of returned tokens"),
+        ("offset" = Option<i64>, Query, description = "number of tokens to skip"),
+    ),
+    responses(
+        (status = 200, description = "Page of enrollment tokens.", body = ApiResponse, example = json!({
+            "items": [
+                {
+                    "user_id": 2,
+                    "username": "hpotter",
+                    "admin_id": 1,
+                    "email": "h.potter@hogwart.edu.uk",
+                    "created_at": "2025-04-06T12:00:00",
+                    "expires_at": "2025-04-07T12:00:00",
+                    "used_at": null,
+                    "token_type": "ENROLLMENT"
+                }
+            ],
+            "total": 1,
+            "limit": 50,
+            "offset": 0
+        })),
+        (status = 401, description = "Unauthorized to list enrollment tokens.", body = ApiResponse, example = json!({"msg": "Session is required"})),
+        (status = 403, description = "You don't have permission to list enrollment tokens.", body = ApiResponse, example = json!({"msg": "requires privileged access"})),
+        (status = 500, description = "Cannot list enrollment tokens.", body = ApiResponse, example = json!({"msg": "Internal server error"}))
+    ),
+    security(
+        ("cookie" = []),
+        ("api_token" = [])
+    )
+)]
+pub async fn list_enrollment_tokens(
+    _role: AdminRole,
+    State(appstate): State<AppState>,
+    Query(pagination): Query<PaginationParams>,
+) -> ApiResult {
+    debug!("Listing enrollment tokens");
+    let tokens: Vec<TokenInfo> =
+        Token::fetch_page_info(&appstate.pool, pagination.limit(), pagination.offset()).await?;
+    let total = Token::count(&appstate.pool).await?;
+    Ok(ApiResponse {
+        json: json!(Page::new(tokens, total, &pagination)),
+        status: StatusCode::OK,
+    })
+}
+
+/// Enrollment statistics cover this many days by default.
+const DEFAULT_ENROLLMENT_STATS_DAYS: i64 = 30;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct EnrollmentStatsQuery {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+}
+
+/// Enrollment statistics
+///
+/// Counts enrollment tokens issued in a given time range (last 30 days by default)
+/// by their progress: started, completed (the user has set a password) and expired unused.
+///
+/// # Returns
+/// Returns `EnrollmentStats` object or `WebError` if error occurs.
+#[utoipa::path(
+    get,
+    path = "/api/v1/enrollment/stats",
+    params(
+        ("from" = Option<String>, Query, description = "Start of the time range (RFC 3339), inclusive."),
+        ("to" = Option<String>, Query, description = "End of the time range (RFC 3339), exclusive; defaults to now."),
+    ),
+    responses(
+        (status = 200, description = "Enrollment statistics.", body = EnrollmentStats, example = json!({
+            "issued": 10,
+            "started": 8,
+            "completed": 7,
+            "expired_unused": 2
+        })),
+        (status = 400, description = "Invalid time range.", body = ApiResponse, example = json!({"msg": "Start of the time range must precede its end"})),
+        (status = 401, description = "Unauthorized to get enrollment statistics.", body = ApiResponse, example = json!({"msg": "Session is required"})),
+        (status = 403, description = "You don't have permission to get enrollment statistics.", body = ApiResponse, example = json!({"msg": "requires privileged access"})),
+        (status = 500, description = "Cannot get enrollment statistics.", body = ApiResponse, example = json!({"msg": "Internal server error"}))
+    ),
+    security(
+        ("cookie" = []),
+        ("api_token" = [])
+    )
+)]
+pub async fn enrollment_stats(
+    _role: AdminRole,
+    State(appstate): State<AppState>,
+    Query(query): Query<EnrollmentStatsQuery>,
+) -> ApiResult {
+    let to = query.to.unwrap_or_else(Utc::now);
+    let from = query
+        .from
+        .unwrap_or(to - TimeDelta::days(DEFAULT_ENROLLMENT_STATS_DAYS));
+    if from >= to {
+        return Err(WebError::BadRequest(
+            "Start of the time range must precede its end".into(),
+        ));
+    }
+    debug!("Counting enrollments from {from} to {to}");
+    let stats = Token::completion_stats(&appstate.pool, from.naive_utc(), to.naive_utc()).await?;
+    Ok(ApiResponse {
+        json: json!(stats),
+        status: StatusCode::OK,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct RevokeTokenQuery {
+    token_type: Option<String>,
+}
+
+/// Revoke enrollment token
+///
+/// Deletes user's enrollment (including desktop configuration) or password reset tokens before
+/// they expire. Session started with the token can't be continued either.
+///
+/// # Returns
+/// Returns empty object or `WebError` if error occurs.
+#[utoipa::path(
+    delete,
+    path = "/api/v1/user/{username}/enrollment",
+    params(
+        ("username" = String, description = "Name of a user whose tokens are revoked."),
+        ("token_type" = Option<String>, Query, description = "Type of tokens to revoke: `ENROLLMENT` (default) or `PASSWORD_RESET`.")
+    ),
+    responses(
+        (status = 200, description = "Tokens revoked.", body = ApiResponse, example = json!({})),
+        (status = 400, description = "Invalid token type.", body = ApiResponse, example = json!({"msg": "Invalid token type"})),
+        (status = 401, description = "Unauthorized to revoke tokens.", body = ApiResponse, example = json!({"msg": "Session is required"})),
+        (status = 403, description = "You don't have permission to revoke tokens.", body = ApiResponse, example = json!({"msg": "requires privileged access"})),
+        (status = 404, description = "User or token not found.", body = ApiResponse, example = json!({"msg": "Enrollment token not found"})),
+        (status = 500, description = "Cannot revoke token.", body = ApiResponse, example = json!({"msg": "Internal server error"}))
+    ),
+    security(
+        ("cookie" = []),
+        ("api_token" = [])
+    )
+)]
+pub async fn revoke_enrollment_token(
+    _role: AdminRole,
+    session: SessionInfo,
+    State(appstate): State<AppState>,
+    Path(username): Path<String>,
+    Query(query): Query<RevokeTokenQuery>,
+) -> ApiResult {
+    let token_type = match query.token_type.as_deref() {
+        None => ENROLLMENT_TOKEN_TYPE,
+        Some(token_type)
+            if token_type == ENROLLMENT_TOKEN_TYPE || token_type == PASSWORD_RESET_TOKEN_TYPE =>
+        {
+            token_type
+        }
+        Some(_) => return Err(WebError::BadRequest("Invalid token type".into())),
+    };
+    debug!(
+        "User {} revoking {token_type} tokens of user {username}",
+        session.user.username
+    );
+    let Some(user) = User::find_by_username(&appstate.pool, &username).await? else {
+        return Err(WebError::ObjectNotFound(format!(
+            "user {username} not found"
+        )));
+    };
+    let tokens = Token::revoke(&appstate.pool, user.id, token_type, session.user.id).await?;
+    info!(
+        "User {} revoked {} {token_type} tokens of user {username}",
+        session.user.username,
+        tokens.len()
+    );
+    Ok(ApiResponse::default())
+}
+
+/// Enrollment token history
+///
+/// Lists lifecycle events of user's enrollment tokens, oldest first: creation, sent emails,
+/// session start, completion, expiry and revocation. History is kept after tokens are revoked.
+///
+/// # Returns
+/// Returns a list of `EnrollmentEvent` objects or `WebError` if error occurs.
+#[utoipa::path(
+    get,
+    path = "/api/v1/user/{username}/enrollment/history",
+    params(
+        ("username" = String, description = "Name of a user to show enrollment history of.")
+    ),
+    responses(
+        (status = 200, description = "Enrollment history.", body = [EnrollmentEvent], example = json!([
+            {
+                "id": 1,
+                "user_id": 2,
+                "event_type": "token_created",
+                "admin_id": 1,
+                "ip_address": null,
+                "user_agent": null,
+                "timestamp": "2025-04-06T12:00:00"
+            }
+        ])),
+        (status = 401, description = "Unauthorized to show token history.", body = ApiResponse, example = json!({"msg": "Session is required"})),
+        (status = 403, description = "You don't have permission to show token history.", body = ApiResponse, example = json!({"msg": "requires privileged access"})),
+        (status = 404, description = "User not found.", body = ApiResponse, example = json!({"msg": "user <username> not found"})),
+        (status = 500, description = "Cannot fetch token history.", body = ApiResponse, example = json!({"msg": "Internal server error"}))
+    ),
+    security(
+        ("cookie" = []),
+        ("api_token" = [])
+    )
+)]
+pub async fn enrollment_token_history(
+    _role: AdminRole,
+    State(appstate): State<AppState>,
+    Path(username): Path<String>,
+) -> ApiResult {
+    let Some(user) = User::find_by_username(&appstate.pool, &username).await? else {
+        debug!("User {username} not found");
+        return Err(WebError::ObjectNotFound(format!(
+            "user {username} not found"
+        )));
+    };
+    let events = Token::history(&appstate.pool, user.id).await?;
+    Ok(ApiResponse {
+        json: json!(events),
+        status: StatusCode::OK,
+    })
+}
+
 /// Returns your data
 ///
 /// Endpoint returns the data associated with the current session user```
@@ -1,14 +1,23 @@
 use axum::{
-    extract::{Json, Path, State},
+    extract::{Json, Path, Query, State},
     http::StatusCode,
 };
+use reqwest::Url;
 use serde_json::json;
+use sqlx::{Error as SqlxError, PgExecutor};
 
 use super::{ApiResponse, ApiResult, WebHookData};
 use crate::{
     appstate::AppState,
     auth::{AdminRole, SessionInfo},
-    db::WebHook,
+    db::{
+        models::{
+            webhook::{validate_body_template, validate_custom_headers, validate_retry_policy},
+            webhook_delivery::WebHookDeliveryLog,
+        },
+        Group, Id, WebHook,
+    },
+    error::WebError,
 };
 
 pub async fn add_webhook(
@@ -19,6 +28,10 @@ pub async fn add_webhook(
 ) -> ApiResult {
     let url = webhookdata.url.clone();
     debug!("User {} adding webhook {url}", session.user.username);
+    validate_webhook_payload(&webhookdata).map_err(WebError::BadRequest)?;
+    if !group_exists(&appstate.pool, webhookdata.group_id).await? {
+        return Err(WebError::BadRequest("group not found".into()));
+    }
     let webhook: WebHook = webhookdata.into();
     let status = match webhook.save(&appstate.pool).await {
         Ok(_) => StatusCode::CREATED,
@@ -32,9 +45,124 @@ pub async fn add_webhook(
     })
 }
 
+/// Outcome of importing a single webhook.
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportStatus {
+    Created,
+    Duplicate,
+    Invalid,
+}
+
+#[derive(Serialize)]
+pub struct ImportResult {
+    pub url: String,
+    pub status: ImportStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ImportResult {
+    fn new(url: String, status: ImportStatus, error: Option<String>) -> Self {
+        Self { url, status, error }
+    }
+}
+
+/// Check webhook definition before import.
+fn validate_webhook(data: &WebHookData) -> Result<(), String> {
+    if Url::parse(&data.url).is_err() {
+        return Err("invalid URL".into());
+    }
+    if data.token.trim().is_empty() {
+        return Err("missing token".into());
+    }
+    validate_webhook_payload(data)
+}
+
+/// Check custom headers, body template and retry policy.
+fn validate_webhook_payload(data: &WebHookData) -> Result<(), String> {
+    validate_custom_headers(&data.custom_headers)?;
+    validate_retry_policy(data.max_attempts, data.retry_base_delay)?;
+    if let Some(template) = &data.body_template {
+        validate_body_template(template)?;
+    }
+    Ok(())
+}
+
+/// Check that the group webhook is scoped to exists. Unscoped webhooks pass.
+async fn group_exists<'e, E>(executor: E, group_id: Option<Id>) -> Result<bool, SqlxError>
+where
+    E: PgExecutor<'e>,
+{
+    match group_id {
+        Some(group_id) => Ok(Group::find_by_id(executor, group_id).await?.is_some()),
+        None => Ok(true),
+    }
+}
+
+/// Create multiple webhooks at once. Webhooks with a URL which already exists
+/// (in an active webhook or earlier in the same request) are skipped.
+pub async fn import_webhooks(
+    _admin: AdminRole,
+    session: SessionInfo,
+    State(appstate): State<AppState>,
+    Json(webhooks): Json<Vec<WebHookData>>,
+) -> ApiResult {
+    debug!(
+        "User {} importing {} webhooks",
+        session.user.username,
+        webhooks.len()
+    );
+    let mut results = Vec::with_capacity(webhooks.len());
+    let (mut created, mut skipped, mut failed) = (0, 0, 0);
+    let mut transaction = appstate.pool.begin().await?;
+    for data in webhooks {
+        let mut validation = validate_webhook(&data);
+        if validation.is_ok() && !group_exists(&mut *transaction, data.group_id).await? {
+            validation = Err("group not found".into());
+        }
+        if let Err(err) = validation {
+            failed += 1;
+            results.push(ImportResult::new(
+                data.url,
+                ImportStatus::Invalid,
+                Some(err),
+            ));
+            continue;
+        }
+        if WebHook::find_by_url(&mut *transaction, &data.url)
+            .await?
+            .is_some()
+        {
+            skipped += 1;
+            results.push(ImportResult::new(data.url, ImportStatus::Duplicate, None));
+            continue;
+        }
+        let url = data.url.clone();
+        WebHook::from(data).save(&mut *transaction).await?;
+        created += 1;
+        results.push(ImportResult::new(url, ImportStatus::Created, None));
+    }
+    transaction.commit().await?;
+    info!(
+        "User {} imported webhooks: {created} created, {skipped} skipped, {failed} invalid",
+        session.user.username
+    );
+
+    Ok(ApiResponse {
+        json: json!({
+            "created": created,
+            "skipped": skipped,
+            "failed": failed,
+            "results": results,
+        }),
+        status: StatusCode::OK,
+    })
+}
+
 // TODO: paginate
 pub async fn list_webhooks(_admin: AdminRole, State(appstate): State<AppState>) -> ApiResult {
-    let webhooks = WebHook::all(&appstate.pool).await?;
+    let webhooks = WebHook::all_active(&appstate.pool).await?;
 
     Ok(ApiResponse {
         json: json!(webhooks),
@@ -47,7 +175,7 @@ pub async fn get_webhook(
     State(appstate): State<AppState>,
     Path(id): Path<i64>,
 ) -> ApiResult {
-    match WebHook::find_by_id(&appstate.pool, id).await? {
+    match WebHook::find_active_by_id(&appstate.pool, id).await? {
         Some(webhook) => Ok(ApiResponse {
             json: json!(webhook),
             status: StatusCode::OK,
@@ -67,7 +195,11 @@ pub async fn change_webhook(
     Json(data): Json<WebHookData>,
 ) -> ApiResult {
     debug!("User {} updating webhook {id}", session.user.username);
-    let status = match WebHook::find_by_id(&appstate.pool, id).await? {
+    validate_webhook_payload(&data).map_err(WebError::BadRequest)?;
+    if !group_exists(&appstate.pool, data.group_id).await? {
+        return Err(WebError::BadRequest("group not found".into()));
+    }
+    let status = match WebHook::find_active_by_id(&appstate.pool, id).await? {
         Some(mut webhook) => {
             webhook.url = data.url;
             webhook.description = data.description;
@@ -77,6 +209,15 @@ pub async fn change_webhook(
             webhook.on_user_deleted = data.on_user_deleted;
             webhook.on_user_modified = data.on_user_modified;
             webhook.on_hwkey_provision = data.on_hwkey_provision;
+            webhook.on_enrollment_pending = data.on_enrollment_pending;
+            webhook.on_enrollment_completed = data.on_enrollment_completed;
+            webhook.on_user_mfa_changed = data.on_user_mfa_changed;
+            webhook.group_id = data.group_id;
+            webhook.paused_until = data.paused_until;
+            webhook.custom_headers.0 = data.custom_headers;
+            webhook.body_template = data.body_template;
+            webhook.max_attempts = data.max_attempts;
+            webhook.retry_base_delay = data.retry_base_delay;
             webhook.save(&appstate.pool).await?;
             StatusCode::OK
         }
@@ -90,27 +231,59 @@ pub async fn change_webhook(
     })
 }
 
+#[derive(Deserialize)]
+pub struct DeleteWebHookParams {
+    // remove the webhook along with its deliveries instead of archiving it
+    #[serde(default)]
+    pub hard: bool,
+}
+
+/// Archive webhook, so it's hidden and no longer triggered, but kept along with
+/// its delivery log. Pass `hard=true` to remove it permanently.
 pub async fn delete_webhook(
     _admin: AdminRole,
     State(appstate): State<AppState>,
     Path(id): Path<i64>,
+    Query(params): Query<DeleteWebHookParams>,
     session: SessionInfo,
 ) -> ApiResult {
     debug!("User {} deleting webhook {id}", session.user.username);
     let status = match WebHook::find_by_id(&appstate.pool, id).await? {
-        Some(webhook) => {
+        Some(webhook) if params.hard => {
             webhook.delete(&appstate.pool).await?;
+            info!("User {} deleted webhook {id}", session.user.username);
+            StatusCode::OK
+        }
+        Some(webhook) if webhook.archived => StatusCode::NOT_FOUND,
+        Some(mut webhook) => {
+            webhook.archive(&appstate.pool).await?;
+            info!("User {} archived webhook {id}", session.user.username);
             StatusCode::OK
         }
         None => StatusCode::NOT_FOUND,
     };
-    info!("User {} deleted webhook {id}", session.user.username);
     Ok(ApiResponse {
         json: json!({}),
         status,
     })
 }
 
+/// List finished deliveries of a webhook, including an archived one.
+pub async fn list_webhook_deliveries(
+    _admin: AdminRole,
+    State(appstate): State<AppState>,
+    Path(id): Path<i64>,
+) -> ApiResult {
+    if WebHook::find_by_id(&appstate.pool, id).await?.is_none() {
+        return Err(WebError::ObjectNotFound(format!("webhook {id} not found")));
+    }
+    let deliveries = WebHookDeliveryLog::all_for_webhook(&appstate.pool, id).await?;
+    Ok(ApiResponse {
+        json: json!(deliveries),
+        status: StatusCode::OK,
+    })
+}
+
 #[derive(Deserialize)]
 pub struct ChangeStateData {
     pub enabled: bool,
@@ -127,7 +300,7 @@ pub async fn change_enabled(
         "User {} changing webhook {id} enabled state to {}",
         session.user.username, data.enabled
     );
-    let status = match WebHook::find_by_id(&appstate.pool, id).await? {
+    let status = match WebHook::find_active_by_id(&appstate.pool, id).await? {
         Some(mut webhook) => {
             webhook.enabled = data.enabled;
             webhook.save(&appstate.pool).await?;
@@ -24,18 +24,18 @@ use crate::{
         models::{
             device::{
                 DeviceConfig, DeviceInfo, DeviceNetworkInfo, DeviceType, ModifyDevice,
-                WireguardNetworkDevice,
+                StaleDeviceInfo, WireguardNetworkDevice,
             },
             wireguard::{
-                DateTimeAggregation, MappedDevice, WireguardDeviceStatsRow, WireguardNetworkInfo,
-                WireguardUserStatsRow,
+                validate_dns_search_domains, DateTimeAggregation, MappedDevice,
+                WireguardDeviceStatsRow, WireguardNetworkInfo, WireguardUserStatsRow,
             },
         },
-        AddDevice, Device, GatewayEvent, Id, WireguardNetwork,
+        AddDevice, Device, GatewayEvent, Id, User, WireguardNetwork,
     },
     enterprise::{handlers::CanManageDevices, limits::update_counts},
     grpc::GatewayMap,
-    handlers::mail::send_new_device_added_email,
+    handlers::mail::{send_device_config_email, send_new_device_added_email},
     server_config,
     templates::TemplateLocation,
     wg_config::{parse_wireguard_config, ImportedDevice},
@@ -57,6 +57,9 @@ pub struct WireguardNetworkData {
     pub port: i32,
     pub allowed_ips: Option<String>,
     pub dns: Option<String>,
+    // left unchanged on modification when absent
+    #[serde(default)]
+    pub dns_search_domains: Option<Vec<String>>,
     pub allowed_groups: Vec<String>,
     pub mfa_enabled: bool,
     pub keepalive_interval: i32,
@@ -97,6 +100,7 @@ pub struct ImportedNetworkData {
     request_body = WireguardNetworkData,
     responses(
         (status = 201, description = "Successfully created network.", body = WireguardNetwork),
+        (status = 400, description = "Invalid network data.", body = ApiResponse, example = json!({"msg": "Invalid DNS search domain: example..com"})),
         (status = 401, description = "Unauthorized to create network.", body = ApiResponse, example = json!({"msg": "Session is required"})),
         (status = 403, description = "You don't have permission to create a network.", body = ApiResponse, example = json!({"msg": "access denied"})),
         (status = 500, description = "Unable to create network.", body = ApiResponse, example = json!({"msg": "Internal server error"}))
@@ -117,8 +121,11 @@ pub(crate) async fn create_network(
         "User {} creating WireGuard network {network_name}",
         session.user.username
     );
+    if let Some(domains) = &data.dns_search_domains {
+        validate_dns_search_domains(domains).map_err(WebError::BadRequest)?;
+    }
     let allowed_ips = data.parse_allowed_ips();
-    let network = WireguardNetwork::new(
+    let mut network = WireguardNetwork::new(
         data.name,
         parse_address_list(&data.address),
         data.port,
@@ -130,6 +137,7 @@ pub(crate) async fn create_network(
         data.peer_disconnect_threshold,
     )
     .map_err(|_| WebError::Serialization("Invalid network address".into()))?;
+    network.dns_search_domains = data.dns_search_domains.unwrap_or_default();
 
     let mut transaction = appstate.pool.begin().await?;
     let network = network.save(&mut *transaction).await?;
@@ -169,6 +177,7 @@ async fn find_network(id: Id, pool: &PgPool) -> Result<WireguardNetwork<Id>, Web
     request_body = WireguardNetworkData,
     responses(
         (status = 200, description = "Successfully modified network.", body = WireguardNetwork),
+        (status = 400, description = "Invalid network data.", body = ApiResponse, example = json!({"msg": "Invalid DNS search domain: example..com"})),
         (status = 401, description = "Unauthorized to modify network.", body = ApiResponse, example = json!({"msg": "Session is required"})),
         (status = 403, description = "You don't have permission to modify a network.", body = ApiResponse, example = json!({"msg": "access denied"})),
         (status = 404, description = "Network not found", body = ApiResponse, example = json!({"msg": "network not found"})),
@@ -190,6 +199,9 @@ pub(crate) async fn modify_network(
         "User {} updating WireGuard network {network_id}",
         session.user.username
     );
+    if let Some(domains) = &data.dns_search_domains {
+        validate_dns_search_domains(domains).map_err(WebError::BadRequest)?;
+    }
     let mut network = find_network(network_id, &appstate.pool).await?;
     network.allowed_ips = data.parse_allowed_ips();
     network.name = data.name;
@@ -200,6 +212,9 @@ pub(crate) async fn modify_network(
     network.endpoint = data.endpoint;
     network.port = data.port;
     network.dns = data.dns;
+    if let Some(domains) = data.dns_search_domains {
+        network.dns_search_domains = domains;
+    }
     network.address = parse_address_list(&data.address);
     network.mfa_enabled = data.mfa_enabled;
     network.keepalive_interval = data.keepalive_interval;
@@ -795,6 +810,32 @@ pub(crate) async fn modify_device(
     })
 }
 
+/// Mark device as user's primary device
+pub(crate) async fn set_primary_device(
+    _can_manage_devices: CanManageDevices,
+    session: SessionInfo,
+    Path(device_id): Path<i64>,
+    State(appstate): State<AppState>,
+) -> ApiResult {
+    debug!(
+        "User {} setting device {device_id} as primary",
+        session.user.username
+    );
+    let mut device = device_for_admin_or_self(&appstate.pool, &session, device_id).await?;
+    let mut transaction = appstate.pool.begin().await?;
+    device.set_primary(&mut transaction).await?;
+    transaction.commit().await?;
+    info!(
+        "User {} set device {device_id} as primary",
+        session.user.username
+    );
+
+    Ok(ApiResponse {
+        json: json!(device),
+        status: StatusCode::OK,
+    })
+}
+
 /// Get device
 ///
 /// # Returns
@@ -914,6 +955,76 @@ pub(crate) async fn list_devices(_role: AdminRole, State(appstate): State<AppSta
     })
 }
 
+/// Devices without a handshake for this many days are considered stale by default.
+const DEFAULT_STALE_DEVICE_DAYS: u32 = 30;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct StaleDevicesQuery {
+    days: Option<u32>,
+}
+
+/// List stale devices
+///
+/// Lists devices which haven't connected to any network in a given number of days (30 by default),
+/// or never connected at all, so that abandoned devices can be cleaned up.
+///
+/// This endpoint requires `admin` role.
+///
+/// # Returns
+/// Returns a list of `StaleDeviceInfo` objects or `WebError` object if error occurs.
+#[utoipa::path(
+    get,
+    path = "/api/v1/device/stale",
+    params(
+        ("days" = Option<u32>, Query, description = "Number of days without a handshake.")
+    ),
+    responses(
+        (status = 200, description = "List stale devices.", body = [StaleDeviceInfo], example = json!([
+            {
+                "id": 1,
+                "name": "laptop",
+                "user_id": 2,
+                "username": "hpotter",
+                "created": "2024-07-10T10:25:43.231Z",
+                "latest_handshake": null
+            }
+        ])),
+        (status = 400, description = "Number of days is out of range.", body = ApiResponse, example = json!({"msg": "Invalid number of days: 4294967295"})),
+        (status = 401, description = "Unauthorized to list stale devices.", body = ApiResponse, example = json!({"msg": "Session is required"})),
+        (status = 403, description = "You don't have permission to list stale devices.", body = ApiResponse, example = json!({"msg": "requires privileged access"})),
+    ),
+    security(
+        ("cookie" = []),
+        ("api_token" = [])
+    )
+)]
+pub(crate) async fn list_stale_devices(
+    _role: AdminRole,
+    State(appstate): State<AppState>,
+    Query(query): Query<StaleDevicesQuery>,
+) -> ApiResult {
+    let days = query.days.unwrap_or(DEFAULT_STALE_DEVICE_DAYS);
+    debug!("Listing devices without a handshake in the last {days} days");
+    let Some(cutoff) = Utc::now()
+        .naive_utc()
+        .checked_sub_signed(TimeDelta::days(days.into()))
+    else {
+        return Err(WebError::BadRequest(format!(
+            "Invalid number of days: {days}"
+        )));
+    };
+    let devices = Device::find_stale(&appstate.pool, cutoff).await?;
+    info!(
+        "Listed {} devices without a handshake in the last {days} days",
+        devices.len()
+    );
+
+    Ok(ApiResponse {
+        json: json!(devices),
+        status: StatusCode::OK,
+    })
+}
+
 /// List user devices
 ///
 /// This endpoint requires `admin` role.
@@ -967,19 +1078,17 @@ pub(crate) async fn list_user_devices(
     })
 }
 
-pub(crate) async fn download_config(
-    session: SessionInfo,
-    State(appstate): State<AppState>,
-    Path((network_id, device_id)): Path<(i64, i64)>,
+/// Generate WireGuard config of a device in a given network. The config never contains private key.
+async fn device_config(
+    pool: &PgPool,
+    network: &WireguardNetwork<Id>,
+    device: &Device<Id>,
 ) -> Result<String, WebError> {
-    debug!("Creating config for device {device_id} in network {network_id}");
-    let network = find_network(network_id, &appstate.pool).await?;
-    let device = device_for_admin_or_self(&appstate.pool, &session, device_id).await?;
     let wireguard_network_device =
-        WireguardNetworkDevice::find(&appstate.pool, device_id, network_id).await?;
+        WireguardNetworkDevice::find(pool, device.id, network.id).await?;
     if let Some(wireguard_network_device) = wireguard_network_device {
-        info!("Created config for device {}({device_id})", device.name);
-        Ok(Device::create_config(&network, &wireguard_network_device))
+        info!("Created config for device {}({})", device.name, device.id);
+        Ok(Device::create_config(network, &wireguard_network_device))
     } else {
         error!(
             "Failed to create config, no IP address found for device: {}({})",
@@ -992,6 +1101,58 @@ pub(crate) async fn download_config(
     }
 }
 
+pub(crate) async fn download_config(
+    session: SessionInfo,
+    State(appstate): State<AppState>,
+    Path((network_id, device_id)): Path<(i64, i64)>,
+) -> Result<String, WebError> {
+    debug!("Creating config for device {device_id} in network {network_id}");
+    let network = find_network(network_id, &appstate.pool).await?;
+    let device = device_for_admin_or_self(&appstate.pool, &session, device_id).await?;
+    device_config(&appstate.pool, &network, &device).await
+}
+
+/// Regenerate WireGuard config of a device and send it by e-mail to the device owner.
+pub(crate) async fn send_config(
+    session: SessionInfo,
+    State(appstate): State<AppState>,
+    Path((network_id, device_id)): Path<(i64, i64)>,
+) -> ApiResult {
+    debug!(
+        "User {} sending config of device {device_id} in network {network_id}",
+        session.user.username
+    );
+    let Some(owner) = User::find_by_device_id(&appstate.pool, device_id).await? else {
+        return Err(WebError::ObjectNotFound(format!(
+            "device id {device_id} not found"
+        )));
+    };
+    let user = user_for_admin_or_self(&appstate.pool, &session, &owner.username).await?;
+    let network = find_network(network_id, &appstate.pool).await?;
+    let Some(device) = Device::find_by_id(&appstate.pool, device_id).await? else {
+        return Err(WebError::ObjectNotFound(format!(
+            "device id {device_id} not found"
+        )));
+    };
+    let config = device_config(&appstate.pool, &network, &device).await?;
+    send_device_config_email(
+        &device.name,
+        &network.name,
+        config,
+        &user.email,
+        &appstate.mail_tx,
+    )?;
+    info!(
+        "User {} sent config of device {}({device_id}) in network {network_id} to {}",
+        session.user.username, device.name, user.email
+    );
+
+    Ok(ApiResponse {
+        json: json!({}),
+        status: StatusCode::OK,
+    })
+}
+
 pub(crate) async fn create_network_token(
     _role: AdminRole,
     State(appstate): State<AppState>,
@@ -19,6 +19,11 @@ use crate::{
 pub struct JobData {
     pub username: String,
     pub worker: String,
+    // optional YubiKey details stored along with the provisioned key
+    #[serde(default)]
+    pub yubikey_model: Option<String>,
+    #[serde(default)]
+    pub yubikey_firmware_version: Option<String>,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -62,6 +67,8 @@ pub async fn create_job(
             user.last_name.clone(),
             user.email,
             job_data.username,
+            job_data.yubikey_model,
+            job_data.yubikey_firmware_version,
         );
         info!(
             "User {} created a worker job (ID {id}) for worker {worker} and user {username}",
@@ -66,3 +66,33 @@ pub async fn rename_yubikey(
         status: StatusCode::OK,
     })
 }
+
+/// Re-enable YubiKey disabled for being unused, so its keys are served to SSH servers again.
+pub async fn enable_yubikey(
+    State(appstate): State<AppState>,
+    session: SessionInfo,
+    Path((username, key_id)): Path<(String, i64)>,
+) -> ApiResult {
+    let user = user_for_admin_or_self(&appstate.pool, &session, &username).await?;
+    debug!(
+        "User {} attempts to enable yubikey {key_id}",
+        session.user.id
+    );
+    let Some(mut yubikey) = YubiKey::find_by_id(&appstate.pool, key_id).await? else {
+        error!("Yubikey with id {key_id} not found");
+        return Err(WebError::ObjectNotFound("YubiKey not found".into()));
+    };
+    if !session.is_admin && yubikey.user_id != user.id {
+        warn!(
+            "User {} tried to enable yubikey {key_id} of user {} without being an admin.",
+            user.id, yubikey.user_id
+        );
+        return Err(WebError::Forbidden("Not allowed to enable YubiKey".into()));
+    }
+    yubikey.enable(&appstate.pool).await?;
+    info!("Yubikey {key_id} enabled by user {}", session.user.id);
+    Ok(ApiResponse {
+        json: json!(yubikey),
+        status: StatusCode::OK,
+    })
+}
@@ -235,7 +235,8 @@ impl LDAPConnection {
         if let Some(entry) = entries.pop() {
             info!("Performed LDAP user search: {username}");
             self.test_bind_user(&entry.dn, password).await?;
-            Ok(User::from_searchentry(&entry, username, password))
+            User::from_searchentry(&entry, username, password)
+                .map_err(|err| LdapError::Ldap(err.to_string()))
         } else {
             Err(LdapError::ObjectNotFound(format!(
                 "User {username} not found",
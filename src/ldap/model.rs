@@ -3,19 +3,25 @@ use std::collections::HashSet;
 use ldap3::{Mod, SearchEntry};
 
 use super::LDAPConfig;
-use crate::{db::User, hashset};
+use crate::{db::User, hashset, password::PasswordError};
 
 impl User {
-    #[must_use]
-    pub fn from_searchentry(entry: &SearchEntry, username: &str, password: &str) -> Self {
-        Self::new(
+    pub fn from_searchentry(
+        entry: &SearchEntry,
+        username: &str,
+        password: &str,
+    ) -> Result<Self, PasswordError> {
+        let mut user = Self::new(
             username.into(),
-            Some(password),
+            None,
             get_value_or_default(entry, "sn"),
             get_value_or_default(entry, "givenName"),
             get_value_or_default(entry, "mail"),
             get_value(entry, "mobile"),
-        )
+        )?;
+        // password policy is enforced by LDAP
+        user.set_unchecked_password(password)?;
+        Ok(user)
     }
 }
 
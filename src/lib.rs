@@ -19,22 +19,24 @@ use enterprise::handlers::{
     openid_login::{auth_callback, get_auth_info},
     openid_providers::{
         add_openid_provider, delete_openid_provider, get_current_openid_provider,
-        test_dirsync_connection,
+        test_dirsync_connection, test_openid_provider,
     },
 };
 use handlers::{
-    group::{bulk_assign_to_groups, list_groups_info},
+    group::{bulk_assign_to_groups, list_group_member_counts, list_groups_info},
     network_devices::{
         add_network_device, check_ip_availability, download_network_device_config,
         find_available_ip, get_network_device, list_network_devices, modify_network_device,
         start_network_device_setup, start_network_device_setup_for_device,
     },
     ssh_authorized_keys::{
-        add_authentication_key, delete_authentication_key, fetch_authentication_keys,
-        rename_authentication_key,
+        add_authentication_key, delete_authentication_key, diagnose_authorized_keys,
+        export_ssh_keys, fetch_authentication_keys, get_ssh_host_groups, rename_authentication_key,
+        report_ssh_key_use, set_authentication_key_expiry, set_authentication_key_options,
+        set_ssh_host_groups,
     },
     updates::check_new_version,
-    yubikey::{delete_yubikey, rename_yubikey},
+    yubikey::{delete_yubikey, enable_yubikey, rename_yubikey},
 };
 use ipnetwork::IpNetwork;
 use secrecy::ExposeSecret;
@@ -59,8 +61,9 @@ use utoipa_swagger_ui::SwaggerUi;
 use self::handlers::wireguard::{
     add_device, add_user_devices, create_network, create_network_token, delete_device,
     delete_network, devices_stats, download_config, gateway_status, get_device, import_network,
-    list_devices, list_networks, list_user_devices, modify_device, modify_network, network_details,
-    network_stats, remove_gateway,
+    list_devices, list_networks, list_stale_devices, list_user_devices, modify_device,
+    modify_network, network_details, network_stats, remove_gateway, send_config,
+    set_primary_device,
 };
 #[cfg(feature = "worker")]
 use self::handlers::worker::{
@@ -87,15 +90,16 @@ use self::{
     },
     handlers::{
         auth::{
-            authenticate, email_mfa_code, email_mfa_disable, email_mfa_enable, email_mfa_init,
-            logout, mfa_disable, mfa_enable, recovery_code, request_email_mfa_code, totp_code,
-            totp_disable, totp_enable, totp_secret, webauthn_end, webauthn_finish, webauthn_init,
-            webauthn_start,
+            authenticate, authenticate_with_mfa_internal, email_mfa_code, email_mfa_disable,
+            email_mfa_enable, email_mfa_init, logout, mfa_disable, mfa_enable, recovery_code,
+            recovery_codes_exchange, regenerate_recovery_codes, request_email_mfa_code, totp_code,
+            totp_disable, totp_enable, totp_secret, verify_totp_internal, webauthn_end,
+            webauthn_finish, webauthn_init, webauthn_start,
         },
         forward_auth::forward_auth,
         group::{
-            add_group_member, create_group, delete_group, get_group, list_groups, modify_group,
-            remove_group_member,
+            add_group_member, clone_group_members, create_group, delete_group, get_group,
+            list_groups, modify_group, remove_group_member,
         },
         mail::{send_support_data, test_mail},
         settings::{
@@ -105,13 +109,16 @@ use self::{
         ssh_authorized_keys::get_authorized_keys,
         support::{configuration, logs},
         user::{
-            add_user, change_password, change_self_password, delete_authorized_app,
-            delete_security_key, delete_user, get_user, list_users, me, modify_user,
-            reset_password, start_enrollment, start_remote_desktop_configuration,
-            username_available,
+            add_user, bulk_disable_mfa, change_password, change_self_password, check_recovery_code,
+            connected_devices, delete_authorized_app, delete_security_key, delete_user,
+            enrollment_stats, enrollment_token_history, get_user, lift_user_suspension,
+            list_enrollment_tokens, list_security_keys, list_users, me, mfa_distribution,
+            modify_user, resend_enrollment_notification, reset_password, revoke_enrollment_token,
+            start_enrollment, start_remote_desktop_configuration, suspend_user, username_available,
         },
         webhooks::{
-            add_webhook, change_enabled, change_webhook, delete_webhook, get_webhook, list_webhooks,
+            add_webhook, change_enabled, change_webhook, delete_webhook, get_webhook,
+            import_webhooks, list_webhook_deliveries, list_webhooks,
         },
     },
     mail::Mail,
@@ -127,6 +134,7 @@ use self::{
 pub mod appstate;
 pub mod assets;
 pub mod auth;
+pub mod cipher;
 pub mod config;
 pub mod db;
 pub mod enterprise;
@@ -138,8 +146,10 @@ pub mod headers;
 pub mod hex;
 pub mod ldap;
 pub mod mail;
+pub mod password;
 pub(crate) mod random;
 pub mod secret;
+pub mod sms;
 pub mod support;
 pub mod templates;
 pub mod updates;
@@ -168,15 +178,21 @@ pub(crate) const KEY_LENGTH: usize = 32;
 
 mod openapi {
     use db::{
-        models::device::{ModifyDevice, UserDevice},
+        models::{
+            device::{ModifyDevice, StaleDeviceInfo, UserDevice},
+            enrollment::EnrollmentStats,
+            enrollment_event::{EnrollmentEvent, EnrollmentEventType},
+            group::SshHostGroup,
+            SecurityKeyOwnerInfo,
+        },
         AddDevice, UserDetails, UserInfo,
     };
     use handlers::{
-        group::{self, BulkAssignToGroupsRequest, Groups},
-        user, wireguard as device, wireguard as network,
+        group::{self, BulkAssignToGroupsRequest, CloneGroupMembers, Groups},
+        ssh_authorized_keys, user, wireguard as device, wireguard as network,
         wireguard::AddDeviceResult,
-        ApiResponse, EditGroupInfo, GroupInfo, PasswordChange, PasswordChangeSelf,
-        StartEnrollmentRequest, Username, SESSION_COOKIE_NAME,
+        ApiResponse, BulkDisableMfa, EditGroupInfo, GroupInfo, PasswordChange, PasswordChangeSelf,
+        StartEnrollmentRequest, SuspendUser, Username, SESSION_COOKIE_NAME,
     };
     use utoipa::{
         openapi::security::{HttpAuthScheme, HttpBuilder},
@@ -194,14 +210,24 @@ mod openapi {
             user::get_user,
             user::add_user,
             user::start_enrollment,
+            user::resend_enrollment_notification,
             user::start_remote_desktop_configuration,
             user::username_available,
             user::modify_user,
             user::delete_user,
+            user::suspend_user,
+            user::lift_user_suspension,
+            user::check_recovery_code,
             user::change_self_password,
             user::change_password,
             user::reset_password,
             user::delete_security_key,
+            user::list_security_keys,
+            user::bulk_disable_mfa,
+            user::list_enrollment_tokens,
+            user::revoke_enrollment_token,
+            user::enrollment_token_history,
+            user::enrollment_stats,
             user::me,
             user::delete_authorized_app,
             // /group
@@ -214,6 +240,7 @@ mod openapi {
             group::delete_group,
             group::add_group_member,
             group::remove_group_member,
+            group::clone_group_members,
             // /device
             device::add_device,
             device::modify_device,
@@ -221,16 +248,20 @@ mod openapi {
             device::delete_device,
             device::list_devices,
             device::list_user_devices,
+            device::list_stale_devices,
             // /network
             network::create_network,
             network::modify_network,
             network::delete_network,
             network::list_networks,
             network::network_details,
+            // /ssh_host
+            ssh_authorized_keys::get_ssh_host_groups,
+            ssh_authorized_keys::set_ssh_host_groups,
         ),
         components(
             schemas(
-                ApiResponse, UserInfo, UserDetails, UserDevice, Groups, Username, StartEnrollmentRequest, PasswordChangeSelf, PasswordChange, AddDevice, AddDeviceResult, Device, ModifyDevice, BulkAssignToGroupsRequest, GroupInfo, EditGroupInfo
+                ApiResponse, UserInfo, UserDetails, UserDevice, Groups, Username, StartEnrollmentRequest, PasswordChangeSelf, PasswordChange, SuspendUser, BulkDisableMfa, SecurityKeyOwnerInfo, AddDevice, AddDeviceResult, Device, ModifyDevice, StaleDeviceInfo, EnrollmentEvent, EnrollmentEventType, EnrollmentStats, BulkAssignToGroupsRequest, CloneGroupMembers, GroupInfo, EditGroupInfo, SshHostGroup
             ),
         ),
         tags(
@@ -326,6 +357,15 @@ pub fn build_webapp(
             .route("/health", get(health_check))
             .route("/info", get(get_app_info))
             .route("/ssh_authorized_keys", get(get_authorized_keys))
+            .route(
+                "/ssh_authorized_keys/diagnose",
+                get(diagnose_authorized_keys),
+            )
+            .route("/ssh_authorized_keys/used", post(report_ssh_key_use))
+            .route(
+                "/ssh_host/{hostname}/groups",
+                get(get_ssh_host_groups).put(set_ssh_host_groups),
+            )
             .route("/api-docs", get(openapi))
             .route("/updates", get(check_new_version))
             // /auth
@@ -341,17 +381,34 @@ pub fn build_webapp(
             .route("/auth/totp", post(totp_enable))
             .route("/auth/totp", delete(totp_disable))
             .route("/auth/totp/verify", post(totp_code))
+            .route("/internal/verify-totp", post(verify_totp_internal))
+            .route(
+                "/internal/authenticate",
+                post(authenticate_with_mfa_internal),
+            )
             .route("/auth/email/init", post(email_mfa_init))
             .route("/auth/email", get(request_email_mfa_code))
             .route("/auth/email", post(email_mfa_enable))
             .route("/auth/email", delete(email_mfa_disable))
             .route("/auth/email/verify", post(email_mfa_code))
             .route("/auth/recovery", post(recovery_code))
+            .route("/mfa/recovery-codes", post(regenerate_recovery_codes))
+            .route("/mfa/distribution", get(mfa_distribution))
+            .route("/mfa/recovery-codes/{token}", get(recovery_codes_exchange))
             // /user
             .route("/user", get(list_users))
             .route("/user/{username}", get(get_user))
+            .route("/user/{username}/connected_devices", get(connected_devices))
             .route("/user", post(add_user))
             .route("/user/{username}/start_enrollment", post(start_enrollment))
+            .route(
+                "/user/{username}/enrollment",
+                delete(revoke_enrollment_token),
+            )
+            .route(
+                "/user/{username}/enrollment/resend",
+                post(resend_enrollment_notification),
+            )
             .route(
                 "/user/{username}/start_desktop",
                 post(start_remote_desktop_configuration),
@@ -359,6 +416,12 @@ pub fn build_webapp(
             .route("/user/available", post(username_available))
             .route("/user/{username}", put(modify_user))
             .route("/user/{username}", delete(delete_user))
+            .route("/user/{username}/suspend", put(suspend_user))
+            .route("/user/{username}/suspend", delete(lift_user_suspension))
+            .route(
+                "/user/{username}/recovery_code/check",
+                post(check_recovery_code),
+            )
             // FIXME: username `change_password` is invalid
             .route("/user/change_password", put(change_self_password))
             .route("/user/{username}/password", put(change_password))
@@ -366,6 +429,7 @@ pub fn build_webapp(
             // auth keys
             .route("/user/{username}/auth_key", get(fetch_authentication_keys))
             .route("/user/{username}/auth_key", post(add_authentication_key))
+            .route("/user/{username}/ssh_keys/export", get(export_ssh_keys))
             .route(
                 "/user/{username}/auth_key/{key_id}",
                 delete(delete_authentication_key),
@@ -374,13 +438,29 @@ pub fn build_webapp(
                 "/user/{username}/auth_key/{key_id}/rename",
                 post(rename_authentication_key),
             )
+            .route(
+                "/user/{username}/auth_key/{key_id}/expiry",
+                put(set_authentication_key_expiry),
+            )
+            .route(
+                "/user/{username}/auth_key/{key_id}/options",
+                put(set_authentication_key_options),
+            )
             // yubi keys
             .route("/user/{username}/yubikey/{key_id}", delete(delete_yubikey))
             .route(
                 "/user/{username}/yubikey/{key_id}/rename",
                 post(rename_yubikey),
             )
+            .route(
+                "/user/{username}/yubikey/{key_id}/enable",
+                post(enable_yubikey),
+            )
             // API tokens
+            .route(
+                "/user/{username}/enrollment/history",
+                get(enrollment_token_history),
+            )
             .route("/user/{username}/api_token", get(fetch_api_tokens))
             .route("/user/{username}/api_token", post(add_api_token))
             .route(
@@ -395,6 +475,10 @@ pub fn build_webapp(
                 "/user/{username}/security_key/{id}",
                 delete(delete_security_key),
             )
+            .route("/security_key", get(list_security_keys))
+            .route("/user/mfa/disable", post(bulk_disable_mfa))
+            .route("/enrollment", get(list_enrollment_tokens))
+            .route("/enrollment/stats", get(enrollment_stats))
             .route("/me", get(me))
             .route(
                 "/user/{username}/oauth_app/{oauth2client_id}",
@@ -410,7 +494,9 @@ pub fn build_webapp(
             .route("/group/{name}", delete(delete_group))
             .route("/group/{name}", post(add_group_member))
             .route("/group/{name}/user/{username}", delete(remove_group_member))
+            .route("/group/{name}/clone-members", post(clone_group_members))
             .route("/group-info", get(list_groups_info))
+            .route("/group-member-counts", get(list_group_member_counts))
             .route("/groups-assign", post(bulk_assign_to_groups))
             // mail
             .route("/mail/test", post(test_mail))
@@ -431,10 +517,12 @@ pub fn build_webapp(
             // webhooks
             .route("/webhook", post(add_webhook))
             .route("/webhook", get(list_webhooks))
+            .route("/webhook/import", post(import_webhooks))
             .route("/webhook/{id}", get(get_webhook))
             .route("/webhook/{id}", put(change_webhook))
             .route("/webhook/{id}", delete(delete_webhook))
             .route("/webhook/{id}", post(change_enabled))
+            .route("/webhook/{id}/deliveries", get(list_webhook_deliveries))
             // ldap
             .route("/ldap/test", get(test_ldap_settings)),
     );
@@ -445,6 +533,7 @@ pub fn build_webapp(
         Router::new()
             .route("/provider", get(get_current_openid_provider))
             .route("/provider", post(add_openid_provider))
+            .route("/provider/test", post(test_openid_provider))
             .route("/provider/{name}", delete(delete_openid_provider))
             .route("/callback", post(auth_callback))
             .route("/auth_info", get(get_auth_info)),
@@ -487,7 +576,9 @@ pub fn build_webapp(
             .route("/device/{device_id}", put(modify_device))
             .route("/device/{device_id}", get(get_device))
             .route("/device/{device_id}", delete(delete_device))
+            .route("/device/{device_id}/primary", post(set_primary_device))
             .route("/device", get(list_devices))
+            .route("/device/stale", get(list_stale_devices))
             .route("/device/user/{username}", get(list_user_devices))
             // Network devices, as opposed to user devices
             .route("/device/network", post(add_network_device))
@@ -528,6 +619,10 @@ pub fn build_webapp(
                 "/network/{network_id}/device/{device_id}/config",
                 get(download_config),
             )
+            .route(
+                "/network/{network_id}/device/{device_id}/config/send",
+                post(send_config),
+            )
             .route("/network/{network_id}/token", get(create_network_token))
             .route("/network/{network_id}/stats/users", get(devices_stats))
             .route("/network/{network_id}/stats", get(network_stats))
@@ -0,0 +1,400 @@
+use clap::ValueEnum;
+#[cfg(feature = "breach-check")]
+use sha1::{Digest, Sha1};
+use thiserror::Error;
+
+#[cfg(feature = "breach-check")]
+use crate::hex::to_lower_hex;
+use crate::SERVER_CONFIG;
+
+#[cfg(feature = "breach-check")]
+static HIBP_RANGE_URL: &str = "https://api.pwnedpasswords.com/range/";
+#[cfg(feature = "breach-check")]
+const HIBP_REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Minimum password length used when configuration is not loaded.
+pub const DEFAULT_PASSWORD_MIN_LENGTH: usize = 8;
+/// Maximum password length used when configuration is not loaded. Bounds Argon2 input.
+pub const DEFAULT_PASSWORD_MAX_LENGTH: usize = 128;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum PasswordError {
+    #[error("Password must be at least {0} characters long")]
+    TooShort(usize),
+    #[error("Password must be at most {0} characters long")]
+    TooLong(usize),
+    #[error("No lowercase characters in password")]
+    MissingLowercase,
+    #[error("No uppercase characters in password")]
+    MissingUppercase,
+    #[error("No numbers in password")]
+    MissingDigit,
+    #[error("No special characters in password")]
+    MissingSpecial,
+    #[error("Failed to hash password: {0}")]
+    Hash(String),
+    #[error("Password was found in known data breaches")]
+    Breached,
+    #[error("Unable to check whether password was found in known data breaches")]
+    BreachCheckFailed,
+    #[error("Password must differ from the last {0} passwords")]
+    Reused(usize),
+    #[error("Failed to check password history: {0}")]
+    History(String),
+}
+
+#[derive(Debug, Error)]
+pub enum BreachCheckError {
+    #[error("Breached password check request failed: {0}")]
+    Request(String),
+}
+
+/// What to do with a password if breached password check can't be completed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum BreachCheckFailure {
+    /// Accept the password.
+    #[default]
+    Open,
+    /// Reject the password.
+    Closed,
+}
+
+/// Source of information about passwords exposed in data breaches.
+#[trait_variant::make(Send)]
+pub trait BreachChecker {
+    /// Number of times the password was found in known data breaches.
+    async fn breach_count(&self, password: &str) -> Result<u64, BreachCheckError>;
+}
+
+/// Character class which has to be present in a password.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum PasswordCharClass {
+    Lowercase,
+    Uppercase,
+    Digit,
+    Special,
+}
+
+impl PasswordCharClass {
+    #[must_use]
+    pub fn all() -> Vec<Self> {
+        vec![Self::Lowercase, Self::Uppercase, Self::Digit, Self::Special]
+    }
+
+    fn matches(self, c: char) -> bool {
+        match self {
+            Self::Lowercase => c.is_ascii_lowercase(),
+            Self::Uppercase => c.is_ascii_uppercase(),
+            Self::Digit => c.is_ascii_digit(),
+            Self::Special => c.is_ascii_punctuation(),
+        }
+    }
+
+    fn error(self) -> PasswordError {
+        match self {
+            Self::Lowercase => PasswordError::MissingLowercase,
+            Self::Uppercase => PasswordError::MissingUppercase,
+            Self::Digit => PasswordError::MissingDigit,
+            Self::Special => PasswordError::MissingSpecial,
+        }
+    }
+}
+
+/// Password policy.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PasswordValidator {
+    pub min_length: usize,
+    pub max_length: usize,
+    pub required_classes: Vec<PasswordCharClass>,
+}
+
+impl Default for PasswordValidator {
+    fn default() -> Self {
+        Self {
+            min_length: DEFAULT_PASSWORD_MIN_LENGTH,
+            max_length: DEFAULT_PASSWORD_MAX_LENGTH,
+            required_classes: PasswordCharClass::all(),
+        }
+    }
+}
+
+impl PasswordValidator {
+    /// Policy from server configuration, or crate defaults if configuration is not loaded.
+    #[must_use]
+    pub fn current() -> Self {
+        SERVER_CONFIG
+            .get()
+            .map(|config| config.password_validator())
+            .unwrap_or_default()
+    }
+
+    /// Check password against the policy. Length is counted in characters.
+    pub fn validate(&self, password: &str) -> Result<(), PasswordError> {
+        let length = password.chars().count();
+        if length < self.min_length {
+            return Err(PasswordError::TooShort(self.min_length));
+        }
+        if length > self.max_length {
+            return Err(PasswordError::TooLong(self.max_length));
+        }
+        for class in &self.required_classes {
+            if !password.chars().any(|c| class.matches(c)) {
+                return Err(class.error());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check password against the currently configured policy.
+pub fn validate_password(password: &str) -> Result<(), PasswordError> {
+    PasswordValidator::current().validate(password)
+}
+
+/// Number of previous passwords which can't be reused, from server configuration.
+/// Password history is disabled if configuration is not loaded.
+#[must_use]
+pub fn password_history_depth() -> usize {
+    SERVER_CONFIG
+        .get()
+        .map_or(0, |config| config.password_history_depth)
+}
+
+/// Reject password which was found in known data breaches more than `threshold` times.
+pub async fn check_password_breach<C: BreachChecker>(
+    checker: &C,
+    password: &str,
+    threshold: u64,
+    on_failure: BreachCheckFailure,
+) -> Result<(), PasswordError> {
+    match checker.breach_count(password).await {
+        Ok(count) if count > threshold => {
+            debug!("Password found in {count} breaches, rejecting it");
+            Err(PasswordError::Breached)
+        }
+        Ok(_) => Ok(()),
+        Err(err) => {
+            warn!("Breached password check failed: {err}");
+            match on_failure {
+                BreachCheckFailure::Open => Ok(()),
+                BreachCheckFailure::Closed => Err(PasswordError::BreachCheckFailed),
+            }
+        }
+    }
+}
+
+/// Check password against HaveIBeenPwned database if enabled in configuration.
+pub async fn check_breached_password(password: &str) -> Result<(), PasswordError> {
+    let Some(config) = SERVER_CONFIG.get() else {
+        return Ok(());
+    };
+    let Some(threshold) = config.password_breach_threshold else {
+        return Ok(());
+    };
+    #[cfg(feature = "breach-check")]
+    {
+        check_password_breach(
+            &HibpBreachChecker::new(),
+            password,
+            threshold,
+            config.password_breach_check_failure,
+        )
+        .await
+    }
+    #[cfg(not(feature = "breach-check"))]
+    {
+        let _ = (password, threshold);
+        warn!("Breached password check is configured, but defguard was built without it");
+        Ok(())
+    }
+}
+
+/// Upper-case hex SHA-1 digest, split into the 5 character prefix sent to the API
+/// and the suffix looked up in the response.
+#[cfg(feature = "breach-check")]
+fn sha1_prefix_and_suffix(password: &str) -> (String, String) {
+    let mut hash = to_lower_hex(&Sha1::digest(password.as_bytes())).to_uppercase();
+    let suffix = hash.split_off(5);
+    (hash, suffix)
+}
+
+/// Find occurrence count of a hash suffix in k-anonymity range API response.
+/// Each line of the response has `SUFFIX:COUNT` format.
+#[cfg(feature = "breach-check")]
+fn parse_range_response(response: &str, suffix: &str) -> u64 {
+    response
+        .lines()
+        .filter_map(|line| line.trim().split_once(':'))
+        .find(|(line_suffix, _)| line_suffix.eq_ignore_ascii_case(suffix))
+        .and_then(|(_, count)| count.parse().ok())
+        .unwrap_or(0)
+}
+
+/// HaveIBeenPwned Passwords client. Only the first 5 characters of password SHA-1 hash
+/// are sent to the service.
+#[cfg(feature = "breach-check")]
+pub struct HibpBreachChecker {
+    client: reqwest::Client,
+}
+
+#[cfg(feature = "breach-check")]
+impl HibpBreachChecker {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(HIBP_REQUEST_TIMEOUT)
+                .build()
+                .unwrap(),
+        }
+    }
+}
+
+#[cfg(feature = "breach-check")]
+impl Default for HibpBreachChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "breach-check")]
+impl BreachChecker for HibpBreachChecker {
+    async fn breach_count(&self, password: &str) -> Result<u64, BreachCheckError> {
+        let (prefix, suffix) = sha1_prefix_and_suffix(password);
+        let response = self
+            .client
+            .get(format!("{HIBP_RANGE_URL}{prefix}"))
+            // padding hides the real number of matching hashes
+            .header("Add-Padding", "true")
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(|err| BreachCheckError::Request(err.to_string()))?
+            .text()
+            .await
+            .map_err(|err| BreachCheckError::Request(err.to_string()))?;
+        Ok(parse_range_response(&response, &suffix))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Pretends every password ending with "123" has been breached.
+    struct MockBreachChecker {
+        available: bool,
+    }
+
+    impl BreachChecker for MockBreachChecker {
+        async fn breach_count(&self, password: &str) -> Result<u64, BreachCheckError> {
+            if !self.available {
+                return Err(BreachCheckError::Request("unavailable".into()));
+            }
+            Ok(if password.ends_with("123") { 1000 } else { 0 })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_password_breach() {
+        let checker = MockBreachChecker { available: true };
+        assert_eq!(
+            check_password_breach(&checker, "Password123", 0, BreachCheckFailure::Open).await,
+            Err(PasswordError::Breached)
+        );
+        assert_eq!(
+            check_password_breach(&checker, "Password123", 1000, BreachCheckFailure::Open).await,
+            Ok(())
+        );
+        assert_eq!(
+            check_password_breach(&checker, "Str0ng-Passw0rd!", 0, BreachCheckFailure::Open).await,
+            Ok(())
+        );
+
+        let checker = MockBreachChecker { available: false };
+        assert_eq!(
+            check_password_breach(&checker, "Password123", 0, BreachCheckFailure::Open).await,
+            Ok(())
+        );
+        assert_eq!(
+            check_password_breach(&checker, "Password123", 0, BreachCheckFailure::Closed).await,
+            Err(PasswordError::BreachCheckFailed)
+        );
+    }
+
+    #[cfg(feature = "breach-check")]
+    #[test]
+    fn test_parse_range_response() {
+        let (prefix, suffix) = sha1_prefix_and_suffix("password");
+        assert_eq!(prefix, "5BAA6");
+        assert_eq!(suffix, "1E4C9B93F3F0682250B6CF8331B7EE68FD8");
+
+        let response = "1D72CD07550416C216D8AD296BF5C0AE8E0:10\r\n\
+            1E4C9B93F3F0682250B6CF8331B7EE68FD8:3861493\r\n\
+            1E9A7CDAF7E8D9F5D8AC54CC4DBA0CD1ECC:0";
+        assert_eq!(parse_range_response(response, &suffix), 3_861_493);
+        assert_eq!(
+            parse_range_response(response, &suffix.to_lowercase()),
+            3_861_493
+        );
+        // padding entries have zero count
+        assert_eq!(
+            parse_range_response(response, "1E9A7CDAF7E8D9F5D8AC54CC4DBA0CD1ECC"),
+            0
+        );
+        assert_eq!(parse_range_response(response, "0000"), 0);
+    }
+
+    #[test]
+    fn test_password_validator() {
+        let validator = PasswordValidator::default();
+        assert_eq!(
+            validator.validate("Sh0rt!"),
+            Err(PasswordError::TooShort(DEFAULT_PASSWORD_MIN_LENGTH))
+        );
+        assert_eq!(
+            validator.validate(&format!(
+                "Password1!{}",
+                "a".repeat(DEFAULT_PASSWORD_MAX_LENGTH)
+            )),
+            Err(PasswordError::TooLong(DEFAULT_PASSWORD_MAX_LENGTH))
+        );
+        assert_eq!(
+            validator.validate("PASSWORD123!"),
+            Err(PasswordError::MissingLowercase)
+        );
+        assert_eq!(
+            validator.validate("password123!"),
+            Err(PasswordError::MissingUppercase)
+        );
+        assert_eq!(
+            validator.validate("Password!!!"),
+            Err(PasswordError::MissingDigit)
+        );
+        assert_eq!(
+            validator.validate("Password123"),
+            Err(PasswordError::MissingSpecial)
+        );
+        assert_eq!(validator.validate("Str0ng-Passw0rd!"), Ok(()));
+    }
+
+    #[test]
+    fn test_password_validator_config() {
+        let validator = PasswordValidator {
+            min_length: 4,
+            max_length: 10,
+            required_classes: vec![PasswordCharClass::Digit],
+        };
+        assert_eq!(validator.validate("1234"), Ok(()));
+        assert_eq!(validator.validate("abcd"), Err(PasswordError::MissingDigit));
+        assert_eq!(validator.validate("123"), Err(PasswordError::TooShort(4)));
+        assert_eq!(
+            validator.validate("12345678901"),
+            Err(PasswordError::TooLong(10))
+        );
+        // multibyte characters are counted once
+        assert_eq!(validator.validate("1ąęść"), Ok(()));
+    }
+}
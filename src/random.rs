@@ -10,6 +10,18 @@ pub(crate) fn gen_alphanumeric(n: usize) -> String {
         .collect()
 }
 
+/// Characters which can't be confused with each other: no `0`/`O`, `1`/`l`/`I`.
+const UNAMBIGUOUS_CHARSET: &[u8] = b"23456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// Generate random string of alphanumeric characters which are easy to tell apart.
+#[must_use]
+pub(crate) fn gen_unambiguous(n: usize) -> String {
+    let mut rng = thread_rng();
+    (0..n)
+        .map(|_| char::from(UNAMBIGUOUS_CHARSET[rng.gen_range(0..UNAMBIGUOUS_CHARSET.len())]))
+        .collect()
+}
+
 /// Generate random 20-byte secret for TOTP.
 #[must_use]
 pub(crate) fn gen_totp_secret() -> Vec<u8> {
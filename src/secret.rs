@@ -1,4 +1,4 @@
-use std::{convert::Infallible, error::Error, str::FromStr};
+use std::{convert::Infallible, error::Error, fmt, str::FromStr};
 
 use secrecy::{ExposeSecret, SecretString};
 use serde::{Deserialize, Serialize};
@@ -8,6 +8,8 @@ use sqlx::{
     Decode, Encode, Postgres, Type,
 };
 
+use crate::cipher::{is_sealed, open, seal, secret_cipher};
+
 /// Wrapper for secrecy `SecretString` struct which implements sqlx traits.
 #[derive(Clone, Debug, Deserialize)]
 pub struct SecretStringWrapper(SecretString);
@@ -66,3 +68,98 @@ impl PartialEq for SecretStringWrapper {
         self.0.expose_secret() == other.0.expose_secret()
     }
 }
+
+/// String stored in the database encrypted with [`secret_cipher`]. Values stored before
+/// encryption was introduced are read as plaintext and encrypted once saved again.
+#[derive(Clone, Deserialize)]
+#[serde(from = "String")]
+pub struct EncryptedSecret {
+    secret: String,
+    stored_as_plaintext: bool,
+}
+
+impl EncryptedSecret {
+    #[must_use]
+    pub fn expose_secret(&self) -> &str {
+        &self.secret
+    }
+
+    /// Check if the value was read from the database unencrypted.
+    #[must_use]
+    pub fn stored_as_plaintext(&self) -> bool {
+        self.stored_as_plaintext
+    }
+}
+
+impl From<String> for EncryptedSecret {
+    fn from(secret: String) -> Self {
+        Self {
+            secret,
+            stored_as_plaintext: false,
+        }
+    }
+}
+
+impl From<&str> for EncryptedSecret {
+    fn from(secret: &str) -> Self {
+        Self::from(secret.to_string())
+    }
+}
+
+impl PartialEq for EncryptedSecret {
+    fn eq(&self, other: &Self) -> bool {
+        self.secret == other.secret
+    }
+}
+
+impl fmt::Debug for EncryptedSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EncryptedSecret([REDACTED])")
+    }
+}
+
+impl Serialize for EncryptedSecret {
+    fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        ser.serialize_str(&self.secret)
+    }
+}
+
+impl Decode<'_, Postgres> for EncryptedSecret {
+    fn decode(value: PgValueRef<'_>) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let value = <String as Decode<'_, Postgres>>::decode(value)?;
+        if is_sealed(&value) {
+            Ok(Self {
+                secret: open(secret_cipher(), &value)?,
+                stored_as_plaintext: false,
+            })
+        } else {
+            Ok(Self {
+                secret: value,
+                stored_as_plaintext: true,
+            })
+        }
+    }
+}
+
+impl Encode<'_, Postgres> for EncryptedSecret {
+    fn encode_by_ref(
+        &self,
+        buf: &mut PgArgumentBuffer,
+    ) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        let sealed = seal(secret_cipher(), &self.secret)?;
+        <String as Encode<Postgres>>::encode_by_ref(&sealed, buf)
+    }
+}
+
+impl Type<Postgres> for EncryptedSecret {
+    fn type_info() -> PgTypeInfo {
+        <String as ::sqlx::Type<Postgres>>::type_info()
+    }
+
+    fn compatible(ty: &PgTypeInfo) -> bool {
+        <String as ::sqlx::Type<Postgres>>::compatible(ty)
+    }
+}
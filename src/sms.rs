@@ -0,0 +1,95 @@
+use std::time::Duration;
+
+use secrecy::{ExposeSecret, SecretString};
+use thiserror::Error;
+
+use crate::server_config;
+
+static TWILIO_API_URL: &str = "https://api.twilio.com/2010-04-01/Accounts";
+const SMS_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Error)]
+pub enum SmsError {
+    #[error("SMS gateway is not configured")]
+    NotConfigured,
+    #[error("SMS request failed: {0}")]
+    Request(String),
+}
+
+/// Text message sent to a phone number.
+#[derive(Debug, PartialEq)]
+pub struct Sms {
+    pub to: String,
+    pub content: String,
+}
+
+/// Gateway delivering text messages.
+#[trait_variant::make(Send)]
+pub trait SmsSender {
+    async fn send(&self, sms: Sms) -> Result<(), SmsError>;
+}
+
+/// Twilio Programmable Messaging client. Every message fails with [`SmsError::NotConfigured`]
+/// unless account SID, auth token and sender number are configured.
+pub struct TwilioSmsSender {
+    client: reqwest::Client,
+    account_sid: Option<String>,
+    auth_token: Option<SecretString>,
+    from: Option<String>,
+}
+
+impl TwilioSmsSender {
+    #[must_use]
+    pub fn new(
+        account_sid: Option<String>,
+        auth_token: Option<SecretString>,
+        from: Option<String>,
+    ) -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(SMS_REQUEST_TIMEOUT)
+                .build()
+                .unwrap(),
+            account_sid,
+            auth_token,
+            from,
+        }
+    }
+
+    /// Create client with credentials from server configuration.
+    #[must_use]
+    pub fn from_config() -> Self {
+        let config = server_config();
+        Self::new(
+            config.sms_twilio_account_sid.clone(),
+            config.sms_twilio_auth_token.clone(),
+            config.sms_from.clone(),
+        )
+    }
+}
+
+impl SmsSender for TwilioSmsSender {
+    async fn send(&self, sms: Sms) -> Result<(), SmsError> {
+        let (Some(account_sid), Some(auth_token), Some(from)) =
+            (&self.account_sid, &self.auth_token, &self.from)
+        else {
+            return Err(SmsError::NotConfigured);
+        };
+        debug!("Sending text message");
+        self.client
+            .post(format!("{TWILIO_API_URL}/{account_sid}/Messages.json"))
+            .basic_auth(account_sid, Some(auth_token.expose_secret()))
+            .form(&[
+                ("To", sms.to.as_str()),
+                ("From", from.as_str()),
+                ("Body", sms.content.as_str()),
+            ])
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(|err| SmsError::Request(err.to_string()))?;
+        debug!("Sent text message");
+
+        Ok(())
+    }
+}
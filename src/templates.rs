@@ -18,6 +18,7 @@ static MAIL_ENROLLMENT_ADMIN_NOTIFICATION: &str =
     include_str!("../templates/mail_enrollment_admin_notification.tera");
 static MAIL_SUPPORT_DATA: &str = include_str!("../templates/mail_support_data.tera");
 static MAIL_NEW_DEVICE_ADDED: &str = include_str!("../templates/mail_new_device_added.tera");
+static MAIL_DEVICE_CONFIG: &str = include_str!("../templates/mail_device_config.tera");
 static MAIL_GATEWAY_DISCONNECTED: &str =
     include_str!("../templates/mail_gateway_disconnected.tera");
 static MAIL_GATEWAY_RECONNECTED: &str = include_str!("../templates/mail_gateway_reconnected.tera");
@@ -32,6 +33,9 @@ static MAIL_PASSWORD_RESET_START: &str =
     include_str!("../templates/mail_password_reset_start.tera");
 static MAIL_PASSWORD_RESET_SUCCESS: &str =
     include_str!("../templates/mail_password_reset_success.tera");
+static MAIL_YUBIKEY_DISABLED: &str = include_str!("../templates/mail_yubikey_disabled.tera");
+static MAIL_SECURITY_NOTIFICATION: &str =
+    include_str!("../templates/mail_security_notification.tera");
 
 #[derive(Error, Debug)]
 pub enum TemplateError {
@@ -107,6 +111,15 @@ pub fn enrollment_start_mail(
 
     Ok(tera.render("mail_enrollment_start", &context)?)
 }
+// text message with link to enrollment service
+#[must_use]
+pub fn enrollment_start_sms(mut enrollment_service_url: Url, enrollment_token: &str) -> String {
+    enrollment_service_url
+        .query_pairs_mut()
+        .append_pair("token", enrollment_token);
+    format!("Complete your defguard enrollment: {enrollment_service_url}")
+}
+
 // mail with link to enrollment service
 pub fn desktop_start_mail(
     context: Context,
@@ -197,6 +210,14 @@ pub fn new_device_added_mail(
     Ok(tera.render("mail_new_device_added", &context)?)
 }
 
+pub fn device_config_mail(device_name: &str, network_name: &str) -> Result<String, TemplateError> {
+    let (mut tera, mut context) = get_base_tera(None, None, None, None)?;
+    context.insert("device_name", device_name);
+    context.insert("network_name", network_name);
+    tera.add_raw_template("mail_device_config", MAIL_DEVICE_CONFIG)?;
+    Ok(tera.render("mail_device_config", &context)?)
+}
+
 pub fn mfa_configured_mail(
     session: Option<&Session>,
     method: &MFAMethod,
@@ -266,6 +287,25 @@ pub fn gateway_reconnected_mail(
     Ok(tera.render("mail_gateway_reconnected", &context)?)
 }
 
+pub fn yubikey_disabled_mail(
+    yubikey_name: &str,
+    yubikey_serial: &str,
+) -> Result<String, TemplateError> {
+    let (mut tera, mut context) = get_base_tera(None, None, None, None)?;
+    context.insert("yubikey_name", yubikey_name);
+    context.insert("yubikey_serial", yubikey_serial);
+    tera.add_raw_template("mail_yubikey_disabled", MAIL_YUBIKEY_DISABLED)?;
+    Ok(tera.render("mail_yubikey_disabled", &context)?)
+}
+
+pub fn security_notification_mail(event: &str, message: &str) -> Result<String, TemplateError> {
+    let (mut tera, mut context) = get_base_tera(None, None, None, None)?;
+    context.insert("event", event);
+    context.insert("message", message);
+    tera.add_raw_template("mail_security_notification", MAIL_SECURITY_NOTIFICATION)?;
+    Ok(tera.render("mail_security_notification", &context)?)
+}
+
 pub fn email_mfa_activation_mail(
     user: &User<Id>,
     code: &str,
@@ -423,6 +463,11 @@ mod test {
             None,
         ));
     }
+    #[test]
+    fn test_device_config_mail() {
+        assert_ok!(device_config_mail("Laptop", "Location1"));
+    }
+
     #[test]
     fn test_gateway_disconnected() {
         assert_ok!(gateway_disconnected_mail(
@@ -432,16 +477,30 @@ mod test {
         ));
     }
 
+    #[test]
+    fn test_yubikey_disabled_mail() {
+        assert_ok!(yubikey_disabled_mail("YubiKey 1", "12345678"));
+    }
+
+    #[test]
+    fn test_security_notification_mail() {
+        assert_ok!(security_notification_mail(
+            "break-glass login",
+            "Admin admin signed in with a recovery code."
+        ));
+    }
+
     #[test]
     fn test_enrollment_admin_notification() {
         let test_user: User = User::new(
             "test",
-            Some("1234"),
+            Some("Pass1234!"),
             "test_last",
             "test_first",
             "test@example.com",
             Some("99999".into()),
-        );
+        )
+        .unwrap();
         assert_ok!(enrollment_admin_notification(
             &test_user,
             &test_user,
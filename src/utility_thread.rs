@@ -1,31 +1,55 @@
 use std::time::Duration;
 
+use chrono::TimeDelta;
 use sqlx::PgPool;
 use tokio::{
-    sync::broadcast::Sender,
+    sync::{broadcast::Sender, mpsc::UnboundedSender},
     time::{sleep, Instant},
 };
 
 use crate::{
-    db::GatewayEvent,
+    db::{
+        models::{
+            authentication_key::AuthenticationKey, enrollment::Token,
+            recovery_codes::RecoveryCodesBundle,
+        },
+        AppEvent, GatewayEvent, User, YubiKey,
+    },
     enterprise::{
         directory_sync::{do_directory_sync, get_directory_sync_interval},
         limits::do_count_update,
     },
+    handlers::mail::send_yubikey_disabled_email,
+    mail::Mail,
+    server_config,
     updates::do_new_version_check,
 };
 
 const UTILITY_THREAD_MAIN_SLEEP_TIME: u64 = 5;
 const COUNT_UPDATE_INTERVAL: u64 = 60 * 60;
 const UPDATES_CHECK_INTERVAL: u64 = 60 * 60 * 6;
+const ENROLLMENT_PENDING_CHECK_INTERVAL: u64 = 60 * 60 * 24;
+const SUSPENSION_CHECK_INTERVAL: u64 = 60;
+const EXPIRED_KEYS_CLEANUP_INTERVAL: u64 = 60 * 60;
+const EXPIRED_TOKENS_CHECK_INTERVAL: u64 = 60 * 60;
+const UNUSED_YUBIKEYS_CHECK_INTERVAL: u64 = 60 * 60;
+const EXPIRED_RECOVERY_CODES_CLEANUP_INTERVAL: u64 = 60;
 
 pub async fn run_utility_thread(
     pool: &PgPool,
     wireguard_tx: Sender<GatewayEvent>,
+    webhook_tx: UnboundedSender<AppEvent>,
+    mail_tx: UnboundedSender<Mail>,
 ) -> Result<(), anyhow::Error> {
     let mut last_count_update = Instant::now();
     let mut last_directory_sync = Instant::now();
     let mut last_updates_check = Instant::now();
+    let mut last_enrollment_pending_check = Instant::now();
+    let mut last_suspension_check = Instant::now();
+    let mut last_expired_keys_cleanup = Instant::now();
+    let mut last_expired_tokens_check = Instant::now();
+    let mut last_unused_yubikeys_check = Instant::now();
+    let mut last_expired_recovery_codes_cleanup = Instant::now();
 
     let directory_sync_task = || async {
         if let Err(e) = do_directory_sync(pool, &wireguard_tx).await {
@@ -45,6 +69,87 @@ pub async fn run_utility_thread(
         }
     };
 
+    let enrollment_pending_task = || async {
+        let threshold = server_config().enrollment_pending_threshold.into();
+        if let Err(e) = Token::notify_pending_enrollments(pool, &webhook_tx, threshold).await {
+            error!("There was an error while checking for pending enrollments: {e:?}");
+        }
+    };
+
+    let suspension_task = || async {
+        if let Err(e) = User::lift_expired_suspensions(pool, &wireguard_tx).await {
+            error!("There was an error while lifting expired user suspensions: {e:?}");
+        }
+    };
+
+    let expired_keys_task = || async {
+        match AuthenticationKey::delete_expired(pool).await {
+            Ok(0) => {}
+            Ok(count) => info!("Removed {count} expired authentication keys"),
+            Err(e) => {
+                error!("There was an error while removing expired authentication keys: {e:?}")
+            }
+        }
+    };
+
+    let expired_tokens_task = || async {
+        match Token::record_expired(pool).await {
+            Ok(0) => {}
+            Ok(count) => info!("Recorded expiry of {count} enrollment tokens"),
+            Err(e) => {
+                error!("There was an error while recording expired enrollment tokens: {e:?}")
+            }
+        }
+    };
+
+    let expired_recovery_codes_task = || async {
+        match RecoveryCodesBundle::delete_expired(pool).await {
+            Ok(0) => {}
+            Ok(count) => debug!("Removed {count} expired recovery codes bundles"),
+            Err(e) => {
+                error!("There was an error while removing expired recovery codes bundles: {e:?}")
+            }
+        }
+    };
+
+    let unused_yubikeys_task = || async {
+        let Some(threshold) = server_config().yubikey_unused_disable_threshold else {
+            return;
+        };
+        let threshold = match TimeDelta::from_std(threshold.into()) {
+            Ok(threshold) => threshold,
+            Err(e) => {
+                error!("Invalid unused YubiKey disable threshold: {e}");
+                return;
+            }
+        };
+        let yubikeys = match YubiKey::disable_unused(pool, threshold).await {
+            Ok(yubikeys) => yubikeys,
+            Err(e) => {
+                error!("There was an error while disabling unused YubiKeys: {e:?}");
+                return;
+            }
+        };
+        for yubikey in yubikeys {
+            info!(
+                "Disabled unused YubiKey {} of user {}",
+                yubikey.id, yubikey.user_id
+            );
+            match User::find_by_id(pool, yubikey.user_id).await {
+                Ok(Some(user)) => {
+                    if let Err(e) = send_yubikey_disabled_email(&user, &yubikey, &mail_tx) {
+                        error!(
+                            "Failed to notify user {} about disabled YubiKey: {e}",
+                            user.id
+                        );
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => error!("Failed to fetch owner of YubiKey {}: {e:?}", yubikey.id),
+            }
+        }
+    };
+
     directory_sync_task().await;
     count_update_task().await;
     updates_check_task().await;
@@ -69,5 +174,43 @@ pub async fn run_utility_thread(
             updates_check_task().await;
             last_updates_check = Instant::now();
         }
+
+        // Notify about users who haven't finished their enrollment
+        if last_enrollment_pending_check.elapsed().as_secs() >= ENROLLMENT_PENDING_CHECK_INTERVAL {
+            enrollment_pending_task().await;
+            last_enrollment_pending_check = Instant::now();
+        }
+
+        // Restore VPN access of users whose suspension has expired
+        if last_suspension_check.elapsed().as_secs() >= SUSPENSION_CHECK_INTERVAL {
+            suspension_task().await;
+            last_suspension_check = Instant::now();
+        }
+
+        // Remove authentication keys past their expiration date
+        if last_expired_keys_cleanup.elapsed().as_secs() >= EXPIRED_KEYS_CLEANUP_INTERVAL {
+            expired_keys_task().await;
+            last_expired_keys_cleanup = Instant::now();
+        }
+
+        // Record expiry of unused enrollment tokens in enrollment audit log
+        if last_expired_tokens_check.elapsed().as_secs() >= EXPIRED_TOKENS_CHECK_INTERVAL {
+            expired_tokens_task().await;
+            last_expired_tokens_check = Instant::now();
+        }
+
+        // Disable YubiKeys which haven't been used for a long time
+        if last_unused_yubikeys_check.elapsed().as_secs() >= UNUSED_YUBIKEYS_CHECK_INTERVAL {
+            unused_yubikeys_task().await;
+            last_unused_yubikeys_check = Instant::now();
+        }
+
+        // Remove recovery codes bundles which were never exchanged
+        if last_expired_recovery_codes_cleanup.elapsed().as_secs()
+            >= EXPIRED_RECOVERY_CODES_CLEANUP_INTERVAL
+        {
+            expired_recovery_codes_task().await;
+            last_expired_recovery_codes_cleanup = Instant::now();
+        }
     }
 }
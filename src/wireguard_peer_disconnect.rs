@@ -3,20 +3,29 @@
 //! If a device does not disconnect explicitly and just becomes inactive
 //! it should be removed from gateway configuration and marked as "not allowed",
 //! which enforces an authentication requirement to connect again.
+//!
+//! The same mechanism is used to enforce the limit of concurrently connected devices per user.
 
-use std::time::Duration;
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    time::Duration,
+};
 
-use sqlx::{query_as, Error as SqlxError, PgPool};
+use chrono::NaiveDateTime;
+use sqlx::{postgres::types::PgInterval, query, query_as, Error as SqlxError, PgPool};
 use thiserror::Error;
 use tokio::{sync::broadcast::Sender, time::sleep};
 
-use crate::db::{
-    models::{
-        device::{DeviceInfo, DeviceNetworkInfo, DeviceType, WireguardNetworkDevice},
-        error::ModelError,
-        wireguard::WireguardNetworkError,
+use crate::{
+    db::{
+        models::{
+            device::{DeviceInfo, DeviceNetworkInfo, DeviceType, WireguardNetworkDevice},
+            error::ModelError,
+            wireguard::{WireguardNetworkError, WIREGUARD_MAX_HANDSHAKE},
+        },
+        Device, GatewayEvent, Id, WireguardNetwork,
     },
-    Device, GatewayEvent, Id, WireguardNetwork,
+    server_config,
 };
 
 // How long to sleep between loop iterations
@@ -49,8 +58,9 @@ pub async fn run_periodic_peer_disconnect(
         let locations = query_as!(
             WireguardNetwork::<Id>,
             "SELECT \
-                id, name, address, port, pubkey, prvkey, endpoint, dns, allowed_ips, \
-                connected_at, mfa_enabled, keepalive_interval, peer_disconnect_threshold \
+                id, name, address, port, pubkey, prvkey, endpoint, dns, \
+                dns_search_domains, allowed_ips, connected_at, mfa_enabled, keepalive_interval, \
+                peer_disconnect_threshold \
             FROM wireguard_network WHERE mfa_enabled = true",
         )
         .fetch_all(&pool)
@@ -68,7 +78,7 @@ pub async fn run_periodic_peer_disconnect(
                 ORDER BY device_id, collected_at DESC \
             ) \
             SELECT d.id, d.name, d.wireguard_pubkey, d.user_id, d.created, d.description,
-            d.device_type \"device_type: DeviceType\", configured \
+            d.device_type \"device_type: DeviceType\", configured, is_primary \
             FROM device d \
             JOIN wireguard_network_device wnd ON wnd.device_id = d.id \
             LEFT JOIN stats on d.id = stats.device_id \
@@ -84,48 +94,203 @@ pub async fn run_periodic_peer_disconnect(
 
             for device in devices {
                 debug!("Processing inactive device {device}");
-
-                // start transaction
-                let mut transaction = pool.begin().await?;
-
-                // get network config for device
-                if let Some(mut device_network_config) =
-                    WireguardNetworkDevice::find(&mut *transaction, device.id, location.id).await?
-                {
-                    info!("Marking device {device} as not authorized to connect to location {location}");
-                    // change `is_authorized` value for device
-                    device_network_config.is_authorized = false;
-                    // clear `preshared_key` value
-                    device_network_config.preshared_key = None;
-                    device_network_config.update(&mut *transaction).await?;
-
-                    debug!("Sending `peer_delete` message to gateway");
-                    let device_info = DeviceInfo {
-                        device,
-                        network_info: vec![DeviceNetworkInfo {
-                            network_id: location.id,
-                            device_wireguard_ip: device_network_config.wireguard_ip,
-                            preshared_key: device_network_config.preshared_key,
-                            is_authorized: device_network_config.is_authorized,
-                        }],
-                    };
-                    let event = GatewayEvent::DeviceDeleted(device_info);
-                    wireguard_tx.send(event).map_err(|err| {
-                        error!("Error sending WireGuard event: {err}");
-                        PeerDisconnectError::EventError(err.to_string())
-                    })?;
-                } else {
-                    error!("Network config for device {device} in location {location} not found. Skipping device...");
-                    continue;
-                }
-
-                // commit transaction
-                transaction.commit().await?;
+                disconnect_device(&pool, &wireguard_tx, device, &location).await?;
             }
         }
 
+        if let Some(limit) = server_config().max_concurrent_devices_per_user {
+            disconnect_devices_over_limit(&pool, &wireguard_tx, limit).await?;
+        }
+
         // wait till next iteration
         debug!("Sleeping until next iteration");
         sleep(DISCONNECT_LOOP_SLEEP).await;
     }
 }
+
+/// Remove device from gateway configuration of a given location and mark it as not authorized.
+async fn disconnect_device(
+    pool: &PgPool,
+    wireguard_tx: &Sender<GatewayEvent>,
+    device: Device<Id>,
+    location: &WireguardNetwork<Id>,
+) -> Result<(), PeerDisconnectError> {
+    // start transaction
+    let mut transaction = pool.begin().await?;
+
+    // get network config for device
+    if let Some(mut device_network_config) =
+        WireguardNetworkDevice::find(&mut *transaction, device.id, location.id).await?
+    {
+        info!("Marking device {device} as not authorized to connect to location {location}");
+        // change `is_authorized` value for device
+        device_network_config.is_authorized = false;
+        // clear `preshared_key` value
+        device_network_config.preshared_key = None;
+        device_network_config.update(&mut *transaction).await?;
+
+        debug!("Sending `peer_delete` message to gateway");
+        let device_info = DeviceInfo {
+            device,
+            network_info: vec![DeviceNetworkInfo {
+                network_id: location.id,
+                device_wireguard_ip: device_network_config.wireguard_ip,
+                preshared_key: device_network_config.preshared_key,
+                is_authorized: device_network_config.is_authorized,
+            }],
+        };
+        let event = GatewayEvent::DeviceDeleted(device_info);
+        wireguard_tx.send(event).map_err(|err| {
+            error!("Error sending WireGuard event: {err}");
+            PeerDisconnectError::EventError(err.to_string())
+        })?;
+    } else {
+        error!("Network config for device {device} in location {location} not found. Skipping device...");
+        return Ok(());
+    }
+
+    // commit transaction
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+/// Choose devices to disconnect so that at most `limit` devices stay connected.
+/// `connections` holds the connection time of every (device, location) pair;
+/// devices which connected first are kept.
+fn devices_over_limit(connections: &[(Id, NaiveDateTime)], limit: usize) -> Vec<Id> {
+    let mut first_connected: HashMap<Id, NaiveDateTime> = HashMap::new();
+    for (device_id, connected_at) in connections {
+        match first_connected.entry(*device_id) {
+            Entry::Occupied(mut entry) => {
+                if connected_at < entry.get() {
+                    entry.insert(*connected_at);
+                }
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(*connected_at);
+            }
+        }
+    }
+    let mut devices: Vec<_> = first_connected.into_iter().collect();
+    devices.sort_by_key(|(device_id, connected_at)| (*connected_at, *device_id));
+    devices
+        .into_iter()
+        .skip(limit)
+        .map(|(device_id, _)| device_id)
+        .collect()
+}
+
+/// Disconnect devices of users who have more than `limit` devices connected at the same time
+/// to MFA-protected locations. Only authorized peers are taken into account, as others can't
+/// connect anyway.
+async fn disconnect_devices_over_limit(
+    pool: &PgPool,
+    wireguard_tx: &Sender<GatewayEvent>,
+    limit: usize,
+) -> Result<(), PeerDisconnectError> {
+    debug!("Checking concurrent device connections against the limit of {limit}");
+    // Connection time is found the same way as in `WireguardNetwork::connected_at`.
+    let connected = query!(
+        "WITH latest AS ( \
+            SELECT DISTINCT ON (device_id, network) device_id, network, latest_handshake \
+            FROM wireguard_peer_stats \
+            ORDER BY device_id, network, collected_at DESC \
+        ), connected AS ( \
+            SELECT d.user_id, latest.device_id, latest.network \
+            FROM latest \
+            JOIN device d ON d.id = latest.device_id \
+            JOIN wireguard_network n ON n.id = latest.network \
+            JOIN wireguard_network_device wnd \
+            ON wnd.device_id = latest.device_id AND wnd.wireguard_network_id = latest.network \
+            WHERE d.device_type = 'user'::device_type AND n.mfa_enabled AND wnd.is_authorized \
+            AND (NOW() - latest.latest_handshake) < $1 \
+        ) \
+        SELECT c.user_id, c.device_id, c.network, \
+        COALESCE( \
+            ( \
+                SELECT latest_handshake FROM wireguard_peer_stats_view v \
+                WHERE v.device_id = c.device_id AND v.network = c.network \
+                AND latest_handshake_diff > $1 \
+                ORDER BY collected_at DESC LIMIT 1 \
+            ), \
+            ( \
+                SELECT latest_handshake FROM wireguard_peer_stats_view v \
+                WHERE v.device_id = c.device_id AND v.network = c.network \
+                ORDER BY collected_at LIMIT 1 \
+            ) \
+        ) \"connected_at: NaiveDateTime\" \
+        FROM connected c",
+        PgInterval::try_from(WIREGUARD_MAX_HANDSHAKE).unwrap(),
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut connections_by_user: HashMap<Id, Vec<(Id, Id, NaiveDateTime)>> = HashMap::new();
+    for row in connected {
+        if let Some(connected_at) = row.connected_at {
+            connections_by_user.entry(row.user_id).or_default().push((
+                row.device_id,
+                row.network,
+                connected_at,
+            ));
+        }
+    }
+
+    let mut locations: HashMap<Id, Option<WireguardNetwork<Id>>> = HashMap::new();
+    for (user_id, connections) in connections_by_user {
+        let connection_times: Vec<_> = connections
+            .iter()
+            .map(|(device_id, _, connected_at)| (*device_id, *connected_at))
+            .collect();
+        for device_id in devices_over_limit(&connection_times, limit) {
+            let Some(device) = Device::find_by_id(pool, device_id).await? else {
+                continue;
+            };
+            warn!(
+                "User {user_id} exceeded the limit of {limit} concurrently connected devices, \
+                disconnecting device {device}"
+            );
+            for (_, network_id, _) in connections.iter().filter(|(id, ..)| *id == device_id) {
+                let location = match locations.entry(*network_id) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => {
+                        entry.insert(WireguardNetwork::find_by_id(pool, *network_id).await?)
+                    }
+                };
+                if let Some(location) = location {
+                    disconnect_device(pool, wireguard_tx, device.clone(), location).await?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{TimeDelta, Utc};
+
+    use super::*;
+
+    #[test]
+    fn test_devices_over_limit() {
+        let now = Utc::now().naive_utc();
+        let minutes_ago = |minutes| now - TimeDelta::minutes(minutes);
+
+        // device 2 is connected to two locations, the earliest connection counts
+        let connections = [
+            (1, minutes_ago(10)),
+            (2, minutes_ago(5)),
+            (2, minutes_ago(30)),
+            (3, minutes_ago(1)),
+            (4, minutes_ago(20)),
+        ];
+        assert!(devices_over_limit(&connections, 4).is_empty());
+        assert!(devices_over_limit(&connections, 5).is_empty());
+        assert_eq!(devices_over_limit(&connections, 3), vec![3]);
+        assert_eq!(devices_over_limit(&connections, 2), vec![1, 3]);
+        assert_eq!(devices_over_limit(&connections, 0), vec![2, 4, 1, 3]);
+    }
+}
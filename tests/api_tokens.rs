@@ -28,7 +28,7 @@ async fn test_normal_user_cannot_access_token_endpoints() {
     let client = make_client().await;
 
     // log in as normal user
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = client.post("/api/v1/auth").json(&auth).send().await;
     assert_eq!(response.status(), StatusCode::OK);
 
@@ -1,26 +1,25 @@
 pub mod common;
 
-use std::time::SystemTime;
-
-use chrono::NaiveDateTime;
+use chrono::{NaiveDateTime, TimeDelta, Utc};
 use claims::{assert_err, assert_ok};
 use common::fetch_user_details;
 use defguard::{
-    auth::{TOTP_CODE_DIGITS, TOTP_CODE_VALIDITY_PERIOD},
+    auth::{Claims, ClaimsType},
+    cipher::is_sealed,
     db::{
-        models::settings::update_current_settings, MFAInfo, MFAMethod, Settings, User, UserDetails,
+        models::{recovery_codes::RecoveryCodesBundle, settings::update_current_settings},
+        MFAInfo, MFAMethod, Settings, User, UserDetails,
     },
     handlers::{Auth, AuthCode, AuthResponse, AuthTotp},
 };
 use reqwest::{header::USER_AGENT, StatusCode};
 use serde::Deserialize;
 use serde_json::json;
-use sqlx::{query, PgPool};
-use totp_lite::{totp_custom, Sha1};
+use sqlx::{query, query_scalar, PgPool};
 use webauthn_authenticator_rs::{prelude::Url, softpasskey::SoftPasskey, WebauthnAuthenticator};
 use webauthn_rs::prelude::{CreationChallengeResponse, RequestChallengeResponse};
 
-use self::common::{client::TestClient, make_test_client, ClientState, X_FORWARDED_FOR};
+use self::common::{client::TestClient, make_test_client, totp_code, ClientState, X_FORWARDED_FOR};
 
 static SESSION_COOKIE_NAME: &str = "defguard_session";
 
@@ -29,6 +28,23 @@ pub struct RecoveryCodes {
     codes: Option<Vec<String>>,
 }
 
+#[derive(Deserialize)]
+pub struct RecoveryCodesToken {
+    token: Option<String>,
+}
+
+async fn exchange_recovery_codes(client: &TestClient, token: &RecoveryCodesToken) -> RecoveryCodes {
+    let response = client
+        .get(format!(
+            "/api/v1/mfa/recovery-codes/{}",
+            token.token.as_ref().unwrap()
+        ))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    response.json().await
+}
+
 async fn make_client() -> TestClient {
     let (client, _) = make_test_client().await;
     client
@@ -48,7 +64,7 @@ async fn make_client_with_state() -> (TestClient, ClientState) {
 async fn test_logout() {
     let mut client = make_client().await;
 
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = client.post("/api/v1/auth").json(&auth).send().await;
     assert_eq!(response.status(), StatusCode::OK);
 
@@ -94,7 +110,7 @@ async fn test_login_bruteforce() {
 async fn test_login_disabled() {
     let client = make_client().await;
 
-    let user_auth = Auth::new("hpotter", "pass123");
+    let user_auth = Auth::new("hpotter", "Pass123!");
     let admin_auth = Auth::new("admin", "pass123");
 
     let response = client.post("/api/v1/auth").json(&admin_auth).send().await;
@@ -130,11 +146,74 @@ async fn test_login_disabled() {
     assert_eq!(response.status(), StatusCode::OK);
 }
 
+#[tokio::test]
+async fn test_login_suspended() {
+    let (client, pool) = make_client_with_db().await;
+
+    let user_auth = Auth::new("hpotter", "Pass123!");
+    let admin_auth = Auth::new("admin", "pass123");
+
+    let response = client.post("/api/v1/auth").json(&admin_auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // suspension end must be in the future
+    let past = Utc::now().naive_utc() - TimeDelta::hours(1);
+    let response = client
+        .put("/api/v1/user/hpotter/suspend")
+        .json(&json!({ "until": past }))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let until = Utc::now().naive_utc() + TimeDelta::hours(1);
+    let response = client
+        .put("/api/v1/user/hpotter/suspend")
+        .json(&json!({ "until": until }))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let user_details = fetch_user_details(&client, "hpotter").await;
+    assert!(user_details.user.suspended_until.is_some());
+    assert!(user_details.user.is_active);
+
+    // active suspension blocks login
+    let response = client.post("/api/v1/auth").json(&user_auth).send().await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let body: serde_json::Value = response.json().await;
+    assert_eq!(body["msg"], "User is suspended");
+    assert!(body["suspended_until"].is_string());
+
+    // suspension expires
+    query!(
+        "UPDATE \"user\" SET suspended_until = $1 WHERE username = 'hpotter'",
+        past
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    let response = client.post("/api/v1/auth").json(&user_auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // suspension can be lifted before it expires
+    let response = client.post("/api/v1/auth").json(&admin_auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client
+        .put("/api/v1/user/hpotter/suspend")
+        .json(&json!({ "until": until }))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client.delete("/api/v1/user/hpotter/suspend").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client.post("/api/v1/auth").json(&user_auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
 #[tokio::test]
 async fn test_cannot_enable_mfa() {
     let client = make_client().await;
 
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = client.post("/api/v1/auth").json(&auth).send().await;
     assert_eq!(response.status(), StatusCode::OK);
 
@@ -143,30 +222,12 @@ async fn test_cannot_enable_mfa() {
     assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
 }
 
-fn totp_code(auth_totp: &AuthTotp) -> AuthCode {
-    let timestamp = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap();
-    let secret = base32::decode(
-        base32::Alphabet::Rfc4648 { padding: false },
-        &auth_totp.secret,
-    )
-    .unwrap();
-    let code = totp_custom::<Sha1>(
-        TOTP_CODE_VALIDITY_PERIOD,
-        TOTP_CODE_DIGITS,
-        &secret,
-        timestamp.as_secs(),
-    );
-    AuthCode::new(code)
-}
-
 #[tokio::test]
 async fn test_totp() {
     let client = make_client().await;
 
     // login
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = client.post("/api/v1/auth").json(&auth).send().await;
     assert_eq!(response.status(), StatusCode::OK);
 
@@ -181,7 +242,8 @@ async fn test_totp() {
     assert_eq!(response.status(), StatusCode::OK);
 
     // check recovery codes
-    let recovery_codes: RecoveryCodes = response.json().await;
+    let recovery_codes_token: RecoveryCodesToken = response.json().await;
+    let recovery_codes = exchange_recovery_codes(&client, &recovery_codes_token).await;
     assert_eq!(recovery_codes.codes.as_ref().unwrap().len(), 8); // RECOVERY_CODES_COUNT
 
     // enable MFA
@@ -265,11 +327,105 @@ async fn test_totp() {
     assert_eq!(response.status(), StatusCode::OK);
 
     // login again
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = client.post("/api/v1/auth").json(&auth).send().await;
     assert_eq!(response.status(), StatusCode::OK);
 }
 
+#[tokio::test]
+async fn test_recovery_codes_token() {
+    let (client, pool) = make_client_with_db().await;
+
+    // login
+    let auth = Auth::new("hpotter", "Pass123!");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // enable TOTP
+    let response = client.post("/api/v1/auth/totp/init").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let auth_totp: AuthTotp = response.json().await;
+    let code = totp_code(&auth_totp);
+    let response = client.post("/api/v1/auth/totp").json(&code).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let recovery_codes_token: RecoveryCodesToken = response.json().await;
+    let token = recovery_codes_token.token.clone().unwrap();
+
+    // token is an opaque handle, not a JWT usable in other contexts
+    assert_err!(Claims::from_jwt(ClaimsType::Auth, &token));
+
+    // invalid token
+    let response = client
+        .get("/api/v1/mfa/recovery-codes/invalid")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // exchange token once
+    let recovery_codes = exchange_recovery_codes(&client, &recovery_codes_token).await;
+    assert_eq!(recovery_codes.codes.unwrap().len(), 8); // RECOVERY_CODES_COUNT
+
+    // token can't be reused
+    let response = client
+        .get(format!("/api/v1/mfa/recovery-codes/{token}"))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // codes already exist, so no new token is issued
+    let response = client.post("/api/v1/auth/totp/init").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let auth_totp: AuthTotp = response.json().await;
+    let code = totp_code(&auth_totp);
+    let response = client.post("/api/v1/auth/totp").json(&code).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let recovery_codes_token: RecoveryCodesToken = response.json().await;
+    assert!(recovery_codes_token.token.is_none());
+
+    // clear codes and enable TOTP again to get a fresh token
+    query!("UPDATE \"user\" SET recovery_codes = '{}' WHERE username = 'hpotter'")
+        .execute(&pool)
+        .await
+        .unwrap();
+    let response = client.post("/api/v1/auth/totp/init").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let auth_totp: AuthTotp = response.json().await;
+    let code = totp_code(&auth_totp);
+    let response = client.post("/api/v1/auth/totp").json(&code).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let recovery_codes_token: RecoveryCodesToken = response.json().await;
+    let token = recovery_codes_token.token.unwrap();
+
+    // codes are not stored as plaintext
+    let codes = query_scalar!(
+        "SELECT codes FROM recovery_codes_bundle WHERE id = $1",
+        token
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert!(is_sealed(&codes));
+
+    // expired token is rejected
+    query!("UPDATE recovery_codes_bundle SET expires = now() - interval '1 minute'")
+        .execute(&pool)
+        .await
+        .unwrap();
+    let response = client
+        .get(format!("/api/v1/mfa/recovery-codes/{token}"))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // expired bundle is purged
+    assert_eq!(RecoveryCodesBundle::delete_expired(&pool).await.unwrap(), 1);
+    let count = query_scalar!("SELECT count(*) FROM recovery_codes_bundle")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(count, Some(0));
+}
+
 static EMAIL_CODE_REGEX: &str = r"<b>(?<code>\d{6})</b>";
 fn extract_email_code(content: &str) -> &str {
     let re = regex::Regex::new(EMAIL_CODE_REGEX).unwrap();
@@ -288,7 +444,7 @@ async fn test_email_mfa() {
     assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
 
     // login
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = client.post("/api/v1/auth").json(&auth).send().await;
     assert_eq!(response.status(), StatusCode::OK);
 
@@ -341,7 +497,8 @@ async fn test_email_mfa() {
     );
 
     // check recovery codes
-    let recovery_codes: RecoveryCodes = response.json().await;
+    let recovery_codes_token: RecoveryCodesToken = response.json().await;
+    let recovery_codes = exchange_recovery_codes(&client, &recovery_codes_token).await;
     assert_eq!(recovery_codes.codes.as_ref().unwrap().len(), 8); // RECOVERY_CODES_COUNT
 
     // enable MFA
@@ -416,7 +573,7 @@ async fn test_email_mfa() {
     assert_eq!(response.status(), StatusCode::OK);
 
     // login again
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = client.post("/api/v1/auth").json(&auth).send().await;
     assert_eq!(response.status(), StatusCode::OK);
 }
@@ -429,7 +586,7 @@ async fn test_webauthn() {
     let origin = Url::parse("http://localhost:8000").unwrap();
 
     // login
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = client.post("/api/v1/auth").json(&auth).send().await;
     assert_eq!(response.status(), StatusCode::OK);
 
@@ -449,7 +606,8 @@ async fn test_webauthn() {
     assert_eq!(response.status(), StatusCode::OK);
 
     // check recovery codes
-    let recovery_codes: RecoveryCodes = response.json().await;
+    let recovery_codes_token: RecoveryCodesToken = response.json().await;
+    let recovery_codes = exchange_recovery_codes(&client, &recovery_codes_token).await;
     assert_eq!(recovery_codes.codes.unwrap().len(), 8); // RECOVERY_CODES_COUNT
 
     // enable MFA
@@ -457,7 +615,7 @@ async fn test_webauthn() {
     assert_eq!(response.status(), StatusCode::OK);
 
     // login again
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = client.post("/api/v1/auth").json(&auth).send().await;
     assert_eq!(response.status(), StatusCode::CREATED);
 
@@ -486,7 +644,7 @@ async fn test_webauthn() {
     assert_eq!(response.status(), StatusCode::OK);
 
     // login again
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = client.post("/api/v1/auth").json(&auth).send().await;
     assert_eq!(response.status(), StatusCode::OK);
 
@@ -506,7 +664,7 @@ async fn test_cannot_skip_otp_by_adding_yubikey() {
     let client = make_client().await;
 
     // login
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = client.post("/api/v1/auth").json(&auth).send().await;
     assert_eq!(response.status(), StatusCode::OK);
 
@@ -541,7 +699,7 @@ async fn test_cannot_skip_security_key_by_adding_yubikey() {
     let origin = Url::parse("http://localhost:8000").unwrap();
 
     // login
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = client.post("/api/v1/auth").json(&auth).send().await;
     assert_eq!(response.status(), StatusCode::OK);
 
@@ -565,7 +723,7 @@ async fn test_cannot_skip_security_key_by_adding_yubikey() {
     assert_eq!(response.status(), StatusCode::OK);
 
     // login again
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = client.post("/api/v1/auth").json(&auth).send().await;
     assert_eq!(response.status(), StatusCode::CREATED);
 
@@ -579,7 +737,7 @@ async fn test_mfa_method_is_updated_when_removing_last_webauthn_passkey() {
     let client = make_client().await;
 
     // login
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = client.post("/api/v1/auth").json(&auth).send().await;
     assert_eq!(response.status(), StatusCode::OK);
 
@@ -594,7 +752,8 @@ async fn test_mfa_method_is_updated_when_removing_last_webauthn_passkey() {
     assert_eq!(response.status(), StatusCode::OK);
 
     // check recovery codes
-    let recovery_codes: RecoveryCodes = response.json().await;
+    let recovery_codes_token: RecoveryCodesToken = response.json().await;
+    let recovery_codes = exchange_recovery_codes(&client, &recovery_codes_token).await;
     assert_eq!(recovery_codes.codes.as_ref().unwrap().len(), 8); // RECOVERY_CODES_COUNT
 
     // enable MFA
@@ -657,7 +816,7 @@ async fn test_mfa_method_is_updated_when_removing_last_webauthn_passkey() {
     assert_eq!(response.status(), StatusCode::OK);
 
     // login again
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = client.post("/api/v1/auth").json(&auth).send().await;
     assert_eq!(response.status(), StatusCode::CREATED);
 
@@ -673,7 +832,7 @@ async fn test_mfa_method_totp_enabled_mail() {
     let user_agent_header = "Mozilla/5.0 (iPhone; CPU iPhone OS 17_1 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.1 Mobile/15E148 Safari/604.1";
 
     // login
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = client
         .post("/api/v1/auth")
         .header(USER_AGENT, user_agent_header)
@@ -713,7 +872,7 @@ async fn test_new_device_login() {
     let user_agent_header_android = "Mozilla/5.0 (Linux; Android 7.0; SM-G930VC Build/NRD90M; wv) AppleWebKit/537.36 (KHTML, like Gecko) Version/4.0 Chrome/58.0.3029.83 Mobile Safari/537.36";
 
     // login
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = client
         .post("/api/v1/auth")
         .header(USER_AGENT, user_agent_header_iphone)
@@ -737,7 +896,7 @@ async fn test_new_device_login() {
     assert_eq!(response.status(), StatusCode::OK);
 
     // login using the same device
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = client
         .post("/api/v1/auth")
         .header(USER_AGENT, user_agent_header_iphone)
@@ -749,7 +908,7 @@ async fn test_new_device_login() {
     assert_err!(mail_rx.try_recv());
 
     // login using a different device
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = client
         .post("/api/v1/auth")
         .header(USER_AGENT, user_agent_header_android)
@@ -776,7 +935,7 @@ async fn test_login_ip_headers() {
     let user_agent_header_iphone = "Mozilla/5.0 (iPhone; CPU iPhone OS 17_1 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.1 Mobile/15E148 Safari/604.1";
 
     // Works with X-Forwarded-For header
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = client
         .post("/api/v1/auth")
         .header(USER_AGENT, user_agent_header_iphone)
@@ -799,7 +958,7 @@ async fn test_login_ip_headers() {
 async fn test_session_cookie() {
     let (client, pool) = make_client_with_db().await;
 
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = client.post("/api/v1/auth").json(&auth).send().await;
     assert_eq!(response.status(), StatusCode::OK);
 
@@ -831,7 +990,7 @@ async fn test_session_cookie() {
 async fn test_all_session_logout() {
     let (client, pool) = make_client_with_db().await;
 
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = client.post("/api/v1/auth").json(&auth).send().await;
     assert_eq!(response.status(), StatusCode::OK);
 
@@ -849,3 +1008,98 @@ async fn test_all_session_logout() {
     let auth_cookie = response.cookies().find(|c| c.name() == SESSION_COOKIE_NAME);
     assert!(auth_cookie.is_none());
 }
+
+async fn register_security_key(client: &TestClient, name: &str) -> StatusCode {
+    let mut authenticator = WebauthnAuthenticator::new(SoftPasskey::new(true));
+    let origin = Url::parse("http://localhost:8000").unwrap();
+
+    let response = client.post("/api/v1/auth/webauthn/init").send().await;
+    if response.status() != StatusCode::OK {
+        return response.status();
+    }
+    let ccr: CreationChallengeResponse = response.json().await;
+    let rpkc = authenticator.do_registration(origin, ccr).unwrap();
+    let response = client
+        .post("/api/v1/auth/webauthn/finish")
+        .json(&json!({
+            "name": name,
+            "rpkc": &rpkc
+        }))
+        .send()
+        .await;
+    response.status()
+}
+
+#[tokio::test]
+async fn test_webauthn_key_limit() {
+    let client = make_client().await;
+    // DEFGUARD_MAX_WEBAUTHN_KEYS_PER_USER default
+    let limit = 5;
+
+    // login
+    let auth = Auth::new("hpotter", "Pass123!");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    for i in 0..limit {
+        let status = register_security_key(&client, &format!("Key {i}")).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    // limit reached
+    let response = client.post("/api/v1/auth/webauthn/init").send().await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = response.json().await;
+    assert_eq!(body["limit"], limit);
+
+    let response = client.get("/api/v1/user/hpotter").send().await;
+    let user_details: UserDetails = response.json().await;
+    assert_eq!(user_details.security_keys.len(), limit);
+}
+
+#[tokio::test]
+async fn test_webauthn_key_limit_admin_bypass() {
+    let client = make_client().await;
+    let limit = 5;
+
+    // login
+    let auth = Auth::new("admin", "pass123");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // administrators are not limited
+    for i in 0..=limit {
+        let status = register_security_key(&client, &format!("Key {i}")).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    let response = client.get("/api/v1/user/admin").send().await;
+    let user_details: UserDetails = response.json().await;
+    assert_eq!(user_details.security_keys.len(), limit + 1);
+}
+
+#[tokio::test]
+async fn test_cannot_set_unconfigured_mfa_method() {
+    let client = make_client().await;
+
+    // login
+    let auth = Auth::new("hpotter", "Pass123!");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // no security key registered
+    let response = client.get("/api/v1/user/hpotter").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let mut user_info: UserDetails = response.json().await;
+    user_info.user.mfa_method = MFAMethod::Webauthn;
+    let response = client
+        .put("/api/v1/user/hpotter")
+        .json(&user_info.user)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = client.get("/api/v1/user/hpotter").send().await;
+    let user_info: UserDetails = response.json().await;
+    assert_eq!(user_info.user.mfa_method, MFAMethod::None);
+}
@@ -3,10 +3,11 @@ pub(crate) mod client;
 use std::{
     str::FromStr,
     sync::{Arc, Mutex},
+    time::SystemTime,
 };
 
 use defguard::{
-    auth::failed_login::FailedLoginMap,
+    auth::{failed_login::FailedLoginMap, TOTP_CODE_DIGITS, TOTP_CODE_VALIDITY_PERIOD},
     build_webapp,
     config::DefGuardConfig,
     db::{
@@ -15,7 +16,7 @@ use defguard::{
     },
     enterprise::license::{set_cached_license, License},
     grpc::{GatewayMap, WorkerState},
-    handlers::Auth,
+    handlers::{Auth, AuthCode, AuthTotp},
     mail::Mail,
     SERVER_CONFIG,
 };
@@ -30,6 +31,7 @@ use tokio::{
         mpsc::{unbounded_channel, UnboundedReceiver},
     },
 };
+use totp_lite::{totp_custom, Sha1};
 
 use self::client::TestClient;
 
@@ -86,12 +88,13 @@ async fn initialize_users(pool: &PgPool, config: &DefGuardConfig) {
 
     User::new(
         "hpotter",
-        Some("pass123"),
+        Some("Pass123!"),
         "Potter",
         "Harry",
         "h.potter@hogwart.edu.uk",
         None,
     )
+    .unwrap()
     .save(pool)
     .await
     .unwrap();
@@ -285,3 +288,28 @@ pub(crate) fn make_network() -> Value {
         "peer_disconnect_threshold": 180
     })
 }
+
+/// Current TOTP code for base32-encoded secret.
+fn current_totp_code(secret: &str, period: u64, digits: u32) -> String {
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap();
+    let secret = base32::decode(base32::Alphabet::Rfc4648 { padding: false }, secret).unwrap();
+    totp_custom::<Sha1>(period, digits, &secret, timestamp.as_secs())
+}
+
+/// Current TOTP code for TOTP setup returned by the API.
+#[allow(dead_code)]
+pub(crate) fn totp_code(auth_totp: &AuthTotp) -> AuthCode {
+    AuthCode::new(current_totp_code(
+        &auth_totp.secret,
+        auth_totp.period,
+        auth_totp.digits,
+    ))
+}
+
+/// Current TOTP code for a secret using default code length and period.
+#[allow(dead_code)]
+pub(crate) fn secret_totp_code(secret: &str) -> String {
+    current_totp_code(secret, TOTP_CODE_VALIDITY_PERIOD, TOTP_CODE_DIGITS)
+}
@@ -38,7 +38,7 @@ async fn test_initialize_enrollment() {
     assert_eq!(response.status(), StatusCode::CREATED);
 
     // verify enrollment token was not created
-    let enrollments = Token::fetch_all(&pool).await.unwrap();
+    let enrollments = Token::fetch_all(&pool, None).await.unwrap();
     assert_eq!(enrollments.len(), 0);
 
     // try to start enrollment
@@ -66,7 +66,7 @@ async fn test_initialize_enrollment() {
     assert_eq!(response.status(), StatusCode::CREATED);
 
     // verify enrollment token was not created
-    let enrollments = Token::fetch_all(&pool).await.unwrap();
+    let enrollments = Token::fetch_all(&pool, None).await.unwrap();
     assert_eq!(enrollments.len(), 0);
 
     // try to start enrollment
@@ -85,6 +85,17 @@ async fn test_initialize_enrollment() {
     assert_eq!(enrollment.user_id, 4);
     assert_eq!(enrollment.admin_id, Some(1));
     assert_eq!(enrollment.used_at, None);
+
+    // token shows up in the admin listing without its secret
+    let response = client.get("/api/v1/enrollment").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let page: serde_json::Value = response.json().await;
+    assert_eq!(page["total"], 1);
+    assert_eq!(page["offset"], 0);
+    let items = page["items"].as_array().unwrap();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0]["username"], "adumbledore2");
+    assert!(items[0].get("id").is_none());
 }
 
 #[tokio::test]
@@ -124,3 +135,207 @@ async fn test_enroll_disabled_user() {
         .await;
     assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
 }
+
+#[tokio::test]
+async fn test_deactivated_admin_enrollments_reassigned() {
+    let (client, pool) = make_client().await;
+
+    let auth = Auth::new("admin", "pass123");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // create second admin
+    let new_admin = AddUserData {
+        username: "adumbledore".into(),
+        last_name: "Dumbledore".into(),
+        first_name: "Albus".into(),
+        email: "a.dumbledore@hogwart.edu.uk".into(),
+        phone: None,
+        password: Some("Password1234543$!".into()),
+    };
+    let response = client.post("/api/v1/user").json(&new_admin).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let mut user_details = fetch_user_details(&client, "adumbledore").await;
+    user_details.user.groups = vec!["admin".into()];
+    let response = client
+        .put("/api/v1/user/adumbledore")
+        .json(&user_details.user)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // second admin starts an enrollment
+    let new_user = AddUserData {
+        username: "rweasley".into(),
+        last_name: "Weasley".into(),
+        first_name: "Ron".into(),
+        email: "r.weasley@hogwart.edu.uk".into(),
+        phone: None,
+        password: None,
+    };
+    let auth = Auth::new("adumbledore", "Password1234543$!");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client.post("/api/v1/user").json(&new_user).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = client
+        .post("/api/v1/user/rweasley/start_enrollment")
+        .json(&json!({}))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let enrollments = Token::fetch_all(&pool, None).await.unwrap();
+    assert_eq!(enrollments.len(), 1);
+    let second_admin_id = enrollments[0].admin_id;
+
+    // first admin deactivates the second one
+    let auth = Auth::new("admin", "pass123");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let mut user_details = fetch_user_details(&client, "adumbledore").await;
+    user_details.user.is_active = false;
+    let response = client
+        .put("/api/v1/user/adumbledore")
+        .json(&user_details.user)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // pending enrollment is now attributed to the deactivating admin
+    let enrollments = Token::fetch_all(&pool, None).await.unwrap();
+    assert_eq!(enrollments.len(), 1);
+    assert_ne!(enrollments[0].admin_id, second_admin_id);
+    assert_eq!(enrollments[0].admin_id, Some(1));
+}
+
+#[tokio::test]
+async fn test_revoke_enrollment_token() {
+    let (client, pool) = make_client().await;
+
+    let auth = Auth::new("admin", "pass123");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let new_user = AddUserData {
+        username: "adumbledore".into(),
+        last_name: "Dumbledore".into(),
+        first_name: "Albus".into(),
+        email: "a.dumbledore@hogwart.edu.uk".into(),
+        phone: None,
+        password: None,
+    };
+    let response = client.post("/api/v1/user").json(&new_user).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    #[derive(Deserialize)]
+    struct StartEnrollmentResponse {
+        enrollment_token: String,
+    }
+    let response = client
+        .post("/api/v1/user/adumbledore/start_enrollment")
+        .json(&json!({}))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let token = response
+        .json::<StartEnrollmentResponse>()
+        .await
+        .enrollment_token;
+
+    // regular users can't revoke tokens
+    let auth = Auth::new("hpotter", "Pass123!");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client
+        .delete("/api/v1/user/adumbledore/enrollment")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let auth = Auth::new("admin", "pass123");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client
+        .delete("/api/v1/user/adumbledore/enrollment?token_type=UNKNOWN")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    // no password reset tokens
+    let response = client
+        .delete("/api/v1/user/adumbledore/enrollment?token_type=PASSWORD_RESET")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert!(Token::find_by_id(&pool, &token).await.is_ok());
+
+    let response = client
+        .delete("/api/v1/user/adumbledore/enrollment")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(Token::find_by_id(&pool, &token).await.is_err());
+
+    // token no longer exists
+    let response = client
+        .delete("/api/v1/user/adumbledore/enrollment")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_enrollment_stats() {
+    let (client, _) = make_client().await;
+
+    let auth = Auth::new("admin", "pass123");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let new_user = AddUserData {
+        username: "adumbledore".into(),
+        last_name: "Dumbledore".into(),
+        first_name: "Albus".into(),
+        email: "a.dumbledore@hogwart.edu.uk".into(),
+        phone: None,
+        password: None,
+    };
+    let response = client.post("/api/v1/user").json(&new_user).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = client
+        .post("/api/v1/user/adumbledore/start_enrollment")
+        .json(&json!({}))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let response = client.get("/api/v1/enrollment/stats").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let stats: serde_json::Value = response.json().await;
+    assert_eq!(
+        stats,
+        json!({"issued": 1, "started": 0, "completed": 0, "expired_unused": 0})
+    );
+
+    // time range before the token was issued
+    let response = client
+        .get("/api/v1/enrollment/stats?from=2020-01-01T00:00:00Z&to=2020-02-01T00:00:00Z")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let stats: serde_json::Value = response.json().await;
+    assert_eq!(stats["issued"], 0);
+
+    // invalid time range
+    let response = client
+        .get("/api/v1/enrollment/stats?from=2020-02-01T00:00:00Z&to=2020-01-01T00:00:00Z")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // only for admins
+    let auth = Auth::new("hpotter", "Pass123!");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client.get("/api/v1/enrollment/stats").send().await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
@@ -99,7 +99,7 @@ async fn test_admin_devices_management_is_enforced() {
     assert_eq!(response.status(), StatusCode::CREATED);
 
     // ensure normal users can't manage devices
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = client.post("/api/v1/auth").json(&auth).send().await;
     assert_eq!(response.status(), StatusCode::OK);
 
@@ -178,7 +178,7 @@ async fn test_regular_user_device_management() {
     assert_eq!(response.status(), StatusCode::CREATED);
 
     // ensure normal users can manage devices
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = client.post("/api/v1/auth").json(&auth).send().await;
     assert_eq!(response.status(), StatusCode::OK);
 
@@ -33,7 +33,7 @@ async fn test_forward_auth() {
     );
 
     // login
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = client.post("/api/v1/auth").json(&auth).send().await;
     assert_eq!(response.status(), StatusCode::OK);
 
@@ -195,3 +195,128 @@ async fn test_modify_last_admin_group() {
     let response = client.put("/api/v1/group/admin").json(&data).send().await;
     assert_eq!(response.status(), StatusCode::BAD_REQUEST);
 }
+
+#[tokio::test]
+async fn test_clone_group_members() {
+    let (client, _) = make_test_client().await;
+
+    // Authorize as an administrator.
+    let auth = Auth::new("admin", "pass123");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Create source and target groups.
+    let data = GroupInfo::new(
+        "hogwards",
+        vec!["hpotter".into(), "admin".into()],
+        Vec::new(),
+        false,
+    );
+    let response = client.post("/api/v1/group").json(&data).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let data = GroupInfo::new("gryffindor", vec!["hpotter".into()], Vec::new(), false);
+    let response = client.post("/api/v1/group").json(&data).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Copy members.
+    let response = client
+        .post("/api/v1/group/gryffindor/clone-members")
+        .json(&json!({ "source": "hogwards" }))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let result: serde_json::Value = response.json().await;
+    assert_eq!(result["added"], 1);
+
+    // Target group has exactly the source group's members.
+    let response = client.get("/api/v1/group/gryffindor").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let mut group_info: GroupInfo = response.json().await;
+    group_info.members.sort();
+    assert_eq!(group_info.members, vec!["admin", "hpotter"]);
+
+    // Unknown source group.
+    let response = client
+        .post("/api/v1/group/gryffindor/clone-members")
+        .json(&json!({ "source": "slytherin" }))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_group_ssh_principal() {
+    let (client, _) = make_test_client().await;
+
+    // Authorize as an administrator.
+    let auth = Auth::new("admin", "pass123");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Invalid principals are rejected.
+    for principal in ["", "two words", "lions,admins"] {
+        let data = json!({
+            "name": "gryffindor",
+            "members": [],
+            "is_admin": false,
+            "ssh_principal": principal,
+        });
+        let response = client.post("/api/v1/group").json(&data).send().await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    let data = json!({
+        "name": "gryffindor",
+        "members": [],
+        "is_admin": false,
+        "ssh_principal": "lions",
+    });
+    let response = client.post("/api/v1/group").json(&data).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Principal is kept when not in the request, e.g. on edit in the UI.
+    let data = json!({
+        "name": "gryffindor",
+        "members": ["hpotter"],
+        "is_admin": false,
+    });
+    let response = client
+        .put("/api/v1/group/gryffindor")
+        .json(&data)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client.get("/api/v1/group/gryffindor").send().await;
+    let group_info: GroupInfo = response.json().await;
+    assert_eq!(group_info.ssh_principal.as_deref(), Some("lions"));
+
+    let data = json!({
+        "name": "gryffindor",
+        "members": ["hpotter"],
+        "is_admin": false,
+        "ssh_principal": "lions admins",
+    });
+    let response = client
+        .put("/api/v1/group/gryffindor")
+        .json(&data)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // Explicit null clears it.
+    let data = json!({
+        "name": "gryffindor",
+        "members": ["hpotter"],
+        "is_admin": false,
+        "ssh_principal": null,
+    });
+    let response = client
+        .put("/api/v1/group/gryffindor")
+        .json(&data)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client.get("/api/v1/group/gryffindor").send().await;
+    let group_info: GroupInfo = response.json().await;
+    assert_eq!(group_info.ssh_principal, None);
+}
@@ -0,0 +1,165 @@
+use chrono::Utc;
+use common::{client::TestClient, make_test_client, secret_totp_code};
+use defguard::{
+    db::User,
+    enterprise::db::models::api_tokens::ApiToken,
+    handlers::{Auth, AuthenticateWithMfaRequest},
+};
+use reqwest::{header::AUTHORIZATION, StatusCode};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+pub mod common;
+
+static AUTHORIZATION_HEADER: &str = "Bearer internal-token-string";
+
+#[derive(Deserialize)]
+struct AuthenticateResponse {
+    result: String,
+}
+
+/// Enable TOTP and MFA for hpotter and create an admin API token.
+/// Returns TOTP secret and recovery codes.
+async fn setup(pool: &PgPool) -> (String, Vec<String>) {
+    let mut user = User::find_by_username(pool, "hpotter")
+        .await
+        .unwrap()
+        .unwrap();
+    let secret = user.new_totp_secret(pool).await.unwrap();
+    let codes = user
+        .complete_totp_setup(pool, &secret_totp_code(&secret))
+        .await
+        .unwrap()
+        .unwrap();
+
+    let admin = User::find_by_username(pool, "admin")
+        .await
+        .unwrap()
+        .unwrap();
+    ApiToken::new(
+        admin.id,
+        Utc::now().naive_utc(),
+        "internal service".into(),
+        "internal-token-string",
+    )
+    .save(pool)
+    .await
+    .unwrap();
+
+    (secret, codes)
+}
+
+async fn authenticate(client: &TestClient, password: &str, code: &str) -> String {
+    let request = AuthenticateWithMfaRequest {
+        username: "hpotter".into(),
+        password: password.into(),
+        code: code.into(),
+    };
+    let response = client
+        .post("/api/v1/internal/authenticate")
+        .header(AUTHORIZATION, AUTHORIZATION_HEADER)
+        .json(&request)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response: AuthenticateResponse = response.json().await;
+    response.result
+}
+
+#[tokio::test]
+async fn test_internal_authenticate() {
+    let (client, state) = make_test_client().await;
+    let (secret, codes) = setup(&state.pool).await;
+
+    // password fails, the code isn't even checked
+    assert_eq!(
+        authenticate(&client, "wrong-password", &secret_totp_code(&secret)).await,
+        "invalid_password"
+    );
+
+    // password is correct, MFA code fails
+    assert_eq!(
+        authenticate(&client, "Pass123!", "000000").await,
+        "invalid_mfa_code"
+    );
+
+    // both factors are correct
+    assert_eq!(
+        authenticate(&client, "Pass123!", &secret_totp_code(&secret)).await,
+        "success"
+    );
+
+    // recovery code works once
+    assert_eq!(
+        authenticate(&client, "Pass123!", &codes[0]).await,
+        "success"
+    );
+    assert_eq!(
+        authenticate(&client, "Pass123!", &codes[0]).await,
+        "invalid_mfa_code"
+    );
+
+    // no session has been created
+    let response = client.get("/api/v1/me").send().await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    // admin session without API token is not enough
+    let auth = Auth::new("admin", "pass123");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let request = AuthenticateWithMfaRequest {
+        username: "hpotter".into(),
+        password: "Pass123!".into(),
+        code: secret_totp_code(&secret),
+    };
+    let response = client
+        .post("/api/v1/internal/authenticate")
+        .json(&request)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_internal_authenticate_mfa_not_enabled() {
+    let (client, state) = make_test_client().await;
+    setup(&state.pool).await;
+
+    let mut user = User::find_by_username(&state.pool, "hpotter")
+        .await
+        .unwrap()
+        .unwrap();
+    user.disable_mfa(&state.pool).await.unwrap();
+
+    assert_eq!(
+        authenticate(&client, "Pass123!", "000000").await,
+        "mfa_not_enabled"
+    );
+}
+
+#[tokio::test]
+async fn test_internal_authenticate_lockout() {
+    let (client, state) = make_test_client().await;
+    let (secret, _) = setup(&state.pool).await;
+
+    for _ in 0..5 {
+        assert_eq!(
+            authenticate(&client, "Pass123!", "000000").await,
+            "invalid_mfa_code"
+        );
+    }
+
+    // even valid credentials are rejected once the user is locked out
+    let request = AuthenticateWithMfaRequest {
+        username: "hpotter".into(),
+        password: "Pass123!".into(),
+        code: secret_totp_code(&secret),
+    };
+    let response = client
+        .post("/api/v1/internal/authenticate")
+        .header(AUTHORIZATION, AUTHORIZATION_HEADER)
+        .json(&request)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+}
@@ -0,0 +1,164 @@
+use chrono::Utc;
+use common::{make_test_client, secret_totp_code};
+use defguard::{
+    db::User,
+    enterprise::db::models::api_tokens::ApiToken,
+    handlers::{Auth, VerifyTotpRequest},
+};
+use reqwest::{header::AUTHORIZATION, StatusCode};
+use serde::Deserialize;
+
+pub mod common;
+
+#[derive(Deserialize)]
+struct VerifyTotpResponse {
+    verified: bool,
+}
+
+#[tokio::test]
+async fn test_internal_verify_totp() {
+    let (client, state) = make_test_client().await;
+
+    // enable TOTP for a user
+    let mut user = User::find_by_username(&state.pool, "hpotter")
+        .await
+        .unwrap()
+        .unwrap();
+    let secret = user.new_totp_secret(&state.pool).await.unwrap();
+    assert!(user
+        .confirm_totp_enroll(&state.pool, &secret_totp_code(&secret))
+        .await
+        .unwrap());
+
+    // API token belonging to an admin
+    let admin = User::find_by_username(&state.pool, "admin")
+        .await
+        .unwrap()
+        .unwrap();
+    let token_string = "internal-token-string";
+    ApiToken::new(
+        admin.id,
+        Utc::now().naive_utc(),
+        "internal service".into(),
+        token_string,
+    )
+    .save(&state.pool)
+    .await
+    .unwrap();
+    let authorization = format!("Bearer {token_string}");
+
+    // valid code
+    let request = VerifyTotpRequest {
+        username: "hpotter".into(),
+        code: secret_totp_code(&secret),
+    };
+    let response = client
+        .post("/api/v1/internal/verify-totp")
+        .header(AUTHORIZATION, &authorization)
+        .json(&request)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let result: VerifyTotpResponse = response.json().await;
+    assert!(result.verified);
+
+    // invalid code
+    let request = VerifyTotpRequest {
+        username: "hpotter".into(),
+        code: "invalid".into(),
+    };
+    let response = client
+        .post("/api/v1/internal/verify-totp")
+        .header(AUTHORIZATION, &authorization)
+        .json(&request)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let result: VerifyTotpResponse = response.json().await;
+    assert!(!result.verified);
+
+    // missing auth
+    let response = client
+        .post("/api/v1/internal/verify-totp")
+        .json(&request)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    // invalid token
+    let response = client
+        .post("/api/v1/internal/verify-totp")
+        .header(AUTHORIZATION, "Bearer wrong-token")
+        .json(&request)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    // admin session without API token is not enough
+    let auth = Auth::new("admin", "pass123");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client
+        .post("/api/v1/internal/verify-totp")
+        .json(&request)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_internal_verify_totp_rate_limit() {
+    let (client, state) = make_test_client().await;
+
+    let mut user = User::find_by_username(&state.pool, "hpotter")
+        .await
+        .unwrap()
+        .unwrap();
+    let secret = user.new_totp_secret(&state.pool).await.unwrap();
+    assert!(user
+        .confirm_totp_enroll(&state.pool, &secret_totp_code(&secret))
+        .await
+        .unwrap());
+    let admin = User::find_by_username(&state.pool, "admin")
+        .await
+        .unwrap()
+        .unwrap();
+    let token_string = "internal-token-string";
+    ApiToken::new(
+        admin.id,
+        Utc::now().naive_utc(),
+        "internal service".into(),
+        token_string,
+    )
+    .save(&state.pool)
+    .await
+    .unwrap();
+    let authorization = format!("Bearer {token_string}");
+
+    let request = VerifyTotpRequest {
+        username: "hpotter".into(),
+        code: "invalid".into(),
+    };
+    for _ in 0..5 {
+        let response = client
+            .post("/api/v1/internal/verify-totp")
+            .header(AUTHORIZATION, &authorization)
+            .json(&request)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    // even a valid code is rejected once the user is locked out
+    let request = VerifyTotpRequest {
+        username: "hpotter".into(),
+        code: secret_totp_code(&secret),
+    };
+    let response = client
+        .post("/api/v1/internal/verify-totp")
+        .header(AUTHORIZATION, &authorization)
+        .json(&request)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+}
@@ -270,7 +270,7 @@ async fn test_openid_app_management_access() {
     let test_app = &apps[0];
 
     // // login as standard user
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = client.post("/api/v1/auth").json(&auth).send().await;
     assert_eq!(response.status(), StatusCode::OK);
 
@@ -2,7 +2,9 @@ use chrono::{Duration, Utc};
 use common::{exceed_enterprise_limits, make_test_client};
 use defguard::{
     enterprise::{
-        db::models::openid_provider::{DirectorySyncTarget, DirectorySyncUserBehavior},
+        db::models::openid_provider::{
+            DirectorySyncTarget, DirectorySyncUserBehavior, GroupMapping,
+        },
         handlers::openid_providers::AddProviderData,
         license::{set_cached_license, License},
     },
@@ -58,6 +60,8 @@ async fn test_openid_providers() {
         okta_dirsync_client_id: None,
         okta_private_jwk: None,
         directory_sync_group_match: None,
+        rp_initiated_logout: false,
+        group_mapping: GroupMapping::default(),
     };
 
     let response = client
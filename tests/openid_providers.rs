@@ -0,0 +1,218 @@
+use axum::{
+    http::{header::AUTHORIZATION, HeaderMap},
+    routing::{get, post},
+    serve, Json, Router,
+};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use common::make_test_client;
+use defguard::{
+    enterprise::{
+        db::models::openid_provider::{
+            DirectorySyncTarget, DirectorySyncUserBehavior, GroupMapping,
+        },
+        handlers::openid_providers::{AddProviderData, TestProviderData},
+    },
+    handlers::Auth,
+};
+use reqwest::{StatusCode, Url};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::net::TcpListener;
+
+pub mod common;
+
+#[derive(Deserialize)]
+struct AuthInfoResponse {
+    url: String,
+    provider: String,
+}
+
+#[derive(Deserialize)]
+struct TestProviderResponse {
+    success: bool,
+    error: Option<String>,
+}
+
+async fn mock_token(headers: HeaderMap) -> (StatusCode, Json<Value>) {
+    let expected = format!(
+        "Basic {}",
+        BASE64_STANDARD.encode("client_id:client_secret")
+    );
+    let authorization = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    if authorization == Some(expected.as_str()) {
+        (
+            StatusCode::OK,
+            Json(json!({ "access_token": "access_token", "token_type": "bearer" })),
+        )
+    } else {
+        (
+            StatusCode::UNAUTHORIZED,
+            Json(json!({ "error": "invalid_client" })),
+        )
+    }
+}
+
+/// Start a minimal OpenID provider and return its issuer URL.
+async fn start_mock_provider() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let issuer = format!("http://127.0.0.1:{}", listener.local_addr().unwrap().port());
+    let metadata = json!({
+        "issuer": issuer,
+        "authorization_endpoint": format!("{issuer}/authorize"),
+        "token_endpoint": format!("{issuer}/token"),
+        "jwks_uri": format!("{issuer}/jwks"),
+        "response_types_supported": ["code"],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": ["RS256"],
+    });
+    let app = Router::new()
+        .route(
+            "/.well-known/openid-configuration",
+            get(move || {
+                let metadata = metadata.clone();
+                async move { Json(metadata) }
+            }),
+        )
+        .route("/jwks", get(|| async { Json(json!({ "keys": [] })) }))
+        .route("/token", post(mock_token));
+    tokio::spawn(async move {
+        serve(listener, app).await.expect("server error");
+    });
+
+    issuer
+}
+
+#[tokio::test]
+async fn test_openid_provider_connection() {
+    let (client, _) = make_test_client().await;
+    let issuer = start_mock_provider().await;
+
+    let auth = Auth::new("admin", "pass123");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // valid credentials
+    let provider_data = TestProviderData {
+        base_url: issuer.clone(),
+        client_id: "client_id".into(),
+        client_secret: "client_secret".into(),
+    };
+    let response = client
+        .post("/api/v1/openid/provider/test")
+        .json(&provider_data)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let result: TestProviderResponse = response.json().await;
+    assert!(result.success);
+
+    // invalid client secret
+    let provider_data = TestProviderData {
+        base_url: issuer,
+        client_id: "client_id".into(),
+        client_secret: "wrong_secret".into(),
+    };
+    let response = client
+        .post("/api/v1/openid/provider/test")
+        .json(&provider_data)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let result: TestProviderResponse = response.json().await;
+    assert!(!result.success);
+    assert_eq!(result.error.as_deref(), Some("invalid_client"));
+
+    // discovery failure
+    let provider_data = TestProviderData {
+        base_url: "http://127.0.0.1:1".into(),
+        client_id: "client_id".into(),
+        client_secret: "client_secret".into(),
+    };
+    let response = client
+        .post("/api/v1/openid/provider/test")
+        .json(&provider_data)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let result: TestProviderResponse = response.json().await;
+    assert!(!result.success);
+    assert_eq!(result.error.as_deref(), Some("discovery"));
+
+    // nothing was saved
+    let response = client.get("/api/v1/openid/provider").send().await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+}
+
+fn provider_data(name: &str, base_url: String) -> AddProviderData {
+    AddProviderData {
+        name: name.into(),
+        base_url,
+        client_id: format!("{name}_client_id"),
+        client_secret: format!("{name}_client_secret"),
+        display_name: Some(name.into()),
+        admin_email: None,
+        google_service_account_email: None,
+        google_service_account_key: None,
+        directory_sync_enabled: false,
+        directory_sync_interval: 100,
+        directory_sync_user_behavior: DirectorySyncUserBehavior::Keep.to_string(),
+        directory_sync_admin_behavior: DirectorySyncUserBehavior::Keep.to_string(),
+        directory_sync_target: DirectorySyncTarget::All.to_string(),
+        create_account: false,
+        okta_dirsync_client_id: None,
+        okta_private_jwk: None,
+        directory_sync_group_match: None,
+        rp_initiated_logout: false,
+        group_mapping: GroupMapping::default(),
+    }
+}
+
+#[tokio::test]
+async fn test_multiple_openid_providers() {
+    let (client, _) = make_test_client().await;
+
+    let auth = Auth::new("admin", "pass123");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    for name in ["azure", "okta"] {
+        let issuer = start_mock_provider().await;
+        let response = client
+            .post("/api/v1/openid/provider")
+            .json(&provider_data(name, issuer))
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    // login starts with the chosen provider
+    for name in ["azure", "okta"] {
+        let response = client
+            .get(format!("/api/v1/openid/auth_info?provider={name}"))
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let auth_info: AuthInfoResponse = response.json().await;
+        assert_eq!(auth_info.provider, name);
+        let url = Url::parse(&auth_info.url).unwrap();
+        let client_id = url
+            .query_pairs()
+            .find(|(key, _)| key == "client_id")
+            .unwrap();
+        assert_eq!(client_id.1, format!("{name}_client_id"));
+    }
+
+    // the first provider is used by default
+    let response = client.get("/api/v1/openid/auth_info").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let auth_info: AuthInfoResponse = response.json().await;
+    assert_eq!(auth_info.provider, "azure");
+
+    let response = client
+        .get("/api/v1/openid/auth_info?provider=google")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,787 @@
+pub mod common;
+
+use chrono::NaiveDateTime;
+use defguard::handlers::{Auth, GroupInfo};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::{query, query_scalar, PgPool};
+
+use self::common::{client::TestClient, make_test_client};
+
+static SSH_KEY: &str = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIK1ej+W6YY6PDUK2HAiJJ0Ia6WuGfxQf3IojblaIRc+A hpotter@hogwart";
+static GPG_KEY: &str = "-----BEGIN PGP PUBLIC KEY BLOCK-----
+
+mDMEatF1ERYJKwYBBAHaRw8BAQdAVHoF2waoSjyADEpLtZkUv1fv6ksvU7hxCdux
+7Noyrz+0JkhhcnJ5IFBvdHRlciA8aC5wb3R0ZXJAaG9nd2FydC5lZHUudWs+iJAE
+ExYIADgWIQTwu39SmTegStLUoyckYj+bOamFFAUCatF1EQIbAwULCQgHAgYVCgkI
+CwIEFgIDAQIeAQIXgAAKCRAkYj+bOamFFClcAP9SgT5wZKKkws6lFvk5QtYC+Zmd
+WleHoRTsbtWzyMbZpAD9GQgaxOF67uGATkwxGvcUqveSpZhZtas+DlityVxrZgA=
+=zKpK
+-----END PGP PUBLIC KEY BLOCK-----";
+
+#[derive(Deserialize)]
+struct Diagnostic {
+    keys: Vec<String>,
+    reason: Option<String>,
+}
+
+async fn make_client() -> TestClient {
+    let (client, _) = make_test_client().await;
+    client
+}
+
+async fn authorized_keys(client: &TestClient, query: &str) -> String {
+    let response = client
+        .get(format!("/api/v1/ssh_authorized_keys?{query}"))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    response.text().await
+}
+
+async fn diagnose(client: &TestClient, query: &str) -> Diagnostic {
+    let response = client
+        .get(format!("/api/v1/ssh_authorized_keys/diagnose?{query}"))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    response.json().await
+}
+
+#[tokio::test]
+async fn test_authorized_keys_diagnostics() {
+    let client = make_client().await;
+
+    let auth = Auth::new("admin", "pass123");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = client
+        .post("/api/v1/user/hpotter/auth_key")
+        .json(&json!({
+            "key": SSH_KEY,
+            "name": "laptop",
+            "key_type": "ssh",
+        }))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // safe mode doesn't reveal why no keys were returned
+    assert_eq!(authorized_keys(&client, "username=hpotter").await, SSH_KEY);
+    assert_eq!(authorized_keys(&client, "username=dmalfoy").await, "");
+    assert_eq!(authorized_keys(&client, "group=slytherin").await, "");
+    assert_eq!(authorized_keys(&client, "username=admin").await, "");
+
+    // verbose mode
+    let result = diagnose(&client, "username=hpotter").await;
+    assert_eq!(result.keys, vec![SSH_KEY.to_string()]);
+    assert_eq!(result.reason, None);
+
+    let result = diagnose(&client, "username=dmalfoy").await;
+    assert!(result.keys.is_empty());
+    assert_eq!(result.reason.as_deref(), Some("user_not_found"));
+
+    let result = diagnose(&client, "group=slytherin").await;
+    assert_eq!(result.reason.as_deref(), Some("group_not_found"));
+
+    let result = diagnose(&client, "group=admin&username=hpotter").await;
+    assert_eq!(result.reason.as_deref(), Some("user_not_in_group"));
+
+    let result = diagnose(&client, "username=admin").await;
+    assert_eq!(result.reason.as_deref(), Some("no_keys"));
+
+    let result = diagnose(&client, "").await;
+    assert_eq!(result.reason.as_deref(), Some("no_filter"));
+
+    // verbose mode is not available to regular users
+    let auth = Auth::new("hpotter", "Pass123!");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client
+        .get("/api/v1/ssh_authorized_keys/diagnose?username=dmalfoy")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // nor to anonymous clients
+    let response = client.post("/api/v1/auth/logout").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client
+        .get("/api/v1/ssh_authorized_keys/diagnose?username=dmalfoy")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_export_ssh_keys() {
+    let client = make_client().await;
+
+    let auth = Auth::new("hpotter", "Pass123!");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let second_key =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIHrFKj9GdPIfzXbZMkmOSF0T1IM9SbFe1o5U3FvYpN9A hpotter@desktop";
+    for (name, key, key_type) in [
+        ("laptop", SSH_KEY, "ssh"),
+        ("desktop", second_key, "ssh"),
+        ("signing", GPG_KEY, "gpg"),
+    ] {
+        let response = client
+            .post("/api/v1/user/hpotter/auth_key")
+            .json(&json!({
+                "key": key,
+                "name": name,
+                "key_type": key_type,
+            }))
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    let response = client
+        .get("/api/v1/user/hpotter/ssh_keys/export")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()["content-type"],
+        "text/plain; charset=utf-8"
+    );
+    assert_eq!(
+        response.headers()["content-disposition"],
+        "attachment; filename=\"hpotter_authorized_keys\""
+    );
+    let exported = response.text().await;
+    let mut lines: Vec<&str> = exported.lines().collect();
+    lines.sort_unstable();
+    let mut expected = vec![SSH_KEY, second_key];
+    expected.sort_unstable();
+    assert_eq!(lines, expected);
+
+    // other users' keys can't be exported
+    let response = client
+        .get("/api/v1/user/admin/ssh_keys/export")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // but admin can export anyone's keys
+    let auth = Auth::new("admin", "pass123");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client
+        .get("/api/v1/user/hpotter/ssh_keys/export")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.text().await.lines().count(), 2);
+}
+
+#[tokio::test]
+async fn test_add_duplicate_ssh_key() {
+    let client = make_client().await;
+
+    let auth = Auth::new("hpotter", "Pass123!");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let add_key = |username: &'static str, key: String| {
+        let client = &client;
+        async move {
+            client
+                .post(format!("/api/v1/user/{username}/auth_key"))
+                .json(&json!({
+                    "key": key,
+                    "name": "laptop",
+                    "key_type": "ssh",
+                }))
+                .send()
+                .await
+                .status()
+        }
+    };
+    assert_eq!(
+        add_key("hpotter", SSH_KEY.to_string()).await,
+        StatusCode::CREATED
+    );
+
+    // comment is stored apart from the key
+    let response = client.get("/api/v1/user/hpotter/auth_key").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let keys: Vec<serde_json::Value> = response.json().await;
+    let (canonical, comment) = SSH_KEY.rsplit_once(' ').unwrap();
+    assert_eq!(keys[0]["key"], canonical);
+    assert_eq!(keys[0]["ssh_key"]["comment"], comment);
+    assert_eq!(authorized_keys(&client, "username=hpotter").await, SSH_KEY);
+
+    // same key with different comment and whitespace is a duplicate
+    let (algorithm, rest) = SSH_KEY.split_once(' ').unwrap();
+    let (body, _) = rest.split_once(' ').unwrap();
+    for key in [
+        format!("{algorithm} {body}"),
+        format!("{algorithm}\t{body}   hpotter@desktop"),
+    ] {
+        assert_eq!(add_key("hpotter", key).await, StatusCode::BAD_REQUEST);
+    }
+
+    // keys may be shared between users unless global uniqueness is enabled,
+    // see `ssh_key_global_unique.rs`
+    let auth = Auth::new("admin", "pass123");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        add_key("admin", SSH_KEY.to_string()).await,
+        StatusCode::CREATED
+    );
+}
+
+#[tokio::test]
+async fn test_ssh_key_expiry() {
+    let client = make_client().await;
+
+    let auth = Auth::new("hpotter", "Pass123!");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // keys can't be added already expired
+    let response = client
+        .post("/api/v1/user/hpotter/auth_key")
+        .json(&json!({
+            "key": SSH_KEY,
+            "name": "laptop",
+            "key_type": "ssh",
+            "expires_at": "2000-01-01T00:00:00",
+        }))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = client
+        .post("/api/v1/user/hpotter/auth_key")
+        .json(&json!({
+            "key": SSH_KEY,
+            "name": "laptop",
+            "key_type": "ssh",
+            "expires_at": "2100-01-01T00:00:00",
+        }))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(authorized_keys(&client, "username=hpotter").await, SSH_KEY);
+
+    let response = client.get("/api/v1/user/hpotter/auth_key").send().await;
+    let keys: Vec<serde_json::Value> = response.json().await;
+    assert_eq!(keys[0]["expires_at"], "2100-01-01T00:00:00");
+    let key_id = keys[0]["id"].as_i64().unwrap();
+    let expiry_url = format!("/api/v1/user/hpotter/auth_key/{key_id}/expiry");
+
+    // only admins can change expiration
+    let response = client
+        .put(&expiry_url)
+        .json(&json!({ "expires_at": null }))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let auth = Auth::new("admin", "pass123");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // expired key is left out of authorized keys
+    let response = client
+        .put(&expiry_url)
+        .json(&json!({ "expires_at": "2000-01-01T00:00:00" }))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(authorized_keys(&client, "username=hpotter").await, "");
+
+    let response = client
+        .put(&expiry_url)
+        .json(&json!({ "expires_at": null }))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(authorized_keys(&client, "username=hpotter").await, SSH_KEY);
+
+    // key must belong to the user from the path
+    let response = client
+        .put(format!("/api/v1/user/admin/auth_key/{key_id}/expiry"))
+        .json(&json!({ "expires_at": null }))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_ssh_key_options() {
+    let client = make_client().await;
+
+    let auth = Auth::new("hpotter", "Pass123!");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // users can't set options of their own keys
+    let options = "from=\"10.0.0.0/8\",no-port-forwarding,no-X11-forwarding";
+    let response = client
+        .post("/api/v1/user/hpotter/auth_key")
+        .json(&json!({
+            "key": SSH_KEY,
+            "name": "laptop",
+            "key_type": "ssh",
+            "options": options,
+        }))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let auth = Auth::new("admin", "pass123");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    for (options, key_type) in [
+        ("no-pty,environment=\"LD_PRELOAD=/tmp/x.so\"", "ssh"),
+        ("from=10.0.0.1", "ssh"),
+        ("no-pty", "gpg"),
+    ] {
+        let response = client
+            .post("/api/v1/user/hpotter/auth_key")
+            .json(&json!({
+                "key": if key_type == "ssh" { SSH_KEY } else { GPG_KEY },
+                "name": "laptop",
+                "key_type": key_type,
+                "options": options,
+            }))
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    let response = client
+        .post("/api/v1/user/hpotter/auth_key")
+        .json(&json!({
+            "key": SSH_KEY,
+            "name": "laptop",
+            "key_type": "ssh",
+            "options": options,
+        }))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(
+        authorized_keys(&client, "username=hpotter").await,
+        format!("{options} {SSH_KEY}")
+    );
+
+    let response = client.get("/api/v1/user/hpotter/auth_key").send().await;
+    let keys: Vec<serde_json::Value> = response.json().await;
+    let key_id = keys[0]["id"].as_i64().unwrap();
+    let options_url = format!("/api/v1/user/hpotter/auth_key/{key_id}/options");
+
+    // admin can clear and change options of existing keys
+    let response = client
+        .put(&options_url)
+        .json(&json!({ "options": null }))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(authorized_keys(&client, "username=hpotter").await, SSH_KEY);
+
+    let response = client
+        .put(&options_url)
+        .json(&json!({ "options": "no-pty" }))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        authorized_keys(&client, "username=hpotter").await,
+        format!("no-pty {SSH_KEY}")
+    );
+
+    let response = client
+        .put(&options_url)
+        .json(&json!({ "options": "from=10.0.0.1" }))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // but users can't
+    let auth = Auth::new("hpotter", "Pass123!");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client
+        .put(&options_url)
+        .json(&json!({ "options": null }))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_ssh_key_usage() {
+    let client = make_client().await;
+
+    let auth = Auth::new("hpotter", "Pass123!");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let deploy_key =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIHrFKj9GdPIfzXbZMkmOSF0T1IM9SbFe1o5U3FvYpN9A ci@hogwart";
+    for (name, key, usage) in [
+        ("laptop", SSH_KEY, "interactive"),
+        ("ci", deploy_key, "deploy"),
+    ] {
+        let response = client
+            .post("/api/v1/user/hpotter/auth_key")
+            .json(&json!({
+                "key": key,
+                "name": name,
+                "key_type": "ssh",
+                "usage": usage,
+            }))
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    // deploy keys are excluded by default
+    assert_eq!(authorized_keys(&client, "username=hpotter").await, SSH_KEY);
+    assert_eq!(
+        authorized_keys(&client, "username=hpotter&usage=interactive").await,
+        SSH_KEY
+    );
+    assert_eq!(
+        authorized_keys(&client, "username=hpotter&usage=deploy").await,
+        deploy_key
+    );
+}
+
+#[tokio::test]
+async fn test_add_gpg_key() {
+    let client = make_client().await;
+
+    let auth = Auth::new("hpotter", "Pass123!");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // malformed keys are rejected
+    for key in [
+        "-----BEGIN PGP PUBLIC KEY BLOCK-----",
+        &GPG_KEY[..GPG_KEY.len() / 2],
+        "garbage",
+    ] {
+        let response = client
+            .post("/api/v1/user/hpotter/auth_key")
+            .json(&json!({
+                "key": key,
+                "name": "signing",
+                "key_type": "gpg",
+            }))
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    let response = client
+        .post("/api/v1/user/hpotter/auth_key")
+        .json(&json!({
+            "key": GPG_KEY,
+            "name": "signing",
+            "key_type": "gpg",
+        }))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let response = client.get("/api/v1/user/hpotter/auth_key").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let keys: Vec<serde_json::Value> = response.json().await;
+    assert_eq!(keys.len(), 1);
+    assert_eq!(
+        keys[0]["gpg_fingerprint"],
+        "f0bb7f529937a04ad2d4a32724623f9b39a98514"
+    );
+}
+
+#[tokio::test]
+async fn test_authorized_keys_multiple_groups() {
+    let client = make_client().await;
+
+    let auth = Auth::new("admin", "pass123");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let admin_key =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIHrFKj9GdPIfzXbZMkmOSF0T1IM9SbFe1o5U3FvYpN9A admin@hogwart";
+    for (username, key) in [("hpotter", SSH_KEY), ("admin", admin_key)] {
+        let response = client
+            .post(format!("/api/v1/user/{username}/auth_key"))
+            .json(&json!({
+                "key": key,
+                "name": "laptop",
+                "key_type": "ssh",
+            }))
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    // both users are in `devops`, only hpotter is in `gryffindor`
+    let data = GroupInfo::new(
+        "devops",
+        vec!["hpotter".into(), "admin".into()],
+        Vec::new(),
+        false,
+    );
+    let response = client.post("/api/v1/group").json(&data).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let data = GroupInfo::new("gryffindor", vec!["hpotter".into()], Vec::new(), false);
+    let response = client.post("/api/v1/group").json(&data).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // single group
+    assert_eq!(authorized_keys(&client, "group=gryffindor").await, SSH_KEY);
+
+    // union of groups, users belonging to several groups are listed once
+    let keys = authorized_keys(&client, "group=gryffindor,devops").await;
+    let mut lines: Vec<&str> = keys.lines().collect();
+    lines.sort_unstable();
+    let mut expected = vec![SSH_KEY, admin_key];
+    expected.sort_unstable();
+    assert_eq!(lines, expected);
+
+    // non-existing groups are skipped
+    assert_eq!(
+        authorized_keys(&client, "group=slytherin,%20gryffindor").await,
+        SSH_KEY
+    );
+    let result = diagnose(&client, "group=slytherin,ravenclaw").await;
+    assert!(result.keys.is_empty());
+    assert_eq!(result.reason.as_deref(), Some("group_not_found"));
+
+    // username combined with groups requires membership in any of them
+    assert_eq!(
+        authorized_keys(&client, "username=hpotter&group=admin,gryffindor").await,
+        SSH_KEY
+    );
+    assert_eq!(
+        authorized_keys(&client, "username=admin&group=slytherin,gryffindor").await,
+        ""
+    );
+    let result = diagnose(&client, "username=admin&group=slytherin,gryffindor").await;
+    assert_eq!(result.reason.as_deref(), Some("user_not_in_group"));
+
+    // no filter still returns an empty response
+    assert_eq!(authorized_keys(&client, "").await, "");
+    assert_eq!(authorized_keys(&client, "group=,").await, "");
+}
+
+#[tokio::test]
+async fn test_authorized_keys_existence_independent() {
+    let client = make_client().await;
+
+    // anonymous requests get the same empty response for existing and non-existing users and
+    // groups without keys
+    for query in [
+        "username=hpotter",
+        "username=dmalfoy",
+        "group=admin",
+        "group=slytherin",
+        "username=hpotter&group=admin",
+        "username=dmalfoy&group=admin",
+        "username=hpotter&group=slytherin",
+        "username=dmalfoy&group=slytherin",
+    ] {
+        assert_eq!(authorized_keys(&client, query).await, "", "{query}");
+    }
+
+    let auth = Auth::new("hpotter", "Pass123!");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client
+        .post("/api/v1/user/hpotter/auth_key")
+        .json(&json!({
+            "key": SSH_KEY,
+            "name": "laptop",
+            "key_type": "ssh",
+        }))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = client.post("/api/v1/auth/logout").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // only a matching existing user yields keys
+    assert_eq!(authorized_keys(&client, "username=hpotter").await, SSH_KEY);
+    for query in [
+        "username=dmalfoy",
+        "username=hpotter&group=admin",
+        "username=hpotter&group=slytherin",
+    ] {
+        assert_eq!(authorized_keys(&client, query).await, "", "{query}");
+    }
+}
+
+async fn yubikey_last_used_at(pool: &PgPool, yubikey_id: i64) -> NaiveDateTime {
+    query_scalar!("SELECT last_used_at FROM yubikey WHERE id = $1", yubikey_id)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_yubikey_use_reported_by_admin_only() {
+    let (client, client_state) = make_test_client().await;
+    let pool = client_state.pool;
+
+    let auth = Auth::new("admin", "pass123");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client
+        .post("/api/v1/user/hpotter/auth_key")
+        .json(&json!({
+            "key": SSH_KEY,
+            "name": "yubikey",
+            "key_type": "ssh",
+        }))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let yubikey_id = query_scalar!(
+        "INSERT INTO yubikey (name, serial, user_id, last_used_at) \
+        SELECT 'YubiKey 1', '12345678', id, now() - interval '100 days' \
+        FROM \"user\" WHERE username = 'hpotter' RETURNING id"
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    query!(
+        "UPDATE authentication_key SET yubikey_id = $1 WHERE key_type = 'ssh'",
+        yubikey_id
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    let initial = yubikey_last_used_at(&pool, yubikey_id).await;
+
+    // serving keys through the public endpoint doesn't count as use
+    let response = client.post("/api/v1/auth/logout").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(!authorized_keys(&client, "username=hpotter")
+        .await
+        .is_empty());
+    assert_eq!(yubikey_last_used_at(&pool, yubikey_id).await, initial);
+
+    // reporting use requires admin credentials
+    let response = client
+        .post("/api/v1/ssh_authorized_keys/used")
+        .json(&json!({ "key": SSH_KEY }))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let auth = Auth::new("hpotter", "Pass123!");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client
+        .post("/api/v1/ssh_authorized_keys/used")
+        .json(&json!({ "key": SSH_KEY }))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert_eq!(yubikey_last_used_at(&pool, yubikey_id).await, initial);
+
+    let auth = Auth::new("admin", "pass123");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client
+        .post("/api/v1/ssh_authorized_keys/used")
+        .json(&json!({ "key": SSH_KEY }))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(yubikey_last_used_at(&pool, yubikey_id).await > initial);
+}
+
+#[tokio::test]
+async fn test_authorized_keys_host_groups() {
+    let client = make_client().await;
+
+    let auth = Auth::new("admin", "pass123");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let admin_key =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIHrFKj9GdPIfzXbZMkmOSF0T1IM9SbFe1o5U3FvYpN9A admin@hogwart";
+    for (username, key) in [("hpotter", SSH_KEY), ("admin", admin_key)] {
+        let response = client
+            .post(format!("/api/v1/user/{username}/auth_key"))
+            .json(&json!({
+                "key": key,
+                "name": "laptop",
+                "key_type": "ssh",
+            }))
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+    let data = GroupInfo::new("gryffindor", vec!["hpotter".into()], Vec::new(), false);
+    let response = client.post("/api/v1/group").json(&data).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // host without groups grants access to nobody
+    assert_eq!(
+        authorized_keys(&client, "username=hpotter&host=common-room").await,
+        ""
+    );
+    assert_eq!(authorized_keys(&client, "host=common-room").await, "");
+    let result = diagnose(&client, "username=hpotter&host=common-room").await;
+    assert_eq!(result.reason.as_deref(), Some("no_host_access"));
+
+    let response = client
+        .put("/api/v1/ssh_host/common-room/groups")
+        .json(&json!(["gryffindor"]))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // only members of groups granted access to the host get keys
+    assert_eq!(
+        authorized_keys(&client, "username=hpotter&host=common-room").await,
+        SSH_KEY
+    );
+    assert_eq!(
+        authorized_keys(&client, "username=admin&host=common-room").await,
+        ""
+    );
+    let result = diagnose(&client, "username=admin&host=common-room").await;
+    assert_eq!(result.reason.as_deref(), Some("no_host_access"));
+    assert_eq!(
+        authorized_keys(&client, "group=admin,gryffindor&host=common-room").await,
+        SSH_KEY
+    );
+    assert_eq!(
+        authorized_keys(&client, "group=admin&host=common-room").await,
+        ""
+    );
+    assert_eq!(authorized_keys(&client, "host=common-room").await, SSH_KEY);
+
+    // without host, mapping doesn't apply
+    assert_eq!(authorized_keys(&client, "username=admin").await, admin_key);
+
+    // revoking all groups revokes access
+    let response = client
+        .put("/api/v1/ssh_host/common-room/groups")
+        .json(&json!([]))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        authorized_keys(&client, "username=hpotter&host=common-room").await,
+        ""
+    );
+}
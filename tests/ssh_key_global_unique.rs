@@ -0,0 +1,67 @@
+pub mod common;
+
+use defguard::{config::DefGuardConfig, handlers::Auth, SERVER_CONFIG};
+use reqwest::StatusCode;
+use serde_json::json;
+
+use self::common::{client::TestClient, make_test_client};
+
+static SSH_KEY: &str = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIK1ej+W6YY6PDUK2HAiJJ0Ia6WuGfxQf3IojblaIRc+A hpotter@hogwart";
+
+// Server configuration is global, so global uniqueness of SSH keys is tested in a separate binary.
+async fn make_client() -> TestClient {
+    let mut config = DefGuardConfig::new_test_config();
+    config.ssh_key_global_unique = true;
+    let _ = SERVER_CONFIG.set(config);
+    let (client, _) = make_test_client().await;
+    client
+}
+
+#[tokio::test]
+async fn test_add_duplicate_ssh_key() {
+    let client = make_client().await;
+
+    let add_key = |username: &'static str, key: String| {
+        let client = &client;
+        async move {
+            client
+                .post(format!("/api/v1/user/{username}/auth_key"))
+                .json(&json!({
+                    "key": key,
+                    "name": "laptop",
+                    "key_type": "ssh",
+                }))
+                .send()
+                .await
+        }
+    };
+
+    let auth = Auth::new("hpotter", "Pass123!");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = add_key("hpotter", SSH_KEY.to_string()).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // the same key of another user is rejected, regardless of comment
+    let auth = Auth::new("admin", "pass123");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let (key, _) = SSH_KEY.rsplit_once(' ').unwrap();
+    for key in [SSH_KEY.to_string(), format!("{key} admin@hogwart")] {
+        let response = add_key("admin", key).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let body: serde_json::Value = response.json().await;
+        assert_eq!(body["msg"], "Key is already registered by another user");
+    }
+    let response = client.get("/api/v1/user/admin/auth_key").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let keys: Vec<serde_json::Value> = response.json().await;
+    assert!(keys.is_empty());
+
+    // owner may still re-add it, which is reported as a duplicate
+    let auth = Auth::new("hpotter", "Pass123!");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = add_key("hpotter", SSH_KEY.to_string()).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
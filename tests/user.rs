@@ -5,9 +5,10 @@ use defguard::{
         models::{oauth2client::OAuth2Client, NewOpenIDClient},
         AddDevice, Id, UserInfo,
     },
-    handlers::{AddUserData, Auth, PasswordChange, PasswordChangeSelf, Username},
+    handlers::{AddUserData, Auth, AuthResponse, PasswordChange, PasswordChangeSelf, Username},
 };
 use reqwest::{header::USER_AGENT, StatusCode};
+use serde_json::json;
 use tokio_stream::{self as stream, StreamExt};
 
 use self::common::{client::TestClient, fetch_user_details, make_network, make_test_client};
@@ -21,7 +22,7 @@ async fn make_client() -> TestClient {
 async fn test_authenticate() {
     let client = make_client().await;
 
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = client.post("/api/v1/auth").json(&auth).send().await;
     assert_eq!(response.status(), StatusCode::OK);
 
@@ -38,7 +39,7 @@ async fn test_authenticate() {
 async fn test_me() {
     let client = make_client().await;
 
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = client.post("/api/v1/auth").json(&auth).send().await;
     assert_eq!(response.status(), StatusCode::OK);
 
@@ -53,7 +54,7 @@ async fn test_me() {
 async fn test_change_self_password() {
     let client = make_client().await;
 
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
 
     let response = client.post("/api/v1/auth").json(&auth).send().await;
     assert_eq!(response.status(), StatusCode::OK);
@@ -68,12 +69,12 @@ async fn test_change_self_password() {
     };
 
     let bad_new_request = PasswordChangeSelf {
-        old_password: "pass123".into(),
+        old_password: "Pass123!".into(),
         new_password: "badnew".into(),
     };
 
     let change_password = PasswordChangeSelf {
-        old_password: "pass123".into(),
+        old_password: "Pass123!".into(),
         new_password: new_password.into(),
     };
 
@@ -155,6 +156,79 @@ async fn test_change_password() {
     assert_eq!(response.status(), StatusCode::FORBIDDEN);
 }
 
+#[tokio::test]
+async fn test_must_change_password() {
+    let client = make_client().await;
+
+    let auth = Auth::new("admin", "pass123");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // admin resets password
+    let reset_password = "resetPassword43$!";
+    let response = client
+        .put("/api/v1/user/hpotter/password")
+        .json(&PasswordChange {
+            new_password: reset_password.into(),
+        })
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // admin's own session is not affected
+    let response = client.get("/api/v1/me").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // login reports required password change
+    let auth = Auth::new("hpotter", reset_password);
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let auth_response: AuthResponse = response.json().await;
+    assert!(auth_response.user.must_change_password);
+
+    // other endpoints are blocked
+    let response = client.get("/api/v1/me").send().await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = client.get("/api/v1/user/hpotter").send().await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // invalid change doesn't clear the flag
+    let response = client
+        .put("/api/v1/user/change_password")
+        .json(&PasswordChangeSelf {
+            old_password: reset_password.into(),
+            new_password: "weak".into(),
+        })
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = client.get("/api/v1/me").send().await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // user sets a new password
+    let new_password = "userPassword43$!";
+    let response = client
+        .put("/api/v1/user/change_password")
+        .json(&PasswordChangeSelf {
+            old_password: reset_password.into(),
+            new_password: new_password.into(),
+        })
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = client.get("/api/v1/me").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let user_info: UserInfo = response.json().await;
+    assert!(!user_info.must_change_password);
+
+    let auth = Auth::new("hpotter", new_password);
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let auth_response: AuthResponse = response.json().await;
+    assert!(!auth_response.user.must_change_password);
+}
+
 #[tokio::test]
 async fn test_list_users() {
     let client = make_client().await;
@@ -163,7 +237,7 @@ async fn test_list_users() {
     assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
 
     // normal user cannot list users
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = client.post("/api/v1/auth").json(&auth).send().await;
     assert_eq!(response.status(), StatusCode::OK);
 
@@ -177,6 +251,83 @@ async fn test_list_users() {
 
     let response = client.get("/api/v1/user").send().await;
     assert_eq!(response.status(), StatusCode::OK);
+    let users: Vec<UserInfo> = response.json().await;
+    assert_eq!(users.len(), 2);
+
+    // paginated listing
+    let response = client.get("/api/v1/user?limit=1&offset=1").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let page: serde_json::Value = response.json().await;
+    assert_eq!(page["total"], 2);
+    assert_eq!(page["limit"], 1);
+    assert_eq!(page["offset"], 1);
+    assert_eq!(page["items"].as_array().unwrap().len(), 1);
+    // users are ordered by id
+    assert_eq!(page["items"][0]["username"], "hpotter");
+}
+
+#[tokio::test]
+async fn test_list_security_keys() {
+    let client = make_client().await;
+
+    let auth = Auth::new("hpotter", "Pass123!");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = client.get("/api/v1/security_key").send().await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let auth = Auth::new("admin", "pass123");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = client
+        .get("/api/v1/security_key?limit=10&offset=0")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let page: serde_json::Value = response.json().await;
+    assert_eq!(
+        page,
+        json!({ "items": [], "total": 0, "limit": 10, "offset": 0 })
+    );
+}
+
+#[tokio::test]
+async fn test_check_recovery_code() {
+    let client = make_client().await;
+    let code = json!({ "code": "invalid code" });
+
+    // users can't check recovery codes, not even their own
+    let auth = Auth::new("hpotter", "Pass123!");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client
+        .post("/api/v1/user/hpotter/recovery_code/check")
+        .json(&code)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let auth = Auth::new("admin", "pass123");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client
+        .post("/api/v1/user/hpotter/recovery_code/check")
+        .json(&code)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = response.json().await;
+    assert_eq!(body["valid"], false);
+
+    // admins can't check their own codes either
+    let response = client
+        .post("/api/v1/user/admin/recovery_code/check")
+        .json(&code)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
 }
 
 #[tokio::test]
@@ -186,7 +337,7 @@ async fn test_get_user() {
     let response = client.get("/api/v1/user/hpotter").send().await;
     assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
 
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = client.post("/api/v1/auth").json(&auth).send().await;
     assert_eq!(response.status(), StatusCode::OK);
 
@@ -200,7 +351,7 @@ async fn test_username_available() {
     let client = make_client().await;
 
     // standard user cannot check username availability
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = client.post("/api/v1/auth").json(&auth).send().await;
     assert_eq!(response.status(), StatusCode::OK);
 
@@ -292,7 +443,7 @@ async fn test_crud_user() {
 async fn test_admin_group() {
     let client = make_client().await;
 
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = client.post("/api/v1/auth").json(&auth).send().await;
     assert_eq!(response.status(), StatusCode::OK);
 
@@ -519,7 +670,7 @@ async fn test_user_add_device() {
         .contains("Device type:</span> iPhone, OS: iOS 17.1, Mobile Safari"));
 
     // log in as normal user
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = client
         .post("/api/v1/auth")
         .header(USER_AGENT, user_agent_header)
@@ -663,3 +814,52 @@ async fn test_unique_email() {
     let response = client.post("/api/v1/user").json(&new_user).send().await;
     assert_eq!(response.status(), StatusCode::BAD_REQUEST);
 }
+
+#[tokio::test]
+async fn test_add_user_field_errors() {
+    let client = make_client().await;
+
+    let auth = Auth::new("admin", "pass123");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // every invalid field is reported, not only the first one
+    let new_user = AddUserData {
+        username: "-adumbledore".into(),
+        last_name: "Dumbledore".into(),
+        first_name: "Albus".into(),
+        email: "not an email".into(),
+        phone: Some("call me".into()),
+        password: Some("weak".into()),
+    };
+    let response = client.post("/api/v1/user").json(&new_user).send().await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = response.json().await;
+    assert_eq!(body["msg"], "Validation failed");
+    let fields: Vec<&str> = body["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|error| error["field"].as_str().unwrap())
+        .collect();
+    assert_eq!(fields, ["username", "email", "password", "phone"]);
+    assert!(body["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .all(|error| !error["message"].as_str().unwrap().is_empty()));
+
+    // duplicate e-mail is reported as a field error as well
+    let new_user = AddUserData {
+        username: "hpotter2".into(),
+        last_name: "Potter".into(),
+        first_name: "Harry".into(),
+        email: "h.potter@hogwart.edu.uk".into(),
+        phone: None,
+        password: Some("Password1234543$!".into()),
+    };
+    let response = client.post("/api/v1/user").json(&new_user).send().await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = response.json().await;
+    assert_eq!(body["errors"][0]["field"], "email");
+}
@@ -1,12 +1,38 @@
 pub mod common;
 
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use axum::{
+    body::Bytes,
+    http::{self, HeaderMap},
+    routing::post,
+    serve, Router,
+};
+use chrono::Utc;
 use defguard::{
     db::{Id, NoId, WebHook},
-    handlers::Auth,
+    handlers::{AddUserData, Auth, AuthTotp, GroupInfo},
+    hex::hex_decode,
 };
+use hmac::{Hmac, Mac};
 use reqwest::StatusCode;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use sqlx::{query_scalar, types::Json};
+use tokio::{
+    net::TcpListener,
+    sync::mpsc::unbounded_channel,
+    time::{sleep, timeout},
+};
 
-use self::common::{client::TestClient, make_test_client};
+use self::common::{client::TestClient, make_test_client, totp_code};
 
 async fn make_client() -> TestClient {
     let (client, _) = make_test_client().await;
@@ -31,6 +57,16 @@ async fn test_webhooks() {
         on_user_deleted: false,
         on_user_modified: true,
         on_hwkey_provision: false,
+        on_enrollment_pending: false,
+        on_enrollment_completed: false,
+        on_user_mfa_changed: false,
+        group_id: None,
+        paused_until: None,
+        custom_headers: Json(HashMap::new()),
+        body_template: None,
+        max_attempts: 5,
+        retry_base_delay: 10,
+        archived: false,
     };
 
     let response = client.post("/api/v1/webhook").json(&webhook).send().await;
@@ -71,3 +107,714 @@ async fn test_webhooks() {
     let webhooks: Vec<WebHook<Id>> = response.json().await;
     assert!(webhooks.is_empty());
 }
+
+#[tokio::test]
+async fn test_import_webhooks() {
+    let client = make_client().await;
+
+    let auth = Auth::new("admin", "pass123");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let webhook = WebHook {
+        id: NoId,
+        url: "http://localhost:3000/existing".into(),
+        description: "Existing".into(),
+        token: "1234567890".into(),
+        enabled: true,
+        on_user_created: true,
+        on_user_deleted: false,
+        on_user_modified: false,
+        on_hwkey_provision: false,
+        on_enrollment_pending: false,
+        on_enrollment_completed: false,
+        on_user_mfa_changed: false,
+        group_id: None,
+        paused_until: None,
+        custom_headers: Json(HashMap::new()),
+        body_template: None,
+        max_attempts: 5,
+        retry_base_delay: 10,
+        archived: false,
+    };
+    let response = client.post("/api/v1/webhook").json(&webhook).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let definition = |url: &str, token: &str| {
+        json!({
+            "url": url,
+            "description": "Imported",
+            "token": token,
+            "enabled": true,
+            "on_user_created": true,
+            "on_user_deleted": true,
+            "on_user_modified": false,
+            "on_hwkey_provision": false,
+            "paused_until": null,
+        })
+    };
+    let webhooks = json!([
+        definition("http://localhost:3000/first", "token1"),
+        definition("http://localhost:3000/existing", "token2"),
+        definition("http://localhost:3000/second", "token3"),
+        definition("not a url", "token4"),
+        definition("http://localhost:3000/third", ""),
+    ]);
+    let response = client
+        .post("/api/v1/webhook/import")
+        .json(&webhooks)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let result: Value = response.json().await;
+    assert_eq!(result["created"], 2);
+    assert_eq!(result["skipped"], 1);
+    assert_eq!(result["failed"], 2);
+    let statuses: Vec<&str> = result["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item["status"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        statuses,
+        ["created", "duplicate", "created", "invalid", "invalid"]
+    );
+
+    let response = client.get("/api/v1/webhook").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let webhooks: Vec<WebHook<Id>> = response.json().await;
+    assert_eq!(webhooks.len(), 3);
+
+    // importing the same definitions again creates nothing
+    let webhooks = json!([definition("http://localhost:3000/first", "token1")]);
+    let response = client
+        .post("/api/v1/webhook/import")
+        .json(&webhooks)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let result: Value = response.json().await;
+    assert_eq!(result["created"], 0);
+    assert_eq!(result["skipped"], 1);
+}
+
+#[tokio::test]
+async fn test_webhook_custom_headers() {
+    let client = make_client().await;
+
+    let auth = Auth::new("admin", "pass123");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // receiver which reports received API key
+    let (tx, mut rx) = unbounded_channel();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!(
+        "http://127.0.0.1:{}/hook",
+        listener.local_addr().unwrap().port()
+    );
+    let app = Router::new().route(
+        "/hook",
+        post(move |headers: HeaderMap| {
+            let tx = tx.clone();
+            async move {
+                let api_key = headers
+                    .get("x-api-key")
+                    .and_then(|value| value.to_str().ok())
+                    .map(ToString::to_string);
+                tx.send(api_key).unwrap();
+            }
+        }),
+    );
+    tokio::spawn(async move {
+        serve(listener, app).await.expect("server error");
+    });
+
+    let mut webhook = WebHook {
+        id: NoId,
+        url,
+        description: "Custom headers".into(),
+        token: "1234567890".into(),
+        enabled: true,
+        on_user_created: true,
+        on_user_deleted: false,
+        on_user_modified: false,
+        on_hwkey_provision: false,
+        on_enrollment_pending: false,
+        on_enrollment_completed: false,
+        on_user_mfa_changed: false,
+        group_id: None,
+        paused_until: None,
+        custom_headers: Json(HashMap::from([("Host".into(), "example.com".into())])),
+        body_template: None,
+        max_attempts: 5,
+        retry_base_delay: 10,
+        archived: false,
+    };
+
+    // reserved header is rejected
+    let response = client.post("/api/v1/webhook").json(&webhook).send().await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = client.get("/api/v1/webhook").send().await;
+    let webhooks: Vec<WebHook<Id>> = response.json().await;
+    assert!(webhooks.is_empty());
+
+    webhook.custom_headers = Json(HashMap::from([("X-Api-Key".into(), "secret".into())]));
+    let response = client.post("/api/v1/webhook").json(&webhook).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let response = client.get("/api/v1/webhook").send().await;
+    let webhooks: Vec<WebHook<Id>> = response.json().await;
+    assert_eq!(webhooks.len(), 1);
+    assert_eq!(
+        webhooks[0].custom_headers.get("X-Api-Key").unwrap(),
+        "secret"
+    );
+
+    // reserved header can't be set on update either
+    webhook.custom_headers = Json(HashMap::from([("Content-Length".into(), "0".into())]));
+    let response = client
+        .put(format!("/api/v1/webhook/{}", webhooks[0].id))
+        .json(&webhook)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // trigger the webhook
+    let new_user = AddUserData {
+        username: "adumbledore".into(),
+        last_name: "Dumbledore".into(),
+        first_name: "Albus".into(),
+        email: "a.dumbledore@hogwart.edu.uk".into(),
+        phone: None,
+        password: None,
+    };
+    let response = client.post("/api/v1/user").json(&new_user).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let api_key = timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .expect("webhook not delivered")
+        .unwrap();
+    assert_eq!(api_key.as_deref(), Some("secret"));
+}
+
+#[tokio::test]
+async fn test_webhook_retries() {
+    let client = make_client().await;
+
+    let auth = Auth::new("admin", "pass123");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // receiver which fails twice, then accepts the request and reports attempt count
+    let (tx, mut rx) = unbounded_channel();
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!(
+        "http://127.0.0.1:{}/hook",
+        listener.local_addr().unwrap().port()
+    );
+    let counter = Arc::clone(&attempts);
+    let app = Router::new().route(
+        "/hook",
+        post(move || {
+            let tx = tx.clone();
+            let counter = Arc::clone(&counter);
+            async move {
+                let attempt = counter.fetch_add(1, Ordering::SeqCst) + 1;
+                if attempt <= 2 {
+                    return http::StatusCode::SERVICE_UNAVAILABLE;
+                }
+                tx.send(attempt).unwrap();
+                http::StatusCode::OK
+            }
+        }),
+    );
+    tokio::spawn(async move {
+        serve(listener, app).await.expect("server error");
+    });
+
+    let webhook = WebHook {
+        id: NoId,
+        url,
+        description: "Retries".into(),
+        token: "1234567890".into(),
+        enabled: true,
+        on_user_created: true,
+        on_user_deleted: false,
+        on_user_modified: false,
+        on_hwkey_provision: false,
+        on_enrollment_pending: false,
+        on_enrollment_completed: false,
+        on_user_mfa_changed: false,
+        group_id: None,
+        paused_until: None,
+        custom_headers: Json(HashMap::new()),
+        body_template: None,
+        max_attempts: 5,
+        retry_base_delay: 1,
+        archived: false,
+    };
+    let response = client.post("/api/v1/webhook").json(&webhook).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // trigger the webhook
+    let new_user = AddUserData {
+        username: "adumbledore".into(),
+        last_name: "Dumbledore".into(),
+        first_name: "Albus".into(),
+        email: "a.dumbledore@hogwart.edu.uk".into(),
+        phone: None,
+        password: None,
+    };
+    let response = client.post("/api/v1/user").json(&new_user).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let attempt = timeout(Duration::from_secs(15), rx.recv())
+        .await
+        .expect("webhook not delivered")
+        .unwrap();
+    assert_eq!(attempt, 3);
+
+    // delivered request is not retried
+    sleep(Duration::from_secs(3)).await;
+    assert_eq!(attempts.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn test_webhook_signature() {
+    let client = make_client().await;
+
+    let auth = Auth::new("admin", "pass123");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // receiver which captures headers and raw body of incoming requests
+    let (tx, mut rx) = unbounded_channel();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!(
+        "http://127.0.0.1:{}/hook",
+        listener.local_addr().unwrap().port()
+    );
+    let app = Router::new().route(
+        "/hook",
+        post(move |headers: HeaderMap, body: Bytes| {
+            let tx = tx.clone();
+            async move {
+                tx.send((headers, body)).unwrap();
+            }
+        }),
+    );
+    tokio::spawn(async move {
+        serve(listener, app).await.expect("server error");
+    });
+
+    let token = "1234567890";
+    let webhook = WebHook {
+        id: NoId,
+        url,
+        description: "Signature".into(),
+        token: token.into(),
+        enabled: true,
+        on_user_created: true,
+        on_user_deleted: false,
+        on_user_modified: false,
+        on_hwkey_provision: false,
+        on_enrollment_pending: false,
+        on_enrollment_completed: false,
+        on_user_mfa_changed: false,
+        group_id: None,
+        paused_until: None,
+        custom_headers: Json(HashMap::new()),
+        body_template: None,
+        max_attempts: 5,
+        retry_base_delay: 1,
+        archived: false,
+    };
+    let response = client.post("/api/v1/webhook").json(&webhook).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // trigger the webhook
+    let new_user = AddUserData {
+        username: "adumbledore".into(),
+        last_name: "Dumbledore".into(),
+        first_name: "Albus".into(),
+        email: "a.dumbledore@hogwart.edu.uk".into(),
+        phone: None,
+        password: None,
+    };
+    let response = client.post("/api/v1/user").json(&new_user).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let (headers, body) = timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .expect("webhook not delivered")
+        .unwrap();
+    let timestamp = headers
+        .get("x-defguard-timestamp")
+        .expect("missing timestamp header")
+        .to_str()
+        .unwrap();
+    assert!((Utc::now().timestamp() - timestamp.parse::<i64>().unwrap()).abs() < 60);
+    let signature = headers
+        .get("x-defguard-signature")
+        .expect("missing signature header")
+        .to_str()
+        .unwrap();
+    let signature = hex_decode(signature.strip_prefix("sha256=").unwrap()).unwrap();
+
+    // recompute the signature the way a receiver would
+    let mut mac = Hmac::<Sha256>::new_from_slice(token.as_bytes()).unwrap();
+    mac.update(format!("{timestamp}.").as_bytes());
+    mac.update(&body);
+    mac.verify_slice(&signature)
+        .expect("signature doesn't match request body");
+
+    // tampered body doesn't validate
+    let mut mac = Hmac::<Sha256>::new_from_slice(token.as_bytes()).unwrap();
+    mac.update(format!("{timestamp}.").as_bytes());
+    mac.update(&body[1..]);
+    assert!(mac.verify_slice(&signature).is_err());
+}
+
+#[tokio::test]
+async fn test_webhook_user_mfa_changed() {
+    let client = make_client().await;
+
+    let auth = Auth::new("admin", "pass123");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // receiver which captures event name and payload
+    let (tx, mut rx) = unbounded_channel();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!(
+        "http://127.0.0.1:{}/hook",
+        listener.local_addr().unwrap().port()
+    );
+    let app = Router::new().route(
+        "/hook",
+        post(move |headers: HeaderMap, body: Bytes| {
+            let tx = tx.clone();
+            async move {
+                let event = headers
+                    .get("x-defguard-event")
+                    .and_then(|value| value.to_str().ok())
+                    .map(ToString::to_string);
+                let payload: Value = serde_json::from_slice(&body).unwrap();
+                tx.send((event, payload)).unwrap();
+            }
+        }),
+    );
+    tokio::spawn(async move {
+        serve(listener, app).await.expect("server error");
+    });
+
+    let webhook = WebHook {
+        id: NoId,
+        url,
+        description: "MFA".into(),
+        token: "1234567890".into(),
+        enabled: true,
+        on_user_created: false,
+        on_user_deleted: false,
+        on_user_modified: false,
+        on_hwkey_provision: false,
+        on_enrollment_pending: false,
+        on_enrollment_completed: false,
+        on_user_mfa_changed: true,
+        group_id: None,
+        paused_until: None,
+        custom_headers: Json(HashMap::new()),
+        body_template: None,
+        max_attempts: 5,
+        retry_base_delay: 1,
+        archived: false,
+    };
+    let response = client.post("/api/v1/webhook").json(&webhook).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // enable TOTP as a regular user
+    let auth = Auth::new("hpotter", "Pass123!");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client.post("/api/v1/auth/totp/init").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let auth_totp: AuthTotp = response.json().await;
+    let code = totp_code(&auth_totp);
+    let response = client.post("/api/v1/auth/totp").json(&code).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let (event, payload) = timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .expect("webhook not delivered")
+        .unwrap();
+    assert_eq!(event.as_deref(), Some("user_mfa_changed"));
+    assert_eq!(payload["username"], "hpotter");
+    assert_eq!(payload["mfa_enabled"], true);
+    assert_eq!(payload["totp_enabled"], true);
+    assert_eq!(payload["mfa_method"], "OneTimePassword");
+    assert_eq!(payload["previous"]["totp_enabled"], false);
+    assert_eq!(payload["previous"]["mfa_method"], "None");
+
+    // disable TOTP
+    let response = client.delete("/api/v1/auth/totp").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let (event, payload) = timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .expect("webhook not delivered")
+        .unwrap();
+    assert_eq!(event.as_deref(), Some("user_mfa_changed"));
+    assert_eq!(payload["totp_enabled"], false);
+    assert_eq!(payload["mfa_enabled"], false);
+    assert_eq!(payload["mfa_method"], "None");
+    assert_eq!(payload["previous"]["totp_enabled"], true);
+}
+
+#[tokio::test]
+async fn test_webhook_group_scope() {
+    let (client, client_state) = make_test_client().await;
+
+    let auth = Auth::new("admin", "pass123");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let group = GroupInfo::new("contractors", vec!["hpotter".into()], Vec::new(), false);
+    let response = client.post("/api/v1/group").json(&group).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let group_id: Id = query_scalar("SELECT id FROM \"group\" WHERE name = 'contractors'")
+        .fetch_one(&client_state.pool)
+        .await
+        .unwrap();
+    let new_user = AddUserData {
+        username: "rweasley".into(),
+        last_name: "Weasley".into(),
+        first_name: "Ron".into(),
+        email: "r.weasley@hogwart.edu.uk".into(),
+        phone: None,
+        password: None,
+    };
+    let response = client.post("/api/v1/user").json(&new_user).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // receiver which reports usernames from payloads
+    let (tx, mut rx) = unbounded_channel();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!(
+        "http://127.0.0.1:{}/hook",
+        listener.local_addr().unwrap().port()
+    );
+    let app = Router::new().route(
+        "/hook",
+        post(move |body: Bytes| {
+            let tx = tx.clone();
+            async move {
+                let payload: Value = serde_json::from_slice(&body).unwrap();
+                tx.send(payload["username"].as_str().unwrap().to_string())
+                    .unwrap();
+            }
+        }),
+    );
+    tokio::spawn(async move {
+        serve(listener, app).await.expect("server error");
+    });
+
+    let mut webhook = WebHook {
+        id: NoId,
+        url,
+        description: "Contractors".into(),
+        token: "1234567890".into(),
+        enabled: true,
+        on_user_created: false,
+        on_user_deleted: true,
+        on_user_modified: false,
+        on_hwkey_provision: false,
+        on_enrollment_pending: false,
+        on_enrollment_completed: false,
+        on_user_mfa_changed: false,
+        group_id: Some(group_id + 100),
+        paused_until: None,
+        custom_headers: Json(HashMap::new()),
+        body_template: None,
+        max_attempts: 5,
+        retry_base_delay: 1,
+        archived: false,
+    };
+    // non-existent group
+    let response = client.post("/api/v1/webhook").json(&webhook).send().await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    webhook.group_id = Some(group_id);
+    let response = client.post("/api/v1/webhook").json(&webhook).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // user outside of the group doesn't trigger the webhook, group member does
+    let response = client.delete("/api/v1/user/rweasley").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client.delete("/api/v1/user/hpotter").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let username = timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .expect("webhook not delivered")
+        .unwrap();
+    assert_eq!(username, "hpotter");
+    sleep(Duration::from_secs(1)).await;
+    assert!(rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn test_webhook_archive() {
+    let client = make_client().await;
+
+    let auth = Auth::new("admin", "pass123");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // receiver which always fails, so that the delivery is kept for a retry
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!(
+        "http://127.0.0.1:{}/hook",
+        listener.local_addr().unwrap().port()
+    );
+    let counter = Arc::clone(&attempts);
+    let app = Router::new().route(
+        "/hook",
+        post(move || {
+            let counter = Arc::clone(&counter);
+            async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                http::StatusCode::SERVICE_UNAVAILABLE
+            }
+        }),
+    );
+    tokio::spawn(async move {
+        serve(listener, app).await.expect("server error");
+    });
+
+    let webhook = WebHook {
+        id: NoId,
+        url,
+        description: "Archive".into(),
+        token: "1234567890".into(),
+        enabled: true,
+        on_user_created: true,
+        on_user_deleted: false,
+        on_user_modified: false,
+        on_hwkey_provision: false,
+        on_enrollment_pending: false,
+        on_enrollment_completed: false,
+        on_user_mfa_changed: false,
+        group_id: None,
+        paused_until: None,
+        custom_headers: Json(HashMap::new()),
+        body_template: None,
+        max_attempts: 5,
+        retry_base_delay: 3600,
+        archived: false,
+    };
+    let response = client.post("/api/v1/webhook").json(&webhook).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = client.get("/api/v1/webhook").send().await;
+    let webhooks: Vec<WebHook<Id>> = response.json().await;
+    let id = webhooks[0].id;
+
+    // trigger the webhook, failed delivery is stored
+    let new_user = AddUserData {
+        username: "adumbledore".into(),
+        last_name: "Dumbledore".into(),
+        first_name: "Albus".into(),
+        email: "a.dumbledore@hogwart.edu.uk".into(),
+        phone: None,
+        password: None,
+    };
+    let response = client.post("/api/v1/user").json(&new_user).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    timeout(Duration::from_secs(5), async {
+        while attempts.load(Ordering::SeqCst) == 0 {
+            sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("webhook not triggered");
+
+    // archive by default
+    let response = client.delete(format!("/api/v1/webhook/{id}")).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // archived webhook is hidden from the list
+    let response = client.get("/api/v1/webhook").send().await;
+    let webhooks: Vec<WebHook<Id>> = response.json().await;
+    assert!(webhooks.is_empty());
+
+    // and can't be fetched, edited, re-enabled or archived again
+    let response = client.get(format!("/api/v1/webhook/{id}")).send().await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = client
+        .put(format!("/api/v1/webhook/{id}"))
+        .json(&webhook)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = client
+        .post(format!("/api/v1/webhook/{id}"))
+        .json(&json!({"enabled": true}))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = client.delete(format!("/api/v1/webhook/{id}")).send().await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // but is still stored with its delivery history
+    let response = client
+        .get(format!("/api/v1/webhook/{id}/deliveries"))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let deliveries: Vec<Value> = response.json().await;
+    assert_eq!(deliveries.len(), 1);
+    assert_eq!(deliveries[0]["event"], "user_created");
+    // pending delivery is discarded rather than retried
+    assert_eq!(deliveries[0]["status"], "discarded");
+
+    // archived webhook isn't triggered
+    let new_user = AddUserData {
+        username: "nlongbottom".into(),
+        last_name: "Longbottom".into(),
+        first_name: "Neville".into(),
+        email: "n.longbottom@hogwart.edu.uk".into(),
+        phone: None,
+        password: None,
+    };
+    let response = client.post("/api/v1/user").json(&new_user).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    sleep(Duration::from_secs(1)).await;
+    assert_eq!(attempts.load(Ordering::SeqCst), 1);
+
+    // URL of archived webhook can be imported again
+    let response = client
+        .post("/api/v1/webhook/import")
+        .json(&json!([webhook]))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let result: Value = response.json().await;
+    assert_eq!(result["created"], 1);
+    assert_eq!(result["skipped"], 0);
+
+    // hard delete removes the webhook along with its deliveries
+    let response = client
+        .delete(format!("/api/v1/webhook/{id}?hard=true"))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client
+        .get(format!("/api/v1/webhook/{id}/deliveries"))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
@@ -12,7 +12,7 @@ use defguard::{
 };
 use matches::assert_matches;
 use reqwest::StatusCode;
-use serde_json::json;
+use serde_json::{json, Value};
 
 use self::common::{make_network, make_test_client};
 
@@ -51,6 +51,7 @@ async fn test_network() {
         port: 55555,
         allowed_ips: Some("10.1.1.0/24".into()),
         dns: None,
+        dns_search_domains: None,
         allowed_groups: vec!["admin".into()],
         mfa_enabled: false,
         keepalive_interval: DEFAULT_KEEPALIVE_INTERVAL,
@@ -98,6 +99,100 @@ async fn test_network() {
     assert_matches!(event, GatewayEvent::NetworkDeleted(..));
 }
 
+#[tokio::test]
+async fn test_network_dns_search_domains() {
+    let (client, _) = make_test_client().await;
+
+    let auth = Auth::new("admin", "pass123");
+    let response = &client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // invalid search domain is rejected
+    let mut network_data = make_network();
+    network_data["dns_search_domains"] = json!(["example.com", "exa mple.com"]);
+    let response = client
+        .post("/api/v1/network")
+        .json(&network_data)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // create network with search domains
+    network_data["dns_search_domains"] = json!(["example.com", "corp.example.com"]);
+    let response = client
+        .post("/api/v1/network")
+        .json(&network_data)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let network: WireguardNetwork<Id> = response.json().await;
+    assert_eq!(
+        network.dns_search_domains,
+        vec!["example.com", "corp.example.com"]
+    );
+
+    // search domains are appended to DNS servers in device config
+    let device = json!({
+        "name": "device",
+        "wireguard_pubkey": "LQKsT6/3HWKuJmMulH63R8iK+5sI8FyYEL6WDIi6lQU=",
+    });
+    let response = client
+        .post("/api/v1/device/admin")
+        .json(&device)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    // desktop clients get them along with DNS servers
+    let result: Value = response.json().await;
+    assert_eq!(
+        result["configs"][0]["dns"],
+        "1.1.1.1, example.com, corp.example.com"
+    );
+    let response = client.get("/api/v1/network/1/device/1/config").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let config = response.text().await;
+    assert!(config.contains("\nDNS = 1.1.1.1, example.com, corp.example.com\n"));
+
+    // modification without search domains keeps them
+    network_data["allowed_groups"] = json!(["admin"]);
+    network_data
+        .as_object_mut()
+        .unwrap()
+        .remove("dns_search_domains");
+    let response = client
+        .put(format!("/api/v1/network/{}", network.id))
+        .json(&network_data)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let network: WireguardNetwork<Id> = response.json().await;
+    assert_eq!(
+        network.dns_search_domains,
+        vec!["example.com", "corp.example.com"]
+    );
+
+    // invalid search domain is rejected on modification
+    network_data["dns_search_domains"] = json!(["-example.com"]);
+    let response = client
+        .put(format!("/api/v1/network/{}", network.id))
+        .json(&network_data)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // only DNS servers after search domains are removed
+    network_data["dns_search_domains"] = json!([]);
+    let response = client
+        .put(format!("/api/v1/network/{}", network.id))
+        .json(&network_data)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client.get("/api/v1/network/1/device/1/config").send().await;
+    let config = response.text().await;
+    assert!(config.contains("\nDNS = 1.1.1.1\n"));
+}
+
 #[tokio::test]
 async fn test_device() {
     let (client, client_state) = make_test_client().await;
@@ -325,7 +420,7 @@ async fn test_device_permissions() {
     assert_eq!(response.status(), StatusCode::CREATED);
 
     // normal user cannot add devices for other users or import multiple devices
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = &client.post("/api/v1/auth").json(&auth).send().await;
     assert_eq!(response.status(), StatusCode::OK);
 
@@ -507,3 +602,123 @@ async fn test_device_pubkey() {
     let devices: Vec<Device<Id>> = response.json().await;
     assert_eq!(devices.len(), 1);
 }
+
+#[tokio::test]
+async fn test_send_device_config() {
+    let (client, client_state) = make_test_client().await;
+
+    let mut mail_rx = client_state.mail_rx;
+
+    let auth = Auth::new("admin", "pass123");
+    let response = &client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // create network
+    let response = client
+        .post("/api/v1/network")
+        .json(&make_network())
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // create device for another user
+    let device = json!({
+        "name": "device",
+        "wireguard_pubkey": "LQKsT6/3HWKuJmMulH63R8iK+5sI8FyYEL6WDIi6lQU=",
+    });
+    let response = client
+        .post("/api/v1/device/hpotter")
+        .json(&device)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = client.get("/api/v1/device/user/hpotter").send().await;
+    let devices: Vec<Device<Id>> = response.json().await;
+    let device = devices[0].clone();
+
+    // skip new device notifications
+    while mail_rx.try_recv().is_ok() {}
+
+    // admin sends config to device owner
+    let response = client
+        .post(format!(
+            "/api/v1/network/1/device/{}/config/send",
+            device.id
+        ))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let mail = mail_rx.try_recv().unwrap();
+    assert_eq!(mail.to, "h.potter@hogwart.edu.uk");
+    assert_eq!(mail.attachments.len(), 1);
+    assert_eq!(mail.attachments[0].filename, "network.conf");
+    let config = String::from_utf8(mail.attachments[0].content.clone()).unwrap();
+    assert!(config.contains("PrivateKey = YOUR_PRIVATE_KEY"));
+    assert!(config.contains("Address = 10.1.1.2"));
+
+    // non-existent device
+    let response = client
+        .post("/api/v1/network/1/device/100/config/send")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert!(mail_rx.try_recv().is_err());
+
+    // normal user cannot request config of other user's device
+    let response = client
+        .post("/api/v1/device/admin")
+        .json(&json!({
+            "name": "admin-device",
+            "wireguard_pubkey": "TJgN9JzUF5zdZAPYD96G/Wys2M3TvaT5TIrErUl20nI=",
+        }))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = client.get("/api/v1/device/user/admin").send().await;
+    let admin_devices: Vec<Device<Id>> = response.json().await;
+    while mail_rx.try_recv().is_ok() {}
+
+    let auth = Auth::new("hpotter", "Pass123!");
+    let response = &client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client
+        .post(format!(
+            "/api/v1/network/1/device/{}/config/send",
+            admin_devices[0].id
+        ))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(mail_rx.try_recv().is_err());
+
+    // but can request config of own device
+    let response = client
+        .post(format!(
+            "/api/v1/network/1/device/{}/config/send",
+            device.id
+        ))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let mail = mail_rx.try_recv().unwrap();
+    assert_eq!(mail.to, "h.potter@hogwart.edu.uk");
+}
+
+#[tokio::test]
+async fn test_stale_devices() {
+    let (client, _) = make_test_client().await;
+
+    let auth = Auth::new("admin", "pass123");
+    let response = &client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = client.get("/api/v1/device/stale?days=7").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // cutoff out of the representable date range
+    let response = client
+        .get(format!("/api/v1/device/stale?days={}", u32::MAX))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
@@ -63,12 +63,13 @@ async fn setup_test_users(pool: &PgPool) -> (Vec<User<Id>>, Vec<Device<Id>>) {
     // standard user in other, non-allowed group
     let other_user = User::new(
         "ssnape",
-        Some("pass123"),
+        Some("Pass123!"),
         "Snape",
         "Severus",
         "s.snape@hogwart.edu.uk",
         None,
     )
+    .unwrap()
     .save(pool)
     .await
     .unwrap();
@@ -93,12 +94,13 @@ async fn setup_test_users(pool: &PgPool) -> (Vec<User<Id>>, Vec<Device<Id>>) {
     // standard user in no groups
     let non_group_user = User::new(
         "dobby",
-        Some("pass123"),
+        Some("Pass123!"),
         "Elf",
         "Dobby",
         "dobby@hogwart.edu.uk",
         None,
     )
+    .unwrap()
     .save(pool)
     .await
     .unwrap();
@@ -373,7 +373,7 @@ async fn test_config_import_nonadmin() {
         PersistentKeepalive = 300
     ";
     let (client, _) = make_test_client().await;
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = &client.post("/api/v1/auth").json(&auth).send().await;
     assert_eq!(response.status(), StatusCode::OK);
 
@@ -3,13 +3,18 @@ pub mod common;
 use std::sync::{Arc, Mutex};
 
 use defguard::{
-    grpc::{worker::JobStatus, WorkerDetail, WorkerState},
+    grpc::{
+        worker::{worker_service_server::WorkerService, JobStatus, WorkerServer},
+        WorkerDetail, WorkerState,
+    },
     handlers::{
         worker::{JobData, Jobid},
         Auth,
     },
 };
 use reqwest::StatusCode;
+use serde_json::Value;
+use tonic::Request;
 
 use self::common::{client::TestClient, make_test_client};
 
@@ -18,6 +23,8 @@ async fn make_client() -> (TestClient, Arc<Mutex<WorkerState>>) {
     (client, client_status.worker_state)
 }
 
+static SSH_KEY: &str = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIK1ej+W6YY6PDUK2HAiJJ0Ia6WuGfxQf3IojblaIRc+A hpotter@hogwart";
+
 #[tokio::test]
 async fn test_scheduling_worker_jobs() {
     let (client, worker_state) = make_client().await;
@@ -29,13 +36,15 @@ async fn test_scheduling_worker_jobs() {
     };
 
     // normal user can only provision keys for themselves
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = client.post("/api/v1/auth").json(&auth).send().await;
     assert_eq!(response.status(), StatusCode::OK);
 
     let job_data = JobData {
         username: "hpotter".to_string(),
         worker: "YubiBridge".to_string(),
+        yubikey_model: None,
+        yubikey_firmware_version: None,
     };
     let response = client
         .post("/api/v1/worker/job")
@@ -48,6 +57,8 @@ async fn test_scheduling_worker_jobs() {
     let job_data = JobData {
         username: "admin".to_string(),
         worker: "YubiBridge".to_string(),
+        yubikey_model: None,
+        yubikey_firmware_version: None,
     };
     let response = client
         .post("/api/v1/worker/job")
@@ -64,6 +75,8 @@ async fn test_scheduling_worker_jobs() {
     let job_data = JobData {
         username: "hpotter".to_string(),
         worker: "YubiBridge".to_string(),
+        yubikey_model: None,
+        yubikey_firmware_version: None,
     };
     let response = client
         .post("/api/v1/worker/job")
@@ -76,6 +89,8 @@ async fn test_scheduling_worker_jobs() {
     let job_data = JobData {
         username: "admin".to_string(),
         worker: "YubiBridge".to_string(),
+        yubikey_model: None,
+        yubikey_firmware_version: None,
     };
     let response = client
         .post("/api/v1/worker/job")
@@ -146,7 +161,7 @@ async fn test_scheduling_worker_jobs() {
     assert_eq!(response.status(), StatusCode::OK);
 
     // // normal user can only fetch status of their own jobs
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = client.post("/api/v1/auth").json(&auth).send().await;
     assert_eq!(response.status(), StatusCode::OK);
 
@@ -203,7 +218,7 @@ async fn test_worker_management_permissions() {
     assert_eq!(workers.len(), 2);
 
     // normal user cannot create worker tokens
-    let auth = Auth::new("hpotter", "pass123");
+    let auth = Auth::new("hpotter", "Pass123!");
     let response = client.post("/api/v1/auth").json(&auth).send().await;
     assert_eq!(response.status(), StatusCode::OK);
 
@@ -218,3 +233,55 @@ async fn test_worker_management_permissions() {
     let response = client.delete("/api/v1/worker/worker_2").send().await;
     assert_eq!(response.status(), StatusCode::FORBIDDEN);
 }
+
+#[tokio::test]
+async fn test_provisioning_stores_yubikey_metadata() {
+    let (client, client_state) = make_test_client().await;
+    let worker_state = client_state.worker_state;
+    worker_state
+        .lock()
+        .unwrap()
+        .register_worker("YubiBridge".to_string());
+
+    let auth = Auth::new("hpotter", "Pass123!");
+    let response = client.post("/api/v1/auth").json(&auth).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let job_data = JobData {
+        username: "hpotter".to_string(),
+        worker: "YubiBridge".to_string(),
+        yubikey_model: Some("YubiKey 5 NFC".to_string()),
+        yubikey_firmware_version: Some("5.4.3".to_string()),
+    };
+    let response = client
+        .post("/api/v1/worker/job")
+        .json(&job_data)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let job_id = response.json::<Jobid>().await.id;
+
+    // worker reports provisioned key
+    let server = WorkerServer::new(client_state.pool.clone(), worker_state);
+    server
+        .set_job_done(Request::new(JobStatus {
+            id: "YubiBridge".to_string(),
+            job_id,
+            success: true,
+            public_key: String::new(),
+            ssh_key: SSH_KEY.to_string(),
+            yubikey_serial: "12345678".to_string(),
+            error: String::new(),
+        }))
+        .await
+        .unwrap();
+
+    let response = client.get("/api/v1/user/hpotter/auth_key").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let keys: Vec<Value> = response.json().await;
+    assert_eq!(keys.len(), 2);
+    for key in keys {
+        assert_eq!(key["yubikey_serial"], "12345678");
+        assert_eq!(key["yubikey_model"], "YubiKey 5 NFC");
+        assert_eq!(key["yubikey_firmware_version"], "5.4.3");
+    }
+}
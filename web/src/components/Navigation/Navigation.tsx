@@ -50,10 +50,14 @@ export const Navigation = () => {
 
   const { mutate: logOutMutation } = useMutation({
     mutationFn: logout,
-    onSuccess: () => {
+    onSuccess: (data) => {
       resetAuthStore();
       resetUserProfile();
       setStore({ isOpen: false });
+      // log out of the OpenID provider as well
+      if (data?.url) {
+        window.location.href = data.url;
+      }
     },
   });
 
@@ -89,7 +89,7 @@ const TOTPRegisterQRCode = () => {
   }, [totpInitError]);
 
   const qrData = useMemo(
-    () => (data ? `otpauth://totp/Defguard?secret=${data.secret}` : undefined),
+    () => data?.uri ?? undefined,
     [data],
   );
 
@@ -20,12 +20,15 @@ import {
   GroupsResponse,
   LoginData,
   LoginResponse,
+  LogoutResponse,
   MFALoginResponse,
   Network,
   NetworkToken,
   OpenidClient,
   OpenIdInfo,
   Provisioner,
+  RecoveryCodes,
+  RecoveryCodesToken,
   RemoveUserClientRequest,
   ResetPasswordRequest,
   Settings,
@@ -152,7 +155,7 @@ const useApi = (props?: HookProps): ApiHook => {
       return {};
     });
 
-  const logout = () => client.post<EmptyApiResponse>('/auth/logout').then(unpackRequest);
+  const logout = () => client.post<LogoutResponse>('/auth/logout').then(unpackRequest);
 
   const getOpenidInfo: ApiHook['auth']['openid']['getOpenIdInfo'] = () =>
     client.get(`/openid/auth_info`).then(unpackRequest);
@@ -280,8 +283,21 @@ const useApi = (props?: HookProps): ApiHook => {
   const mfaWebauthnRegisterStart: ApiHook['auth']['mfa']['webauthn']['register']['start'] =
     () => client.post('/auth/webauthn/init').then(unpackRequest);
 
+  const exchangeRecoveryCodes = async (
+    res: RecoveryCodesToken | undefined,
+  ): Promise<RecoveryCodes | undefined> => {
+    if (!res?.token) return undefined;
+    return client
+      .get<RecoveryCodes>(`/mfa/recovery-codes/${res.token}`)
+      .then(unpackRequest);
+  };
+
   const mfaWebauthnRegisterFinish: ApiHook['auth']['mfa']['webauthn']['register']['finish'] =
-    async (data) => client.post('/auth/webauthn/finish', data).then(unpackRequest);
+    async (data) =>
+      client
+        .post<RecoveryCodesToken>('/auth/webauthn/finish', data)
+        .then(unpackRequest)
+        .then(exchangeRecoveryCodes);
 
   const mfaWebauthnStart = () => client.post('/auth/webauthn/start').then(unpackRequest);
 
@@ -291,7 +307,10 @@ const useApi = (props?: HookProps): ApiHook => {
   const mfaTOTPInit = () => client.post('/auth/totp/init').then(unpackRequest);
 
   const mfaTOTPEnable: ApiHook['auth']['mfa']['totp']['enable'] = (data) =>
-    client.post('/auth/totp', data).then(unpackRequest);
+    client
+      .post<RecoveryCodesToken>('/auth/totp', data)
+      .then(unpackRequest)
+      .then(exchangeRecoveryCodes);
 
   const mfaTOTPDisable = () => client.delete('/auth/totp').then(unpackRequest);
 
@@ -303,7 +322,11 @@ const useApi = (props?: HookProps): ApiHook => {
 
   const mfaEmailMFAEnable: ApiHook['auth']['mfa']['email']['register']['finish'] = (
     data,
-  ) => client.post('/auth/email', data).then(unpackRequest);
+  ) =>
+    client
+      .post<RecoveryCodesToken>('/auth/email', data)
+      .then(unpackRequest)
+      .then(exchangeRecoveryCodes);
 
   const mfaEmailMFADisable = () => client.delete('/auth/email').then(unpackRequest);
 
@@ -123,6 +123,7 @@ export interface Network {
   allowed_ips?: string[];
   allowed_groups?: string[];
   dns?: string;
+  dns_search_domains?: string[];
   mfa_enabled: boolean;
   keepalive_interval: number;
   peer_disconnect_threshold: number;
@@ -303,6 +304,11 @@ export interface RecoveryCodes {
   codes: string[];
 }
 
+// One-time token returned after enabling MFA, exchanged for the actual codes.
+export interface RecoveryCodesToken {
+  token?: string;
+}
+
 export interface RecoveryLoginRequest {
   code: string;
 }
@@ -547,7 +553,7 @@ export interface ApiHook {
   };
   auth: {
     login: (data: LoginData) => Promise<LoginResponse>;
-    logout: () => EmptyApiResponse;
+    logout: () => Promise<LogoutResponse>;
     openid: {
       getOpenIdInfo: () => Promise<OpenIdInfoResponse>;
       callback: (data: CallbackData) => Promise<LoginResponse>;
@@ -577,7 +583,12 @@ export interface ApiHook {
         deleteKey: (data: DeleteWebAuthNKeyRequest) => EmptyApiResponse;
       };
       totp: {
-        init: () => Promise<{ secret: string }>;
+        init: () => Promise<{
+          secret: string;
+          digits: number;
+          period: number;
+          uri?: string;
+        }>;
         enable: (data: TOTPRequest) => MFARecoveryCodesResponse;
         disable: () => EmptyApiResponse;
         verify: (data: TOTPRequest) => Promise<MFAFinishResponse>;
@@ -648,6 +659,11 @@ export interface NavigationStore {
 
 export type EmptyApiResponse = AxiosPromise<unknown>;
 
+export interface LogoutResponse {
+  // OpenID provider logout URL, present when RP-initiated logout is enabled
+  url?: string;
+}
+
 export interface WorkerCreateJobResponse {
   id: number;
 }
@@ -825,7 +841,9 @@ export type Settings = SettingsModules &
   SettingsLDAP &
   SettingsOpenID &
   SettingsLicense &
-  SettingsGatewayNotifications;
+  SettingsGatewayNotifications &
+  SettingsRecoveryCodes &
+  SettingsSecurityNotifications;
 
 // essentials for core frontend, includes only those that are required for frontend operations
 export type SettingsEssentials = SettingsModules & SettingsBranding;
@@ -890,6 +908,14 @@ export type SettingsGatewayNotifications = {
   gateway_disconnect_notifications_reconnect_notification_enabled: boolean;
 };
 
+export type SettingsRecoveryCodes = {
+  recovery_codes_enabled: boolean;
+};
+
+export type SettingsSecurityNotifications = {
+  security_notification_email?: string;
+};
+
 export type SettingsEnterprise = {
   admin_device_management: boolean;
   disable_all_traffic: boolean;